@@ -0,0 +1,1119 @@
+//! Resident agent for features that need a long-lived process.
+//!
+//! `demon agent` watches every daemon of a root directory and applies the
+//! policies given to `demon run`: restarting daemons that exit (`--restart`),
+//! running health checks (`--health-cmd`) and starting scheduled jobs again
+//! (`--every`). Policies are stored in the daemon metadata, so the agent can be
+//! started before or after the daemons it manages. It also starts the cron
+//! jobs of `demon schedule add`, which are defined in `<root>/schedule.json`.
+//!
+//! Other commands talk to the agent over `<root>/.agent.sock` with a line
+//! protocol, and fall back to working on their own when no agent is running:
+//!
+//! - `ping` replies `ok <agent pid>`
+//! - `status <id>` replies `ok <json>` with the agent's view of a daemon
+//! - `hold <id> <pid>` keeps the agent from restarting a daemon being stopped
+//! - `tail <id> <stdout|stderr|both>` streams log lines written from then on as
+//!   `<stream> <line>` until the client disconnects, and `rotated <stream>`
+//!   when a log started over for a new run
+//! - `shutdown` stops the agent
+
+use crate::follow::{self, Follower};
+use crate::{
+    autorestart::Watch,
+    cgroup::Resources,
+    color, config, demon_exe, events,
+    exit::{self, Exit},
+    format_duration, hooks,
+    labels::Labels,
+    limits::Limits,
+    logs::{CHUNK, LogPrinter, Stream},
+    parse_duration,
+    pidfile::{DaemonMeta, PidFile, PidFileReadError, build_file_path, daemon_ids},
+    priority::Priority,
+    reload::Signal,
+    requirements::Requirements,
+    runner::{RunOptions, run_daemon},
+    sandbox::Sandbox,
+    schedule::{self, CatchUp},
+    ship,
+    singleton::Singleton,
+    status::is_daemon_running,
+    stop::stop_daemon,
+    unix_now,
+};
+use anyhow::{Context, Result};
+use clap::{Args, ValueEnum};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs::OpenOptions;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+/// How long clients wait for the agent to answer a request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How often the agent looks at every daemon
+const SCAN_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Consecutive failed health checks after which a daemon is unhealthy
+const HEALTH_RETRIES: u32 = 3;
+
+/// Default seconds between health checks
+const DEFAULT_HEALTH_INTERVAL: u64 = 10;
+
+/// Restart delays double from the first to the last after each quick exit
+const FIRST_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// A daemon that stays up this long resets its restart delay
+const STABLE_UPTIME: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum Restart {
+    Never,
+    OnFailure,
+    Always,
+}
+
+/// Policies given to `demon run`, applied by the agent (restarts, health
+/// checks, schedules), the supervisor (sd_notify readiness and watchdog,
+/// maximum runtime, stripping escape sequences, collecting core dumps,
+/// restarting on file changes),
+/// `run` (singletons), `reload` and whoever spawns the daemon (resource limits,
+/// priorities, cgroups, sandboxes, systemd scopes and the free memory and disk
+/// space it requires), along with the group and labels other commands pick
+/// the daemon by
+#[derive(Args, Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct Policy {
+    /// Restart the daemon when it exits; applied by `demon agent`
+    #[arg(long, value_enum, value_name = "POLICY")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) restart: Option<Restart>,
+
+    /// Shell command the agent runs periodically to check the daemon; after 3
+    /// failures in a row the daemon is unhealthy and restarted unless
+    /// --restart is never
+    #[arg(long, value_name = "COMMAND")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) health_cmd: Option<String>,
+
+    /// Seconds between health checks (default: 10)
+    #[arg(long, value_name = "SECONDS", requires = "health_cmd")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) health_interval: Option<u64>,
+
+    /// Run the command again at this interval once it has finished, e.g. 10m
+    /// or 1h (a scheduled job); applied by `demon agent`
+    #[arg(long, value_name = "INTERVAL", value_parser = parse_interval)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) every: Option<u64>,
+
+    /// What to do about the runs of a scheduled job missed while the machine
+    /// slept or no agent ran (default: run-once)
+    #[arg(long, value_enum, value_name = "POLICY", requires = "every")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) catch_up: Option<CatchUp>,
+
+    /// Pass NOTIFY_SOCKET so the daemon can report that it is ready with
+    /// sd_notify(READY=1), which `run` waits for (implies --supervise)
+    #[arg(long)]
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub(crate) sd_notify: bool,
+
+    /// Kill the daemon with SIGABRT when it does not send an sd_notify
+    /// WATCHDOG=1 at least this often, e.g. 30s (implies --sd-notify)
+    #[arg(long, value_name = "INTERVAL", value_parser = parse_interval)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) watchdog: Option<u64>,
+
+    /// Terminate the daemon (SIGTERM, then SIGKILL after the stop timeout)
+    /// when it is still running after this long, e.g. 2h, and record its
+    /// exit as TIMEOUT (implies --supervise)
+    #[arg(long, value_name = "INTERVAL", value_parser = parse_interval)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) max_runtime: Option<u64>,
+
+    /// Refuse to start the daemon while another root directory runs one with
+    /// the same ID; `machine` covers every root directory of the user
+    #[arg(long, value_enum, value_name = "SCOPE")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) singleton: Option<Singleton>,
+
+    /// Signal `demon reload` sends to make the daemon re-read its
+    /// configuration, e.g. USR2 (default: HUP)
+    #[arg(long, value_name = "SIGNAL")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) reload_signal: Option<Signal>,
+
+    /// Remove ANSI escape sequences (colors) from the output before it is
+    /// written to the logs (implies --supervise)
+    #[arg(long)]
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub(crate) strip_ansi: bool,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    pub(crate) limits: Limits,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    pub(crate) priority: Priority,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    pub(crate) resources: Resources,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    pub(crate) requirements: Requirements,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    pub(crate) autorestart: Watch,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    pub(crate) sandbox: Sandbox,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    pub(crate) labels: Labels,
+
+    /// Run the daemon in a transient systemd scope of the user's service
+    /// manager (systemd-run --user --scope), so systemd tracks it and it
+    /// survives the end of the login session
+    #[arg(long)]
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub(crate) systemd_scope: bool,
+}
+
+pub(crate) fn parse_interval(value: &str) -> Result<u64, String> {
+    match parse_duration(value).map(|interval| interval.as_secs()) {
+        Ok(0) => Err(format!(
+            "invalid interval '{value}': expected e.g. 30s, 10m or 2h"
+        )),
+        Ok(secs) => Ok(secs),
+        Err(e) => Err(format!("invalid interval '{value}': {e}")),
+    }
+}
+
+impl Policy {
+    pub(crate) fn is_empty(&self) -> bool {
+        !self.needs_agent()
+            && !self.uses_sd_notify()
+            && self.max_runtime.is_none()
+            && self.singleton.is_none()
+            && self.reload_signal.is_none()
+            && !self.strip_ansi
+            && self.limits.is_empty()
+            && self.priority.is_empty()
+            && self.resources.is_empty()
+            && self.requirements.is_empty()
+            && self.autorestart.is_empty()
+            && self.sandbox.is_empty()
+            && self.labels.is_empty()
+            && !self.systemd_scope
+    }
+
+    /// Whether there is anything for the agent to apply
+    pub(crate) fn needs_agent(&self) -> bool {
+        self.restart.is_some() || self.health_cmd.is_some() || self.every.is_some()
+    }
+
+    /// Whether the supervisor listens for sd_notify messages
+    pub(crate) fn uses_sd_notify(&self) -> bool {
+        self.sd_notify || self.watchdog.is_some()
+    }
+
+    /// Whether the policy is applied by a supervisor
+    pub(crate) fn needs_supervisor(&self) -> bool {
+        self.uses_sd_notify()
+            || self.max_runtime.is_some()
+            || self.strip_ansi
+            || self.limits.core_dump_dir.is_some()
+            || !self.autorestart.is_empty()
+    }
+
+    /// Command line flags that recreate this policy
+    pub(crate) fn to_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if let Some(restart) = self.restart
+            && let Some(value) = restart.to_possible_value()
+        {
+            args.extend(["--restart".to_string(), value.get_name().to_string()]);
+        }
+        if let Some(command) = &self.health_cmd {
+            args.extend(["--health-cmd".to_string(), command.clone()]);
+        }
+        if let Some(interval) = self.health_interval {
+            args.extend(["--health-interval".to_string(), interval.to_string()]);
+        }
+        if let Some(every) = self.every {
+            args.extend(["--every".to_string(), format!("{every}s")]);
+        }
+        if let Some(catch_up) = self.catch_up {
+            args.extend(["--catch-up".to_string(), catch_up.name().to_string()]);
+        }
+        if self.sd_notify {
+            args.push("--sd-notify".to_string());
+        }
+        if let Some(watchdog) = self.watchdog {
+            args.extend(["--watchdog".to_string(), format!("{watchdog}s")]);
+        }
+        if let Some(max_runtime) = self.max_runtime {
+            args.extend(["--max-runtime".to_string(), format!("{max_runtime}s")]);
+        }
+        if let Some(singleton) = self.singleton
+            && let Some(value) = singleton.to_possible_value()
+        {
+            args.extend(["--singleton".to_string(), value.get_name().to_string()]);
+        }
+        if let Some(signal) = self.reload_signal {
+            args.extend(["--reload-signal".to_string(), String::from(signal)]);
+        }
+        if self.strip_ansi {
+            args.push("--strip-ansi".to_string());
+        }
+        args.extend(self.limits.to_args());
+        args.extend(self.priority.to_args());
+        args.extend(self.resources.to_args());
+        args.extend(self.requirements.to_args());
+        args.extend(self.autorestart.to_args());
+        args.extend(self.sandbox.to_args());
+        args.extend(self.labels.to_args());
+        if self.systemd_scope {
+            args.push("--systemd-scope".to_string());
+        }
+        args
+    }
+
+    fn health_interval(&self) -> Duration {
+        Duration::from_secs(self.health_interval.unwrap_or(DEFAULT_HEALTH_INTERVAL))
+    }
+}
+
+fn socket_path(root_dir: &Path) -> PathBuf {
+    root_dir.join(".agent.sock")
+}
+
+/// Send a request to the agent of a root directory and return its reply
+pub(crate) fn request(root_dir: &Path, request: &str) -> Result<String> {
+    let mut stream = UnixStream::connect(socket_path(root_dir))?;
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    stream.set_write_timeout(Some(REQUEST_TIMEOUT))?;
+    writeln!(stream, "{request}")?;
+
+    let mut reply = String::new();
+    BufReader::new(stream).read_line(&mut reply)?;
+    if reply.is_empty() {
+        return Err(anyhow::anyhow!("Agent closed the connection"));
+    }
+    let reply = reply.trim_end();
+    match reply.strip_prefix("error ") {
+        Some(err) => Err(anyhow::anyhow!("{}", err)),
+        None => Ok(reply.strip_prefix("ok").unwrap_or(reply).trim().to_string()),
+    }
+}
+
+/// PID of the agent serving a root directory, if one is running
+pub(crate) fn running(root_dir: &Path) -> Option<u32> {
+    request(root_dir, "ping").ok()?.parse().ok()
+}
+
+/// Set in the agent process, which holds daemons itself before stopping them
+static IN_AGENT: AtomicBool = AtomicBool::new(false);
+
+/// Keep a running agent from restarting a daemon that is being stopped
+pub(crate) fn hold(root_dir: &Path, id: &str, pid: u32) {
+    if !IN_AGENT.load(Ordering::SeqCst)
+        && socket_path(root_dir).exists()
+        && let Err(e) = request(root_dir, &format!("hold {id} {pid}"))
+    {
+        tracing::warn!("Failed to tell the agent about stopping '{}': {}", id, e);
+    }
+}
+
+/// The agent's view of a daemon, as reported by `status`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub(crate) struct DaemonState {
+    /// Restarts done by the agent since it started
+    pub(crate) restarts: u32,
+    /// Result of the health checks, None before the first one
+    pub(crate) healthy: Option<bool>,
+    /// Unix time the agent will next start the daemon
+    pub(crate) next_start: Option<u64>,
+}
+
+/// Ask the agent about a daemon; None when no agent is running
+pub(crate) fn daemon_state(root_dir: &Path, id: &str) -> Option<DaemonState> {
+    if !socket_path(root_dir).exists() {
+        return None;
+    }
+    let reply = request(root_dir, &format!("status {id}")).ok()?;
+    serde_json::from_str(&reply).ok()
+}
+
+/// Print the policies of a daemon and how the agent is applying them, for
+/// `demon status`
+pub(crate) fn print_status(root_dir: &Path, id: &str, policy: &Policy, colored: bool) {
+    if !policy.needs_agent() {
+        return;
+    }
+    if let Some(restart) = policy.restart
+        && let Some(value) = restart.to_possible_value()
+    {
+        println!("Restart policy: {}", value.get_name());
+    }
+    if let Some(command) = &policy.health_cmd {
+        println!(
+            "Health check: {} (every {})",
+            command,
+            format_duration(policy.health_interval())
+        );
+    }
+    if let Some(every) = policy.every {
+        println!(
+            "Schedule: every {}, catch-up {}",
+            format_duration(Duration::from_secs(every)),
+            policy.catch_up.unwrap_or_default().name()
+        );
+    }
+
+    let Some(pid) = running(root_dir) else {
+        println!("Agent: not running, policies are not applied");
+        return;
+    };
+    let state = daemon_state(root_dir, id).unwrap_or_default();
+    let health = match state.healthy {
+        Some(true) => format!(", {}", color::status(colored, "healthy")),
+        Some(false) => format!(", {}", color::status(colored, "unhealthy")),
+        None => String::new(),
+    };
+    println!("Agent: PID {pid}, {} restarts{health}", state.restarts);
+    if let Some(next_start) = state.next_start {
+        let wait = next_start.saturating_sub(unix_now());
+        println!(
+            "Next start: in {}",
+            format_duration(Duration::from_secs(wait))
+        );
+    }
+}
+
+/// Stream log lines written from now on through the agent, printing them
+/// until Ctrl+C. Returns false without printing anything when no agent is
+/// running.
+pub(crate) fn tail(
+    root_dir: &Path,
+    id: &str,
+    streams: &str,
+    printer: &mut LogPrinter,
+) -> Result<bool> {
+    let Ok(mut stream) = UnixStream::connect(socket_path(root_dir)) else {
+        return Ok(false);
+    };
+    writeln!(stream, "tail {id} {streams}")?;
+
+    let running = Arc::new(AtomicBool::new(true));
+    let r = running.clone();
+    ctrlc::set_handler(move || {
+        r.store(false, Ordering::SeqCst);
+    })?;
+    stream.set_read_timeout(Some(Duration::from_millis(100)))?;
+
+    tracing::info!("Following logs through the agent... Press Ctrl+C to stop.");
+    let mut reader = BufReader::with_capacity(CHUNK, stream);
+    let mut line = String::new();
+    while running.load(Ordering::SeqCst) {
+        match reader.read_line(&mut line) {
+            Ok(0) => return Err(anyhow::anyhow!("Agent closed the connection")),
+            Ok(_) if line.ends_with('\n') => {
+                let (name, text) = line.split_once(' ').unwrap_or((line.trim_end(), "\n"));
+                if name == "rotated" {
+                    printer.new_run();
+                } else if let Some(stream) = Stream::from_extension(name) {
+                    printer.print(stream, text, true);
+                }
+                line.clear();
+                printer.flush_if_due()?;
+            }
+            // Partial line or timeout: keep what was read and try again
+            Ok(_) => {}
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                ) =>
+            {
+                printer.flush()?
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(true)
+}
+
+/// Start an agent in the background, logging to `<root>/agent.log`
+pub(crate) fn start_detached(root_dir: &Path) -> Result<()> {
+    if let Some(pid) = running(root_dir) {
+        return Err(anyhow::anyhow!("An agent is already running (PID {})", pid));
+    }
+    let log_path = root_dir.join("agent.log");
+    let log = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&log_path)
+        .with_context(|| format!("Failed to open {}", log_path.display()))?;
+
+    let exe = demon_exe()?;
+    let mut cmd = Command::new(exe);
+    cmd.arg("agent")
+        .arg("--root-dir")
+        .arg(root_dir)
+        .stdin(Stdio::null())
+        .stdout(Stdio::from(log.try_clone()?))
+        .stderr(Stdio::from(log));
+    // SAFETY: setsid is async-signal-safe and touches no memory
+    unsafe {
+        std::os::unix::process::CommandExt::pre_exec(&mut cmd, || {
+            if libc::setsid() == -1 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    let mut child = cmd.spawn().context("Failed to start agent")?;
+
+    for _ in 0..50 {
+        if let Some(pid) = running(root_dir) {
+            // The agent keeps running; it is reparented once we exit
+            std::mem::forget(child);
+            println!(
+                "Agent started (PID {pid}), logging to {}",
+                log_path.display()
+            );
+            return Ok(());
+        }
+        if let Some(status) = child.try_wait()? {
+            return Err(anyhow::anyhow!(
+                "Agent exited with {}, see {}",
+                status,
+                log_path.display()
+            ));
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+    Err(anyhow::anyhow!(
+        "Agent did not start listening, see {}",
+        log_path.display()
+    ))
+}
+
+/// Ask the running agent to shut down
+pub(crate) fn stop(root_dir: &Path) -> Result<()> {
+    let Some(pid) = running(root_dir) else {
+        println!("No agent is running");
+        return Ok(());
+    };
+    request(root_dir, "shutdown")?;
+    println!("Agent (PID {pid}) stopped");
+    Ok(())
+}
+
+/// Per-daemon bookkeeping of the agent
+struct Tracked {
+    pid: u32,
+    state: DaemonState,
+    /// When the agent may start the daemon again, once it has exited
+    next_start: Option<Instant>,
+    backoff: Duration,
+    /// When the current run was first seen running
+    seen_running: Instant,
+    next_health: Instant,
+    /// Health check in progress and when it was started
+    check: Option<(Child, Instant)>,
+    failures: u32,
+    /// Whether shipping the logs of the finished run was attempted
+    shipped: bool,
+}
+
+impl Tracked {
+    fn new(pid: u32) -> Self {
+        Self {
+            pid,
+            state: DaemonState::default(),
+            next_start: None,
+            backoff: FIRST_BACKOFF,
+            seen_running: Instant::now(),
+            next_health: Instant::now(),
+            check: None,
+            failures: 0,
+            shipped: false,
+        }
+    }
+}
+
+struct Agent {
+    root_dir: PathBuf,
+    daemons: HashMap<String, Tracked>,
+    /// Runs being stopped on purpose, which must not be restarted
+    held: HashSet<(String, u32)>,
+    stop_timeout: u64,
+}
+
+/// Entry point of `demon agent`: serve requests and apply policies until
+/// Ctrl+C or a `shutdown` request
+pub(crate) fn run_agent(root_dir: &Path) -> Result<()> {
+    if let Some(pid) = running(root_dir) {
+        return Err(anyhow::anyhow!("An agent is already running (PID {})", pid));
+    }
+    let socket = socket_path(root_dir);
+    // Nobody answered, so a leftover socket is stale
+    let _ = std::fs::remove_file(&socket);
+    let listener = UnixListener::bind(&socket)
+        .with_context(|| format!("Failed to bind {}", socket.display()))?;
+    listener.set_nonblocking(true)?;
+    IN_AGENT.store(true, Ordering::SeqCst);
+
+    let running = Arc::new(AtomicBool::new(true));
+    let r = running.clone();
+    ctrlc::set_handler(move || {
+        r.store(false, Ordering::SeqCst);
+    })?;
+
+    let config = config::Config::load(root_dir)?;
+    let mut agent = Agent {
+        root_dir: root_dir.to_path_buf(),
+        daemons: HashMap::new(),
+        held: HashSet::new(),
+        stop_timeout: config.integer("stop.timeout"),
+    };
+    tracing::info!("Agent listening on {}", socket.display());
+
+    let mut last_scan: Option<Instant> = None;
+    while running.load(Ordering::SeqCst) {
+        while let Ok((stream, _)) = listener.accept() {
+            match agent.handle_request(stream) {
+                Ok(true) => running.store(false, Ordering::SeqCst),
+                Ok(false) => {}
+                Err(e) => tracing::warn!("Failed to handle agent request: {}", e),
+            }
+        }
+        if last_scan.is_none_or(|at| at.elapsed() >= SCAN_INTERVAL) {
+            agent.scan();
+            last_scan = Some(Instant::now());
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+
+    // Only remove the socket if it is still ours
+    drop(listener);
+    let _ = std::fs::remove_file(&socket);
+    tracing::info!("Agent stopped");
+    Ok(())
+}
+
+impl Agent {
+    /// Answer one request; returns true on `shutdown`
+    fn handle_request(&mut self, stream: UnixStream) -> Result<bool> {
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+        let mut line = String::new();
+        BufReader::new(&stream).read_line(&mut line)?;
+
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        let reply = match parts.as_slice() {
+            ["ping"] => format!("ok {}", std::process::id()),
+            ["status", id] => {
+                let state = self
+                    .daemons
+                    .get(*id)
+                    .map(|tracked| tracked.state.clone())
+                    .unwrap_or_default();
+                format!("ok {}", serde_json::to_string(&state)?)
+            }
+            ["hold", id, pid] => match pid.parse::<u32>() {
+                Ok(pid) => {
+                    self.held.insert((id.to_string(), pid));
+                    "ok".to_string()
+                }
+                Err(_) => format!("error invalid PID '{pid}'"),
+            },
+            ["tail", id, streams] => {
+                let names: &[&str] = match *streams {
+                    "stdout" => &["stdout"],
+                    "stderr" => &["stderr"],
+                    _ => &["stdout", "stderr"],
+                };
+                let mut followers = Vec::new();
+                for name in names {
+                    let mut follower = Follower::new(build_file_path(&self.root_dir, id, name));
+                    // The client has already shown what was written so far
+                    follower.poll_lines()?;
+                    followers.push((name.to_string(), follower.with_batch(follow::BATCH)));
+                }
+                std::thread::spawn(move || stream_logs(stream, followers));
+                return Ok(false);
+            }
+            ["shutdown"] => {
+                writeln!(&stream, "ok")?;
+                return Ok(true);
+            }
+            _ => format!("error unknown request '{}'", line.trim()),
+        };
+        writeln!(&stream, "{reply}")?;
+        Ok(false)
+    }
+
+    /// Look at every daemon and apply its policy
+    fn scan(&mut self) {
+        self.reap_health_checks();
+
+        let ids = match daemon_ids(&self.root_dir) {
+            Ok(ids) => ids,
+            Err(e) => {
+                tracing::warn!("Failed to list daemons: {}", e);
+                return;
+            }
+        };
+        let mut seen = HashSet::new();
+        for id in ids {
+            let Ok(data) = PidFile::read(&self.root_dir, &id) else {
+                continue;
+            };
+            seen.insert(id.clone());
+            if let Err(e) = self.check_daemon(&id, &data) {
+                tracing::warn!("Failed to apply the policy of '{}': {}", id, e);
+            }
+        }
+        self.start_cron_jobs();
+        // Daemons that were stopped or cleaned are no longer managed
+        self.daemons.retain(|id, _| seen.contains(id));
+        self.held.retain(|(id, _)| seen.contains(id));
+        if let Err(e) = schedule::retain(&self.root_dir, |id| seen.contains(id)) {
+            tracing::warn!("Failed to update the schedule state: {}", e);
+        }
+    }
+
+    fn check_daemon(&mut self, id: &str, data: &PidFile) -> Result<()> {
+        let mut meta = DaemonMeta::read(&self.root_dir, id);
+        // Daemons and supervisors started by the agent are its children
+        if let Some(supervisor_pid) = meta.supervisor_pid {
+            reap(supervisor_pid);
+        }
+        if let Some(status) = reap(data.pid)
+            && !meta.supervised
+        {
+            meta.exit_code = status.code();
+            meta.exit_signal = status.signal();
+            meta.write(&self.root_dir, id)?;
+        }
+        let policy = &meta.policy;
+        let tracked = self
+            .daemons
+            .entry(id.to_string())
+            .or_insert_with(|| Tracked::new(data.pid));
+        if tracked.pid != data.pid {
+            // Started again by someone else; keep the restart count
+            let state = tracked.state.clone();
+            *tracked = Tracked::new(data.pid);
+            tracked.state = DaemonState {
+                restarts: state.restarts,
+                ..Default::default()
+            };
+        }
+
+        if is_daemon_running(&self.root_dir, id, data.pid) {
+            tracked.next_start = None;
+            tracked.state.next_start = None;
+            if tracked.seen_running.elapsed() >= STABLE_UPTIME {
+                tracked.backoff = FIRST_BACKOFF;
+            }
+            if let Some(command) = &policy.health_cmd
+                && tracked.check.is_none()
+                && Instant::now() >= tracked.next_health
+            {
+                tracked.check = Some((spawn_health_check(command, id, data.pid)?, Instant::now()));
+                tracked.next_health = Instant::now() + policy.health_interval();
+            }
+            return Ok(());
+        }
+
+        if self.held.contains(&(id.to_string(), data.pid)) {
+            return Ok(());
+        }
+        if !meta.supervised && !tracked.shipped {
+            tracked.shipped = true;
+            ship::ship_logs(&self.root_dir, id, &meta.hooks);
+        }
+
+        // The daemon has exited: decide whether and when to start it again
+        let failed = meta.exit_code != Some(0);
+        let (due, reason) = if let Some(every) = policy.every {
+            let next = schedule::next_run(
+                &self.root_dir,
+                id,
+                meta.started_at.unwrap_or_default(),
+                every,
+                policy.catch_up.unwrap_or_default(),
+                unix_now(),
+            )?;
+            (next, "scheduled".to_string())
+        } else if policy.restart == Some(Restart::Always)
+            || (policy.restart == Some(Restart::OnFailure) && failed)
+        {
+            let delay = tracked.backoff;
+            if tracked.next_start.is_none() {
+                tracked.next_start = Some(Instant::now() + delay);
+                tracked.backoff = (tracked.backoff * 2).min(MAX_BACKOFF);
+            }
+            let remaining = tracked.next_start.map_or(Duration::ZERO, |at| {
+                at.saturating_duration_since(Instant::now())
+            });
+            let detail = match (meta.exit_code, meta.exit_signal) {
+                (Some(code), _) => format!("exited with code {code}"),
+                (None, Some(signal)) => format!("killed by signal {signal}"),
+                (None, None) => "exited".to_string(),
+            };
+            (unix_now() + remaining.as_secs(), detail)
+        } else {
+            return Ok(());
+        };
+
+        tracked.state.next_start = Some(due);
+        if unix_now() < due || tracked.next_start.is_some_and(|at| Instant::now() < at) {
+            return Ok(());
+        }
+
+        tracing::info!("Starting '{}' again: {}", id, reason);
+        let scheduled = policy.every.is_some();
+        let pid = match restart(
+            &self.root_dir,
+            id,
+            &data.command,
+            &meta,
+            self.stop_timeout,
+            None,
+        ) {
+            Ok(pid) => pid,
+            Err(e) => {
+                // E.g. not enough free memory yet: try again after a backoff
+                // rather than on every scan
+                let tracked = self.daemons.get_mut(id).expect("tracked above");
+                tracked.next_start = Some(Instant::now() + tracked.backoff);
+                tracked.backoff = (tracked.backoff * 2).min(MAX_BACKOFF);
+                return Err(e);
+            }
+        };
+        let tracked = self.daemons.get_mut(id).expect("tracked above");
+        let restarts = tracked.state.restarts + u32::from(!scheduled);
+        *tracked = Tracked {
+            backoff: tracked.backoff,
+            ..Tracked::new(pid)
+        };
+        tracked.state.restarts = restarts;
+        if !scheduled {
+            events::emit(
+                &self.root_dir,
+                id,
+                events::Kind::Restarted,
+                Some(pid),
+                Some(reason),
+            );
+            fire(
+                &self.root_dir,
+                id,
+                events::Kind::Restarted,
+                pid,
+                &data.command,
+            );
+        }
+        Ok(())
+    }
+
+    /// Start the cron jobs whose trigger has come, under a supervisor so
+    /// their exit is recorded
+    fn start_cron_jobs(&mut self) {
+        let due = match schedule::due_cron_jobs(&self.root_dir, unix_now()) {
+            Ok(due) => due,
+            Err(e) => {
+                tracing::warn!("Failed to read the cron jobs: {}", e);
+                return;
+            }
+        };
+        for (id, command, requirements) in due {
+            tracing::info!("Starting cron job '{}'", id);
+            let options = RunOptions {
+                supervise: true,
+                policy: Policy {
+                    requirements,
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+            match run_daemon(&id, &command, &self.root_dir, &options) {
+                Ok(pid) => {
+                    self.daemons.insert(id, Tracked::new(pid));
+                }
+                Err(e) => tracing::warn!("Failed to start cron job '{}': {:#}", id, e),
+            }
+        }
+    }
+
+    /// Collect the results of finished health checks
+    fn reap_health_checks(&mut self) {
+        let mut unhealthy = Vec::new();
+        for (id, tracked) in &mut self.daemons {
+            let Some((child, started)) = &mut tracked.check else {
+                continue;
+            };
+            let passed = match child.try_wait() {
+                Ok(Some(status)) => status.success(),
+                Ok(None) if started.elapsed() < Duration::from_secs(DEFAULT_HEALTH_INTERVAL) => {
+                    continue;
+                }
+                // A check that hangs counts as failed
+                Ok(None) | Err(_) => {
+                    let _ = child.kill();
+                    let _ = child.wait();
+                    false
+                }
+            };
+            tracked.check = None;
+            if passed {
+                tracked.failures = 0;
+                tracked.state.healthy = Some(true);
+                continue;
+            }
+            tracked.failures += 1;
+            tracing::info!(
+                "Health check of '{}' failed ({}/{})",
+                id,
+                tracked.failures,
+                HEALTH_RETRIES
+            );
+            if tracked.failures >= HEALTH_RETRIES && tracked.state.healthy != Some(false) {
+                tracked.state.healthy = Some(false);
+                unhealthy.push((id.clone(), tracked.pid));
+            }
+        }
+
+        for (id, pid) in unhealthy {
+            if let Err(e) = self.handle_unhealthy(&id, pid) {
+                tracing::warn!("Failed to handle unhealthy daemon '{}': {}", id, e);
+            }
+        }
+    }
+
+    fn handle_unhealthy(&mut self, id: &str, pid: u32) -> Result<()> {
+        // The daemon may have been stopped while its last check was running
+        let data = match PidFile::read(&self.root_dir, id) {
+            Ok(data) if data.pid == pid => data,
+            _ => return Ok(()),
+        };
+        let detail = format!("{HEALTH_RETRIES} failed health checks");
+        events::emit(
+            &self.root_dir,
+            id,
+            events::Kind::Unhealthy,
+            Some(pid),
+            Some(detail.clone()),
+        );
+        fire(
+            &self.root_dir,
+            id,
+            events::Kind::Unhealthy,
+            pid,
+            &data.command,
+        );
+
+        let meta = DaemonMeta::read(&self.root_dir, id);
+        if matches!(meta.policy.restart, None | Some(Restart::Never)) {
+            return Ok(());
+        }
+        self.held.insert((id.to_string(), pid));
+        let new_pid = restart(
+            &self.root_dir,
+            id,
+            &data.command,
+            &meta,
+            self.stop_timeout,
+            Some(pid),
+        )?;
+        events::emit(
+            &self.root_dir,
+            id,
+            events::Kind::Restarted,
+            Some(new_pid),
+            Some(detail),
+        );
+        fire(
+            &self.root_dir,
+            id,
+            events::Kind::Restarted,
+            new_pid,
+            &data.command,
+        );
+        let tracked = self
+            .daemons
+            .get_mut(id)
+            .expect("unhealthy daemons are tracked");
+        let restarts = tracked.state.restarts + 1;
+        *tracked = Tracked::new(new_pid);
+        tracked.state.restarts = restarts;
+        Ok(())
+    }
+}
+
+/// Start a daemon again with the options it was started with, stopping the
+/// current run first if given
+fn restart(
+    root_dir: &Path,
+    id: &str,
+    command: &[String],
+    meta: &DaemonMeta,
+    stop_timeout: u64,
+    running_pid: Option<u32>,
+) -> Result<u32> {
+    if running_pid.is_some() {
+        stop_daemon(id, stop_timeout, root_dir)?;
+    }
+    let options = RunOptions {
+        supervise: meta.supervised,
+        pty: meta.pty,
+        stdin: meta.stdin.clone(),
+        hooks: meta.hooks.clone(),
+        policy: meta.policy.clone(),
+    };
+    run_daemon(id, command, root_dir, &options)
+}
+
+/// `demon restart`: stop daemon `id` if it runs and start it again with the
+/// options it was started with, returning the new PID
+pub(crate) fn restart_daemon(root_dir: &Path, id: &str, stop_timeout: u64) -> Result<u32> {
+    let data = match PidFile::read(root_dir, id) {
+        Ok(data) => data,
+        Err(PidFileReadError::FileNotFound) => {
+            return Err(Exit::new(exit::NOT_FOUND, format!("No daemon '{id}'")).into());
+        }
+        Err(e) => return Err(anyhow::anyhow!("Cannot restart '{}': {}", id, e)),
+    };
+    let meta = DaemonMeta::read(root_dir, id);
+    let running_pid = is_daemon_running(root_dir, id, data.pid).then_some(data.pid);
+    let pid = restart(
+        root_dir,
+        id,
+        &data.command,
+        &meta,
+        stop_timeout,
+        running_pid,
+    )?;
+    events::emit(
+        root_dir,
+        id,
+        events::Kind::Restarted,
+        Some(pid),
+        Some("demon restart".to_string()),
+    );
+    fire(root_dir, id, events::Kind::Restarted, pid, &data.command);
+    Ok(pid)
+}
+
+/// Run the hooks (and webhook) of a daemon for an event seen by the agent
+fn fire(root_dir: &Path, id: &str, event: events::Kind, pid: u32, command: &[String]) {
+    DaemonMeta::read(root_dir, id).hooks.fire(
+        root_dir,
+        id,
+        &hooks::HookEvent {
+            event,
+            pid,
+            command,
+            exit_code: None,
+            exit_signal: None,
+        },
+    );
+}
+
+/// Collect a child process that has exited, returning how it ended
+fn reap(pid: u32) -> Option<ExitStatus> {
+    let mut status = 0;
+    // SAFETY: waitpid only writes the status of the given process
+    let reaped = unsafe { libc::waitpid(pid as i32, &mut status, libc::WNOHANG) };
+    (reaped == pid as i32).then(|| ExitStatus::from_raw(status))
+}
+
+pub(crate) fn spawn_health_check(command: &str, id: &str, pid: u32) -> Result<Child> {
+    Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .env("DEMON_ID", id)
+        .env("DEMON_PID", pid.to_string())
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .with_context(|| format!("Failed to run health check '{command}'"))
+}
+
+/// Push new lines of the followed logs to a client until it disconnects
+fn stream_logs(stream: UnixStream, mut followers: Vec<(String, Follower)>) {
+    let mut writer = std::io::BufWriter::new(stream);
+    loop {
+        for (name, follower) in &mut followers {
+            let lines = match follower.poll_lines() {
+                Ok((rotated, lines)) => {
+                    if rotated && writeln!(writer, "rotated {name}").is_err() {
+                        return;
+                    }
+                    lines
+                }
+                Err(e) => {
+                    tracing::warn!("Failed to read {}: {}", follower.path().display(), e);
+                    continue;
+                }
+            };
+            for line in lines {
+                if writeln!(writer, "{name} {line}").is_err() {
+                    return;
+                }
+            }
+        }
+        if writer.flush().is_err() {
+            return;
+        }
+        // An empty write does not notice a closed connection, so probe it
+        if probe_closed(writer.get_ref()) {
+            return;
+        }
+        if !followers.iter().any(|(_, follower)| follower.is_behind()) {
+            std::thread::sleep(Duration::from_millis(100));
+        }
+    }
+}
+
+/// Whether the client end of a stream has been closed
+fn probe_closed(stream: &UnixStream) -> bool {
+    let mut byte = [0u8; 1];
+    if stream.set_nonblocking(true).is_err() {
+        return true;
+    }
+    // SAFETY: recv writes at most one byte into the buffer
+    let result = unsafe {
+        libc::recv(
+            std::os::fd::AsRawFd::as_raw_fd(stream),
+            byte.as_mut_ptr().cast(),
+            1,
+            libc::MSG_PEEK,
+        )
+    };
+    let _ = stream.set_nonblocking(false);
+    result == 0
+}
@@ -0,0 +1,235 @@
+//! `policy.toml`: what may be started from a root directory, for automation
+//! that drives demon with broad access to the file system.
+//!
+//! The file sits in the root directory and every key is optional:
+//!
+//! ```toml
+//! # Programs that may be run: a pattern without a slash matches a program
+//! # found in PATH by name, one with a slash matches the absolute path of the
+//! # program, wherever it was found
+//! programs = ["python3", "node", "/opt/app/bin/*"]
+//! # IDs that may be started
+//! ids = ["web", "worker-*"]
+//! # Daemons that may run at once
+//! max_daemons = 10
+//!
+//! # The most a daemon may ask for with --memory, --cpus and --limit-*
+//! [max]
+//! memory = "2G"
+//! cpus = 2
+//! limit_nofile = 4096
+//! ```
+//!
+//! It is enforced when a daemon starts (`run`, `up`, restarts by the agent,
+//! `serve`), which fails with the rule that was broken. Hooks (`--on-start`,
+//! ...) count as programs, and `--health-cmd` and shell lines in `demon.toml`
+//! run `sh`, so `sh` must be allowed for them. A flag with a maximum must be
+//! given, since without it the daemon is not limited at all or inherits the
+//! limit of whoever started it; the hard limit of `--limit-*` is compared.
+//! Without the file anything may be started.
+
+use crate::{
+    cgroup::deserialize_size,
+    format_bytes,
+    pidfile::daemon_ids,
+    runner::{RunOptions, resolve_program},
+    status::is_process_running,
+};
+use anyhow::{Context, Result};
+use glob::Pattern;
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+
+pub(crate) const FILE_NAME: &str = "policy.toml";
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Allowlist {
+    /// Patterns of the programs that may be run
+    programs: Option<Vec<String>>,
+    /// Patterns of the IDs that may be started
+    ids: Option<Vec<String>>,
+    /// How many daemons may run at once
+    max_daemons: Option<usize>,
+    /// The most the resource flags may ask for
+    #[serde(default)]
+    max: Max,
+}
+
+/// Maximums of the resource flags of `run`, by the name of the flag
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Max {
+    #[serde(default, deserialize_with = "deserialize_size")]
+    memory: Option<u64>,
+    cpus: Option<f64>,
+    #[serde(default, deserialize_with = "deserialize_size")]
+    limit_nofile: Option<u64>,
+    #[serde(default, deserialize_with = "deserialize_size")]
+    limit_core: Option<u64>,
+    #[serde(default, deserialize_with = "deserialize_size")]
+    limit_as: Option<u64>,
+    #[serde(default, deserialize_with = "deserialize_size")]
+    limit_nproc: Option<u64>,
+    #[serde(default, deserialize_with = "deserialize_size")]
+    limit_cpu: Option<u64>,
+    #[serde(default, deserialize_with = "deserialize_size")]
+    limit_fsize: Option<u64>,
+    #[serde(default, deserialize_with = "deserialize_size")]
+    limit_stack: Option<u64>,
+    #[serde(default, deserialize_with = "deserialize_size")]
+    limit_memlock: Option<u64>,
+}
+
+impl Max {
+    /// The maximums of the `--limit-*` flags, by the name of the flag without
+    /// `--limit-`
+    fn limits(&self) -> [(&'static str, Option<u64>); 8] {
+        [
+            ("nofile", self.limit_nofile),
+            ("core", self.limit_core),
+            ("as", self.limit_as),
+            ("nproc", self.limit_nproc),
+            ("cpu", self.limit_cpu),
+            ("fsize", self.limit_fsize),
+            ("stack", self.limit_stack),
+            ("memlock", self.limit_memlock),
+        ]
+    }
+
+    /// The rule broken by the first resource flag of `options` that is above
+    /// its maximum or missing
+    fn check(&self, options: &RunOptions) -> Option<String> {
+        let rule = |flag: &str, given: Option<String>, max: String| match given {
+            Some(given) => format!("{flag} {given} is above the {max} allowed"),
+            None => format!("Running without {flag} is not allowed"),
+        };
+        let resources = &options.policy.resources;
+        if let Some(max) = self.memory
+            && resources.memory.is_none_or(|memory| memory > max)
+        {
+            let given = resources.memory.map(format_bytes);
+            return Some(rule("--memory", given, format_bytes(max)));
+        }
+        if let Some(max) = self.cpus
+            && resources.cpus.is_none_or(|cpus| cpus > max)
+        {
+            let given = resources.cpus.map(|cpus| cpus.to_string());
+            return Some(rule("--cpus", given, max.to_string()));
+        }
+        for (name, max) in self.limits() {
+            let Some(max) = max else {
+                continue;
+            };
+            let limit = options
+                .policy
+                .limits
+                .set()
+                .find_map(|(set, limit)| (set == name).then_some(limit));
+            if limit.is_none_or(|limit| limit.hard().is_none_or(|hard| hard > max)) {
+                let given = limit.map(|limit| limit.to_string());
+                return Some(rule(&format!("--limit-{name}"), given, max.to_string()));
+            }
+        }
+        None
+    }
+}
+
+pub(crate) fn path(root_dir: &Path) -> PathBuf {
+    root_dir.join(FILE_NAME)
+}
+
+impl Allowlist {
+    /// The allowlist of a root directory, or None without one
+    pub(crate) fn load(root_dir: &Path) -> Result<Option<Self>> {
+        let path = path(root_dir);
+        let contents = match std::fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+        let allowlist: Allowlist = toml::from_str(&contents)
+            .with_context(|| format!("Invalid policy file {}", path.display()))?;
+        for pattern in allowlist.programs.iter().chain(&allowlist.ids).flatten() {
+            Pattern::new(pattern)
+                .with_context(|| format!("Invalid pattern '{}' in {}", pattern, path.display()))?;
+        }
+        Ok(Some(allowlist))
+    }
+
+    /// Fail unless the daemon may be started
+    pub(crate) fn check(
+        &self,
+        root_dir: &Path,
+        id: &str,
+        command: &[String],
+        options: &RunOptions,
+    ) -> Result<()> {
+        let denied = |rule: String| anyhow::anyhow!("{} by {}", rule, path(root_dir).display());
+        if let Some(ids) = &self.ids
+            && !ids.iter().any(|pattern| matches(pattern, id))
+        {
+            return Err(denied(format!("ID '{id}' is not allowed")));
+        }
+
+        let hooks = [
+            &options.hooks.on_start,
+            &options.hooks.on_exit,
+            &options.hooks.on_crash,
+        ];
+        let programs = command
+            .first()
+            .cloned()
+            .into_iter()
+            .chain(
+                hooks
+                    .into_iter()
+                    .flatten()
+                    .map(|hook| hook.display().to_string()),
+            )
+            .chain(options.policy.health_cmd.as_ref().map(|_| "sh".to_string()));
+        for program in programs {
+            if !self.allows_program(&program) {
+                return Err(denied(format!("Program '{program}' is not allowed")));
+            }
+        }
+
+        if let Some(rule) = self.max.check(options) {
+            return Err(denied(rule));
+        }
+
+        if let Some(max) = self.max_daemons {
+            let running = daemon_ids(root_dir)?
+                .iter()
+                .filter(|other| *other != id)
+                .filter(|other| is_process_running(root_dir, other).unwrap_or(false))
+                .count();
+            if running >= max {
+                return Err(denied(format!(
+                    "Already running {running} daemons, the most allowed"
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    fn allows_program(&self, program: &str) -> bool {
+        let Some(patterns) = &self.programs else {
+            return true;
+        };
+        let resolved = resolve_program(program)
+            .ok()
+            .and_then(|path| std::path::absolute(path).ok());
+        patterns.iter().any(|pattern| match pattern.contains('/') {
+            true => resolved
+                .as_ref()
+                .and_then(|path| path.to_str())
+                .is_some_and(|path| matches(pattern, path)),
+            false => !program.contains('/') && matches(pattern, program),
+        })
+    }
+}
+
+fn matches(pattern: &str, text: &str) -> bool {
+    Pattern::new(pattern).is_ok_and(|pattern| pattern.matches(text))
+}
@@ -0,0 +1,140 @@
+//! ANSI escape sequences in daemon output (`--strip-ansi`).
+//!
+//! Many programs color their output, or set the terminal title, even when it
+//! goes to a file, and the escape sequences end up in the logs where they get
+//! in the way of `grep` and log parsers. `cat`, `tail`, `grep` and `logs`
+//! remove them from what they print with `--strip-ansi`; given to `run`, the
+//! supervisor removes them from the output before it is written to the logs.
+//!
+//! Removed are CSI sequences (`ESC [`, colors and cursor movement), string
+//! sequences (`ESC ]` titles and hyperlinks, and `ESC P`, `ESC X`, `ESC ^`,
+//! `ESC _`) ended by BEL or `ESC \`, and the other two- and three-byte escapes.
+//! A newline always ends a sequence and is kept, so a malformed one cannot
+//! swallow the rest of a log.
+
+use std::fs::File;
+use std::io::{Read, Write};
+use std::os::fd::AsRawFd;
+
+const ESC: u8 = 0x1b;
+const BEL: u8 = 0x07;
+
+/// Where in an escape sequence the last byte left off
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum State {
+    #[default]
+    Text,
+    /// After ESC
+    Escape,
+    /// After ESC and intermediate bytes, before the final byte
+    Intermediate,
+    /// In a control sequence, `ESC [` up to its final byte
+    Csi,
+    /// In a string, `ESC ]` and the like up to BEL or `ESC \`
+    String,
+    /// After ESC in a string
+    StringEscape,
+}
+
+/// Removes escape sequences from a stream, also when they are split between
+/// the chunks it is given
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Stripper {
+    state: State,
+}
+
+impl Stripper {
+    /// The next chunk of the stream without escape sequences
+    pub(crate) fn strip(&mut self, input: &[u8]) -> Vec<u8> {
+        let mut output = Vec::with_capacity(input.len());
+        for &byte in input {
+            self.state = match (self.state, byte) {
+                (State::Text, ESC) => State::Escape,
+                (_, b'\n') | (State::Text, _) => {
+                    output.push(byte);
+                    State::Text
+                }
+                (State::Escape, b'[') => State::Csi,
+                (State::Escape, b']' | b'P' | b'X' | b'^' | b'_') => State::String,
+                (State::Escape | State::Intermediate, 0x20..=0x2f) => State::Intermediate,
+                (State::Escape | State::Intermediate, 0x30..=0x7e) => State::Text,
+                // Not an escape sequence after all: drop the ESC only
+                (State::Escape | State::Intermediate, _) => {
+                    output.push(byte);
+                    State::Text
+                }
+                (State::Csi, 0x40..=0x7e) => State::Text,
+                (State::Csi, _) => State::Csi,
+                (State::String, BEL) | (State::StringEscape, b'\\') => State::Text,
+                (State::String | State::StringEscape, ESC) => State::StringEscape,
+                (State::String | State::StringEscape, _) => State::String,
+            };
+        }
+        output
+    }
+}
+
+/// `text` without escape sequences
+pub(crate) fn strip(text: &[u8]) -> Vec<u8> {
+    Stripper::default().strip(text)
+}
+
+/// A pipe the daemon writes stdout or stderr to, which the supervisor copies
+/// to the log without escape sequences
+pub(crate) struct Capture {
+    pipe: Option<File>,
+    log: File,
+    stripper: Stripper,
+}
+
+impl Capture {
+    pub(crate) fn new(pipe: File, log: File) -> Self {
+        Self {
+            pipe: Some(pipe),
+            log,
+            stripper: Stripper::default(),
+        }
+    }
+
+    /// The pipe to wait on, or -1 once the daemon closed it
+    pub(crate) fn pollfd(&self) -> libc::pollfd {
+        libc::pollfd {
+            fd: self.pipe.as_ref().map_or(-1, |pipe| pipe.as_raw_fd()),
+            events: libc::POLLIN,
+            revents: 0,
+        }
+    }
+
+    /// Copy what the daemon wrote to the log. Returns false once the pipe is
+    /// closed, by the daemon and every process it left behind.
+    pub(crate) fn read(&mut self) -> bool {
+        let Some(pipe) = &mut self.pipe else {
+            return false;
+        };
+        let mut buffer = [0; 8192];
+        let read = match pipe.read(&mut buffer) {
+            Ok(0) | Err(_) => {
+                self.pipe = None;
+                return false;
+            }
+            Ok(read) => read,
+        };
+        if let Err(e) = self.log.write_all(&self.stripper.strip(&buffer[..read])) {
+            tracing::warn!("Failed to write output to the log: {}", e);
+        }
+        true
+    }
+
+    /// Copy the output still in the pipe once the daemon has exited
+    pub(crate) fn finish(mut self) {
+        loop {
+            let mut fds = [self.pollfd()];
+            // SAFETY: fds is a valid array of pollfd for the duration of the call
+            let ready = unsafe { libc::poll(fds.as_mut_ptr(), 1, 0) };
+            // Processes the daemon left behind may keep the pipe open
+            if ready <= 0 || fds[0].revents == 0 || !self.read() {
+                break;
+            }
+        }
+    }
+}
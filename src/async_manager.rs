@@ -0,0 +1,263 @@
+//! [`AsyncDaemonManager`]: the library API for tokio services and test
+//! frameworks, behind the `async` feature.
+//!
+//! Calls that touch the root directory run on tokio's blocking thread pool, so
+//! they never stall the runtime; waiting for a daemon to stop or exit and
+//! following logs poll with [`tokio::time::sleep`] and hold no thread while
+//! idle.
+
+use crate::{
+    follow::{self, Follower},
+    logs::Stream,
+    manager::DaemonManager,
+    pidfile::build_file_path,
+    runner::RunOptions,
+    status::DaemonStatus,
+    stop::{self, SIGKILL_WAIT, Sigterm, Stopped},
+};
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+
+/// How often [`AsyncDaemonManager::stop`], [`AsyncDaemonManager::wait`] and
+/// [`AsyncDaemonManager::tail`] look at the daemon again
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Lines buffered by [`AsyncDaemonManager::tail`] for a slow consumer
+const TAIL_BUFFER: usize = 1024;
+
+/// Async counterpart of [`DaemonManager`], sharing the same root directory
+/// files.
+///
+/// ```no_run
+/// use demon::{AsyncDaemonManager, RunOptions, Stream};
+/// use std::time::Duration;
+/// use tokio_stream::StreamExt;
+///
+/// # async fn example() -> anyhow::Result<()> {
+/// let manager = AsyncDaemonManager::new("/tmp/demon")?;
+/// let command = ["python3", "-m", "http.server", "8080"].map(String::from);
+/// manager.spawn("web", &command, &RunOptions::default()).await?;
+/// let mut lines = manager.tail("web", Stream::Stderr);
+/// while let Some(line) = lines.next().await {
+///     if line?.contains("Serving HTTP") {
+///         break;
+///     }
+/// }
+/// manager.stop("web", Duration::from_secs(10)).await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct AsyncDaemonManager {
+    inner: DaemonManager,
+}
+
+impl AsyncDaemonManager {
+    /// Manage the daemons in an existing directory
+    pub fn new(root_dir: impl Into<PathBuf>) -> Result<Self> {
+        Ok(Self {
+            inner: DaemonManager::new(root_dir)?,
+        })
+    }
+
+    /// Manage the daemons in `.demon` at the root of the git repository
+    /// containing the current directory, like the command line does
+    pub fn discover() -> Result<Self> {
+        Ok(Self {
+            inner: DaemonManager::discover()?,
+        })
+    }
+
+    /// Directory holding the PID files and logs
+    pub fn root_dir(&self) -> &Path {
+        self.inner.root_dir()
+    }
+
+    /// The blocking manager of the same root directory
+    pub fn blocking(&self) -> &DaemonManager {
+        &self.inner
+    }
+
+    /// Run a blocking call of the inner manager on the blocking thread pool
+    async fn run<T, F>(&self, call: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&DaemonManager) -> Result<T> + Send + 'static,
+    {
+        let inner = self.inner.clone();
+        tokio::task::spawn_blocking(move || call(&inner))
+            .await
+            .map_err(|e| anyhow::anyhow!("Daemon operation failed: {}", e))?
+    }
+
+    /// Start `command` in the background as daemon `id`, returning its PID.
+    /// Fails if a daemon with the same ID is already running.
+    pub async fn spawn(&self, id: &str, command: &[String], options: &RunOptions) -> Result<u32> {
+        let (id, command, options) = (id.to_string(), command.to_vec(), options.clone());
+        self.run(move |manager| manager.spawn(&id, &command, &options))
+            .await
+    }
+
+    /// Stop a daemon with SIGTERM, sending SIGKILL if it is still running
+    /// after `timeout`
+    pub async fn stop(&self, id: &str, timeout: Duration) -> Result<Stopped> {
+        let (id, secs) = (id.to_string(), timeout.as_secs());
+        let stopping = match self
+            .run(move |manager| stop::send_sigterm(&id, secs, manager.root_dir()))
+            .await?
+        {
+            Sigterm::Sent(stopping) => stopping,
+            Sigterm::Skipped(stopped) => return Ok(stopped),
+        };
+
+        // Checking once more at the deadline, so a daemon that exited
+        // meanwhile is not sent SIGKILL
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(secs);
+        loop {
+            let step = stopping.clone();
+            if let Some(stopped) = self
+                .run(move |manager| step.terminated(manager.root_dir()))
+                .await?
+            {
+                return Ok(stopped);
+            }
+            let now = tokio::time::Instant::now();
+            if now >= deadline {
+                break;
+            }
+            tokio::time::sleep(POLL_INTERVAL.min(deadline - now)).await;
+        }
+
+        let step = stopping.clone();
+        self.run(move |manager| step.send_sigkill(manager.root_dir()))
+            .await?;
+        let deadline = tokio::time::Instant::now() + SIGKILL_WAIT;
+        loop {
+            tokio::time::sleep(POLL_INTERVAL).await;
+            let step = stopping.clone();
+            if let Some(stopped) = self
+                .run(move |manager| step.killed(manager.root_dir()))
+                .await?
+            {
+                return Ok(stopped);
+            }
+            if tokio::time::Instant::now() >= deadline {
+                return Err(stopping.still_running());
+            }
+        }
+    }
+
+    /// The status of a daemon, or None if there is no daemon with this ID
+    pub async fn status(&self, id: &str) -> Result<Option<DaemonStatus>> {
+        let id = id.to_string();
+        self.run(move |manager| manager.status(&id)).await
+    }
+
+    /// The status of every daemon, sorted by ID
+    pub async fn list(&self) -> Result<Vec<DaemonStatus>> {
+        self.run(|manager| manager.list()).await
+    }
+
+    /// Wait for a running daemon to exit, for at most `timeout` (forever if
+    /// None). Fails if the daemon is not running or the timeout is reached.
+    pub async fn wait(&self, id: &str, timeout: Option<Duration>) -> Result<()> {
+        match self.status(id).await? {
+            None => return Err(anyhow::anyhow!("Process '{}' not found (no PID file)", id)),
+            Some(status) if !status.running => {
+                return Err(anyhow::anyhow!("Process '{}' is not running", id));
+            }
+            Some(_) => {}
+        }
+        let exited = async {
+            loop {
+                tokio::time::sleep(POLL_INTERVAL).await;
+                if !self.status(id).await?.is_some_and(|status| status.running) {
+                    return Ok(());
+                }
+            }
+        };
+        match timeout {
+            Some(timeout) => tokio::time::timeout(timeout, exited).await.map_err(|_| {
+                anyhow::anyhow!("Timeout reached waiting for process '{}' to terminate", id)
+            })?,
+            None => exited.await,
+        }
+    }
+
+    /// The output of a daemon, or only its last `lines` lines
+    pub async fn logs(&self, id: &str, stream: Stream, lines: Option<usize>) -> Result<String> {
+        let id = id.to_string();
+        self.run(move |manager| manager.logs(&id, stream, lines))
+            .await
+    }
+
+    /// Lines written to a log from now on, without their line terminators.
+    /// When the daemon is restarted the stream continues with the new run.
+    /// The stream ends after the first error, and stops following the log once
+    /// it is dropped.
+    ///
+    /// Must be called within a tokio runtime.
+    pub fn tail(&self, id: &str, stream: Stream) -> ReceiverStream<Result<String>> {
+        let (sender, receiver) = mpsc::channel(TAIL_BUFFER);
+        // Start at the end, skipping what was written before without reading
+        // it; only the first bytes are read, to notice the log being replaced
+        let started = Follower::from_last_lines(
+            build_file_path(self.inner.root_dir(), id, stream.extension()),
+            0,
+            0,
+        )
+        .map(|follower| follower.with_batch(follow::BATCH));
+        tokio::spawn(async move {
+            let mut follower = match started {
+                Ok(follower) => follower,
+                Err(e) => {
+                    let _ = sender.send(Err(e)).await;
+                    return;
+                }
+            };
+            loop {
+                // A poll stopped at the batch size reads the rest right away
+                if !follower.is_behind() {
+                    tokio::time::sleep(POLL_INTERVAL).await;
+                }
+                let polled = tokio::task::spawn_blocking(move || {
+                    let lines = follower.poll_lines();
+                    (follower, lines)
+                })
+                .await;
+                let lines = match polled {
+                    Ok((polled, lines)) => {
+                        follower = polled;
+                        lines
+                    }
+                    Err(e) => {
+                        let _ = sender
+                            .send(Err(anyhow::anyhow!("Failed to read the log: {}", e)))
+                            .await;
+                        return;
+                    }
+                };
+                match lines {
+                    Ok((_, lines)) => {
+                        for line in lines {
+                            if sender.send(Ok(line)).await.is_err() {
+                                return;
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        let _ = sender.send(Err(e)).await;
+                        return;
+                    }
+                }
+                if sender.is_closed() {
+                    return;
+                }
+            }
+        });
+        ReceiverStream::new(receiver)
+    }
+}
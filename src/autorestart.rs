@@ -0,0 +1,218 @@
+//! Restarting a daemon when its files change (`--watch`), for a dev loop in
+//! the style of watchexec or cargo-watch.
+//!
+//! The supervisor watches the paths recursively and, once the changes have
+//! settled for `dev.debounce` milliseconds, terminates the daemon's process
+//! group and starts the command again. A daemon that exits on its own is
+//! started again on the next change, until it is stopped or replaced. Changes
+//! in the root directory, version control directories, editor scratch files
+//! and paths matching a `--watch-ignore` glob never restart it.
+
+use crate::{config::Config, dev, pidfile::PidFile, watch};
+use anyhow::{Context, Result};
+use clap::Args;
+use glob::Pattern;
+use notify::{Event, RecursiveMode, Watcher};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{Receiver, RecvTimeoutError, channel};
+use std::time::{Duration, Instant};
+
+/// How often a daemon that exited is checked for being stopped or replaced
+/// while waiting for changes
+const TICK: Duration = Duration::from_millis(100);
+
+/// Watch flags of `run`
+#[derive(Args, Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct Watch {
+    /// Restart the daemon when a file under PATH changes; repeatable
+    /// (implies --supervise)
+    #[arg(long, value_name = "PATH")]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub(crate) watch: Vec<PathBuf>,
+
+    /// Ignore changes to paths matching this glob, relative to the watched
+    /// path, e.g. 'target' or '*.log'; repeatable
+    #[arg(long, value_name = "GLOB", requires = "watch", value_parser = parse_glob)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub(crate) watch_ignore: Vec<String>,
+}
+
+fn parse_glob(value: &str) -> Result<String, String> {
+    Pattern::new(value)
+        .map(|_| value.to_string())
+        .map_err(|e| format!("invalid glob '{value}': {e}"))
+}
+
+impl Watch {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.watch.is_empty()
+    }
+
+    /// Make the paths absolute, so the supervisor watches the same files
+    /// wherever the daemon is restarted from
+    pub(crate) fn resolve(&mut self) -> Result<()> {
+        for path in &mut self.watch {
+            *path = std::path::absolute(&*path)?;
+        }
+        Ok(())
+    }
+
+    /// Fail before spawning the daemon when a path cannot be watched
+    pub(crate) fn check(&self) -> Result<()> {
+        for path in &self.watch {
+            if !path.exists() {
+                anyhow::bail!("Cannot watch {}: it does not exist", path.display());
+            }
+        }
+        Ok(())
+    }
+
+    /// Command line flags that recreate these settings
+    pub(crate) fn to_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        for path in &self.watch {
+            args.extend(["--watch".to_string(), path.display().to_string()]);
+        }
+        for glob in &self.watch_ignore {
+            args.extend(["--watch-ignore".to_string(), glob.clone()]);
+        }
+        args
+    }
+
+    /// One line describing what is watched, for `demon status`
+    pub(crate) fn summary(&self) -> String {
+        let paths: Vec<String> = self
+            .watch
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect();
+        if self.watch_ignore.is_empty() {
+            paths.join(", ")
+        } else {
+            format!(
+                "{} (ignoring {})",
+                paths.join(", "),
+                self.watch_ignore.join(", ")
+            )
+        }
+    }
+}
+
+/// Changes to the watched paths of a daemon, kept by its supervisor across
+/// restarts
+pub(crate) struct Changes {
+    _watcher: Box<dyn Watcher>,
+    rx: Receiver<notify::Result<Event>>,
+    paths: Vec<PathBuf>,
+    ignore: Vec<Pattern>,
+    root_dir: PathBuf,
+    debounce: Duration,
+    /// Time of the last relevant change and the file that changed first
+    pending: Option<(Instant, PathBuf)>,
+}
+
+impl Changes {
+    /// Start watching, or None when the daemon was not started with --watch
+    pub(crate) fn watch(root_dir: &Path, settings: &Watch) -> Result<Option<Changes>> {
+        if settings.is_empty() {
+            return Ok(None);
+        }
+        settings.check()?;
+        let ignore = settings
+            .watch_ignore
+            .iter()
+            .map(|glob| Pattern::new(glob).with_context(|| format!("Invalid glob '{glob}'")))
+            .collect::<Result<Vec<_>>>()?;
+        let config = Config::load_lenient(Some(root_dir));
+        let (tx, rx) = channel();
+        let recursive: Vec<_> = settings
+            .watch
+            .iter()
+            .map(|path| (path.as_path(), RecursiveMode::Recursive))
+            .collect();
+        let watcher = watch::watcher(&recursive, tx, config.bool("watch.poll"))
+            .context("Failed to watch for changes")?;
+        Ok(Some(Changes {
+            _watcher: watcher,
+            rx,
+            paths: settings.watch.clone(),
+            ignore,
+            root_dir: std::fs::canonicalize(root_dir).unwrap_or_else(|_| root_dir.to_path_buf()),
+            debounce: Duration::from_millis(config.integer("dev.debounce")),
+            pending: None,
+        }))
+    }
+
+    /// The first file that changed, once no change was seen for the debounce
+    /// interval
+    pub(crate) fn settled(&mut self) -> Option<PathBuf> {
+        loop {
+            match self.rx.try_recv() {
+                Ok(Ok(event)) => self.record(&event),
+                Ok(Err(e)) => tracing::error!("Watch error: {:?}", e),
+                Err(_) => break,
+            }
+        }
+        match &self.pending {
+            Some((at, _)) if at.elapsed() >= self.debounce => {
+                self.pending.take().map(|(_, path)| path)
+            }
+            _ => None,
+        }
+    }
+
+    /// Wait for the next change after the daemon exited on its own, or None
+    /// once the PID file no longer names that run (stopped or replaced)
+    pub(crate) fn next(&mut self, root_dir: &Path, id: &str, pid: u32) -> Option<PathBuf> {
+        loop {
+            if !PidFile::read(root_dir, id).is_ok_and(|data| data.pid == pid) {
+                return None;
+            }
+            match self.rx.recv_timeout(TICK) {
+                Ok(Ok(event)) => self.record(&event),
+                Ok(Err(e)) => tracing::error!("Watch error: {:?}", e),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => return None,
+            }
+            if let Some(path) = self.settled() {
+                return Some(path);
+            }
+        }
+    }
+
+    fn record(&mut self, event: &Event) {
+        let ignored: Vec<String> = dev::ALWAYS_IGNORED.iter().map(|s| s.to_string()).collect();
+        if !dev::is_relevant(event, &ignored, &self.root_dir) {
+            return;
+        }
+        let Some(path) = event.paths.iter().find(|path| !self.is_ignored(path)) else {
+            return;
+        };
+        let first = self
+            .pending
+            .take()
+            .map_or_else(|| path.clone(), |(_, first)| first);
+        self.pending = Some((Instant::now(), first));
+    }
+
+    /// Whether a glob matches the path relative to the watched path it is
+    /// in, one of its parent directories or its file name
+    fn is_ignored(&self, path: &Path) -> bool {
+        let relative = self
+            .paths
+            .iter()
+            .find_map(|watched| path.strip_prefix(watched).ok())
+            .filter(|relative| !relative.as_os_str().is_empty());
+        let name = path.file_name().map(Path::new);
+        self.ignore.iter().any(|pattern| {
+            relative.is_some_and(|relative| {
+                relative
+                    .ancestors()
+                    .filter(|ancestor| !ancestor.as_os_str().is_empty())
+                    .any(|ancestor| pattern.matches_path(ancestor))
+            }) || name.is_some_and(|name| pattern.matches_path(name))
+        })
+    }
+}
@@ -0,0 +1,173 @@
+//! `demon bench`: run a command several times and compare the runs, like
+//! hyperfine but with the command managed as a daemon.
+//!
+//! Every run is an ordinary supervised run of the daemon, so its output is
+//! captured in the logs and it shows up in `demon history` and the event log.
+//! The supervisor measures the run when it reaps the command: the wall-clock
+//! time since it was spawned, and the CPU time and peak RSS reported by wait4.
+
+use crate::{
+    format_bytes,
+    history::{self, RunRecord},
+    runner::{RunOptions, run_daemon},
+    status::is_daemon_running,
+};
+use anyhow::Result;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// How often a run is checked for having finished
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// How long the supervisor may take to record a finished run
+const RECORD_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Mean, median, sample standard deviation, minimum and maximum
+struct Summary {
+    mean: f64,
+    median: f64,
+    stddev: f64,
+    min: f64,
+    max: f64,
+}
+
+impl Summary {
+    fn new(values: &[f64]) -> Self {
+        let mut sorted = values.to_vec();
+        sorted.sort_by(f64::total_cmp);
+        let count = sorted.len() as f64;
+        let mean = sorted.iter().sum::<f64>() / count;
+        let middle = sorted.len() / 2;
+        let median = match sorted.len() % 2 {
+            0 => (sorted[middle - 1] + sorted[middle]) / 2.0,
+            _ => sorted[middle],
+        };
+        let variance = match sorted.len() {
+            1 => 0.0,
+            _ => {
+                sorted
+                    .iter()
+                    .map(|value| (value - mean).powi(2))
+                    .sum::<f64>()
+                    / (count - 1.0)
+            }
+        };
+        Self {
+            mean,
+            median,
+            stddev: variance.sqrt(),
+            min: sorted[0],
+            max: sorted[sorted.len() - 1],
+        }
+    }
+
+    fn print(&self, name: &str, format: impl Fn(f64) -> String) {
+        println!(
+            "{:<10} {:<10} {:<10} {:<10} {:<10} {}",
+            name,
+            format(self.mean),
+            format(self.median),
+            format(self.stddev),
+            format(self.min),
+            format(self.max)
+        );
+    }
+}
+
+/// Format milliseconds, e.g. `850.2ms` or `1.204s`
+fn format_millis(millis: f64) -> String {
+    if millis < 1000.0 {
+        format!("{millis:.1}ms")
+    } else {
+        format!("{:.3}s", millis / 1000.0)
+    }
+}
+
+/// Wait for a run to finish and return its history record
+fn wait_for_record(root_dir: &Path, id: &str, pid: u32) -> Result<RunRecord> {
+    while is_daemon_running(root_dir, id, pid) {
+        std::thread::sleep(POLL_INTERVAL);
+    }
+    // The supervisor records the run right after reaping it
+    let deadline = Instant::now() + RECORD_TIMEOUT;
+    loop {
+        let record = history::read(root_dir, id)?
+            .into_iter()
+            .rev()
+            .find(|record| record.pid == pid);
+        if let Some(record) = record {
+            return Ok(record);
+        }
+        if Instant::now() >= deadline {
+            return Err(anyhow::anyhow!(
+                "Run of '{}' (PID {}) ended without being recorded in the history",
+                id,
+                pid
+            ));
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// Run `command` `runs` times one after the other as the supervised daemon
+/// `id`, then print statistics of the runs. Stops at the first failed run.
+pub(crate) fn bench(
+    id: &str,
+    command: &[String],
+    root_dir: &Path,
+    runs: u32,
+    options: &RunOptions,
+) -> Result<()> {
+    let options = RunOptions {
+        supervise: true,
+        ..options.clone()
+    };
+
+    let mut wall = Vec::new();
+    let mut cpu = Vec::new();
+    let mut rss = Vec::new();
+    for run in 1..=runs {
+        let pid = run_daemon(id, command, root_dir, &options)?;
+        let record = wait_for_record(root_dir, id, pid)?;
+        if record.exit_code != Some(0) {
+            return Err(anyhow::anyhow!(
+                "Run {} of '{}' failed with exit {}; see 'demon cat {}'",
+                run,
+                id,
+                record.exit().unwrap_or_else(|| "unknown".to_string()),
+                id
+            ));
+        }
+
+        let mut line = format!("Run {run}/{runs}:");
+        if let Some(wall_ms) = record.wall_ms {
+            wall.push(wall_ms as f64);
+            line.push_str(&format!(" wall {}", format_millis(wall_ms as f64)));
+        }
+        if let Some(cpu_ms) = record.cpu_ms {
+            cpu.push(cpu_ms as f64);
+            line.push_str(&format!(", CPU {}", format_millis(cpu_ms as f64)));
+        }
+        if let Some(peak_rss) = record.peak_rss {
+            rss.push(peak_rss as f64);
+            line.push_str(&format!(", peak RSS {}", format_bytes(peak_rss)));
+        }
+        println!("{line}");
+    }
+
+    println!();
+    println!(
+        "{:<10} {:<10} {:<10} {:<10} {:<10} MAX",
+        "", "MEAN", "MEDIAN", "STDDEV", "MIN"
+    );
+    if !wall.is_empty() {
+        Summary::new(&wall).print("Wall", format_millis);
+    }
+    if !cpu.is_empty() {
+        Summary::new(&cpu).print("CPU", format_millis);
+    }
+    if !rss.is_empty() {
+        Summary::new(&rss).print("Peak RSS", |bytes| format_bytes(bytes.round() as u64));
+    }
+    Ok(())
+}
@@ -0,0 +1,281 @@
+//! `demon report --bundle`: diagnostics for a bug report, as one tar.gz.
+//!
+//! The archive holds a directory `demon-report-<time>/` with the report in
+//! Markdown and JSON, the recent events of the daemons, the configuration and
+//! manifest, information about the machine, and for every daemon its metadata,
+//! the environment of its process and the last `--log-kb` kilobytes of each
+//! log. With `--redact` the values of environment variables are replaced, so
+//! the bundle can be attached to a public issue.
+//!
+//! The archive is written in the ustar format by hand; compressing it needs
+//! the `log-shipping` feature, which brings in flate2 and is enabled by
+//! default.
+
+use crate::{
+    agent, events, format_bytes,
+    logs::Stream,
+    manifest,
+    pidfile::{DaemonMeta, build_file_path},
+    report::{self, ReportOptions},
+    snapshot::file_stamp,
+    unix_now,
+};
+use anyhow::{Context, Result};
+use serde_json::Value;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+/// Replaces the values of environment variables with `--redact`
+const REDACTED: &str = "[redacted]";
+
+pub(crate) struct BundleOptions {
+    /// Where to write the archive, or None for `demon-report-<time>.tar.gz`
+    pub(crate) path: Option<PathBuf>,
+    /// Kilobytes at the end of each log to include
+    pub(crate) log_kb: u64,
+    /// Whether to hide the values of environment variables
+    pub(crate) redact: bool,
+}
+
+/// Write the bundle of the daemons in `options`
+pub(crate) fn write_bundle(
+    root_dir: &Path,
+    options: &ReportOptions,
+    bundle: &BundleOptions,
+) -> Result<()> {
+    if !cfg!(feature = "log-shipping") {
+        return Err(anyhow::anyhow!(
+            "--bundle needs demon built with the log-shipping feature"
+        ));
+    }
+    let now = unix_now();
+    let name = format!("demon-report-{}", file_stamp(now));
+    let path = bundle
+        .path
+        .clone()
+        .unwrap_or_else(|| PathBuf::from(format!("{name}.tar.gz")));
+
+    let (daemons, events) = report::collect(root_dir, options)?;
+    let ids: Vec<&str> = daemons
+        .iter()
+        .filter_map(|daemon| daemon["id"].as_str())
+        .collect();
+    let mut tar = Tar::new(now);
+    tar.append(
+        &format!("{name}/report.md"),
+        report::markdown(root_dir, options, &daemons, &events).as_bytes(),
+    )?;
+    let record = report::json(root_dir, options, &daemons, &events);
+    tar.append(
+        &format!("{name}/report.json"),
+        serde_json::to_string_pretty(&record)?.as_bytes(),
+    )?;
+    tar.append(
+        &format!("{name}/environment.json"),
+        serde_json::to_string_pretty(&environment(root_dir, bundle.redact))?.as_bytes(),
+    )?;
+
+    // Every event, not only the disruptions of the report
+    let filter = events::Filter {
+        id: None,
+        since: Some(options.since),
+    };
+    let lines: String = events::read(root_dir, &filter)?
+        .iter()
+        .filter(|event| ids.contains(&event.id.as_str()))
+        .filter_map(|event| serde_json::to_string(event).ok())
+        .map(|line| line + "\n")
+        .collect();
+    tar.append(&format!("{name}/events.jsonl"), lines.as_bytes())?;
+
+    for (file, source) in [
+        ("config.toml", root_dir.join("config.toml")),
+        ("demon.toml", manifest::path(root_dir)),
+    ] {
+        if let Ok(contents) = std::fs::read(&source) {
+            tar.append(&format!("{name}/{file}"), &contents)?;
+        }
+    }
+
+    for daemon in &daemons {
+        let id = daemon["id"].as_str().unwrap_or_default();
+        let meta = DaemonMeta::read(root_dir, id);
+        tar.append(
+            &format!("{name}/{id}/meta.json"),
+            serde_json::to_string_pretty(&meta)?.as_bytes(),
+        )?;
+        if let Some(pid) = daemon["pid"].as_u64()
+            && daemon["status"] == "RUNNING"
+            && let Some(environ) = environ(pid, bundle.redact)
+        {
+            tar.append(&format!("{name}/{id}/environ.txt"), environ.as_bytes())?;
+        }
+        for stream in [Stream::Stdout, Stream::Stderr] {
+            let log = build_file_path(root_dir, id, stream.extension());
+            tar.append(
+                &format!("{name}/{id}/{}.log", stream.extension()),
+                &log_tail(&log, bundle.log_kb * 1024),
+            )?;
+        }
+    }
+
+    let files = tar.files;
+    compress(&tar.finish(), &path)
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    let size = std::fs::metadata(&path).map_or(0, |metadata| metadata.len());
+    println!(
+        "Wrote {} ({} files, {})",
+        path.display(),
+        files,
+        format_bytes(size)
+    );
+    Ok(())
+}
+
+/// The machine and the demon build, for `environment.json`
+fn environment(root_dir: &Path, redact: bool) -> Value {
+    let kernel = std::fs::read_to_string("/proc/sys/kernel/osrelease")
+        .map(|release| release.trim().to_string())
+        .ok();
+    let variables: serde_json::Map<String, Value> = std::env::vars()
+        .filter(|(name, _)| name.starts_with("DEMON_"))
+        .map(|(name, value)| (name, redacted(value, redact).into()))
+        .collect();
+    serde_json::json!({
+        "demon_version": env!("CARGO_PKG_VERSION"),
+        "os": std::env::consts::OS,
+        "arch": std::env::consts::ARCH,
+        "kernel": kernel,
+        "root_dir": root_dir,
+        "agent_pid": agent::running(root_dir),
+        "env": variables,
+    })
+}
+
+/// The environment of a process as `NAME=value` lines
+fn environ(pid: u64, redact: bool) -> Option<String> {
+    let contents = std::fs::read(format!("/proc/{pid}/environ")).ok()?;
+    let lines = contents
+        .split(|&byte| byte == 0)
+        .filter(|variable| !variable.is_empty())
+        .map(|variable| {
+            let variable = String::from_utf8_lossy(variable);
+            match variable.split_once('=') {
+                Some((name, value)) => format!("{name}={}\n", redacted(value.to_string(), redact)),
+                None => format!("{variable}\n"),
+            }
+        })
+        .collect();
+    Some(lines)
+}
+
+fn redacted(value: String, redact: bool) -> String {
+    if redact { REDACTED.to_string() } else { value }
+}
+
+/// The last `bytes` of a log, from the first whole line
+fn log_tail(path: &Path, bytes: u64) -> Vec<u8> {
+    let Ok(mut file) = File::open(path) else {
+        return Vec::new();
+    };
+    let start = file
+        .metadata()
+        .map_or(0, |metadata| metadata.len())
+        .saturating_sub(bytes);
+    let mut contents = Vec::new();
+    if file.seek(SeekFrom::Start(start)).is_err() || file.read_to_end(&mut contents).is_err() {
+        return Vec::new();
+    }
+    if start > 0
+        && let Some(newline) = contents.iter().position(|&byte| byte == b'\n')
+    {
+        contents.drain(..=newline);
+    }
+    contents
+}
+
+/// An uncompressed tar archive in memory
+struct Tar {
+    data: Vec<u8>,
+    /// Modification time of the files
+    mtime: u64,
+    files: usize,
+}
+
+impl Tar {
+    fn new(mtime: u64) -> Self {
+        Self {
+            data: Vec::new(),
+            mtime,
+            files: 0,
+        }
+    }
+
+    /// Add a regular file
+    fn append(&mut self, path: &str, contents: &[u8]) -> Result<()> {
+        let mut header = [0u8; 512];
+        // Names longer than 100 bytes are split at a slash into a prefix
+        let (prefix, name) = if path.len() <= 100 {
+            ("", path)
+        } else {
+            path.char_indices()
+                .filter(|&(at, c)| c == '/' && at <= 155 && path.len() - at - 1 <= 100)
+                .map(|(at, _)| (&path[..at], &path[at + 1..]))
+                .next()
+                .ok_or_else(|| anyhow::anyhow!("Path too long for the archive: {}", path))?
+        };
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        octal(&mut header[100..108], 0o644);
+        octal(&mut header[108..116], 0);
+        octal(&mut header[116..124], 0);
+        octal(&mut header[124..136], contents.len() as u64);
+        octal(&mut header[136..148], self.mtime);
+        header[156] = b'0';
+        header[257..263].copy_from_slice(b"ustar\0");
+        header[263..265].copy_from_slice(b"00");
+        header[345..345 + prefix.len()].copy_from_slice(prefix.as_bytes());
+        // The checksum is computed with its own field set to spaces
+        header[148..156].fill(b' ');
+        let checksum: u64 = header.iter().map(|&byte| u64::from(byte)).sum();
+        header[148..155].copy_from_slice(format!("{checksum:06o}\0").as_bytes());
+
+        self.data.extend_from_slice(&header);
+        self.data.extend_from_slice(contents);
+        let padding = (512 - contents.len() % 512) % 512;
+        self.data.resize(self.data.len() + padding, 0);
+        self.files += 1;
+        Ok(())
+    }
+
+    /// The archive, ended by two empty blocks
+    fn finish(mut self) -> Vec<u8> {
+        self.data.resize(self.data.len() + 1024, 0);
+        self.data
+    }
+}
+
+/// Write `value` in octal, zero-padded and NUL-terminated, into a header field
+fn octal(field: &mut [u8], value: u64) {
+    let digits = format!("{value:0width$o}\0", width = field.len() - 1);
+    field.copy_from_slice(digits.as_bytes());
+}
+
+#[cfg(feature = "log-shipping")]
+fn compress(archive: &[u8], target: &Path) -> Result<()> {
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
+    use std::io::Write;
+
+    let mut encoder = GzEncoder::new(File::create(target)?, Compression::default());
+    encoder.write_all(archive)?;
+    encoder.finish()?;
+    Ok(())
+}
+
+#[cfg(not(feature = "log-shipping"))]
+fn compress(_archive: &[u8], _target: &Path) -> Result<()> {
+    Err(anyhow::anyhow!(
+        "demon was built without the log-shipping feature"
+    ))
+}
@@ -0,0 +1,362 @@
+//! Memory and CPU limits with cgroups v2 (`demon run --memory 512M --cpus 1.5`).
+//!
+//! A daemon with either flag gets a cgroup of its own, `demon/<id>-<hash>`
+//! under the cgroup delegated to the user (`user@<uid>.service` with systemd,
+//! or the root cgroup for root), where the hash tells root directories apart.
+//! The forked child moves itself into the cgroup before it executes the
+//! daemon, so the daemon and everything it starts are limited and accounted
+//! together: `stats` reports the usage of the whole cgroup, and `stop` kills
+//! whatever is left in it once the daemon has exited.
+//!
+//! Like resource limits, the flags are part of the daemon's policy, so
+//! restarts put the daemon in the same cgroup again.
+
+use crate::{parse_bytes, pidfile::DaemonMeta};
+use anyhow::{Context, Result};
+use clap::Args;
+use serde::{Deserialize, Deserializer, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::{File, OpenOptions};
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// Period of `cpu.max`, in microseconds
+const CPU_PERIOD: u64 = 100_000;
+
+/// How long `kill` waits for the processes of a cgroup to go away
+const KILL_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Cgroup limit flags of `run`
+#[derive(Args, Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct Resources {
+    /// Memory the daemon and its children may use together, e.g. 512M; past
+    /// it the kernel reclaims memory and then kills them (cgroup memory.max)
+    #[arg(long, value_name = "SIZE", value_parser = parse_bytes)]
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "deserialize_size"
+    )]
+    pub(crate) memory: Option<u64>,
+
+    /// CPUs the daemon and its children may use together, e.g. 1.5 for one
+    /// and a half cores (cgroup cpu.max)
+    #[arg(long, value_name = "CPUS", value_parser = parse_cpus)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) cpus: Option<f64>,
+}
+
+fn parse_cpus(value: &str) -> Result<f64, String> {
+    value
+        .trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|cpus| cpus.is_finite() && *cpus >= 0.01)
+        .ok_or_else(|| format!("invalid CPU count '{value}', expected e.g. 0.5 or 2"))
+}
+
+/// A size given as a number of bytes or a string like on the command line
+pub(crate) fn deserialize_size<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<u64>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Value {
+        Number(u64),
+        Text(String),
+    }
+    match Value::deserialize(deserializer)? {
+        Value::Number(bytes) => Ok(Some(bytes)),
+        Value::Text(text) => parse_bytes(&text)
+            .map(Some)
+            .map_err(serde::de::Error::custom),
+    }
+}
+
+impl Resources {
+    pub(crate) fn is_empty(&self) -> bool {
+        *self == Resources::default()
+    }
+
+    /// Command line flags that recreate these limits
+    pub(crate) fn to_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if let Some(memory) = self.memory {
+            args.extend(["--memory".to_string(), memory.to_string()]);
+        }
+        if let Some(cpus) = self.cpus {
+            args.extend(["--cpus".to_string(), cpus.to_string()]);
+        }
+        args
+    }
+
+    /// The controllers the limits need
+    fn controllers(&self) -> Vec<&'static str> {
+        let mut controllers = Vec::new();
+        if self.memory.is_some() {
+            controllers.push("memory");
+        }
+        if self.cpus.is_some() {
+            controllers.push("cpu");
+        }
+        controllers
+    }
+
+    /// Fail early when the limits cannot be enforced
+    pub(crate) fn check(&self) -> Result<()> {
+        if !self.is_empty() {
+            delegated(&self.controllers())?;
+        }
+        Ok(())
+    }
+}
+
+/// Where cgroup v2 is mounted
+fn mount_point() -> Option<PathBuf> {
+    let mountinfo = std::fs::read_to_string("/proc/self/mountinfo").ok()?;
+    mountinfo.lines().find_map(|line| {
+        // The filesystem type follows the " - " separator
+        let (fields, rest) = line.split_once(" - ")?;
+        let mount = fields.split_whitespace().nth(4)?;
+        (rest.split_whitespace().next()? == "cgroup2").then(|| PathBuf::from(mount))
+    })
+}
+
+/// The cgroup delegated to us that has the `controllers`, or why there is none
+fn delegated(controllers: &[&str]) -> Result<PathBuf> {
+    const HINT: &str = "--memory and --cpus need cgroup v2";
+    let mount = mount_point()
+        .ok_or_else(|| anyhow::anyhow!("{HINT}, which is not mounted on this system"))?;
+    let own = std::fs::read_to_string("/proc/self/cgroup")
+        .ok()
+        .and_then(|contents| {
+            contents
+                .lines()
+                .find_map(|line| line.strip_prefix("0::").map(str::to_string))
+        })
+        .ok_or_else(|| anyhow::anyhow!("{HINT}, which this process is not part of"))?;
+    // SAFETY: geteuid cannot fail
+    let uid = unsafe { libc::geteuid() };
+    let user_service = format!("user@{uid}.service");
+    let own = Path::new(own.trim().trim_start_matches('/'));
+    let base = match own
+        .ancestors()
+        .find(|ancestor| ancestor.file_name() == Some(user_service.as_ref()))
+    {
+        Some(service) => mount.join(service),
+        None if uid == 0 => mount,
+        None => {
+            return Err(anyhow::anyhow!(
+                "{HINT} delegated to the user, as systemd does for {user_service}; \
+                 run demon from a user session"
+            ));
+        }
+    };
+    let available = std::fs::read_to_string(base.join("cgroup.controllers")).unwrap_or_default();
+    let missing: Vec<&str> = controllers
+        .iter()
+        .copied()
+        .filter(|controller| !available.split_whitespace().any(|c| c == *controller))
+        .collect();
+    if !missing.is_empty() {
+        return Err(anyhow::anyhow!(
+            "{HINT} with the {} controller{} available in {}",
+            missing.join(" and "),
+            if missing.len() == 1 { "" } else { "s" },
+            base.display()
+        ));
+    }
+    Ok(base)
+}
+
+/// Enable `controllers` for the children of the cgroup `path`
+fn enable(path: &Path, controllers: &[&str]) -> Result<()> {
+    let file = path.join("cgroup.subtree_control");
+    let enabled = std::fs::read_to_string(&file).unwrap_or_default();
+    let request: Vec<String> = controllers
+        .iter()
+        .filter(|controller| !enabled.split_whitespace().any(|c| c == **controller))
+        .map(|controller| format!("+{controller}"))
+        .collect();
+    if request.is_empty() {
+        return Ok(());
+    }
+    std::fs::write(&file, request.join(" ")).with_context(|| {
+        format!(
+            "Failed to enable {} in {}",
+            request.join(" "),
+            file.display()
+        )
+    })
+}
+
+/// Name of the cgroup of a daemon, `<id>-<hash>`, where the hash of the root
+/// directory tells root directories apart
+pub(crate) fn name(root_dir: &Path, id: &str) -> String {
+    let hash = Sha256::digest(root_dir.as_os_str().as_encoded_bytes());
+    let hash: String = hash[..4].iter().map(|byte| format!("{byte:02x}")).collect();
+    format!("{id}-{hash}")
+}
+
+/// The cgroup of a daemon
+#[derive(Debug, Clone)]
+pub(crate) struct Cgroup {
+    pub(crate) path: PathBuf,
+}
+
+impl Cgroup {
+    /// Create (or reuse) the cgroup of a daemon and apply its limits; None
+    /// when it has no limits
+    pub(crate) fn create(root_dir: &Path, id: &str, resources: &Resources) -> Result<Option<Self>> {
+        if resources.is_empty() {
+            return Ok(None);
+        }
+        let controllers = resources.controllers();
+        let base = delegated(&controllers)?;
+        let parent = base.join("demon");
+        enable(&base, &controllers)?;
+        create_dir(&parent)?;
+        enable(&parent, &controllers)?;
+
+        let path = parent.join(name(root_dir, id));
+        create_dir(&path)?;
+
+        let memory = resources
+            .memory
+            .map_or("max".to_string(), |bytes| bytes.to_string());
+        write(&path, "memory.max", &memory, resources.memory.is_some())?;
+        let cpu = resources.cpus.map_or("max".to_string(), |cpus| {
+            format!("{} {CPU_PERIOD}", (cpus * CPU_PERIOD as f64) as u64)
+        });
+        write(&path, "cpu.max", &cpu, resources.cpus.is_some())?;
+        Ok(Some(Self { path }))
+    }
+
+    /// The cgroup recorded in a daemon's metadata, if it still exists
+    pub(crate) fn of(meta: &DaemonMeta) -> Option<Self> {
+        meta.cgroup
+            .as_ref()
+            .filter(|path| path.is_dir())
+            .map(|path| Self { path: path.clone() })
+    }
+
+    /// Open `cgroup.procs` for [`enter`], before forking
+    pub(crate) fn procs(&self) -> Result<File> {
+        let path = self.path.join("cgroup.procs");
+        OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .with_context(|| format!("Failed to open {}", path.display()))
+    }
+
+    /// PIDs of the processes in the cgroup, sorted
+    pub(crate) fn pids(&self) -> Vec<u32> {
+        let mut pids: Vec<u32> = std::fs::read_to_string(self.path.join("cgroup.procs"))
+            .unwrap_or_default()
+            .lines()
+            .filter_map(|line| line.parse().ok())
+            .collect();
+        pids.sort_unstable();
+        pids
+    }
+
+    /// Memory and CPU usage of every process in the cgroup together
+    pub(crate) fn usage(&self) -> Usage {
+        let read = |file: &str| std::fs::read_to_string(self.path.join(file)).unwrap_or_default();
+        let number = |file: &str| read(file).trim().parse().ok();
+        let cpu_stat = read("cpu.stat");
+        let stat = |key: &str| {
+            cpu_stat.lines().find_map(|line| {
+                let (name, value) = line.split_once(' ')?;
+                (name == key).then(|| value.parse().ok())?
+            })
+        };
+        let oom_kills = read("memory.events").lines().find_map(|line| {
+            let value = line.strip_prefix("oom_kill ")?;
+            value.parse().ok()
+        });
+        Usage {
+            memory: number("memory.current"),
+            memory_peak: number("memory.peak"),
+            memory_max: number("memory.max"),
+            cpu_usec: stat("usage_usec"),
+            throttled_usec: stat("throttled_usec"),
+            oom_kills,
+        }
+    }
+
+    /// Kill every process left in the cgroup and wait for them to go away
+    pub(crate) fn kill(&self) {
+        if self.pids().is_empty() {
+            return;
+        }
+        tracing::info!("Killing the processes left in {}", self.path.display());
+        // cgroup.kill needs Linux 5.14; older kernels get SIGKILL one by one
+        if std::fs::write(self.path.join("cgroup.kill"), "1").is_err() {
+            for pid in self.pids() {
+                // SAFETY: kill has no memory safety preconditions
+                unsafe { libc::kill(pid as libc::pid_t, libc::SIGKILL) };
+            }
+        }
+        let deadline = Instant::now() + KILL_TIMEOUT;
+        while !self.pids().is_empty() && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(20));
+        }
+    }
+
+    /// Remove the cgroup; it stays while processes are left in it
+    pub(crate) fn remove(&self) {
+        match std::fs::remove_dir(&self.path) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) if e.raw_os_error() == Some(libc::EBUSY) => {
+                tracing::debug!("{} still has processes, leaving it", self.path.display());
+            }
+            Err(e) => tracing::warn!("Failed to remove {}: {}", self.path.display(), e),
+        }
+    }
+}
+
+/// Usage of a cgroup; None where the kernel does not report it
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct Usage {
+    pub(crate) memory: Option<u64>,
+    pub(crate) memory_peak: Option<u64>,
+    /// None when unlimited
+    pub(crate) memory_max: Option<u64>,
+    pub(crate) cpu_usec: Option<u64>,
+    pub(crate) throttled_usec: Option<u64>,
+    pub(crate) oom_kills: Option<u64>,
+}
+
+fn create_dir(path: &Path) -> Result<()> {
+    match std::fs::create_dir(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::AlreadyExists => {
+            Err(e).with_context(|| format!("Failed to create cgroup {}", path.display()))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Write a limit, or reset it when `set` is false and the file exists (a
+/// reused cgroup may have had it)
+fn write(path: &Path, file: &str, value: &str, set: bool) -> Result<()> {
+    let file = path.join(file);
+    if !set && !file.exists() {
+        return Ok(());
+    }
+    std::fs::write(&file, value)
+        .with_context(|| format!("Failed to write {value} to {}", file.display()))
+}
+
+/// Move the calling process into the cgroup whose `cgroup.procs` is open as
+/// `procs`; called in the forked child
+pub(crate) fn enter(procs: &File) -> std::io::Result<()> {
+    // SAFETY: write is async-signal-safe and the buffer outlives the call;
+    // writing 0 moves the writer
+    if unsafe { libc::write(procs.as_raw_fd(), b"0".as_ptr().cast(), 1) } != 1 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
@@ -0,0 +1,131 @@
+//! `demon chaos`: randomly disrupt a daemon, to test how an application
+//! recovers from crashes together with its restart policy.
+//!
+//! Each time the daemon has been running for the `--kill-after` interval, it
+//! is sent the signal (SIGKILL by default) with the given probability; when it
+//! is spared, the next roll is one interval later. The PID file is read again
+//! on every check, so a daemon restarted by its restart policy gets a full
+//! interval to come up and keeps being disrupted. Every disruption is logged
+//! as a `chaos` event, next to the `crashed` and `restarted` events it leads
+//! to.
+
+use crate::{
+    events,
+    pidfile::{PidFile, PidFileReadError},
+    reload::Signal,
+    status::is_daemon_running,
+};
+use anyhow::Result;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+/// How often the PID file is checked for a restarted daemon
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+pub(crate) struct ChaosOptions {
+    /// How long the daemon runs before each roll
+    pub(crate) kill_after: Duration,
+    pub(crate) signal: Signal,
+    /// Chance of disrupting the daemon on each roll, from 0 to 1
+    pub(crate) probability: f64,
+    /// Stop after this many disruptions
+    pub(crate) count: Option<u32>,
+}
+
+/// Parse a `--probability` between 0 and 1
+pub(crate) fn parse_probability(value: &str) -> Result<f64, String> {
+    value
+        .parse::<f64>()
+        .ok()
+        .filter(|probability| (0.0..=1.0).contains(probability))
+        .ok_or_else(|| format!("invalid probability '{value}', expected a number from 0 to 1"))
+}
+
+/// Disrupt a daemon until Ctrl+C, until it is stopped or after `count`
+/// disruptions
+pub(crate) fn run_chaos(id: &str, root_dir: &Path, options: &ChaosOptions) -> Result<()> {
+    if let Err(PidFileReadError::FileNotFound) = PidFile::read(root_dir, id) {
+        return Err(anyhow::anyhow!("Process '{}' not found (no PID file)", id));
+    }
+
+    let running = Arc::new(AtomicBool::new(true));
+    let r = running.clone();
+    ctrlc::set_handler(move || {
+        r.store(false, Ordering::SeqCst);
+    })?;
+
+    tracing::info!(
+        "Sending {} to '{}' every {}s of uptime with probability {}... Press Ctrl+C to stop.",
+        options.signal,
+        id,
+        options.kill_after.as_secs(),
+        options.probability
+    );
+
+    let mut disruptions = 0;
+    // The run being timed: its PID and when it was first seen running
+    let mut current: Option<(u32, Instant)> = None;
+    while running.load(Ordering::SeqCst) && options.count.is_none_or(|count| disruptions < count) {
+        let pid = match PidFile::read(root_dir, id) {
+            Ok(data) => Some(data.pid),
+            Err(PidFileReadError::FileNotFound) => {
+                println!("Daemon '{id}' was stopped, ending chaos");
+                break;
+            }
+            Err(_) => None,
+        };
+        let pid = pid.filter(|pid| is_daemon_running(root_dir, id, *pid));
+        match (pid, current) {
+            (None, _) => current = None,
+            (Some(pid), Some((timed, since))) if pid == timed => {
+                if since.elapsed() >= options.kill_after {
+                    if fastrand::f64() >= options.probability {
+                        tracing::info!("Spared '{}' (PID {}) this time", id, pid);
+                    } else if let Err(e) = disrupt(root_dir, id, pid, options.signal) {
+                        // It may have exited since the check
+                        tracing::warn!("Failed to disrupt '{}': {}", id, e);
+                    } else {
+                        disruptions += 1;
+                    }
+                    current = Some((pid, Instant::now()));
+                }
+            }
+            (Some(pid), _) => current = Some((pid, Instant::now())),
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    }
+
+    println!(
+        "Disrupted daemon '{}' {} time{}",
+        id,
+        disruptions,
+        if disruptions == 1 { "" } else { "s" }
+    );
+    Ok(())
+}
+
+/// Signal the daemon directly, not through its supervisor, which would take
+/// the exit for a requested stop instead of a crash the restart policy
+/// applies to
+fn disrupt(root_dir: &Path, id: &str, pid: u32, signal: Signal) -> Result<()> {
+    // SAFETY: kill has no memory safety preconditions
+    if unsafe { libc::kill(pid as libc::pid_t, signal.0) } == -1 {
+        return Err(anyhow::anyhow!(
+            "Failed to send {} to PID {}: {}",
+            signal,
+            pid,
+            std::io::Error::last_os_error()
+        ));
+    }
+    println!("Sent {signal} to daemon '{id}' (PID {pid})");
+    events::emit(
+        root_dir,
+        id,
+        events::Kind::Chaos,
+        Some(pid),
+        Some(signal.to_string()),
+    );
+    Ok(())
+}
@@ -0,0 +1,139 @@
+//! `demon check`: a check for Nagios and the monitoring systems that speak
+//! its plugin protocol (Icinga, Sensu, Zabbix, ...).
+//!
+//! It prints a single line, `DEMON <STATE> - <summary> | <performance data>`,
+//! and exits with the state: 0 OK, 1 WARNING, 2 CRITICAL or 3 UNKNOWN. A
+//! daemon that is missing, dead or failing its health checks is CRITICAL; the
+//! memory and restart thresholds apply to a running one. When the state cannot
+//! be told, e.g. the PID file is unreadable, the check is UNKNOWN rather than
+//! failing with exit code 1, which would read as WARNING.
+
+use crate::{
+    agent, checks, events, format_bytes, format_duration,
+    pidfile::{PidFile, PidFileReadError},
+    proc::ProcStat,
+    status::is_daemon_running,
+    unix_now,
+};
+use anyhow::Result;
+use std::path::Path;
+use std::time::Duration;
+
+/// Service state of the Nagios plugin protocol, which is also the exit code
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum State {
+    Ok = 0,
+    Warning = 1,
+    Critical = 2,
+    Unknown = 3,
+}
+
+impl State {
+    pub(crate) fn code(self) -> i32 {
+        self as i32
+    }
+}
+
+impl std::fmt::Display for State {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            State::Ok => "OK",
+            State::Warning => "WARNING",
+            State::Critical => "CRITICAL",
+            State::Unknown => "UNKNOWN",
+        })
+    }
+}
+
+pub(crate) struct CheckOptions {
+    /// RSS in bytes from which the daemon is WARNING
+    pub(crate) warn_mem: Option<u64>,
+    /// RSS in bytes from which the daemon is CRITICAL
+    pub(crate) crit_mem: Option<u64>,
+    /// Restarts in `window` above which the daemon is WARNING
+    pub(crate) max_restarts: Option<usize>,
+    /// How far back restarts are counted
+    pub(crate) window: Duration,
+}
+
+/// The state of a daemon and the line describing it
+pub(crate) fn evaluate(
+    id: &str,
+    root_dir: &Path,
+    options: &CheckOptions,
+) -> Result<(State, String)> {
+    let pid = match PidFile::read(root_dir, id) {
+        Ok(data) => data.pid,
+        Err(PidFileReadError::FileNotFound) => {
+            return Ok((State::Critical, format!("{id} not found")));
+        }
+        Err(e) => return Err(anyhow::anyhow!("Cannot read PID file for '{}': {}", id, e)),
+    };
+    if !is_daemon_running(root_dir, id, pid) {
+        return Ok((State::Critical, format!("{id} is dead (PID {pid})")));
+    }
+    let rss = ProcStat::read(pid)
+        .map(|stat| stat.rss_bytes())
+        .ok_or_else(|| anyhow::anyhow!("Cannot read the memory usage of PID {}", pid))?;
+    let filter = events::Filter {
+        id: Some(id.to_string()),
+        since: Some(unix_now().saturating_sub(options.window.as_secs())),
+    };
+    let restarts = events::count(root_dir, events::Kind::Restarted, &filter)?;
+    let unhealthy =
+        agent::daemon_state(root_dir, id).and_then(|state| state.healthy) == Some(false);
+
+    let mut state = State::Ok;
+    let mut summary = format!("{id} is running (PID {pid})");
+    if unhealthy {
+        state = State::Critical;
+        summary.push_str(", failing its health check");
+    }
+    summary.push_str(&format!(": RSS {}", format_bytes(rss)));
+    if let Some(crit) = options.crit_mem.filter(|crit| rss >= *crit) {
+        state = state.max(State::Critical);
+        summary.push_str(&format!(" (critical at {})", format_bytes(crit)));
+    } else if let Some(warn) = options.warn_mem.filter(|warn| rss >= *warn) {
+        state = state.max(State::Warning);
+        summary.push_str(&format!(" (warning at {})", format_bytes(warn)));
+    }
+    summary.push_str(&format!(
+        ", {restarts} restarts in {}",
+        format_duration(options.window)
+    ));
+    if let Some(max) = options.max_restarts.filter(|max| restarts > *max) {
+        state = state.max(State::Warning);
+        summary.push_str(&format!(" (more than {max})"));
+    }
+    let results = checks::run(root_dir, id, pid);
+    for check in &results {
+        state = state.max(check.state);
+        summary.push_str(&format!(", {} {}", check.name, check.state));
+        if !check.message.is_empty() {
+            summary.push_str(&format!(": {}", check.message));
+        }
+    }
+
+    let threshold = |value: Option<u64>| value.map(|value| value.to_string()).unwrap_or_default();
+    let mut perfdata = format!(
+        "rss={rss}B;{};{};0; restarts={restarts};{};;0;",
+        threshold(options.warn_mem),
+        threshold(options.crit_mem),
+        threshold(options.max_restarts.map(|max| max as u64)),
+    );
+    // Metrics of the check plugins, as `<plugin>.<metric>`
+    for check in &results {
+        for (name, value) in &check.metrics {
+            perfdata.push_str(&format!(" '{}.{name}'={value}", check.name));
+        }
+    }
+    Ok((state, format!("{summary} | {perfdata}")))
+}
+
+/// Print the result of a check as the plugin's single line, and return its
+/// state; errors are UNKNOWN
+pub(crate) fn report(result: Result<(State, String)>) -> State {
+    let (state, line) = result.unwrap_or_else(|e| (State::Unknown, e.to_string()));
+    println!("DEMON {state} - {line}");
+    state
+}
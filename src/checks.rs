@@ -0,0 +1,163 @@
+//! Check plugins: executables in `<root>/checks.d/<id>/` that tell whether a
+//! running daemon works, for checks too specific to fit in `--health-cmd`.
+//!
+//! A plugin is run with `DEMON_ID`, `DEMON_PID` and `DEMON_ROOT_DIR` and
+//! prints one JSON object: `{"status": "ok", "message": "...", "metrics":
+//! {"name": 1.5}}`, where the status is `ok`, `warning`, `critical` or
+//! `unknown`. A plugin that prints something else is judged by its exit
+//! status like a Nagios plugin (0 OK, 1 WARNING, 2 CRITICAL, anything else
+//! UNKNOWN), with its first line as the message. One still running after
+//! `checks.timeout` seconds is killed and UNKNOWN. The plugins of a daemon run
+//! in parallel whenever `demon status` or `demon check` looks at it.
+
+use crate::{check::State, config::Config, plugins};
+use anyhow::Result;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+/// What a check plugin reported
+#[derive(Debug, Clone)]
+pub(crate) struct CheckResult {
+    /// File name of the plugin
+    pub(crate) name: String,
+    pub(crate) state: State,
+    pub(crate) message: String,
+    pub(crate) metrics: BTreeMap<String, f64>,
+}
+
+impl CheckResult {
+    pub(crate) fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "name": self.name,
+            "status": self.state.to_string(),
+            "message": self.message,
+            "metrics": self.metrics,
+        })
+    }
+}
+
+/// The JSON a plugin prints
+#[derive(Deserialize)]
+struct Output {
+    status: String,
+    #[serde(default)]
+    message: String,
+    #[serde(default)]
+    metrics: BTreeMap<String, f64>,
+}
+
+fn checks_dir(root_dir: &Path, id: &str) -> PathBuf {
+    root_dir.join("checks.d").join(id)
+}
+
+/// Whether a daemon has check plugins
+pub(crate) fn has_checks(root_dir: &Path, id: &str) -> bool {
+    !plugins::executables(&checks_dir(root_dir, id)).is_empty()
+}
+
+/// Run the check plugins of a daemon, in name order
+pub(crate) fn run(root_dir: &Path, id: &str, pid: u32) -> Vec<CheckResult> {
+    let plugins = plugins::executables(&checks_dir(root_dir, id));
+    if plugins.is_empty() {
+        return Vec::new();
+    }
+    let timeout =
+        Duration::from_secs(Config::load_lenient(Some(root_dir)).integer("checks.timeout"));
+    let handles: Vec<_> = plugins
+        .into_iter()
+        .map(|plugin| {
+            let root_dir = root_dir.to_path_buf();
+            let id = id.to_string();
+            std::thread::spawn(move || run_one(&plugin, &root_dir, &id, pid, timeout))
+        })
+        .collect();
+    handles
+        .into_iter()
+        .filter_map(|handle| handle.join().ok())
+        .collect()
+}
+
+fn run_one(plugin: &Path, root_dir: &Path, id: &str, pid: u32, timeout: Duration) -> CheckResult {
+    let name = plugin
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let (state, message, metrics) = match execute(plugin, root_dir, id, pid, timeout) {
+        Ok(Some((code, stdout))) => interpret(code, &stdout),
+        Ok(None) => (
+            State::Unknown,
+            format!("timed out after {}s", timeout.as_secs()),
+            BTreeMap::new(),
+        ),
+        Err(e) => (State::Unknown, format!("{e:#}"), BTreeMap::new()),
+    };
+    CheckResult {
+        name,
+        state,
+        message,
+        metrics,
+    }
+}
+
+/// Exit code and output of a plugin, or None when it timed out
+fn execute(
+    plugin: &Path,
+    root_dir: &Path,
+    id: &str,
+    pid: u32,
+    timeout: Duration,
+) -> Result<Option<(Option<i32>, String)>> {
+    let mut child = Command::new(plugin)
+        .env("DEMON_ID", id)
+        .env("DEMON_PID", pid.to_string())
+        .env("DEMON_ROOT_DIR", root_dir)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .process_group(0)
+        .spawn()?;
+    // Read while waiting, so a plugin with a lot to say does not block on a
+    // full pipe
+    let mut stdout = child.stdout.take().expect("stdout is piped");
+    let reader = std::thread::spawn(move || {
+        let mut output = String::new();
+        let _ = stdout.read_to_string(&mut output);
+        output
+    });
+    let status = plugins::wait_timeout(&mut child, timeout)?;
+    let output = reader.join().unwrap_or_default();
+    Ok(status.map(|status| (status.code(), output)))
+}
+
+fn interpret(code: Option<i32>, stdout: &str) -> (State, String, BTreeMap<String, f64>) {
+    if let Ok(output) = serde_json::from_str::<Output>(stdout.trim()) {
+        let state = match output.status.to_ascii_lowercase().as_str() {
+            "ok" => Some(State::Ok),
+            "warning" => Some(State::Warning),
+            "critical" => Some(State::Critical),
+            "unknown" => Some(State::Unknown),
+            _ => None,
+        };
+        return match state {
+            Some(state) => (state, output.message, output.metrics),
+            None => (
+                State::Unknown,
+                format!("invalid status '{}'", output.status),
+                output.metrics,
+            ),
+        };
+    }
+    let state = match code {
+        Some(0) => State::Ok,
+        Some(1) => State::Warning,
+        Some(2) => State::Critical,
+        _ => State::Unknown,
+    };
+    let message = stdout.lines().next().unwrap_or_default().trim().to_string();
+    (state, message, BTreeMap::new())
+}
@@ -0,0 +1,279 @@
+//! `demon clean`: removing the files of dead daemons.
+//!
+//! By default every file of every dead daemon goes, except its runs in the
+//! history. The sweep can be scoped to IDs matching a glob (`--id`), to
+//! daemons whose files were last written a while ago (`--older-than`), and to
+//! the logs alone (`--logs-only`), which keeps the PID file and metadata so
+//! the daemon is still listed. `--dry-run` prints what would be removed.
+
+use crate::{
+    events, history, layout, logs,
+    pidfile::{PidFile, PidFileReadError, RootLock, build_file_path, daemon_ids},
+    progress::Progress,
+    runs,
+    status::is_daemon_running,
+    stop::{STATE_EXTENSIONS, remove_state_files},
+    timeline,
+};
+use anyhow::Result;
+use std::fs::OpenOptions;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// Which files `demon clean` removes
+#[derive(Debug, Default)]
+pub(crate) struct CleanOptions {
+    /// Only print what would be removed
+    pub(crate) dry_run: bool,
+    /// Only daemons whose ID matches
+    pub(crate) id: Option<glob::Pattern>,
+    /// Only remove the logs and their timeline, keeping the PID file and
+    /// metadata
+    pub(crate) logs_only: bool,
+    /// Only daemons whose files were last written longer ago than this
+    pub(crate) older_than: Option<Duration>,
+}
+
+/// Extensions of the logs of a daemon and the timeline dating them
+const LOG_EXTENSIONS: [&str; 3] = ["stdout", "stderr", timeline::EXTENSION];
+
+/// Remove a log of a dead daemon. A log that is a symbolic link, e.g. onto
+/// another disk, is emptied instead: the link is the user's setup, which the
+/// next run writes through again, and its target is not ours to delete.
+fn remove_log_file(path: &Path) {
+    match logs::link_target(path) {
+        Some(target) => match OpenOptions::new().write(true).truncate(true).open(&target) {
+            Ok(_) => tracing::info!(
+                "Emptied {} (linked from {})",
+                target.display(),
+                path.display()
+            ),
+            // A dangling link has nothing to free
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => tracing::warn!("Failed to empty {}: {}", target.display(), e),
+        },
+        None if path.exists() => match std::fs::remove_file(path) {
+            Ok(()) => tracing::info!("Removed {}", path.display()),
+            Err(e) => tracing::warn!("Failed to remove {}: {}", path.display(), e),
+        },
+        None => {}
+    }
+}
+
+/// Remove the logs of a daemon, the timeline dating them and the logs of its
+/// past runs, and its directory when that leaves it empty
+pub(crate) fn remove_logs(root_dir: &Path, id: &str) {
+    runs::remove(root_dir, id);
+    remove_current_logs(root_dir, id);
+}
+
+/// Remove the logs of the last run of a daemon and the timeline dating them,
+/// keeping its past runs
+pub(crate) fn remove_current_logs(root_dir: &Path, id: &str) {
+    for extension in ["stdout", "stderr"] {
+        remove_log_file(&build_file_path(root_dir, id, extension));
+    }
+    let _ = std::fs::remove_file(build_file_path(root_dir, id, timeline::EXTENSION));
+    layout::remove_daemon_dir(root_dir, id);
+}
+
+/// The files of a daemon that exist, the logs only with `logs_only`
+fn daemon_files(root_dir: &Path, id: &str, logs_only: bool) -> Vec<PathBuf> {
+    let mut extensions = LOG_EXTENSIONS.to_vec();
+    if !logs_only {
+        extensions.extend(["pid", "meta"]);
+        extensions.extend(STATE_EXTENSIONS);
+    }
+    extensions
+        .into_iter()
+        .map(|extension| build_file_path(root_dir, id, extension))
+        .filter(|path| path.symlink_metadata().is_ok())
+        .collect()
+}
+
+/// Whether the files of a daemon were all last written before `older_than`
+fn is_old_enough(root_dir: &Path, id: &str, older_than: Option<Duration>) -> bool {
+    let Some(older_than) = older_than else {
+        return true;
+    };
+    let last_written = daemon_files(root_dir, id, false)
+        .iter()
+        .filter_map(|path| {
+            path.metadata()
+                .and_then(|metadata| metadata.modified())
+                .ok()
+        })
+        .max();
+    last_written.is_none_or(|modified| {
+        SystemTime::now()
+            .duration_since(modified)
+            .is_ok_and(|age| age >= older_than)
+    })
+}
+
+/// Remove the files of dead daemons and of invalid PID files, as scoped by
+/// `options`. Returns a record (`id`, `pid`, `reason`, `files`) per cleaned
+/// daemon; the messages are only printed when `verbose`.
+pub(crate) fn clean_orphaned_files(
+    root_dir: &Path,
+    options: &CleanOptions,
+    verbose: bool,
+) -> Result<Vec<serde_json::Value>> {
+    tracing::info!("Scanning for orphaned daemon files...");
+
+    // Hold the root lock for the whole sweep so no daemon can be started (and
+    // have its fresh files removed) while we decide what is orphaned
+    let _lock = if options.dry_run {
+        RootLock::shared(root_dir)?
+    } else {
+        RootLock::exclusive(root_dir)?
+    };
+    let ids = daemon_ids(root_dir)?;
+    let mut progress = Progress::start("clean", None, Some(ids.len() as u64));
+    let mut cleaned = Vec::new();
+
+    for id in &ids {
+        let record = clean_daemon(root_dir, id, options, verbose);
+        progress.step(
+            Some(id),
+            record
+                .as_ref()
+                .and_then(|record| record["reason"].as_str())
+                .unwrap_or("skipped"),
+        );
+        cleaned.extend(record);
+    }
+
+    if verbose {
+        match (cleaned.len(), options.dry_run) {
+            (0, _) => println!("No orphaned files found."),
+            (count, true) => println!("Would clean up {count} orphaned daemon(s)."),
+            (count, false) => println!("Cleaned up {count} orphaned daemon(s)."),
+        }
+    }
+
+    progress.finish(None, "cleaned", true);
+    Ok(cleaned)
+}
+
+/// Remove the files of one daemon if it is dead and in scope, returning the
+/// record of what was removed
+fn clean_daemon(
+    root_dir: &Path,
+    id: &str,
+    options: &CleanOptions,
+    verbose: bool,
+) -> Option<serde_json::Value> {
+    if options.id.as_ref().is_some_and(|glob| !glob.matches(id))
+        || !is_old_enough(root_dir, id, options.older_than)
+    {
+        return None;
+    }
+    let verb = if options.dry_run {
+        "Would clean up"
+    } else {
+        "Cleaning up"
+    };
+
+    let path = build_file_path(root_dir, id, "pid");
+    match PidFile::read(root_dir, id) {
+        Ok(pid_file_data) => {
+            // Check if process is still running
+            if is_daemon_running(root_dir, id, pid_file_data.pid) {
+                tracing::info!(
+                    "Skipping '{}' (PID: {}) - process is still running",
+                    id,
+                    pid_file_data.pid
+                );
+                return None;
+            }
+            let files = daemon_files(root_dir, id, options.logs_only);
+            // Logs already cleaned leave nothing to do
+            if files.is_empty() {
+                return None;
+            }
+            if verbose {
+                let what = if options.logs_only {
+                    "logs"
+                } else {
+                    "orphaned files"
+                };
+                println!("{verb} {what} for '{id}' (PID: {})", pid_file_data.pid);
+                if options.dry_run {
+                    for file in &files {
+                        println!("  {}", file.display());
+                    }
+                }
+            }
+            let record = serde_json::json!({
+                "id": id,
+                "pid": pid_file_data.pid,
+                "reason": "dead",
+                "files": files,
+            });
+            if options.dry_run {
+                return Some(record);
+            }
+
+            if options.logs_only {
+                events::emit(
+                    root_dir,
+                    id,
+                    events::Kind::Cleaned,
+                    Some(pid_file_data.pid),
+                    Some("logs only".to_string()),
+                );
+            } else {
+                history::record_retired(root_dir, id, &pid_file_data, None, None);
+                events::emit(
+                    root_dir,
+                    id,
+                    events::Kind::Cleaned,
+                    Some(pid_file_data.pid),
+                    None,
+                );
+
+                if let Err(e) = PidFile::remove(root_dir, id) {
+                    tracing::warn!("Failed to remove the PID file of '{}': {:#}", id, e);
+                } else {
+                    tracing::info!("Removed the PID file of '{}'", id);
+                }
+                remove_state_files(root_dir, id);
+            }
+
+            remove_logs(root_dir, id);
+            Some(record)
+        }
+        Err(PidFileReadError::FileNotFound) => {
+            // This shouldn't happen since we found the file, but handle gracefully
+            tracing::warn!("PID file of '{}' disappeared during processing", id);
+            None
+        }
+        // Kept with the other PID files by --logs-only
+        Err(PidFileReadError::FileInvalid(_)) | Err(PidFileReadError::IoError(_))
+            if options.logs_only =>
+        {
+            None
+        }
+        Err(PidFileReadError::FileInvalid(_)) | Err(PidFileReadError::IoError(_)) => {
+            if verbose {
+                println!("{verb} invalid PID file: {}", path.display());
+            }
+            let record = serde_json::json!({
+                "id": id,
+                "pid": null,
+                "reason": "invalid-pid-file",
+                "files": [path],
+            });
+            if options.dry_run {
+                return Some(record);
+            }
+            if let Err(e) = PidFile::remove(root_dir, id) {
+                tracing::warn!("Failed to remove invalid PID file of '{}': {:#}", id, e);
+                return None;
+            }
+            tracing::info!("Removed invalid PID file {}", path.display());
+            Some(record)
+        }
+    }
+}
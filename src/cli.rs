@@ -0,0 +1,2484 @@
+//! The `demon` command line: argument definitions and dispatch.
+
+use crate::{
+    RootStrategy, agent, bench, bundle, chaos, check,
+    clean::{CleanOptions, clean_orphaned_files},
+    color, config, dev, events,
+    exit::{self, Exit},
+    export, find_root, format_duration, gc, get, grep, history, hooks, init,
+    labels::Selector,
+    layout, llm,
+    logs::{self, cat_logs, tail_group, tail_logs},
+    manifest::{self, Manifest},
+    nuke,
+    output::{self, Format},
+    parse_bytes,
+    pidfile::{OperationLock, build_file_path},
+    plugins,
+    progress::{self, Progress},
+    project_dir,
+    proxy::proxy_daemon,
+    pty, ready, reload, report,
+    requirements::Requirements,
+    roots,
+    runner::{RunOptions, run_daemon, verify_detach},
+    runs, schedule, sd_notify, serve, shell_hook, snapshot,
+    status::{
+        Column, ListOptions, SortKey, StatusFilter, is_process_running, list_daemons,
+        list_daemons_records, stats_daemon, status_all, status_daemon, status_exit_code,
+        status_record, wait_daemon,
+    },
+    stdin::{self, Stdin, StdinMode},
+    stop::stop_daemon,
+    supervisor,
+    table::MaxWidths,
+    top,
+    view::{self, ViewOptions},
+    watch, whois,
+};
+use anyhow::{Context, Result};
+use clap::{Args, Parser, Subcommand};
+use std::io::IsTerminal;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+#[derive(Parser)]
+#[command(name = "demon")]
+#[command(about = "A daemon process management CLI", long_about = None)]
+#[command(version)]
+#[command(propagate_version = true)]
+pub(crate) struct Cli {
+    #[command(subcommand)]
+    command: Commands,
+}
+
+#[derive(Args)]
+struct Global {
+    /// Root directory for daemon files (pid, logs). If not specified, searches for git root.
+    #[arg(long, global = true, env = "DEMON_ROOT_DIR")]
+    root_dir: Option<PathBuf>,
+
+    /// Where the root directory is searched without --root-dir: `.demon` at
+    /// the git root, next to the nearest demon.toml (one per project of a
+    /// monorepo) or in the current directory
+    #[arg(
+        long,
+        global = true,
+        value_enum,
+        value_name = "STRATEGY",
+        env = "DEMON_ROOT_STRATEGY",
+        default_value = "git"
+    )]
+    root_strategy: RootStrategy,
+
+    /// Color the output: auto (on a terminal, unless NO_COLOR is set), always
+    /// or never [config: color]
+    #[arg(long, global = true, value_enum, value_name = "WHEN")]
+    color: Option<color::When>,
+
+    /// Output format: text (or table), json, csv, plain or markdown; run,
+    /// stop, restart, wait, clean, status, list and events print records
+    /// with a stable schema in the structured formats [config: format]
+    #[arg(long, global = true, value_enum, value_name = "FORMAT")]
+    format: Option<Format>,
+
+    /// Refuse commands that change daemons or files (run, stop, clean, ...),
+    /// to hand demon to an agent or script that should only inspect; top and
+    /// serve keep only their read-only actions
+    #[arg(long, global = true, env = "DEMON_READ_ONLY", value_parser = clap::builder::FalseyValueParser::new())]
+    read_only: bool,
+    /// Report the progress of stop, up, down, wait and clean as JSON lines on
+    /// stderr, for wrappers drawing progress bars
+    #[arg(
+        long,
+        global = true,
+        value_enum,
+        value_name = "MODE",
+        env = "DEMON_PROGRESS"
+    )]
+    progress: Option<progress::Mode>,
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    /// Spawn a background process and redirect stdout/stderr to files
+    Run(RunArgs),
+
+    /// Stop a running daemon process, or every daemon in a group or with a
+    /// label
+    Stop(StopArgs),
+
+    /// Stop a daemon and start it again with the same command and options,
+    /// or every daemon in a group or with a label
+    Restart(RestartArgs),
+
+    /// Send a daemon its reload signal (SIGHUP unless run with
+    /// --reload-signal) so it re-reads its configuration
+    Reload(ReloadArgs),
+
+    /// Connect the terminal to a daemon started with --pty; Ctrl-] detaches
+    Attach(AttachArgs),
+
+    /// Write to the standard input of a daemon started with --stdin fifo
+    Input(InputArgs),
+
+    /// Tail daemon logs in real-time
+    Tail(TailArgs),
+
+    /// Display daemon log contents
+    Cat(CatArgs),
+
+    /// Display the logs of one or more daemons, with --merge as one stream
+    /// in the order the lines were written
+    Logs(LogsArgs),
+
+    /// Search daemon logs for a regular expression, line by line
+    Grep(GrepArgs),
+
+    /// List all running daemon processes
+    List(ListArgs),
+
+    /// Check status of a daemon process
+    Status(StatusArgs),
+
+    /// Copy a consistent snapshot of daemon logs to a directory or URL,
+    /// recording their checksums in the history
+    CpLogs(CpLogsArgs),
+
+    /// Clean up orphaned pid and log files
+    Clean(CleanArgs),
+
+    /// Remove old logs and log copies per the retention policy of demon.toml
+    Gc(GcArgs),
+
+    /// Output comprehensive usage guide for LLMs
+    Llm(LlmArgs),
+
+    /// Wait for a daemon process to terminate
+    Wait(WaitArgs),
+
+    /// Hold a port and start the daemon on the first incoming connection
+    Proxy(ProxyArgs),
+
+    /// Live dashboard of all daemons with resource usage and logs
+    Top(TopArgs),
+
+    /// Browse the logs of a daemon interactively: search, jump to a time,
+    /// toggle stdout and stderr, follow new output
+    View(ViewArgs),
+
+    /// Show detailed resource usage of a daemon and its child processes
+    Stats(StatsArgs),
+
+    /// Print one value of a daemon, such as its PID, for shell scripts
+    Get(GetArgs),
+
+    /// Check a daemon for Nagios-compatible monitoring: one status line and
+    /// exit code 0 OK, 1 WARNING, 2 CRITICAL or 3 UNKNOWN
+    Check(CheckArgs),
+
+    /// Find the daemon a PID, port or file belongs to
+    Whois(WhoisArgs),
+
+    /// List previous runs of a daemon
+    History(HistoryArgs),
+
+    /// List the runs of a daemon whose logs are kept, for `cat`, `tail` and
+    /// `grep --run`
+    Runs(RunsArgs),
+
+    /// Run a command several times as a supervised daemon and report the
+    /// mean, median and standard deviation of its wall time, CPU time and
+    /// peak RSS
+    Bench(BenchArgs),
+
+    /// Show the lifecycle event log
+    Events(EventsArgs),
+
+    /// Print a status page of the daemons (table, restarts, failing health
+    /// checks, log excerpts), in Markdown by default
+    Report(ReportArgs),
+
+    /// Run a command in a dev loop: restart on source changes, report
+    /// readiness and show its logs
+    Dev(DevArgs),
+
+    /// Randomly kill a daemon, to test crash recovery with its restart
+    /// policy
+    Chaos(ChaosArgs),
+
+    /// Manage cron jobs and show scheduled jobs (`run --every`, `schedule
+    /// add`): last and next run and missed triggers
+    Schedule(ScheduleArgs),
+
+    /// Resident process that applies restart policies, health checks and
+    /// schedules, and pushes new log lines to `tail -f`
+    Agent(AgentArgs),
+
+    /// Serve an HTTP+JSON API to list, run, stop and tail daemons remotely
+    Serve(ServeArgs),
+
+    /// Prepare the project: create the root directory, a starter config.toml
+    /// and demon.toml manifest, and make git ignore the daemon files
+    Init(InitArgs),
+
+    /// Start the daemons of the demon.toml manifest that are not running
+    Up(UpArgs),
+
+    /// Stop the daemons of the demon.toml manifest
+    Down(DownArgs),
+
+    /// Generate service units from daemons, to promote them to real services
+    Export(ExportArgs),
+
+    /// Stop the agent and every daemon, then remove the files of all
+    /// daemons, to reset a development environment
+    Nuke(NukeArgs),
+
+    /// Print a bash or zsh hook that offers to start a project's daemons on
+    /// entering it and can stop them again on leaving
+    ShellHook(ShellHookArgs),
+
+    /// Inspect and change configuration
+    #[command(after_help = format!("Keys:\n{}", config::describe_keys()))]
+    Config(ConfigArgs),
+
+    /// Internal: supervise a daemon process (spawned by `run --supervise`)
+    #[command(hide = true)]
+    Supervise(SuperviseArgs),
+
+    /// Internal: run the plugins in hooks.d for an event read from stdin
+    #[command(hide = true)]
+    RunPlugins(RunPluginsArgs),
+}
+
+#[derive(Args)]
+struct RunArgs {
+    #[clap(flatten)]
+    global: Global,
+
+    /// Process identifier
+    id: String,
+
+    /// Verify that the spawned process runs in its own session and process
+    /// group without a controlling terminal, failing if it does not
+    #[arg(long)]
+    verify_detach: bool,
+
+    /// Run under a supervisor process that holds a pidfd for the daemon,
+    /// making stop and liveness checks immune to PID reuse and recording the
+    /// exit status [config: run.supervise]
+    #[arg(long)]
+    supervise: bool,
+
+    /// Run the command on a pseudo-terminal that `demon attach` connects to,
+    /// for interactive programs and programs that only flush every line on a
+    /// terminal; stderr goes to the stdout log (implies --supervise)
+    #[arg(long)]
+    pty: bool,
+
+    /// Standard input of the daemon: null (/dev/null) or fifo, a named pipe
+    /// `<id>.stdin` that `demon input` writes to
+    #[arg(long, value_enum, value_name = "MODE", conflicts_with = "pty")]
+    stdin: Option<StdinMode>,
+
+    /// Read standard input from a file, like `command < file`, e.g. for batch
+    /// jobs such as `psql`
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["pty", "stdin"])]
+    stdin_file: Option<PathBuf>,
+
+    #[clap(flatten)]
+    hooks: hooks::Hooks,
+
+    #[clap(flatten)]
+    policy: agent::Policy,
+
+    /// Command and arguments to execute
+    command: Vec<String>,
+}
+
+#[derive(Args)]
+struct StopArgs {
+    #[clap(flatten)]
+    global: Global,
+
+    /// Process identifier
+    #[arg(required_unless_present_any = ["group", "label"], conflicts_with_all = ["group", "label"])]
+    id: Option<String>,
+
+    #[clap(flatten)]
+    selector: Selector,
+
+    /// Timeout in seconds before sending SIGKILL after SIGTERM [config:
+    /// daemon.<id>.stop_timeout, stop.timeout]
+    #[arg(long)]
+    timeout: Option<u64>,
+}
+
+#[derive(Args)]
+struct RestartArgs {
+    #[clap(flatten)]
+    global: Global,
+
+    /// Process identifier
+    #[arg(required_unless_present_any = ["group", "label"], conflicts_with_all = ["group", "label"])]
+    id: Option<String>,
+
+    #[clap(flatten)]
+    selector: Selector,
+
+    /// Timeout in seconds before sending SIGKILL after SIGTERM [config:
+    /// daemon.<id>.stop_timeout, stop.timeout]
+    #[arg(long)]
+    timeout: Option<u64>,
+}
+
+#[derive(Args)]
+struct ReloadArgs {
+    #[clap(flatten)]
+    global: Global,
+
+    /// Process identifier
+    id: String,
+}
+
+#[derive(Args)]
+struct AttachArgs {
+    #[clap(flatten)]
+    global: Global,
+
+    /// Process identifier
+    id: String,
+}
+
+#[derive(Args)]
+struct InputArgs {
+    #[clap(flatten)]
+    global: Global,
+
+    /// Process identifier
+    id: String,
+
+    /// Text to send as one line; without text or --file, our standard input
+    /// is sent
+    text: Vec<String>,
+
+    /// Send the contents of a file as they are
+    #[arg(long, conflicts_with = "text")]
+    file: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct TailArgs {
+    #[clap(flatten)]
+    global: Global,
+
+    /// Process identifier
+    #[arg(required_unless_present_any = ["group", "label"], conflicts_with_all = ["group", "label"])]
+    id: Option<String>,
+
+    /// Tail every daemon in a group or with a label, each line prefixed
+    /// with the daemon and stream it comes from
+    #[clap(flatten)]
+    selector: Selector,
+
+    /// Show a past run instead of the latest: previous, an index from
+    /// `demon runs` (1 is the previous run) or a time, given like --since,
+    /// picking the run that was live then
+    #[arg(long, value_name = "RUN", conflicts_with_all = ["follow", "group", "label"])]
+    run: Option<String>,
+
+    /// Show the kept past runs too, the oldest first, each like the current
+    /// run, which comes last and is the one followed
+    #[arg(long, conflicts_with_all = ["run", "group", "label"])]
+    all_runs: bool,
+
+    /// Only tail stdout
+    #[arg(long)]
+    stdout: bool,
+
+    /// Only tail stderr
+    #[arg(long)]
+    stderr: bool,
+
+    /// Follow mode - continuously watch for new content (like tail -f)
+    #[arg(short = 'f', long)]
+    follow: bool,
+
+    /// Stop following once the daemon has exited and its logs are printed,
+    /// e.g. to stream the logs of a job in CI
+    #[arg(long, requires = "follow")]
+    until_exit: bool,
+
+    /// Number of lines to display from the end [config: tail.lines]
+    #[arg(short = 'n', long)]
+    lines: Option<usize>,
+
+    /// Only show lines written since a time: a duration ago (10m, 2h), a
+    /// Unix timestamp or a local date and time (2025-06-01 14:30); needs a
+    /// supervised daemon
+    #[arg(long, value_name = "TIME")]
+    since: Option<String>,
+
+    /// Only show lines written before a time, given like --since
+    #[arg(long, value_name = "TIME", conflicts_with = "follow")]
+    until: Option<String>,
+
+    /// Poll the logs instead of using inotify, for containers, WSL1 and
+    /// network file systems [config: watch.poll]
+    #[arg(long)]
+    no_notify_watch: bool,
+
+    /// Print the logs as they are, or each line as a JSON object
+    /// [config: format]
+    #[arg(long, value_enum, value_name = "FORMAT")]
+    output: Option<logs::Output>,
+
+    /// Write the daemon's stderr to stderr instead of stdout, so the streams
+    /// can be redirected separately
+    #[arg(long)]
+    split_streams: bool,
+    /// Remove ANSI escape sequences (colors) from the output
+    #[arg(long)]
+    strip_ansi: bool,
+
+    /// Milliseconds followed output may stay buffered while the logs keep
+    /// growing; 0 writes it as soon as it is read [config: tail.flush_interval]
+    #[arg(long, value_name = "MS", requires = "follow")]
+    flush_interval: Option<u64>,
+
+    /// Print at most this many new lines a second while following, dropping
+    /// the others, for daemons that log faster than a terminal can show
+    #[arg(long, value_name = "LINES", requires = "follow", value_parser = clap::value_parser!(u64).range(1..))]
+    max_rate: Option<u64>,
+}
+
+#[derive(Args)]
+struct CatArgs {
+    #[clap(flatten)]
+    global: Global,
+
+    /// Process identifier
+    id: String,
+
+    /// Show a past run instead of the latest: previous, an index from
+    /// `demon runs` (1 is the previous run) or a time, given like --since,
+    /// picking the run that was live then
+    #[arg(long, value_name = "RUN")]
+    run: Option<String>,
+
+    /// Show the kept past runs too, the oldest first and the current run last
+    #[arg(long, conflicts_with = "run")]
+    all_runs: bool,
+
+    /// Only show stdout
+    #[arg(long)]
+    stdout: bool,
+
+    /// Only show stderr
+    #[arg(long)]
+    stderr: bool,
+
+    /// Only show the first N lines of each log
+    #[arg(long, value_name = "N", conflicts_with_all = ["tail", "bytes"])]
+    head: Option<usize>,
+
+    /// Only show the last N lines of each log
+    #[arg(long, value_name = "N", conflicts_with = "bytes")]
+    tail: Option<usize>,
+
+    /// Only show the last N bytes of each log, e.g. 4096 or 10M
+    #[arg(long, value_name = "N", value_parser = parse_bytes)]
+    bytes: Option<u64>,
+
+    /// Only show lines written since a time: a duration ago (10m, 2h), a
+    /// Unix timestamp or a local date and time (2025-06-01 14:30); needs a
+    /// supervised daemon
+    #[arg(long, value_name = "TIME")]
+    since: Option<String>,
+
+    /// Only show lines written before a time, given like --since
+    #[arg(long, value_name = "TIME")]
+    until: Option<String>,
+
+    /// Print the logs as they are, or each line as a JSON object
+    /// [config: format]
+    #[arg(long, value_enum, value_name = "FORMAT")]
+    output: Option<logs::Output>,
+
+    /// Write the daemon's stderr to stderr instead of stdout, so the streams
+    /// can be redirected separately
+    #[arg(long)]
+    split_streams: bool,
+    /// Remove ANSI escape sequences (colors) from the output
+    #[arg(long)]
+    strip_ansi: bool,
+}
+
+#[derive(Args)]
+struct LogsArgs {
+    #[clap(flatten)]
+    global: Global,
+
+    /// Process identifiers
+    #[arg(required = true)]
+    ids: Vec<String>,
+
+    /// Interleave stdout and stderr of all the daemons by when each line was
+    /// written, to within 250ms; needs supervised daemons
+    #[arg(long)]
+    merge: bool,
+
+    /// Only show lines written since a time, given like for cat
+    #[arg(long, value_name = "TIME")]
+    since: Option<String>,
+
+    /// Only show lines written before a time, given like for cat
+    #[arg(long, value_name = "TIME")]
+    until: Option<String>,
+
+    /// Print the logs as they are, or each line as a JSON object
+    /// [config: format]
+    #[arg(long, value_enum, value_name = "FORMAT")]
+    output: Option<logs::Output>,
+    /// Remove ANSI escape sequences (colors) from the output
+    #[arg(long)]
+    strip_ansi: bool,
+}
+
+#[derive(Args)]
+struct GrepArgs {
+    #[clap(flatten)]
+    global: Global,
+
+    /// Process identifier; with --all, the pattern
+    id: String,
+
+    /// Regular expression to search for
+    pattern: Option<String>,
+
+    /// Search a past run instead of the latest: previous, an index from
+    /// `demon runs` (1 is the previous run) or a time, given like --since,
+    /// picking the run that was live then
+    #[arg(long, value_name = "RUN", conflicts_with = "all")]
+    run: Option<String>,
+
+    /// Search the logs of every daemon in the root directory
+    #[arg(long)]
+    all: bool,
+
+    /// Match case-insensitively
+    #[arg(short = 'i', long)]
+    ignore_case: bool,
+
+    /// Print NUM lines of context after every match
+    #[arg(short = 'A', long, value_name = "NUM")]
+    after_context: Option<usize>,
+
+    /// Print NUM lines of context before every match
+    #[arg(short = 'B', long, value_name = "NUM")]
+    before_context: Option<usize>,
+
+    /// Print NUM lines of context before and after every match
+    #[arg(short = 'C', long, value_name = "NUM")]
+    context: Option<usize>,
+
+    /// Prefix lines with their line number in the log
+    #[arg(short = 'n', long)]
+    line_number: bool,
+
+    /// Only search stdout
+    #[arg(long)]
+    stdout: bool,
+
+    /// Only search stderr
+    #[arg(long)]
+    stderr: bool,
+    /// Remove ANSI escape sequences (colors) from the lines before searching
+    #[arg(long)]
+    strip_ansi: bool,
+}
+
+#[derive(Args)]
+struct CpLogsArgs {
+    #[clap(flatten)]
+    global: Global,
+
+    /// Process identifier
+    id: String,
+
+    /// Directory to copy into, or a URL such as s3://bucket/prefix
+    /// [config: logs.uploader]
+    destination: String,
+
+    /// Only copy stdout
+    #[arg(long)]
+    stdout: bool,
+
+    /// Only copy stderr
+    #[arg(long)]
+    stderr: bool,
+}
+
+#[derive(Args)]
+struct ListArgs {
+    #[clap(flatten)]
+    global: Global,
+
+    /// Quiet mode - output only process data without headers
+    #[arg(short, long)]
+    quiet: bool,
+
+    /// One table of RUNNING and DEAD daemons instead of sections by state
+    #[arg(long)]
+    flat: bool,
+
+    /// Only list daemons in this state
+    #[arg(long, value_enum, default_value_t)]
+    status: StatusFilter,
+
+    /// Only list daemons whose ID matches a glob, e.g. 'web-*'
+    #[arg(long, value_name = "GLOB")]
+    filter: Option<String>,
+
+    #[clap(flatten)]
+    selector: Selector,
+
+    /// Order of the daemons
+    #[arg(long, value_enum, default_value_t)]
+    sort: SortKey,
+
+    /// Comma-separated columns to show, e.g. id,pid,status,uptime
+    #[arg(long, value_enum, value_delimiter = ',', value_name = "COLUMNS")]
+    columns: Vec<Column>,
+
+    /// Redraw the list every interval (default 2s) and whenever a daemon
+    /// changes, e.g. --watch or --watch=10s
+    #[arg(long, value_name = "INTERVAL", num_args = 0..=1, require_equals = true,
+          default_missing_value = "2s", value_parser = agent::parse_interval)]
+    watch: Option<u64>,
+
+    /// With --watch, poll the root directory instead of using inotify
+    /// [config: watch.poll]
+    #[arg(long)]
+    no_notify_watch: bool,
+
+    /// List the daemons of every root directory daemons were run from,
+    /// flagging the same ID or command running in more than one
+    #[arg(long, conflicts_with_all = ["quiet", "flat", "columns", "watch"])]
+    all_roots: bool,
+}
+
+#[derive(Args)]
+struct StatusArgs {
+    #[clap(flatten)]
+    global: Global,
+
+    /// Process identifier
+    #[arg(required_unless_present = "all")]
+    id: Option<String>,
+
+    /// Show the status of every daemon, sorted by ID
+    #[arg(long, conflicts_with = "id")]
+    all: bool,
+
+    /// Redraw the status every interval (default 2s) and whenever the
+    /// daemon changes, e.g. --watch or --watch=10s
+    #[arg(long, value_name = "INTERVAL", num_args = 0..=1, require_equals = true,
+          default_missing_value = "2s", value_parser = agent::parse_interval)]
+    watch: Option<u64>,
+
+    /// With --watch, poll the root directory instead of using inotify
+    /// [config: watch.poll]
+    #[arg(long)]
+    no_notify_watch: bool,
+}
+
+#[derive(Args)]
+struct CleanArgs {
+    #[clap(flatten)]
+    global: Global,
+
+    /// Only print what would be removed
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Only clean daemons whose ID matches a glob pattern
+    #[arg(long, value_name = "GLOB")]
+    id: Option<String>,
+
+    /// Only remove the logs, keeping the PID file and metadata so the daemon
+    /// is still listed
+    #[arg(long)]
+    logs_only: bool,
+
+    /// Only clean daemons whose files were last written longer ago, e.g. 7d
+    #[arg(long, value_name = "INTERVAL", value_parser = agent::parse_interval)]
+    older_than: Option<u64>,
+}
+
+#[derive(Args)]
+struct GcArgs {
+    #[clap(flatten)]
+    global: Global,
+
+    #[clap(flatten)]
+    retention: gc::Retention,
+
+    /// Only print what would be removed
+    #[arg(long)]
+    dry_run: bool,
+}
+
+#[derive(Args)]
+struct LlmArgs {
+    /// Print the markdown guide, or a JSON description of every command for
+    /// generating tool bindings
+    #[arg(long, value_enum, default_value_t = llm::Format::Markdown)]
+    format: llm::Format,
+}
+
+#[derive(Args)]
+struct WaitArgs {
+    #[clap(flatten)]
+    global: Global,
+
+    /// Process identifier
+    id: String,
+
+    /// Timeout in seconds (0 = infinite) [config: wait.timeout]
+    #[arg(long)]
+    timeout: Option<u64>,
+
+    /// Polling interval in seconds [config: wait.interval]
+    #[arg(long)]
+    interval: Option<u64>,
+}
+
+#[derive(Args)]
+pub(crate) struct ProxyArgs {
+    #[clap(flatten)]
+    global: Global,
+
+    /// Process identifier
+    pub(crate) id: String,
+
+    /// Address the proxy listens on (the port clients already use)
+    #[arg(long)]
+    pub(crate) listen: String,
+
+    /// Address the daemon itself listens on
+    #[arg(long)]
+    pub(crate) upstream: String,
+
+    /// Seconds to wait for the upstream to accept connections after starting
+    /// [config: proxy.connect_timeout]
+    #[arg(long)]
+    pub(crate) connect_timeout: Option<u64>,
+
+    /// Command and arguments used to start the daemon
+    pub(crate) command: Vec<String>,
+}
+
+#[derive(Args)]
+struct TopArgs {
+    #[clap(flatten)]
+    global: Global,
+
+    /// Refresh interval in milliseconds [config: top.interval]
+    #[arg(long)]
+    interval: Option<u64>,
+}
+
+#[derive(Args)]
+struct ViewArgs {
+    #[clap(flatten)]
+    global: Global,
+
+    /// Process identifier
+    id: String,
+
+    /// Show a past run instead of the latest: previous, an index from
+    /// `demon runs` (1 is the previous run) or a time, given like --since,
+    /// picking the run that was live then
+    #[arg(long, value_name = "RUN", conflicts_with = "follow")]
+    run: Option<String>,
+
+    /// Lines of each log to load from the end
+    #[arg(short = 'n', long, default_value_t = 100_000)]
+    lines: usize,
+
+    /// Start following new output
+    #[arg(short = 'f', long)]
+    follow: bool,
+}
+
+#[derive(Args)]
+struct StatsArgs {
+    #[clap(flatten)]
+    global: Global,
+
+    /// Process identifier
+    id: String,
+}
+
+#[derive(Args)]
+struct GetArgs {
+    #[clap(flatten)]
+    global: Global,
+
+    /// Process identifier
+    id: String,
+
+    /// Value to print
+    #[arg(value_enum)]
+    field: get::Field,
+}
+
+#[derive(Args)]
+struct WhoisArgs {
+    #[clap(flatten)]
+    global: Global,
+
+    #[clap(flatten)]
+    target: WhoisTarget,
+}
+
+#[derive(Args)]
+#[group(required = true, multiple = false)]
+struct WhoisTarget {
+    /// A process: the daemon itself, one it started or its supervisor
+    #[arg(long)]
+    pid: Option<u32>,
+
+    /// A TCP port listened on or a UDP port bound to
+    #[arg(long)]
+    port: Option<u16>,
+
+    /// A file or directory held open, used as working directory or executed
+    #[arg(long)]
+    file: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct HistoryArgs {
+    #[clap(flatten)]
+    global: Global,
+
+    /// Process identifier
+    id: String,
+
+    /// Maximum number of finished runs to show
+    #[arg(short = 'n', long, default_value = "20")]
+    limit: usize,
+
+    /// Summarize the success rate, durations and duration trend of all
+    /// recorded runs instead of listing them
+    #[arg(long, conflicts_with = "limit")]
+    stats: bool,
+}
+
+#[derive(Args)]
+struct RunsArgs {
+    #[clap(flatten)]
+    global: Global,
+
+    /// Process identifier
+    id: String,
+}
+
+#[derive(Args)]
+struct BenchArgs {
+    #[clap(flatten)]
+    global: Global,
+
+    /// Process identifier
+    id: String,
+
+    /// Number of runs
+    #[arg(long, default_value = "5", value_parser = clap::value_parser!(u32).range(1..))]
+    runs: u32,
+
+    /// Read standard input of every run from a file, like `command < file`
+    #[arg(long, value_name = "PATH")]
+    stdin_file: Option<PathBuf>,
+
+    /// Command and arguments to execute
+    command: Vec<String>,
+}
+
+#[derive(Args)]
+struct EventsArgs {
+    #[clap(flatten)]
+    global: Global,
+
+    /// Only show events of this daemon
+    #[arg(long)]
+    id: Option<String>,
+
+    /// Only show events since a time: a duration ago (10m, 2h, 7d), a Unix
+    /// timestamp or a local date and time (2025-06-01 14:30)
+    #[arg(long)]
+    since: Option<String>,
+
+    /// Keep watching for new events (like tail -f)
+    #[arg(short = 'f', long)]
+    follow: bool,
+
+    /// Poll the event log instead of using inotify, for containers, WSL1 and
+    /// network file systems [config: watch.poll]
+    #[arg(long)]
+    no_notify_watch: bool,
+}
+
+#[derive(Args)]
+struct ReportArgs {
+    #[clap(flatten)]
+    global: Global,
+
+    /// Daemons to report on
+    #[arg(required_unless_present = "all")]
+    ids: Vec<String>,
+
+    /// Report on every daemon in the root directory
+    #[arg(long, conflicts_with = "ids")]
+    all: bool,
+
+    /// Report restarts and other events since a time: a duration ago (10m,
+    /// 2h, 7d), a Unix timestamp or a local date and time
+    #[arg(long, default_value = "24h")]
+    since: String,
+
+    /// Lines of each log to include
+    #[arg(short = 'n', long, default_value_t = 10)]
+    lines: usize,
+
+    /// Write a tar.gz for a bug report instead: the report, recent events,
+    /// configuration, machine information, and the metadata, environment and
+    /// log tails of every daemon (default: demon-report-<time>.tar.gz)
+    #[arg(long, value_name = "PATH", num_args = 0..=1)]
+    bundle: Option<Option<PathBuf>>,
+
+    /// Kilobytes at the end of each log to put in the bundle
+    #[arg(long, default_value_t = 64, requires = "bundle")]
+    log_kb: u64,
+
+    /// Replace the values of environment variables in the bundle
+    #[arg(long, requires = "bundle")]
+    redact: bool,
+}
+
+#[derive(Args)]
+struct AgentArgs {
+    #[clap(flatten)]
+    global: Global,
+
+    /// Run in the background, logging to agent.log in the root directory
+    #[arg(long, conflicts_with = "stop")]
+    detach: bool,
+
+    /// Stop the running agent
+    #[arg(long)]
+    stop: bool,
+}
+
+#[derive(Args)]
+struct ServeArgs {
+    #[clap(flatten)]
+    global: Global,
+
+    /// Address to listen on
+    #[arg(long, default_value = "127.0.0.1:8787")]
+    listen: String,
+
+    /// Require `Authorization: Bearer <TOKEN>` on every request
+    #[arg(long, env = "DEMON_SERVE_TOKEN", hide_env_values = true)]
+    token: Option<String>,
+
+    /// Have the running server of the root directory replace itself with the
+    /// demon binary installed now, keeping its socket, PID and daemons
+    #[arg(long, conflicts_with = "listen")]
+    upgrade: bool,
+}
+
+#[derive(Args)]
+struct DevArgs {
+    #[clap(flatten)]
+    global: Global,
+
+    /// Process identifier
+    id: String,
+
+    /// File or directory to watch, can be repeated (default: picked from the
+    /// command, e.g. src and Cargo.toml for cargo)
+    #[arg(short = 'w', long = "watch")]
+    watch: Vec<PathBuf>,
+
+    /// Directory name to ignore changes in, in addition to the preset's
+    #[arg(long)]
+    ignore: Vec<String>,
+
+    /// Case-insensitive text that marks the daemon as ready when it shows up
+    /// in its logs, can be repeated (default: picked from the command, e.g.
+    /// "listening")
+    #[arg(long)]
+    ready: Vec<String>,
+
+    /// Milliseconds to wait for changes to settle before restarting [config: dev.debounce]
+    #[arg(long)]
+    debounce: Option<u64>,
+
+    /// Poll the watched paths instead of using inotify, for containers, WSL1
+    /// and network file systems [config: watch.poll]
+    #[arg(long)]
+    no_notify_watch: bool,
+
+    /// Command and arguments to execute
+    command: Vec<String>,
+}
+
+#[derive(Args)]
+struct CheckArgs {
+    #[clap(flatten)]
+    global: Global,
+
+    /// Process identifier
+    id: String,
+
+    /// RSS from which the daemon is WARNING, e.g. 512M or 1.5G
+    #[arg(long, value_name = "SIZE", value_parser = parse_bytes)]
+    warn_mem: Option<u64>,
+
+    /// RSS from which the daemon is CRITICAL, e.g. 1G
+    #[arg(long, value_name = "SIZE", value_parser = parse_bytes)]
+    crit_mem: Option<u64>,
+
+    /// Number of restarts within --window above which the daemon is WARNING
+    #[arg(long, value_name = "N")]
+    max_restarts: Option<usize>,
+
+    /// How far back restarts are counted, e.g. 1h or 30m
+    #[arg(long, value_name = "INTERVAL", default_value = "1h", value_parser = agent::parse_interval)]
+    window: u64,
+}
+
+#[derive(Args)]
+struct ChaosArgs {
+    #[clap(flatten)]
+    global: Global,
+
+    /// Process identifier
+    id: String,
+
+    /// Uptime of the daemon before each roll of the dice, e.g. 30s or 5m
+    #[arg(long, value_name = "INTERVAL", default_value = "30s", value_parser = agent::parse_interval)]
+    kill_after: u64,
+
+    /// Signal to send, e.g. KILL, TERM, SEGV or 9
+    #[arg(long, default_value = "KILL")]
+    signal: reload::Signal,
+
+    /// Chance of sending the signal on each roll, from 0 to 1
+    #[arg(long, default_value = "1", value_parser = chaos::parse_probability)]
+    probability: f64,
+
+    /// Stop after this many disruptions (default: until Ctrl+C or the daemon
+    /// is stopped)
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    count: Option<u32>,
+}
+
+#[derive(Args)]
+struct InitArgs {
+    #[clap(flatten)]
+    global: Global,
+
+    /// Only append patterns ignoring PID files and logs to the .gitignore at
+    /// the top of the working tree
+    #[arg(long, conflicts_with = "procfile")]
+    gitignore: bool,
+
+    /// Import the processes of a Procfile into the new demon.toml (default:
+    /// the Procfile next to it)
+    #[arg(long, value_name = "PATH", num_args = 0..=1)]
+    procfile: Option<Option<PathBuf>>,
+}
+
+#[derive(Args)]
+struct UpArgs {
+    #[clap(flatten)]
+    global: Global,
+
+    /// Daemons to start (default: all of them, in manifest order)
+    ids: Vec<String>,
+
+    /// Wait until every daemon runs, has sent READY=1 with sd_notify and
+    /// passed its health_cmd, printing when each became ready
+    #[arg(long)]
+    wait_healthy: bool,
+
+    /// How long --wait-healthy waits, e.g. 120s or 5m; the daemons not ready
+    /// by then are listed and demon exits with 5
+    #[arg(long, value_name = "INTERVAL", value_parser = agent::parse_interval, default_value = "60s", requires = "wait_healthy")]
+    timeout: u64,
+
+    /// When another up or down runs in the root directory, wait for it to
+    /// finish instead of failing, at most the given interval if any, e.g.
+    /// --wait-lock or --wait-lock=30s
+    #[arg(long, value_name = "INTERVAL", num_args = 0..=1, require_equals = true,
+          value_parser = agent::parse_interval)]
+    wait_lock: Option<Option<u64>>,
+}
+
+#[derive(Args)]
+struct ExportArgs {
+    #[clap(flatten)]
+    global: Global,
+
+    #[command(subcommand)]
+    target: ExportTarget,
+}
+
+#[derive(Subcommand)]
+enum ExportTarget {
+    /// A systemd .service unit with the command, working directory,
+    /// environment, restart policy and limits of the daemon
+    Systemd {
+        /// Daemon to export, from its last run or else from demon.toml
+        #[arg(required_unless_present = "all")]
+        id: Option<String>,
+
+        /// Export every daemon of demon.toml
+        #[arg(long, conflicts_with = "id")]
+        all: bool,
+
+        /// Write <id>.service files to this directory instead of printing
+        /// them, e.g. ~/.config/systemd/user
+        #[arg(long, value_name = "DIR")]
+        output: Option<PathBuf>,
+
+        /// Install into multi-user.target of the system manager instead of
+        /// default.target of the user's
+        #[arg(long)]
+        system: bool,
+    },
+
+    /// A launchd agent plist with the command, working directory,
+    /// environment, restart policy and limits of the daemon, for macOS
+    Launchd {
+        /// Daemon to export, from its last run or else from demon.toml
+        #[arg(required_unless_present = "all")]
+        id: Option<String>,
+
+        /// Export every daemon of demon.toml
+        #[arg(long, conflicts_with = "id")]
+        all: bool,
+
+        /// Write local.demon.<id>.plist files to this directory instead of
+        /// printing them, e.g. ~/Library/LaunchAgents
+        #[arg(long, value_name = "DIR")]
+        output: Option<PathBuf>,
+    },
+}
+
+#[derive(Args)]
+struct DownArgs {
+    #[clap(flatten)]
+    global: Global,
+
+    /// Daemons to stop (default: all of them, in reverse manifest order)
+    ids: Vec<String>,
+
+    /// Timeout in seconds before sending SIGKILL after SIGTERM [config:
+    /// daemon.<id>.stop_timeout, stop.timeout]
+    #[arg(long)]
+    timeout: Option<u64>,
+
+    /// When another up or down runs in the root directory, wait for it to
+    /// finish instead of failing, at most the given interval if any, e.g.
+    /// --wait-lock or --wait-lock=30s
+    #[arg(long, value_name = "INTERVAL", num_args = 0..=1, require_equals = true,
+          value_parser = agent::parse_interval)]
+    wait_lock: Option<Option<u64>>,
+}
+
+#[derive(Args)]
+struct NukeArgs {
+    #[clap(flatten)]
+    global: Global,
+
+    /// Do not ask for confirmation
+    #[arg(short, long)]
+    yes: bool,
+
+    /// Timeout in seconds before sending SIGKILL after SIGTERM [config: stop.timeout]
+    #[arg(long)]
+    timeout: Option<u64>,
+
+    /// When an up or down runs in the root directory, wait for it to finish
+    /// instead of failing, at most the given interval if any, e.g.
+    /// --wait-lock or --wait-lock=30s
+    #[arg(long, value_name = "INTERVAL", num_args = 0..=1, require_equals = true,
+          value_parser = agent::parse_interval)]
+    wait_lock: Option<Option<u64>>,
+}
+
+#[derive(Args)]
+struct ShellHookArgs {
+    #[clap(flatten)]
+    global: Global,
+
+    /// Shell to print the hook for
+    #[arg(value_enum, required_unless_present = "state")]
+    shell: Option<shell_hook::Shell>,
+
+    /// On entering a project whose daemons are not all running: ask before
+    /// starting them, start them, or only say so
+    #[arg(long, value_enum, default_value = "ask")]
+    up: shell_hook::Up,
+
+    /// Stop the daemons the shell started: never, on leaving the project or
+    /// exiting the shell, or only on exiting
+    #[arg(long, value_enum, default_value = "never")]
+    stop: shell_hook::Stop,
+
+    /// Internal: print the root directory, manifest and stopped daemons of
+    /// the current directory, for the hook
+    #[arg(long, hide = true)]
+    state: bool,
+}
+
+#[derive(Args)]
+struct ScheduleArgs {
+    #[clap(flatten)]
+    global: Global,
+
+    #[command(subcommand)]
+    action: ScheduleAction,
+}
+
+#[derive(Subcommand)]
+enum ScheduleAction {
+    /// List scheduled jobs with their last and next run, catch-up policy and
+    /// missed triggers
+    List,
+
+    /// Add a job the agent runs on a cron schedule, e.g. `--cron "0 3 * * *"`;
+    /// adding an existing ID replaces its schedule and command
+    Add {
+        /// Process identifier
+        id: String,
+
+        /// Cron expression: minute hour day-of-month month day-of-week, in
+        /// local time, or @hourly, @daily, @weekly, @monthly, @yearly
+        #[arg(long, value_name = "EXPR")]
+        cron: String,
+
+        #[command(flatten)]
+        requirements: Requirements,
+
+        /// Command and arguments to execute
+        #[arg(last = true, required = true)]
+        command: Vec<String>,
+    },
+
+    /// Remove a cron job; a run in progress is not stopped
+    Remove {
+        /// Process identifier
+        id: String,
+    },
+}
+
+#[derive(Args)]
+struct ConfigArgs {
+    #[clap(flatten)]
+    global: Global,
+
+    #[command(subcommand)]
+    action: ConfigAction,
+}
+
+#[derive(Subcommand)]
+enum ConfigAction {
+    /// Print the resolved value of a key
+    Get {
+        /// Configuration key, e.g. stop.timeout
+        key: String,
+    },
+
+    /// Set a key in the per-root config file (or the global one)
+    Set {
+        /// Configuration key, e.g. stop.timeout
+        key: String,
+
+        /// New value
+        value: String,
+
+        /// Write to the global config file instead of the root's
+        #[arg(long)]
+        global: bool,
+    },
+
+    /// List all keys with their resolved values and where they come from
+    List,
+
+    /// Check config files and DEMON_* variables for errors
+    Validate,
+}
+
+#[derive(Args)]
+struct RunPluginsArgs {
+    #[clap(flatten)]
+    global: Global,
+}
+
+#[derive(Args)]
+struct SuperviseArgs {
+    #[clap(flatten)]
+    global: Global,
+
+    /// Process identifier
+    id: String,
+
+    /// Run the daemon on a pseudo-terminal
+    #[arg(long)]
+    pty: bool,
+
+    /// Standard input of the daemon
+    #[arg(long, value_enum)]
+    stdin: Option<StdinMode>,
+
+    /// Read standard input from a file
+    #[arg(long)]
+    stdin_file: Option<PathBuf>,
+
+    #[clap(flatten)]
+    hooks: hooks::Hooks,
+
+    #[clap(flatten)]
+    policy: agent::Policy,
+
+    /// Command and arguments to execute
+    command: Vec<String>,
+}
+
+/// Entry point of the `demon` binary
+pub fn main() {
+    let cli = Cli::parse();
+
+    tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .with_ansi(use_color(&cli.command))
+        .init();
+
+    if let Err(e) = run_command(cli.command) {
+        let exit = e.downcast_ref::<Exit>();
+        if !exit.is_some_and(Exit::is_silent) {
+            tracing::error!("Error: {}", e);
+        }
+        std::process::exit(exit.map_or(exit::FAILURE, |exit| exit.code));
+    }
+}
+
+impl Commands {
+    fn global(&self) -> Option<&Global> {
+        match self {
+            Commands::Run(args) => Some(&args.global),
+            Commands::Stop(args) => Some(&args.global),
+            Commands::Restart(args) => Some(&args.global),
+            Commands::Reload(args) => Some(&args.global),
+            Commands::Attach(args) => Some(&args.global),
+            Commands::Input(args) => Some(&args.global),
+            Commands::Tail(args) => Some(&args.global),
+            Commands::Cat(args) => Some(&args.global),
+            Commands::Logs(args) => Some(&args.global),
+            Commands::Grep(args) => Some(&args.global),
+            Commands::CpLogs(args) => Some(&args.global),
+            Commands::List(args) => Some(&args.global),
+            Commands::Status(args) => Some(&args.global),
+            Commands::Clean(args) => Some(&args.global),
+            Commands::Gc(args) => Some(&args.global),
+            Commands::Llm(_) => None,
+            Commands::Wait(args) => Some(&args.global),
+            Commands::Proxy(args) => Some(&args.global),
+            Commands::Top(args) => Some(&args.global),
+            Commands::View(args) => Some(&args.global),
+            Commands::Stats(args) => Some(&args.global),
+            Commands::Get(args) => Some(&args.global),
+            Commands::Check(args) => Some(&args.global),
+            Commands::Whois(args) => Some(&args.global),
+            Commands::History(args) => Some(&args.global),
+            Commands::Runs(args) => Some(&args.global),
+            Commands::Bench(args) => Some(&args.global),
+            Commands::Events(args) => Some(&args.global),
+            Commands::Report(args) => Some(&args.global),
+            Commands::Dev(args) => Some(&args.global),
+            Commands::Chaos(args) => Some(&args.global),
+            Commands::Schedule(args) => Some(&args.global),
+            Commands::Agent(args) => Some(&args.global),
+            Commands::Serve(args) => Some(&args.global),
+            Commands::Init(args) => Some(&args.global),
+            Commands::Up(args) => Some(&args.global),
+            Commands::Down(args) => Some(&args.global),
+            Commands::Export(args) => Some(&args.global),
+            Commands::Nuke(args) => Some(&args.global),
+            Commands::ShellHook(args) => Some(&args.global),
+            Commands::Config(args) => Some(&args.global),
+            Commands::Supervise(args) => Some(&args.global),
+            Commands::RunPlugins(args) => Some(&args.global),
+        }
+    }
+
+    /// Whether the command changes daemons or files, so `--read-only` refuses
+    /// it. `top` and `serve` are allowed and disable their own actions, but
+    /// `serve --upgrade` replaces the running server.
+    fn mutates(&self) -> bool {
+        match self {
+            Commands::Tail(_)
+            | Commands::Cat(_)
+            | Commands::Logs(_)
+            | Commands::Grep(_)
+            | Commands::List(_)
+            | Commands::Status(_)
+            | Commands::Llm(_)
+            | Commands::Wait(_)
+            | Commands::Top(_)
+            | Commands::View(_)
+            | Commands::Stats(_)
+            | Commands::Get(_)
+            | Commands::Check(_)
+            | Commands::Whois(_)
+            | Commands::History(_)
+            | Commands::Runs(_)
+            | Commands::Events(_)
+            | Commands::ShellHook(_) => false,
+            Commands::Serve(args) => args.upgrade,
+            Commands::Report(args) => args.bundle.is_some(),
+            Commands::Export(args) => match &args.target {
+                ExportTarget::Systemd { output, .. } | ExportTarget::Launchd { output, .. } => {
+                    output.is_some()
+                }
+            },
+            Commands::Clean(args) => !args.dry_run,
+            Commands::Gc(args) => !args.dry_run,
+            Commands::Config(args) => matches!(args.action, ConfigAction::Set { .. }),
+            Commands::Schedule(args) => !matches!(args.action, ScheduleAction::List),
+            Commands::Run(_)
+            | Commands::Stop(_)
+            | Commands::Restart(_)
+            | Commands::Reload(_)
+            | Commands::Attach(_)
+            | Commands::Input(_)
+            | Commands::CpLogs(_)
+            | Commands::Proxy(_)
+            | Commands::Bench(_)
+            | Commands::Dev(_)
+            | Commands::Chaos(_)
+            | Commands::Agent(_)
+            | Commands::Init(_)
+            | Commands::Up(_)
+            | Commands::Down(_)
+            | Commands::Nuke(_)
+            | Commands::Supervise(_)
+            | Commands::RunPlugins(_) => true,
+        }
+    }
+
+    fn read_only(&self) -> bool {
+        self.global().is_some_and(|global| global.read_only)
+    }
+}
+
+/// Whether diagnostics on stderr should be colored, per `--color` or the
+/// `color` setting.
+///
+/// This runs before logging is set up, so it only looks at an existing root
+/// directory and ignores invalid configuration (reported later by the command).
+fn use_color(command: &Commands) -> bool {
+    let root_dir = command.global().and_then(|global| match &global.root_dir {
+        Some(dir) => Some(dir.clone()),
+        None => project_dir(global.root_strategy).map(|root| root.join(".demon")),
+    });
+    let config = config::Config::load_lenient(root_dir.as_deref().filter(|dir| dir.is_dir()));
+    color::enabled(
+        command.global().and_then(|global| global.color),
+        &config,
+        std::io::stderr().is_terminal(),
+    )
+}
+
+/// Whether output on stdout should be colored, per `--color` or the `color`
+/// setting
+fn stdout_color(global: &Global, config: &config::Config) -> bool {
+    color::enabled(global.color, config, std::io::stdout().is_terminal())
+}
+
+fn run_command(command: Commands) -> Result<()> {
+    if command.read_only() && command.mutates() {
+        return Err(anyhow::anyhow!(
+            "This command changes daemons or files and is disabled in read-only mode (--read-only or DEMON_READ_ONLY)"
+        ));
+    }
+    let read_only = command.read_only();
+    if command
+        .global()
+        .is_some_and(|global| global.progress == Some(progress::Mode::Json))
+    {
+        progress::enable();
+    }
+    match command {
+        Commands::Run(args) => {
+            if args.command.is_empty() {
+                return Err(anyhow::anyhow!("Command cannot be empty"));
+            }
+            let root_dir = resolve_root_dir(&args.global)?;
+            let config = config::Config::load(&root_dir)?;
+            let defaults = config.daemon(&args.id);
+            let stdin = defaults.stdin(args.pty, args.stdin, args.stdin_file.as_deref())?;
+            let pty = args.pty || (defaults.pty && stdin == Stdin::Null);
+            let options = run_options(
+                &root_dir,
+                &config,
+                args.supervise || defaults.supervise,
+                pty,
+                stdin,
+                &defaults.hooks(args.hooks)?,
+                defaults.policy(args.policy)?,
+            )?;
+            let pid = run_daemon(&args.id, &args.command, &root_dir, &options)?;
+            if options.policy.uses_sd_notify() {
+                sd_notify::wait_ready(&root_dir, &args.id, pid, sd_notify::READY_TIMEOUT)?;
+            }
+            let pid_file = build_file_path(&root_dir, &args.id, "pid");
+            match Format::resolve(args.global.format, &config) {
+                Format::Text => println!(
+                    "Started daemon '{}' with PID written to {}",
+                    args.id,
+                    pid_file.display()
+                ),
+                format => output::print_record(
+                    format,
+                    &serde_json::json!({
+                        "id": args.id,
+                        "pid": pid,
+                        "pid_file": pid_file,
+                        "stdout_file": build_file_path(&root_dir, &args.id, "stdout"),
+                        "stderr_file": build_file_path(&root_dir, &args.id, "stderr"),
+                    }),
+                )?,
+            }
+            if args.verify_detach {
+                verify_detach(pid)?;
+            }
+            Ok(())
+        }
+        Commands::Stop(args) => {
+            let root_dir = resolve_root_dir(&args.global)?;
+            let config = config::Config::load(&root_dir)?;
+            let format = Format::resolve(args.global.format, &config);
+            let ids = selected_ids(&root_dir, args.id.as_deref(), &args.selector)?;
+            let mut records = Vec::new();
+            let mut code = 0;
+            for id in &ids {
+                let timeout = args
+                    .timeout
+                    .or(config.daemon(id).stop_timeout)
+                    .unwrap_or_else(|| config.integer("stop.timeout"));
+                let stopped = match stop_daemon(id, timeout, &root_dir) {
+                    Ok(stopped) => stopped,
+                    // One daemon failing does not keep the rest of a group
+                    // running
+                    Err(e) if args.id.is_none() => {
+                        tracing::error!("Failed to stop '{}': {:#}", id, e);
+                        records.push(serde_json::json!({
+                            "id": id,
+                            "pid": null,
+                            "result": "failed",
+                            "error": format!("{e:#}"),
+                        }));
+                        code = exit::FAILURE;
+                        continue;
+                    }
+                    Err(e) => return Err(e),
+                };
+                if format.is_text() {
+                    println!("{}", stopped.message(id));
+                }
+                records.push(serde_json::json!({
+                    "id": id,
+                    "pid": stopped.pid(),
+                    "result": stopped.result(),
+                }));
+                // A selected daemon that was not running is only reported in
+                // its record
+                if args.id.is_some() {
+                    code = stopped.exit_code();
+                }
+            }
+            match (format, args.id) {
+                (Format::Text, _) => {}
+                (format, Some(_)) => output::print_record(format, &records[0])?,
+                (format, None) => {
+                    output::print_records(format, &["id", "pid", "result", "error"], &records)?
+                }
+            }
+            match code {
+                0 => Ok(()),
+                code => Err(Exit::silent(code).into()),
+            }
+        }
+        Commands::Restart(args) => {
+            let root_dir = resolve_root_dir(&args.global)?;
+            let config = config::Config::load(&root_dir)?;
+            let format = Format::resolve(args.global.format, &config);
+            let ids = selected_ids(&root_dir, args.id.as_deref(), &args.selector)?;
+            let mut records = Vec::new();
+            let mut failed = false;
+            for id in &ids {
+                let timeout = args
+                    .timeout
+                    .or(config.daemon(id).stop_timeout)
+                    .unwrap_or_else(|| config.integer("stop.timeout"));
+                let pid = match agent::restart_daemon(&root_dir, id, timeout) {
+                    Ok(pid) => pid,
+                    Err(e) if args.id.is_none() => {
+                        tracing::error!("Failed to restart '{}': {:#}", id, e);
+                        records.push(serde_json::json!({
+                            "id": id,
+                            "pid": null,
+                            "result": "failed",
+                            "error": format!("{e:#}"),
+                        }));
+                        failed = true;
+                        continue;
+                    }
+                    Err(e) => return Err(e),
+                };
+                if format.is_text() {
+                    println!("Restarted daemon '{id}' with PID {pid}");
+                }
+                records.push(serde_json::json!({
+                    "id": id,
+                    "pid": pid,
+                    "result": "restarted",
+                }));
+            }
+            match (format, args.id) {
+                (Format::Text, _) => {}
+                (format, Some(_)) => output::print_record(format, &records[0])?,
+                (format, None) => {
+                    output::print_records(format, &["id", "pid", "result", "error"], &records)?
+                }
+            }
+            if failed {
+                return Err(Exit::silent(exit::FAILURE).into());
+            }
+            Ok(())
+        }
+        Commands::Reload(args) => {
+            let root_dir = resolve_root_dir(&args.global)?;
+            let (pid, signal) = reload::reload_daemon(&args.id, &root_dir)?;
+            println!("Sent {} to daemon '{}' (PID {})", signal, args.id, pid);
+            Ok(())
+        }
+        Commands::Attach(args) => {
+            let root_dir = resolve_root_dir(&args.global)?;
+            pty::attach(&args.id, &root_dir)
+        }
+        Commands::Input(args) => {
+            let root_dir = resolve_root_dir(&args.global)?;
+            let input = match args.file {
+                Some(file) => stdin::Input::File(file),
+                None if args.text.is_empty() => stdin::Input::Stdin,
+                None => stdin::Input::Line(args.text.join(" ")),
+            };
+            stdin::send_input(&args.id, &root_dir, input)
+        }
+        Commands::Tail(args) => {
+            let show_stdout = !args.stderr || args.stdout;
+            let show_stderr = !args.stdout || args.stderr;
+            let root_dir = resolve_root_dir(&args.global)?;
+            let config = config::Config::load(&root_dir)?;
+            let lines = args
+                .lines
+                .unwrap_or_else(|| config.integer("tail.lines") as usize);
+            let options = logs::TailOptions {
+                show_stdout,
+                show_stderr,
+                follow: args.follow,
+                lines,
+                poll: args.no_notify_watch || config.bool("watch.poll"),
+                output: log_output(args.output, args.global.format, &config),
+                split_streams: args.split_streams,
+                color: stdout_color(&args.global, &config),
+                until_exit: args.until_exit,
+                window: log_window(args.since.as_deref(), args.until.as_deref())?,
+                strip_ansi: args.strip_ansi,
+                flush_interval: Duration::from_millis(
+                    args.flush_interval
+                        .unwrap_or_else(|| config.integer("tail.flush_interval")),
+                ),
+                max_rate: args.max_rate,
+            };
+            let Some(id) = args.id else {
+                let ids = selected_ids(&root_dir, None, &args.selector)?;
+                return tail_group(&ids, &root_dir, &options);
+            };
+            if args.all_runs {
+                // The past runs are printed, and the current one below
+                let mut dirs = runs::dirs(&root_dir, &id)?;
+                dirs.pop();
+                let past = logs::TailOptions {
+                    follow: false,
+                    ..options.clone()
+                };
+                for dir in &dirs {
+                    tail_logs(&id, dir, &past)?;
+                }
+            }
+            let logs_dir = match &args.run {
+                Some(run) => runs::select(&root_dir, &id, run)?,
+                None => root_dir,
+            };
+            tail_logs(&id, &logs_dir, &options)
+        }
+        Commands::Cat(args) => {
+            let show_stdout = !args.stderr || args.stdout;
+            let show_stderr = !args.stdout || args.stderr;
+            let root_dir = resolve_root_dir(&args.global)?;
+            let config = config::Config::load(&root_dir)?;
+            let range = match (args.head, args.tail, args.bytes) {
+                (Some(lines), _, _) => logs::Range::Head(lines),
+                (_, Some(lines), _) => logs::Range::Tail(lines),
+                (_, _, Some(bytes)) => logs::Range::Bytes(bytes),
+                _ => logs::Range::All,
+            };
+            let options = logs::CatOptions {
+                show_stdout,
+                show_stderr,
+                range,
+                window: log_window(args.since.as_deref(), args.until.as_deref())?,
+                output: log_output(args.output, args.global.format, &config),
+                split_streams: args.split_streams,
+                strip_ansi: args.strip_ansi,
+            };
+            if args.all_runs {
+                for dir in runs::dirs(&root_dir, &args.id)? {
+                    cat_logs(&args.id, &dir, &options)?;
+                }
+                return Ok(());
+            }
+            let logs_dir = match &args.run {
+                Some(run) => runs::select(&root_dir, &args.id, run)?,
+                None => root_dir,
+            };
+            cat_logs(&args.id, &logs_dir, &options)
+        }
+        Commands::Logs(args) => {
+            let root_dir = resolve_root_dir(&args.global)?;
+            let config = config::Config::load(&root_dir)?;
+            let window = log_window(args.since.as_deref(), args.until.as_deref())?;
+            let output = log_output(args.output, args.global.format, &config);
+            if args.merge {
+                let options = logs::MergeOptions {
+                    window,
+                    output,
+                    color: stdout_color(&args.global, &config),
+                    strip_ansi: args.strip_ansi,
+                };
+                return logs::merge_logs(&args.ids, &root_dir, &options);
+            }
+            let options = logs::CatOptions {
+                show_stdout: true,
+                show_stderr: true,
+                range: logs::Range::All,
+                window,
+                output,
+                split_streams: false,
+                strip_ansi: args.strip_ansi,
+            };
+            for id in &args.ids {
+                cat_logs(id, &root_dir, &options)?;
+            }
+            Ok(())
+        }
+        Commands::Grep(args) => {
+            let (ids, pattern) = match (args.all, args.pattern) {
+                (false, Some(pattern)) => (vec![args.id], pattern),
+                (false, None) => return Err(anyhow::anyhow!("A pattern is required")),
+                (true, None) => (Vec::new(), args.id),
+                (true, Some(_)) => {
+                    return Err(anyhow::anyhow!("--all takes the pattern without an ID"));
+                }
+            };
+            let root_dir = resolve_root_dir(&args.global)?;
+            let config = config::Config::load(&root_dir)?;
+            let ids = match args.all {
+                true => grep::daemons_with_logs(&root_dir)?,
+                false => ids,
+            };
+            let options = grep::GrepOptions {
+                pattern,
+                ignore_case: args.ignore_case,
+                before: args.before_context.or(args.context).unwrap_or(0),
+                after: args.after_context.or(args.context).unwrap_or(0),
+                line_numbers: args.line_number,
+                show_stdout: !args.stderr || args.stdout,
+                show_stderr: !args.stdout || args.stderr,
+                color: stdout_color(&args.global, &config),
+                strip_ansi: args.strip_ansi,
+            };
+            let logs_dir = match &args.run {
+                Some(run) => runs::select(&root_dir, &ids[0], run)?,
+                None => root_dir,
+            };
+            match grep::grep_logs(&ids, &logs_dir, &options)? {
+                true => Ok(()),
+                // No match, like grep
+                false => Err(Exit::silent(exit::FAILURE).into()),
+            }
+        }
+        Commands::CpLogs(args) => {
+            let root_dir = resolve_root_dir(&args.global)?;
+            let config = config::Config::load(&root_dir)?;
+            let streams: Vec<&'static str> = [
+                (!args.stderr || args.stdout, "stdout"),
+                (!args.stdout || args.stderr, "stderr"),
+            ]
+            .into_iter()
+            .filter_map(|(shown, stream)| shown.then_some(stream))
+            .collect();
+            let uploader = snapshot::uploader_for(&args.destination, config.text("logs.uploader"))?;
+            snapshot::copy_logs(
+                &args.id,
+                &streams,
+                &args.destination,
+                uploader.as_ref(),
+                &root_dir,
+            )
+        }
+        Commands::List(args) => {
+            let root_dir = resolve_root_dir(&args.global)?;
+            let config = config::Config::load(&root_dir)?;
+            let filter = args
+                .filter
+                .map(|filter| {
+                    glob::Pattern::new(&filter).map_err(|e| {
+                        anyhow::anyhow!("Invalid --filter pattern '{}': {}", filter, e)
+                    })
+                })
+                .transpose()?;
+            let options = ListOptions {
+                status: args.status,
+                filter,
+                selector: args.selector,
+                sort: args.sort,
+                columns: args.columns,
+                quiet: args.quiet,
+                flat: args.flat,
+                color: stdout_color(&args.global, &config),
+                widths: MaxWidths::from_config(&config),
+            };
+            // An explicit --quiet wins over the format
+            let format = match args.quiet {
+                true => Format::Text,
+                false => Format::resolve(args.global.format, &config),
+            };
+            if args.all_roots {
+                return roots::list_all(&root_dir, &options, format);
+            }
+            let list = || match format {
+                Format::Text => list_daemons(&options, &root_dir),
+                format => list_daemons_records(&options, format, &root_dir),
+            };
+            match args.watch {
+                Some(interval) => watch::redraw(
+                    &root_dir,
+                    "demon list",
+                    Duration::from_secs(interval),
+                    args.no_notify_watch || config.bool("watch.poll"),
+                    list,
+                ),
+                None => list(),
+            }
+        }
+        Commands::Status(args) => {
+            let root_dir = resolve_root_dir(&args.global)?;
+            let config = config::Config::load(&root_dir)?;
+            let format = Format::resolve(args.global.format, &config);
+            let colored = stdout_color(&args.global, &config);
+            let status = || match (&args.id, format) {
+                (None, format) => status_all(&root_dir, format, colored),
+                (Some(id), Format::Text) => status_daemon(id, &root_dir, colored),
+                (Some(id), format) => output::print_record(format, &status_record(id, &root_dir)?),
+            };
+            let Some(interval) = args.watch else {
+                status()?;
+                let Some(id) = &args.id else {
+                    return Ok(());
+                };
+                return match status_exit_code(&root_dir, id) {
+                    0 => Ok(()),
+                    code => Err(Exit::silent(code).into()),
+                };
+            };
+            watch::redraw(
+                &root_dir,
+                &format!("demon status {}", args.id.as_deref().unwrap_or("--all")),
+                Duration::from_secs(interval),
+                args.no_notify_watch || config.bool("watch.poll"),
+                status,
+            )
+        }
+        Commands::Clean(args) => {
+            let root_dir = resolve_root_dir(&args.global)?;
+            let config = config::Config::load(&root_dir)?;
+            let format = Format::resolve(args.global.format, &config);
+            let id = args
+                .id
+                .map(|id| {
+                    glob::Pattern::new(&id)
+                        .map_err(|e| anyhow::anyhow!("Invalid --id pattern '{}': {}", id, e))
+                })
+                .transpose()?;
+            let options = CleanOptions {
+                dry_run: args.dry_run,
+                id,
+                logs_only: args.logs_only,
+                older_than: args.older_than.map(Duration::from_secs),
+            };
+            let cleaned = clean_orphaned_files(&root_dir, &options, format.is_text())?;
+            if !format.is_text() {
+                output::print_records(format, &["id", "pid", "reason", "files"], &cleaned)?;
+            }
+            Ok(())
+        }
+        Commands::Gc(args) => {
+            let root_dir = resolve_root_dir(&args.global)?;
+            let retention = gc::policy(&root_dir, args.retention)?;
+            gc::collect_garbage(&root_dir, &retention, args.dry_run)
+        }
+        Commands::Llm(args) => {
+            llm::print_llm_guide(args.format);
+            Ok(())
+        }
+        Commands::Wait(args) => {
+            let root_dir = resolve_root_dir(&args.global)?;
+            let config = config::Config::load(&root_dir)?;
+            let timeout = args
+                .timeout
+                .unwrap_or_else(|| config.integer("wait.timeout"));
+            let interval = args
+                .interval
+                .unwrap_or_else(|| config.integer("wait.interval"));
+            // A timeout of 0 waits forever
+            let timeout = (timeout > 0).then(|| Duration::from_secs(timeout));
+            let started = Instant::now();
+            let pid = wait_daemon(&args.id, timeout, Duration::from_secs(interval), &root_dir)?;
+            match Format::resolve(args.global.format, &config) {
+                Format::Text => {}
+                format => output::print_record(
+                    format,
+                    &serde_json::json!({
+                        "id": args.id,
+                        "pid": pid,
+                        "waited_secs": started.elapsed().as_secs_f64(),
+                    }),
+                )?,
+            }
+            Ok(())
+        }
+        Commands::Proxy(args) => {
+            if args.command.is_empty() {
+                return Err(anyhow::anyhow!("Command cannot be empty"));
+            }
+            let root_dir = resolve_root_dir(&args.global)?;
+            let config = config::Config::load(&root_dir)?;
+            let connect_timeout = args
+                .connect_timeout
+                .unwrap_or_else(|| config.integer("proxy.connect_timeout"));
+            proxy_daemon(&args, Duration::from_secs(connect_timeout), &root_dir)
+        }
+        Commands::Top(args) => {
+            let root_dir = resolve_root_dir(&args.global)?;
+            let config = config::Config::load(&root_dir)?;
+            let interval = args
+                .interval
+                .unwrap_or_else(|| config.integer("top.interval"));
+            top::run_top(&root_dir, Duration::from_millis(interval), read_only)
+        }
+        Commands::View(args) => {
+            let root_dir = resolve_root_dir(&args.global)?;
+            let logs_dir = match &args.run {
+                Some(run) => runs::select(&root_dir, &args.id, run)?,
+                None => root_dir,
+            };
+            let options = ViewOptions {
+                lines: args.lines,
+                follow: args.follow,
+            };
+            view::run_view(&args.id, &logs_dir, &options)
+        }
+        Commands::Stats(args) => {
+            let root_dir = resolve_root_dir(&args.global)?;
+            stats_daemon(&args.id, &root_dir)
+        }
+        Commands::Get(args) => {
+            let root_dir = resolve_root_dir(&args.global)?;
+            println!("{}", get::get(&root_dir, &args.id, args.field)?);
+            Ok(())
+        }
+        Commands::Check(args) => {
+            let options = check::CheckOptions {
+                warn_mem: args.warn_mem,
+                crit_mem: args.crit_mem,
+                max_restarts: args.max_restarts,
+                window: Duration::from_secs(args.window),
+            };
+            let result = resolve_root_dir(&args.global)
+                .and_then(|root_dir| check::evaluate(&args.id, &root_dir, &options));
+            match check::report(result).code() {
+                0 => Ok(()),
+                code => Err(Exit::silent(code).into()),
+            }
+        }
+        Commands::Whois(args) => {
+            let root_dir = resolve_root_dir(&args.global)?;
+            let query = match (args.target.pid, args.target.port, args.target.file) {
+                (Some(pid), _, _) => whois::Query::Pid(pid),
+                (_, Some(port), _) => whois::Query::Port(port),
+                (_, _, Some(file)) => whois::Query::File(file),
+                (None, None, None) => unreachable!("clap requires one of them"),
+            };
+            whois::whois(&root_dir, &query)
+        }
+        Commands::History(args) => {
+            let root_dir = resolve_root_dir(&args.global)?;
+            if args.stats {
+                history::print_stats(&args.id, &root_dir)
+            } else {
+                history::print_history(&args.id, args.limit, &root_dir)
+            }
+        }
+        Commands::Runs(args) => {
+            let root_dir = resolve_root_dir(&args.global)?;
+            let config = config::Config::load(&root_dir)?;
+            let format = Format::resolve(args.global.format, &config);
+            runs::print_runs(&root_dir, &args.id, format)
+        }
+        Commands::Bench(args) => {
+            if args.command.is_empty() {
+                return Err(anyhow::anyhow!("Command cannot be empty"));
+            }
+            let root_dir = resolve_root_dir(&args.global)?;
+            let options = RunOptions {
+                stdin: Stdin::new(StdinMode::Null, args.stdin_file.as_deref())?,
+                ..Default::default()
+            };
+            bench::bench(&args.id, &args.command, &root_dir, args.runs, &options)
+        }
+        Commands::Events(args) => {
+            let root_dir = resolve_root_dir(&args.global)?;
+            let config = config::Config::load(&root_dir)?;
+            let filter = events::Filter {
+                id: args.id,
+                since: args.since.as_deref().map(events::parse_since).transpose()?,
+            };
+            let format = Format::resolve(args.global.format, &config);
+            let poll = args.no_notify_watch || config.bool("watch.poll");
+            events::show_events(&root_dir, &filter, args.follow, format, poll)
+        }
+        Commands::Report(args) => {
+            let root_dir = resolve_root_dir(&args.global)?;
+            let config = config::Config::load(&root_dir)?;
+            let options = report::ReportOptions {
+                ids: args.ids,
+                since: events::parse_since(&args.since)?,
+                lines: args.lines,
+            };
+            if let Some(path) = args.bundle {
+                let bundle = bundle::BundleOptions {
+                    path,
+                    log_kb: args.log_kb,
+                    redact: args.redact,
+                };
+                return bundle::write_bundle(&root_dir, &options, &bundle);
+            }
+            let format = Format::resolve(args.global.format, &config);
+            report::print_report(&root_dir, &options, format)
+        }
+        Commands::Schedule(args) => {
+            let root_dir = resolve_root_dir(&args.global)?;
+            let config = config::Config::load(&root_dir)?;
+            let format = Format::resolve(args.global.format, &config);
+            match args.action {
+                ScheduleAction::List => schedule::print_list(&root_dir, format),
+                ScheduleAction::Add {
+                    id,
+                    cron,
+                    requirements,
+                    command,
+                } => schedule::add(&root_dir, &id, &cron, &command, requirements),
+                ScheduleAction::Remove { id } => schedule::remove(&root_dir, &id),
+            }
+        }
+        Commands::Chaos(args) => {
+            let root_dir = resolve_root_dir(&args.global)?;
+            let options = chaos::ChaosOptions {
+                kill_after: Duration::from_secs(args.kill_after),
+                signal: args.signal,
+                probability: args.probability,
+                count: args.count,
+            };
+            chaos::run_chaos(&args.id, &root_dir, &options)
+        }
+        Commands::Dev(args) => {
+            if args.command.is_empty() {
+                return Err(anyhow::anyhow!("Command cannot be empty"));
+            }
+            let root_dir = resolve_root_dir(&args.global)?;
+            let config = config::Config::load(&root_dir)?;
+            let options = dev::DevOptions {
+                watch: args.watch,
+                ignore: args.ignore,
+                ready: args.ready,
+                debounce: Duration::from_millis(
+                    args.debounce
+                        .unwrap_or_else(|| config.integer("dev.debounce")),
+                ),
+                stop_timeout: Duration::from_secs(config.integer("stop.timeout")),
+                color: stdout_color(&args.global, &config),
+                poll: args.no_notify_watch || config.bool("watch.poll"),
+            };
+            dev::run_dev(&args.id, &args.command, &root_dir, &options)
+        }
+        Commands::Agent(args) => {
+            let root_dir = resolve_root_dir(&args.global)?;
+            if args.stop {
+                agent::stop(&root_dir)
+            } else if args.detach {
+                agent::start_detached(&root_dir)
+            } else {
+                agent::run_agent(&root_dir)
+            }
+        }
+        Commands::Serve(args) => {
+            let root_dir = resolve_root_dir(&args.global)?;
+            if args.upgrade {
+                return serve::request_upgrade(&root_dir);
+            }
+            let config = config::Config::load(&root_dir)?;
+            let options = serve::ServeOptions {
+                listen: args.listen,
+                token: args.token,
+                stop_timeout: config.integer("stop.timeout"),
+                lines: config.integer("tail.lines") as usize,
+                read_only,
+            };
+            serve::serve(&root_dir, options)
+        }
+        Commands::Export(args) => {
+            let root_dir = std::path::absolute(resolve_root_dir(&args.global)?)?;
+            let (id, target, output) = match args.target {
+                ExportTarget::Systemd {
+                    id, output, system, ..
+                } => (id, export::Target::Systemd { system }, output),
+                ExportTarget::Launchd { id, output, .. } => (id, export::Target::Launchd, output),
+            };
+            export::export(&root_dir, id.as_deref(), &target, output.as_deref())
+        }
+        Commands::Init(args) => {
+            // Not through resolve_root_dir, which would warn about what init fixes
+            let root_dir = find_root_dir(&args.global)?;
+            let procfile = args.procfile.map(|path| {
+                path.unwrap_or_else(|| manifest::path(&root_dir).with_file_name("Procfile"))
+            });
+            let options = init::InitOptions {
+                gitignore: args.gitignore,
+                procfile,
+            };
+            init::run_init(&root_dir, &options)
+        }
+        Commands::Up(args) => {
+            let start = Instant::now();
+            let root_dir = std::path::absolute(resolve_root_dir(&args.global)?)?;
+            let config = config::Config::load(&root_dir)?;
+            let manifest = Manifest::load(&root_dir)?;
+            let services = manifest.select(&args.ids)?;
+            let _lock = OperationLock::acquire(&root_dir, "up", args.wait_lock)?;
+            if services.is_empty() {
+                println!("No daemons in {}", manifest::path(&root_dir).display());
+                return Ok(());
+            }
+            // Commands and relative paths of the manifest are relative to it
+            std::env::set_current_dir(&manifest.dir)
+                .with_context(|| format!("Failed to enter {}", manifest.dir.display()))?;
+            let mut progress = Progress::start("up", None, Some(services.len() as u64));
+            for service in &services {
+                if is_process_running(&root_dir, &service.id)? {
+                    println!("Daemon '{}' is already running", service.id);
+                    progress.step(Some(&service.id), "already-running");
+                    continue;
+                }
+                let options = run_options(
+                    &root_dir,
+                    &config,
+                    service.supervise,
+                    service.pty,
+                    service.stdin.clone(),
+                    &service.hooks,
+                    service.policy.clone(),
+                )
+                .with_context(|| format!("Failed to start daemon '{}'", service.id))?;
+                let pid = run_daemon(&service.id, &service.command, &root_dir, &options)
+                    .with_context(|| format!("Failed to start daemon '{}'", service.id))?;
+                // Daemons after it may depend on it being ready
+                if options.policy.uses_sd_notify() {
+                    sd_notify::wait_ready(&root_dir, &service.id, pid, sd_notify::READY_TIMEOUT)?;
+                }
+                println!(
+                    "Started daemon '{}' with PID written to {}",
+                    service.id,
+                    build_file_path(&root_dir, &service.id, "pid").display()
+                );
+                progress.step(Some(&service.id), "started");
+            }
+            progress.finish(None, "started", true);
+            if !args.wait_healthy {
+                return Ok(());
+            }
+
+            let timeout = Duration::from_secs(args.timeout);
+            println!(
+                "Waiting up to {} for {} daemons to be healthy",
+                format_duration(timeout),
+                services.len()
+            );
+            let stragglers = ready::wait_healthy(&root_dir, &services, start, timeout)?;
+            if stragglers.is_empty() {
+                println!(
+                    "All daemons are healthy after {:.1}s",
+                    start.elapsed().as_secs_f64()
+                );
+                return Ok(());
+            }
+            let stragglers: Vec<String> = stragglers
+                .iter()
+                .map(|(id, waiting_for)| format!("{id} ({waiting_for})"))
+                .collect();
+            Err(Exit::new(
+                exit::TIMEOUT,
+                format!(
+                    "Not healthy after {}: {}",
+                    format_duration(timeout),
+                    stragglers.join(", ")
+                ),
+            )
+            .into())
+        }
+        Commands::Down(args) => {
+            let root_dir = resolve_root_dir(&args.global)?;
+            let config = config::Config::load(&root_dir)?;
+            let manifest = Manifest::load(&root_dir)?;
+            let _lock = OperationLock::acquire(&root_dir, "down", args.wait_lock)?;
+            let services = manifest.select(&args.ids)?;
+            let mut progress = Progress::start("down", None, Some(services.len() as u64));
+            // Dependents usually come after what they depend on
+            for service in services.into_iter().rev() {
+                let timeout = args
+                    .timeout
+                    .or(config.daemon(&service.id).stop_timeout)
+                    .unwrap_or_else(|| config.integer("stop.timeout"));
+                let stopped = stop_daemon(&service.id, timeout, &root_dir)?;
+                println!("{}", stopped.message(&service.id));
+                progress.step(Some(&service.id), stopped.result());
+            }
+            progress.finish(None, "stopped", true);
+            Ok(())
+        }
+        Commands::Nuke(args) => {
+            let root_dir = resolve_root_dir(&args.global)?;
+            let config = config::Config::load(&root_dir)?;
+            let timeout = args
+                .timeout
+                .unwrap_or_else(|| config.integer("stop.timeout"));
+            nuke::nuke(&root_dir, timeout, args.yes, args.wait_lock)
+        }
+        Commands::ShellHook(args) => {
+            if args.state {
+                // Outside a project, and without creating `.demon` in every
+                // repository the shell passes through
+                let root_dir = match args.global.root_dir {
+                    Some(dir) => Some(dir),
+                    None => project_dir(args.global.root_strategy).map(|root| root.join(".demon")),
+                };
+                return shell_hook::print_state(root_dir.as_deref());
+            }
+            if let Some(shell) = args.shell {
+                print!("{}", shell_hook::script(shell, args.up, args.stop));
+            }
+            Ok(())
+        }
+        Commands::Config(args) => {
+            let root_dir = resolve_root_dir(&args.global)?;
+            match args.action {
+                ConfigAction::Get { key } => config::get(&key, &root_dir),
+                ConfigAction::Set { key, value, global } => {
+                    config::set(&key, &value, global, &root_dir)
+                }
+                ConfigAction::List => config::list(&root_dir),
+                ConfigAction::Validate => config::validate_all(&root_dir),
+            }
+        }
+        Commands::Supervise(args) => {
+            let root_dir = resolve_root_dir(&args.global)?;
+            supervisor::run_supervisor(
+                &args.id,
+                &args.command,
+                &root_dir,
+                args.hooks,
+                args.policy,
+                args.pty,
+                Stdin::new(args.stdin.unwrap_or_default(), args.stdin_file.as_deref())?,
+            )
+        }
+        Commands::RunPlugins(args) => {
+            let root_dir = resolve_root_dir(&args.global)?;
+            let config = config::Config::load(&root_dir)?;
+            plugins::deliver(
+                &root_dir,
+                Duration::from_secs(config.integer("plugins.timeout")),
+                config.integer("plugins.retries"),
+            )
+        }
+    }
+}
+
+/// Output format of `cat` and `tail`: --output, or else --format or the
+/// `format` setting
+fn log_output(
+    output: Option<logs::Output>,
+    format: Option<Format>,
+    config: &config::Config,
+) -> logs::Output {
+    output.unwrap_or(match Format::resolve(format, config) {
+        Format::Json => logs::Output::Json,
+        _ => logs::Output::Text,
+    })
+}
+
+/// The time window of `--since` and `--until`
+fn log_window(since: Option<&str>, until: Option<&str>) -> Result<logs::Window> {
+    Ok(logs::Window {
+        since: since.map(events::parse_since).transpose()?,
+        until: until
+            .map(|until| events::parse_time("--until", until))
+            .transpose()?,
+    })
+}
+
+/// Options for starting a daemon, with the configured defaults applied
+fn run_options(
+    root_dir: &std::path::Path,
+    config: &config::Config,
+    supervise: bool,
+    pty: bool,
+    stdin: Stdin,
+    hooks: &hooks::Hooks,
+    mut policy: agent::Policy,
+) -> Result<RunOptions> {
+    let mut hooks = hooks.resolve()?;
+    policy.limits.resolve()?;
+    policy.sandbox.resolve()?;
+    policy.requirements.resolve()?;
+    policy.autorestart.resolve()?;
+    let supervise = supervise
+        || pty
+        || config.bool("run.supervise")
+        || hooks.needs_supervisor()
+        || policy.needs_supervisor();
+    // Crashes are only noticed by a supervisor, so the configured
+    // webhook and collector apply to supervised daemons
+    if supervise && hooks.notify_url.is_none() {
+        hooks.notify_url = config.url("notify.url").map(str::to_string);
+    }
+    if supervise && hooks.otel_endpoint.is_none() {
+        hooks.otel_endpoint = config.url("otel.endpoint").map(str::to_string);
+    }
+    if policy.needs_agent() && agent::running(root_dir).is_none() {
+        tracing::warn!(
+            "--restart, --health-cmd and --every are applied by the agent, which is not running; start it with `demon agent --detach`"
+        );
+    }
+    Ok(RunOptions {
+        supervise,
+        pty,
+        stdin,
+        hooks,
+        policy,
+    })
+}
+
+/// The daemon named on the command line, or else every daemon picked by
+/// `--group` and `--label`, failing when there is none
+fn selected_ids(
+    root_dir: &std::path::Path,
+    id: Option<&str>,
+    selector: &Selector,
+) -> Result<Vec<String>> {
+    if let Some(id) = id {
+        return Ok(vec![id.to_string()]);
+    }
+    let ids = selector.select(root_dir)?;
+    if ids.is_empty() {
+        return Err(Exit::new(
+            exit::NOT_FOUND,
+            format!("No daemon matches {}", selector.describe()),
+        )
+        .into());
+    }
+    Ok(ids)
+}
+
+fn resolve_root_dir(global: &Global) -> Result<PathBuf> {
+    let root_dir = find_root_dir(global)?;
+    // Read-only mode writes nothing: no marker of the git check, and the flat
+    // files are read where they are
+    if !global.read_only {
+        init::warn_if_not_ignored(&root_dir);
+        layout::migrate(&root_dir);
+    }
+    Ok(root_dir)
+}
+
+fn find_root_dir(global: &Global) -> Result<PathBuf> {
+    match &global.root_dir {
+        Some(dir) => {
+            if !dir.exists() {
+                return Err(anyhow::anyhow!(
+                    "Specified root directory does not exist: {}",
+                    dir.display()
+                ));
+            }
+            if !dir.is_dir() {
+                return Err(anyhow::anyhow!(
+                    "Specified root path is not a directory: {}",
+                    dir.display()
+                ));
+            }
+            Ok(dir.clone())
+        }
+        None => {
+            if global.read_only
+                && let Some(project) = project_dir(global.root_strategy)
+                && !project.join(".demon").exists()
+            {
+                return Err(anyhow::anyhow!(
+                    "No daemon directory in {}, and read-only mode does not create it",
+                    project.display()
+                ));
+            }
+            find_root(global.root_strategy)
+        }
+    }
+}
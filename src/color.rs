@@ -0,0 +1,64 @@
+//! ANSI colors of the output (`--color`, the `color` setting).
+//!
+//! Colors are only used where the output is read by people: statuses in
+//! `list` and `status`, the daemon's stderr in `tail`, matches in `grep`, and
+//! the output of `dev`. With `auto` they are disabled off a terminal and when `NO_COLOR` is
+//! set, so scripts never see escape codes.
+
+use crate::config::Config;
+use clap::ValueEnum;
+
+/// ANSI color codes
+pub(crate) const RED: &str = "31";
+pub(crate) const GREEN: &str = "32";
+pub(crate) const YELLOW: &str = "33";
+pub(crate) const CYAN: &str = "36";
+
+/// When to color the output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub(crate) enum When {
+    /// On a terminal, unless NO_COLOR is set
+    Auto,
+    /// Also off a terminal and with NO_COLOR
+    Always,
+    Never,
+}
+
+/// Whether to color a stream that is or is not a terminal: `--color`, or else
+/// the `color` setting
+pub(crate) fn enabled(flag: Option<When>, config: &Config, terminal: bool) -> bool {
+    let when =
+        flag.unwrap_or_else(|| When::from_str(config.choice("color"), true).unwrap_or(When::Auto));
+    match when {
+        When::Always => true,
+        When::Never => false,
+        When::Auto => terminal && std::env::var_os("NO_COLOR").is_none(),
+    }
+}
+
+/// `text` in `color` when `enabled`
+pub(crate) fn paint(enabled: bool, text: &str, color: &str) -> String {
+    match enabled {
+        true => format!("\x1b[{color}m{text}\x1b[0m"),
+        false => text.to_string(),
+    }
+}
+
+/// Color of a daemon status such as `RUNNING` or `exit 1`
+pub(crate) fn of_status(status: &str) -> Option<&'static str> {
+    match status {
+        "RUNNING" | "COMPLETED" | "exit 0" | "healthy" | "OK" => Some(GREEN),
+        "UNHEALTHY" | "unhealthy" | "WARNING" | "UNKNOWN" => Some(YELLOW),
+        "DEAD" | "FAILED" | "ERROR" | "TIMEOUT" | "CRITICAL" => Some(RED),
+        _ if status.starts_with("exit ") || status.starts_with("signal ") => Some(RED),
+        _ => None,
+    }
+}
+
+/// A status in its color, if it has one
+pub(crate) fn status(enabled: bool, status: &str) -> String {
+    match of_status(status) {
+        Some(color) => paint(enabled, status, color),
+        None => status.to_string(),
+    }
+}
@@ -0,0 +1,768 @@
+//! Layered configuration.
+//!
+//! Settings are resolved from, in increasing order of precedence:
+//!
+//! 1. built-in defaults
+//! 2. the global file, `$XDG_CONFIG_HOME/demon/config.toml` (or `~/.config/...`)
+//! 3. the per-root file, `<root>/config.toml` (usually `.demon/config.toml`)
+//! 4. `DEMON_*` environment variables (`stop.timeout` -> `DEMON_STOP_TIMEOUT`)
+//! 5. command line flags
+//!
+//! Every key is declared in [`SETTINGS`] with its type, so files and variables
+//! are validated up front and each resolved value remembers where it came from.
+//!
+//! The files can also hold defaults for single daemons, in `[daemon.<id>]`
+//! tables taking the options of `demon run` like a manifest entry, plus
+//! `stop_timeout`. `demon run <id>` and `demon stop <id>` use them for the
+//! flags not given on the command line.
+
+use crate::{
+    agent::Policy,
+    hooks::Hooks,
+    stdin::{Stdin, StdinMode},
+};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use toml::{Table, Value};
+
+/// Type of a setting, used to parse and validate values
+#[derive(Debug, Clone, Copy)]
+enum Kind {
+    /// Non-negative integer
+    Integer,
+    Bool,
+    /// One of a fixed set of strings
+    Choice(&'static [&'static str]),
+    /// An http(s) URL, or empty for none
+    Url,
+    /// Free-form text, or empty for none
+    Text,
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Kind::Integer => write!(f, "a non-negative integer"),
+            Kind::Bool => write!(f, "a boolean"),
+            Kind::Choice(choices) => write!(f, "one of {}", choices.join(", ")),
+            Kind::Url => write!(f, "an http:// or https:// URL"),
+            Kind::Text => write!(f, "a string"),
+        }
+    }
+}
+
+struct Setting {
+    key: &'static str,
+    kind: Kind,
+    default: &'static str,
+    description: &'static str,
+}
+
+/// Every supported configuration key
+const SETTINGS: &[Setting] = &[
+    Setting {
+        key: "color",
+        kind: Kind::Choice(&["auto", "always", "never"]),
+        default: "auto",
+        description: "Colored output and diagnostics; auto disables them off a terminal or with NO_COLOR",
+    },
+    Setting {
+        key: "format",
+        kind: Kind::Choice(&["text", "table", "json", "csv", "plain"]),
+        default: "text",
+        description: "Output format of run, stop, wait, clean, status, list, events, cat and tail",
+    },
+    Setting {
+        key: "run.supervise",
+        kind: Kind::Bool,
+        default: "false",
+        description: "Run daemons under a supervisor by default",
+    },
+    Setting {
+        key: "stop.timeout",
+        kind: Kind::Integer,
+        default: "10",
+        description: "Seconds to wait after SIGTERM before sending SIGKILL",
+    },
+    Setting {
+        key: "tail.lines",
+        kind: Kind::Integer,
+        default: "50",
+        description: "Number of lines shown by tail",
+    },
+    Setting {
+        key: "tail.flush_interval",
+        kind: Kind::Integer,
+        default: "100",
+        description: "Milliseconds tail -f keeps output buffered while the logs keep growing",
+    },
+    Setting {
+        key: "table.max_width",
+        kind: Kind::Integer,
+        default: "40",
+        description: "Widest a column of the list tables gets before its cells are cut with … (0 = no limit)",
+    },
+    Setting {
+        key: "table.max_command_width",
+        kind: Kind::Integer,
+        default: "0",
+        description: "Widest the last column (COMMAND) of the list tables gets (0 = no limit)",
+    },
+    Setting {
+        key: "wait.timeout",
+        kind: Kind::Integer,
+        default: "30",
+        description: "Seconds wait blocks before giving up (0 = infinite)",
+    },
+    Setting {
+        key: "wait.interval",
+        kind: Kind::Integer,
+        default: "1",
+        description: "Polling interval of wait in seconds",
+    },
+    Setting {
+        key: "top.interval",
+        kind: Kind::Integer,
+        default: "1000",
+        description: "Refresh interval of top in milliseconds",
+    },
+    Setting {
+        key: "proxy.connect_timeout",
+        kind: Kind::Integer,
+        default: "30",
+        description: "Seconds proxy waits for the upstream after starting the daemon",
+    },
+    Setting {
+        key: "dev.debounce",
+        kind: Kind::Integer,
+        default: "300",
+        description: "Milliseconds dev and run --watch wait for changes to settle before restarting",
+    },
+    Setting {
+        key: "watch.poll",
+        kind: Kind::Bool,
+        default: "false",
+        description: "Poll files in tail -f, events -f, dev, run --watch and list/status --watch instead of using inotify",
+    },
+    Setting {
+        key: "history.keep_runs",
+        kind: Kind::Integer,
+        default: "1000",
+        description: "Finished runs (and log copies) kept in the history per daemon (0 = all)",
+    },
+    Setting {
+        key: "history.keep_days",
+        kind: Kind::Integer,
+        default: "0",
+        description: "Days records are kept in the history (0 = forever)",
+    },
+    Setting {
+        key: "runs.keep",
+        kind: Kind::Integer,
+        default: "5",
+        description: "Past runs whose logs are kept per daemon for `demon runs` and --run (0 = none)",
+    },
+    Setting {
+        key: "notify.url",
+        kind: Kind::Url,
+        default: "",
+        description: "Webhook notified when a daemon crashes or is restarted",
+    },
+    Setting {
+        key: "otel.endpoint",
+        kind: Kind::Url,
+        default: "",
+        description: "OTLP/HTTP collector the lifecycle events of daemons are sent to as spans",
+    },
+    Setting {
+        key: "logs.ship_url",
+        kind: Kind::Text,
+        default: "",
+        description: "Directory or URL the compressed logs of finished runs are shipped to",
+    },
+    Setting {
+        key: "logs.uploader",
+        kind: Kind::Text,
+        default: "",
+        description: "Shell command cp-logs runs to upload to a URL, given $DEMON_FILE and $DEMON_URL (built in: aws s3 cp for s3://)",
+    },
+    Setting {
+        key: "checks.timeout",
+        kind: Kind::Integer,
+        default: "10",
+        description: "Seconds a check plugin in checks.d/<id> may run before it is killed and UNKNOWN",
+    },
+    Setting {
+        key: "plugins.timeout",
+        kind: Kind::Integer,
+        default: "10",
+        description: "Seconds a plugin in hooks.d may take to handle an event before it is killed",
+    },
+    Setting {
+        key: "plugins.retries",
+        kind: Kind::Integer,
+        default: "2",
+        description: "Times a plugin in hooks.d that fails or times out is run again for the same event",
+    },
+];
+
+fn setting(key: &str) -> Option<&'static Setting> {
+    SETTINGS.iter().find(|setting| setting.key == key)
+}
+
+/// Name of the environment variable overriding a key
+fn env_var(key: &str) -> String {
+    format!("DEMON_{}", key.replace('.', "_").to_uppercase())
+}
+
+/// Where a resolved value came from
+#[derive(Debug, Clone)]
+pub(crate) enum Source {
+    Default,
+    File(PathBuf),
+    Env(String),
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Default => write!(f, "default"),
+            Source::File(path) => write!(f, "{}", display_path(path)),
+            Source::Env(var) => write!(f, "env {var}"),
+        }
+    }
+}
+
+/// Show paths below the current directory relative to it, like `.demon/config.toml`
+fn display_path(path: &Path) -> String {
+    std::env::current_dir()
+        .ok()
+        .and_then(|cwd| path.strip_prefix(cwd).ok())
+        .unwrap_or(path)
+        .display()
+        .to_string()
+}
+
+/// Path of the global configuration file
+pub(crate) fn global_config_path() -> Option<PathBuf> {
+    let base = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(base.join("demon").join("config.toml"))
+}
+
+/// Path of the per-root configuration file
+pub(crate) fn root_config_path(root_dir: &Path) -> PathBuf {
+    root_dir.join("config.toml")
+}
+
+/// A resolved value and its origin
+#[derive(Debug, Clone)]
+pub(crate) struct Entry {
+    pub(crate) value: Value,
+    pub(crate) source: Source,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Config {
+    entries: BTreeMap<&'static str, Entry>,
+    /// Options of the `[daemon.<id>]` tables, by ID and key
+    daemons: BTreeMap<String, BTreeMap<String, Entry>>,
+}
+
+/// Defaults of `run` and `stop` for one daemon, from its `[daemon.<id>]`
+/// tables
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub(crate) struct DaemonDefaults {
+    pub(crate) supervise: bool,
+    pub(crate) pty: bool,
+    pub(crate) stdin: Option<StdinMode>,
+    pub(crate) stdin_file: Option<PathBuf>,
+    /// Seconds `stop` waits after SIGTERM before sending SIGKILL
+    pub(crate) stop_timeout: Option<u64>,
+    #[serde(flatten)]
+    pub(crate) hooks: Hooks,
+    #[serde(flatten)]
+    pub(crate) policy: Policy,
+}
+
+impl DaemonDefaults {
+    /// Stdin given on the command line, or else the default one; a daemon
+    /// given `--pty` has none
+    pub(crate) fn stdin(
+        &self,
+        pty: bool,
+        mode: Option<StdinMode>,
+        file: Option<&Path>,
+    ) -> Result<Stdin> {
+        match (mode, file) {
+            (None, None) if !pty => {
+                Stdin::new(self.stdin.unwrap_or_default(), self.stdin_file.as_deref())
+            }
+            (mode, file) => Stdin::new(mode.unwrap_or_default(), file),
+        }
+    }
+
+    /// Hooks given on the command line, completed with the defaults
+    pub(crate) fn hooks(&self, given: Hooks) -> Result<Hooks> {
+        overlay(given, &self.hooks)
+    }
+
+    /// Policy given on the command line, completed with the defaults
+    pub(crate) fn policy(&self, given: Policy) -> Result<Policy> {
+        overlay(given, &self.policy)
+    }
+}
+
+/// `given` with the fields it leaves unset, None or false, taken from
+/// `defaults`
+fn overlay<T: Serialize + DeserializeOwned>(given: T, defaults: &T) -> Result<T> {
+    let serde_json::Value::Object(mut merged) = serde_json::to_value(defaults)? else {
+        return Ok(given);
+    };
+    let serde_json::Value::Object(given) = serde_json::to_value(given)? else {
+        unreachable!("options serialize to objects");
+    };
+    for (key, value) in given {
+        if !matches!(
+            value,
+            serde_json::Value::Null | serde_json::Value::Bool(false)
+        ) {
+            merged.insert(key, value);
+        }
+    }
+    Ok(serde_json::from_value(serde_json::Value::Object(merged))?)
+}
+
+/// The `[daemon.<id>]` tables of a file, with the keys written like flags
+/// (`stop-timeout`) turned into options (`stop_timeout`)
+fn daemon_tables(table: &mut Table) -> Result<Vec<(String, Table)>> {
+    let Some(daemons) = table.remove("daemon") else {
+        return Ok(Vec::new());
+    };
+    let Value::Table(daemons) = daemons else {
+        return Err(anyhow::anyhow!("'daemon' must be a table of daemon IDs"));
+    };
+    let mut tables = Vec::new();
+    for (id, options) in daemons {
+        let Value::Table(options) = options else {
+            return Err(anyhow::anyhow!("'daemon.{}' must be a table", id));
+        };
+        let options: Table = options
+            .into_iter()
+            .map(|(key, value)| (key.replace('-', "_"), value))
+            .collect();
+        Value::Table(options.clone())
+            .try_into::<DaemonDefaults>()
+            .map_err(|e| anyhow::anyhow!("'daemon.{}': {}", id, e))?;
+        tables.push((id, options));
+    }
+    Ok(tables)
+}
+
+impl Config {
+    /// Resolve the configuration for a root directory, failing on the first
+    /// invalid file or variable
+    pub(crate) fn load(root_dir: &Path) -> Result<Self> {
+        let (config, problems) = Self::load_collecting(root_dir);
+        match problems.first() {
+            Some(problem) => Err(anyhow::anyhow!(
+                "Invalid configuration: {} (run 'demon config validate' for details)",
+                problem
+            )),
+            None => Ok(config),
+        }
+    }
+
+    /// Resolve the configuration ignoring invalid values, for settings needed
+    /// before errors can be reported (such as `color`). Without a root directory
+    /// only the global file and the environment are considered.
+    pub(crate) fn load_lenient(root_dir: Option<&Path>) -> Self {
+        Self::load_layers(root_dir).0
+    }
+
+    /// Resolve the configuration, skipping and returning every invalid value
+    fn load_collecting(root_dir: &Path) -> (Self, Vec<String>) {
+        Self::load_layers(Some(root_dir))
+    }
+
+    fn load_layers(root_dir: Option<&Path>) -> (Self, Vec<String>) {
+        let mut entries = BTreeMap::new();
+        let mut daemons: BTreeMap<String, BTreeMap<String, Entry>> = BTreeMap::new();
+        let mut problems = Vec::new();
+
+        for setting in SETTINGS {
+            let value =
+                parse_value(setting.kind, setting.default).expect("built-in defaults are valid");
+            entries.insert(
+                setting.key,
+                Entry {
+                    value,
+                    source: Source::Default,
+                },
+            );
+        }
+
+        let files = global_config_path()
+            .into_iter()
+            .chain(root_dir.map(root_config_path));
+        for path in files {
+            let mut table = match read_table(&path) {
+                Ok(Some(table)) => table,
+                Ok(None) => continue,
+                Err(e) => {
+                    problems.push(format!("{e:#}"));
+                    continue;
+                }
+            };
+            match daemon_tables(&mut table) {
+                Ok(tables) => {
+                    for (id, options) in tables {
+                        let daemon = daemons.entry(id).or_default();
+                        for (key, value) in options {
+                            let source = Source::File(path.clone());
+                            daemon.insert(key, Entry { value, source });
+                        }
+                    }
+                }
+                Err(e) => problems.push(format!("{}: {}", display_path(&path), e)),
+            }
+            for (key, value) in flatten(&table) {
+                match validate(&key, &value) {
+                    Ok(setting) => {
+                        entries.insert(
+                            setting.key,
+                            Entry {
+                                value,
+                                source: Source::File(path.clone()),
+                            },
+                        );
+                    }
+                    Err(e) => problems.push(format!("{}: {}", display_path(&path), e)),
+                }
+            }
+        }
+
+        for setting in SETTINGS {
+            let var = env_var(setting.key);
+            let Ok(raw) = std::env::var(&var) else {
+                continue;
+            };
+            match parse_value(setting.kind, &raw) {
+                Ok(value) => {
+                    entries.insert(
+                        setting.key,
+                        Entry {
+                            value,
+                            source: Source::Env(var),
+                        },
+                    );
+                }
+                Err(e) => problems.push(format!("{var}: {e}")),
+            }
+        }
+
+        (Self { entries, daemons }, problems)
+    }
+
+    pub(crate) fn get(&self, key: &str) -> Option<&Entry> {
+        self.entries.get(key)
+    }
+
+    /// Defaults of daemon `id`, none when it has no `[daemon.<id>]` table
+    pub(crate) fn daemon(&self, id: &str) -> DaemonDefaults {
+        let Some(options) = self.daemons.get(id) else {
+            return DaemonDefaults::default();
+        };
+        let table: Table = options
+            .iter()
+            .map(|(key, entry)| (key.clone(), entry.value.clone()))
+            .collect();
+        Value::Table(table).try_into().unwrap_or_default()
+    }
+
+    /// Value of an integer setting
+    pub(crate) fn integer(&self, key: &str) -> u64 {
+        match self.get(key).map(|entry| &entry.value) {
+            Some(Value::Integer(n)) => *n as u64,
+            _ => panic!("'{key}' is not an integer setting"),
+        }
+    }
+
+    /// Value of a choice setting
+    pub(crate) fn choice(&self, key: &str) -> &str {
+        match self.get(key).map(|entry| &entry.value) {
+            Some(Value::String(s)) => s,
+            _ => panic!("'{key}' is not a choice setting"),
+        }
+    }
+
+    /// Value of a URL setting, None if it is empty
+    pub(crate) fn url(&self, key: &str) -> Option<&str> {
+        match self.get(key).map(|entry| &entry.value) {
+            Some(Value::String(s)) => Some(s.as_str()).filter(|s| !s.is_empty()),
+            _ => panic!("'{key}' is not a URL setting"),
+        }
+    }
+
+    /// Value of a text setting, None if it is empty
+    pub(crate) fn text(&self, key: &str) -> Option<&str> {
+        match self.get(key).map(|entry| &entry.value) {
+            Some(Value::String(s)) => Some(s.as_str()).filter(|s| !s.is_empty()),
+            _ => panic!("'{key}' is not a text setting"),
+        }
+    }
+
+    /// Value of a boolean setting
+    pub(crate) fn bool(&self, key: &str) -> bool {
+        match self.get(key).map(|entry| &entry.value) {
+            Some(Value::Boolean(b)) => *b,
+            _ => panic!("'{key}' is not a boolean setting"),
+        }
+    }
+}
+
+/// Read a TOML file, returning None if it does not exist
+fn read_table(path: &Path) -> Result<Option<Table>> {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    };
+    let table = contents
+        .parse::<Table>()
+        .with_context(|| format!("{}: invalid TOML", display_path(path)))?;
+    Ok(Some(table))
+}
+
+/// Flatten nested tables into dotted keys, e.g. `[stop] timeout = 5` into
+/// `stop.timeout = 5`
+fn flatten(table: &Table) -> Vec<(String, Value)> {
+    let mut out = Vec::new();
+    for (key, value) in table {
+        match value {
+            Value::Table(inner) => {
+                for (inner_key, inner_value) in flatten(inner) {
+                    out.push((format!("{key}.{inner_key}"), inner_value));
+                }
+            }
+            _ => out.push((key.clone(), value.clone())),
+        }
+    }
+    out
+}
+
+fn validate(key: &str, value: &Value) -> Result<&'static Setting> {
+    let setting = setting(key).ok_or_else(|| anyhow::anyhow!("unknown key '{}'", key))?;
+    let valid = match (setting.kind, value) {
+        (Kind::Integer, Value::Integer(n)) => *n >= 0,
+        (Kind::Bool, Value::Boolean(_)) => true,
+        (Kind::Choice(choices), Value::String(s)) => choices.contains(&s.as_str()),
+        (Kind::Url, Value::String(s)) => is_url(s),
+        (Kind::Text, Value::String(_)) => true,
+        _ => false,
+    };
+    if !valid {
+        return Err(anyhow::anyhow!(
+            "'{}' must be {}, got {}",
+            key,
+            setting.kind,
+            value
+        ));
+    }
+    Ok(setting)
+}
+
+/// Parse a value given as text (defaults, environment variables, `config set`)
+fn parse_value(kind: Kind, raw: &str) -> Result<Value> {
+    let raw = raw.trim();
+    match kind {
+        Kind::Integer => raw
+            .parse::<u32>()
+            .map(|n| Value::Integer(n.into()))
+            .map_err(|_| anyhow::anyhow!("expected {}, got '{}'", kind, raw)),
+        Kind::Bool => match raw {
+            "true" | "1" | "yes" | "on" => Ok(Value::Boolean(true)),
+            "false" | "0" | "no" | "off" => Ok(Value::Boolean(false)),
+            _ => Err(anyhow::anyhow!("expected {}, got '{}'", kind, raw)),
+        },
+        Kind::Choice(choices) if choices.contains(&raw) => Ok(Value::String(raw.to_string())),
+        Kind::Choice(_) => Err(anyhow::anyhow!("expected {}, got '{}'", kind, raw)),
+        Kind::Url if is_url(raw) => Ok(Value::String(raw.to_string())),
+        Kind::Url => Err(anyhow::anyhow!("expected {}, got '{}'", kind, raw)),
+        Kind::Text => Ok(Value::String(raw.to_string())),
+    }
+}
+
+fn is_url(value: &str) -> bool {
+    value.is_empty() || value.starts_with("http://") || value.starts_with("https://")
+}
+
+/// Print a resolved value without TOML quoting
+fn format_value(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+pub(crate) fn list(root_dir: &Path) -> Result<()> {
+    let config = Config::load(root_dir)?;
+    for setting in SETTINGS {
+        let entry = config.get(setting.key).expect("every setting is resolved");
+        println!(
+            "{}={} from {}",
+            setting.key,
+            format_value(&entry.value),
+            entry.source
+        );
+    }
+    for (id, options) in &config.daemons {
+        for (key, entry) in options {
+            println!(
+                "daemon.{}.{}={} from {}",
+                id,
+                key,
+                format_value(&entry.value),
+                entry.source
+            );
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn get(key: &str, root_dir: &Path) -> Result<()> {
+    if setting(key).is_none() {
+        return Err(unknown_key(key));
+    }
+    let config = Config::load(root_dir)?;
+    let entry = config.get(key).expect("every setting is resolved");
+    println!("{}", format_value(&entry.value));
+    Ok(())
+}
+
+pub(crate) fn set(key: &str, raw: &str, global: bool, root_dir: &Path) -> Result<()> {
+    let setting = setting(key).ok_or_else(|| unknown_key(key))?;
+    let value =
+        parse_value(setting.kind, raw).with_context(|| format!("Invalid value for '{key}'"))?;
+
+    let path = if global {
+        global_config_path()
+            .ok_or_else(|| anyhow::anyhow!("Cannot locate the global config directory"))?
+    } else {
+        root_config_path(root_dir)
+    };
+    let mut table = read_table(&path)?.unwrap_or_default();
+
+    // Insert into the nested table, e.g. stop.timeout into [stop]
+    let mut current = &mut table;
+    let mut parts: Vec<&str> = key.split('.').collect();
+    let leaf = parts.pop().expect("keys are never empty");
+    for part in parts {
+        let next = current
+            .entry(part)
+            .or_insert_with(|| Value::Table(Table::new()));
+        current = match next {
+            Value::Table(inner) => inner,
+            _ => {
+                return Err(anyhow::anyhow!(
+                    "{}: '{}' is not a table",
+                    display_path(&path),
+                    part
+                ));
+            }
+        };
+    }
+    current.insert(leaf.to_string(), value.clone());
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(&path, toml::to_string(&table)?)
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    println!(
+        "{}={} in {}",
+        key,
+        format_value(&value),
+        display_path(&path)
+    );
+    Ok(())
+}
+
+pub(crate) fn validate_all(root_dir: &Path) -> Result<()> {
+    let (_, problems) = Config::load_collecting(root_dir);
+    if problems.is_empty() {
+        println!("Configuration is valid");
+        return Ok(());
+    }
+    for problem in &problems {
+        println!("error: {problem}");
+    }
+    Err(anyhow::anyhow!(
+        "Found {} configuration problem(s)",
+        problems.len()
+    ))
+}
+
+fn unknown_key(key: &str) -> anyhow::Error {
+    let keys: Vec<&str> = SETTINGS.iter().map(|setting| setting.key).collect();
+    anyhow::anyhow!(
+        "Unknown configuration key '{}' (known keys: {})",
+        key,
+        keys.join(", ")
+    )
+}
+
+/// Starter per-root file written by `demon init`: every key commented out
+/// with its default
+pub(crate) fn starter() -> String {
+    let mut text = String::from(
+        "# Settings of this root directory; uncomment a line to change it.\n\
+         # Run `demon config list` to see the value in effect and where it comes from.\n",
+    );
+    for setting in SETTINGS {
+        let value = parse_value(setting.kind, setting.default).expect("defaults are valid");
+        text.push_str(&format!(
+            "\n# {}\n# {} = {}\n",
+            setting.description, setting.key, value
+        ));
+    }
+    text.push_str(
+        "\n# Defaults of `demon run <id>` and `demon stop <id>` for one daemon, for\n\
+         # the flags not given on the command line:\n\
+         # [daemon.api]\n\
+         # stop_timeout = 30\n\
+         # restart = \"on-failure\"\n",
+    );
+    text
+}
+
+/// Descriptions of all keys, for help output
+pub(crate) fn describe_keys() -> String {
+    SETTINGS
+        .iter()
+        .map(|setting| {
+            format!(
+                "  {:<24} {} (default: {}, env: {})",
+                setting.key,
+                setting.description,
+                if setting.default.is_empty() {
+                    "none"
+                } else {
+                    setting.default
+                },
+                env_var(setting.key)
+            )
+        })
+        .chain(std::iter::once(format!(
+            "  {:<24} Default of a flag of run, or stop_timeout, for daemon <id> (in [daemon.<id>])",
+            "daemon.<id>.<option>"
+        )))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
@@ -0,0 +1,124 @@
+//! Core dumps of daemons started with `--core-dump-dir`.
+//!
+//! Where the kernel writes a core dump is set for the whole machine by
+//! `kernel.core_pattern`, which only root may change. demon leaves it alone:
+//! when the pattern names a file, the supervisor of a daemon that dumped core
+//! moves that file into the daemon's core dump directory as
+//! `<id>.<pid>.core` and records it, so `demon status` shows where it is.
+//! When the pattern pipes dumps to a program such as `systemd-coredump`, that
+//! program keeps them, and demon can only point to it.
+
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+
+/// Where the kernel writes core dumps
+const CORE_PATTERN: &str = "/proc/sys/kernel/core_pattern";
+
+/// Whether the kernel appends the PID to patterns without %p
+const CORE_USES_PID: &str = "/proc/sys/kernel/core_uses_pid";
+
+/// Longest process name the kernel keeps, which %e expands to
+const COMM_LEN: usize = 15;
+
+fn pattern() -> Option<String> {
+    let pattern = std::fs::read_to_string(CORE_PATTERN).ok()?;
+    Some(pattern.trim_end_matches('\n').to_string())
+}
+
+/// The program the kernel pipes core dumps to, if it does
+pub(crate) fn handler() -> Option<String> {
+    let pattern = pattern()?;
+    let command = pattern.strip_prefix('|')?;
+    Some(
+        command
+            .split_whitespace()
+            .next()
+            .unwrap_or(command)
+            .to_string(),
+    )
+}
+
+/// Path of the core dump of a process, expanding the specifiers of the
+/// pattern that can be known after the process is gone; None when it has
+/// others
+fn core_path(pattern: &str, cwd: &Path, pid: u32, program: &str, signal: i32) -> Option<PathBuf> {
+    let name: String = Path::new(program)
+        .file_name()?
+        .to_string_lossy()
+        .chars()
+        .take(COMM_LEN)
+        .collect();
+    let mut path = String::new();
+    let mut has_pid = false;
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            path.push(c);
+            continue;
+        }
+        match chars.next()? {
+            '%' => path.push('%'),
+            'p' | 'P' | 'i' | 'I' => {
+                path.push_str(&pid.to_string());
+                has_pid = true;
+            }
+            'e' => path.push_str(&name),
+            's' => path.push_str(&signal.to_string()),
+            // SAFETY: getuid and getgid cannot fail
+            'u' => path.push_str(&unsafe { libc::getuid() }.to_string()),
+            'g' => path.push_str(&unsafe { libc::getgid() }.to_string()),
+            'h' => path.push_str(&crate::webhook::hostname()),
+            _ => return None,
+        }
+    }
+    let uses_pid = std::fs::read_to_string(CORE_USES_PID).is_ok_and(|value| value.trim() != "0");
+    if uses_pid && !has_pid {
+        path.push_str(&format!(".{pid}"));
+    }
+    Some(cwd.join(path))
+}
+
+/// Move the core dump of daemon `id` into `dir`, returning its new path, or
+/// None when the kernel did not write it to a file demon can find
+pub(crate) fn collect(
+    dir: &Path,
+    id: &str,
+    pid: u32,
+    command: &[String],
+    cwd: &Path,
+    signal: i32,
+) -> Result<Option<PathBuf>> {
+    let Some(pattern) = pattern().filter(|pattern| !pattern.starts_with('|')) else {
+        return Ok(None);
+    };
+    let Some(program) = command.first() else {
+        return Ok(None);
+    };
+    let Some(source) = core_path(&pattern, cwd, pid, program, signal) else {
+        tracing::warn!(
+            "Cannot find the core dump of '{}': kernel.core_pattern '{}' is not supported",
+            id,
+            pattern
+        );
+        return Ok(None);
+    };
+    if !source.is_file() {
+        return Ok(None);
+    }
+    let dest = dir.join(format!("{id}.{pid}.core"));
+    if std::fs::rename(&source, &dest).is_err() {
+        // Another file system
+        std::fs::copy(&source, &dest)
+            .with_context(|| format!("Failed to copy {}", source.display()))?;
+        std::fs::remove_file(&source)?;
+    }
+    Ok(Some(dest))
+}
+
+/// Where the core dumps of a daemon end up, for `demon status`
+pub(crate) fn describe(dir: &Path) -> String {
+    match handler() {
+        Some(handler) => format!("{} (but handed to {handler})", dir.display()),
+        None => dir.display().to_string(),
+    }
+}
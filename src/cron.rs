@@ -0,0 +1,187 @@
+//! Cron expressions for `demon schedule add --cron`.
+//!
+//! The usual five fields, minute, hour, day of month, month and day of week,
+//! each `*`, a number, a range `a-b`, a step `*/n` or `a-b/n`, or a list of
+//! those separated by commas. Months and days of the week may be given by
+//! their English names (`jan`, `mon`), and Sunday is both 0 and 7. Like cron,
+//! a job whose day of month and day of week are both restricted runs on the
+//! days matching either. `@hourly`, `@daily` (`@midnight`), `@weekly`,
+//! `@monthly` and `@yearly` (`@annually`) stand for their usual expansions.
+//! Times are in the local time zone.
+
+use anyhow::Result;
+
+const MONTHS: [&str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+const WEEKDAYS: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+/// How far ahead the next trigger is looked for before giving up, e.g. for
+/// `0 0 30 2 *`
+const MAX_STEPS: usize = 100_000;
+
+/// A parsed cron expression; each field is a bit set of the values it matches
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Cron {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    /// Whether the day of month and day of week fields were `*`
+    any_day: bool,
+    any_weekday: bool,
+}
+
+impl Cron {
+    pub(crate) fn parse(expr: &str) -> Result<Cron> {
+        let expanded = match expr.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            other => other,
+        };
+        let fields: Vec<&str> = expanded.split_whitespace().collect();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            anyhow::bail!(
+                "Invalid cron expression '{expr}': expected 5 fields (minute hour day-of-month month day-of-week), got {}",
+                fields.len()
+            );
+        };
+        let field = |text: &str, name: &str, min: u32, max: u32, names: &[&str]| {
+            parse_field(text, min, max, names).map_err(|e| {
+                anyhow::anyhow!("Invalid {name} '{text}' in cron expression '{expr}': {e}")
+            })
+        };
+        let mut weekdays = field(weekday, "day of week", 0, 7, &WEEKDAYS)?;
+        // Sunday is both 0 and 7
+        if weekdays & (1 << 7) != 0 {
+            weekdays |= 1;
+        }
+        Ok(Cron {
+            minutes: field(minute, "minute", 0, 59, &[])?,
+            hours: field(hour, "hour", 0, 23, &[])?,
+            days: field(day, "day of month", 1, 31, &[])?,
+            months: field(month, "month", 1, 12, &MONTHS)?,
+            weekdays,
+            any_day: day == "*",
+            any_weekday: weekday == "*",
+        })
+    }
+
+    fn matches_day(&self, tm: &libc::tm) -> bool {
+        let day = self.days & (1 << tm.tm_mday) != 0;
+        let weekday = self.weekdays & (1 << tm.tm_wday) != 0;
+        match (self.any_day, self.any_weekday) {
+            (false, false) => day || weekday,
+            _ => day && weekday,
+        }
+    }
+
+    /// The first trigger strictly after `after` (Unix time), or None when the
+    /// expression never matches
+    pub(crate) fn next_after(&self, after: u64) -> Option<u64> {
+        let mut time = (after / 60 + 1) * 60;
+        for _ in 0..MAX_STEPS {
+            let tm = local_time(time)?;
+            let next = if self.months & (1 << (tm.tm_mon + 1)) == 0 {
+                // First minute of the next month
+                from_local(tm.tm_year, tm.tm_mon + 1, 1, 0, 0)?
+            } else if !self.matches_day(&tm) {
+                from_local(tm.tm_year, tm.tm_mon, tm.tm_mday + 1, 0, 0)?
+            } else if self.hours & (1 << tm.tm_hour) == 0 {
+                from_local(tm.tm_year, tm.tm_mon, tm.tm_mday, tm.tm_hour + 1, 0)?
+            } else if self.minutes & (1 << tm.tm_min) == 0 {
+                time + 60
+            } else {
+                return Some(time);
+            };
+            // Around a DST change mktime may land on an hour already seen
+            time = next.max(time + 60);
+        }
+        None
+    }
+}
+
+/// Parse one field into the bit set of the values it matches
+fn parse_field(text: &str, min: u32, max: u32, names: &[&str]) -> Result<u64, String> {
+    let value = |part: &str| -> Result<u32, String> {
+        let lower = part.to_ascii_lowercase();
+        // Names count from the smallest value of the field: jan is 1, sun is 0
+        let named = names
+            .iter()
+            .position(|name| *name == lower)
+            .map(|index| index as u32 + min);
+        let n = match named {
+            Some(n) => n,
+            None => part
+                .parse::<u32>()
+                .map_err(|_| format!("'{part}' is not a number"))?,
+        };
+        if n < min || n > max {
+            return Err(format!("{n} is not between {min} and {max}"));
+        }
+        Ok(n)
+    };
+
+    let mut bits = 0u64;
+    for item in text.split(',') {
+        let (range, step) = match item.split_once('/') {
+            Some((range, step)) => {
+                let step = step
+                    .parse::<u32>()
+                    .ok()
+                    .filter(|step| *step > 0)
+                    .ok_or_else(|| format!("'{step}' is not a valid step"))?;
+                (range, step)
+            }
+            None => (item, 1),
+        };
+        let (start, end) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((start, end)) => (value(start)?, value(end)?),
+                // `5/15` means from 5 to the end in steps of 15
+                None if step > 1 => (value(range)?, max),
+                None => {
+                    let n = value(range)?;
+                    (n, n)
+                }
+            },
+        };
+        if start > end {
+            return Err(format!("the range {start}-{end} is empty"));
+        }
+        for n in (start..=end).step_by(step as usize) {
+            bits |= 1 << n;
+        }
+    }
+    Ok(bits)
+}
+
+fn local_time(time: u64) -> Option<libc::tm> {
+    let time = time as libc::time_t;
+    // SAFETY: tm is plain data and both pointers are valid for the call
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    if unsafe { libc::localtime_r(&time, &mut tm) }.is_null() {
+        return None;
+    }
+    Some(tm)
+}
+
+/// Unix time of a local time; mktime carries overflowing fields, so the day
+/// after the 31st is the 1st of the next month
+fn from_local(year: i32, month: i32, day: i32, hour: i32, minute: i32) -> Option<u64> {
+    // SAFETY: tm is plain data and mktime only reads and normalizes it
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    tm.tm_year = year;
+    tm.tm_mon = month;
+    tm.tm_mday = day;
+    tm.tm_hour = hour;
+    tm.tm_min = minute;
+    tm.tm_isdst = -1;
+    let time = unsafe { libc::mktime(&mut tm) };
+    u64::try_from(time).ok()
+}
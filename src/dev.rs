@@ -0,0 +1,491 @@
+//! `demon dev`: a batteries-included development loop.
+//!
+//! The command runs as a regular daemon with the given ID, so every other
+//! command (`status`, `tail`, `history`, ...) works on it as usual. On top of
+//! that, `dev` stays in the foreground and
+//!
+//! - restarts the daemon whenever a watched source file changes,
+//! - terminates the daemon's whole process tree on restart, so wrappers like
+//!   `cargo run` or `npm start` do not leave the real server behind,
+//! - reports when the daemon looks ready, i.e. logs a line such as "listening",
+//! - prints both log streams to the terminal, prefixed and colored.
+//!
+//! Sensible defaults for the watched paths, ignored directories and readiness
+//! patterns are picked from a preset matching the program being run.
+
+use crate::follow::Follower;
+use crate::{
+    color::{self, CYAN, GREEN, RED, YELLOW},
+    events,
+    pidfile::{PidFile, build_file_path},
+    proc::{ProcStat, ProcStatus, process_descendants},
+    runner::{RunOptions, run_daemon},
+    stop::remove_pid_file_if_owned,
+    unix_now, watch,
+};
+use anyhow::{Context, Result};
+use notify::{Event, EventKind, RecursiveMode};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{Receiver, channel};
+use std::time::{Duration, Instant};
+
+/// How often logs and the daemon state are polled
+const TICK: Duration = Duration::from_millis(100);
+
+/// Defaults for a family of dev commands, picked by program name
+struct Preset {
+    name: &'static str,
+    programs: &'static [&'static str],
+    watch: &'static [&'static str],
+    ignore: &'static [&'static str],
+    ready: &'static [&'static str],
+}
+
+const PRESETS: &[Preset] = &[
+    Preset {
+        name: "cargo",
+        programs: &["cargo"],
+        watch: &["src", "Cargo.toml", "build.rs"],
+        ignore: &["target"],
+        ready: &["listening", "ready", "started"],
+    },
+    Preset {
+        name: "node",
+        programs: &["npm", "npx", "pnpm", "yarn", "node", "bun", "deno"],
+        watch: &["."],
+        ignore: &["node_modules", "dist", "build", ".next"],
+        ready: &["listening", "ready", "compiled", "local:"],
+    },
+    Preset {
+        name: "python",
+        programs: &["python", "python3", "uvicorn", "flask", "gunicorn"],
+        watch: &["."],
+        ignore: &["__pycache__", ".venv", "venv"],
+        ready: &["listening", "running on", "serving", "startup complete"],
+    },
+    Preset {
+        name: "go",
+        programs: &["go"],
+        watch: &["."],
+        ignore: &["vendor"],
+        ready: &["listening", "ready", "started"],
+    },
+];
+
+const DEFAULT_PRESET: Preset = Preset {
+    name: "generic",
+    programs: &[],
+    watch: &["."],
+    ignore: &[],
+    ready: &["listening", "ready"],
+};
+
+/// Directories that never trigger a restart
+pub(crate) const ALWAYS_IGNORED: &[&str] = &[".git", ".hg", ".svn", ".demon"];
+
+fn preset_for(command: &[String]) -> &'static Preset {
+    let program = Path::new(&command[0])
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or_default();
+    PRESETS
+        .iter()
+        .find(|preset| preset.programs.contains(&program))
+        .unwrap_or(&DEFAULT_PRESET)
+}
+
+/// Settings of a dev session; empty lists fall back to the preset
+pub(crate) struct DevOptions {
+    pub(crate) watch: Vec<PathBuf>,
+    pub(crate) ignore: Vec<String>,
+    pub(crate) ready: Vec<String>,
+    pub(crate) debounce: Duration,
+    pub(crate) stop_timeout: Duration,
+    pub(crate) color: bool,
+    /// Poll the watched paths instead of using inotify
+    pub(crate) poll: bool,
+}
+
+/// One log stream of the daemon and the color of its prefix
+struct LogStream {
+    follower: Follower,
+    color: &'static str,
+}
+
+struct Session<'a> {
+    id: &'a str,
+    command: &'a [String],
+    root_dir: &'a Path,
+    ready: Vec<String>,
+    options: &'a DevOptions,
+    streams: [LogStream; 2],
+    /// PID of the current run, None while waiting for changes after an exit
+    pid: Option<u32>,
+    /// Process group of the last run, which may outlive its leader
+    group: Option<u32>,
+    /// When the current run started, until it is reported ready
+    waiting_ready: Option<Instant>,
+}
+
+pub(crate) fn run_dev(
+    id: &str,
+    command: &[String],
+    root_dir: &Path,
+    options: &DevOptions,
+) -> Result<()> {
+    let preset = preset_for(command);
+    let watch: Vec<PathBuf> = if options.watch.is_empty() {
+        preset.watch.iter().map(PathBuf::from).collect()
+    } else {
+        options.watch.clone()
+    };
+    let mut ignore: Vec<String> = ALWAYS_IGNORED.iter().map(|s| s.to_string()).collect();
+    ignore.extend(preset.ignore.iter().map(|s| s.to_string()));
+    ignore.extend(options.ignore.iter().cloned());
+    let ready = if options.ready.is_empty() {
+        preset.ready.iter().map(|s| s.to_string()).collect()
+    } else {
+        options.ready.clone()
+    };
+
+    let watched: Vec<&Path> = watch
+        .iter()
+        .map(PathBuf::as_path)
+        .filter(|path| path.exists())
+        .collect();
+    if watched.is_empty() {
+        return Err(anyhow::anyhow!(
+            "None of the paths to watch exist: {}",
+            watch
+                .iter()
+                .map(|path| path.display().to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ));
+    }
+    let (tx, rx) = channel();
+    let recursive: Vec<_> = watched
+        .iter()
+        .map(|path| (*path, RecursiveMode::Recursive))
+        .collect();
+    let _watcher =
+        watch::watcher(&recursive, tx, options.poll).context("Failed to watch for changes")?;
+    let watched: Vec<String> = watched
+        .iter()
+        .map(|path| path.display().to_string())
+        .collect();
+
+    let running = Arc::new(AtomicBool::new(true));
+    let r = running.clone();
+    ctrlc::set_handler(move || {
+        r.store(false, Ordering::SeqCst);
+    })?;
+
+    let mut session = Session {
+        id,
+        command,
+        root_dir,
+        ready,
+        options,
+        streams: [
+            LogStream {
+                follower: Follower::new(build_file_path(root_dir, id, "stdout")),
+                color: CYAN,
+            },
+            LogStream {
+                follower: Follower::new(build_file_path(root_dir, id, "stderr")),
+                color: RED,
+            },
+        ],
+        pid: None,
+        group: None,
+        waiting_ready: None,
+    };
+    session.say(
+        YELLOW,
+        &format!(
+            "{} preset, watching {} (Ctrl+C to stop)",
+            preset.name,
+            watched.join(", ")
+        ),
+    );
+    session.start()?;
+
+    let root_dir = std::fs::canonicalize(root_dir).unwrap_or_else(|_| root_dir.to_path_buf());
+    let result = event_loop(&mut session, &rx, &ignore, &root_dir, &running);
+    let stopped = session.stop();
+    result.and(stopped)
+}
+
+fn event_loop(
+    session: &mut Session,
+    rx: &Receiver<notify::Result<Event>>,
+    ignore: &[String],
+    root_dir: &Path,
+    running: &AtomicBool,
+) -> Result<()> {
+    // Time of the last relevant change and the file that changed first
+    let mut pending: Option<(Instant, PathBuf)> = None;
+
+    while running.load(Ordering::SeqCst) {
+        match rx.recv_timeout(TICK) {
+            Ok(Ok(event)) if is_relevant(&event, ignore, root_dir) => {
+                let first = pending
+                    .take()
+                    .map(|(_, path)| path)
+                    .unwrap_or_else(|| event.paths[0].clone());
+                pending = Some((Instant::now(), first));
+            }
+            Ok(Ok(_)) => {}
+            Ok(Err(e)) => tracing::error!("Watch error: {:?}", e),
+            Err(std::sync::mpsc::RecvTimeoutError::Timeout) => {}
+            Err(e) => return Err(e.into()),
+        }
+
+        session.pump_logs();
+        session.check_exited();
+
+        if let Some((at, path)) = &pending
+            && at.elapsed() >= session.options.debounce
+        {
+            let path = path
+                .strip_prefix(std::env::current_dir().unwrap_or_default())
+                .unwrap_or(path)
+                .display()
+                .to_string();
+            session.say(YELLOW, &format!("{path} changed, restarting"));
+            pending = None;
+            session.stop()?;
+            if let Err(e) = session.start() {
+                session.say(RED, &format!("failed to start: {e}; waiting for changes"));
+                continue;
+            }
+            events::emit(
+                session.root_dir,
+                session.id,
+                events::Kind::Restarted,
+                session.pid,
+                Some(format!("{path} changed")),
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Whether a file system event should trigger a restart
+pub(crate) fn is_relevant(event: &Event, ignore: &[String], root_dir: &Path) -> bool {
+    let changes_content = matches!(
+        event.kind,
+        EventKind::Create(_)
+            | EventKind::Remove(_)
+            | EventKind::Modify(
+                notify::event::ModifyKind::Data(_)
+                    | notify::event::ModifyKind::Name(_)
+                    | notify::event::ModifyKind::Any
+                    // What the polling watcher reports for a write
+                    | notify::event::ModifyKind::Metadata(notify::event::MetadataKind::WriteTime)
+            )
+    );
+    changes_content
+        && event.paths.iter().any(|path| {
+            let ignored_dir = path.components().any(|component| {
+                ignore
+                    .iter()
+                    .any(|name| component.as_os_str() == name.as_str())
+            });
+            // Editor swap and backup files
+            let name = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .unwrap_or_default();
+            let scratch = name.ends_with('~')
+                || name.starts_with(".#")
+                || name.ends_with(".swp")
+                || name.ends_with(".swx");
+            !ignored_dir && !scratch && !path.starts_with(root_dir)
+        })
+}
+
+impl Session<'_> {
+    /// Print a message of demon itself
+    fn say(&self, color: &str, message: &str) {
+        let prefix = format!("[dev] {}", self.id);
+        println!("{} {message}", self.paint(&prefix, color));
+        let _ = std::io::stdout().flush();
+    }
+
+    fn paint(&self, text: &str, color: &str) -> String {
+        color::paint(self.options.color, text, &format!("1;{color}"))
+    }
+
+    fn start(&mut self) -> Result<()> {
+        let pid = run_daemon(self.id, self.command, self.root_dir, &RunOptions::default())?;
+        self.pid = Some(pid);
+        self.group = Some(pid);
+        self.waiting_ready = Some(Instant::now());
+        Ok(())
+    }
+
+    /// Print new log lines, watching for a readiness pattern
+    fn pump_logs(&mut self) {
+        for index in 0..self.streams.len() {
+            let color = self.streams[index].color;
+            // The logs start over on restart, which the follower picks up
+            let lines = match self.streams[index].follower.poll_lines() {
+                Ok((_, lines)) => lines,
+                Err(e) => {
+                    tracing::warn!("Failed to read logs of '{}': {}", self.id, e);
+                    Vec::new()
+                }
+            };
+            for line in lines {
+                println!("{} {line}", self.paint(&format!("{} |", self.id), color));
+                if let Some(started) = self.waiting_ready {
+                    let lower = line.to_lowercase();
+                    if self.ready.iter().any(|p| lower.contains(&p.to_lowercase())) {
+                        let elapsed = started.elapsed().as_secs_f64();
+                        self.say(GREEN, &format!("ready in {elapsed:.1}s"));
+                        self.waiting_ready = None;
+                    }
+                }
+            }
+        }
+        let _ = std::io::stdout().flush();
+    }
+
+    /// Report when the daemon exits on its own; it is restarted on the next change
+    fn check_exited(&mut self) {
+        let Some(pid) = self.pid else {
+            return;
+        };
+        // The daemon is our child, so its exit status can be collected here
+        let mut status = 0;
+        // SAFETY: waitpid only writes to the status integer we pass
+        let reaped = unsafe { libc::waitpid(pid as libc::pid_t, &mut status, libc::WNOHANG) };
+        if reaped != pid as libc::pid_t {
+            return;
+        }
+        self.pump_logs();
+        let how = if libc::WIFEXITED(status) {
+            format!("exited with code {}", libc::WEXITSTATUS(status))
+        } else {
+            format!("killed by signal {}", libc::WTERMSIG(status))
+        };
+        let color = if libc::WIFEXITED(status) && libc::WEXITSTATUS(status) == 0 {
+            YELLOW
+        } else {
+            RED
+        };
+        self.say(color, &format!("{how}; waiting for changes"));
+        self.waiting_ready = None;
+        self.pid = None;
+    }
+
+    /// Terminate the current run and every process it started
+    fn stop(&mut self) -> Result<()> {
+        let Some(pid) = self.pid.take() else {
+            // The daemon exited on its own; processes it left in its group
+            // are stopped and its PID file retired
+            if let Some(group) = self.group.take()
+                && !alive(group, &[]).is_empty()
+            {
+                terminate_tree(group, self.options.stop_timeout);
+            }
+            if let Ok(data) = PidFile::read(self.root_dir, self.id) {
+                remove_pid_file_if_owned(self.root_dir, self.id, data.pid, None, None)?;
+            }
+            return Ok(());
+        };
+
+        self.group = None;
+        let peak_rss = ProcStatus::read(pid).and_then(|status| status.vm_hwm);
+        let killed = terminate_tree(pid, self.options.stop_timeout);
+        self.pump_logs();
+        events::emit(
+            self.root_dir,
+            self.id,
+            events::Kind::Stopped,
+            Some(pid),
+            Some(if killed { "SIGKILL" } else { "SIGTERM" }.to_string()),
+        );
+        remove_pid_file_if_owned(self.root_dir, self.id, pid, Some(unix_now()), peak_rss)
+    }
+}
+
+/// Send SIGTERM to a daemon's process group and descendants, then SIGKILL to
+/// whatever is left after the timeout. Returns whether SIGKILL was needed.
+///
+/// Daemons lead their own process group, which covers the usual wrapper and
+/// child setups; descendants that moved to a group of their own are signalled
+/// individually.
+fn terminate_tree(pid: u32, timeout: Duration) -> bool {
+    let mut tree = process_descendants(pid);
+    tree.push(pid);
+
+    signal_tree(pid, &tree, libc::SIGTERM);
+    let deadline = Instant::now() + timeout;
+    while Instant::now() < deadline {
+        reap(pid);
+        if alive(pid, &tree).is_empty() {
+            return false;
+        }
+        std::thread::sleep(TICK);
+    }
+
+    signal_tree(pid, &tree, libc::SIGKILL);
+    for _ in 0..10 {
+        reap(pid);
+        if alive(pid, &tree).is_empty() {
+            break;
+        }
+        std::thread::sleep(TICK);
+    }
+    true
+}
+
+fn signal_tree(pgid: u32, tree: &[u32], signal: i32) {
+    // SAFETY: kill has no memory safety preconditions
+    unsafe {
+        libc::kill(-(pgid as libc::pid_t), signal);
+        for &pid in tree {
+            if ProcStat::read(pid).is_some_and(|stat| stat.pgrp != pgid) {
+                libc::kill(pid as libc::pid_t, signal);
+            }
+        }
+    }
+}
+
+/// Live (non-zombie) processes of the group or the given tree
+fn alive(pgid: u32, tree: &[u32]) -> Vec<u32> {
+    let mut pids = Vec::new();
+    if let Ok(entries) = std::fs::read_dir("/proc") {
+        for entry in entries.flatten() {
+            let Some(pid) = entry
+                .file_name()
+                .to_str()
+                .and_then(|name| name.parse::<u32>().ok())
+            else {
+                continue;
+            };
+            if let Some(stat) = ProcStat::read(pid)
+                && stat.state != 'Z'
+                && (stat.pgrp == pgid || tree.contains(&pid))
+            {
+                pids.push(pid);
+            }
+        }
+    }
+    pids
+}
+
+/// Collect the exit status of the daemon, which is our child
+fn reap(pid: u32) {
+    let mut status = 0;
+    // SAFETY: waitpid only writes to the status integer we pass
+    unsafe {
+        libc::waitpid(pid as libc::pid_t, &mut status, libc::WNOHANG);
+    }
+}
@@ -0,0 +1,286 @@
+//! Append-only lifecycle event log, kept in `<root>/events.jsonl`.
+//!
+//! Commands append one JSON line per event as they happen. Unlike the run
+//! history, which has one record per finished run, this captures every
+//! transition in order and is meant to be followed live with `demon events -f`.
+
+use crate::follow::Follower;
+use crate::{
+    format_timestamp,
+    output::{self, Format},
+    parse_duration, plugins, unix_now,
+    watch::DirWatch,
+};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Kind {
+    /// `demon run` started the daemon
+    Started,
+    /// `demon stop` terminated the daemon
+    Stopped,
+    /// A supervised daemon exited successfully on its own
+    Exited,
+    /// A supervised daemon exited with a failure or was killed externally
+    Crashed,
+    /// `demon clean` removed the files of a dead daemon
+    Cleaned,
+    /// The daemon was stopped and started again
+    Restarted,
+    /// The agent saw the daemon fail its health checks, or its sd_notify
+    /// watchdog expired
+    Unhealthy,
+    /// A daemon started with `--sd-notify` reported that it is ready
+    Ready,
+    /// `demon reload` sent the daemon its reload signal
+    Reloaded,
+    /// `demon chaos` disrupted the daemon
+    Chaos,
+}
+
+impl std::fmt::Display for Kind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Kind::Started => "started",
+            Kind::Stopped => "stopped",
+            Kind::Exited => "exited",
+            Kind::Crashed => "crashed",
+            Kind::Cleaned => "cleaned",
+            Kind::Restarted => "restarted",
+            Kind::Unhealthy => "unhealthy",
+            Kind::Ready => "ready",
+            Kind::Reloaded => "reloaded",
+            Kind::Chaos => "chaos",
+        };
+        f.pad(name)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct DaemonEvent {
+    /// Unix time of the event
+    pub(crate) ts: u64,
+    pub(crate) id: String,
+    pub(crate) event: Kind,
+    pub(crate) pid: Option<u32>,
+    /// Human readable details, e.g. the command or exit status
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) detail: Option<String>,
+}
+
+fn events_path(root_dir: &Path) -> PathBuf {
+    root_dir.join("events.jsonl")
+}
+
+/// Append an event. Failures are logged, never fatal: the event log must not
+/// get in the way of managing the daemon itself.
+pub(crate) fn emit(
+    root_dir: &Path,
+    id: &str,
+    event: Kind,
+    pid: Option<u32>,
+    detail: Option<String>,
+) {
+    let record = DaemonEvent {
+        ts: unix_now(),
+        id: id.to_string(),
+        event,
+        pid,
+        detail,
+    };
+    let result = serde_json::to_string(&record)
+        .map_err(anyhow::Error::from)
+        .and_then(|mut line| {
+            line.push('\n');
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(events_path(root_dir))?
+                .write_all(line.as_bytes())?;
+            Ok(())
+        });
+    if let Err(e) = result {
+        tracing::warn!("Failed to record '{}' event for '{}': {}", event, id, e);
+    }
+    plugins::dispatch(root_dir, &record);
+}
+
+/// Which events to show
+pub(crate) struct Filter {
+    pub(crate) id: Option<String>,
+    pub(crate) since: Option<u64>,
+}
+
+impl Filter {
+    fn matches(&self, event: &DaemonEvent) -> bool {
+        self.id.as_ref().is_none_or(|id| *id == event.id)
+            && self.since.is_none_or(|since| event.ts >= since)
+    }
+}
+
+/// The recorded events that match a filter, oldest first
+pub(crate) fn read(root_dir: &Path, filter: &Filter) -> Result<Vec<DaemonEvent>> {
+    let path = events_path(root_dir);
+    let contents = match std::fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    };
+    Ok(contents
+        .lines()
+        .filter_map(|line| serde_json::from_str::<DaemonEvent>(line).ok())
+        .filter(|event| filter.matches(event))
+        .collect())
+}
+
+/// Count the recorded events of a kind that match a filter
+pub(crate) fn count(root_dir: &Path, kind: Kind, filter: &Filter) -> Result<usize> {
+    Ok(read(root_dir, filter)?
+        .iter()
+        .filter(|event| event.event == kind)
+        .count())
+}
+
+/// Fields of the events in the CSV and plain formats
+const FIELDS: [&str; 5] = ["ts", "id", "event", "pid", "detail"];
+
+fn print_event(event: &DaemonEvent, format: Format) -> Result<()> {
+    if !format.is_text() {
+        return output::print_row(format, &FIELDS, &serde_json::to_value(event)?);
+    }
+    let pid = event.pid.map(|pid| pid.to_string()).unwrap_or_default();
+    let line = format!(
+        "{:<20} {:<10} {:<20} {:<8} {}",
+        format_timestamp(event.ts),
+        event.event,
+        event.id,
+        pid,
+        event.detail.as_deref().unwrap_or_default()
+    );
+    println!("{}", line.trim_end());
+    Ok(())
+}
+
+/// Print the events written since the last call
+fn print_new(follower: &mut Follower, filter: &Filter, format: Format) -> Result<()> {
+    let (_, lines) = follower
+        .poll_lines()
+        .with_context(|| format!("Failed to read {}", follower.path().display()))?;
+    for line in lines {
+        match serde_json::from_str::<DaemonEvent>(&line) {
+            Ok(event) if filter.matches(&event) => print_event(&event, format)?,
+            Ok(_) => {}
+            Err(e) => tracing::warn!("Skipping invalid event line: {}", e),
+        }
+    }
+    std::io::stdout().flush()?;
+    Ok(())
+}
+
+pub(crate) fn show_events(
+    root_dir: &Path,
+    filter: &Filter,
+    follow: bool,
+    format: Format,
+    poll: bool,
+) -> Result<()> {
+    let path = events_path(root_dir);
+    output::print_header(format, &FIELDS);
+    if !follow && !path.exists() {
+        if format.is_text() {
+            println!("No events recorded.");
+        }
+        return Ok(());
+    }
+
+    let mut follower = Follower::new(&path);
+    print_new(&mut follower, filter, format)?;
+    if !follow {
+        return Ok(());
+    }
+
+    tracing::info!("Watching for new events... Press Ctrl+C to stop.");
+
+    let mut watch = DirWatch::new(&[root_dir], poll);
+
+    let running = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(true));
+    let r = running.clone();
+    ctrlc::set_handler(move || {
+        r.store(false, std::sync::atomic::Ordering::SeqCst);
+    })?;
+
+    while running.load(std::sync::atomic::Ordering::SeqCst) {
+        if watch.changed(&[&path], Duration::from_millis(100)) {
+            print_new(&mut follower, filter, format)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Parse a `--since` value: a duration ago (`30s`, `10m`, `2h`, `7d`), a Unix
+/// timestamp, or a local date and time (`2025-06-01`, `2025-06-01 14:30`)
+pub(crate) fn parse_since(value: &str) -> Result<u64> {
+    parse_time("--since", value)
+}
+
+/// Parse a point in time given like `--since` to `flag`
+pub(crate) fn parse_time(flag: &str, value: &str) -> Result<u64> {
+    let value = value.trim();
+    let invalid = || {
+        anyhow::anyhow!(
+            "Invalid {} '{}': expected a duration like 10m, a Unix timestamp or YYYY-MM-DD [HH:MM[:SS]]",
+            flag,
+            value
+        )
+    };
+
+    if value
+        .chars()
+        .last()
+        .is_some_and(|c| c.is_ascii_alphabetic())
+    {
+        let ago = parse_duration(value)
+            .map_err(|e| anyhow::anyhow!("Invalid {} '{}': {}", flag, value, e))?;
+        return Ok(unix_now().saturating_sub(ago.as_secs()));
+    }
+
+    if let Ok(timestamp) = value.parse::<u64>() {
+        return Ok(timestamp);
+    }
+
+    let (date, time) = value
+        .split_once([' ', 'T'])
+        .map_or((value, None), |(date, time)| (date, Some(time)));
+    let numbers = |text: &str, separator: char| -> Result<Vec<i32>> {
+        text.split(separator)
+            .map(|part| part.parse::<i32>().map_err(|_| invalid()))
+            .collect()
+    };
+    let date = numbers(date, '-')?;
+    let time = time
+        .map(|time| numbers(time, ':'))
+        .transpose()?
+        .unwrap_or_default();
+    if date.len() != 3 || time.len() > 3 || (time.len() == 1) {
+        return Err(invalid());
+    }
+
+    // SAFETY: tm is plain data and mktime only reads and normalizes it
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    tm.tm_year = date[0] - 1900;
+    tm.tm_mon = date[1] - 1;
+    tm.tm_mday = date[2];
+    tm.tm_hour = time.first().copied().unwrap_or(0);
+    tm.tm_min = time.get(1).copied().unwrap_or(0);
+    tm.tm_sec = time.get(2).copied().unwrap_or(0);
+    tm.tm_isdst = -1;
+    let timestamp = unsafe { libc::mktime(&mut tm) };
+    u64::try_from(timestamp).map_err(|_| invalid())
+}
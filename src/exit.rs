@@ -0,0 +1,157 @@
+//! Exit codes of `demon`.
+//!
+//! Besides 0 for success, 1 for failures and 2 for invalid arguments, the
+//! commands that look at a single daemon tell its state in the exit code,
+//! like the LSB init script actions, so scripts can write
+//! `demon status web && ...` instead of parsing the output:
+//!
+//! | Code | `status`  | `stop`                    | `wait`                   |
+//! |------|-----------|---------------------------|--------------------------|
+//! | 0    | running   | stopped                   | the daemon exited        |
+//! | 3    | dead      | was not running           | was not running          |
+//! | 4    | not found | not found                 | not found                |
+//! | 5    |           |                           | timed out                |
+//!
+//! `up --wait-healthy` exits with 5 too when a daemon is not ready in time.
+//! `restart` exits with 4 when there is no such daemon, and so do `stop`,
+//! `restart` and `tail` when no daemon has the given `--group` or `--label`.
+//! Given a group or labels, `stop` and `restart` go on past the daemons they
+//! fail on and then exit with 1, and a daemon that was not running is only
+//! told in its record.
+//! `get` exits with 3 and 4 like `status` when the value needs a running
+//! daemon or a daemon at all, and with 6 when the daemon has no such value.
+//! `check` exits with the states of Nagios plugins instead, see
+//! [`check`](crate::check).
+
+/// The command failed
+pub(crate) const FAILURE: i32 = 1;
+/// The daemon is not running, but its PID file is still there
+pub(crate) const NOT_RUNNING: i32 = 3;
+/// There is no daemon with that ID
+pub(crate) const NOT_FOUND: i32 = 4;
+/// `wait` gave up before the daemon exited, or `up --wait-healthy` before
+/// every daemon was ready
+pub(crate) const TIMEOUT: i32 = 5;
+/// `get` has no value to print, e.g. the exit code of a running daemon
+pub(crate) const NO_VALUE: i32 = 6;
+
+/// Exit codes of every command
+pub(crate) const COMMON: &[(i32, &str)] = &[
+    (0, "success"),
+    (
+        FAILURE,
+        "the command failed; the error is printed to stderr",
+    ),
+    (2, "invalid arguments; usage is printed to stderr"),
+];
+
+/// Exit codes that tell the state of the daemon, for the commands that have
+/// them
+pub(crate) fn of_command(command: &str) -> &'static [(i32, &'static str)] {
+    match command {
+        "status" => &[
+            (0, "the daemon is running"),
+            (
+                NOT_RUNNING,
+                "the daemon is dead (its PID file is left behind)",
+            ),
+            (NOT_FOUND, "no daemon with that ID"),
+        ],
+        "stop" => &[
+            (0, "the daemon was stopped"),
+            (
+                NOT_RUNNING,
+                "the daemon was not running; its PID file was removed",
+            ),
+            (
+                NOT_FOUND,
+                "no daemon with that ID, or in the group or with the labels",
+            ),
+        ],
+        "restart" => &[
+            (0, "the daemons were started again"),
+            (
+                NOT_FOUND,
+                "no daemon with that ID, or in the group or with the labels",
+            ),
+        ],
+        "wait" => &[
+            (0, "the daemon exited"),
+            (NOT_RUNNING, "the daemon was not running"),
+            (NOT_FOUND, "no daemon with that ID"),
+            (TIMEOUT, "the timeout was reached"),
+        ],
+        "get" => &[
+            (0, "the value was printed"),
+            (
+                NOT_RUNNING,
+                "the value needs a running daemon, and it is dead",
+            ),
+            (NOT_FOUND, "no daemon with that ID, or no such log"),
+            (
+                NO_VALUE,
+                "the daemon has no such value, e.g. no exit code while it runs",
+            ),
+        ],
+        "up" => &[(
+            TIMEOUT,
+            "with --wait-healthy, a daemon was not ready within the timeout",
+        )],
+        "grep" => &[
+            (0, "a line matched"),
+            (FAILURE, "no line matched, or the search failed"),
+        ],
+        "check" => &[
+            (0, "OK: the daemon is running within its thresholds"),
+            (
+                1,
+                "WARNING: a --warn-mem or --max-restarts threshold is exceeded",
+            ),
+            (
+                2,
+                "CRITICAL: the daemon is missing, dead, unhealthy or above --crit-mem; also invalid arguments",
+            ),
+            (3, "UNKNOWN: the state could not be told"),
+        ],
+        _ => &[],
+    }
+}
+
+/// An error that makes `demon` exit with `code`. Without a message nothing
+/// is printed, for outcomes the command already reported on stdout.
+#[derive(Debug)]
+pub(crate) struct Exit {
+    pub(crate) code: i32,
+    message: Option<String>,
+}
+
+impl Exit {
+    pub(crate) fn new(code: i32, message: String) -> Self {
+        Self {
+            code,
+            message: Some(message),
+        }
+    }
+
+    pub(crate) fn silent(code: i32) -> Self {
+        Self {
+            code,
+            message: None,
+        }
+    }
+
+    pub(crate) fn is_silent(&self) -> bool {
+        self.message.is_none()
+    }
+}
+
+impl std::fmt::Display for Exit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.message {
+            Some(message) => f.write_str(message),
+            None => write!(f, "exit code {}", self.code),
+        }
+    }
+}
+
+impl std::error::Error for Exit {}
@@ -0,0 +1,610 @@
+//! `demon export`: turn daemons into service definitions of the system's
+//! service manager, a systemd unit or a launchd property list.
+//!
+//! A daemon prototyped with demon becomes a service with the same command,
+//! working directory, environment and policies, so promoting it to a real
+//! service does not start from a blank file. A single daemon is exported
+//! from what its last run recorded, or from `demon.toml` when it never ran;
+//! `--all` exports every daemon of the manifest.
+//!
+//! The environment is read from the running daemon (`/proc/<pid>/environ`),
+//! without the variables that belong to the login session or to demon; a
+//! daemon that is not running is exported without one. Policies the service
+//! manager has no counterpart for, such as health checks, are listed in a
+//! comment of the file.
+
+use crate::{
+    agent::{Policy, Restart},
+    manifest::{Manifest, Service},
+    pidfile::{DaemonMeta, PidFile, build_file_path},
+    priority::IoClass,
+    runner::resolve_program,
+    status::is_daemon_running,
+    stdin::Stdin,
+};
+use anyhow::{Context, Result};
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+/// Variables of the login session or of demon, which a service must not
+/// inherit from the shell the daemon was started from
+const SESSION_VARS: &[&str] = &[
+    "_",
+    "PWD",
+    "OLDPWD",
+    "SHLVL",
+    "HOME",
+    "USER",
+    "LOGNAME",
+    "SHELL",
+    "MAIL",
+    "TERM",
+    "COLORTERM",
+    "LS_COLORS",
+    "DISPLAY",
+    "WAYLAND_DISPLAY",
+    "XAUTHORITY",
+    "DBUS_SESSION_BUS_ADDRESS",
+    "TMUX",
+    "TMUX_PANE",
+    "STY",
+    "WINDOWID",
+    "NOTIFY_SOCKET",
+    "TRACEPARENT",
+];
+
+/// Prefixes of session variables, like [`SESSION_VARS`]
+const SESSION_PREFIXES: &[&str] = &["SSH_", "XDG_", "TERM_", "DEMON_"];
+
+/// Comment of a service exported without an environment
+const NO_ENVIRONMENT: &str = "The daemon is not running, so its environment is not known";
+
+/// Service managers `demon export` writes for
+pub(crate) enum Target {
+    /// A systemd `.service` unit, installed into the user's `default.target`
+    /// or with `system` into `multi-user.target` of the system manager
+    Systemd { system: bool },
+    /// A launchd agent property list, for `~/Library/LaunchAgents`
+    Launchd,
+}
+
+/// What a service is made of
+struct Source {
+    id: String,
+    command: Vec<String>,
+    cwd: Option<PathBuf>,
+    env: Option<Vec<(String, String)>>,
+    stdin: Stdin,
+    policy: Policy,
+    /// Hooks and other settings the unit leaves out
+    dropped: Vec<&'static str>,
+}
+
+impl Source {
+    /// The last run of a daemon, or its manifest entry if it has none
+    fn of_daemon(root_dir: &Path, id: &str) -> Result<Self> {
+        let Ok(data) = PidFile::read(root_dir, id) else {
+            let manifest = Manifest::load(root_dir).map_err(|_| {
+                anyhow::anyhow!("No daemon '{}' ran here and there is no demon.toml", id)
+            })?;
+            let service = manifest.select(&[id.to_string()])?[0];
+            return Ok(Self::of_service(root_dir, &manifest, service));
+        };
+        let meta = DaemonMeta::read(root_dir, id);
+        let running = is_daemon_running(root_dir, id, data.pid);
+        let cwd = match running {
+            true => std::fs::read_link(format!("/proc/{}/cwd", data.pid)).ok(),
+            false => None,
+        };
+        Ok(Self {
+            id: id.to_string(),
+            command: data.command,
+            cwd: cwd.or(meta.cwd.clone()),
+            env: running.then(|| environ(data.pid)).flatten(),
+            stdin: meta.stdin.clone(),
+            dropped: dropped(&meta.hooks, meta.pty),
+            policy: meta.policy,
+        })
+    }
+
+    /// A daemon of the manifest, which runs in the manifest's directory
+    fn of_service(root_dir: &Path, manifest: &Manifest, service: &Service) -> Self {
+        let pid = PidFile::read(root_dir, &service.id)
+            .ok()
+            .map(|data| data.pid)
+            .filter(|pid| is_daemon_running(root_dir, &service.id, *pid));
+        Self {
+            id: service.id.clone(),
+            command: service.command.clone(),
+            cwd: Some(manifest.dir.clone()),
+            env: pid.and_then(environ),
+            stdin: service.stdin.clone(),
+            policy: service.policy.clone(),
+            dropped: dropped(&service.hooks, service.pty),
+        }
+    }
+
+    /// Settings that every service manager leaves out, as flags
+    fn not_exported(&self) -> Vec<String> {
+        let policy = &self.policy;
+        let mut flags: Vec<String> = self.dropped.iter().map(|s| s.to_string()).collect();
+        if policy.health_cmd.is_some() {
+            flags.push("--health-cmd".to_string());
+        }
+        if policy.singleton.is_some() {
+            flags.push("--singleton".to_string());
+        }
+        if policy.strip_ansi {
+            flags.push("--strip-ansi".to_string());
+        }
+        if policy.limits.core_dump_dir.is_some() {
+            flags.push("--core-dump-dir".to_string());
+        }
+        if policy.sandbox.confines_files() {
+            flags.push("--sandbox".to_string());
+        }
+        if policy.requirements.require_free_memory.is_some() {
+            flags.push("--require-free-memory".to_string());
+        }
+        if !policy.requirements.require_free_disk.is_empty() {
+            flags.push("--require-free-disk".to_string());
+        }
+        if !policy.autorestart.is_empty() {
+            flags.push("--watch".to_string());
+        }
+        if let Stdin::Fifo = self.stdin {
+            flags.push("--stdin fifo".to_string());
+        }
+        flags
+    }
+
+    /// The program as an absolute path, which both managers need
+    fn program(&self) -> String {
+        let mut program = PathBuf::from(&self.command[0]);
+        if let Ok(resolved) = resolve_program(&self.command[0]) {
+            program = resolved;
+        }
+        if program.is_relative()
+            && let Some(cwd) = &self.cwd
+        {
+            program = cwd.join(program);
+        }
+        program.display().to_string()
+    }
+
+    fn file_name(&self, target: &Target) -> String {
+        match target {
+            Target::Systemd { .. } => format!("{}.service", self.id),
+            Target::Launchd => format!("{}.plist", self.label()),
+        }
+    }
+
+    fn render(&self, root_dir: &Path, target: &Target) -> String {
+        match target {
+            Target::Systemd { system } => self.unit(root_dir, *system),
+            Target::Launchd => self.plist(root_dir),
+        }
+    }
+
+    /// The systemd unit
+    fn unit(&self, root_dir: &Path, system: bool) -> String {
+        let policy = &self.policy;
+        let mut unit = String::new();
+        let _ = writeln!(
+            unit,
+            "# Exported by `demon export systemd` from daemon '{}' of {}",
+            self.id,
+            root_dir.display()
+        );
+        let mut unsupported = self.not_exported();
+        if let Some(every) = policy.every {
+            unsupported.push(format!(
+                "--every (add a .timer with OnUnitInactiveSec={every}s and Type=oneshot)"
+            ));
+        }
+        if !unsupported.is_empty() {
+            let _ = writeln!(unit, "# Not exported: {}", unsupported.join(", "));
+        }
+        if self.env.is_none() {
+            let _ = writeln!(unit, "# {NO_ENVIRONMENT}");
+        }
+
+        let _ = writeln!(unit, "\n[Unit]");
+        let _ = writeln!(unit, "Description={}", escape(&self.id));
+        if system {
+            let _ = writeln!(unit, "After=network.target");
+        }
+
+        let _ = writeln!(unit, "\n[Service]");
+        let kind = if policy.uses_sd_notify() {
+            "notify"
+        } else {
+            "simple"
+        };
+        let _ = writeln!(unit, "Type={kind}");
+        if let Some(cwd) = &self.cwd {
+            let _ = writeln!(
+                unit,
+                "WorkingDirectory={}",
+                quote(&cwd.display().to_string())
+            );
+        }
+        for (key, value) in self.env.iter().flatten() {
+            let _ = writeln!(unit, "Environment={}", quote(&format!("{key}={value}")));
+        }
+        let exec_start: Vec<String> = std::iter::once(self.program())
+            .chain(self.command[1..].iter().cloned())
+            .map(|arg| quote(&arg))
+            .collect();
+        let _ = writeln!(unit, "ExecStart={}", exec_start.join(" "));
+        if let Some(signal) = policy.reload_signal {
+            let _ = writeln!(
+                unit,
+                "ExecReload=/bin/kill -s {} $MAINPID",
+                String::from(signal)
+            );
+        }
+        if let Stdin::File(file) = &self.stdin {
+            let _ = writeln!(unit, "StandardInput=file:{}", file.display());
+        }
+        let restart = match policy.restart {
+            Some(Restart::OnFailure) => Some("on-failure"),
+            Some(Restart::Always) => Some("always"),
+            Some(Restart::Never) | None => None,
+        };
+        if let Some(restart) = restart {
+            let _ = writeln!(unit, "Restart={restart}");
+            let _ = writeln!(unit, "RestartSec=1");
+        }
+        if let Some(watchdog) = policy.watchdog {
+            let _ = writeln!(unit, "WatchdogSec={watchdog}");
+        }
+        if let Some(max_runtime) = policy.max_runtime {
+            let _ = writeln!(unit, "RuntimeMaxSec={max_runtime}");
+        }
+        for (name, limit) in policy.limits.set() {
+            let value = limit.to_string().replace("unlimited", "infinity");
+            let _ = writeln!(unit, "Limit{}={value}", name.to_ascii_uppercase());
+        }
+        if let Some(umask) = policy.limits.umask {
+            let _ = writeln!(unit, "UMask={umask}");
+        }
+        if let Some(nice) = policy.priority.nice {
+            let _ = writeln!(unit, "Nice={nice}");
+        }
+        if let Some((class, level)) = policy.priority.io() {
+            let _ = writeln!(unit, "IOSchedulingClass={}", class.name());
+            if let Some(level) = level {
+                let _ = writeln!(unit, "IOSchedulingPriority={level}");
+            }
+        }
+        if let Some(cpus) = &policy.priority.cpu_affinity {
+            let _ = writeln!(unit, "CPUAffinity={}", cpus.to_string().replace(',', " "));
+        }
+        if let Some(adj) = policy.priority.oom_score_adj {
+            let _ = writeln!(unit, "OOMScoreAdjust={adj}");
+        }
+        if policy.sandbox.no_network {
+            let _ = writeln!(unit, "PrivateNetwork=yes");
+        }
+        if let Some(memory) = policy.resources.memory {
+            let _ = writeln!(unit, "MemoryMax={memory}");
+        }
+        if let Some(cpus) = policy.resources.cpus {
+            let _ = writeln!(unit, "CPUQuota={}%", (cpus * 100.0).round() as u64);
+        }
+
+        let _ = writeln!(unit, "\n[Install]");
+        let target = match system {
+            true => "multi-user.target",
+            false => "default.target",
+        };
+        let _ = writeln!(unit, "WantedBy={target}");
+        unit
+    }
+
+    /// Label of the launchd job, which names its plist too
+    fn label(&self) -> String {
+        format!("local.demon.{}", self.id)
+    }
+
+    /// The launchd property list. The daemon keeps writing to its logs in
+    /// the root directory, so `demon cat` and `demon tail` still read them.
+    fn plist(&self, root_dir: &Path) -> String {
+        let policy = &self.policy;
+        let mut unsupported = self.not_exported();
+        if policy.uses_sd_notify() {
+            unsupported.push("--sd-notify".to_string());
+        }
+        if policy.watchdog.is_some() {
+            unsupported.push("--watchdog".to_string());
+        }
+        if policy.max_runtime.is_some() {
+            unsupported.push("--max-runtime".to_string());
+        }
+        if policy.reload_signal.is_some() {
+            unsupported.push("--reload-signal".to_string());
+        }
+        if !policy.resources.is_empty() {
+            unsupported.push("--memory and --cpus".to_string());
+        }
+        if policy.priority.cpu_affinity.is_some() {
+            unsupported.push("--cpu-affinity".to_string());
+        }
+        if policy.priority.oom_score_adj.is_some() {
+            unsupported.push("--oom-score-adj".to_string());
+        }
+        if policy.sandbox.no_network {
+            unsupported.push("--no-network".to_string());
+        }
+        if policy
+            .priority
+            .io()
+            .is_some_and(|(class, _)| class != IoClass::Idle)
+        {
+            unsupported.push("--ionice-class".to_string());
+        }
+        let mut soft = Vec::new();
+        let mut hard = Vec::new();
+        for (name, limit) in policy.limits.set() {
+            let key = match name {
+                "nofile" => "NumberOfFiles",
+                "core" => "Core",
+                "nproc" => "NumberOfProcesses",
+                "cpu" => "CPU",
+                "fsize" => "FileSize",
+                "stack" => "Stack",
+                "memlock" => "MemoryLock",
+                _ => {
+                    unsupported.push(format!("--limit-{name}"));
+                    continue;
+                }
+            };
+            // Unlimited is launchd's default, which has no value to write
+            soft.extend(limit.soft().map(|value| (key, value)));
+            hard.extend(limit.hard().map(|value| (key, value)));
+        }
+
+        let mut plist = String::new();
+        let _ = writeln!(plist, r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+        let _ = writeln!(
+            plist,
+            r#"<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">"#
+        );
+        // Comments cannot contain "--"
+        let comment = |text: &str| format!("<!-- {} -->", xml(&text.replace("--", "")));
+        let _ = writeln!(
+            plist,
+            "{}",
+            comment(&format!(
+                "Exported by demon export launchd from daemon '{}' of {}",
+                self.id,
+                root_dir.display()
+            ))
+        );
+        if !unsupported.is_empty() {
+            let text = format!("Not exported: {}", unsupported.join(", "));
+            let _ = writeln!(plist, "{}", comment(&text));
+        }
+        if self.env.is_none() {
+            let _ = writeln!(plist, "{}", comment(NO_ENVIRONMENT));
+        }
+        let _ = writeln!(plist, r#"<plist version="1.0">"#);
+        let _ = writeln!(plist, "<dict>");
+        let key = |plist: &mut String, key: &str| {
+            let _ = writeln!(plist, "\t<key>{}</key>", xml(key));
+        };
+        let string = |plist: &mut String, indent: &str, value: &str| {
+            let _ = writeln!(plist, "{indent}<string>{}</string>", xml(value));
+        };
+
+        key(&mut plist, "Label");
+        string(&mut plist, "\t", &self.label());
+        key(&mut plist, "ProgramArguments");
+        let _ = writeln!(plist, "\t<array>");
+        string(&mut plist, "\t\t", &self.program());
+        for arg in &self.command[1..] {
+            string(&mut plist, "\t\t", arg);
+        }
+        let _ = writeln!(plist, "\t</array>");
+        if let Some(cwd) = &self.cwd {
+            key(&mut plist, "WorkingDirectory");
+            string(&mut plist, "\t", &cwd.display().to_string());
+        }
+        if let Some(env) = self.env.as_ref().filter(|env| !env.is_empty()) {
+            key(&mut plist, "EnvironmentVariables");
+            let _ = writeln!(plist, "\t<dict>");
+            for (name, value) in env {
+                let _ = writeln!(plist, "\t\t<key>{}</key>", xml(name));
+                string(&mut plist, "\t\t", value);
+            }
+            let _ = writeln!(plist, "\t</dict>");
+        }
+        if let Some(nice) = policy.priority.nice {
+            key(&mut plist, "Nice");
+            let _ = writeln!(plist, "\t<integer>{nice}</integer>");
+        }
+        if let Some((IoClass::Idle, _)) = policy.priority.io() {
+            key(&mut plist, "LowPriorityIO");
+            let _ = writeln!(plist, "\t<true/>");
+        }
+        if let Some(umask) = policy.limits.umask {
+            key(&mut plist, "Umask");
+            let _ = writeln!(plist, "\t<integer>{}</integer>", umask.bits());
+        }
+        key(&mut plist, "RunAtLoad");
+        let _ = writeln!(plist, "\t<true/>");
+        match policy.restart {
+            Some(Restart::Always) => {
+                key(&mut plist, "KeepAlive");
+                let _ = writeln!(plist, "\t<true/>");
+            }
+            Some(Restart::OnFailure) => {
+                key(&mut plist, "KeepAlive");
+                let _ = writeln!(plist, "\t<dict>");
+                let _ = writeln!(plist, "\t\t<key>SuccessfulExit</key>");
+                let _ = writeln!(plist, "\t\t<false/>");
+                let _ = writeln!(plist, "\t</dict>");
+            }
+            Some(Restart::Never) | None => {}
+        }
+        if let Some(every) = policy.every {
+            key(&mut plist, "StartInterval");
+            let _ = writeln!(plist, "\t<integer>{every}</integer>");
+        }
+        if let Stdin::File(file) = &self.stdin {
+            key(&mut plist, "StandardInPath");
+            string(&mut plist, "\t", &file.display().to_string());
+        }
+        key(&mut plist, "StandardOutPath");
+        string(
+            &mut plist,
+            "\t",
+            &build_file_path(root_dir, &self.id, "stdout")
+                .display()
+                .to_string(),
+        );
+        key(&mut plist, "StandardErrorPath");
+        string(
+            &mut plist,
+            "\t",
+            &build_file_path(root_dir, &self.id, "stderr")
+                .display()
+                .to_string(),
+        );
+        for (name, limits) in [("SoftResourceLimits", soft), ("HardResourceLimits", hard)] {
+            if limits.is_empty() {
+                continue;
+            }
+            key(&mut plist, name);
+            let _ = writeln!(plist, "\t<dict>");
+            for (limit, value) in limits {
+                let _ = writeln!(plist, "\t\t<key>{limit}</key>");
+                let _ = writeln!(plist, "\t\t<integer>{value}</integer>");
+            }
+            let _ = writeln!(plist, "\t</dict>");
+        }
+        let _ = writeln!(plist, "</dict>");
+        let _ = writeln!(plist, "</plist>");
+        plist
+    }
+}
+
+/// Settings of the daemon's run that no service manager has a place for
+fn dropped(hooks: &crate::hooks::Hooks, pty: bool) -> Vec<&'static str> {
+    let mut dropped = Vec::new();
+    if !hooks.is_empty() {
+        dropped.push("hooks");
+    }
+    if pty {
+        dropped.push("--pty");
+    }
+    dropped
+}
+
+/// The environment of a running process without the session's variables
+fn environ(pid: u32) -> Option<Vec<(String, String)>> {
+    let environ = std::fs::read(format!("/proc/{pid}/environ")).ok()?;
+    let mut env: Vec<(String, String)> = environ
+        .split(|byte| *byte == 0)
+        .filter_map(|entry| {
+            let entry = String::from_utf8_lossy(entry);
+            let (key, value) = entry.split_once('=')?;
+            Some((key.to_string(), value.to_string()))
+        })
+        .filter(|(key, _)| {
+            !key.is_empty()
+                && !SESSION_VARS.contains(&key.as_str())
+                && !SESSION_PREFIXES
+                    .iter()
+                    .any(|prefix| key.starts_with(prefix))
+        })
+        .collect();
+    env.sort();
+    Some(env)
+}
+
+/// `%` and `$` are specifiers and variables in unit files
+fn escape(text: &str) -> String {
+    text.replace('%', "%%").replace('$', "$$")
+}
+
+/// An argument of `ExecStart=` or value of `Environment=`, quoted when it
+/// is not a plain word
+fn quote(arg: &str) -> String {
+    let plain = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "/._+,:=@-".contains(c));
+    if plain {
+        return escape(arg);
+    }
+    let mut quoted = String::from("\"");
+    for c in escape(arg).chars() {
+        match c {
+            '"' | '\\' => {
+                quoted.push('\\');
+                quoted.push(c);
+            }
+            '\n' => quoted.push_str("\\n"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Text of a property list
+fn xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// Export one daemon, or with `id` None every daemon of the manifest, to
+/// stdout or as files in `output`
+pub(crate) fn export(
+    root_dir: &Path,
+    id: Option<&str>,
+    target: &Target,
+    output: Option<&Path>,
+) -> Result<()> {
+    let sources = match id {
+        Some(id) => vec![Source::of_daemon(root_dir, id)?],
+        None => {
+            let manifest = Manifest::load(root_dir)?;
+            manifest
+                .services
+                .iter()
+                .map(|service| Source::of_service(root_dir, &manifest, service))
+                .collect()
+        }
+    };
+    if sources.is_empty() {
+        return Err(anyhow::anyhow!("demon.toml declares no daemons"));
+    }
+
+    for (index, source) in sources.iter().enumerate() {
+        let contents = source.render(root_dir, target);
+        match output {
+            Some(dir) => {
+                std::fs::create_dir_all(dir)
+                    .with_context(|| format!("Failed to create {}", dir.display()))?;
+                let path = dir.join(source.file_name(target));
+                std::fs::write(&path, contents)
+                    .with_context(|| format!("Failed to write {}", path.display()))?;
+                println!("Wrote {}", path.display());
+            }
+            None => {
+                if index > 0 {
+                    println!();
+                }
+                if sources.len() > 1 {
+                    println!("### {}", source.file_name(target));
+                }
+                print!("{contents}");
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,201 @@
+//! Following a growing file, as `tail -f` does.
+//!
+//! Every consumer owns its own [`Follower`] with its own read position, so any
+//! number of followers of the same file (several `tail -f`, `events -f`, `dev`)
+//! never interfere with each other. Nothing about a follower is stored on disk.
+//!
+//! Log files are not only appended to: `demon run` starts new ones when a
+//! daemon is restarted and external tools may rotate them. A follower notices both and
+//! starts a new epoch, reading the new contents from the beginning instead of
+//! waiting for the file to grow past the old position.
+//!
+//! A follower may read at most a batch of bytes per poll, so one that follows a
+//! log written faster than it is printed catches up in bounded steps instead of
+//! reading everything into memory at once.
+
+use crate::logs::tail_offset;
+use anyhow::Result;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::os::unix::fs::{FileExt, MetadataExt};
+use std::path::{Path, PathBuf};
+
+/// Number of leading bytes remembered to recognize a truncated and rewritten file
+const FINGERPRINT_LEN: usize = 64;
+
+/// Batch of followers that print what they read, which may fall behind a
+/// daemon writing quickly
+pub(crate) const BATCH: u64 = 1024 * 1024;
+
+/// New data returned by [`Follower::poll`]
+#[derive(Debug, Default)]
+pub(crate) struct Update {
+    /// The file was truncated or replaced since the previous poll, and `text`
+    /// starts at the beginning of the new contents
+    pub(crate) rotated: bool,
+    pub(crate) text: String,
+}
+
+#[derive(Debug)]
+pub(crate) struct Follower {
+    path: PathBuf,
+    /// Offset of the next byte to read in the current epoch
+    position: u64,
+    /// Device and inode of the file being read, to detect replacement
+    identity: Option<(u64, u64)>,
+    /// First bytes of the file, to detect truncation followed by new writes
+    fingerprint: Vec<u8>,
+    /// Incomplete UTF-8 sequence at the end of the previous read
+    pending: Vec<u8>,
+    /// Incomplete line kept by [`Follower::poll_lines`]
+    partial_line: String,
+    epoch: u64,
+    /// Most bytes read by one poll
+    batch: u64,
+    /// The last poll stopped at `batch` bytes, before the end of the file
+    behind: bool,
+}
+
+impl Follower {
+    /// Follow a file from its beginning. The file does not need to exist yet.
+    pub(crate) fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            position: 0,
+            identity: None,
+            fingerprint: Vec::new(),
+            pending: Vec::new(),
+            partial_line: String::new(),
+            epoch: 0,
+            batch: u64::MAX,
+            behind: false,
+        }
+    }
+
+    /// Read at most `bytes` per poll
+    pub(crate) fn with_batch(mut self, bytes: u64) -> Self {
+        self.batch = bytes;
+        self
+    }
+
+    /// Follow a file from the start of its last `lines` lines, but not before
+    /// the offset `not_before`, as `tail -n N -f` does, without reading what
+    /// comes before them
+    pub(crate) fn from_last_lines(
+        path: impl Into<PathBuf>,
+        lines: usize,
+        not_before: u64,
+    ) -> Result<Self> {
+        let mut follower = Self::new(path);
+        let mut file = match File::open(&follower.path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(follower),
+            Err(e) => return Err(e.into()),
+        };
+        let metadata = file.metadata()?;
+        follower.identity = Some((metadata.dev(), metadata.ino()));
+        follower.position = tail_offset(&mut file, lines)?.max(not_before);
+        // The bytes skipped still tell a truncated and rewritten file apart
+        let mut fingerprint = vec![0; FINGERPRINT_LEN.min(follower.position as usize)];
+        file.read_exact_at(&mut fingerprint, 0)?;
+        follower.fingerprint = fingerprint;
+        Ok(follower)
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Number of truncations or replacements seen so far
+    pub(crate) fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    /// Whether the last poll left data to read, so the next one should not
+    /// wait for the file to change
+    pub(crate) fn is_behind(&self) -> bool {
+        self.behind
+    }
+
+    /// Read everything written since the previous poll
+    pub(crate) fn poll(&mut self) -> Result<Update> {
+        let mut file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Update::default()),
+            Err(e) => return Err(e.into()),
+        };
+        let metadata = file.metadata()?;
+        let identity = (metadata.dev(), metadata.ino());
+
+        let replaced = self.identity.is_some_and(|known| known != identity);
+        let rotated =
+            replaced || metadata.len() < self.position || !self.fingerprint_matches(&file)?;
+        if rotated {
+            self.epoch += 1;
+            self.position = 0;
+            self.fingerprint.clear();
+            self.pending.clear();
+            self.partial_line.clear();
+        }
+        self.identity = Some(identity);
+
+        file.seek(SeekFrom::Start(self.position))?;
+        let mut bytes = std::mem::take(&mut self.pending);
+        let start = bytes.len();
+        let read = file.take(self.batch).read_to_end(&mut bytes)?;
+        self.behind = read as u64 == self.batch;
+        if (self.position as usize) < FINGERPRINT_LEN {
+            let wanted = FINGERPRINT_LEN - self.position as usize;
+            let new = &bytes[start..];
+            self.fingerprint
+                .extend_from_slice(&new[..wanted.min(new.len())]);
+        }
+        self.position += read as u64;
+
+        // Keep a multi-byte character split by the writer for the next poll
+        let complete = match std::str::from_utf8(&bytes) {
+            Err(e) if e.error_len().is_none() => e.valid_up_to(),
+            _ => bytes.len(),
+        };
+        self.pending = bytes.split_off(complete);
+
+        Ok(Update {
+            rotated,
+            text: String::from_utf8_lossy(&bytes).into_owned(),
+        })
+    }
+
+    /// Like [`Follower::poll`], but only return complete lines, without their
+    /// line terminators. A trailing partial line is kept for the next call.
+    pub(crate) fn poll_lines(&mut self) -> Result<(bool, Vec<String>)> {
+        let update = self.poll()?;
+        self.partial_line.push_str(&update.text);
+        // Split off the complete lines at once rather than one at a time, which
+        // would move the rest of a large read for every line
+        let Some(last) = self.partial_line.rfind('\n') else {
+            return Ok((update.rotated, Vec::new()));
+        };
+        let rest = self.partial_line.split_off(last + 1);
+        let lines = std::mem::replace(&mut self.partial_line, rest)
+            .lines()
+            .map(|line| line.trim_end_matches('\r').to_string())
+            .collect();
+        Ok((update.rotated, lines))
+    }
+
+    /// Whether the file still starts with the bytes read in this epoch
+    fn fingerprint_matches(&self, file: &File) -> Result<bool> {
+        if self.fingerprint.is_empty() {
+            return Ok(true);
+        }
+        let mut head = vec![0; self.fingerprint.len()];
+        let mut filled = 0;
+        while filled < head.len() {
+            match file.read_at(&mut head[filled..], filled as u64)? {
+                0 => return Ok(false),
+                n => filled += n,
+            }
+        }
+        Ok(head == self.fingerprint)
+    }
+}
@@ -0,0 +1,322 @@
+//! `demon gc`: a retention policy for the logs that pile up in a long-lived
+//! root directory.
+//!
+//! Three kinds of files are collected: the logs of daemons that are not
+//! running, the past runs kept in `runs/<id>/` (see [`crate::runs`]), each on
+//! its own, and the local copies of logs, made by `cp-logs` or shipped to a
+//! directory, that the history knows of. They are removed when
+//!
+//! - a daemon has more than `max_copies` copies, the oldest first
+//! - they are older than `max_age`: copies by when they were made, past runs
+//!   by when they ended, logs by when they were last written
+//! - all logs together, those of running daemons included, take more than
+//!   `max_total_bytes`: the oldest first until the rest fits
+//!
+//! The policy is the `[retention]` table of `demon.toml`, and flags override
+//! it. The logs of running daemons and copies uploaded to URLs are never
+//! removed, and the history keeps the records of removed copies.
+
+use crate::{
+    agent::parse_interval,
+    clean::remove_current_logs,
+    events, format_bytes, format_duration, format_timestamp, history, layout, manifest,
+    manifest::Manifest,
+    parse_bytes,
+    pidfile::{RootLock, build_file_path},
+    runs,
+    status::is_process_running,
+    timeline, unix_now,
+};
+use anyhow::Result;
+use clap::Args;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+/// How many logs `demon gc` keeps; in `demon.toml` ages are in seconds
+#[derive(Args, Debug, Clone, Copy, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct Retention {
+    /// Remove the oldest logs while all logs together take more than this,
+    /// e.g. 2G [manifest: retention.max_total_bytes]
+    #[arg(long, value_name = "BYTES", value_parser = parse_bytes)]
+    pub(crate) max_total_bytes: Option<u64>,
+
+    /// Remove logs older than this, e.g. 14d [manifest: retention.max_age]
+    #[arg(long, value_name = "INTERVAL", value_parser = parse_interval)]
+    pub(crate) max_age: Option<u64>,
+
+    /// Keep at most this many copies of the logs of each daemon
+    /// [manifest: retention.max_copies]
+    #[arg(long, value_name = "COUNT")]
+    pub(crate) max_copies: Option<usize>,
+}
+
+impl Retention {
+    /// This policy, with the limits it leaves unset taken from `other`
+    pub(crate) fn or(self, other: Retention) -> Retention {
+        Retention {
+            max_total_bytes: self.max_total_bytes.or(other.max_total_bytes),
+            max_age: self.max_age.or(other.max_age),
+            max_copies: self.max_copies.or(other.max_copies),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.max_total_bytes.is_none() && self.max_age.is_none() && self.max_copies.is_none()
+    }
+}
+
+/// Files `gc` may remove together
+#[derive(Debug)]
+enum Kind {
+    /// The logs of a daemon that is not running
+    Logs,
+    /// A kept past run of a daemon, by its directory
+    Run(PathBuf),
+    /// A copy of the logs of a daemon
+    Copy(Vec<PathBuf>),
+}
+
+#[derive(Debug)]
+struct Candidate {
+    id: String,
+    kind: Kind,
+    bytes: u64,
+    /// Unix time the logs were last written, the run ended or the copy was
+    /// made
+    time: u64,
+}
+
+impl Candidate {
+    fn describe(&self) -> String {
+        match &self.kind {
+            Kind::Logs => format!("logs of '{}'", self.id),
+            Kind::Run(_) => format!(
+                "past run of '{}' ended {}",
+                self.id,
+                format_timestamp(self.time)
+            ),
+            Kind::Copy(_) => format!(
+                "copy of the logs of '{}' from {}",
+                self.id,
+                format_timestamp(self.time)
+            ),
+        }
+    }
+}
+
+/// The policy of the manifest, if the root directory has one, with `flags`
+/// taking precedence
+pub(crate) fn policy(root_dir: &Path, flags: Retention) -> Result<Retention> {
+    if !manifest::path(root_dir).is_file() {
+        return Ok(flags);
+    }
+    Ok(flags.or(Manifest::load(root_dir)?.retention))
+}
+
+/// Remove the logs and copies `retention` does not keep, or only print them
+/// with `dry_run`
+pub(crate) fn collect_garbage(root_dir: &Path, retention: &Retention, dry_run: bool) -> Result<()> {
+    if retention.is_empty() {
+        return Err(anyhow::anyhow!(
+            "No retention policy: set max_total_bytes, max_age or max_copies in the [retention] table of {}, or pass them as flags",
+            manifest::FILE_NAME
+        ));
+    }
+    // No daemon can start, and have its logs removed, while we decide
+    let _lock = if dry_run {
+        RootLock::shared(root_dir)?
+    } else {
+        RootLock::exclusive(root_dir)?
+    };
+
+    let (mut candidates, kept) = candidates(root_dir)?;
+    let mut removed: Vec<(Candidate, String)> = Vec::new();
+    let mut take = |candidates: &mut Vec<Candidate>, index: usize, reason: String| {
+        removed.push((candidates.remove(index), reason));
+    };
+
+    if let Some(max_copies) = retention.max_copies {
+        let mut copies: HashMap<String, usize> = HashMap::new();
+        // Newest first, so the newest copies are the ones counted as kept;
+        // copies made in the same second keep the order of the history
+        candidates.reverse();
+        candidates.sort_by_key(|candidate| std::cmp::Reverse(candidate.time));
+        let mut index = 0;
+        while index < candidates.len() {
+            let candidate = &candidates[index];
+            if matches!(candidate.kind, Kind::Copy(_)) {
+                let count = copies.entry(candidate.id.clone()).or_default();
+                *count += 1;
+                if *count > max_copies {
+                    take(
+                        &mut candidates,
+                        index,
+                        format!("more than {max_copies} copies"),
+                    );
+                    continue;
+                }
+            }
+            index += 1;
+        }
+    }
+    if let Some(max_age) = retention.max_age {
+        let cutoff = unix_now().saturating_sub(max_age);
+        let mut index = 0;
+        while index < candidates.len() {
+            if candidates[index].time < cutoff {
+                let age = format_duration(Duration::from_secs(max_age));
+                take(&mut candidates, index, format!("older than {age}"));
+            } else {
+                index += 1;
+            }
+        }
+    }
+    if let Some(max_total) = retention.max_total_bytes {
+        candidates.sort_by_key(|candidate| candidate.time);
+        let mut total = kept + candidates.iter().map(|c| c.bytes).sum::<u64>();
+        while total > max_total && !candidates.is_empty() {
+            total -= candidates[0].bytes;
+            take(
+                &mut candidates,
+                0,
+                format!("over {} in total", format_bytes(max_total)),
+            );
+        }
+    }
+
+    let verb = if dry_run { "Would remove" } else { "Removed" };
+    let mut freed = 0;
+    for (candidate, reason) in &removed {
+        if !dry_run {
+            remove(root_dir, candidate);
+        }
+        freed += candidate.bytes;
+        println!(
+            "{verb} {} ({}, {})",
+            candidate.describe(),
+            format_bytes(candidate.bytes),
+            reason
+        );
+    }
+    let left = kept + candidates.iter().map(|c| c.bytes).sum::<u64>();
+    match (removed.len(), dry_run) {
+        (0, _) => println!("Nothing to remove; logs take {}", format_bytes(left)),
+        (_, true) => println!(
+            "Would free {}; logs would take {}",
+            format_bytes(freed),
+            format_bytes(left)
+        ),
+        (_, false) => println!(
+            "Freed {}; logs take {}",
+            format_bytes(freed),
+            format_bytes(left)
+        ),
+    }
+    Ok(())
+}
+
+/// What `gc` may remove, and the bytes of logs it must keep: those of
+/// running daemons
+fn candidates(root_dir: &Path) -> Result<(Vec<Candidate>, u64)> {
+    let ids = layout::ids_with(root_dir, &["stdout", "stderr"])?;
+
+    // Past runs first, so they go before the logs of the run after them
+    // when both were written in the same second
+    let mut candidates = Vec::new();
+    for run in runs::archived(root_dir)? {
+        let time = match run.time {
+            Some(time) => time,
+            None => run.dir.metadata().map_or(0, |metadata| modified(&metadata)),
+        };
+        candidates.push(Candidate {
+            id: run.id,
+            kind: Kind::Run(run.dir),
+            bytes: run.bytes,
+            time,
+        });
+    }
+
+    let mut kept = 0;
+    for id in ids {
+        let mut bytes = 0;
+        let mut time = 0;
+        for extension in ["stdout", "stderr", timeline::EXTENSION] {
+            if let Ok(metadata) = build_file_path(root_dir, &id, extension).metadata() {
+                bytes += metadata.len();
+                time = time.max(modified(&metadata));
+            }
+        }
+        if is_process_running(root_dir, &id)? {
+            kept += bytes;
+        } else {
+            candidates.push(Candidate {
+                id,
+                kind: Kind::Logs,
+                bytes,
+                time,
+            });
+        }
+    }
+
+    for copy in history::read_all_copies(root_dir)? {
+        // Only what is still on this machine
+        let files: Vec<PathBuf> = copy
+            .files
+            .iter()
+            .map(|file| PathBuf::from(&file.location))
+            .filter(|path| path.is_absolute() && path.is_file())
+            .collect();
+        if files.is_empty() {
+            continue;
+        }
+        candidates.push(Candidate {
+            id: copy.id,
+            bytes: files
+                .iter()
+                .filter_map(|path| path.metadata().ok())
+                .map(|metadata| metadata.len())
+                .sum(),
+            kind: Kind::Copy(files),
+            time: copy.copied_at,
+        });
+    }
+    Ok((candidates, kept))
+}
+
+fn modified(metadata: &std::fs::Metadata) -> u64 {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |since| since.as_secs())
+}
+
+fn remove(root_dir: &Path, candidate: &Candidate) {
+    match &candidate.kind {
+        Kind::Logs => {
+            remove_current_logs(root_dir, &candidate.id);
+            events::emit(
+                root_dir,
+                &candidate.id,
+                events::Kind::Cleaned,
+                None,
+                Some("logs removed by gc".to_string()),
+            );
+        }
+        Kind::Run(dir) => match runs::remove_run(root_dir, &candidate.id, dir) {
+            Ok(()) => tracing::info!("Removed {}", dir.display()),
+            Err(e) => tracing::warn!("{:#}", e),
+        },
+        Kind::Copy(files) => {
+            for file in files {
+                match std::fs::remove_file(file) {
+                    Ok(()) => tracing::info!("Removed {}", file.display()),
+                    Err(e) => tracing::warn!("Failed to remove {}: {}", file.display(), e),
+                }
+            }
+        }
+    }
+}
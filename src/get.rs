@@ -0,0 +1,91 @@
+//! `demon get <id> <field>`: one value of a daemon for shell scripts.
+//!
+//! The value is printed alone on one line, and only when it exists, so
+//! `PID=$(demon get api pid)` works under `set -euo pipefail`: when there is
+//! no value the command prints nothing on stdout, says why on stderr and
+//! exits with a code telling what is missing (see [`exit`]).
+
+use crate::{
+    exit::{self, Exit},
+    pidfile::build_file_path,
+    proc::ProcStat,
+    status::DaemonFiles,
+};
+use anyhow::Result;
+use clap::ValueEnum;
+use std::path::Path;
+
+/// Values `get` prints
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub(crate) enum Field {
+    /// PID of the running daemon
+    Pid,
+    /// RUNNING, FAILED, COMPLETED, DEAD or ERROR, as in `list`
+    Status,
+    /// Seconds the running daemon has been up
+    Uptime,
+    /// Exit code of the daemon once it exited, 128 + the signal when killed
+    /// by one, like shells report it
+    ExitCode,
+    /// Absolute path of the stdout log
+    StdoutPath,
+    /// Absolute path of the stderr log
+    StderrPath,
+}
+
+/// The value of `field` for daemon `id`, or the [`Exit`] telling why there is
+/// none
+pub(crate) fn get(root_dir: &Path, id: &str, field: Field) -> Result<String> {
+    let extension = match field {
+        Field::StdoutPath => "stdout",
+        Field::StderrPath => "stderr",
+        _ => return daemon_value(root_dir, id, field),
+    };
+    let path = build_file_path(&std::path::absolute(root_dir)?, id, extension);
+    if !path.exists() {
+        return Err(Exit::new(exit::NOT_FOUND, format!("No {extension} log for '{id}'")).into());
+    }
+    Ok(path.display().to_string())
+}
+
+/// A value read from the state files of the daemon
+fn daemon_value(root_dir: &Path, id: &str, field: Field) -> Result<String> {
+    let Some(files) = DaemonFiles::read(root_dir, id)? else {
+        return Err(Exit::new(exit::NOT_FOUND, format!("No daemon '{id}'")).into());
+    };
+    if field == Field::Status {
+        return Ok(files.state(root_dir).to_string());
+    }
+    let data = files
+        .pid_file
+        .as_ref()
+        .map_err(|e| anyhow::anyhow!("Cannot read PID file for '{}': {}", id, e))?;
+    let running = files.running(root_dir);
+    let meta = &files.meta;
+    let value = match field {
+        Field::Pid if running => data.pid.to_string(),
+        Field::Uptime if running => meta
+            .uptime()
+            .or_else(|| ProcStat::read(data.pid).and_then(|stat| stat.uptime()))
+            .map_or(0, |uptime| uptime.as_secs())
+            .to_string(),
+        Field::ExitCode if running => {
+            let message = format!("Daemon '{id}' is still running");
+            return Err(Exit::new(exit::NO_VALUE, message).into());
+        }
+        Field::ExitCode => match (meta.exit_code, meta.exit_signal) {
+            (Some(code), _) => code.to_string(),
+            (None, Some(signal)) => (128 + signal).to_string(),
+            (None, None) => {
+                let message =
+                    format!("The exit status of '{id}' is unknown; it was not supervised");
+                return Err(Exit::new(exit::NO_VALUE, message).into());
+            }
+        },
+        _ => {
+            let message = format!("Daemon '{id}' is not running");
+            return Err(Exit::new(exit::NOT_RUNNING, message).into());
+        }
+    };
+    Ok(value)
+}
@@ -0,0 +1,193 @@
+//! `demon grep`: search the logs of one or every daemon.
+//!
+//! Logs are read line by line, so searching a log of several gigabytes uses
+//! as little memory as `grep` itself, unlike `demon cat | grep`. Lines are
+//! matched as bytes, so output that is not UTF-8 is searched and printed as
+//! it is. The output follows GNU grep: when more than one log is searched
+//! every line starts with the log's file name, context lines use `-` instead
+//! of `:` after the prefix, and `--` separates groups of lines that are not
+//! adjacent.
+
+use crate::{ansi, color, layout, logs::Stream, pidfile::build_file_path};
+use anyhow::{Context, Result};
+use regex::bytes::{Regex, RegexBuilder};
+use std::collections::VecDeque;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+/// What `grep` searches for and how it prints what it finds
+pub(crate) struct GrepOptions {
+    pub(crate) pattern: String,
+    pub(crate) ignore_case: bool,
+    /// Lines of context to print before every match
+    pub(crate) before: usize,
+    /// Lines of context to print after every match
+    pub(crate) after: usize,
+    pub(crate) line_numbers: bool,
+    pub(crate) show_stdout: bool,
+    pub(crate) show_stderr: bool,
+    /// Highlight matches in red
+    pub(crate) color: bool,
+    /// Remove escape sequences before searching
+    pub(crate) strip_ansi: bool,
+}
+
+/// IDs of every daemon with a log in the root directory, sorted
+pub(crate) fn daemons_with_logs(root_dir: &Path) -> Result<Vec<String>> {
+    layout::ids_with(
+        root_dir,
+        &[Stream::Stdout.extension(), Stream::Stderr.extension()],
+    )
+}
+
+/// Search the logs of `ids`, printing the matching lines. Returns whether any
+/// line matched.
+pub(crate) fn grep_logs(ids: &[String], root_dir: &Path, options: &GrepOptions) -> Result<bool> {
+    let regex = RegexBuilder::new(&options.pattern)
+        .case_insensitive(options.ignore_case)
+        .build()
+        .map_err(|e| anyhow::anyhow!("Invalid pattern: {}", e))?;
+    let paths: Vec<PathBuf> = ids
+        .iter()
+        .flat_map(|id| {
+            [
+                (options.show_stdout, Stream::Stdout),
+                (options.show_stderr, Stream::Stderr),
+            ]
+            .into_iter()
+            .filter(|(shown, _)| *shown)
+            .map(|(_, stream)| build_file_path(root_dir, id, stream.extension()))
+        })
+        .collect();
+
+    let stdout = std::io::stdout();
+    let mut searcher = Searcher {
+        regex,
+        options,
+        out: std::io::BufWriter::new(stdout.lock()),
+        printed_any: false,
+    };
+    let mut matched = false;
+    for path in &paths {
+        if !path.exists() {
+            // Only complain about the logs asked for by name
+            if ids.len() == 1 {
+                tracing::warn!("Could not read {}", path.display());
+            }
+            continue;
+        }
+        let name = file_name(path);
+        let prefix = (paths.len() > 1).then_some(name.as_str());
+        matched |= searcher
+            .search(path, prefix)
+            .with_context(|| format!("Failed to search {}", path.display()))?;
+    }
+    searcher.out.flush()?;
+    Ok(matched)
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+struct Searcher<'a, W: Write> {
+    regex: Regex,
+    options: &'a GrepOptions,
+    out: W,
+    /// Whether a line was printed yet, to separate groups with `--`
+    printed_any: bool,
+}
+
+impl<W: Write> Searcher<'_, W> {
+    /// Search one log, prefixing lines with `prefix`. Returns whether any line
+    /// matched.
+    fn search(&mut self, path: &Path, prefix: Option<&str>) -> Result<bool> {
+        let mut reader = BufReader::new(std::fs::File::open(path)?);
+        let mut before: VecDeque<(u64, Vec<u8>)> = VecDeque::with_capacity(self.options.before);
+        // Number of the last line printed, to tell whether the next one
+        // continues the same group
+        let mut last_printed: Option<u64> = None;
+        let mut after_left = 0;
+        let mut matched = false;
+        let mut line = Vec::new();
+        let mut number = 0;
+        loop {
+            line.clear();
+            if reader.read_until(b'\n', &mut line)? == 0 {
+                break;
+            }
+            number += 1;
+            if line.last() == Some(&b'\n') {
+                line.pop();
+            }
+            if self.options.strip_ansi {
+                line = ansi::strip(&line);
+            }
+            if self.regex.is_match(&line) {
+                matched = true;
+                let first = before.front().map_or(number, |(number, _)| *number);
+                self.separate(last_printed, first)?;
+                for (number, context) in before.drain(..) {
+                    self.print(prefix, number, &context, false)?;
+                }
+                self.print(prefix, number, &line, true)?;
+                last_printed = Some(number);
+                after_left = self.options.after;
+            } else if after_left > 0 {
+                self.print(prefix, number, &line, false)?;
+                last_printed = Some(number);
+                after_left -= 1;
+            } else if self.options.before > 0 {
+                if before.len() == self.options.before {
+                    before.pop_front();
+                }
+                before.push_back((number, line.clone()));
+            }
+        }
+        Ok(matched)
+    }
+
+    /// Print `--` before a group of lines starting at `first` that does not
+    /// follow the last one printed
+    fn separate(&mut self, last_printed: Option<u64>, first: u64) -> Result<()> {
+        let context = self.options.before > 0 || self.options.after > 0;
+        let adjacent = last_printed.is_some_and(|last| last + 1 == first);
+        if context && self.printed_any && !adjacent {
+            writeln!(self.out, "--")?;
+        }
+        Ok(())
+    }
+
+    fn print(
+        &mut self,
+        prefix: Option<&str>,
+        number: u64,
+        line: &[u8],
+        matched: bool,
+    ) -> Result<()> {
+        let separator = if matched { ":" } else { "-" };
+        if let Some(prefix) = prefix {
+            write!(self.out, "{prefix}{separator}")?;
+        }
+        if self.options.line_numbers {
+            write!(self.out, "{number}{separator}")?;
+        }
+        if matched && self.options.color {
+            let mut end = 0;
+            for found in self.regex.find_iter(line).filter(|found| !found.is_empty()) {
+                self.out.write_all(&line[end..found.start()])?;
+                let text = String::from_utf8_lossy(found.as_bytes());
+                write!(self.out, "{}", color::paint(true, &text, color::RED))?;
+                end = found.end();
+            }
+            self.out.write_all(&line[end..])?;
+        } else {
+            self.out.write_all(line)?;
+        }
+        self.out.write_all(b"\n")?;
+        self.printed_any = true;
+        Ok(())
+    }
+}
@@ -0,0 +1,119 @@
+//! How fast the logs of a daemon grow, shown by `status` to spot a runaway
+//! logger before it fills the disk.
+//!
+//! The sizes of both logs are sampled into `<id>.logsizes`: every
+//! [`SAMPLE_INTERVAL`] by the supervisor, and whenever `status` looks at a
+//! running daemon, so `status --watch` measures unsupervised daemons too. The
+//! rate is the growth since the oldest sample of the last [`WINDOW`]. Until
+//! there is one, it is the average since the daemon started, as every run
+//! starts with empty logs.
+
+use crate::{pidfile::build_file_path, unix_now};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::time::Duration;
+
+/// How often the supervisor samples the log sizes
+pub(crate) const SAMPLE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How far back the rate looks
+const WINDOW: Duration = Duration::from_secs(300);
+
+/// Shortest time a rate is measured over, and between two samples
+const MIN_SPAN: Duration = Duration::from_secs(10);
+
+/// Extension of the file with the samples
+pub(crate) const EXTENSION: &str = "logsizes";
+
+/// Sizes of the logs at a point in time
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct Sample {
+    ts: u64,
+    stdout: u64,
+    stderr: u64,
+}
+
+impl Sample {
+    fn now(root_dir: &Path, id: &str) -> Self {
+        let size = |extension: &str| {
+            std::fs::metadata(build_file_path(root_dir, id, extension))
+                .map(|metadata| metadata.len())
+                .unwrap_or(0)
+        };
+        Self {
+            ts: unix_now(),
+            stdout: size("stdout"),
+            stderr: size("stderr"),
+        }
+    }
+
+    /// Whether `later` can have grown from this sample, i.e. the logs were
+    /// not truncated for a new run in between
+    fn precedes(&self, later: &Sample) -> bool {
+        self.ts <= later.ts && self.stdout <= later.stdout && self.stderr <= later.stderr
+    }
+}
+
+/// Growth of both logs in bytes per minute
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Rate {
+    pub(crate) stdout: f64,
+    pub(crate) stderr: f64,
+    /// How long the growth was measured over
+    pub(crate) over: Duration,
+}
+
+/// Sample the log sizes, keeping the samples of the last [`WINDOW`] from the
+/// current run. Returns them, oldest first, ending with the current sizes.
+fn record(root_dir: &Path, id: &str) -> Vec<Sample> {
+    let path = build_file_path(root_dir, id, EXTENSION);
+    let now = Sample::now(root_dir, id);
+    let mut samples: Vec<Sample> = std::fs::read_to_string(&path)
+        .ok()
+        .and_then(|contents| serde_json::from_str(&contents).ok())
+        .unwrap_or_default();
+    samples.retain(|sample| sample.precedes(&now) && now.ts - sample.ts <= WINDOW.as_secs());
+    let due = samples
+        .last()
+        .is_none_or(|last| now.ts - last.ts >= MIN_SPAN.as_secs());
+    samples.push(now);
+    if due {
+        let tmp = build_file_path(root_dir, id, &format!("{EXTENSION}.tmp"));
+        let written = serde_json::to_string(&samples)
+            .map_err(std::io::Error::from)
+            .and_then(|json| std::fs::write(&tmp, json))
+            .and_then(|()| std::fs::rename(&tmp, &path));
+        if let Err(e) = written {
+            tracing::debug!("Failed to record log sizes of '{}': {}", id, e);
+        }
+    }
+    samples
+}
+
+/// Sample the log sizes of a daemon, for the supervisor
+pub(crate) fn sample(root_dir: &Path, id: &str) {
+    record(root_dir, id);
+}
+
+/// Sample the log sizes of a running daemon and return how fast they grow,
+/// or None while that cannot be told yet
+pub(crate) fn rate(root_dir: &Path, id: &str, started_at: Option<u64>) -> Option<Rate> {
+    let samples = record(root_dir, id);
+    let now = *samples.last()?;
+    let start = started_at.map(|ts| Sample {
+        ts,
+        stdout: 0,
+        stderr: 0,
+    });
+    let since = samples
+        .iter()
+        .copied()
+        .find(|sample| now.ts - sample.ts >= MIN_SPAN.as_secs())
+        .or(start.filter(|start| start.precedes(&now) && now.ts - start.ts >= MIN_SPAN.as_secs()))?;
+    let minutes = (now.ts - since.ts) as f64 / 60.0;
+    Some(Rate {
+        stdout: (now.stdout - since.stdout) as f64 / minutes,
+        stderr: (now.stderr - since.stderr) as f64 / minutes,
+        over: Duration::from_secs(now.ts - since.ts),
+    })
+}
@@ -0,0 +1,490 @@
+//! Persistent run history, kept in `<root>/history.jsonl`.
+//!
+//! One JSON record is appended per finished run. The file is never touched by
+//! `demon clean`, so runs stay inspectable after their logs are gone.
+//! Supervised runs are recorded by their supervisor, which knows the exact exit
+//! status and peak RSS; unsupervised runs are recorded by whichever command
+//! retires their PID file (`stop`, `clean` or a new `run` with the same ID).
+//!
+//! Copies of the logs made by `demon cp-logs` are recorded in the same file,
+//! with the SHA-256 of every copied file, so a copy can be verified later.
+//!
+//! Every append also applies the retention settings (`history.keep_runs` per
+//! daemon and `history.keep_days`), rewriting the file without the oldest
+//! records, so it stays bounded for daemons that run over and over. Appending
+//! and pruning both hold a lock on the file itself, as its writers may already
+//! hold the root lock.
+
+use crate::{
+    config::Config,
+    format_bytes, format_duration, format_timestamp,
+    pidfile::{DaemonMeta, PidFile, PidFileReadError},
+    status::is_daemon_running,
+    unix_now,
+};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// One finished run of a daemon
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct RunRecord {
+    pub(crate) id: String,
+    pub(crate) command: Vec<String>,
+    pub(crate) pid: u32,
+    /// Unix time the run started
+    pub(crate) started_at: Option<u64>,
+    /// Unix time the run ended, if known
+    pub(crate) ended_at: Option<u64>,
+    pub(crate) exit_code: Option<i32>,
+    pub(crate) exit_signal: Option<i32>,
+    /// Whether the run was terminated for exceeding `--max-runtime`
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub(crate) timed_out: bool,
+    /// Peak resident set size in bytes
+    pub(crate) peak_rss: Option<u64>,
+    /// Exact wall-clock duration in milliseconds, for supervised runs
+    pub(crate) wall_ms: Option<u64>,
+    /// User plus system CPU time in milliseconds, for supervised runs
+    pub(crate) cpu_ms: Option<u64>,
+}
+
+impl RunRecord {
+    /// Exit code or signal, if known, or TIMEOUT
+    pub(crate) fn exit(&self) -> Option<String> {
+        if self.timed_out {
+            return Some("TIMEOUT".to_string());
+        }
+        match (self.exit_code, self.exit_signal) {
+            (Some(code), _) => Some(code.to_string()),
+            (None, Some(signal)) => Some(format!("signal {signal}")),
+            (None, None) => None,
+        }
+    }
+
+    fn duration(&self) -> Option<u64> {
+        Some(self.ended_at?.saturating_sub(self.started_at?))
+    }
+}
+
+/// A copy of a daemon's logs made by `demon cp-logs`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct LogCopy {
+    pub(crate) id: String,
+    /// Unix time the snapshot was taken
+    pub(crate) copied_at: u64,
+    pub(crate) files: Vec<CopiedFile>,
+}
+
+/// One log file of a [`LogCopy`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct CopiedFile {
+    /// `stdout` or `stderr`
+    pub(crate) stream: String,
+    /// Path or URL of the copy
+    pub(crate) location: String,
+    pub(crate) bytes: u64,
+    /// Hex encoded SHA-256 of the copied bytes
+    pub(crate) sha256: String,
+}
+
+/// A line of the history file
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Line {
+    Run(RunRecord),
+    Copy(LogCopy),
+}
+
+fn history_path(root_dir: &Path) -> PathBuf {
+    root_dir.join("history.jsonl")
+}
+
+/// How many records the history keeps, from the `history.*` settings
+#[derive(Debug, Clone, Copy)]
+struct Retention {
+    /// Finished runs, and separately log copies, kept per daemon (0 = all)
+    keep_runs: u64,
+    /// Days records are kept for (0 = forever)
+    keep_days: u64,
+}
+
+impl Retention {
+    fn from_config(config: &Config) -> Self {
+        Self {
+            keep_runs: config.integer("history.keep_runs"),
+            keep_days: config.integer("history.keep_days"),
+        }
+    }
+
+    fn is_unlimited(&self) -> bool {
+        self.keep_runs == 0 && self.keep_days == 0
+    }
+}
+
+/// Open the history file for appending, locked. A pruner may replace the
+/// file while we wait for the lock, in which case the new one is opened.
+fn open_locked(path: &Path) -> std::io::Result<File> {
+    loop {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        file.lock()?;
+        match std::fs::metadata(path) {
+            Ok(current) if current.ino() == file.metadata()?.ino() => return Ok(file),
+            Ok(_) => continue,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Append a record to the history file, then drop the records the retention
+/// settings no longer keep
+pub(crate) fn append(root_dir: &Path, record: &impl Serialize) -> Result<()> {
+    let path = history_path(root_dir);
+    let mut line = serde_json::to_string(record)?;
+    line.push('\n');
+    // A single append-mode write keeps lines intact even with concurrent writers
+    // Held until pruning is done
+    let _file = open_locked(&path)
+        .and_then(|mut file| file.write_all(line.as_bytes()).map(|()| file))
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+
+    let retention = match Config::load(root_dir) {
+        Ok(config) => Retention::from_config(&config),
+        Err(e) => {
+            tracing::warn!("Not pruning the run history: {}", e);
+            return Ok(());
+        }
+    };
+    if let Err(e) = prune(&path, retention) {
+        tracing::warn!("Failed to prune {}: {:#}", path.display(), e);
+    }
+    Ok(())
+}
+
+/// Rewrite the locked history file without the records `retention` does not
+/// keep, if there are any. Lines that cannot be parsed are left alone.
+fn prune(path: &Path, retention: Retention) -> Result<()> {
+    if retention.is_unlimited() {
+        return Ok(());
+    }
+    let contents = std::fs::read_to_string(path)?;
+    let lines: Vec<&str> = contents.lines().collect();
+    let cutoff = match retention.keep_days {
+        0 => None,
+        days => Some(unix_now().saturating_sub(days * 24 * 60 * 60)),
+    };
+
+    // Walk newest first so the count keeps the latest records of each daemon
+    let mut runs: HashMap<String, u64> = HashMap::new();
+    let mut copies: HashMap<String, u64> = HashMap::new();
+    let mut keep = vec![true; lines.len()];
+    for (index, line) in lines.iter().enumerate().rev() {
+        let (seen, id, time) = match serde_json::from_str::<Line>(line) {
+            Ok(Line::Run(record)) => (&mut runs, record.id, record.ended_at.or(record.started_at)),
+            Ok(Line::Copy(copy)) => (&mut copies, copy.id, Some(copy.copied_at)),
+            Err(_) => continue,
+        };
+        let count = seen.entry(id).or_default();
+        *count += 1;
+        let too_many = retention.keep_runs != 0 && *count > retention.keep_runs;
+        let too_old = matches!((cutoff, time), (Some(cutoff), Some(time)) if time < cutoff);
+        keep[index] = !too_many && !too_old;
+    }
+
+    let pruned = keep.iter().filter(|kept| !**kept).count();
+    if pruned == 0 {
+        return Ok(());
+    }
+    let mut kept = String::new();
+    for (line, _) in lines.iter().zip(&keep).filter(|(_, kept)| **kept) {
+        kept.push_str(line);
+        kept.push('\n');
+    }
+    // Replace the file in one step so readers never see it half written
+    let tmp = path.with_extension("jsonl.tmp");
+    std::fs::write(&tmp, kept).with_context(|| format!("Failed to write {}", tmp.display()))?;
+    std::fs::rename(&tmp, path).with_context(|| format!("Failed to replace {}", path.display()))?;
+    tracing::debug!("Pruned {} records from {}", pruned, path.display());
+    Ok(())
+}
+
+/// Record the end of an unsupervised run whose PID file is about to be removed
+/// or replaced. Supervised runs are skipped, their supervisor records them.
+pub(crate) fn record_retired(
+    root_dir: &Path,
+    id: &str,
+    data: &PidFile,
+    ended_at: Option<u64>,
+    peak_rss: Option<u64>,
+) {
+    let meta = DaemonMeta::read(root_dir, id);
+    if meta.supervised {
+        return;
+    }
+    let record = RunRecord {
+        id: id.to_string(),
+        command: data.command.clone(),
+        pid: data.pid,
+        started_at: meta.started_at,
+        ended_at,
+        exit_code: None,
+        exit_signal: None,
+        timed_out: false,
+        peak_rss,
+        wall_ms: None,
+        cpu_ms: None,
+    };
+    if let Err(e) = append(root_dir, &record) {
+        tracing::warn!("Failed to record run history for '{}': {}", id, e);
+    }
+}
+
+/// Read all lines of the history file, oldest first
+fn read_lines(root_dir: &Path) -> Result<Vec<Line>> {
+    let path = history_path(root_dir);
+    let contents = match std::fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    };
+
+    let mut lines = Vec::new();
+    for (number, line) in contents.lines().enumerate() {
+        match serde_json::from_str::<Line>(line) {
+            Ok(line) => lines.push(line),
+            Err(e) => tracing::warn!("Skipping invalid history line {}: {}", number + 1, e),
+        }
+    }
+    Ok(lines)
+}
+
+/// Read all run records of a daemon, oldest first
+pub(crate) fn read(root_dir: &Path, id: &str) -> Result<Vec<RunRecord>> {
+    Ok(read_lines(root_dir)?
+        .into_iter()
+        .filter_map(|line| match line {
+            Line::Run(record) if record.id == id => Some(record),
+            _ => None,
+        })
+        .collect())
+}
+
+/// Read all log copies of a daemon, oldest first
+pub(crate) fn read_copies(root_dir: &Path, id: &str) -> Result<Vec<LogCopy>> {
+    Ok(read_all_copies(root_dir)?
+        .into_iter()
+        .filter(|copy| copy.id == id)
+        .collect())
+}
+
+/// Read the log copies of every daemon, oldest first
+pub(crate) fn read_all_copies(root_dir: &Path) -> Result<Vec<LogCopy>> {
+    Ok(read_lines(root_dir)?
+        .into_iter()
+        .filter_map(|line| match line {
+            Line::Copy(copy) => Some(copy),
+            _ => None,
+        })
+        .collect())
+}
+
+pub(crate) fn print_history(id: &str, limit: usize, root_dir: &Path) -> Result<()> {
+    let records = read(root_dir, id)?;
+    let copies = read_copies(root_dir, id)?;
+
+    // The current run has no record yet, show it on top of the finished ones
+    let current = match PidFile::read(root_dir, id) {
+        Ok(data) if is_daemon_running(root_dir, id, data.pid) => Some(data),
+        Ok(_) | Err(PidFileReadError::FileNotFound) | Err(PidFileReadError::FileInvalid(_)) => None,
+        Err(PidFileReadError::IoError(e)) => return Err(e.into()),
+    };
+
+    if records.is_empty() && current.is_none() && copies.is_empty() {
+        println!("No recorded runs for '{id}'");
+        return Ok(());
+    }
+
+    println!(
+        "{:<20} {:<10} {:<8} {:<10} {:<8} COMMAND",
+        "STARTED", "DURATION", "PID", "EXIT", "PEAK RSS"
+    );
+    println!("{}", "-".repeat(70));
+
+    if let Some(data) = &current {
+        let started_at = DaemonMeta::read(root_dir, id).started_at;
+        println!(
+            "{:<20} {:<10} {:<8} {:<10} {:<8} {}",
+            started_at
+                .map(format_timestamp)
+                .unwrap_or_else(|| "-".into()),
+            started_at
+                .map(|start| format_duration(Duration::from_secs(unix_now().saturating_sub(start))))
+                .unwrap_or_else(|| "-".into()),
+            data.pid,
+            "running",
+            "-",
+            data.command_string()
+        );
+    }
+
+    let skip = records.len().saturating_sub(limit);
+    for record in records.iter().skip(skip).rev() {
+        let duration = match (record.started_at, record.ended_at) {
+            (Some(start), Some(end)) => {
+                format_duration(Duration::from_secs(end.saturating_sub(start)))
+            }
+            _ => "-".to_string(),
+        };
+        let exit = record.exit().unwrap_or_else(|| "-".to_string());
+        println!(
+            "{:<20} {:<10} {:<8} {:<10} {:<8} {}",
+            record
+                .started_at
+                .map(format_timestamp)
+                .unwrap_or_else(|| "-".into()),
+            duration,
+            record.pid,
+            exit,
+            record
+                .peak_rss
+                .map(format_bytes)
+                .unwrap_or_else(|| "-".into()),
+            record.command.join(" ")
+        );
+    }
+
+    if !copies.is_empty() {
+        println!();
+        println!(
+            "{:<20} {:<7} {:<8} {:<64} LOCATION",
+            "LOGS COPIED", "STREAM", "SIZE", "SHA-256"
+        );
+        println!("{}", "-".repeat(110));
+        let skip = copies.len().saturating_sub(limit);
+        for copy in copies.iter().skip(skip).rev() {
+            for file in &copy.files {
+                println!(
+                    "{:<20} {:<7} {:<8} {:<64} {}",
+                    format_timestamp(copy.copied_at),
+                    file.stream,
+                    format_bytes(file.bytes),
+                    file.sha256,
+                    file.location
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Average of some durations in seconds
+fn average(durations: &[u64]) -> u64 {
+    durations.iter().sum::<u64>() / durations.len().max(1) as u64
+}
+
+fn format_secs(secs: u64) -> String {
+    format_duration(Duration::from_secs(secs))
+}
+
+/// Summarize the recorded runs of a daemon: how often they succeed and how
+/// long they take, and whether recent runs got slower or faster
+pub(crate) fn print_stats(id: &str, root_dir: &Path) -> Result<()> {
+    let records = read(root_dir, id)?;
+    if records.is_empty() {
+        println!("No recorded runs for '{id}'");
+        return Ok(());
+    }
+
+    let first = records.iter().find_map(|record| record.started_at);
+    match first {
+        Some(first) => println!(
+            "Runs:         {} since {}",
+            records.len(),
+            format_timestamp(first)
+        ),
+        None => println!("Runs:         {}", records.len()),
+    }
+
+    let known: Vec<&RunRecord> = records
+        .iter()
+        .filter(|record| record.exit().is_some())
+        .collect();
+    let succeeded = known
+        .iter()
+        .filter(|record| record.exit_code == Some(0))
+        .count();
+    if known.is_empty() {
+        println!("Success rate: unknown, exit codes are only recorded for --supervise runs");
+    } else {
+        println!(
+            "Success rate: {:.1}% ({} of {} runs with a known exit status)",
+            succeeded as f64 * 100.0 / known.len() as f64,
+            succeeded,
+            known.len()
+        );
+    }
+    let failed: Vec<&&RunRecord> = known
+        .iter()
+        .filter(|record| record.exit_code != Some(0))
+        .collect();
+    if let Some(last) = failed.last() {
+        let exit = last.exit().unwrap_or_default();
+        match last.ended_at {
+            Some(ended_at) => println!(
+                "Failures:     {}, last at {} (exit {})",
+                failed.len(),
+                format_timestamp(ended_at),
+                exit
+            ),
+            None => println!("Failures:     {}, last with exit {}", failed.len(), exit),
+        }
+    }
+
+    let durations: Vec<u64> = records.iter().filter_map(RunRecord::duration).collect();
+    let (Some(shortest), Some(longest)) = (durations.iter().min(), durations.iter().max()) else {
+        println!("Duration:     unknown, no run has a recorded start and end");
+        return Ok(());
+    };
+    println!(
+        "Duration:     average {}, shortest {}, longest {}",
+        format_secs(average(&durations)),
+        format_secs(*shortest),
+        format_secs(*longest)
+    );
+
+    // Compare the latest runs (up to 10) with as many runs before them
+    let window = (durations.len() / 2).min(10);
+    if window < 2 {
+        println!("Trend:        not enough runs");
+        return Ok(());
+    }
+    let recent = average(&durations[durations.len() - window..]);
+    let before = average(&durations[durations.len() - 2 * window..durations.len() - window]);
+    let change = match before {
+        0 if recent == 0 => "unchanged".to_string(),
+        0 => "up".to_string(),
+        _ => {
+            let percent = (recent as f64 - before as f64) * 100.0 / before as f64;
+            match percent.round() as i64 {
+                0 => "unchanged".to_string(),
+                percent => format!("{percent:+}%"),
+            }
+        }
+    };
+    println!(
+        "Trend:        last {} runs average {}, {} from the {} before ({})",
+        window,
+        format_secs(recent),
+        change,
+        window,
+        format_secs(before)
+    );
+    Ok(())
+}
@@ -0,0 +1,261 @@
+//! Per-daemon hooks: executables run when a daemon starts, exits or crashes.
+//!
+//! `--on-start` is run by `demon run` once the daemon is up. `--on-exit` and
+//! `--on-crash` are run by the supervisor when it sees the daemon end, so
+//! setting either of them implies `--supervise`. `--on-exit` runs however the
+//! daemon ended (stopped, exited or crashed); `--on-crash` runs in addition
+//! when it exited with a failure or was killed without `demon stop`.
+//!
+//! Hooks are started in the background with the event described in `DEMON_*`
+//! environment variables; their output is appended to the daemon's stderr log.
+//!
+//! `--notify-url` is the webhook counterpart: crashes and restarts of the daemon
+//! are POSTed to the URL (see [`crate::webhook`]). `--ship-logs` uploads the
+//! logs of every finished run (see [`crate::ship`]). `--trace-context` and
+//! `--otel-endpoint` tie the daemon's runs into OpenTelemetry traces (see
+//! [`crate::otel`]).
+
+use crate::{events, otel, pidfile::build_file_path, ship, webhook};
+use anyhow::Result;
+use clap::Args;
+use serde::{Deserialize, Serialize};
+use std::fs::OpenOptions;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// Hook flags shared by `run` and the internal `supervise` command
+#[derive(Args, Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct Hooks {
+    /// Executable to run once the daemon has started
+    #[arg(long, value_name = "PATH")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) on_start: Option<PathBuf>,
+
+    /// Executable to run whenever the daemon ends, including `demon stop`
+    /// (implies --supervise)
+    #[arg(long, value_name = "PATH")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) on_exit: Option<PathBuf>,
+
+    /// Executable to run when the daemon fails or is killed by a signal
+    /// (implies --supervise)
+    #[arg(long, value_name = "PATH")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) on_crash: Option<PathBuf>,
+
+    /// Webhook URL notified when the daemon crashes, is restarted or becomes
+    /// unhealthy
+    /// (implies --supervise) [config: notify.url]
+    #[arg(long, value_name = "URL")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) notify_url: Option<String>,
+
+    /// Directory or URL (e.g. s3://bucket/logs) the compressed logs of every
+    /// finished run are shipped to [config: logs.ship_url]
+    #[arg(long, value_name = "URL")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) ship_logs: Option<String>,
+
+    /// Start the daemon with TRACEPARENT and OTEL_RESOURCE_ATTRIBUTES naming
+    /// its run, so its spans join the run's trace
+    #[arg(long)]
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub(crate) trace_context: bool,
+
+    /// OTLP/HTTP collector the lifecycle events of the daemon are sent to as
+    /// spans (implies --supervise) [config: otel.endpoint]
+    #[arg(long, value_name = "URL")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) otel_endpoint: Option<String>,
+}
+
+/// What happened to the daemon, passed to a hook
+pub(crate) struct HookEvent<'a> {
+    pub(crate) event: events::Kind,
+    pub(crate) pid: u32,
+    pub(crate) command: &'a [String],
+    pub(crate) exit_code: Option<i32>,
+    pub(crate) exit_signal: Option<i32>,
+}
+
+impl Hooks {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.on_start.is_none()
+            && self.on_exit.is_none()
+            && self.on_crash.is_none()
+            && self.notify_url.is_none()
+            && self.ship_logs.is_none()
+            && !self.trace_context
+            && self.otel_endpoint.is_none()
+    }
+
+    /// Whether hooks that only a supervisor can run are set
+    pub(crate) fn needs_supervisor(&self) -> bool {
+        self.on_exit.is_some()
+            || self.on_crash.is_some()
+            || self.notify_url.is_some()
+            || self.otel_endpoint.is_some()
+    }
+
+    /// Resolve every hook to an absolute path, so it still works when the
+    /// daemon is restarted from another directory, and check that it can be run
+    pub(crate) fn resolve(&self) -> Result<Self> {
+        let resolve = |hook: &Option<PathBuf>, flag: &str| -> Result<Option<PathBuf>> {
+            let Some(path) = hook else {
+                return Ok(None);
+            };
+            let absolute = std::fs::canonicalize(path)
+                .map_err(|e| anyhow::anyhow!("Invalid {} hook {}: {}", flag, path.display(), e))?;
+            let metadata = std::fs::metadata(&absolute)?;
+            if !metadata.is_file() || metadata.permissions().mode() & 0o111 == 0 {
+                return Err(anyhow::anyhow!(
+                    "Invalid {} hook {}: not an executable file",
+                    flag,
+                    path.display()
+                ));
+            }
+            Ok(Some(absolute))
+        };
+        for (flag, url) in [
+            ("--notify-url", &self.notify_url),
+            ("--otel-endpoint", &self.otel_endpoint),
+        ] {
+            if let Some(url) = url
+                && !url.starts_with("http://")
+                && !url.starts_with("https://")
+            {
+                return Err(anyhow::anyhow!(
+                    "Invalid {} '{}': expected an http:// or https:// URL",
+                    flag,
+                    url
+                ));
+            }
+        }
+        if self.ship_logs.is_some() && !ship::ENABLED {
+            return Err(anyhow::anyhow!(
+                "--ship-logs needs demon built with the log-shipping feature"
+            ));
+        }
+        // A relative directory must still work from the supervisor and agent
+        let ship_logs = match &self.ship_logs {
+            Some(dest) if !dest.contains("://") => Some(
+                std::path::absolute(dest)
+                    .map_err(|e| anyhow::anyhow!("Invalid --ship-logs {}: {}", dest, e))?
+                    .to_string_lossy()
+                    .to_string(),
+            ),
+            other => other.clone(),
+        };
+        Ok(Self {
+            on_start: resolve(&self.on_start, "--on-start")?,
+            on_exit: resolve(&self.on_exit, "--on-exit")?,
+            on_crash: resolve(&self.on_crash, "--on-crash")?,
+            notify_url: self.notify_url.clone(),
+            ship_logs,
+            trace_context: self.trace_context,
+            otel_endpoint: self.otel_endpoint.clone(),
+        })
+    }
+
+    /// Command line flags that recreate these hooks
+    pub(crate) fn to_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        for (flag, hook) in [
+            ("--on-start", &self.on_start),
+            ("--on-exit", &self.on_exit),
+            ("--on-crash", &self.on_crash),
+        ] {
+            if let Some(path) = hook {
+                args.push(flag.to_string());
+                args.push(path.to_string_lossy().to_string());
+            }
+        }
+        if let Some(url) = &self.notify_url {
+            args.push("--notify-url".to_string());
+            args.push(url.clone());
+        }
+        if let Some(dest) = &self.ship_logs {
+            args.push("--ship-logs".to_string());
+            args.push(dest.clone());
+        }
+        if self.trace_context {
+            args.push("--trace-context".to_string());
+        }
+        if let Some(endpoint) = &self.otel_endpoint {
+            args.push("--otel-endpoint".to_string());
+            args.push(endpoint.clone());
+        }
+        args
+    }
+
+    /// Run the hooks that apply to an event, without waiting for them
+    pub(crate) fn fire(&self, root_dir: &Path, id: &str, event: &HookEvent) {
+        let hooks = match event.event {
+            events::Kind::Started => vec![&self.on_start],
+            events::Kind::Crashed => vec![&self.on_exit, &self.on_crash],
+            events::Kind::Stopped | events::Kind::Exited => vec![&self.on_exit],
+            events::Kind::Restarted
+            | events::Kind::Cleaned
+            | events::Kind::Unhealthy
+            | events::Kind::Ready
+            | events::Kind::Reloaded
+            | events::Kind::Chaos => vec![],
+        };
+        for hook in hooks.into_iter().flatten() {
+            if let Err(e) = spawn(hook, root_dir, id, event) {
+                tracing::warn!("Failed to run hook {} for '{}': {}", hook.display(), id, e);
+            }
+        }
+
+        if let Some(url) = &self.notify_url
+            && matches!(
+                event.event,
+                events::Kind::Crashed | events::Kind::Restarted | events::Kind::Unhealthy
+            )
+        {
+            let detail = match (event.exit_code, event.exit_signal) {
+                (Some(code), _) => Some(format!("exit code {code}")),
+                (None, Some(signal)) => Some(format!("signal {signal}")),
+                (None, None) => None,
+            };
+            let sent = webhook::send(url, root_dir, id, event.event, event.pid, detail.as_deref());
+            if let Err(e) = sent {
+                tracing::warn!("Failed to notify {} for '{}': {}", url, id, e);
+            }
+        }
+
+        if let Some(endpoint) = &self.otel_endpoint
+            && let Err(e) = otel::send(endpoint, root_dir, id, event)
+        {
+            tracing::warn!("Failed to send span to {} for '{}': {}", endpoint, id, e);
+        }
+    }
+}
+
+fn spawn(hook: &Path, root_dir: &Path, id: &str, event: &HookEvent) -> Result<()> {
+    let log = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(build_file_path(root_dir, id, "stderr"))?;
+
+    let mut cmd = Command::new(hook);
+    cmd.env("DEMON_EVENT", event.event.to_string())
+        .env("DEMON_ID", id)
+        .env("DEMON_PID", event.pid.to_string())
+        .env("DEMON_COMMAND", event.command.join(" "))
+        .env("DEMON_ROOT_DIR", root_dir)
+        .stdin(Stdio::null())
+        .stdout(Stdio::from(log.try_clone()?))
+        .stderr(Stdio::from(log));
+    if let Some(code) = event.exit_code {
+        cmd.env("DEMON_EXIT_CODE", code.to_string());
+    }
+    if let Some(signal) = event.exit_signal {
+        cmd.env("DEMON_EXIT_SIGNAL", signal.to_string());
+    }
+    // The hook runs on its own; whoever fired it may exit right away
+    cmd.spawn()?;
+    Ok(())
+}
@@ -0,0 +1,229 @@
+//! `demon init`: prepare a project for demon.
+//!
+//! Bootstraps a project: the root directory (usually `.demon` inside a git
+//! working tree), a starter `config.toml` in it, a starter `demon.toml`
+//! manifest next to it, optionally imported from a Procfile, and `.gitignore`
+//! entries for the daemon files. Existing files are left alone.
+//!
+//! PID files and logs are easy to commit by accident, so every command also
+//! checks once per root whether git ignores the daemon files and, if not, warns
+//! and points at `demon init --gitignore`, which only appends the patterns to
+//! the `.gitignore` at the top of the working tree.
+
+use crate::{config, manifest};
+use anyhow::{Context, Result};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// Marker left in the root directory once the check has run
+const CHECKED_MARKER: &str = ".gitignore-checked";
+
+/// Files demon creates in a root directory that is the top of the working tree
+const FILE_PATTERNS: &[&str] = &[
+    "/*/*.pid",
+    "/*/*.stdout",
+    "/*/*.stderr",
+    "/*/*.meta",
+    "/*/*.sock",
+    "/*/*.stdin",
+    "/*/*.gz",
+    "/*/*.snapshot",
+    "/*/*.times",
+    "/history.jsonl",
+    "/events.jsonl",
+    "/agent.log",
+    "/hooks.log",
+    "/schedule.json",
+    "/serve.json",
+    "/state.db",
+    "/runs/",
+    "/.lock",
+    "/.up.lock",
+    "/.gitignore-checked",
+];
+
+/// Top of the git working tree containing `dir`
+fn work_tree(dir: &Path) -> Option<PathBuf> {
+    let mut current = std::fs::canonicalize(dir).ok()?;
+    loop {
+        if current.join(".git").exists() {
+            return Some(current);
+        }
+        current = current.parent()?.to_path_buf();
+    }
+}
+
+/// Whether git ignores the PID files in `root_dir`, or None if git could not
+/// tell (not installed, not a repository)
+fn is_ignored(root_dir: &Path) -> Option<bool> {
+    let status = Command::new("git")
+        .arg("-C")
+        .arg(root_dir)
+        .args(["check-ignore", "--quiet", "--no-index", "demon/demon.pid"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .ok()?;
+    match status.code() {
+        Some(0) => Some(true),
+        Some(1) => Some(false),
+        _ => None,
+    }
+}
+
+/// Warn, once per root directory, when it is inside a git working tree that
+/// does not ignore the daemon files
+pub(crate) fn warn_if_not_ignored(root_dir: &Path) {
+    let marker = root_dir.join(CHECKED_MARKER);
+    if marker.exists() || work_tree(root_dir).is_none() {
+        return;
+    }
+    if is_ignored(root_dir) == Some(false) {
+        tracing::warn!(
+            "PID files and logs in {} are not ignored by git and may be committed by accident; \
+             run 'demon init --gitignore' to ignore them",
+            root_dir.display()
+        );
+    }
+    let _ = std::fs::write(&marker, "");
+}
+
+/// Patterns ignoring the daemon files of `root_dir`, relative to `work_tree`.
+/// The per-root `config.toml` and the plugins in `hooks.d` and `checks.d` stay
+/// committable.
+fn patterns(root_dir: &Path, work_tree: &Path) -> Vec<String> {
+    let root_dir = std::fs::canonicalize(root_dir).unwrap_or_else(|_| root_dir.to_path_buf());
+    match root_dir.strip_prefix(work_tree) {
+        Ok(relative) if !relative.as_os_str().is_empty() => {
+            let relative = relative.display();
+            vec![
+                format!("/{relative}/*"),
+                format!("!/{relative}/config.toml"),
+                format!("!/{relative}/hooks.d/"),
+                format!("!/{relative}/checks.d/"),
+            ]
+        }
+        _ => FILE_PATTERNS.iter().map(|p| p.to_string()).collect(),
+    }
+}
+
+/// Append the patterns ignoring the daemon files to the top-level `.gitignore`
+fn add_to_gitignore(root_dir: &Path) -> Result<()> {
+    let work_tree = work_tree(root_dir)
+        .ok_or_else(|| anyhow::anyhow!("{} is not inside a git repository", root_dir.display()))?;
+    if is_ignored(root_dir) == Some(true) {
+        println!(
+            "Daemon files in {} are already ignored by git",
+            root_dir.display()
+        );
+        return Ok(());
+    }
+
+    let path = work_tree.join(".gitignore");
+    let existing = match std::fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    };
+    let patterns = patterns(root_dir, &work_tree);
+    let mut text = String::new();
+    if !existing.is_empty() && !existing.ends_with('\n') {
+        text.push('\n');
+    }
+    text.push_str("# demon PID files and logs\n");
+    for pattern in &patterns {
+        text.push_str(pattern);
+        text.push('\n');
+    }
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .and_then(|mut file| file.write_all(text.as_bytes()))
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    println!("Added to {}: {}", path.display(), patterns.join(" "));
+    Ok(())
+}
+
+/// Write a file unless it already exists, returning whether it was written
+fn create(path: &Path, contents: &str) -> Result<bool> {
+    let file = OpenOptions::new().write(true).create_new(true).open(path);
+    match file {
+        Ok(mut file) => {
+            file.write_all(contents.as_bytes())
+                .with_context(|| format!("Failed to write {}", path.display()))?;
+            Ok(true)
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => Ok(false),
+        Err(e) => Err(e).with_context(|| format!("Failed to create {}", path.display())),
+    }
+}
+
+/// Contents of the starter manifest, imported from a Procfile if one is given
+fn manifest_contents(procfile: Option<&Path>) -> Result<String> {
+    let Some(procfile) = procfile else {
+        return Ok(manifest::STARTER.to_string());
+    };
+    let contents = std::fs::read_to_string(procfile)
+        .map_err(|e| anyhow::anyhow!("Failed to read {}: {}", procfile.display(), e))?;
+    manifest::from_procfile(&contents, procfile)
+}
+
+/// Options of `demon init`
+pub(crate) struct InitOptions {
+    /// Only add the `.gitignore` entries
+    pub(crate) gitignore: bool,
+    /// Import this Procfile into the manifest; None for a starter manifest
+    pub(crate) procfile: Option<PathBuf>,
+}
+
+pub(crate) fn run_init(root_dir: &Path, options: &InitOptions) -> Result<()> {
+    println!("Root directory: {}", root_dir.display());
+    if options.gitignore {
+        add_to_gitignore(root_dir)?;
+        // Checked just now, no need to warn later
+        let _ = std::fs::write(root_dir.join(CHECKED_MARKER), "");
+        return Ok(());
+    }
+
+    let config_path = config::root_config_path(root_dir);
+    if create(&config_path, &config::starter())? {
+        println!("Created {}", config_path.display());
+    } else {
+        println!("Kept existing {}", config_path.display());
+    }
+
+    let manifest_path = manifest::path(root_dir);
+    if manifest_path.exists() {
+        if let Some(procfile) = &options.procfile {
+            return Err(anyhow::anyhow!(
+                "Not importing {}: {} already exists",
+                procfile.display(),
+                manifest_path.display()
+            ));
+        }
+        println!("Kept existing {}", manifest_path.display());
+    } else {
+        create(
+            &manifest_path,
+            &manifest_contents(options.procfile.as_deref())?,
+        )?;
+        match &options.procfile {
+            Some(procfile) => println!(
+                "Created {} from {}",
+                manifest_path.display(),
+                procfile.display()
+            ),
+            None => println!("Created {}", manifest_path.display()),
+        }
+    }
+
+    if work_tree(root_dir).is_some() {
+        add_to_gitignore(root_dir)?;
+    }
+    let _ = std::fs::write(root_dir.join(CHECKED_MARKER), "");
+    Ok(())
+}
@@ -0,0 +1,195 @@
+//! Groups and labels (`--group`, `--label KEY=VALUE`), for managing related
+//! daemons together, e.g. the frontend and backend of one app.
+//!
+//! They are kept in the daemon metadata with the rest of the policy, so they
+//! survive restarts by the agent and the supervisor. `list`, `stop`,
+//! `restart` and `tail` take the same flags to pick every daemon in a group
+//! or with a label instead of one ID; given several, a daemon must match all
+//! of them.
+
+use crate::pidfile::{DaemonMeta, daemon_ids};
+use anyhow::Result;
+use clap::Args;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// Group and label flags of `run`
+#[derive(Args, Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct Labels {
+    /// Put the daemon in a group, so `list`, `stop`, `restart` and `tail`
+    /// can act on the whole group with --group
+    #[arg(long, value_name = "NAME")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) group: Option<String>,
+
+    /// Attach a label to the daemon, e.g. tier=web, for picking it with
+    /// --label; repeatable
+    #[arg(long, value_name = "KEY=VALUE", value_parser = Label::parse)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub(crate) label: Vec<Label>,
+}
+
+/// A `KEY=VALUE` label
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub(crate) struct Label {
+    pub(crate) key: String,
+    pub(crate) value: String,
+}
+
+impl Label {
+    fn parse(value: &str) -> Result<Label, String> {
+        match value.split_once('=') {
+            Some((key, label)) if !key.is_empty() => Ok(Label {
+                key: key.to_string(),
+                value: label.to_string(),
+            }),
+            _ => Err(format!("invalid label '{value}', expected KEY=VALUE")),
+        }
+    }
+}
+
+impl TryFrom<String> for Label {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Label::parse(&value)
+    }
+}
+
+impl From<Label> for String {
+    fn from(label: Label) -> String {
+        label.to_string()
+    }
+}
+
+impl std::fmt::Display for Label {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}={}", self.key, self.value)
+    }
+}
+
+impl Labels {
+    pub(crate) fn is_empty(&self) -> bool {
+        *self == Labels::default()
+    }
+
+    /// Fail before spawning the daemon on an empty group or a label given
+    /// twice
+    pub(crate) fn check(&self) -> Result<()> {
+        if self.group.as_deref() == Some("") {
+            anyhow::bail!("The group name cannot be empty");
+        }
+        for (index, label) in self.label.iter().enumerate() {
+            if self.label[..index]
+                .iter()
+                .any(|other| other.key == label.key)
+            {
+                anyhow::bail!("Label '{}' is given more than once", label.key);
+            }
+        }
+        Ok(())
+    }
+
+    /// Value of the label `key`
+    pub(crate) fn get(&self, key: &str) -> Option<&str> {
+        self.label
+            .iter()
+            .find(|label| label.key == key)
+            .map(|label| label.value.as_str())
+    }
+
+    /// Command line flags that recreate these labels
+    pub(crate) fn to_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if let Some(group) = &self.group {
+            args.extend(["--group".to_string(), group.clone()]);
+        }
+        for label in &self.label {
+            args.extend(["--label".to_string(), label.to_string()]);
+        }
+        args
+    }
+
+    /// The labels as a JSON object, for the records of `list` and `status`
+    pub(crate) fn to_json(&self) -> serde_json::Value {
+        self.label
+            .iter()
+            .map(|label| (label.key.clone(), label.value.clone().into()))
+            .collect::<serde_json::Map<_, _>>()
+            .into()
+    }
+
+    /// The labels of a record of `list`, as written by [`Labels::to_json`]
+    pub(crate) fn from_record(record: &serde_json::Value) -> Labels {
+        Labels {
+            group: record["group"].as_str().map(str::to_string),
+            label: record["labels"]
+                .as_object()
+                .into_iter()
+                .flatten()
+                .map(|(key, value)| Label {
+                    key: key.clone(),
+                    value: value.as_str().unwrap_or_default().to_string(),
+                })
+                .collect(),
+        }
+    }
+
+    /// The labels as `key=value` pairs, for `demon status`
+    pub(crate) fn summary(&self) -> String {
+        self.label
+            .iter()
+            .map(Label::to_string)
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+/// Which daemons `list`, `stop`, `restart` and `tail` act on, by group and
+/// labels
+#[derive(Args, Debug, Clone, Default)]
+pub(crate) struct Selector {
+    /// Only daemons in this group
+    #[arg(long, value_name = "NAME")]
+    pub(crate) group: Option<String>,
+
+    /// Only daemons with this label; repeatable, a daemon must have all of
+    /// them
+    #[arg(long, value_name = "KEY=VALUE", value_parser = Label::parse)]
+    pub(crate) label: Vec<Label>,
+}
+
+impl Selector {
+    /// Whether a daemon with these labels is selected
+    pub(crate) fn matches(&self, labels: &Labels) -> bool {
+        self.group
+            .as_ref()
+            .is_none_or(|group| labels.group.as_ref() == Some(group))
+            && self
+                .label
+                .iter()
+                .all(|label| labels.get(&label.key) == Some(label.value.as_str()))
+    }
+
+    /// IDs of the selected daemons, sorted
+    pub(crate) fn select(&self, root_dir: &Path) -> Result<Vec<String>> {
+        Ok(daemon_ids(root_dir)?
+            .into_iter()
+            .filter(|id| self.matches(&DaemonMeta::read(root_dir, id).policy.labels))
+            .collect())
+    }
+
+    /// The selection as flags, for messages
+    pub(crate) fn describe(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(group) = &self.group {
+            parts.push(format!("--group {group}"));
+        }
+        for label in &self.label {
+            parts.push(format!("--label {label}"));
+        }
+        parts.join(" ")
+    }
+}
@@ -0,0 +1,197 @@
+//! Where the files of a daemon live in a root directory: a directory of its
+//! own, `<root>/<id>/`, holding `<id>.pid`, `<id>.stdout` and the rest, next
+//! to the files of the root itself (`config.toml`, `runs/`, `hooks.d/`, ...).
+//!
+//! Earlier versions kept the files of every daemon directly in the root
+//! directory, where an ID with dots in it, like `my.app`, could not be told
+//! apart from an extension. Such flat files are moved into the daemon
+//! directories the first time a command finds them, unless it runs in
+//! read-only mode. Renaming leaves running daemons writing to their logs, and
+//! logs linked elsewhere stay links. Until then, and in the kept past runs of
+//! those versions, flat files are read where they are.
+
+use crate::{growth, pidfile::RootLock, pty, sd_notify, timeline};
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+
+/// Names in the root directory that belong to demon itself, so no daemon
+/// directory can take them
+const RESERVED: &[&str] = &[
+    "runs",
+    "hooks.d",
+    "checks.d",
+    "config.toml",
+    "policy.toml",
+    "events.jsonl",
+    "history.jsonl",
+    "agent.log",
+    "hooks.log",
+    "schedule.json",
+    "serve.json",
+    "state.db",
+];
+
+/// Extensions that identify a daemon in the flat layout
+const FLAT_RECORDS: [&str; 4] = ["pid", "meta", "stdout", "stderr"];
+
+/// Directory holding the files of daemon `id`
+pub(crate) fn daemon_dir(root_dir: &Path, id: &str) -> PathBuf {
+    root_dir.join(id)
+}
+
+/// Path of the `extension` file of daemon `id`, or of its flat file when
+/// only that one exists
+pub(crate) fn file_path(root_dir: &Path, id: &str, extension: &str) -> PathBuf {
+    let path = dir_file_path(root_dir, id, extension);
+    if path.symlink_metadata().is_err() {
+        let flat = root_dir.join(format!("{id}.{extension}"));
+        if flat.symlink_metadata().is_ok() {
+            return flat;
+        }
+    }
+    path
+}
+
+/// Path of the `extension` file of daemon `id` in its daemon directory
+fn dir_file_path(root_dir: &Path, id: &str, extension: &str) -> PathBuf {
+    daemon_dir(root_dir, id).join(format!("{id}.{extension}"))
+}
+
+/// Fail when `id` cannot name a directory of the root directory
+pub(crate) fn check_id(id: &str) -> Result<()> {
+    if id.is_empty() {
+        anyhow::bail!("Daemon ID cannot be empty");
+    }
+    if id.starts_with('.') || id.contains(['/', '\0']) {
+        anyhow::bail!("Invalid daemon ID '{id}': it cannot start with '.' or contain '/'");
+    }
+    if RESERVED.contains(&id) {
+        anyhow::bail!("Invalid daemon ID '{id}': the name is used by demon in the root directory");
+    }
+    Ok(())
+}
+
+/// Create the directory of daemon `id` before its first files are written
+pub(crate) fn create_daemon_dir(root_dir: &Path, id: &str) -> Result<()> {
+    check_id(id)?;
+    let dir = daemon_dir(root_dir, id);
+    std::fs::create_dir_all(&dir).with_context(|| format!("Cannot create {}", dir.display()))
+}
+
+/// Remove the directory of daemon `id` once nothing is left in it
+pub(crate) fn remove_daemon_dir(root_dir: &Path, id: &str) {
+    let _ = std::fs::remove_dir(daemon_dir(root_dir, id));
+}
+
+/// IDs of the daemons with a file with one of `extensions`, in a daemon
+/// directory or still flat, sorted, since the order of `read_dir` changes
+/// between runs
+pub(crate) fn ids_with(root_dir: &Path, extensions: &[&str]) -> Result<Vec<String>> {
+    let entries = std::fs::read_dir(root_dir)
+        .with_context(|| format!("Failed to read {}", root_dir.display()))?;
+    let mut ids: Vec<String> = entries
+        .flatten()
+        .filter(|entry| entry.file_type().is_ok_and(|kind| kind.is_dir()))
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .filter(|id| check_id(id).is_ok())
+        .filter(|id| {
+            extensions.iter().any(|extension| {
+                dir_file_path(root_dir, id, extension)
+                    .symlink_metadata()
+                    .is_ok()
+            })
+        })
+        .collect();
+    ids.extend(flat_ids(root_dir, extensions));
+    ids.sort();
+    ids.dedup();
+    Ok(ids)
+}
+
+/// IDs of the daemon directories holding any daemon file, e.g. only the
+/// logs of a daemon that was stopped
+pub(crate) fn ids_with_files(root_dir: &Path) -> Result<Vec<String>> {
+    ids_with(root_dir, &extensions())
+}
+
+/// Every extension a daemon file has, for moving them out of the flat layout
+fn extensions() -> [&'static str; 10] {
+    [
+        "pid",
+        "meta",
+        "stdout",
+        "stderr",
+        "sock",
+        "stdin",
+        pty::SOCKET_EXTENSION,
+        sd_notify::SOCKET_EXTENSION,
+        timeline::EXTENSION,
+        growth::EXTENSION,
+    ]
+}
+
+/// IDs of the daemons with a file with one of `extensions` directly in `dir`
+fn flat_ids(dir: &Path, extensions: &[&str]) -> Vec<String> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut ids: Vec<String> = entries
+        .flatten()
+        .filter(|entry| entry.file_type().is_ok_and(|kind| !kind.is_dir()))
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            extensions.iter().find_map(|extension| {
+                let id = name.strip_suffix(extension)?.strip_suffix('.')?;
+                check_id(id).is_ok().then(|| id.to_string())
+            })
+        })
+        .collect();
+    ids.sort();
+    ids.dedup();
+    ids
+}
+
+/// Move the flat files of daemon `id` in `dir` into its daemon directory
+fn move_flat_files(dir: &Path, id: &str) -> Result<()> {
+    create_daemon_dir(dir, id)?;
+    for extension in extensions() {
+        let source = dir.join(format!("{id}.{extension}"));
+        if source.symlink_metadata().is_err() {
+            continue;
+        }
+        let dest = dir_file_path(dir, id, extension);
+        std::fs::rename(&source, &dest)
+            .with_context(|| format!("Cannot move {} to {}", source.display(), dest.display()))?;
+    }
+    tracing::info!(
+        "Moved the files of '{}' into {}",
+        id,
+        daemon_dir(dir, id).display()
+    );
+    Ok(())
+}
+
+/// Move the files of the root directory left in the flat layout into daemon
+/// directories. Skipped while the root lock is held, e.g. by the `run` that
+/// started this process as a supervisor; the next command moves them.
+pub(crate) fn migrate(root_dir: &Path) {
+    if flat_ids(root_dir, &FLAT_RECORDS).is_empty() {
+        return;
+    }
+    let Ok(file) = RootLock::open(root_dir) else {
+        return;
+    };
+    if file.try_lock().is_err() {
+        return;
+    }
+    for id in flat_ids(root_dir, &FLAT_RECORDS) {
+        if let Err(e) = move_flat_files(root_dir, &id) {
+            tracing::warn!(
+                "Failed to move the files of '{}' into a directory of their own: {:#}; \
+                 stop it and start it again under another ID",
+                id,
+                e
+            );
+        }
+    }
+}
@@ -0,0 +1,292 @@
+//! demon: spawn, stop and observe background processes.
+//!
+//! The `demon` binary is a thin wrapper around this crate; other tools and test
+//! harnesses can manage daemons directly through [`DaemonManager`], or through
+//! `AsyncDaemonManager` with the `async` feature.
+
+mod agent;
+mod allowlist;
+mod ansi;
+#[cfg(feature = "async")]
+mod async_manager;
+mod autorestart;
+mod bench;
+mod bundle;
+mod cgroup;
+mod chaos;
+mod check;
+mod checks;
+mod clean;
+#[doc(hidden)]
+pub mod cli;
+mod color;
+mod config;
+mod coredump;
+mod cron;
+mod dev;
+mod events;
+mod exit;
+mod export;
+mod follow;
+mod gc;
+mod get;
+mod grep;
+mod growth;
+mod history;
+mod hooks;
+mod init;
+mod labels;
+mod layout;
+mod limits;
+mod llm;
+mod logs;
+mod manager;
+mod manifest;
+mod nuke;
+mod otel;
+mod output;
+mod pidfile;
+mod plugins;
+mod priority;
+mod proc;
+mod progress;
+mod proxy;
+mod pty;
+mod ready;
+mod reload;
+mod report;
+mod requirements;
+mod roots;
+mod runner;
+mod runs;
+mod sandbox;
+mod schedule;
+mod sd_notify;
+mod serve;
+mod shell_hook;
+mod ship;
+mod singleton;
+mod snapshot;
+mod status;
+mod stdin;
+mod stop;
+mod store;
+mod supervisor;
+mod systemd;
+mod table;
+mod timeline;
+mod top;
+mod view;
+mod watch;
+mod webhook;
+mod whois;
+
+#[cfg(feature = "async")]
+pub use async_manager::AsyncDaemonManager;
+pub use logs::Stream;
+pub use manager::DaemonManager;
+pub use runner::RunOptions;
+pub use status::DaemonStatus;
+pub use stop::Stopped;
+#[cfg(feature = "sqlite")]
+pub use store::SqliteStore;
+pub use store::{FileStore, MemoryStore, Record, StateStore};
+
+use anyhow::{Context, Result};
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// The `demon` binary, which runs supervisors and the agent: this process, or
+/// `$DEMON_BIN` (default `demon` from the PATH) when demon is used as a library
+pub(crate) fn demon_exe() -> Result<PathBuf> {
+    let exe = std::env::current_exe().context("Cannot locate demon binary")?;
+    if exe.file_stem().is_some_and(|name| name == "demon") {
+        return Ok(exe);
+    }
+    Ok(std::env::var_os("DEMON_BIN").map_or_else(|| PathBuf::from("demon"), PathBuf::from))
+}
+
+/// Where `.demon` goes when no `--root-dir` is given
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub(crate) enum RootStrategy {
+    /// The top of the git repository containing the current directory
+    #[default]
+    Git,
+    /// The nearest directory with a demon.toml, so each project of a
+    /// monorepo gets its own daemons, or else the top of the git repository
+    Manifest,
+    /// The current directory
+    Cwd,
+}
+
+/// Closest ancestor of the current directory containing `.git`
+pub(crate) fn git_root() -> Option<PathBuf> {
+    let mut current = std::env::current_dir().ok()?;
+    loop {
+        if current.join(".git").exists() {
+            return Some(current);
+        }
+        current = current.parent()?.to_path_buf();
+    }
+}
+
+/// Closest ancestor of the current directory containing `demon.toml` or,
+/// failing that, `.git`
+fn manifest_root() -> Option<PathBuf> {
+    let mut current = std::env::current_dir().ok()?;
+    loop {
+        if current.join(manifest::FILE_NAME).is_file() || current.join(".git").exists() {
+            return Some(current);
+        }
+        current = current.parent()?.to_path_buf();
+    }
+}
+
+/// Directory `.demon` belongs in per `strategy`
+pub(crate) fn project_dir(strategy: RootStrategy) -> Option<PathBuf> {
+    match strategy {
+        RootStrategy::Git => git_root(),
+        RootStrategy::Manifest => manifest_root(),
+        RootStrategy::Cwd => std::env::current_dir().ok(),
+    }
+}
+
+pub(crate) fn find_git_root() -> Result<PathBuf> {
+    find_root(RootStrategy::Git)
+}
+
+/// The `.demon` root directory found per `strategy`, created if needed
+pub(crate) fn find_root(strategy: RootStrategy) -> Result<PathBuf> {
+    let project_dir = project_dir(strategy).ok_or_else(|| match strategy {
+        RootStrategy::Manifest => anyhow::anyhow!(
+            "No demon.toml or git repository found. Please specify --root-dir or run from within a project"
+        ),
+        _ => anyhow::anyhow!(
+            "No git repository found. Please specify --root-dir or run from within a git repository"
+        ),
+    })?;
+
+    // Create .demon subdirectory within the project
+    let demon_dir = project_dir.join(".demon");
+
+    // Handle the case where .demon already exists
+    if demon_dir.exists() {
+        if !demon_dir.is_dir() {
+            return Err(anyhow::anyhow!(
+                "Path {} exists but is not a directory. Please remove it or specify --root-dir",
+                demon_dir.display()
+            ));
+        }
+        // .demon exists and is a directory, we can use it
+        return Ok(demon_dir);
+    }
+
+    // Create .demon directory
+    std::fs::create_dir(&demon_dir)
+        .with_context(|| format!("Failed to create daemon directory {}", demon_dir.display()))?;
+
+    tracing::info!("Created daemon directory: {}", demon_dir.display());
+
+    Ok(demon_dir)
+}
+
+/// Parse a duration such as `30s`, `10m`, `2h` or `7d`; a bare number is seconds
+pub(crate) fn parse_duration(value: &str) -> Result<Duration, String> {
+    let invalid = || "expected e.g. 30s, 10m or 2h".to_string();
+    let value = value.trim();
+    let (amount, unit) = match value.chars().last() {
+        Some(unit) if unit.is_ascii_alphabetic() => (&value[..value.len() - 1], unit),
+        _ => (value, 's'),
+    };
+    let amount: u64 = amount
+        .parse()
+        .map_err(|e: std::num::ParseIntError| match e.kind() {
+            std::num::IntErrorKind::PosOverflow => "duration too large".to_string(),
+            _ => invalid(),
+        })?;
+    let unit_seconds: u64 = match unit {
+        's' => 1,
+        'm' => 60,
+        'h' => 3600,
+        'd' => 86400,
+        _ => return Err(invalid()),
+    };
+    let seconds = amount
+        .checked_mul(unit_seconds)
+        .ok_or_else(|| "duration too large".to_string())?;
+    Ok(Duration::from_secs(seconds))
+}
+
+/// Format a duration compactly for tables, e.g. `45s`, `12m05s`, `3h07m`, `2d04h`
+pub(crate) fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    match secs {
+        0..60 => format!("{secs}s"),
+        60..3600 => format!("{}m{:02}s", secs / 60, secs % 60),
+        3600..86400 => format!("{}h{:02}m", secs / 3600, (secs % 3600) / 60),
+        _ => format!("{}d{:02}h", secs / 86400, (secs % 86400) / 3600),
+    }
+}
+
+/// Seconds since the Unix epoch
+pub(crate) fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Format a Unix timestamp in local time, e.g. `2025-06-01 14:03:22`
+pub(crate) fn format_timestamp(secs: u64) -> String {
+    let time = secs as libc::time_t;
+    // SAFETY: tm is plain data and both pointers are valid for the call
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    if unsafe { libc::localtime_r(&time, &mut tm) }.is_null() {
+        return secs.to_string();
+    }
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        tm.tm_year + 1900,
+        tm.tm_mon + 1,
+        tm.tm_mday,
+        tm.tm_hour,
+        tm.tm_min,
+        tm.tm_sec
+    )
+}
+
+/// Parse a byte count such as `512M`, `1.5G` or `4096`, with binary units
+/// (`K`, `M`, `G`, `T`, optionally followed by `B`); a bare number is bytes
+pub(crate) fn parse_bytes(value: &str) -> Result<u64, String> {
+    let upper = value.trim().to_ascii_uppercase();
+    let number = upper.strip_suffix('B').unwrap_or(&upper);
+    let (amount, exponent) = match number.chars().last() {
+        Some('K') => (&number[..number.len() - 1], 1),
+        Some('M') => (&number[..number.len() - 1], 2),
+        Some('G') => (&number[..number.len() - 1], 3),
+        Some('T') => (&number[..number.len() - 1], 4),
+        _ => (number, 0),
+    };
+    amount
+        .parse::<f64>()
+        .ok()
+        .filter(|amount| amount.is_finite() && *amount >= 0.0)
+        .map(|amount| (amount * 1024f64.powi(exponent)) as u64)
+        .ok_or_else(|| format!("invalid size '{value}', expected e.g. 512M, 1.5G or 4096"))
+}
+
+/// Format a byte count with a binary unit suffix, e.g. `512B`, `1.5K`, `230.0M`
+pub(crate) fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "K", "M", "G", "T"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{bytes}B")
+    } else {
+        format!("{value:.1}{}", UNITS[unit])
+    }
+}
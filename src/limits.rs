@@ -0,0 +1,370 @@
+//! Resource limits of daemons (`demon run --limit-nofile 1024`, ...), and
+//! their file creation mask (`--umask 027`) and core dumps
+//! (`--core-dump-dir crashes`).
+//!
+//! Each limit is set with `setrlimit` in the forked child right before it
+//! executes the daemon, so it binds the daemon and its children but not demon
+//! or the supervisor. A value is `<soft>[:<hard>]`, where a single value sets
+//! both, and either is a number, with a K, M, G or T suffix for sizes, or
+//! `unlimited`. Sizes are in bytes and `--limit-cpu` is in seconds of CPU time.
+//! The umask is set the same way.
+//!
+//! Limits are part of the daemon's policy, so they are recorded in its
+//! metadata and restarts by the agent or `top` apply them again.
+
+use crate::{coredump, parse_bytes};
+use anyhow::{Context, Result};
+use clap::Args;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// A soft and a hard limit; None is unlimited
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Limit {
+    soft: Option<u64>,
+    hard: Option<u64>,
+}
+
+impl Limit {
+    /// The soft limit, None when unlimited
+    pub(crate) fn soft(self) -> Option<u64> {
+        self.soft
+    }
+
+    /// The hard limit, None when unlimited
+    pub(crate) fn hard(self) -> Option<u64> {
+        self.hard
+    }
+
+    fn rlimit(self) -> libc::rlimit {
+        let value = |limit: Option<u64>| limit.map_or(libc::RLIM_INFINITY, |limit| limit as _);
+        libc::rlimit {
+            rlim_cur: value(self.soft),
+            rlim_max: value(self.hard),
+        }
+    }
+}
+
+fn parse_value(value: &str) -> Result<Option<u64>, String> {
+    match value.trim() {
+        "unlimited" | "infinity" => Ok(None),
+        value => parse_bytes(value)
+            .map(Some)
+            .map_err(|_| format!("invalid limit '{value}', expected a number or 'unlimited'")),
+    }
+}
+
+impl FromStr for Limit {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, String> {
+        let (soft, hard) = match value.split_once(':') {
+            Some((soft, hard)) => (parse_value(soft)?, parse_value(hard)?),
+            None => {
+                let limit = parse_value(value)?;
+                (limit, limit)
+            }
+        };
+        if let (soft, Some(hard)) = (soft, hard)
+            && soft.is_none_or(|soft| soft > hard)
+        {
+            return Err(format!(
+                "invalid limit '{value}': the soft limit is above the hard limit"
+            ));
+        }
+        Ok(Limit { soft, hard })
+    }
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = |limit: Option<u64>| limit.map_or("unlimited".to_string(), |l| l.to_string());
+        match self.soft == self.hard {
+            true => write!(f, "{}", value(self.soft)),
+            false => write!(f, "{}:{}", value(self.soft), value(self.hard)),
+        }
+    }
+}
+
+impl Serialize for Limit {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Limit {
+    /// A string like on the command line, or a number for both limits
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Value {
+            Number(u64),
+            Text(String),
+        }
+        match Value::deserialize(deserializer)? {
+            Value::Number(limit) => Ok(Limit {
+                soft: Some(limit),
+                hard: Some(limit),
+            }),
+            Value::Text(text) => text.parse().map_err(serde::de::Error::custom),
+        }
+    }
+}
+
+/// File creation mask, given in octal like to `umask`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub(crate) struct Umask(u32);
+
+impl Umask {
+    pub(crate) fn bits(self) -> u32 {
+        self.0
+    }
+}
+
+impl FromStr for Umask {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, String> {
+        u32::from_str_radix(value.trim(), 8)
+            .ok()
+            .filter(|mask| *mask <= 0o777)
+            .map(Umask)
+            .ok_or_else(|| format!("invalid umask '{value}', expected octal like 022 or 0027"))
+    }
+}
+
+impl TryFrom<String> for Umask {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, String> {
+        value.parse()
+    }
+}
+
+impl From<Umask> for String {
+    fn from(umask: Umask) -> String {
+        umask.to_string()
+    }
+}
+
+impl fmt::Display for Umask {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04o}", self.0)
+    }
+}
+
+/// Resource limit flags of `run`, like `ulimit` or `prlimit`
+#[derive(Args, Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct Limits {
+    /// Maximum number of open files (RLIMIT_NOFILE), e.g. 1024 or 1024:4096
+    #[arg(long, value_name = "LIMIT")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) limit_nofile: Option<Limit>,
+
+    /// Maximum size of core dumps, e.g. 0 or unlimited (RLIMIT_CORE)
+    #[arg(long, value_name = "SIZE")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) limit_core: Option<Limit>,
+
+    /// Maximum size of the address space, e.g. 4G (RLIMIT_AS)
+    #[arg(long, value_name = "SIZE")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) limit_as: Option<Limit>,
+
+    /// Maximum number of processes of the user (RLIMIT_NPROC)
+    #[arg(long, value_name = "LIMIT")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) limit_nproc: Option<Limit>,
+
+    /// Maximum CPU time in seconds; past the soft limit the daemon gets
+    /// SIGXCPU (RLIMIT_CPU)
+    #[arg(long, value_name = "SECONDS")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) limit_cpu: Option<Limit>,
+
+    /// Maximum size of a file the daemon writes (RLIMIT_FSIZE)
+    #[arg(long, value_name = "SIZE")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) limit_fsize: Option<Limit>,
+
+    /// Maximum size of the stack (RLIMIT_STACK)
+    #[arg(long, value_name = "SIZE")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) limit_stack: Option<Limit>,
+
+    /// Maximum memory locked in RAM (RLIMIT_MEMLOCK)
+    #[arg(long, value_name = "SIZE")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) limit_memlock: Option<Limit>,
+
+    /// File creation mask of the daemon in octal, e.g. 027 (default: demon's)
+    #[arg(long, value_name = "MASK")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) umask: Option<Umask>,
+
+    /// Collect the core dumps of the daemon in this directory, as
+    /// <id>.<pid>.core, raising its core size limit unless --limit-core is
+    /// given (implies --supervise)
+    #[arg(long, value_name = "DIR")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) core_dump_dir: Option<PathBuf>,
+}
+
+/// Limits and umask as the forked child sets them
+pub(crate) struct Prepared {
+    limits: Vec<(Resource, libc::rlimit)>,
+    umask: Option<libc::mode_t>,
+}
+
+impl Limits {
+    /// Every limit with the name of its flag without `--limit-` and its
+    /// resource
+    fn all(&self) -> [(&'static str, Resource, Option<Limit>); 8] {
+        [
+            ("nofile", libc::RLIMIT_NOFILE, self.limit_nofile),
+            ("core", libc::RLIMIT_CORE, self.limit_core),
+            ("as", libc::RLIMIT_AS, self.limit_as),
+            ("nproc", libc::RLIMIT_NPROC, self.limit_nproc),
+            ("cpu", libc::RLIMIT_CPU, self.limit_cpu),
+            ("fsize", libc::RLIMIT_FSIZE, self.limit_fsize),
+            ("stack", libc::RLIMIT_STACK, self.limit_stack),
+            ("memlock", libc::RLIMIT_MEMLOCK, self.limit_memlock),
+        ]
+    }
+
+    /// The limits that are set, by the name of their flag without `--limit-`
+    pub(crate) fn set(&self) -> impl Iterator<Item = (&'static str, Limit)> {
+        self.all()
+            .into_iter()
+            .filter_map(|(name, _, limit)| Some((name, limit?)))
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        *self == Limits::default()
+    }
+
+    /// Command line flags that recreate these limits
+    pub(crate) fn to_args(&self) -> Vec<String> {
+        let mut args: Vec<String> = self
+            .set()
+            .flat_map(|(name, limit)| [format!("--limit-{name}"), limit.to_string()])
+            .collect();
+        if let Some(umask) = self.umask {
+            args.extend(["--umask".to_string(), umask.to_string()]);
+        }
+        if let Some(dir) = &self.core_dump_dir {
+            args.extend(["--core-dump-dir".to_string(), dir.display().to_string()]);
+        }
+        args
+    }
+
+    /// Make the core dump directory absolute, so it does not depend on where
+    /// the daemon is restarted from
+    pub(crate) fn resolve(&mut self) -> Result<()> {
+        if let Some(dir) = &mut self.core_dump_dir {
+            *dir = std::path::absolute(&*dir)?;
+        }
+        Ok(())
+    }
+
+    /// Fail early on limits the daemon could not be given, since raising a
+    /// hard limit needs privileges
+    pub(crate) fn check(&self) -> Result<()> {
+        if let Some(dir) = &self.core_dump_dir {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("Cannot create {}", dir.display()))?;
+            if self.limit_core.is_none() && current_limit(libc::RLIMIT_CORE).rlim_max == 0 {
+                return Err(anyhow::anyhow!(
+                    "--core-dump-dir: core dumps are disabled by a hard core size limit of 0"
+                ));
+            }
+            if let Some(handler) = coredump::handler() {
+                tracing::warn!(
+                    "Core dumps are handed to {} (kernel.core_pattern), so they cannot be collected in {}",
+                    handler,
+                    dir.display()
+                );
+            }
+        }
+        // SAFETY: geteuid cannot fail
+        if unsafe { libc::geteuid() } == 0 {
+            return Ok(());
+        }
+        for (name, resource, limit) in self.all() {
+            let Some(limit) = limit else {
+                continue;
+            };
+            let current = current_limit(resource);
+            if current.rlim_max != libc::RLIM_INFINITY && limit.rlimit().rlim_max > current.rlim_max
+            {
+                return Err(anyhow::anyhow!(
+                    "--limit-{} {} is above the hard limit of {}, which only root can raise",
+                    name,
+                    limit,
+                    current.rlim_max
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// The limits as arguments of setrlimit, prepared before forking since
+    /// the child must not allocate. Collecting core dumps raises the core
+    /// size limit to the hard limit, unless one was given.
+    pub(crate) fn prepare(&self) -> Prepared {
+        let mut limits: Vec<_> = self
+            .all()
+            .into_iter()
+            .filter_map(|(_, resource, limit)| Some((resource, limit?.rlimit())))
+            .collect();
+        if self.core_dump_dir.is_some() && self.limit_core.is_none() {
+            let current = current_limit(libc::RLIMIT_CORE);
+            limits.push((
+                libc::RLIMIT_CORE,
+                libc::rlimit {
+                    rlim_cur: current.rlim_max,
+                    rlim_max: current.rlim_max,
+                },
+            ));
+        }
+        Prepared {
+            limits,
+            umask: self.umask.map(|umask| umask.bits() as libc::mode_t),
+        }
+    }
+}
+
+/// The limits demon itself has for a resource; unlimited when unknown
+fn current_limit(resource: Resource) -> libc::rlimit {
+    let mut current = libc::rlimit {
+        rlim_cur: libc::RLIM_INFINITY,
+        rlim_max: libc::RLIM_INFINITY,
+    };
+    // SAFETY: getrlimit only writes the limits it is given
+    unsafe { libc::getrlimit(resource, &mut current) };
+    current
+}
+
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+pub(crate) type Resource = libc::__rlimit_resource_t;
+#[cfg(not(all(target_os = "linux", target_env = "gnu")))]
+pub(crate) type Resource = libc::c_int;
+
+/// Set prepared limits and umask; called in the forked child
+pub(crate) fn apply(prepared: &Prepared) -> std::io::Result<()> {
+    for (resource, limit) in &prepared.limits {
+        // SAFETY: setrlimit is async-signal-safe and only reads the limit
+        if unsafe { libc::setrlimit(*resource, limit) } != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+    if let Some(umask) = prepared.umask {
+        // SAFETY: umask is async-signal-safe and cannot fail
+        unsafe { libc::umask(umask) };
+    }
+    Ok(())
+}
@@ -0,0 +1,1008 @@
+//! `demon llm` usage guide.
+//!
+//! The command reference is generated from the clap definitions, so every
+//! subcommand, argument, flag and default shown here is the one the binary
+//! actually accepts. Only the prose (per-command notes and examples, overview and
+//! workflows) is written by hand.
+//!
+//! `--format json` describes the same commands as data, for tools that generate
+//! bindings from it instead of reading the guide.
+
+use crate::{cli::Cli, exit};
+use clap::{Arg, ArgAction, CommandFactory, ValueEnum};
+use serde_json::{Value, json};
+use std::fmt::Write;
+
+/// Output of `demon llm`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub(crate) enum Format {
+    /// Usage guide in markdown
+    Markdown,
+    /// Description of every command, its arguments, output formats and exit codes
+    Json,
+}
+
+/// Version of the `--format json` document, bumped on incompatible changes
+const SCHEMA_VERSION: u32 = 1;
+
+/// Hand-written annotations for a subcommand
+struct Notes {
+    behavior: &'static [&'static str],
+    examples: &'static [&'static str],
+}
+
+fn notes(command: &str) -> Notes {
+    match command {
+        "run" => Notes {
+            behavior: &[
+                "Creates `<id>.pid`, `<id>.stdout`, `<id>.stderr` files in the directory `<id>/` of the root directory",
+                "IDs may contain dots; they cannot start with `.`, contain `/` or be a name demon uses in the root directory (`runs`, `hooks.d`, `checks.d`, `config.toml`, ...)",
+                "Moves the logs of the previous run into `runs/<id>/` (see `demon runs`), or truncates them when `runs.keep` is 0",
+                "Fails if a process with the same ID is already running",
+                "Fails with the cause if the command cannot be executed, e.g. not found in PATH or a script without a `#!` line",
+                "Starts the process in a new session; `demon run` returns immediately",
+                "Use `--` to separate demon flags from flags of the command",
+                "Hooks get `DEMON_EVENT`, `DEMON_ID`, `DEMON_PID`, `DEMON_COMMAND`, `DEMON_ROOT_DIR` and `DEMON_EXIT_CODE`/`DEMON_EXIT_SIGNAL`",
+                "`--on-exit` also runs after `demon stop`; `--on-crash` only on failures",
+                "`--notify-url` POSTs crashes and restarts to a Slack, Discord or generic JSON webhook (requires `curl`)",
+                "Executables in `.demon/hooks.d/` run for every event of every daemon with the event as JSON on stdin; non-zero exits and timeouts (`plugins.timeout`) are retried `plugins.retries` times, output goes to `hooks.log`",
+                "`--trace-context` sets TRACEPARENT and OTEL_RESOURCE_ATTRIBUTES (`demon.id`, `demon.run_id`); `--otel-endpoint` sends lifecycle events as OTLP/HTTP JSON spans (requires `curl`)",
+                "`--restart`, `--health-cmd` and `--every` are applied by `demon agent`, which must be running",
+                "`--sd-notify` sets NOTIFY_SOCKET and waits up to 90s for the daemon to send READY=1; `--watchdog` kills it with SIGABRT when WATCHDOG=1 pings stop",
+                "`--max-runtime 2h` has the supervisor send SIGTERM once the daemon ran that long, then SIGKILL after the stop timeout; the exit is recorded as TIMEOUT (`status` `Exit reason:`, JSON `exit_reason`, `list`/`history`) and counts as a crash",
+                "`--require-free-memory 2G` and `--require-free-disk 5G@/var` (repeatable) refuse to start the daemon while less memory is available or less disk space is free; the agent retries restarts and `--every` runs with a backoff",
+                "`--watch <path>` (repeatable) has the supervisor restart the daemon when a file under the path changes (debounced by `dev.debounce`), killing its process group; `--watch-ignore <glob>` skips matching paths; a daemon that exited is started again on the next change",
+                "`--pty` runs the command on a pseudo-terminal for `demon attach`; its stderr is merged into the stdout log",
+                "Stdin is /dev/null unless `--stdin fifo` gives the daemon the named pipe `<id>.stdin`, written with `demon input`",
+                "`--stdin-file <path>` feeds a file to the daemon's stdin, like `command < file`",
+                "Use `--pty` for programs that buffer output when it is not a terminal, so `tail -f` shows each line as it is written",
+                "`--ship-logs` (or `logs.ship_url`) uploads gzipped logs once the run has finished, named `<id>-<start time>.stdout.gz`",
+                "`--reload-signal <name>` sets the signal `demon reload` sends (default HUP)",
+                "`--singleton machine` fails while another root directory of the user runs a daemon with the same ID; `status` names the owning root",
+                "`--strip-ansi` removes ANSI escape sequences (colors) from the output before it is written to the logs (implies `--supervise`)",
+                "`--limit-nofile|core|as|nproc|cpu|fsize|stack|memlock <soft>[:<hard>]` set resource limits (setrlimit) for the daemon; sizes take K/M/G, cpu is seconds, `unlimited` lifts one",
+                "`--nice <n>` (-20..19), `--ionice-class realtime|best-effort|idle` with `--ionice-level 0-7` and `--cpu-affinity 0-3,6` set the daemon's CPU and I/O priority before it executes, `--oom-score-adj <n>` (-1000..1000) makes it likelier (positive) or less likely to be killed when memory runs out; lowering niceness or the OOM adjustment and the realtime class need root",
+                "`--umask 027` sets the daemon's file creation mask; `--core-dump-dir <dir>` raises its core size limit and has the supervisor move its core dumps there as `<id>.<pid>.core`, shown by `status` (`Core dump:`, JSON `core_dump`); cores piped to a handler by kernel.core_pattern (systemd-coredump) stay with it",
+                "`--sandbox` confines the daemon with Landlock to reading the system, its working directory and `--ro-path <path>`, and writing only to `--rw-path <path>` (both repeatable, imply --sandbox; /tmp is not included); `--no-network` runs it in its own network namespace without network, not even localhost; use them for untrusted or generated commands",
+                "`--memory <size>` and `--cpus <n>` confine the daemon and all its descendants to a cgroup v2 of their own (memory.max, cpu.max); `stats` then covers the whole cgroup and `stop` kills what is left in it; `run` fails where cgroup v2 controllers are not delegated",
+                "`--group <name>` and `--label <key>=<value>` (repeatable) tag the daemon, so `list`, `stop`, `restart` and `tail` can pick related daemons with the same flags instead of an ID",
+                "`--systemd-scope` runs the daemon in a transient `systemd-run --user --scope` unit (`demon-<id>-<hash>.scope`), so it survives logout and systemd tracks it; `--memory`/`--cpus` become MemoryMax/CPUQuota; needs a systemd user session",
+            ],
+            examples: &[
+                "demon run web-server python -m http.server 8080",
+                "demon run backup-job -- rsync -av /data/ /backup/",
+                "demon run --supervise worker ./worker.sh",
+                "demon run worker --on-crash ./notify.sh -- ./worker.sh",
+                "demon run api --restart on-failure --health-cmd \"curl -sf localhost:8080/health\" -- ./api",
+                "demon run report --every 1h -- ./report.sh",
+            ],
+        },
+        "stop" => Notes {
+            behavior: &[
+                "Sends SIGTERM, then SIGKILL if the process is still alive after the timeout",
+                "Removes the PID file after termination",
+                "Handles already-dead processes gracefully",
+                "`--group <name>` or `--label <key>=<value>` instead of an ID stops every matching daemon; exits 4 when none matches",
+            ],
+            examples: &[
+                "demon stop web-server",
+                "demon stop backup-job --timeout 30",
+                "demon stop --group shop",
+            ],
+        },
+        "restart" => Notes {
+            behavior: &[
+                "Stops the daemon, then starts it again with the command and options of `run` it was started with; a dead daemon is just started",
+                "The daemon starts in the working directory of `restart`",
+                "`--group <name>` or `--label <key>=<value>` instead of an ID restarts every matching daemon; exits 4 when none matches",
+                "Logs a `restarted` event",
+            ],
+            examples: &["demon restart api", "demon restart --label tier=web"],
+        },
+        "reload" => Notes {
+            behavior: &[
+                "Sends SIGHUP, or the signal given with `run --reload-signal`, without restarting the daemon",
+                "Fails if the daemon is not running",
+                "Logs a `reloaded` event",
+            ],
+            examples: &["demon reload nginx"],
+        },
+        "attach" => Notes {
+            behavior: &[
+                "Only works for daemons started with `run --pty`",
+                "Puts the terminal in raw mode and forwards keys and window size changes; Ctrl-] detaches",
+                "Returns when the daemon exits; without a terminal on stdin, pipes stdin to the daemon and prints its output",
+            ],
+            examples: &["demon run --pty console -- python3", "demon attach console"],
+        },
+        "input" => Notes {
+            behavior: &[
+                "Only works for running daemons started with `run --stdin fifo`",
+                "Sends the text as one line, the contents of `--file` as they are, or else everything read from stdin",
+                "The daemon never sees end-of-file; input can be sent any number of times",
+            ],
+            examples: &[
+                "demon run --stdin fifo repl -- python3 -i",
+                "demon input repl 'print(1 + 1)'",
+                "demon input repl --file commands.txt",
+            ],
+        },
+        "tail" => Notes {
+            behavior: &[
+                "Shows the last lines of both stdout and stderr by default",
+                "Only shows output of the current run: starting the daemon moves the logs of the last one aside; `--run previous` (or an index from `demon runs`, or a time) shows an earlier run, without `-f`; `--all-runs` shows every kept run, the oldest first and the current one last, which `-f` follows",
+                "With `-f`, shows the last lines and keeps following new output until Ctrl+C",
+                "`-f --until-exit` stops once the daemon has exited and its remaining output is printed, e.g. to stream logs in CI",
+                "Reads logs backwards from the end, so `-n` is instant on logs of any size",
+                "With `-f` and a running agent, new lines are pushed by the agent",
+                "`--output json` prints one `{id, stream, ts, line, run}` object per line; `ts` is when `-f` read a new line, `run` the start time of the run",
+                "`--split-streams` writes the daemon's stderr to stderr instead of stdout, without headers",
+                "`--strip-ansi` removes ANSI escape sequences (colors) from the output",
+                "`--since TIME` shows only lines written since then (e.g. `10m`, a Unix time or `2024-05-01 14:00:00`); without `-f`, `--until TIME` too. Needs a supervised daemon, precise to 250ms",
+                "Polls files instead of using inotify with `--no-notify-watch`, `watch.poll`, or automatically on NFS, SMB, 9p, FUSE and WSL1",
+                "With `-f`, output is buffered for up to `--flush-interval` ms (`tail.flush_interval`, default 100) while the logs keep growing, and written as soon as they stop",
+                "`--max-rate N` prints at most N new lines a second with `-f` and reports how many were dropped",
+                "`--group <name>` or `--label <key>=<value>` instead of an ID tails every matching daemon, each line prefixed with `<id>.<stream>:`; with `--until-exit` it stops once all have exited",
+            ],
+            examples: &[
+                "demon tail web-server -n 100",
+                "demon tail web-server -f --stderr",
+                "demon tail migrate -f --until-exit",
+                "demon tail -f --group shop",
+            ],
+        },
+        "cp-logs" => Notes {
+            behavior: &[
+                "Copies the logs as they were when the command started, even while the daemon writes",
+                "Files are named `<id>-<timestamp>.stdout` / `.stderr` inside the destination",
+                "URLs are uploaded by `logs.uploader` (`$DEMON_FILE` to `$DEMON_URL`); `s3://` defaults to `aws s3 cp`",
+                "The SHA-256 of every copy is recorded in the history and shown by `demon history`",
+            ],
+            examples: &[
+                "demon cp-logs web-server ./incident-logs",
+                "demon cp-logs web-server s3://my-bucket/logs --stderr",
+            ],
+        },
+        "cat" => Notes {
+            behavior: &[
+                "Shows both logs by default, with a header per file",
+                "Streams the logs in constant memory and prints their bytes as they are, even when not UTF-8",
+                "`--head N` and `--tail N` show only the first or last N lines of each log, `--bytes N` the last N bytes (e.g. `10M`)",
+                "`--output json` prints one `{id, stream, ts, line, run}` object per line; `ts` is null for lines read from the file",
+                "`--split-streams` writes the daemon's stderr to stderr instead of stdout, without headers",
+                "`--strip-ansi` removes ANSI escape sequences (colors) from the output",
+                "`--since TIME` and `--until TIME` (e.g. `10m`, `1h`, a Unix time or `2024-05-01 14:00:00`) show only lines written in that window; needs a supervised daemon, whose supervisor records when output was written, to within 250ms",
+                "`--run previous` shows the logs of the run before the current one; `--run N` the run numbered N by `demon runs`, `--run TIME` the run that was live then; `--all-runs` every kept run, the oldest first and the current one last",
+            ],
+            examples: &[
+                "demon cat web-server",
+                "demon cat web-server --stdout",
+                "demon cat web-server --tail 100",
+                "demon cat web-server --since 1h --until 30m",
+                "demon cat web-server --output json",
+            ],
+        },
+        "logs" => Notes {
+            behavior: &[
+                "Without `--merge`, prints the logs of every given daemon in turn, like `cat`",
+                "`--merge` interleaves stdout and stderr of all the daemons in the order the lines were written, each line prefixed with its time (ms) and log, e.g. `api.stderr:`",
+                "Times are recorded by the supervisor every 250ms, so `--merge` needs supervised daemons; lines within the same 250ms keep the order of the IDs, stdout first",
+                "`--since TIME` and `--until TIME` limit the window, as for `cat`",
+                "`--output json` prints one `{id, stream, ts, line, run}` object per line, with `ts` when the line was written",
+                "`--strip-ansi` removes ANSI escape sequences (colors) from the output",
+            ],
+            examples: &[
+                "demon logs web-server --merge",
+                "demon logs api worker --merge --since 10m",
+                "demon logs api worker --merge --output json",
+            ],
+        },
+        "grep" => Notes {
+            behavior: &[
+                "Reads the logs line by line, so large logs are searched without loading them into memory",
+                "Patterns are regular expressions (Rust regex syntax); `-i` ignores case",
+                "`-A`, `-B` and `-C` print context lines; `-n` prefixes line numbers",
+                "Lines are prefixed with the log file name, e.g. `web.stderr:`, when more than one log is searched",
+                "`--all` searches every daemon with logs in the root directory and takes only the pattern",
+                "`--strip-ansi` removes ANSI escape sequences (colors) from the lines before they are searched and printed",
+                "Exits with 1 when no line matched, like grep",
+                "`--run previous`, `--run N` or `--run TIME` searches the logs of an earlier run, see `demon runs`",
+            ],
+            examples: &[
+                "demon grep web-server 'timeout|refused' -i -C 2",
+                "demon grep web-server panic --stderr -n",
+                "demon grep --all 'ERROR'",
+            ],
+        },
+        "list" => Notes {
+            behavior: &[
+                "Groups daemons into RUNNING, FAILED (non-zero exit or signal), COMPLETED (exit 0) and DEAD (exit status unknown, files still present) sections with counts",
+                "Exit statuses are known for supervised daemons and daemons the agent started",
+                "`--flat` prints one table with status `RUNNING` or `DEAD`, as before sections existed",
+                "`--quiet` prints one `id:pid:status` line per daemon (`RUNNING` or `DEAD`), for scripts",
+                "`--status running|dead|all`, `--filter <glob>` on the ID, `--group <name>`, `--label <key>=<value>` and `--sort id|pid|uptime|rss` select and order daemons",
+                "`--columns` picks from id, pid, status, uptime, cpu, rss, threads, command, group; with `--quiet` they are joined by `:`",
+                "Tables are aligned by display width; cells wider than `table.max_width` (default 40) are cut with `…`, the command only at `table.max_command_width`; `--quiet` and `--format` never cut",
+                "`--watch[=<interval>]` redraws every interval (default 2s) and when a daemon starts or stops; it runs until interrupted, so agents should not use it",
+                "`--all-roots` lists the daemons of every root directory daemons were run from, with their root; the same ID or command running in two roots is flagged (`*` and a warning, `duplicate_of` in JSON), as it usually means a port conflict",
+            ],
+            examples: &[
+                "demon list",
+                "demon list --flat",
+                "demon list --status running --quiet",
+                "demon list --filter 'web-*' --sort rss --columns id,pid,uptime,rss",
+                "demon list --all-roots --status running",
+            ],
+        },
+        "status" => Notes {
+            behavior: &[
+                "Shows PID, command, session, uptime, CPU, memory and log file sizes and line counts",
+                "Shows how fast the logs grow in bytes per minute, over the last 5 minutes of samples or since the start",
+                "Reports the exit code of supervised daemons that have exited",
+                "Uptime is measured on the boot clock, so NTP and clock changes do not skew it; a `Clock:` line (`clock_skew_secs` in JSON) reports how far the wall clock moved since the start",
+                "Runs the check plugins in `.demon/checks.d/<id>/` of a running daemon and shows their state, message and metrics (`checks` in JSON)",
+                "`--watch[=<interval>]` redraws it like `list --watch`, until interrupted",
+                "`--all` shows every daemon sorted by ID (a record per daemon in JSON) and exits 0",
+            ],
+            examples: &[
+                "demon status web-server",
+                "demon status web-server --watch=5s",
+            ],
+        },
+        "clean" => Notes {
+            behavior: &[
+                "Removes the files of dead daemons and invalid PID files",
+                "A log that is a symbolic link (e.g. onto another disk) is kept and its target emptied",
+                "`--dry-run` lists the files that would be removed; `--id <glob>` and `--older-than <interval>` (last write) scope the sweep",
+                "`--logs-only` removes only logs and timeline, keeping PID file and metadata so the daemon stays listed",
+            ],
+            examples: &[
+                "demon clean",
+                "demon clean --dry-run",
+                "demon clean --id 'worker-*' --older-than 7d --logs-only",
+            ],
+        },
+        "gc" => Notes {
+            behavior: &[
+                "Removes the logs of dead daemons, past runs kept in `runs/<id>/` (each on its own, aged by when it ended) and local copies of logs (`cp-logs`, log shipping) per a retention policy",
+                "Policy: `[retention]` in demon.toml (`max_total_bytes`, `max_age` in seconds, `max_copies`); flags override it, and one is required",
+                "Logs of running daemons count toward `--max-total-bytes` but are never removed; PID files and metadata are kept",
+                "`--dry-run` lists what would be removed",
+            ],
+            examples: &[
+                "demon gc --max-total-bytes 2G --max-age 14d",
+                "demon gc --max-copies 3 --dry-run",
+            ],
+        },
+        "init" => Notes {
+            behavior: &[
+                "Creates the root directory, a starter `config.toml` and `demon.toml`, and `.gitignore` entries; existing files are kept",
+                "`--procfile` turns the `<name>: <command>` lines of a Procfile into manifest daemons",
+                "Commands warn once per root directory when git does not ignore it",
+                "`--gitignore` only appends the patterns to the top-level `.gitignore`, keeping `config.toml` committable",
+            ],
+            examples: &[
+                "demon init",
+                "demon init --procfile",
+                "demon init --gitignore",
+            ],
+        },
+        "up" => Notes {
+            behavior: &[
+                "Reads `[daemons.<id>]` tables from `demon.toml` next to the root directory",
+                "A daemon has a `command` (a string run by `sh -c`, or a list) and the options of `demon run` with underscores",
+                "Starts daemons in manifest order from the manifest's directory, skipping running ones; IDs given pick daemons but keep that order",
+                "`--wait-healthy` waits until every daemon runs, sent READY=1 (sd_notify) and passed its health_cmd once, printing when each became ready",
+                "With `--wait-healthy`, daemons not ready within `--timeout` (default 60s) are listed with what they wait for",
+                "Only one `up` or `down` runs per root directory; another fails naming the holder (PID, start) unless `--wait-lock` or `--wait-lock=<interval>` makes it wait",
+            ],
+            examples: &[
+                "demon up",
+                "demon up web worker",
+                "demon up --wait-healthy --timeout 120s",
+                "demon up --wait-lock=30s",
+            ],
+        },
+        "down" => Notes {
+            behavior: &[
+                "Stops the manifest's daemons in reverse order, like `demon stop`",
+                "Takes the same lock as `up`, with `--wait-lock` to wait for it",
+            ],
+            examples: &["demon down", "demon down web --timeout 30"],
+        },
+        "export" => Notes {
+            behavior: &[
+                "`export systemd <id>` prints a `.service` unit with the daemon's command (program made absolute), working directory, environment, restart policy, reload signal, sd_notify/watchdog, max runtime, limits, `--memory` and `--cpus`",
+                "Uses the daemon's last run, or its demon.toml entry when it never ran; `--all` exports every daemon of demon.toml",
+                "The environment is read from the running daemon without login session and `DEMON_*` variables; it is left out when the daemon is not running",
+                "Settings without a unit counterpart (health checks, `--every`, hooks, `--pty`, `--singleton`, `--require-free-*`, `--watch`) are listed in a `# Not exported` comment",
+                "`--output <dir>` writes `<id>.service` files; `--system` installs into multi-user.target instead of default.target",
+                "`export launchd` prints a macOS launchd agent plist labelled `local.demon.<id>`, with KeepAlive for `--restart`, StartInterval for `--every` and resource limits; its logs stay the daemon's files in the root directory",
+            ],
+            examples: &[
+                "demon export systemd web",
+                "demon export systemd --all --output ~/.config/systemd/user",
+                "demon export launchd --all --output ~/Library/LaunchAgents",
+            ],
+        },
+        "nuke" => Notes {
+            behavior: &[
+                "Stops the agent, then every running daemon like `demon stop`, then removes the files of all daemons like `demon clean`",
+                "Keeps the history, events, configuration and manifest",
+                "Asks for confirmation unless `--yes`; without a terminal on stdin it fails instead of asking",
+                "A daemon that fails to stop keeps its files; the others are still stopped and removed, then it exits 1",
+                "Takes the same lock as `up` and `down`",
+            ],
+            examples: &["demon nuke --yes", "demon nuke --yes --timeout 2"],
+        },
+        "shell-hook" => Notes {
+            behavior: &[
+                "Prints a script to `eval` in bash or zsh; it runs before the prompt after the directory changed",
+                "Exports `DEMON_SHELL_ROOT` inside a project with a `demon.toml`, for the prompt",
+                "`--up` asks before starting the manifest's stopped daemons (`ask`), starts them (`always`) or only lists them (`never`)",
+                "`--stop leave` stops the daemons the shell started on leaving the project or exiting, `--stop exit` only on exiting",
+            ],
+            examples: &[
+                "eval \"$(demon shell-hook zsh)\"",
+                "eval \"$(demon shell-hook bash --up always --stop leave)\"",
+            ],
+        },
+        "llm" => Notes {
+            behavior: &[
+                "`--format json` describes every command, its arguments, output formats and exit codes",
+            ],
+            examples: &["demon llm > DEMON.md", "demon llm --format json"],
+        },
+        "wait" => Notes {
+            behavior: &[
+                "Fails if the daemon does not exist or the timeout is reached",
+                "Does not clean up PID files (use `demon clean` for that)",
+            ],
+            examples: &[
+                "demon wait batch-job --timeout 0",
+                "demon wait short-task --interval 2",
+            ],
+        },
+        "proxy" => Notes {
+            behavior: &[
+                "Listens on `--listen` and starts the daemon on the first connection",
+                "Forwards every connection to `--upstream` once it accepts connections",
+            ],
+            examples: &[
+                "demon proxy dev-server --listen 127.0.0.1:8080 --upstream 127.0.0.1:8081 -- python -m http.server 8081",
+            ],
+        },
+        "top" => Notes {
+            behavior: &["Requires an interactive terminal; use `demon list` in scripts"],
+            examples: &["demon top --interval 500"],
+        },
+        "view" => Notes {
+            behavior: &[
+                "Requires an interactive terminal; agents and scripts should use `demon cat`, `demon grep` or `demon logs --merge`",
+                "Loads the last `-n` lines of each log (default 100000); supervised daemons get both logs interleaved by time, others stdout then stderr",
+                "Keys: j/k, space/b, g/G scroll; `/` searches a regex (case-insensitive unless it has capitals), n/N next/previous match; `t` jumps to a time (`10m`, `14:05`, a date); o/e toggle stdout/stderr; f follows new output; q quits",
+                "`--run previous` (or an index from `demon runs`, or a time) views an earlier run",
+            ],
+            examples: &["demon view web-server", "demon view web-server -f"],
+        },
+        "stats" => Notes {
+            behavior: &[
+                "Includes every child process and aggregated totals",
+                "For daemons run with `--memory`/`--cpus`, lists the processes of the cgroup and its memory, peak, OOM kills and CPU time",
+            ],
+            examples: &["demon stats web-server"],
+        },
+        "get" => Notes {
+            behavior: &[
+                "Prints exactly one value with no decoration: `pid`, `uptime` (seconds), `status` (RUNNING, FAILED, COMPLETED, DEAD, ERROR), `exit-code` (128+signal when killed), `stdout-path` or `stderr-path`",
+                "Without a value stdout is empty and the exit code tells why, so it is safe under `set -euo pipefail`",
+            ],
+            examples: &["PID=$(demon get api pid)", "demon get migrate exit-code"],
+        },
+        "check" => Notes {
+            behavior: &[
+                "Prints one line, `DEMON <STATE> - <summary> | <perfdata>`, for Nagios, Icinga and compatible systems",
+                "CRITICAL when the daemon is missing, dead, failing its health check (per the agent) or at `--crit-mem`",
+                "WARNING at `--warn-mem` RSS, or with more than `--max-restarts` restarted events within `--window` (1h)",
+                "UNKNOWN when the state cannot be told, e.g. an unreadable PID file",
+                "Executables in `.demon/checks.d/<id>/` are run as check plugins: they print `{\"status\": \"ok|warning|critical|unknown\", \"message\": ..., \"metrics\": {...}}` (or exit 0-3 like Nagios plugins); the worst state wins and metrics are added to the perfdata",
+            ],
+            examples: &["demon check web-server --warn-mem 512M --crit-mem 1G --max-restarts 3"],
+        },
+        "whois" => Notes {
+            behavior: &[
+                "Searches every running daemon, its descendants and its supervisor",
+                "`--port` matches TCP sockets listening on the port and UDP sockets bound to it",
+                "`--file` matches open file descriptors, working directories and executables; a directory matches everything inside it",
+                "Prints one line per matching process; fails when no daemon owns it",
+            ],
+            examples: &[
+                "demon whois --port 8080",
+                "demon whois --pid 4242",
+                "demon whois --file data/db.sqlite",
+            ],
+        },
+        "history" => Notes {
+            behavior: &[
+                "Lists finished runs newest first, with the current run on top",
+                "Exit codes and peak RSS are exact for supervised runs",
+                "History is kept in `history.jsonl` and survives `demon clean`",
+                "Log copies made by `demon cp-logs` are listed below the runs with their SHA-256",
+                "`--stats` prints the success rate (exit 0), failures, average/shortest/longest duration and the trend of the last 10 runs against the 10 before",
+                "Recording a run prunes the history to `history.keep_runs` per daemon (default 1000) and `history.keep_days` (default 0, forever)",
+            ],
+            examples: &[
+                "demon history web-server",
+                "demon history web-server -n 5",
+                "demon history nightly-backup --stats",
+            ],
+        },
+        "runs" => Notes {
+            behavior: &[
+                "Lists the runs whose logs are kept, newest first; run 0 is the current or last run, 1 the previous one",
+                "Starting a daemon moves the logs of its last run to `runs/<id>/<start>.<pid>/`; `runs.keep` (default 5, 0 for none) bounds how many are kept",
+                "`cat`, `tail` and `grep` read a past run with `--run previous`, `--run N` or `--run TIME` (like `--since`); a missing run exits with 4",
+                "`demon clean` removes the past runs with the logs",
+            ],
+            examples: &[
+                "demon runs web-server",
+                "demon cat web-server --run previous --stderr",
+                "demon grep web-server panic --run 2",
+            ],
+        },
+        "bench" => Notes {
+            behavior: &[
+                "Runs the command `--runs` times (default 5) one after the other as the supervised daemon <id>",
+                "Prints wall time, CPU time and peak RSS of each run, then their mean, median, stddev, min and max",
+                "Stops with an error at the first run that does not exit with 0; its logs stay readable with `demon cat`",
+                "Runs are recorded in the history like any supervised run",
+            ],
+            examples: &[
+                "demon bench build --runs 10 -- cargo build --release",
+                "demon bench import --stdin-file dump.sql -- psql mydb",
+            ],
+        },
+        "events" => Notes {
+            behavior: &[
+                "Event kinds: started, stopped, exited, crashed, cleaned, restarted, unhealthy, ready, reloaded, chaos",
+                "exited/crashed are only detected for supervised daemons",
+                "Events are kept in `events.jsonl`; `--format json` prints them as JSON lines",
+                "`-f --no-notify-watch` polls the event log instead of using inotify",
+            ],
+            examples: &["demon events --id web-server --since 1h", "demon events -f"],
+        },
+        "report" => Notes {
+            behavior: &[
+                "Prints a Markdown status page: a table of the daemons (status, PID, uptime, CPU, memory, restarts, health), failing health checks, restarts and crashes since `--since` (default 24h) and the last `-n` (default 10) lines of every log",
+                "Takes daemon IDs or `--all`",
+                "`--format json` prints one record with `daemons` (the `list` fields plus restarts, healthy, stdout_tail, stderr_tail) and `events`; csv and plain print the daemon rows",
+                "`--bundle [<path>]` writes a tar.gz for bug reports instead: report.md and report.json, events.jsonl, config.toml, demon.toml, environment.json, and per daemon meta.json, environ.txt and the last `--log-kb` (default 64) KB of each log",
+                "`--redact` replaces environment variable values in the bundle",
+            ],
+            examples: &[
+                "demon report --all",
+                "demon report --all --since 7d >> \"$GITHUB_STEP_SUMMARY\"",
+                "demon report web --format json",
+                "demon report --all --bundle --redact",
+            ],
+        },
+        "dev" => Notes {
+            behavior: &[
+                "Runs the command as a daemon and stays in the foreground until Ctrl+C",
+                "Restarts it when a watched file changes, terminating its whole process tree",
+                "Reports readiness when a `--ready` text (e.g. \"listening\") appears in its logs",
+                "Defaults for watched paths, ignored directories and ready texts depend on the program (cargo, npm, python, go)",
+                "`--no-notify-watch` polls the watched paths, for file systems where inotify misses changes",
+            ],
+            examples: &[
+                "demon dev api -- cargo run",
+                "demon dev web --watch src --ready \"compiled\" -- npm run dev",
+            ],
+        },
+        "chaos" => Notes {
+            behavior: &[
+                "Stays in the foreground until Ctrl+C, `--count` disruptions, or the daemon is stopped",
+                "Each time the daemon has run for `--kill-after`, sends it `--signal` (KILL by default) with `--probability`",
+                "Follows restarts: a new PID gets a full `--kill-after` before the next roll",
+                "Logs a `chaos` event per disruption; restarts are up to the daemon's `--restart` policy and the agent",
+            ],
+            examples: &[
+                "demon run api --restart on-failure -- ./api",
+                "demon chaos api --kill-after 30s --signal KILL --probability 0.5",
+            ],
+        },
+        "agent" => Notes {
+            behavior: &[
+                "Runs in the foreground until Ctrl+C; `--detach` runs it in the background",
+                "Restarts daemons per `--restart` (never, on-failure, always) with a growing delay",
+                "Runs `--health-cmd` every `--health-interval` seconds; 3 failures in a row emit `unhealthy` and restart the daemon unless the policy is never",
+                "Starts `--every` jobs again once they have finished and the interval since their last start has passed",
+                "`--catch-up skip|run-once|run-all` (default run-once) handles runs missed while the machine slept; see `demon schedule list`",
+                "Exit codes are known for supervised daemons and daemons the agent started; other exits count as failures",
+                "`demon stop` is never undone by a restart policy",
+            ],
+            examples: &["demon agent --detach", "demon agent --stop"],
+        },
+        "serve" => Notes {
+            behavior: &[
+                "`GET /daemons` lists daemons; `GET /daemons/<id>` returns status, metadata and agent state",
+                "`POST /daemons/<id>/run` with `{\"command\": [...], \"supervise\": false, \"restart\": \"on-failure\"}` starts a daemon",
+                "`POST /daemons/<id>/stop?timeout=<seconds>` stops it",
+                "`GET /daemons/<id>/logs?lines=<n>&stream=stdout|stderr` returns the last lines as JSON",
+                "`GET /daemons/<id>/tail` streams new lines as server-sent events named `stdout`/`stderr`",
+                "Errors are JSON `{\"error\": ...}` with 400, 401, 404, 409 or 500",
+                "`GET /llms.txt` serves the endpoints and this guide, `GET /tools.json` the `--format json` schema with the endpoints in `http_api`; both without a token",
+                "`--upgrade` (or SIGUSR2) makes the running server exec the demon binary installed now, with the same PID and listening socket; daemons keep running",
+            ],
+            examples: &[
+                "demon serve",
+                "demon serve --upgrade",
+                "DEMON_SERVE_TOKEN=secret demon serve --listen 0.0.0.0:8787",
+                "curl -X POST localhost:8787/daemons/web/run -d '{\"command\": [\"python\", \"-m\", \"http.server\"]}'",
+            ],
+        },
+        "schedule" => Notes {
+            behavior: &[
+                "`schedule add <id> --cron <expr> -- <command>` defines a cron job (5 fields in local time, or @hourly/@daily/@weekly/@monthly/@yearly) that the agent starts, supervised, at every trigger; adding an existing ID replaces it",
+                "Each cron run is a daemon run: logs in `<id>.stdout`/`.stderr` and `demon runs`, exit status in `demon history`; missed triggers are made up for with one run, and triggers during a run are skipped",
+                "`schedule add` takes `--require-free-memory` and `--require-free-disk`; a trigger whose requirements are not met is skipped",
+                "`schedule remove <id>` drops a cron job without stopping a run in progress (exit 4 when there is no such job)",
+                "`schedule list` shows every cron and `--every` job with its schedule, catch-up policy, last run, next run and missed triggers",
+                "The state is kept by the agent in `<root>/schedule.json`; without a running agent jobs are not started",
+            ],
+            examples: &[
+                "demon schedule add backup --cron '0 3 * * *' -- ./backup.sh",
+                "demon schedule list",
+                "demon schedule list --format json",
+                "demon schedule remove backup",
+            ],
+        },
+        "config" => Notes {
+            behavior: &[
+                "Subcommands: `get <key>`, `set <key> <value> [--global]`, `list`, `validate`",
+                "Precedence: defaults < global config < `<root>/config.toml` < `DEMON_*` env < flags",
+                "`list` shows where every value comes from",
+                "A `[daemon.<id>]` table holds defaults for one daemon: the options of `run` as in a manifest entry (`restart`, `supervise`, `limit_nofile`, ...) plus `stop_timeout`; `run <id>` and `stop <id>` use them for the flags not given",
+            ],
+            examples: &[
+                "demon config set stop.timeout 30",
+                "demon config list",
+                "DEMON_STOP_TIMEOUT=5 demon config get stop.timeout",
+            ],
+        },
+        _ => Notes {
+            behavior: &[],
+            examples: &[],
+        },
+    }
+}
+
+/// Output formats of a command; `--format` or `format` in the configuration
+/// picks one
+fn output_formats(command: &str) -> &'static [&'static str] {
+    match command {
+        "run" | "stop" | "restart" | "wait" | "clean" | "status" | "list" | "runs" | "events"
+        | "schedule" => &["text", "json", "csv", "plain", "markdown"],
+        "report" => &["markdown", "json", "csv", "plain"],
+        "cat" | "tail" | "logs" => &["text", "json"],
+        "llm" => &["markdown", "json"],
+        "top" | "view" => &["terminal UI"],
+        _ => &["text"],
+    }
+}
+
+pub(crate) fn print_llm_guide(format: Format) {
+    match format {
+        Format::Markdown => print!("{}", render_guide()),
+        Format::Json => println!(
+            "{}",
+            serde_json::to_string_pretty(&render_schema()).expect("schema is valid JSON")
+        ),
+    }
+}
+
+/// Global arguments, which clap propagates to every subcommand
+fn global_args(cli: &clap::Command) -> Vec<&Arg> {
+    cli.get_subcommands()
+        .flat_map(|sub| sub.get_arguments())
+        .filter(|arg| arg.is_global_set())
+        .fold(Vec::new(), |mut acc, arg| {
+            if !acc.iter().any(|seen: &&Arg| seen.get_id() == arg.get_id()) {
+                acc.push(arg);
+            }
+            acc
+        })
+}
+
+/// Subcommands worth documenting, skipping hidden ones and `help`
+fn is_documented(sub: &clap::Command) -> bool {
+    !sub.is_hide_set() && sub.get_name() != "help"
+}
+
+fn is_option(arg: &Arg) -> bool {
+    !arg.is_positional()
+        && !arg.is_hide_set()
+        && !arg.is_global_set()
+        && !matches!(arg.get_id().as_str(), "help" | "version")
+}
+
+pub(crate) fn render_schema() -> Value {
+    let mut cli = Cli::command();
+    cli.build();
+
+    let exit_codes = exit_codes_schema("");
+    let globals: Vec<Value> = global_args(&cli).into_iter().map(arg_schema).collect();
+
+    let mut commands = Vec::new();
+    for sub in cli.get_subcommands_mut() {
+        if !is_documented(sub) {
+            continue;
+        }
+        let name = sub.get_name().to_string();
+        let usage = sub.render_usage().to_string();
+        let notes = notes(&name);
+        commands.push(json!({
+            "name": name,
+            "about": sub.get_about().map(|about| about.to_string()),
+            "usage": usage.trim().trim_start_matches("Usage: "),
+            "arguments": sub
+                .get_positionals()
+                .filter(|arg| !arg.is_hide_set())
+                .map(arg_schema)
+                .collect::<Vec<_>>(),
+            "options": sub
+                .get_arguments()
+                .filter(|arg| is_option(arg))
+                .map(arg_schema)
+                .collect::<Vec<_>>(),
+            "output_formats": output_formats(&name),
+            "exit_codes": exit_codes_schema(&name),
+            "behavior": notes.behavior,
+            "examples": notes.examples,
+        }));
+    }
+
+    json!({
+        "schema_version": SCHEMA_VERSION,
+        "name": cli.get_name(),
+        "version": cli.get_version(),
+        "about": cli.get_about().map(|about| about.to_string()),
+        "global_options": globals,
+        "exit_codes": exit_codes,
+        "commands": commands,
+    })
+}
+
+/// Exit codes of a command as JSON: the common ones, unless the command
+/// gives them a more specific meaning, and those telling the daemon's state
+fn exit_codes_schema(command: &str) -> Vec<Value> {
+    let specific = exit::of_command(command);
+    let mut codes: Vec<(i32, &str)> = exit::COMMON
+        .iter()
+        .filter(|(code, _)| !specific.iter().any(|(known, _)| known == code))
+        .chain(specific)
+        .copied()
+        .collect();
+    codes.sort_by_key(|(code, _)| *code);
+    codes
+        .into_iter()
+        .map(|(code, meaning)| json!({ "code": code, "meaning": meaning }))
+        .collect()
+}
+
+/// An argument as JSON, e.g.
+/// `{"name": "lines", "kind": "option", "long": "lines", "short": "n", ...}`
+fn arg_schema(arg: &Arg) -> Value {
+    let kind = if arg.is_positional() {
+        "positional"
+    } else if arg.get_action().takes_values() {
+        "option"
+    } else {
+        "flag"
+    };
+    let multiple = matches!(arg.get_action(), ArgAction::Append)
+        || arg.get_num_args().is_some_and(|n| n.max_values() > 1);
+    let mut schema = json!({
+        "name": arg.get_id().as_str(),
+        "kind": kind,
+        "required": arg.is_required_set(),
+        "multiple": multiple,
+    });
+    let fields = schema.as_object_mut().expect("schema is an object");
+    if let Some(long) = arg.get_long() {
+        fields.insert("long".into(), json!(long));
+    }
+    if let Some(short) = arg.get_short() {
+        fields.insert("short".into(), json!(short.to_string()));
+    }
+    if kind != "flag"
+        && let Some(name) = arg.get_value_names().and_then(|names| names.first())
+    {
+        fields.insert("value_name".into(), json!(name.as_str()));
+    }
+    if let Some(help) = arg.get_help() {
+        fields.insert("help".into(), json!(help.to_string()));
+    }
+    let possible: Vec<String> = arg
+        .get_possible_values()
+        .iter()
+        .filter(|value| !value.is_hide_set())
+        .map(|value| value.get_name().to_string())
+        .collect();
+    if kind != "flag" && !possible.is_empty() {
+        fields.insert("possible_values".into(), json!(possible));
+    }
+    let defaults: Vec<String> = arg
+        .get_default_values()
+        .iter()
+        .map(|value| value.to_string_lossy().to_string())
+        .collect();
+    if kind != "flag" && !defaults.is_empty() {
+        fields.insert("default".into(), json!(defaults.join(",")));
+    }
+    if let Some(env) = arg.get_env() {
+        fields.insert("env".into(), json!(env.to_string_lossy()));
+    }
+    schema
+}
+
+pub(crate) fn render_guide() -> String {
+    let mut cli = Cli::command();
+    cli.build();
+
+    let mut out = String::new();
+    out.push_str(HEADER);
+
+    out.push_str("## Global Options\n");
+    out.push_str("Accepted by every command:\n\n");
+    for arg in global_args(&cli) {
+        let _ = writeln!(out, "{}", describe_arg(arg));
+    }
+    out.push('\n');
+
+    out.push_str("## Available Commands\n\n");
+    for sub in cli.get_subcommands_mut() {
+        if !is_documented(sub) {
+            continue;
+        }
+        let name = sub.get_name().to_string();
+        let about = sub.get_about().map(|about| about.to_string());
+        let usage = sub.render_usage().to_string();
+        let usage = usage.trim().trim_start_matches("Usage: ");
+
+        let _ = writeln!(out, "### demon {name}");
+        if let Some(about) = about {
+            let _ = writeln!(out, "{about}.");
+        }
+        let _ = writeln!(out, "\n**Syntax**: `{usage}`\n");
+
+        let arguments: Vec<String> = sub
+            .get_positionals()
+            .filter(|arg| !arg.is_hide_set())
+            .map(describe_arg)
+            .collect();
+        if !arguments.is_empty() {
+            let _ = writeln!(out, "**Arguments**:\n{}\n", arguments.join("\n"));
+        }
+
+        let options: Vec<String> = sub
+            .get_arguments()
+            .filter(|arg| is_option(arg))
+            .map(describe_arg)
+            .collect();
+        if !options.is_empty() {
+            let _ = writeln!(out, "**Options**:\n{}\n", options.join("\n"));
+        }
+
+        let notes = notes(&name);
+        if !notes.behavior.is_empty() {
+            out.push_str("**Behavior**:\n");
+            for line in notes.behavior {
+                let _ = writeln!(out, "- {line}");
+            }
+            out.push('\n');
+        }
+        let codes = exit::of_command(&name);
+        if !codes.is_empty() {
+            out.push_str("**Exit codes**:\n");
+            for (code, meaning) in codes {
+                let _ = writeln!(out, "- `{code}`: {meaning}");
+            }
+            out.push('\n');
+        }
+        if !notes.examples.is_empty() {
+            out.push_str("**Examples**:\n```bash\n");
+            for example in notes.examples {
+                let _ = writeln!(out, "{example}");
+            }
+            out.push_str("```\n\n");
+        }
+    }
+
+    out.push_str(FOOTER);
+    out
+}
+
+/// One markdown bullet describing an argument, e.g.
+/// `` - `-n, --lines <LINES>`: Number of lines (default: 50) ``
+fn describe_arg(arg: &Arg) -> String {
+    let value = arg
+        .get_value_names()
+        .and_then(|names| names.first())
+        .map(|name| name.to_string())
+        .unwrap_or_else(|| arg.get_id().as_str().to_uppercase());
+
+    let mut syntax = if arg.is_positional() {
+        let multiple = matches!(arg.get_action(), ArgAction::Append)
+            || arg.get_num_args().is_some_and(|n| n.max_values() > 1);
+        let dots = if multiple { "..." } else { "" };
+        if arg.is_required_set() {
+            format!("<{value}>{dots}")
+        } else {
+            format!("[{value}]{dots}")
+        }
+    } else {
+        let mut names = Vec::new();
+        if let Some(short) = arg.get_short() {
+            names.push(format!("-{short}"));
+        }
+        if let Some(long) = arg.get_long() {
+            names.push(format!("--{long}"));
+        }
+        names.join(", ")
+    };
+    let optional_value = arg.get_num_args().is_some_and(|n| n.min_values() == 0);
+    if !arg.is_positional() && arg.get_action().takes_values() {
+        match (optional_value, arg.is_require_equals_set()) {
+            (true, true) => {
+                let _ = write!(syntax, "[=<{value}>]");
+            }
+            (true, false) => {
+                let _ = write!(syntax, " [<{value}>]");
+            }
+            (false, _) => {
+                let _ = write!(syntax, " <{value}>");
+            }
+        }
+    }
+
+    let mut line = format!("- `{syntax}`");
+    if let Some(help) = arg.get_help() {
+        let _ = write!(line, ": {help}");
+    }
+    let defaults: Vec<String> = arg
+        .get_default_values()
+        .iter()
+        .map(|value| value.to_string_lossy().to_string())
+        .collect();
+    if !defaults.is_empty() && arg.get_action().takes_values() {
+        let _ = write!(line, " (default: {})", defaults.join(", "));
+    }
+    if let Some(env) = arg.get_env() {
+        let _ = write!(line, " (env: `{}`)", env.to_string_lossy());
+    }
+    line
+}
+
+const HEADER: &str = r#"# Demon - Daemon Process Management CLI
+
+## Overview
+Demon is a command-line tool for spawning, managing, and monitoring background processes (daemons) on Linux systems. It redirects process stdout/stderr to files and provides commands to control and observe these processes.
+
+## Core Concept
+- Each daemon is identified by a unique string ID
+- Three files are created per daemon: `<id>.pid`, `<id>.stdout`, `<id>.stderr`
+- Files are created in a directory per daemon, `<id>/`, in the root directory: `.demon` in the git root, or `--root-dir` / `DEMON_ROOT_DIR`
+- `--root-strategy manifest` (or `DEMON_ROOT_STRATEGY=manifest`) puts `.demon` next to the nearest `demon.toml` instead, one root per project of a monorepo; `cwd` puts it in the current directory
+- Processes run detached from the parent shell, in their own session
+
+"#;
+
+const FOOTER: &str = r#"## File Management
+
+### Created Files
+For each daemon with ID "example", in `example/` in the root directory (files left directly in the root by older versions are moved there by the first command):
+- `example.pid`: Contains the process ID and command
+- `example.stdout`: Contains standard output from the process
+- `example.stderr`: Contains standard error from the process
+- `example.meta`: Extra metadata (start time, supervisor, exit status)
+- `example.logsizes`: Recent sizes of the logs, for the growth rate in `status`
+- `example.times`: When the output of a supervised daemon was written, for `cat`/`tail --since`
+
+In the root directory itself:
+- `history.jsonl`: One record per finished run of any daemon
+- `runs/example/`: The logs of the last `runs.keep` runs before the current one
+- `events.jsonl`: Lifecycle events of all daemons
+- `hooks.d/`: Plugins run for every event, given it as JSON on stdin (output in `hooks.log`)
+
+### Cleanup
+- Files persist after process termination for inspection
+- Use `demon clean` to remove files from dead processes
+
+## Output Formats
+`--format json|csv|plain|markdown` (or `format` in the configuration) makes `run`, `stop`, `restart`, `wait`, `clean`, `status`, `list`, `runs` and `events` print records with fixed fields, null when not applicable:
+- `run`: id, pid, pid_file, stdout_file, stderr_file
+- `stop`: id, pid, result (not-running, invalid-pid-file, already-exited, terminated, killed); an array with `--group`/`--label`, with result failed and an error for a daemon that could not be stopped
+- `restart`: id, pid (of the new run), result (restarted); an array with `--group`/`--label`, with result failed and an error for a daemon that could not be restarted
+- `wait`: id, pid, waited_secs
+- `clean`: id, pid, reason (dead, invalid-pid-file), files, one record per cleaned daemon
+- `status`: id, pid, status (RUNNING, DEAD, NOT FOUND, ERROR), command, group, labels (object), pid_file, supervised, supervisor_pid, started_at, uptime_secs, clock_skew_secs (wall clock moved since the start, or null), exit_code, exit_signal, exit_reason (TIMEOUT or null), core_dump, cpu_percent, rss_bytes, threads, stdout_file, stdout_target, stdout_bytes, stdout_lines, stdout_bytes_per_min, stderr_file, stderr_target, stderr_bytes, stderr_lines, stderr_bytes_per_min, checks (results of the check plugins or null), error
+- `list`: id, pid, status, uptime_secs, command, group, labels (object), cpu_percent, rss_bytes, threads, error; JSON adds generated_at (Unix time the state files were read)
+- `runs`: id, run, started_at, ended_at, pid, exit_code, exit_signal, stdout_bytes, stderr_bytes, dir
+- `events`: ts, id, event, pid, detail (JSON lines with `--format json`)
+
+`csv` has a header row; `plain` is tab-separated without a header; `markdown` is a table.
+
+## Progress Events
+`--progress json` or `DEMON_PROGRESS=json` makes stop, up, down, wait and clean write JSON lines to stderr: ts, op (stop, up, wait-healthy, down, wait, clean), event (start, step, done), id, phase, done, total, ok. `done`/`total` count daemons, or seconds for stop and wait. Errors end with phase `failed`; skip stderr lines not starting with `{`.
+
+## Read-Only Mode
+`--read-only` or `DEMON_READ_ONLY=1` refuses commands that change daemons or files (run, stop, reload, attach, input, clean, cp-logs, up, down, nuke, gc, init, agent, dev, bench, chaos, proxy, report --bundle, export --output, config set, serve --upgrade). Inspection commands work as usual, and so do `clean --dry-run`, `gc --dry-run` and `export` without `--output`; `top` disables stopping and restarting and `serve` answers run and stop requests with 403. It writes no files at all, and fails instead of creating a missing `.demon`.
+
+## Policy File
+A `policy.toml` in the root directory restricts starts by `run`, `up`, `serve` and agent restarts: `programs` (glob patterns; without a slash matched against the program name, with one against its absolute path), `ids` (glob patterns), `max_daemons` and, in `[max]`, the most `--memory`, `--cpus` and `--limit-*` may ask for (`memory`, `cpus`, `limit_nofile`, ...; a flag with a maximum must then be given). Hooks count as programs and `--health-cmd` and shell lines of `demon.toml` need `sh`. A refused start fails with the broken rule; do not edit the file to get around it.
+
+`--color auto|always|never` (or `color` in the configuration) colors statuses in `list` and `status` and the daemon's stderr in `tail`; `auto` disables colors off a terminal and with `NO_COLOR`.
+
+## Common Workflows
+
+### Starting a Web Server
+```bash
+demon run my-web-server python -m http.server 8080
+demon status my-web-server  # Check if it started
+demon tail my-web-server -f # Monitor logs
+```
+
+### Waiting for Process Completion
+```bash
+demon run batch-job python process_data.py
+demon wait batch-job --timeout 600  # Wait up to 10 minutes
+demon cat batch-job                 # Check output after completion
+```
+
+### Managing Multiple Services
+```bash
+demon run api-server ./api --port 3000
+demon run worker-queue ./worker --config prod.conf
+demon list                  # See all running services
+demon stop api-server       # Stop specific service
+```
+
+## Error Handling
+
+### Common Error Scenarios
+- **"Process already running"**: Another process with the same ID exists
+- **"Command cannot be empty"**: No command specified after the ID
+- **"Process not found"**: No PID file exists for the given ID
+- **"Failed to start process"**: Command not found or permission denied
+
+### Best Practices
+1. Use descriptive, unique IDs for each daemon
+2. Check status before starting to avoid conflicts
+3. Use `demon clean` periodically to remove old files
+4. Monitor logs with `demon tail -f` for debugging
+5. Use `--timeout` with stop for processes that may take time to shutdown
+
+## Integration Tips
+
+### Scripting
+```bash
+# Check if service is running
+if demon status my-service | grep -q "RUNNING"; then
+    echo "Service is running"
+fi
+
+# Read a field without parsing text
+demon status my-service --format json | jq -r .status
+
+# Start service if not running
+demon list --quiet | grep -q "my-service:" || demon run my-service ./my-app
+```
+
+### Process Management
+- Demon handles process detachment automatically
+- Processes continue running even if demon exits
+- Log rotation should be handled by the application itself
+
+This tool is designed for Linux environments and provides a simple interface for managing background processes with persistent logging.
+"#;
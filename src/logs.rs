@@ -0,0 +1,1249 @@
+//! Daemon logs and the `cat` and `tail` commands.
+
+use crate::{
+    agent,
+    ansi::{self, Stripper},
+    color,
+    follow::{self, Follower},
+    format_timestamp,
+    pidfile::{DaemonMeta, build_file_path},
+    status::is_process_running,
+    timeline::Timeline,
+    unix_now,
+    watch::DirWatch,
+};
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use serde::Serialize;
+use std::borrow::Cow;
+use std::io::{BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// Size of the chunks logs are read and printed in
+pub(crate) const CHUNK: usize = 64 * 1024;
+
+/// One of the two logs of a daemon
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+impl Stream {
+    /// Extension of the log file, e.g. `web.stdout`
+    pub(crate) fn extension(self) -> &'static str {
+        match self {
+            Stream::Stdout => "stdout",
+            Stream::Stderr => "stderr",
+        }
+    }
+
+    pub(crate) fn from_extension(extension: &str) -> Option<Self> {
+        match extension {
+            "stdout" => Some(Stream::Stdout),
+            "stderr" => Some(Stream::Stderr),
+            _ => None,
+        }
+    }
+}
+
+/// The file a log is written to when its path is a symbolic link, e.g. onto
+/// another disk. Links are resolved as far as they go, so a dangling link
+/// still names its target.
+pub(crate) fn link_target(path: &Path) -> Option<PathBuf> {
+    let target = std::fs::read_link(path).ok()?;
+    std::fs::canonicalize(path)
+        .ok()
+        .or_else(|| Some(path.parent()?.join(target)))
+}
+
+/// Number of lines in a log, counting a last line without a newline
+pub(crate) fn count_lines(path: &Path) -> std::io::Result<u64> {
+    let mut file = std::fs::File::open(path)?;
+    let mut buf = vec![0; CHUNK];
+    let mut lines = 0;
+    let mut last = b'\n';
+    loop {
+        let read = file.read(&mut buf)?;
+        if read == 0 {
+            break;
+        }
+        lines += buf[..read].iter().filter(|&&byte| byte == b'\n').count() as u64;
+        last = buf[read - 1];
+    }
+    Ok(lines + u64::from(last != b'\n'))
+}
+
+/// Read a log of a daemon, or only its last `lines` lines
+pub(crate) fn read_log(
+    root_dir: &Path,
+    id: &str,
+    stream: Stream,
+    lines: Option<usize>,
+) -> Result<String> {
+    let path = build_file_path(root_dir, id, stream.extension());
+    match lines {
+        Some(lines) => read_last_n_lines(&path, lines),
+        None => std::fs::read_to_string(&path).map_err(Into::into),
+    }
+    .with_context(|| format!("Failed to read {}", path.display()))
+}
+
+/// Output format of `cat` and `tail`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub(crate) enum Output {
+    /// The logs as they are, with `==> file <==` headers between the streams
+    Text,
+    /// One JSON object per line: `{"id", "stream", "ts", "line", "run"}`
+    Json,
+}
+
+/// A log line in JSON output
+#[derive(Serialize)]
+struct LogLine<'a> {
+    id: &'a str,
+    stream: &'static str,
+    /// Unix time the line was read, for lines followed as they are written
+    ts: Option<u64>,
+    line: &'a str,
+    /// Start time of the run that wrote the line
+    run: Option<u64>,
+}
+
+/// Prints the logs of one daemon in the chosen output format
+pub(crate) struct LogPrinter<'a> {
+    root_dir: &'a Path,
+    id: &'a str,
+    output: Output,
+    /// Print a `==> file <==` header whenever the output switches streams
+    show_headers: bool,
+    /// Print the daemon's stderr to our stderr instead of stdout
+    split_streams: bool,
+    /// Print the daemon's stderr in red
+    color: bool,
+    /// Print an empty line before every header but the first, as `tail -f`
+    /// does
+    separate: bool,
+    last_shown: Option<Stream>,
+    /// Start time of the run the lines belong to
+    run: Option<u64>,
+    /// Remove escape sequences from stdout and stderr, with `--strip-ansi`
+    strippers: Option<[Stripper; 2]>,
+    /// Output to stdout, written in chunks rather than line by line
+    out: BufWriter<std::io::Stdout>,
+    /// The first error writing the output, returned by `flush`
+    error: Option<std::io::Error>,
+    /// How long `flush_if_due` keeps output buffered
+    flush_interval: Duration,
+    last_flush: Instant,
+    /// Drop followed lines beyond a rate, with `--max-rate`
+    limit: Option<RateLimit>,
+    /// Prefix every line with `<id>.<stream>:` instead of printing headers
+    label: bool,
+}
+
+/// Keeps at most `max` followed lines a second and counts the others
+#[derive(Debug)]
+struct RateLimit {
+    max: u64,
+    /// Unix time of the current second
+    second: u64,
+    /// Lines kept in the current second
+    kept: u64,
+    /// Lines dropped in the current second
+    dropped: u64,
+    /// Lines dropped in earlier seconds and not yet reported
+    unreported: u64,
+    /// Whether the start of the unfinished last line was kept
+    partial: Option<bool>,
+}
+
+impl RateLimit {
+    fn new(max: u64) -> Self {
+        Self {
+            max,
+            second: 0,
+            kept: 0,
+            dropped: 0,
+            unreported: 0,
+            partial: None,
+        }
+    }
+
+    /// Start counting a new second once the current one is over, or right
+    /// away with `end`, when no more lines come
+    fn roll(&mut self, end: bool) {
+        let now = unix_now();
+        if now != self.second || end {
+            self.second = now;
+            self.kept = 0;
+            self.unreported += std::mem::take(&mut self.dropped);
+        }
+    }
+
+    /// The lines of `text` to print in the current second. A line split between two reads is kept
+    /// or dropped as a whole.
+    fn sample(&mut self, text: &str) -> String {
+        let mut sampled = String::new();
+        for line in text.split_inclusive('\n') {
+            let keep = match self.partial.take() {
+                Some(keep) => keep,
+                None if self.kept < self.max => {
+                    self.kept += 1;
+                    true
+                }
+                None => {
+                    self.dropped += 1;
+                    false
+                }
+            };
+            if keep {
+                sampled.push_str(line);
+            }
+            if !line.ends_with('\n') {
+                self.partial = Some(keep);
+            }
+        }
+        sampled
+    }
+}
+
+impl<'a> LogPrinter<'a> {
+    /// A printer for `streams` of the daemon; with `split_streams` the
+    /// daemon's stderr goes to stderr, so no headers are needed
+    pub(crate) fn new(
+        root_dir: &'a Path,
+        id: &'a str,
+        output: Output,
+        streams: usize,
+        split_streams: bool,
+    ) -> Self {
+        Self {
+            root_dir,
+            id,
+            output,
+            show_headers: streams > 1 && !split_streams,
+            split_streams,
+            color: false,
+            separate: false,
+            last_shown: None,
+            run: DaemonMeta::read(root_dir, id).started_at,
+            strippers: None,
+            out: BufWriter::with_capacity(CHUNK, std::io::stdout()),
+            error: None,
+            flush_interval: Duration::ZERO,
+            last_flush: Instant::now(),
+            limit: None,
+            label: false,
+        }
+    }
+
+    /// Prefix every line with the daemon and stream it comes from, for the
+    /// logs of several daemons in one output
+    pub(crate) fn label_lines(&mut self) {
+        self.label = true;
+        self.show_headers = false;
+    }
+
+    /// Print the logs without escape sequences
+    pub(crate) fn strip_ansi(&mut self) {
+        self.strippers = Some(Default::default());
+    }
+
+    /// `bytes` without escape sequences, once `strip_ansi` was called
+    fn stripped<'b>(&mut self, stream: Stream, bytes: &'b [u8]) -> Cow<'b, [u8]> {
+        match &mut self.strippers {
+            Some([stdout, stderr]) => Cow::Owned(match stream {
+                Stream::Stdout => stdout.strip(bytes),
+                Stream::Stderr => stderr.strip(bytes),
+            }),
+            None => Cow::Borrowed(bytes),
+        }
+    }
+
+    pub(crate) fn is_json(&self) -> bool {
+        self.output == Output::Json
+    }
+
+    /// Read the start time of the run again, after the logs were truncated
+    /// for a new one
+    pub(crate) fn new_run(&mut self) {
+        self.run = DaemonMeta::read(self.root_dir, self.id).started_at;
+    }
+
+    /// Print text read from a log. In JSON each line becomes an object, with
+    /// the current time as `ts` for `live` text, which was just written, and
+    /// no time for text written earlier, since the logs hold no timestamps.
+    /// With `--max-rate`, `live` text beyond the rate is dropped.
+    pub(crate) fn print(&mut self, stream: Stream, text: &str, live: bool) {
+        if live {
+            self.report_dropped(false);
+        }
+        let sampled = match &mut self.limit {
+            Some(limit) if live => Cow::Owned(limit.sample(text)),
+            _ => Cow::Borrowed(text),
+        };
+        if sampled.is_empty() {
+            return;
+        }
+        let stripped = self.stripped(stream, sampled.as_bytes());
+        let text = &*String::from_utf8_lossy(&stripped);
+        if self.output == Output::Json {
+            let ts = live.then(unix_now);
+            for line in text.lines() {
+                let line = LogLine {
+                    id: self.id,
+                    stream: stream.extension(),
+                    ts,
+                    line,
+                    run: self.run,
+                };
+                match serde_json::to_string(&line) {
+                    Ok(json) => self.write(stream, &format!("{json}\n")),
+                    Err(e) => tracing::error!("Failed to encode log line: {}", e),
+                }
+            }
+            return;
+        }
+        self.header(stream);
+        let mut text = Cow::Borrowed(text);
+        if self.color && stream == Stream::Stderr {
+            text = Cow::Owned(
+                text.split_inclusive('\n')
+                    .map(|line| {
+                        let (line, newline) = match line.strip_suffix('\n') {
+                            Some(line) => (line, "\n"),
+                            None => (line, ""),
+                        };
+                        format!("{}{newline}", color::paint(true, line, color::RED))
+                    })
+                    .collect(),
+            );
+        }
+        // Labelled lines are printed whole, so the lines of other daemons
+        // cannot continue them
+        if self.label {
+            let label = format!("{}.{}:", self.id, stream.extension());
+            text = Cow::Owned(
+                text.lines()
+                    .map(|line| format!("{label} {line}\n"))
+                    .collect(),
+            );
+        }
+        self.write(stream, &text);
+    }
+
+    /// Print bytes read from a log as they are, in text output. Unlike
+    /// `print`, this takes logs that are not UTF-8, and fails instead of
+    /// panicking when the output is closed, e.g. by `| head`.
+    pub(crate) fn print_bytes(&mut self, stream: Stream, bytes: &[u8]) -> std::io::Result<()> {
+        let bytes = &*self.stripped(stream, bytes);
+        self.header(stream);
+        match (self.split_streams, stream) {
+            (true, Stream::Stderr) => self
+                .out
+                .flush()
+                .and_then(|_| std::io::stderr().write_all(bytes)),
+            _ => self.out.write_all(bytes),
+        }
+    }
+
+    /// Write the buffered output, failing if any output could not be written.
+    /// Lines dropped by `--max-rate` are counted once their second is over.
+    pub(crate) fn flush(&mut self) -> std::io::Result<()> {
+        self.report_dropped(false);
+        self.last_flush = Instant::now();
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        self.out.flush()?;
+        std::io::stderr().flush()
+    }
+
+    /// Tell how many lines `--max-rate` dropped in the seconds that are over,
+    /// or so far with `end`
+    fn report_dropped(&mut self, end: bool) {
+        let dropped = self.limit.as_mut().and_then(|limit| {
+            limit.roll(end);
+            (limit.unreported > 0).then(|| (std::mem::take(&mut limit.unreported), limit.max))
+        });
+        if let Some((lines, max)) = dropped {
+            self.notice(&format!("[{lines} lines dropped over --max-rate {max}]"));
+        }
+    }
+
+    /// Write the output at the end, with the lines `--max-rate` dropped last
+    pub(crate) fn finish(&mut self) -> std::io::Result<()> {
+        self.report_dropped(true);
+        self.flush()
+    }
+
+    /// Write the buffered output once the flush interval has passed since it
+    /// was last written, so output written quickly goes out in large chunks
+    pub(crate) fn flush_if_due(&mut self) -> std::io::Result<()> {
+        match self.last_flush.elapsed() >= self.flush_interval {
+            true => self.flush(),
+            false => Ok(()),
+        }
+    }
+
+    /// Print a `==> file <==` header when the output switches to `stream`
+    fn header(&mut self, stream: Stream) {
+        if self.show_headers && self.last_shown != Some(stream) {
+            let mut header = format!(
+                "==> {} <==\n",
+                build_file_path(self.root_dir, self.id, stream.extension()).display()
+            );
+            if self.separate && self.last_shown.is_some() {
+                header.insert(0, '\n');
+            }
+            self.write(Stream::Stdout, &header);
+        }
+        self.last_shown = Some(stream);
+    }
+
+    /// Write to stdout, or to stderr for the daemon's stderr when the streams
+    /// are split
+    fn write(&mut self, stream: Stream, text: &str) {
+        let written = match (self.split_streams, stream) {
+            (true, Stream::Stderr) => self
+                .out
+                .flush()
+                .and_then(|_| std::io::stderr().write_all(text.as_bytes())),
+            _ => self.out.write_all(text.as_bytes()),
+        };
+        if let Err(e) = written {
+            self.error.get_or_insert(e);
+        }
+    }
+
+    /// Tell the user something without mixing it into JSON output
+    fn notice(&mut self, message: &str) {
+        match self.output {
+            Output::Text => self.write(Stream::Stdout, &format!("{message}\n")),
+            Output::Json => tracing::info!("{}", message.trim()),
+        }
+    }
+}
+
+/// The part of a log `cat` prints
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Range {
+    All,
+    /// The first lines
+    Head(usize),
+    /// The last lines
+    Tail(usize),
+    /// The last bytes
+    Bytes(u64),
+}
+
+/// The lines written within a time span, from `--since` and `--until`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct Window {
+    /// Unix time of the first lines shown
+    pub(crate) since: Option<u64>,
+    /// Unix time after which lines are no longer shown
+    pub(crate) until: Option<u64>,
+}
+
+impl Window {
+    fn is_all(&self) -> bool {
+        self.since.is_none() && self.until.is_none()
+    }
+
+    /// The timeline of the daemon when the window needs one, failing when it
+    /// has none
+    fn timeline(&self, root_dir: &Path, id: &str) -> Result<Option<Timeline>> {
+        if self.is_all() {
+            return Ok(None);
+        }
+        required_timeline(root_dir, id).map(Some)
+    }
+
+    /// Offsets of the whole lines of a log written within the window
+    fn bounds(
+        &self,
+        timeline: Option<&Timeline>,
+        stream: Stream,
+        file: &mut std::fs::File,
+    ) -> std::io::Result<(u64, u64)> {
+        let len = file.metadata()?.len();
+        let Some(timeline) = timeline else {
+            return Ok((0, len));
+        };
+        let (start, end) = timeline.range(
+            stream,
+            self.since.map(|since| since * 1000),
+            self.until.map(|until| until * 1000),
+        );
+        let end = match end {
+            Some(end) if end < len => line_end(file, end)?,
+            _ => len,
+        };
+        let start = line_start(file, start.min(end))?;
+        Ok((start, end))
+    }
+}
+
+/// The timeline of a daemon, failing when it has none
+fn required_timeline(root_dir: &Path, id: &str) -> Result<Timeline> {
+    Timeline::load(root_dir, id)?.ok_or_else(|| {
+        anyhow::anyhow!(
+            "No timestamps recorded for '{}': only supervised daemons record when their output was written (run with --supervise)",
+            id
+        )
+    })
+}
+
+/// Offset of the start of the line `offset` is in
+fn line_start(file: &mut std::fs::File, offset: u64) -> std::io::Result<u64> {
+    if offset == 0 {
+        return Ok(0);
+    }
+    let mut byte = [0; 1];
+    file.seek(SeekFrom::Start(offset - 1))?;
+    file.read_exact(&mut byte)?;
+    match byte[0] {
+        b'\n' => Ok(offset),
+        _ => tail_offset_before(file, offset, 1),
+    }
+}
+
+/// Offset just after the end of the line `offset` is in, or of the file
+fn line_end(file: &mut std::fs::File, offset: u64) -> std::io::Result<u64> {
+    if offset == 0 {
+        return Ok(0);
+    }
+    file.seek(SeekFrom::Start(offset - 1))?;
+    let mut reader = BufReader::with_capacity(CHUNK, &mut *file);
+    let mut line = Vec::new();
+    let read = reader.read_until(b'\n', &mut line)?;
+    Ok(offset - 1 + read as u64)
+}
+
+/// What `cat` shows and how
+#[derive(Debug, Clone)]
+pub(crate) struct CatOptions {
+    pub(crate) show_stdout: bool,
+    pub(crate) show_stderr: bool,
+    pub(crate) range: Range,
+    /// Applied before `range`, which picks from the lines within it
+    pub(crate) window: Window,
+    pub(crate) output: Output,
+    /// Print the daemon's stderr to stderr instead of stdout
+    pub(crate) split_streams: bool,
+    /// Remove escape sequences
+    pub(crate) strip_ansi: bool,
+}
+
+/// Print the logs of a daemon. They are streamed in chunks, so logs of any
+/// size print in constant memory, and the bytes are printed as they are; JSON
+/// output replaces bytes that are not UTF-8.
+pub(crate) fn cat_logs(id: &str, root_dir: &Path, options: &CatOptions) -> Result<()> {
+    let streams = usize::from(options.show_stdout) + usize::from(options.show_stderr);
+    let mut printer = LogPrinter::new(root_dir, id, options.output, streams, options.split_streams);
+    if options.strip_ansi {
+        printer.strip_ansi();
+    }
+    let timeline = options.window.timeline(root_dir, id)?;
+    let mut files_found = false;
+
+    for (shown, stream) in [
+        (options.show_stdout, Stream::Stdout),
+        (options.show_stderr, Stream::Stderr),
+    ] {
+        if !shown {
+            continue;
+        }
+        let path = build_file_path(root_dir, id, stream.extension());
+        let Ok(mut file) = std::fs::File::open(&path) else {
+            tracing::warn!("Could not read {}", path.display());
+            continue;
+        };
+        let printed = options
+            .window
+            .bounds(timeline.as_ref(), stream, &mut file)
+            .and_then(|bounds| print_log(&mut printer, stream, file, options.range, bounds));
+        match printed {
+            Ok(printed) => files_found |= printed,
+            // The reader has seen enough, e.g. `demon cat web | head`
+            Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => return Ok(()),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        }
+    }
+
+    if !files_found {
+        printer.notice(&format!("No log files found for daemon '{id}'"));
+    }
+
+    printer.flush().or_else(closed_output)
+}
+
+/// Print the `range` of the part of a log between the offsets `bounds`,
+/// returning whether it had anything to print
+fn print_log(
+    printer: &mut LogPrinter,
+    stream: Stream,
+    mut file: std::fs::File,
+    range: Range,
+    (lower, upper): (u64, u64),
+) -> std::io::Result<bool> {
+    let start = match range {
+        Range::All | Range::Head(_) => lower,
+        Range::Tail(lines) => tail_offset_before(&mut file, upper, lines)?.max(lower),
+        Range::Bytes(bytes) => upper.saturating_sub(bytes).max(lower),
+    };
+    file.seek(SeekFrom::Start(start))?;
+    let mut reader = BufReader::with_capacity(CHUNK, file.take(upper - start));
+    // Lines left to print with --head
+    let mut lines_left = match range {
+        Range::Head(lines) => Some(lines),
+        _ => None,
+    };
+    let mut printed = false;
+
+    if printer.is_json() {
+        let mut line = Vec::new();
+        while lines_left != Some(0) && reader.read_until(b'\n', &mut line)? > 0 {
+            printer.print(stream, &String::from_utf8_lossy(&line), false);
+            printed = true;
+            lines_left = lines_left.map(|left| left - 1);
+            line.clear();
+        }
+        return Ok(printed);
+    }
+
+    while lines_left != Some(0) {
+        let chunk = reader.fill_buf()?;
+        if chunk.is_empty() {
+            break;
+        }
+        let mut take = chunk.len();
+        if let Some(left) = &mut lines_left {
+            let mut newlines = chunk.iter().enumerate().filter(|(_, byte)| **byte == b'\n');
+            match newlines.nth(*left - 1) {
+                Some((last, _)) => {
+                    take = last + 1;
+                    *left = 0;
+                }
+                None => *left -= chunk.iter().filter(|byte| **byte == b'\n').count(),
+            }
+        }
+        printer.print_bytes(stream, &chunk[..take])?;
+        printed = true;
+        reader.consume(take);
+    }
+    Ok(printed)
+}
+
+/// What `logs --merge` shows and how
+#[derive(Debug, Clone)]
+pub(crate) struct MergeOptions {
+    pub(crate) window: Window,
+    pub(crate) output: Output,
+    /// Print the daemons' stderr in red
+    pub(crate) color: bool,
+    /// Remove escape sequences
+    pub(crate) strip_ansi: bool,
+}
+
+/// A log being merged, with its next line
+struct MergeSource {
+    /// Index of the daemon in the merged IDs
+    daemon: usize,
+    stream: Stream,
+    reader: BufReader<std::io::Take<std::fs::File>>,
+    /// Offset of the end of `line` in the log
+    offset: u64,
+    line: Vec<u8>,
+    /// Unix time in milliseconds `line` was written by
+    ms: u64,
+}
+
+impl MergeSource {
+    /// Read the next line, returning false at the end of the log
+    fn advance(&mut self, timeline: &Timeline) -> std::io::Result<bool> {
+        self.line.clear();
+        let read = self.reader.read_until(b'\n', &mut self.line)?;
+        self.offset += read as u64;
+        self.ms = timeline.time_of(self.stream, self.offset);
+        Ok(read > 0)
+    }
+
+    fn label(&self, ids: &[String]) -> String {
+        format!("{}.{}", ids[self.daemon], self.stream.extension())
+    }
+}
+
+/// Print the stdout and stderr of daemons as one stream, in the order the
+/// lines were written. Lines are ordered by the sample of the timeline that
+/// first saw them, so lines written within the same interval keep the order
+/// of `ids`, stdout before stderr. Every log is read line by line, so logs of
+/// any size merge in constant memory.
+pub(crate) fn merge_logs(ids: &[String], root_dir: &Path, options: &MergeOptions) -> Result<()> {
+    let mut timelines = Vec::new();
+    let mut runs = Vec::new();
+    let mut sources = Vec::new();
+    for (daemon, id) in ids.iter().enumerate() {
+        let timeline = required_timeline(root_dir, id)?;
+        for stream in [Stream::Stdout, Stream::Stderr] {
+            let path = build_file_path(root_dir, id, stream.extension());
+            let Ok(mut file) = std::fs::File::open(&path) else {
+                tracing::warn!("Could not read {}", path.display());
+                continue;
+            };
+            let mut source = options
+                .window
+                .bounds(Some(&timeline), stream, &mut file)
+                .and_then(|(start, end)| {
+                    file.seek(SeekFrom::Start(start))?;
+                    Ok(MergeSource {
+                        daemon,
+                        stream,
+                        reader: BufReader::with_capacity(CHUNK, file.take(end - start)),
+                        offset: start,
+                        line: Vec::new(),
+                        ms: 0,
+                    })
+                })
+                .with_context(|| format!("Failed to read {}", path.display()))?;
+            if source.advance(&timeline)? {
+                sources.push(source);
+            }
+        }
+        timelines.push(timeline);
+        runs.push(DaemonMeta::read(root_dir, id).started_at);
+    }
+
+    let width = sources
+        .iter()
+        .map(|source| source.label(ids).len())
+        .max()
+        .unwrap_or_default();
+    let mut stdout = std::io::stdout().lock();
+    while let Some((next, _)) = sources
+        .iter()
+        .enumerate()
+        .min_by_key(|(index, source)| (source.ms, *index))
+    {
+        let source = &mut sources[next];
+        let line = match options.strip_ansi {
+            true => Cow::Owned(ansi::strip(&source.line)),
+            false => Cow::Borrowed(&source.line[..]),
+        };
+        let printed = match options.output {
+            Output::Json => {
+                let text = String::from_utf8_lossy(&line);
+                let line = LogLine {
+                    id: &ids[source.daemon],
+                    stream: source.stream.extension(),
+                    ts: Some(source.ms / 1000),
+                    line: text.lines().next().unwrap_or_default(),
+                    run: runs[source.daemon],
+                };
+                writeln!(stdout, "{}", serde_json::to_string(&line)?)
+            }
+            Output::Text => {
+                let line = line.strip_suffix(b"\n").unwrap_or(&line);
+                let prefix = format!(
+                    "{}.{:03} {:<width$} ",
+                    format_timestamp(source.ms / 1000),
+                    source.ms % 1000,
+                    format!("{}:", source.label(ids)),
+                    width = width + 1
+                );
+                stdout.write_all(prefix.as_bytes()).and_then(|_| {
+                    match options.color && source.stream == Stream::Stderr {
+                        true => write!(
+                            stdout,
+                            "{}",
+                            color::paint(true, &String::from_utf8_lossy(line), color::RED)
+                        ),
+                        false => stdout.write_all(line),
+                    }
+                    .and_then(|_| stdout.write_all(b"\n"))
+                })
+            }
+        };
+        match printed {
+            Ok(()) => {}
+            // The reader has seen enough, e.g. `demon logs web --merge | head`
+            Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => return Ok(()),
+            Err(e) => return Err(e.into()),
+        }
+        if !source.advance(&timelines[source.daemon])? {
+            sources.remove(next);
+        }
+    }
+    Ok(())
+}
+
+/// Offset of the last `lines` lines of a file, found by reading it backwards
+/// from the end in chunks, so only those lines are read and a log of any size
+/// takes as long as its last lines
+pub(crate) fn tail_offset(file: &mut std::fs::File, lines: usize) -> std::io::Result<u64> {
+    let len = file.metadata()?.len();
+    tail_offset_before(file, len, lines)
+}
+
+/// Offset of the last `lines` lines before the offset `len`
+fn tail_offset_before(file: &mut std::fs::File, len: u64, lines: usize) -> std::io::Result<u64> {
+    if lines == 0 {
+        return Ok(len);
+    }
+    let mut buf = vec![0; CHUNK];
+    let mut end = len;
+    let mut newlines = 0;
+    while end > 0 {
+        let start = end.saturating_sub(CHUNK as u64);
+        let chunk = &mut buf[..(end - start) as usize];
+        file.seek(SeekFrom::Start(start))?;
+        file.read_exact(chunk)?;
+        for (i, &byte) in chunk.iter().enumerate().rev() {
+            let offset = start + i as u64;
+            // A newline at the end of the file ends the last line, it does
+            // not start another one
+            if byte == b'\n' && offset + 1 != len {
+                newlines += 1;
+                if newlines == lines {
+                    return Ok(offset + 1);
+                }
+            }
+        }
+        end = start;
+    }
+    Ok(0)
+}
+
+/// What `tail` shows and how
+#[derive(Debug, Clone)]
+pub(crate) struct TailOptions {
+    pub(crate) show_stdout: bool,
+    pub(crate) show_stderr: bool,
+    /// Keep printing new output until Ctrl+C
+    pub(crate) follow: bool,
+    /// Number of lines to show from the end
+    pub(crate) lines: usize,
+    /// Poll the logs instead of using inotify
+    pub(crate) poll: bool,
+    pub(crate) output: Output,
+    /// Print the daemon's stderr to stderr instead of stdout
+    pub(crate) split_streams: bool,
+    /// Print the daemon's stderr in red
+    pub(crate) color: bool,
+    /// Stop following once the daemon is no longer running
+    pub(crate) until_exit: bool,
+    /// Only lines written within this window; `lines` picks from them
+    pub(crate) window: Window,
+    /// Remove escape sequences
+    pub(crate) strip_ansi: bool,
+    /// How long followed output may stay buffered before it is written
+    pub(crate) flush_interval: Duration,
+    /// Most lines a second to print while following, dropping the others
+    pub(crate) max_rate: Option<u64>,
+}
+
+/// The streams `tail` shows
+fn shown_streams(options: &TailOptions) -> Vec<Stream> {
+    [
+        (options.show_stdout, Stream::Stdout),
+        (options.show_stderr, Stream::Stderr),
+    ]
+    .into_iter()
+    .filter_map(|(shown, stream)| shown.then_some(stream))
+    .collect()
+}
+
+/// A printer for the shown streams of a daemon, set up from the options
+fn tail_printer<'a>(
+    root_dir: &'a Path,
+    id: &'a str,
+    streams: &[Stream],
+    options: &TailOptions,
+) -> LogPrinter<'a> {
+    let mut printer = LogPrinter::new(
+        root_dir,
+        id,
+        options.output,
+        streams.len(),
+        options.split_streams,
+    );
+    printer.color = options.color;
+    if options.strip_ansi {
+        printer.strip_ansi();
+    }
+    printer
+}
+
+/// Print the last lines of the shown streams of a daemon, returning whether
+/// it has any
+fn print_last_lines(
+    id: &str,
+    root_dir: &Path,
+    streams: &[Stream],
+    options: &TailOptions,
+    printer: &mut LogPrinter,
+) -> Result<bool> {
+    let timeline = options.window.timeline(root_dir, id)?;
+    let mut files_found = false;
+    for &stream in streams {
+        let path = build_file_path(root_dir, id, stream.extension());
+        let Ok(mut file) = std::fs::File::open(&path) else {
+            continue;
+        };
+        let (lower, upper) = options
+            .window
+            .bounds(timeline.as_ref(), stream, &mut file)?;
+        let start = tail_offset_before(&mut file, upper, options.lines)?.max(lower);
+        file.seek(SeekFrom::Start(start))?;
+        let mut content = Vec::new();
+        file.take(upper - start).read_to_end(&mut content)?;
+        let content = String::from_utf8_lossy(&content);
+        if !content.is_empty() {
+            files_found = true;
+            printer.print(stream, &content, false);
+        }
+    }
+    Ok(files_found)
+}
+
+/// Followers of the shown streams of a daemon. Each tail has its own, so any
+/// number of tails of the same daemon can run side by side. They start at
+/// the last lines, and a log created or truncated later is followed from its
+/// start.
+fn tail_followers(
+    id: &str,
+    root_dir: &Path,
+    streams: &[Stream],
+    options: &TailOptions,
+) -> Result<Vec<(Stream, Follower)>> {
+    let timeline = options.window.timeline(root_dir, id)?;
+    streams
+        .iter()
+        .map(|&stream| {
+            let path = build_file_path(root_dir, id, stream.extension());
+            let since = match std::fs::File::open(&path) {
+                Ok(mut file) => {
+                    options
+                        .window
+                        .bounds(timeline.as_ref(), stream, &mut file)?
+                        .0
+                }
+                Err(_) => 0,
+            };
+            let follower = Follower::from_last_lines(path, options.lines, since)?;
+            Ok((stream, follower.with_batch(follow::BATCH)))
+        })
+        .collect()
+}
+
+/// Watch the root directory and the directories of the followed logs for new
+/// files and changes. Writes to a log that links elsewhere, e.g. onto another
+/// disk, show up at its target. Returns the watch and the files to look for.
+fn watch_followers<'a>(
+    root_dir: &Path,
+    followers: impl Iterator<Item = &'a Follower>,
+    poll: bool,
+) -> (DirWatch, Vec<PathBuf>) {
+    let mut paths: Vec<PathBuf> = Vec::new();
+    let mut dirs: Vec<PathBuf> = vec![root_dir.to_path_buf()];
+    for follower in followers {
+        paths.push(follower.path().to_path_buf());
+        if let Some(dir) = follower
+            .path()
+            .parent()
+            .filter(|dir| dir.is_dir() && !dirs.iter().any(|known| known == dir))
+        {
+            dirs.push(dir.to_path_buf());
+        }
+        if let Some(target) = link_target(follower.path()) {
+            if let Some(dir) = target
+                .parent()
+                .filter(|dir| !dirs.iter().any(|known| known == dir))
+            {
+                dirs.push(dir.to_path_buf());
+            }
+            paths.push(target);
+        }
+    }
+    let dirs: Vec<&Path> = dirs.iter().map(PathBuf::as_path).collect();
+    (DirWatch::new(&dirs, poll), paths)
+}
+
+/// Stop a follow loop on Ctrl+C
+fn ctrlc_flag() -> Result<std::sync::Arc<std::sync::atomic::AtomicBool>> {
+    let running = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(true));
+    let r = running.clone();
+    ctrlc::set_handler(move || {
+        r.store(false, std::sync::atomic::Ordering::SeqCst);
+    })?;
+    Ok(running)
+}
+
+pub(crate) fn tail_logs(id: &str, root_dir: &Path, options: &TailOptions) -> Result<()> {
+    let streams = shown_streams(options);
+    let mut printer = tail_printer(root_dir, id, &streams, options);
+
+    if !options.follow {
+        // Non-follow mode: just show the last n lines and exit
+        if !print_last_lines(id, root_dir, &streams, options, &mut printer)? {
+            printer.notice(&format!("No log files found for daemon '{id}'"));
+        }
+        return printer.flush().or_else(closed_output);
+    }
+
+    let mut followers = tail_followers(id, root_dir, &streams, options)?;
+    printer.separate = true;
+    printer.flush_interval = options.flush_interval;
+    printer.limit = options.max_rate.map(RateLimit::new);
+
+    if !followers
+        .iter()
+        .any(|(_, follower)| follower.path().exists())
+    {
+        printer.notice(&format!(
+            "No log files found for daemon '{id}'. Watching for new files..."
+        ));
+    }
+    // Checked before reading, so what the daemon wrote before exiting has been
+    // printed when the tail stops
+    let mut exited = options.until_exit && !is_process_running(root_dir, id)?;
+    while print_follow_updates(&mut followers, &mut printer, false)? {}
+    if let Err(e) = printer.flush() {
+        return closed_output(e);
+    }
+    if exited {
+        tracing::info!("Daemon '{}' is not running, stopping", id);
+        return Ok(());
+    }
+
+    // A running agent pushes new lines as they are written; it cannot tell
+    // when the daemon exits, so --until-exit watches the files itself
+    let agent_streams = match (options.show_stdout, options.show_stderr) {
+        (true, false) => "stdout",
+        (false, true) => "stderr",
+        _ => "both",
+    };
+    if !options.until_exit && agent::tail(root_dir, id, agent_streams, &mut printer)? {
+        printer.finish().or_else(closed_output)?;
+        printer.notice("\nTailing stopped.");
+        return Ok(());
+    }
+
+    match options.until_exit {
+        true => tracing::info!("Watching for changes to log files until '{}' exits...", id),
+        false => tracing::info!("Watching for changes to log files... Press Ctrl+C to stop."),
+    }
+
+    let (mut watch, paths) = watch_followers(
+        root_dir,
+        followers.iter().map(|(_, follower)| follower),
+        options.poll,
+    );
+    let running = ctrlc_flag()?;
+
+    let paths: Vec<&Path> = paths.iter().map(PathBuf::as_path).collect();
+    // Whether a follower stopped at its batch, so there is more to read now
+    let mut behind = false;
+    while running.load(std::sync::atomic::Ordering::SeqCst) {
+        if options.until_exit && !exited {
+            exited = !is_process_running(root_dir, id)?;
+        }
+        let changed = behind || watch.changed(&paths, Duration::from_millis(100));
+        if changed || exited {
+            match print_follow_updates(&mut followers, &mut printer, true) {
+                Ok(more) => behind = more,
+                Err(e) => tracing::error!("Error reading log files: {}", e),
+            }
+        }
+        // Output is written in chunks while the logs keep growing, and as soon
+        // as they stop
+        let flushed = match changed && !exited {
+            true => printer.flush_if_due(),
+            false => printer.flush(),
+        };
+        if let Err(e) = flushed {
+            return closed_output(e);
+        }
+        if exited && !behind {
+            tracing::info!("Daemon '{}' exited, stopping", id);
+            return printer.finish().or_else(closed_output);
+        }
+    }
+
+    printer.finish().or_else(closed_output)?;
+    printer.notice("\nTailing stopped.");
+    Ok(())
+}
+
+/// A daemon followed by [`tail_group`]
+struct Tailed<'a> {
+    id: &'a str,
+    printer: LogPrinter<'a>,
+    followers: Vec<(Stream, Follower)>,
+    /// Whether the daemon exited, with `--until-exit`
+    exited: bool,
+    /// Whether a follower stopped at its batch
+    behind: bool,
+}
+
+/// `tail --group` and `--label`: the logs of several daemons in one output,
+/// every line prefixed with the daemon and stream it comes from (or with
+/// the ID in JSON). Lines are printed whole, a partial line once it is
+/// finished. Following stops on Ctrl+C, or with `--until-exit` once every
+/// daemon has exited.
+pub(crate) fn tail_group(ids: &[String], root_dir: &Path, options: &TailOptions) -> Result<()> {
+    if let [id] = ids {
+        return tail_logs(id, root_dir, options);
+    }
+    let streams = shown_streams(options);
+    let printer = |id| {
+        let mut printer = tail_printer(root_dir, id, &streams, options);
+        printer.label_lines();
+        printer
+    };
+
+    if !options.follow {
+        for id in ids {
+            let mut printer = printer(id);
+            if !print_last_lines(id, root_dir, &streams, options, &mut printer)? {
+                printer.notice(&format!("No log files found for daemon '{id}'"));
+            }
+            printer.flush().or_else(closed_output)?;
+        }
+        return Ok(());
+    }
+
+    let mut tailed = Vec::new();
+    for id in ids {
+        let mut printer = printer(id);
+        printer.flush_interval = options.flush_interval;
+        printer.limit = options.max_rate.map(RateLimit::new);
+        let mut daemon = Tailed {
+            id,
+            printer,
+            followers: tail_followers(id, root_dir, &streams, options)?,
+            exited: options.until_exit && !is_process_running(root_dir, id)?,
+            behind: false,
+        };
+        while print_follow_updates(&mut daemon.followers, &mut daemon.printer, false)? {}
+        if let Err(e) = daemon.printer.flush() {
+            return closed_output(e);
+        }
+        tailed.push(daemon);
+    }
+    if tailed.iter().all(|daemon| daemon.exited) {
+        tracing::info!("No daemon is running, stopping");
+        return Ok(());
+    }
+    match options.until_exit {
+        true => tracing::info!("Watching for changes to log files until the daemons exit..."),
+        false => tracing::info!("Watching for changes to log files... Press Ctrl+C to stop."),
+    }
+
+    let (mut watch, paths) = watch_followers(
+        root_dir,
+        tailed
+            .iter()
+            .flat_map(|daemon| daemon.followers.iter().map(|(_, follower)| follower)),
+        options.poll,
+    );
+    let running = ctrlc_flag()?;
+
+    let paths: Vec<&Path> = paths.iter().map(PathBuf::as_path).collect();
+    while running.load(std::sync::atomic::Ordering::SeqCst) {
+        for daemon in &mut tailed {
+            if options.until_exit && !daemon.exited {
+                daemon.exited = !is_process_running(root_dir, daemon.id)?;
+            }
+        }
+        let behind = tailed.iter().any(|daemon| daemon.behind);
+        let changed = behind || watch.changed(&paths, Duration::from_millis(100));
+        for daemon in &mut tailed {
+            if changed || daemon.exited {
+                match print_follow_updates(&mut daemon.followers, &mut daemon.printer, true) {
+                    Ok(more) => daemon.behind = more,
+                    Err(e) => tracing::error!("Error reading log files: {}", e),
+                }
+            }
+            let flushed = match changed && !daemon.exited {
+                true => daemon.printer.flush_if_due(),
+                false => daemon.printer.flush(),
+            };
+            if let Err(e) = flushed {
+                return closed_output(e);
+            }
+        }
+        if tailed.iter().all(|daemon| daemon.exited && !daemon.behind) {
+            tracing::info!("Every daemon exited, stopping");
+            for daemon in &mut tailed {
+                daemon.printer.finish().or_else(closed_output)?;
+            }
+            return Ok(());
+        }
+    }
+
+    for daemon in &mut tailed {
+        daemon.printer.finish().or_else(closed_output)?;
+    }
+    tailed[0].printer.notice("\nTailing stopped.");
+    tailed[0].printer.flush().or_else(closed_output)
+}
+
+/// Stop quietly when the reader of the output has gone, e.g. after
+/// `demon tail -f web | head`
+fn closed_output(e: std::io::Error) -> Result<()> {
+    match e.kind() {
+        std::io::ErrorKind::BrokenPipe => Ok(()),
+        _ => Err(e.into()),
+    }
+}
+
+pub(crate) fn read_last_n_lines<P: AsRef<Path>>(file_path: P, n: usize) -> Result<String> {
+    let mut file = std::fs::File::open(file_path)?;
+    let start = tail_offset(&mut file, n)?;
+    file.seek(SeekFrom::Start(start))?;
+    let mut content = Vec::new();
+    file.read_to_end(&mut content)?;
+    Ok(String::from_utf8_lossy(&content).into_owned())
+}
+
+/// Print what was written to the followed files since the last call; `live`
+/// tells whether it was written just now, rather than before `tail` started.
+/// Returns whether a follower has more to read right away.
+fn print_follow_updates(
+    followers: &mut [(Stream, Follower)],
+    printer: &mut LogPrinter,
+    live: bool,
+) -> Result<bool> {
+    let mut behind = false;
+    for (stream, follower) in followers.iter_mut() {
+        // JSON and labelled lines need whole lines, so a partial line waits
+        // for the next poll
+        let (rotated, text) = if printer.is_json() || printer.label {
+            let (rotated, lines) = follower.poll_lines()?;
+            (
+                rotated,
+                lines.iter().map(|line| format!("{line}\n")).collect(),
+            )
+        } else {
+            let update = follower.poll()?;
+            (update.rotated, update.text)
+        };
+        if rotated {
+            tracing::info!(
+                "{} was truncated or replaced (rotation {}), following from the start",
+                follower.path().display(),
+                follower.epoch()
+            );
+            printer.new_run();
+        }
+        if !text.is_empty() {
+            printer.print(*stream, &text, live);
+        }
+        behind |= follower.is_behind();
+    }
+    Ok(behind)
+}
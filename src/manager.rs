@@ -0,0 +1,125 @@
+//! [`DaemonManager`]: the library API, for tools and test harnesses that manage
+//! daemons without shelling out to the `demon` binary.
+
+use crate::{
+    find_git_root, layout,
+    logs::{Stream, read_log},
+    pidfile::{RootLock, daemon_ids},
+    runner::{RunOptions, run_daemon},
+    status::{DaemonStatus, daemon_status, wait_daemon},
+    stop::{Stopped, stop_daemon},
+    store::{self, StateStore},
+};
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// How often [`DaemonManager::wait`] checks whether the daemon has exited
+const WAIT_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The daemons of one root directory, sharing their files with the `demon`
+/// command line: daemons spawned here show up in `demon list` and vice versa.
+///
+/// ```no_run
+/// use demon::{DaemonManager, RunOptions, Stream};
+/// use std::time::Duration;
+///
+/// let manager = DaemonManager::new("/tmp/demon")?;
+/// let command = ["python3", "-m", "http.server", "8080"].map(String::from);
+/// manager.spawn("web", &command, &RunOptions::default())?;
+/// assert!(manager.status("web")?.is_some_and(|status| status.running));
+/// print!("{}", manager.logs("web", Stream::Stderr, Some(10))?);
+/// manager.stop("web", Duration::from_secs(10))?;
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct DaemonManager {
+    root_dir: PathBuf,
+}
+
+impl DaemonManager {
+    /// Manage the daemons in an existing directory
+    pub fn new(root_dir: impl Into<PathBuf>) -> Result<Self> {
+        let root_dir = root_dir.into();
+        if !root_dir.is_dir() {
+            return Err(anyhow::anyhow!(
+                "Root directory does not exist: {}",
+                root_dir.display()
+            ));
+        }
+        layout::migrate(&root_dir);
+        Ok(Self { root_dir })
+    }
+
+    /// Manage the daemons in an existing directory, keeping their state in
+    /// `store` instead of files. The store serves the directory for the rest
+    /// of the process, also to managers created later; other processes do
+    /// not see it, so its daemons cannot be supervised.
+    pub fn with_store(
+        root_dir: impl Into<PathBuf>,
+        store: impl StateStore + 'static,
+    ) -> Result<Self> {
+        let manager = Self::new(root_dir)?;
+        store::register(&manager.root_dir, Arc::new(store))?;
+        Ok(manager)
+    }
+
+    /// Manage the daemons in `.demon` at the root of the git repository
+    /// containing the current directory, like the command line does
+    pub fn discover() -> Result<Self> {
+        let root_dir = find_git_root()?;
+        layout::migrate(&root_dir);
+        Ok(Self { root_dir })
+    }
+
+    /// Directory holding the PID files and logs
+    pub fn root_dir(&self) -> &Path {
+        &self.root_dir
+    }
+
+    /// Start `command` in the background as daemon `id`, returning its PID.
+    /// Fails if a daemon with the same ID is already running.
+    pub fn spawn(&self, id: &str, command: &[String], options: &RunOptions) -> Result<u32> {
+        if command.is_empty() {
+            return Err(anyhow::anyhow!("Command cannot be empty"));
+        }
+        run_daemon(id, command, &self.root_dir, options)
+    }
+
+    /// Stop a daemon with SIGTERM, sending SIGKILL if it is still running
+    /// after `timeout`
+    pub fn stop(&self, id: &str, timeout: Duration) -> Result<Stopped> {
+        stop_daemon(id, timeout.as_secs(), &self.root_dir)
+    }
+
+    /// The status of a daemon, or None if there is no daemon with this ID
+    pub fn status(&self, id: &str) -> Result<Option<DaemonStatus>> {
+        daemon_status(&self.root_dir, id)
+    }
+
+    /// The status of every daemon, sorted by ID
+    pub fn list(&self) -> Result<Vec<DaemonStatus>> {
+        let _lock = RootLock::shared(&self.root_dir)?;
+        let ids = daemon_ids(&self.root_dir)?;
+        let mut daemons = Vec::new();
+        for id in ids {
+            if let Some(status) = daemon_status(&self.root_dir, &id)? {
+                daemons.push(status);
+            }
+        }
+        Ok(daemons)
+    }
+
+    /// Wait for a running daemon to exit, for at most `timeout` (forever if
+    /// None). Fails if the daemon is not running or the timeout is reached.
+    pub fn wait(&self, id: &str, timeout: Option<Duration>) -> Result<()> {
+        wait_daemon(id, timeout, WAIT_INTERVAL, &self.root_dir)?;
+        Ok(())
+    }
+
+    /// The output of a daemon, or only its last `lines` lines
+    pub fn logs(&self, id: &str, stream: Stream, lines: Option<usize>) -> Result<String> {
+        read_log(&self.root_dir, id, stream, lines)
+    }
+}
@@ -0,0 +1,255 @@
+//! `demon.toml`: the daemons of a project, started together by `demon up` and
+//! stopped by `demon down`.
+//!
+//! The manifest sits next to the root directory (`demon.toml` beside `.demon`,
+//! or inside a root directory given with `--root-dir`) and lists daemons in the
+//! order they start:
+//!
+//! ```toml
+//! [daemons.db]
+//! command = "postgres -D data"    # a string is run by sh -c
+//!
+//! [daemons.web]
+//! command = ["python3", "-m", "http.server", "8000"]
+//! supervise = true
+//! restart = "on-failure"
+//! ```
+//!
+//! Besides `command`, a daemon takes the options of `demon run` with
+//! underscores (`health_cmd`, `on_crash`, `limit_nofile`, ...); `every`,
+//! `health_interval`, `watchdog` and `max_runtime` are in seconds. Commands
+//! and relative paths are relative to the directory of the manifest.
+//!
+//! A `[retention]` table sets what `demon gc` keeps of the logs:
+//! `max_total_bytes`, `max_age` in seconds and `max_copies` per daemon.
+
+use crate::{
+    agent::Policy,
+    gc::Retention,
+    hooks::Hooks,
+    stdin::{Stdin, StdinMode},
+};
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use toml::{Table, Value};
+
+pub(crate) const FILE_NAME: &str = "demon.toml";
+
+/// A command given as a shell line or as program and arguments
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum CommandLine {
+    Shell(String),
+    Args(Vec<String>),
+}
+
+#[derive(Deserialize)]
+struct Entry {
+    command: CommandLine,
+    #[serde(default)]
+    supervise: bool,
+    #[serde(default)]
+    pty: bool,
+    #[serde(default)]
+    stdin: StdinMode,
+    stdin_file: Option<PathBuf>,
+    #[serde(flatten)]
+    hooks: Hooks,
+    #[serde(flatten)]
+    policy: Policy,
+}
+
+/// A daemon declared in the manifest
+#[derive(Debug, Clone)]
+pub(crate) struct Service {
+    pub(crate) id: String,
+    pub(crate) command: Vec<String>,
+    pub(crate) supervise: bool,
+    pub(crate) pty: bool,
+    pub(crate) stdin: Stdin,
+    pub(crate) hooks: Hooks,
+    pub(crate) policy: Policy,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Manifest {
+    /// Directory the manifest is in, where its daemons run
+    pub(crate) dir: PathBuf,
+    /// Daemons in the order they are declared
+    pub(crate) services: Vec<Service>,
+    /// What `demon gc` keeps, from the `[retention]` table
+    pub(crate) retention: Retention,
+}
+
+/// Path of the manifest belonging to a root directory
+pub(crate) fn path(root_dir: &Path) -> PathBuf {
+    let root_dir = std::path::absolute(root_dir).unwrap_or_else(|_| root_dir.to_path_buf());
+    match root_dir.parent() {
+        Some(parent) if root_dir.file_name() == Some(".demon".as_ref()) => parent.join(FILE_NAME),
+        _ => root_dir.join(FILE_NAME),
+    }
+}
+
+impl Manifest {
+    /// Read the manifest of a root directory
+    pub(crate) fn load(root_dir: &Path) -> Result<Self> {
+        let path = path(root_dir);
+        let contents = std::fs::read_to_string(&path).map_err(|e| {
+            if e.kind() == std::io::ErrorKind::NotFound {
+                anyhow::anyhow!(
+                    "No manifest found at {}; create one with 'demon init'",
+                    path.display()
+                )
+            } else {
+                anyhow::anyhow!("Failed to read {}: {}", path.display(), e)
+            }
+        })?;
+        Self::parse(&contents, &path)
+    }
+
+    fn parse(contents: &str, path: &Path) -> Result<Self> {
+        let dir = path.parent().unwrap_or(Path::new("."));
+        let table = contents
+            .parse::<Table>()
+            .with_context(|| format!("{}: invalid TOML", path.display()))?;
+        let daemons = match table.get("daemons") {
+            None => Table::new(),
+            Some(Value::Table(daemons)) => daemons.clone(),
+            Some(_) => {
+                return Err(anyhow::anyhow!(
+                    "{}: 'daemons' must be a table",
+                    path.display()
+                ));
+            }
+        };
+
+        let mut services = Vec::new();
+        for (id, value) in daemons {
+            let entry: Entry = value
+                .try_into()
+                .map_err(|e| anyhow::anyhow!("{}: daemon '{}': {}", path.display(), id, e))?;
+            let command = match entry.command {
+                CommandLine::Shell(line) => vec!["sh".to_string(), "-c".to_string(), line],
+                CommandLine::Args(args) => args,
+            };
+            if command.is_empty() || command[0].is_empty() {
+                return Err(anyhow::anyhow!(
+                    "{}: daemon '{}' has an empty command",
+                    path.display(),
+                    id
+                ));
+            }
+            services.push(Service {
+                id,
+                command,
+                supervise: entry.supervise,
+                pty: entry.pty,
+                stdin: Stdin::new(
+                    entry.stdin,
+                    entry.stdin_file.map(|file| dir.join(file)).as_deref(),
+                )?,
+                hooks: entry.hooks,
+                policy: entry.policy,
+            });
+        }
+        let retention = match table.get("retention") {
+            None => Retention::default(),
+            Some(value) => value
+                .clone()
+                .try_into()
+                .map_err(|e| anyhow::anyhow!("{}: retention: {}", path.display(), e))?,
+        };
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            services,
+            retention,
+        })
+    }
+
+    /// The daemons with the given IDs, or all of them if none are given, in
+    /// the order they are declared whatever the order of `ids`
+    pub(crate) fn select(&self, ids: &[String]) -> Result<Vec<&Service>> {
+        if let Some(id) = ids
+            .iter()
+            .find(|id| !self.services.iter().any(|service| &service.id == *id))
+        {
+            return Err(anyhow::anyhow!("No daemon '{}' in {}", id, FILE_NAME));
+        }
+        Ok(self
+            .services
+            .iter()
+            .filter(|service| ids.is_empty() || ids.contains(&service.id))
+            .collect())
+    }
+}
+
+/// Starter manifest written by `demon init`
+pub(crate) const STARTER: &str = "\
+# Daemons of this project, started by `demon up` and stopped by `demon down`.
+# Each [daemons.<id>] has a command, run by `sh -c` when it is a string, and
+# takes the options of `demon run` with underscores: supervise, pty, stdin,
+# stdin_file, restart, health_cmd, health_interval, every, sd_notify, watchdog
+# and max_runtime (seconds), catch_up, singleton, reload_signal, strip_ansi,
+# on_start, on_exit, on_crash, notify_url, ship_logs, trace_context,
+# otel_endpoint and the
+# resource limits limit_nofile, limit_core, limit_as, ... (e.g. \"1024:4096\"),
+# umask (e.g. \"027\"), core_dump_dir, nice, ionice_class, ionice_level,
+# cpu_affinity (e.g. \"0-3\"), oom_score_adj, memory (e.g. \"512M\"), cpus,
+# sandbox, ro_path and rw_path (lists), no_network, systemd_scope,
+# require_free_memory (e.g. \"2G\"), require_free_disk (e.g. [\"5G@/var\"]),
+# watch and watch_ignore (lists), group and label (e.g. [\"tier=web\"]).
+#
+# [daemons.web]
+# command = \"python3 -m http.server 8000\"
+# restart = \"on-failure\"
+#
+# What `demon gc` keeps of old logs and log copies (ages in seconds):
+# [retention]
+# max_total_bytes = 1073741824
+# max_age = 1209600
+# max_copies = 10
+";
+
+/// Manifest running the processes of a Procfile (`<name>: <command>` lines)
+pub(crate) fn from_procfile(contents: &str, path: &Path) -> Result<String> {
+    let mut daemons = Table::new();
+    for (number, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (name, command) = line
+            .split_once(':')
+            .map(|(name, command)| (name.trim(), command.trim()))
+            .filter(|(name, command)| {
+                !name.is_empty()
+                    && !command.is_empty()
+                    && name
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+            })
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "{}:{}: expected '<name>: <command>'",
+                    path.display(),
+                    number + 1
+                )
+            })?;
+        let mut daemon = Table::new();
+        daemon.insert("command".to_string(), Value::String(command.to_string()));
+        daemons.insert(name.to_string(), Value::Table(daemon));
+    }
+
+    let mut table = Table::new();
+    table.insert("daemons".to_string(), Value::Table(daemons));
+    Ok(format!(
+        "# Daemons of this project, started by `demon up` and stopped by `demon down`.\n\
+         # Imported from {}; each daemon also takes the options of `demon run` with\n\
+         # underscores, e.g. supervise, restart or health_cmd.\n\n{}",
+        path.file_name()
+            .unwrap_or(path.as_os_str())
+            .to_string_lossy(),
+        toml::to_string(&table)?
+    ))
+}
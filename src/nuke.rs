@@ -0,0 +1,119 @@
+//! `demon nuke`: stop everything and remove the files of every daemon.
+//!
+//! One command to reset a development environment. The agent is stopped
+//! first, so it does not restart what is being stopped, then every running
+//! daemon is stopped like `demon stop` (SIGTERM, then SIGKILL after the
+//! timeout), and finally the PID files, logs, metadata and past runs of all
+//! daemons are removed like `demon clean` does, including those of daemons
+//! stopped earlier. The history, the events, the configuration and the
+//! manifest are kept.
+//!
+//! Without `--yes` the command asks first, and refuses when stdin is not a
+//! terminal to ask on.
+
+use crate::{
+    agent,
+    clean::{CleanOptions, clean_orphaned_files, remove_logs},
+    layout,
+    pidfile::{OperationLock, daemon_ids},
+    runs,
+    status::is_process_running,
+    stop::{remove_state_files, stop_daemon},
+};
+use anyhow::Result;
+use std::collections::BTreeSet;
+use std::io::{BufRead, IsTerminal, Write};
+use std::path::Path;
+
+/// Stop the agent and every daemon in `root_dir`, then remove their files
+pub(crate) fn nuke(
+    root_dir: &Path,
+    timeout: u64,
+    yes: bool,
+    wait_lock: Option<Option<u64>>,
+) -> Result<()> {
+    let _lock = OperationLock::acquire(root_dir, "nuke", wait_lock)?;
+    let mut running = Vec::new();
+    for id in daemon_ids(root_dir)? {
+        if is_process_running(root_dir, &id)? {
+            running.push(id);
+        }
+    }
+    // Daemons stopped earlier have no PID file, only their logs, metadata or
+    // past runs
+    let mut ids: BTreeSet<String> = layout::ids_with_files(root_dir)?.into_iter().collect();
+    ids.extend(runs::ids(root_dir));
+    let agent = agent::running(root_dir);
+    if ids.is_empty() && agent.is_none() {
+        println!("Nothing to nuke in {}", root_dir.display());
+        return Ok(());
+    }
+
+    if !yes {
+        let mut question = format!(
+            "Stop {} running daemon(s){} and remove the files of {} daemon(s) in {}?",
+            running.len(),
+            if agent.is_some() {
+                " and the agent"
+            } else {
+                ""
+            },
+            ids.len(),
+            root_dir.display()
+        );
+        if !running.is_empty() {
+            question.push_str(&format!(" Running: {}.", running.join(", ")));
+        }
+        if !confirm(&question)? {
+            println!("Nothing was changed");
+            return Ok(());
+        }
+    }
+
+    if agent.is_some() {
+        agent::stop(root_dir)?;
+    }
+    // One daemon failing to stop does not keep the others running; its files
+    // stay, since it may still be running
+    let mut failed = Vec::new();
+    for id in &running {
+        match stop_daemon(id, timeout, root_dir) {
+            Ok(stopped) => println!("{}", stopped.message(id)),
+            Err(e) => {
+                tracing::error!("Failed to stop '{}': {:#}", id, e);
+                failed.push(id.as_str());
+            }
+        }
+    }
+    ids.retain(|id| !failed.contains(&id.as_str()));
+    // Stopping retired the PID files of the running daemons, leaving only
+    // their logs, and clean takes the dead ones
+    clean_orphaned_files(root_dir, &CleanOptions::default(), false)?;
+    for id in &ids {
+        remove_state_files(root_dir, id);
+        remove_logs(root_dir, id);
+    }
+    println!("Removed the files of {} daemon(s)", ids.len());
+    if !failed.is_empty() {
+        return Err(anyhow::anyhow!(
+            "Failed to stop {}, its files were kept",
+            failed.join(", ")
+        ));
+    }
+    Ok(())
+}
+
+/// Ask a yes/no question on the terminal, failing when there is none
+fn confirm(question: &str) -> Result<bool> {
+    if !std::io::stdin().is_terminal() {
+        return Err(anyhow::anyhow!(
+            "{} Refusing to ask without a terminal; pass --yes",
+            question
+        ));
+    }
+    print!("{question} [y/N] ");
+    std::io::stdout().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().lock().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes" | "Yes"))
+}
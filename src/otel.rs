@@ -0,0 +1,223 @@
+//! OpenTelemetry: trace context for daemons and lifecycle events as spans.
+//!
+//! Every run of a daemon started with `--trace-context` or `--otel-endpoint`
+//! gets a trace, whose root span covers the run from start to exit. Its IDs
+//! are kept in the daemon's metadata, so the supervisor and agent add their
+//! events to the same trace.
+//!
+//! With `--trace-context` the daemon is started with `TRACEPARENT` naming the
+//! run's span, and with `demon.id` and `demon.run_id` (the trace ID) appended
+//! to `OTEL_RESOURCE_ATTRIBUTES`, so the spans of an instrumented daemon are
+//! children of its run. With `--otel-endpoint` (or the `otel.endpoint`
+//! setting) demon sends every lifecycle event of the daemon as a span to an
+//! OTLP/HTTP collector, in JSON and by `curl` in the background like the
+//! webhooks, and the run's span once the daemon has ended. Ends are only seen
+//! by a supervisor, so the endpoint implies `--supervise`.
+
+use crate::{
+    events,
+    hooks::{HookEvent, Hooks},
+    pidfile::DaemonMeta,
+    webhook,
+};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Span kind INTERNAL
+const KIND_INTERNAL: u8 = 1;
+/// Span status ERROR
+const STATUS_ERROR: u8 = 2;
+
+/// The trace of one run of a daemon
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct Trace {
+    /// 32 hex digits, also the run ID
+    pub(crate) trace_id: String,
+    /// 16 hex digits, the span of the whole run
+    pub(crate) span_id: String,
+}
+
+impl Trace {
+    fn new() -> Self {
+        Self {
+            trace_id: format!("{:032x}", fastrand::u128(1..)),
+            span_id: new_span_id(),
+        }
+    }
+
+    /// W3C `traceparent` naming the run's span, sampled
+    fn traceparent(&self) -> String {
+        format!("00-{}-{}-01", self.trace_id, self.span_id)
+    }
+
+    /// Environment that makes the daemon's spans children of its run
+    fn env(&self, id: &str) -> Vec<(String, String)> {
+        let mut attributes = std::env::var("OTEL_RESOURCE_ATTRIBUTES").unwrap_or_default();
+        if !attributes.is_empty() {
+            attributes.push(',');
+        }
+        attributes.push_str(&format!(
+            "demon.id={},demon.run_id={}",
+            encode(id),
+            self.trace_id
+        ));
+        vec![
+            ("TRACEPARENT".to_string(), self.traceparent()),
+            ("OTEL_RESOURCE_ATTRIBUTES".to_string(), attributes),
+        ]
+    }
+}
+
+/// The trace of a new run of a daemon, if it is traced, with the environment
+/// the daemon is started with
+pub(crate) fn new_run(id: &str, hooks: &Hooks) -> (Option<Trace>, Vec<(String, String)>) {
+    if !hooks.trace_context && hooks.otel_endpoint.is_none() {
+        return (None, Vec::new());
+    }
+    let trace = Trace::new();
+    let env = match hooks.trace_context {
+        true => trace.env(id),
+        false => Vec::new(),
+    };
+    (Some(trace), env)
+}
+
+/// Send an event of a daemon as a span in its run's trace, ending the run's
+/// span too when the daemon has ended
+pub(crate) fn send(endpoint: &str, root_dir: &Path, id: &str, event: &HookEvent) -> Result<()> {
+    let meta = DaemonMeta::read(root_dir, id);
+    let Some(trace) = &meta.trace else {
+        return Ok(());
+    };
+    let now = unix_nanos(SystemTime::now());
+    let failed = event.event == events::Kind::Crashed || event.event == events::Kind::Unhealthy;
+
+    let mut attributes = vec![
+        attribute("demon.id", id),
+        attribute("demon.event", &event.event.to_string()),
+        int_attribute("process.pid", event.pid.into()),
+    ];
+    if let Some(code) = event.exit_code {
+        attributes.push(int_attribute("process.exit.code", code.into()));
+    }
+    if let Some(signal) = event.exit_signal {
+        attributes.push(int_attribute("demon.exit_signal", signal.into()));
+    }
+    let mut spans = vec![span(
+        trace,
+        new_span_id(),
+        Some(&trace.span_id),
+        format!("demon.{}", event.event),
+        (now, now),
+        attributes.clone(),
+        failed,
+    )];
+
+    let ended = matches!(
+        event.event,
+        events::Kind::Stopped | events::Kind::Exited | events::Kind::Crashed
+    );
+    if ended {
+        let start = meta
+            .started_at
+            .map(|secs| unix_nanos(UNIX_EPOCH + Duration::from_secs(secs)))
+            .unwrap_or(now);
+        attributes.push(attribute("process.command_line", &event.command.join(" ")));
+        spans.push(span(
+            trace,
+            trace.span_id.clone(),
+            None,
+            format!("demon.run {id}"),
+            (start, now),
+            attributes,
+            event.event == events::Kind::Crashed,
+        ));
+    }
+
+    let body = json!({
+        "resourceSpans": [{
+            "resource": {
+                "attributes": [
+                    attribute("service.name", "demon"),
+                    attribute("host.name", &webhook::hostname()),
+                ],
+            },
+            "scopeSpans": [{
+                "scope": { "name": "demon", "version": env!("CARGO_PKG_VERSION") },
+                "spans": spans,
+            }],
+        }],
+    });
+    webhook::post(&traces_url(endpoint), root_dir, id, &body.to_string())
+}
+
+/// The traces URL of a collector, given its base URL or the traces URL itself
+fn traces_url(endpoint: &str) -> String {
+    let endpoint = endpoint.trim_end_matches('/');
+    match endpoint.ends_with("/v1/traces") {
+        true => endpoint.to_string(),
+        false => format!("{endpoint}/v1/traces"),
+    }
+}
+
+fn span(
+    trace: &Trace,
+    span_id: String,
+    parent: Option<&str>,
+    name: String,
+    (start, end): (u128, u128),
+    attributes: Vec<Value>,
+    failed: bool,
+) -> Value {
+    let mut span = json!({
+        "traceId": trace.trace_id,
+        "spanId": span_id,
+        "name": name,
+        "kind": KIND_INTERNAL,
+        // 64-bit integers are strings in OTLP/JSON
+        "startTimeUnixNano": start.to_string(),
+        "endTimeUnixNano": end.to_string(),
+        "attributes": attributes,
+    });
+    if let Some(parent) = parent {
+        span["parentSpanId"] = parent.into();
+    }
+    if failed {
+        span["status"] = json!({ "code": STATUS_ERROR });
+    }
+    span
+}
+
+fn attribute(key: &str, value: &str) -> Value {
+    json!({ "key": key, "value": { "stringValue": value } })
+}
+
+fn int_attribute(key: &str, value: i64) -> Value {
+    json!({ "key": key, "value": { "intValue": value.to_string() } })
+}
+
+fn new_span_id() -> String {
+    format!("{:016x}", fastrand::u64(1..))
+}
+
+fn unix_nanos(time: SystemTime) -> u128 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_nanos())
+        .unwrap_or_default()
+}
+
+/// Percent-encode a value of `OTEL_RESOURCE_ATTRIBUTES`
+fn encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' | b'_' | b'.' => {
+                (byte as char).to_string()
+            }
+            byte => format!("%{byte:02X}"),
+        })
+        .collect()
+}
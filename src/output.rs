@@ -0,0 +1,146 @@
+//! Output formats of command results (`--format`, the `format` setting).
+//!
+//! In the default text format every command prints for humans, as it always
+//! has. In the structured formats a command describes its result as records,
+//! JSON objects whose fields are always present (null when unknown) and come
+//! in a fixed order, so scripts can rely on them:
+//!
+//! - `json`: the record, or an array of records
+//! - `csv`: a header row with the field names, then one row per record
+//! - `plain`: one tab-separated line per record without a header, for `cut`
+//!   and `awk`
+//! - `markdown`: a table with a header row, for notes and CI job summaries
+//!
+//! Lists (arrays of strings, like commands) are joined with spaces in CSV and
+//! plain rows.
+
+use crate::config::Config;
+use anyhow::Result;
+use clap::ValueEnum;
+use serde_json::Value;
+
+/// How commands print their results
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub(crate) enum Format {
+    /// Human-readable messages and tables
+    #[default]
+    #[value(alias = "table")]
+    Text,
+    /// JSON objects with a stable schema
+    Json,
+    /// CSV with a header row
+    Csv,
+    /// Tab-separated values without a header
+    Plain,
+    /// Markdown tables
+    #[value(alias = "md")]
+    Markdown,
+}
+
+impl Format {
+    /// The format asked for with `--format`, or else the `format` setting
+    pub(crate) fn resolve(flag: Option<Format>, config: &Config) -> Format {
+        flag.unwrap_or_else(|| Format::from_str(config.choice("format"), true).unwrap_or_default())
+    }
+
+    pub(crate) fn is_text(self) -> bool {
+        self == Format::Text
+    }
+}
+
+/// Text of a field in a CSV or plain row
+fn cell(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        Value::Array(items) => items.iter().map(cell).collect::<Vec<_>>().join(" "),
+        other => other.to_string(),
+    }
+}
+
+/// A CSV field, quoted when needed (RFC 4180)
+fn csv_field(text: &str) -> String {
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+/// A cell of a Markdown table, which must stay on one line
+pub(crate) fn markdown_cell(text: &str) -> String {
+    text.replace('|', "\\|").replace(['\n', '\r'], " ")
+}
+
+/// Print the header of a stream of rows; CSV and Markdown have one
+pub(crate) fn print_header(format: Format, fields: &[&str]) {
+    match format {
+        Format::Csv => {
+            let names: Vec<String> = fields.iter().map(|name| csv_field(name)).collect();
+            println!("{}", names.join(","));
+        }
+        Format::Markdown => {
+            let names: Vec<String> = fields.iter().map(|name| markdown_cell(name)).collect();
+            println!("| {} |", names.join(" | "));
+            println!("|{}", " --- |".repeat(fields.len()));
+        }
+        _ => {}
+    }
+}
+
+/// Print one record as a row of `fields`, or as a JSON line
+pub(crate) fn print_row(format: Format, fields: &[&str], record: &Value) -> Result<()> {
+    let cells = fields.iter().map(|name| cell(&record[*name]));
+    match format {
+        Format::Json | Format::Text => println!("{}", serde_json::to_string(record)?),
+        Format::Csv => println!(
+            "{}",
+            cells
+                .map(|text| csv_field(&text))
+                .collect::<Vec<_>>()
+                .join(",")
+        ),
+        Format::Plain => println!(
+            "{}",
+            cells
+                .map(|text| text.replace(['\t', '\n', '\r'], " "))
+                .collect::<Vec<_>>()
+                .join("\t")
+        ),
+        Format::Markdown => println!(
+            "| {} |",
+            cells
+                .map(|text| markdown_cell(&text))
+                .collect::<Vec<_>>()
+                .join(" | ")
+        ),
+    }
+    Ok(())
+}
+
+/// Print the records of a command: a JSON array, or rows of `fields`
+pub(crate) fn print_records(format: Format, fields: &[&str], records: &[Value]) -> Result<()> {
+    if matches!(format, Format::Json | Format::Text) {
+        println!("{}", serde_json::to_string_pretty(records)?);
+        return Ok(());
+    }
+    print_header(format, fields);
+    for record in records {
+        print_row(format, fields, record)?;
+    }
+    Ok(())
+}
+
+/// Print the result of a command that has a single record, e.g. `status`
+pub(crate) fn print_record(format: Format, record: &Value) -> Result<()> {
+    if matches!(format, Format::Json | Format::Text) {
+        println!("{}", serde_json::to_string_pretty(record)?);
+        return Ok(());
+    }
+    let fields: Vec<&str> = record
+        .as_object()
+        .map(|fields| fields.keys().map(String::as_str).collect())
+        .unwrap_or_default();
+    print_header(format, &fields);
+    print_row(format, &fields, record)
+}
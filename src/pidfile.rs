@@ -0,0 +1,404 @@
+//! State of a daemon: its PID record with its PID and command, its metadata
+//! record with extra state (`<id>/<id>.pid` and `<id>/<id>.meta` in the default
+//! [store](crate::store)), the lock serializing changes to them, and the lock
+//! `up` and `down` hold while they run.
+
+use crate::{
+    agent, format_duration, hooks, layout, otel, proc,
+    stdin::Stdin,
+    store::{self, Record},
+    unix_now,
+};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// Error types for reading PID files
+#[derive(Debug)]
+pub enum PidFileReadError {
+    /// The PID file does not exist
+    FileNotFound,
+    /// The PID file exists but has invalid content
+    FileInvalid(String),
+    /// IO error occurred while reading
+    IoError(std::io::Error),
+}
+
+impl std::fmt::Display for PidFileReadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PidFileReadError::FileNotFound => write!(f, "PID file not found"),
+            PidFileReadError::FileInvalid(reason) => write!(f, "PID file invalid: {reason}"),
+            PidFileReadError::IoError(err) => write!(f, "IO error reading PID file: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for PidFileReadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PidFileReadError::IoError(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// Represents the contents of a PID file
+#[derive(Debug, Clone)]
+pub(crate) struct PidFile {
+    /// Process ID
+    pub(crate) pid: u32,
+    /// Command that was executed (program + arguments)
+    pub(crate) command: Vec<String>,
+}
+
+impl PidFile {
+    /// Create a new PidFile instance
+    pub(crate) fn new(pid: u32, command: Vec<String>) -> Self {
+        Self { pid, command }
+    }
+
+    /// Write the PID record of daemon `id`
+    pub(crate) fn write(&self, root_dir: &Path, id: &str) -> Result<()> {
+        let mut contents = format!("{}\n", self.pid);
+        for arg in &self.command {
+            contents.push_str(arg);
+            contents.push('\n');
+        }
+        store::open(root_dir).write(id, Record::Pid, &contents)
+    }
+
+    /// Read the PID record of daemon `id`
+    pub(crate) fn read(root_dir: &Path, id: &str) -> Result<Self, PidFileReadError> {
+        match store::open(root_dir).read(id, Record::Pid) {
+            Ok(Some(contents)) => Self::parse(&contents),
+            Ok(None) => Err(PidFileReadError::FileNotFound),
+            Err(e) => Err(PidFileReadError::IoError(std::io::Error::other(e))),
+        }
+    }
+
+    /// Whether daemon `id` has a PID record
+    pub(crate) fn exists(root_dir: &Path, id: &str) -> bool {
+        !matches!(store::open(root_dir).read(id, Record::Pid), Ok(None))
+    }
+
+    /// Remove the PID record of daemon `id`
+    pub(crate) fn remove(root_dir: &Path, id: &str) -> Result<()> {
+        store::open(root_dir).remove(id, Record::Pid)
+    }
+
+    fn parse(contents: &str) -> Result<Self, PidFileReadError> {
+        let lines: Vec<&str> = contents.lines().collect();
+
+        if lines.is_empty() {
+            return Err(PidFileReadError::FileInvalid(
+                "PID file is empty".to_string(),
+            ));
+        }
+
+        let pid = lines[0]
+            .trim()
+            .parse::<u32>()
+            .map_err(|_| PidFileReadError::FileInvalid("Invalid PID on first line".to_string()))?;
+
+        let command: Vec<String> = lines[1..].iter().map(|line| line.to_string()).collect();
+
+        if command.is_empty() {
+            return Err(PidFileReadError::FileInvalid(
+                "No command found in PID file".to_string(),
+            ));
+        }
+
+        Ok(Self { pid, command })
+    }
+
+    /// Get the command as a formatted string for display
+    pub(crate) fn command_string(&self) -> String {
+        self.command.join(" ")
+    }
+}
+
+/// Extra daemon state, kept as JSON in its metadata record (`<id>.meta`)
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct DaemonMeta {
+    /// Unix time the daemon was started
+    pub(crate) started_at: Option<u64>,
+    /// Process start time in clock ticks after boot, used to detect PID reuse
+    /// and to measure the uptime without the wall clock
+    pub(crate) start_ticks: Option<u64>,
+    /// Boot the daemon was started in, for which `start_ticks` are valid
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) boot_id: Option<String>,
+    /// Whether a supervisor process owns the daemon
+    pub(crate) supervised: bool,
+    /// PID of the supervisor process
+    pub(crate) supervisor_pid: Option<u32>,
+    /// Whether the daemon runs on a pseudo-terminal held by its supervisor
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub(crate) pty: bool,
+    /// Where the daemon reads its standard input from
+    #[serde(skip_serializing_if = "Stdin::is_null")]
+    pub(crate) stdin: Stdin,
+    /// Exit code, once a supervised daemon has exited normally
+    pub(crate) exit_code: Option<i32>,
+    /// Terminating signal, once a supervised daemon was killed by a signal
+    pub(crate) exit_signal: Option<i32>,
+    /// Whether the supervisor terminated the daemon for running longer than
+    /// `--max-runtime`
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub(crate) timed_out: bool,
+    /// Core dump of the run, collected with `--core-dump-dir`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) core_dump: Option<PathBuf>,
+    /// Hooks to run on lifecycle events, with absolute paths
+    #[serde(skip_serializing_if = "hooks::Hooks::is_empty")]
+    pub(crate) hooks: hooks::Hooks,
+    /// Restart, health check and schedule policies applied by the agent
+    #[serde(skip_serializing_if = "agent::Policy::is_empty")]
+    pub(crate) policy: agent::Policy,
+    /// Unix time a daemon started with `--sd-notify` reported READY=1
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) ready_at: Option<u64>,
+    /// Last STATUS= text sent over sd_notify
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) notify_status: Option<String>,
+    /// Whether the logs of this run were shipped (see `--ship-logs`)
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub(crate) logs_shipped: bool,
+    /// Trace of this run, with `--trace-context` or `--otel-endpoint`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) trace: Option<otel::Trace>,
+    /// Directory the daemon was started in
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) cwd: Option<PathBuf>,
+    /// Cgroup of a daemon started with `--memory` or `--cpus`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) cgroup: Option<PathBuf>,
+}
+
+impl DaemonMeta {
+    /// Read the metadata of a daemon, returning the default for daemons started
+    /// by older versions that did not write any
+    pub(crate) fn read(root_dir: &Path, id: &str) -> Self {
+        store::open(root_dir)
+            .read(id, Record::Meta)
+            .ok()
+            .flatten()
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default()
+    }
+
+    /// Whether daemon `id` has a metadata record
+    pub(crate) fn exists(root_dir: &Path, id: &str) -> bool {
+        store::open(root_dir)
+            .read(id, Record::Meta)
+            .is_ok_and(|contents| contents.is_some())
+    }
+
+    /// Atomically replace the metadata of a daemon
+    pub(crate) fn write(&self, root_dir: &Path, id: &str) -> Result<()> {
+        store::open(root_dir).write(id, Record::Meta, &serde_json::to_string_pretty(self)?)
+    }
+
+    /// Remove the metadata of a daemon
+    pub(crate) fn remove(root_dir: &Path, id: &str) -> Result<()> {
+        store::open(root_dir).remove(id, Record::Meta)
+    }
+
+    /// How long the run has been going. Measured on the boot clock, which
+    /// NTP corrections and changes to the wall clock do not move and which
+    /// keeps counting through suspend, when the run is from this boot;
+    /// otherwise from the wall-clock start time.
+    pub(crate) fn uptime(&self) -> Option<Duration> {
+        self.boot_uptime().or_else(|| {
+            let started_at = self.started_at?;
+            Some(Duration::from_secs(unix_now().saturating_sub(started_at)))
+        })
+    }
+
+    fn boot_uptime(&self) -> Option<Duration> {
+        let ticks = self.start_ticks?;
+        if self.boot_id.is_none() || self.boot_id != proc::boot_id() {
+            return None;
+        }
+        let started = Duration::from_secs_f64(ticks as f64 / proc::clock_ticks_per_second());
+        Some(proc::system_uptime()?.saturating_sub(started))
+    }
+
+    /// Seconds the wall clock moved since the daemon started, forward when
+    /// positive, when that is more than the start time can be off by. A
+    /// negative uptime by the wall clock shows as a backward move.
+    pub(crate) fn clock_skew(&self) -> Option<i64> {
+        let by_wall = unix_now() as i64 - self.started_at? as i64;
+        let by_boot = self.boot_uptime()?.as_secs() as i64;
+        let skew = by_wall - by_boot;
+        (skew.abs() > MAX_START_ERROR).then_some(skew)
+    }
+}
+
+/// Seconds by which the wall-clock and boot-clock start times of a run may
+/// disagree without the clock having changed: the wall-clock time is taken
+/// just after spawning and rounded down to the second
+const MAX_START_ERROR: i64 = 5;
+
+/// Path of the `extension` file of daemon `id`, in its
+/// [directory](crate::layout) or where an earlier version left it
+pub(crate) fn build_file_path(root_dir: &Path, id: &str, extension: &str) -> PathBuf {
+    layout::file_path(root_dir, id, extension)
+}
+
+/// Advisory lock (flock) on the root directory.
+///
+/// Commands that create or remove daemon files hold an exclusive lock for the
+/// short critical section where they touch the files, so `run`, `stop` and
+/// `clean` can never interleave. `list` takes a shared lock so it never observes
+/// a half-written daemon. The lock is released when the value is dropped.
+pub(crate) struct RootLock {
+    pub(crate) _file: Option<File>,
+}
+
+impl RootLock {
+    /// Acquire an exclusive lock, blocking until it is available
+    pub(crate) fn exclusive(root_dir: &Path) -> Result<Self> {
+        let file = Self::open(root_dir)?;
+        file.lock().context("Failed to lock root directory")?;
+        Ok(Self { _file: Some(file) })
+    }
+
+    /// Acquire a shared lock, blocking while a mutating command holds the lock.
+    /// Without a lock file no mutating command ever ran, and readers do not
+    /// create it, so read-only mode leaves the root directory untouched.
+    pub(crate) fn shared(root_dir: &Path) -> Result<Self> {
+        let path = root_dir.join(".lock");
+        let file = match File::open(&path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self { _file: None }),
+            Err(e) => {
+                return Err(e)
+                    .with_context(|| format!("Failed to open lock file {}", path.display()));
+            }
+        };
+        file.lock_shared()
+            .context("Failed to lock root directory")?;
+        Ok(Self { _file: Some(file) })
+    }
+
+    pub(crate) fn open(root_dir: &Path) -> Result<File> {
+        let path = root_dir.join(".lock");
+        std::fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)
+            .with_context(|| format!("Failed to open lock file {}", path.display()))
+    }
+}
+
+/// Lock `up` and `down` hold for their whole run, in `.up.lock`.
+///
+/// Two of them at once would race to start the same daemons, or stop what the
+/// other starts. The holder records its PID, command and start time in the
+/// file, so the one that finds it locked can say who holds it.
+pub(crate) struct OperationLock {
+    _file: File,
+}
+
+/// Who holds the [`OperationLock`]
+#[derive(Debug, Serialize, Deserialize)]
+struct Holder {
+    pid: u32,
+    command: String,
+    /// Unix time the lock was taken
+    started_at: u64,
+}
+
+impl Holder {
+    fn describe(holder: Option<Self>) -> String {
+        match holder {
+            Some(holder) => format!(
+                "'demon {}' (PID {}, started {} ago)",
+                holder.command,
+                holder.pid,
+                format_duration(Duration::from_secs(
+                    unix_now().saturating_sub(holder.started_at)
+                ))
+            ),
+            None => "'demon up' or 'demon down'".to_string(),
+        }
+    }
+}
+
+impl OperationLock {
+    /// Take the lock for `command`. When another command holds it, fail
+    /// unless `wait` is given: then wait for it, at most the given seconds
+    /// if any.
+    pub(crate) fn acquire(
+        root_dir: &Path,
+        command: &str,
+        wait: Option<Option<u64>>,
+    ) -> Result<Self> {
+        let path = root_dir.join(".up.lock");
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .read(true)
+            .write(true)
+            .open(&path)
+            .with_context(|| format!("Failed to open lock file {}", path.display()))?;
+        let holder = || {
+            std::fs::read_to_string(&path)
+                .ok()
+                .and_then(|contents| serde_json::from_str::<Holder>(&contents).ok())
+        };
+
+        let start = Instant::now();
+        let mut waiting = false;
+        loop {
+            match file.try_lock() {
+                Ok(()) => break,
+                Err(std::fs::TryLockError::WouldBlock) => {}
+                Err(std::fs::TryLockError::Error(e)) => {
+                    return Err(e).context("Failed to lock root directory");
+                }
+            }
+            let Some(timeout) = wait else {
+                return Err(anyhow::anyhow!(
+                    "Another {} is in progress in {}; pass --wait-lock to wait for it",
+                    Holder::describe(holder()),
+                    root_dir.display()
+                ));
+            };
+            if !waiting {
+                eprintln!("Waiting for {} to finish", Holder::describe(holder()));
+                waiting = true;
+            }
+            if timeout.is_some_and(|timeout| start.elapsed() >= Duration::from_secs(timeout)) {
+                return Err(anyhow::anyhow!(
+                    "Timed out after {} waiting for {} to finish",
+                    format_duration(start.elapsed()),
+                    Holder::describe(holder())
+                ));
+            }
+            std::thread::sleep(Duration::from_millis(100));
+        }
+
+        let holder = Holder {
+            pid: std::process::id(),
+            command: command.to_string(),
+            started_at: unix_now(),
+        };
+        file.set_len(0)?;
+        (&file).write_all(serde_json::to_string(&holder)?.as_bytes())?;
+        Ok(Self { _file: file })
+    }
+}
+
+/// IDs of the daemons with a PID record, sorted, since the order of
+/// `read_dir` changes between runs
+pub(crate) fn daemon_ids(root_dir: &Path) -> Result<Vec<String>> {
+    store::open(root_dir).ids()
+}
@@ -0,0 +1,225 @@
+//! Event plugins: executables in `<root>/hooks.d/` run for every lifecycle
+//! event of every daemon.
+//!
+//! The protocol is deliberately small so plugins can be written in anything:
+//! a plugin gets the event as one JSON object on stdin (`version`, `ts`, `id`,
+//! `event`, `pid`, `detail`, `host`, `root_dir`) and reports success with exit
+//! status 0. Anything else, or running longer than `plugins.timeout`, is a
+//! failure and the plugin is run again for the same event up to
+//! `plugins.retries` times, backing off 1s, 2s, 4s, ... Plugins run one after
+//! the other in name order, in a detached process, so emitting an event never
+//! waits for them. Their output and failures go to `<root>/hooks.log`.
+
+use crate::{demon_exe, events::DaemonEvent, format_timestamp, unix_now, webhook};
+use anyhow::{Context, Result};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::time::{Duration, Instant};
+
+/// Version of the JSON event plugins receive, bumped on incompatible changes
+const PROTOCOL_VERSION: u32 = 1;
+
+/// How often a running plugin is checked for exit
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+fn plugins_dir(root_dir: &Path) -> PathBuf {
+    root_dir.join("hooks.d")
+}
+
+fn log_path(root_dir: &Path) -> PathBuf {
+    root_dir.join("hooks.log")
+}
+
+/// The executable files in `hooks.d`
+pub(crate) fn discover(root_dir: &Path) -> Vec<PathBuf> {
+    executables(&plugins_dir(root_dir))
+}
+
+/// The executable files in a plugin directory, in name order. Hidden files
+/// and backups (`~`) are skipped, so a plugin is disabled with `chmod -x`.
+pub(crate) fn executables(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut plugins: Vec<PathBuf> = entries
+        .flatten()
+        .filter(|entry| {
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            !name.starts_with('.') && !name.ends_with('~')
+        })
+        .filter(|entry| {
+            std::fs::metadata(entry.path())
+                .is_ok_and(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+        })
+        .map(|entry| entry.path())
+        .collect();
+    plugins.sort();
+    plugins
+}
+
+/// Hand an event to the plugins, if there are any, without waiting for them.
+/// Failures are logged, never fatal, like the event log itself.
+pub(crate) fn dispatch(root_dir: &Path, event: &DaemonEvent) {
+    if discover(root_dir).is_empty() {
+        return;
+    }
+    if let Err(e) = spawn_delivery(root_dir, event) {
+        tracing::warn!(
+            "Failed to run the plugins for the '{}' event of '{}': {}",
+            event.event,
+            event.id,
+            e
+        );
+    }
+}
+
+fn spawn_delivery(root_dir: &Path, event: &DaemonEvent) -> Result<()> {
+    let payload = serde_json::json!({
+        "version": PROTOCOL_VERSION,
+        "ts": event.ts,
+        "id": event.id,
+        "event": event.event,
+        "pid": event.pid,
+        "detail": event.detail,
+        "host": webhook::hostname(),
+        "root_dir": root_dir,
+    });
+
+    let mut cmd = Command::new(demon_exe()?);
+    cmd.arg("run-plugins")
+        .arg("--root-dir")
+        .arg(root_dir)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    // Retries may outlive the command that emitted the event
+    // SAFETY: setsid is async-signal-safe and touches no memory
+    unsafe {
+        cmd.pre_exec(|| {
+            if libc::setsid() == -1 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    let mut child = cmd.spawn().context("Failed to start plugin delivery")?;
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    writeln!(stdin, "{payload}")?;
+    drop(stdin);
+    // Long-lived callers (the agent, supervisors) must not collect zombies
+    std::thread::spawn(move || child.wait());
+    Ok(())
+}
+
+/// Run every plugin for the event on stdin (the hidden `run-plugins`
+/// command), retrying failures
+pub(crate) fn deliver(root_dir: &Path, timeout: Duration, retries: u64) -> Result<()> {
+    let mut payload = String::new();
+    std::io::stdin()
+        .read_to_string(&mut payload)
+        .context("Failed to read the event")?;
+    let event: serde_json::Value =
+        serde_json::from_str(&payload).context("Invalid event on stdin")?;
+    let field = |key: &str| match &event[key] {
+        serde_json::Value::String(s) => s.clone(),
+        serde_json::Value::Null => String::new(),
+        value => value.to_string(),
+    };
+    let (id, kind) = (field("id"), field("event"));
+
+    for plugin in discover(root_dir) {
+        let name = plugin
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let attempts = retries + 1;
+        for attempt in 1..=attempts {
+            let mut cmd = Command::new(&plugin);
+            cmd.env("DEMON_EVENT", &kind)
+                .env("DEMON_ID", &id)
+                .env("DEMON_PID", field("pid"))
+                .env("DEMON_ROOT_DIR", root_dir)
+                .env("DEMON_ATTEMPT", attempt.to_string());
+            let error = match run_once(cmd, &payload, timeout, root_dir) {
+                Ok(status) if status.success() => break,
+                Ok(status) => status.to_string(),
+                Err(e) => format!("{e:#}"),
+            };
+            log_failure(
+                root_dir,
+                &format!(
+                    "plugin '{name}' failed on the '{kind}' event of '{id}' (attempt {attempt}/{attempts}): {error}"
+                ),
+            );
+            if attempt < attempts {
+                std::thread::sleep(Duration::from_secs(1 << (attempt - 1).min(6)));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Run a plugin once with the event on stdin, killing it (and anything it
+/// started) after the timeout
+fn run_once(
+    mut cmd: Command,
+    payload: &str,
+    timeout: Duration,
+    root_dir: &Path,
+) -> Result<ExitStatus> {
+    let log = open_log(root_dir)?;
+    cmd.stdin(Stdio::piped())
+        .stdout(Stdio::from(log.try_clone()?))
+        .stderr(Stdio::from(log))
+        .process_group(0);
+    let mut child = cmd.spawn()?;
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    // A plugin that ignores its input may exit before reading it
+    let _ = stdin.write_all(payload.as_bytes());
+    drop(stdin);
+
+    wait_timeout(&mut child, timeout)?
+        .ok_or_else(|| anyhow::anyhow!("timed out after {}s", timeout.as_secs()))
+}
+
+/// Wait for a plugin started in its own process group, killing the group
+/// when it is still running after the timeout (None)
+pub(crate) fn wait_timeout(child: &mut Child, timeout: Duration) -> Result<Option<ExitStatus>> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        if Instant::now() >= deadline {
+            // SAFETY: kill has no memory safety requirements
+            unsafe {
+                libc::kill(-(child.id() as i32), libc::SIGKILL);
+            }
+            let _ = child.wait();
+            return Ok(None);
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    }
+}
+
+fn open_log(root_dir: &Path) -> Result<File> {
+    Ok(OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_path(root_dir))?)
+}
+
+fn log_failure(root_dir: &Path, message: &str) {
+    let result = open_log(root_dir).and_then(|mut log| {
+        writeln!(log, "[{}] {}", format_timestamp(unix_now()), message)?;
+        Ok(())
+    });
+    if let Err(e) = result {
+        tracing::warn!("{}: {}", message, e);
+    }
+}
@@ -0,0 +1,373 @@
+//! CPU, I/O and OOM priority of daemons (`demon run --nice 10 --ionice-class
+//! idle --cpu-affinity 0-3 --oom-score-adj 500`).
+//!
+//! Like resource limits, the niceness, I/O scheduling class, CPU affinity and
+//! OOM score adjustment are set in the forked child right before it executes the daemon, so they
+//! bind the daemon and the processes it starts but not demon or the
+//! supervisor. They are part of the daemon's policy, so restarts by the agent
+//! or `top` apply them again, and `demon status` shows them.
+
+use anyhow::Result;
+use clap::{Args, ValueEnum};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// `ioprio_set` target: a single process
+const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+
+/// Bits of the priority level in an I/O priority
+const IOPRIO_CLASS_SHIFT: libc::c_int = 13;
+
+/// Level of the realtime and best-effort classes when none is given, as
+/// `ionice` uses
+const DEFAULT_IO_LEVEL: u8 = 4;
+
+/// OOM score adjustment of the calling process
+const OOM_SCORE_ADJ: &str = "/proc/self/oom_score_adj";
+
+/// I/O scheduling classes, as `ionice -c`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum IoClass {
+    /// Served before everything else; needs root
+    Realtime,
+    /// The default, with a level from 0 (highest) to 7
+    BestEffort,
+    /// Only gets the disk when no other process needs it
+    Idle,
+}
+
+impl IoClass {
+    /// Number of the class for `ioprio_set`
+    fn number(self) -> libc::c_int {
+        match self {
+            IoClass::Realtime => 1,
+            IoClass::BestEffort => 2,
+            IoClass::Idle => 3,
+        }
+    }
+
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            IoClass::Realtime => "realtime",
+            IoClass::BestEffort => "best-effort",
+            IoClass::Idle => "idle",
+        }
+    }
+}
+
+/// A set of CPUs given as `0-3,6`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub(crate) struct CpuList(Vec<usize>);
+
+impl FromStr for CpuList {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, String> {
+        let invalid = || format!("invalid CPU list '{value}', expected e.g. 0-3 or 0,2,4-7");
+        let mut cpus = Vec::new();
+        for part in value.split(',') {
+            let (first, last) = match part.trim().split_once('-') {
+                Some((first, last)) => (first.trim(), last.trim()),
+                None => (part.trim(), part.trim()),
+            };
+            let first: usize = first.parse().map_err(|_| invalid())?;
+            let last: usize = last.parse().map_err(|_| invalid())?;
+            if first > last {
+                return Err(invalid());
+            }
+            if last >= libc::CPU_SETSIZE as usize {
+                return Err(format!("CPU {last} is above the highest CPU number"));
+            }
+            cpus.extend(first..=last);
+        }
+        cpus.sort_unstable();
+        cpus.dedup();
+        Ok(CpuList(cpus))
+    }
+}
+
+impl fmt::Display for CpuList {
+    /// Consecutive CPUs are written as ranges, like the kernel's lists
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut ranges: Vec<(usize, usize)> = Vec::new();
+        for &cpu in &self.0 {
+            match ranges.last_mut() {
+                Some((_, last)) if *last + 1 == cpu => *last = cpu,
+                _ => ranges.push((cpu, cpu)),
+            }
+        }
+        let ranges: Vec<String> = ranges
+            .into_iter()
+            .map(|(first, last)| match first == last {
+                true => first.to_string(),
+                false => format!("{first}-{last}"),
+            })
+            .collect();
+        write!(f, "{}", ranges.join(","))
+    }
+}
+
+impl TryFrom<String> for CpuList {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, String> {
+        value.parse()
+    }
+}
+
+impl From<CpuList> for String {
+    fn from(cpus: CpuList) -> Self {
+        cpus.to_string()
+    }
+}
+
+/// Priority flags of `run`, like `nice`, `ionice` and `taskset`
+#[derive(Args, Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct Priority {
+    /// Niceness of the daemon, from -20 (favored) to 19 (yields the CPU to
+    /// everything else); going below demon's own needs root
+    #[arg(long, value_name = "N", allow_negative_numbers = true, value_parser = clap::value_parser!(i32).range(-20..=19))]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) nice: Option<i32>,
+
+    /// I/O scheduling class of the daemon; idle only gets the disk when no
+    /// other process needs it, realtime needs root
+    #[arg(long, value_enum, value_name = "CLASS")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) ionice_class: Option<IoClass>,
+
+    /// I/O priority within the class, from 0 (highest) to 7 (default: 4);
+    /// without --ionice-class the class is best-effort
+    #[arg(long, value_name = "LEVEL", value_parser = clap::value_parser!(u8).range(0..=7))]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) ionice_level: Option<u8>,
+
+    /// CPUs the daemon and its children may run on, e.g. 0-3 or 0,2,4-7
+    #[arg(long, value_name = "CPUS")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) cpu_affinity: Option<CpuList>,
+
+    /// Added to the daemon's OOM score, from -1000 (never killed when memory
+    /// runs out) to 1000 (killed first); going below demon's own needs root
+    #[arg(long, value_name = "N", allow_negative_numbers = true, value_parser = clap::value_parser!(i32).range(-1000..=1000))]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) oom_score_adj: Option<i32>,
+}
+
+/// Priorities as arguments of the system calls, prepared before forking since
+/// the child must not allocate
+pub(crate) struct Prepared {
+    nice: Option<i32>,
+    ioprio: Option<libc::c_int>,
+    affinity: Option<libc::cpu_set_t>,
+    oom_score_adj: Option<Vec<u8>>,
+}
+
+impl Priority {
+    pub(crate) fn is_empty(&self) -> bool {
+        *self == Priority::default()
+    }
+
+    /// The I/O class and level, if either is given
+    pub(crate) fn io(&self) -> Option<(IoClass, Option<u8>)> {
+        match (self.ionice_class, self.ionice_level) {
+            (None, None) => None,
+            (Some(IoClass::Idle), _) => Some((IoClass::Idle, None)),
+            (class, level) => Some((
+                class.unwrap_or(IoClass::BestEffort),
+                Some(level.unwrap_or(DEFAULT_IO_LEVEL)),
+            )),
+        }
+    }
+
+    /// Command line flags that recreate these priorities
+    pub(crate) fn to_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if let Some(nice) = self.nice {
+            args.push(format!("--nice={nice}"));
+        }
+        if let Some(class) = self.ionice_class {
+            args.extend(["--ionice-class".to_string(), class.name().to_string()]);
+        }
+        if let Some(level) = self.ionice_level {
+            args.extend(["--ionice-level".to_string(), level.to_string()]);
+        }
+        if let Some(cpus) = &self.cpu_affinity {
+            args.extend(["--cpu-affinity".to_string(), cpus.to_string()]);
+        }
+        if let Some(adj) = self.oom_score_adj {
+            args.push(format!("--oom-score-adj={adj}"));
+        }
+        args
+    }
+
+    /// The priorities as `status` shows them, e.g. `nice=10, io=idle`
+    pub(crate) fn summary(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(nice) = self.nice {
+            parts.push(format!("nice={nice}"));
+        }
+        match self.io() {
+            Some((class, Some(level))) => parts.push(format!("io={}/{level}", class.name())),
+            Some((class, None)) => parts.push(format!("io={}", class.name())),
+            None => {}
+        }
+        if let Some(cpus) = &self.cpu_affinity {
+            parts.push(format!("cpu-affinity={cpus}"));
+        }
+        if let Some(adj) = self.oom_score_adj {
+            parts.push(format!("oom-score-adj={adj}"));
+        }
+        parts.join(", ")
+    }
+
+    /// Fail early on priorities the daemon could not be given, since
+    /// favoring a process needs privileges
+    pub(crate) fn check(&self) -> Result<()> {
+        if let Some(nice) = self.nice {
+            if !(-20..=19).contains(&nice) {
+                return Err(anyhow::anyhow!(
+                    "invalid niceness {nice}, expected -20 to 19"
+                ));
+            }
+            // SAFETY: getpriority and getrlimit take no pointers we do not own
+            let (current, floor) = unsafe {
+                let current = libc::getpriority(libc::PRIO_PROCESS, 0);
+                let mut limit = libc::rlimit {
+                    rlim_cur: 0,
+                    rlim_max: 0,
+                };
+                libc::getrlimit(libc::RLIMIT_NICE, &mut limit);
+                (current, 20 - limit.rlim_cur.min(40) as i32)
+            };
+            if nice < current && nice < floor && !is_root() {
+                return Err(anyhow::anyhow!(
+                    "--nice {} is below demon's own niceness of {}, which only root can do",
+                    nice,
+                    current
+                ));
+            }
+        }
+        if self.ionice_level.is_some_and(|level| level > 7) {
+            return Err(anyhow::anyhow!("invalid --ionice-level, expected 0 to 7"));
+        }
+        match (self.ionice_class, self.ionice_level) {
+            (Some(IoClass::Idle), Some(_)) => {
+                return Err(anyhow::anyhow!(
+                    "--ionice-level does not apply to the idle class"
+                ));
+            }
+            (Some(IoClass::Realtime), _) if !is_root() => {
+                return Err(anyhow::anyhow!("--ionice-class realtime needs root"));
+            }
+            _ => {}
+        }
+        if let Some(cpus) = &self.cpu_affinity {
+            // SAFETY: a zeroed cpu_set_t is an empty set, which
+            // sched_getaffinity fills
+            let mut allowed: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+            let size = std::mem::size_of::<libc::cpu_set_t>();
+            if unsafe { libc::sched_getaffinity(0, size, &mut allowed) } == 0 {
+                let available: Vec<usize> = (0..libc::CPU_SETSIZE as usize)
+                    .filter(|&cpu| unsafe { libc::CPU_ISSET(cpu, &allowed) })
+                    .collect();
+                if let Some(cpu) = cpus.0.iter().find(|cpu| !available.contains(cpu)) {
+                    return Err(anyhow::anyhow!(
+                        "--cpu-affinity {} includes CPU {}, which demon cannot run on (available: {})",
+                        cpus,
+                        cpu,
+                        CpuList(available)
+                    ));
+                }
+            }
+        }
+        if let Some(adj) = self.oom_score_adj {
+            if !(-1000..=1000).contains(&adj) {
+                return Err(anyhow::anyhow!(
+                    "invalid OOM score adjustment {adj}, expected -1000 to 1000"
+                ));
+            }
+            let current = std::fs::read_to_string(OOM_SCORE_ADJ)
+                .ok()
+                .and_then(|value| value.trim().parse::<i32>().ok());
+            if let Some(current) = current
+                && adj < current
+                && !is_root()
+            {
+                return Err(anyhow::anyhow!(
+                    "--oom-score-adj {} is below demon's own adjustment of {}, which only root can do",
+                    adj,
+                    current
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn prepare(&self) -> Prepared {
+        let ioprio = self.io().map(|(class, level)| {
+            (class.number() << IOPRIO_CLASS_SHIFT) | libc::c_int::from(level.unwrap_or(0))
+        });
+        let affinity = self.cpu_affinity.as_ref().map(|cpus| {
+            // SAFETY: a zeroed cpu_set_t is an empty set; CPU_SET only
+            // writes bits within it, and CpuList holds CPUs below CPU_SETSIZE
+            let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+            for &cpu in &cpus.0 {
+                unsafe { libc::CPU_SET(cpu, &mut set) };
+            }
+            set
+        });
+        Prepared {
+            nice: self.nice,
+            ioprio,
+            affinity,
+            oom_score_adj: self.oom_score_adj.map(|adj| adj.to_string().into_bytes()),
+        }
+    }
+}
+
+fn is_root() -> bool {
+    // SAFETY: geteuid cannot fail
+    unsafe { libc::geteuid() == 0 }
+}
+
+/// Set prepared priorities; called in the forked child
+pub(crate) fn apply(prepared: &Prepared) -> std::io::Result<()> {
+    // SAFETY: setpriority, the ioprio_set system call, sched_setaffinity,
+    // open, write and close are async-signal-safe and only read what they
+    // are given
+    unsafe {
+        if let Some(nice) = prepared.nice
+            && libc::setpriority(libc::PRIO_PROCESS, 0, nice) != 0
+        {
+            return Err(std::io::Error::last_os_error());
+        }
+        if let Some(ioprio) = prepared.ioprio
+            && libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, ioprio) != 0
+        {
+            return Err(std::io::Error::last_os_error());
+        }
+        if let Some(set) = &prepared.affinity
+            && libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), set) != 0
+        {
+            return Err(std::io::Error::last_os_error());
+        }
+        if let Some(adj) = &prepared.oom_score_adj {
+            let fd = libc::open(c"/proc/self/oom_score_adj".as_ptr(), libc::O_WRONLY);
+            if fd < 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            let written = libc::write(fd, adj.as_ptr().cast(), adj.len());
+            let error = std::io::Error::last_os_error();
+            libc::close(fd);
+            if written < 0 {
+                return Err(error);
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,218 @@
+//! Process information read from `/proc`.
+
+use std::process::Command;
+use std::time::Duration;
+
+/// Selected fields of `/proc/<pid>/stat`
+#[derive(Debug, Clone)]
+pub(crate) struct ProcStat {
+    /// Single character process state (R, S, D, Z, ...)
+    pub(crate) state: char,
+    /// Parent process ID
+    pub(crate) ppid: u32,
+    /// Process group ID
+    pub(crate) pgrp: u32,
+    /// Session ID
+    pub(crate) session: u32,
+    /// Controlling terminal device number, 0 if there is none
+    pub(crate) tty_nr: i32,
+    /// User mode CPU time in clock ticks
+    pub(crate) utime: u64,
+    /// Kernel mode CPU time in clock ticks
+    pub(crate) stime: u64,
+    /// Number of threads
+    pub(crate) num_threads: u64,
+    /// Start time in clock ticks after system boot
+    pub(crate) starttime: u64,
+    /// Resident set size in pages
+    pub(crate) rss_pages: u64,
+}
+
+impl ProcStat {
+    /// Read the stat file of a process, returning None if it does not exist
+    pub(crate) fn read(pid: u32) -> Option<Self> {
+        let stat = std::fs::read_to_string(format!("/proc/{pid}/stat")).ok()?;
+        // The fields follow the parenthesized command name, which may itself
+        // contain spaces or parentheses
+        let (_, rest) = stat.rsplit_once(')')?;
+        let fields: Vec<&str> = rest.split_whitespace().collect();
+        Some(Self {
+            state: fields.first()?.chars().next()?,
+            ppid: fields.get(1)?.parse().ok()?,
+            pgrp: fields.get(2)?.parse().ok()?,
+            session: fields.get(3)?.parse().ok()?,
+            tty_nr: fields.get(4)?.parse().ok()?,
+            utime: fields.get(11)?.parse().ok()?,
+            stime: fields.get(12)?.parse().ok()?,
+            num_threads: fields.get(17)?.parse().ok()?,
+            starttime: fields.get(19)?.parse().ok()?,
+            rss_pages: fields.get(21)?.parse().ok()?,
+        })
+    }
+
+    /// Total CPU time consumed by the process
+    pub(crate) fn cpu_time(&self) -> Duration {
+        Duration::from_secs_f64((self.utime + self.stime) as f64 / clock_ticks_per_second())
+    }
+
+    /// Resident set size in bytes
+    pub(crate) fn rss_bytes(&self) -> u64 {
+        self.rss_pages * page_size()
+    }
+
+    /// Time elapsed since the process started
+    pub(crate) fn uptime(&self) -> Option<Duration> {
+        let started = self.starttime as f64 / clock_ticks_per_second();
+        let since_boot = system_uptime()?.as_secs_f64();
+        Some(Duration::from_secs_f64((since_boot - started).max(0.0)))
+    }
+
+    /// Average CPU usage over the lifetime of the process, in percent of one core
+    pub(crate) fn cpu_percent(&self) -> Option<f64> {
+        let uptime = self.uptime()?.as_secs_f64();
+        if uptime <= 0.0 {
+            return None;
+        }
+        Some(self.cpu_time().as_secs_f64() / uptime * 100.0)
+    }
+
+    /// Format the controlling terminal for display
+    pub(crate) fn tty_display(&self) -> String {
+        if self.tty_nr == 0 {
+            "none".to_string()
+        } else {
+            // Linux encodes the device number as major in bits 8-15 and the
+            // minor split across bits 0-7 and 20-31
+            let major = (self.tty_nr >> 8) & 0xfff;
+            let minor = (self.tty_nr & 0xff) | ((self.tty_nr >> 12) & 0xfff00);
+            format!("{major}:{minor}")
+        }
+    }
+}
+
+/// Selected fields of `/proc/<pid>/status`, memory values in bytes
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ProcStatus {
+    /// Peak virtual memory size
+    pub(crate) vm_peak: Option<u64>,
+    /// Peak resident set size ("high water mark")
+    pub(crate) vm_hwm: Option<u64>,
+    /// Voluntary context switches
+    pub(crate) voluntary_ctxt_switches: Option<u64>,
+    /// Involuntary context switches
+    pub(crate) nonvoluntary_ctxt_switches: Option<u64>,
+}
+
+impl ProcStatus {
+    pub(crate) fn read(pid: u32) -> Option<Self> {
+        let contents = std::fs::read_to_string(format!("/proc/{pid}/status")).ok()?;
+        let mut status = Self::default();
+        for line in contents.lines() {
+            let Some((key, value)) = line.split_once(':') else {
+                continue;
+            };
+            let mut parts = value.split_whitespace();
+            let number = parts.next().and_then(|n| n.parse::<u64>().ok());
+            // Memory values are reported in kB
+            let bytes = number.map(|n| match parts.next() {
+                Some("kB") => n * 1024,
+                _ => n,
+            });
+            match key {
+                "VmPeak" => status.vm_peak = bytes,
+                "VmHWM" => status.vm_hwm = bytes,
+                "voluntary_ctxt_switches" => status.voluntary_ctxt_switches = number,
+                "nonvoluntary_ctxt_switches" => status.nonvoluntary_ctxt_switches = number,
+                _ => {}
+            }
+        }
+        Some(status)
+    }
+}
+
+/// Command line of a process, with arguments separated by spaces
+pub(crate) fn proc_cmdline(pid: u32) -> Option<String> {
+    let raw = std::fs::read(format!("/proc/{pid}/cmdline")).ok()?;
+    let args: Vec<String> = raw
+        .split(|b| *b == 0)
+        .filter(|arg| !arg.is_empty())
+        .map(|arg| String::from_utf8_lossy(arg).into_owned())
+        .collect();
+    Some(args.join(" "))
+}
+
+/// All live descendants of a process (children, grandchildren, ...) in
+/// breadth-first order
+pub(crate) fn process_descendants(pid: u32) -> Vec<u32> {
+    let mut children: std::collections::HashMap<u32, Vec<u32>> = std::collections::HashMap::new();
+    if let Ok(entries) = std::fs::read_dir("/proc") {
+        for entry in entries.flatten() {
+            let Some(child) = entry
+                .file_name()
+                .to_str()
+                .and_then(|n| n.parse::<u32>().ok())
+            else {
+                continue;
+            };
+            if let Some(stat) = ProcStat::read(child) {
+                children.entry(stat.ppid).or_default().push(child);
+            }
+        }
+    }
+
+    let mut result = Vec::new();
+    let mut queue = std::collections::VecDeque::from([pid]);
+    while let Some(parent) = queue.pop_front() {
+        if let Some(kids) = children.get_mut(&parent) {
+            kids.sort_unstable();
+            for &kid in kids.iter() {
+                result.push(kid);
+                queue.push_back(kid);
+            }
+        }
+    }
+    result
+}
+
+pub(crate) fn clock_ticks_per_second() -> f64 {
+    // SAFETY: sysconf has no preconditions
+    let ticks = unsafe { libc::sysconf(libc::_SC_CLK_TCK) };
+    if ticks > 0 { ticks as f64 } else { 100.0 }
+}
+
+pub(crate) fn page_size() -> u64 {
+    // SAFETY: sysconf has no preconditions
+    let size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+    if size > 0 { size as u64 } else { 4096 }
+}
+
+/// Time since boot, read from `/proc/uptime`
+pub(crate) fn system_uptime() -> Option<Duration> {
+    let contents = std::fs::read_to_string("/proc/uptime").ok()?;
+    let seconds: f64 = contents.split_whitespace().next()?.parse().ok()?;
+    Some(Duration::from_secs_f64(seconds))
+}
+
+/// Random ID of the current boot, which tells whether times measured from
+/// boot (process start ticks, [`system_uptime`]) are comparable with now
+pub(crate) fn boot_id() -> Option<String> {
+    let contents = std::fs::read_to_string("/proc/sys/kernel/random/boot_id").ok()?;
+    Some(contents.trim().to_string()).filter(|id| !id.is_empty())
+}
+
+pub(crate) fn is_process_running_by_pid(pid: u32) -> bool {
+    let output = Command::new("kill").args(["-0", &pid.to_string()]).output();
+
+    match output {
+        Ok(output) => output.status.success() && !is_zombie(pid),
+        Err(_) => false,
+    }
+}
+
+/// Check whether a process has exited but not yet been reaped by its parent.
+///
+/// `kill -0` succeeds for zombies, which would make finished daemons whose
+/// parent never calls wait() show up as RUNNING forever.
+pub(crate) fn is_zombie(pid: u32) -> bool {
+    ProcStat::read(pid).is_some_and(|stat| stat.state == 'Z')
+}
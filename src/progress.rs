@@ -0,0 +1,114 @@
+//! Progress of long operations as JSON lines on stderr (`--progress json`).
+//!
+//! `stop`, `up`, `down`, `wait` and `clean` report what they are doing, so a
+//! wrapper or a web UI can draw a progress bar instead of parsing the text
+//! meant for people. Every line is an object with the same fields, `null` when
+//! they do not apply:
+//!
+//! - `ts`: Unix time in milliseconds
+//! - `op`: the operation, `stop`, `up`, `wait-healthy`, `down`, `wait` or
+//!   `clean`; `down` stops every daemon with a `stop` of its own
+//! - `event`: `start`, then `step` for every step, then `done`
+//! - `id`: the daemon the line is about
+//! - `phase`: what happened, e.g. `sigterm` or `started`
+//! - `done` and `total`: how far the operation is, in daemons, or in seconds
+//!   for `stop` and `wait`
+//! - `ok`: on `done`, whether the operation succeeded
+//!
+//! An operation that fails with an error ends with `done`, phase `failed`.
+//! Log messages go to stderr too and are not JSON.
+
+use clap::ValueEnum;
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// How to report progress
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub(crate) enum Mode {
+    /// Not at all
+    #[default]
+    None,
+    /// As JSON lines on stderr
+    Json,
+}
+
+/// Report progress from now on in this process
+pub(crate) fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// An operation whose progress is reported
+pub(crate) struct Progress {
+    op: &'static str,
+    /// The daemon the whole operation is about, if one
+    id: Option<String>,
+    done: u64,
+    total: Option<u64>,
+    finished: bool,
+}
+
+impl Progress {
+    /// Report the start of an operation of `total` steps, if known
+    pub(crate) fn start(op: &'static str, id: Option<&str>, total: Option<u64>) -> Self {
+        let progress = Self {
+            op,
+            id: id.map(str::to_string),
+            done: 0,
+            total,
+            finished: false,
+        };
+        progress.emit("start", id, None, None);
+        progress
+    }
+
+    /// Report a finished step
+    pub(crate) fn step(&mut self, id: Option<&str>, phase: &str) {
+        self.done += 1;
+        self.emit("step", id, Some(phase), None);
+    }
+
+    /// Report where the operation is, `done` steps of the total
+    pub(crate) fn update(&mut self, id: Option<&str>, phase: &str, done: u64) {
+        self.done = done;
+        self.emit("step", id, Some(phase), None);
+    }
+
+    /// Report the end of the operation
+    pub(crate) fn finish(mut self, id: Option<&str>, phase: &str, ok: bool) {
+        self.emit("done", id, Some(phase), Some(ok));
+        self.finished = true;
+    }
+
+    fn emit(&self, event: &str, id: Option<&str>, phase: Option<&str>, ok: Option<bool>) {
+        if !ENABLED.load(Ordering::Relaxed) {
+            return;
+        }
+        let ts = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_millis() as u64)
+            .unwrap_or_default();
+        let line = serde_json::json!({
+            "ts": ts,
+            "op": self.op,
+            "event": event,
+            "id": id,
+            "phase": phase,
+            "done": self.done,
+            "total": self.total,
+            "ok": ok,
+        });
+        let _ = writeln!(std::io::stderr().lock(), "{line}");
+    }
+}
+
+impl Drop for Progress {
+    /// An operation that did not finish failed with an error
+    fn drop(&mut self) {
+        if !self.finished {
+            self.emit("done", self.id.as_deref(), Some("failed"), Some(false));
+        }
+    }
+}
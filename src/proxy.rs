@@ -0,0 +1,105 @@
+//! `demon proxy`: hold a port and start the daemon on the first connection.
+
+use crate::{
+    cli::ProxyArgs,
+    runner::{RunOptions, run_daemon},
+    status::is_process_running,
+};
+use anyhow::{Context, Result};
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+pub(crate) fn proxy_daemon(
+    args: &ProxyArgs,
+    connect_timeout: Duration,
+    root_dir: &Path,
+) -> Result<()> {
+    let listener = std::net::TcpListener::bind(&args.listen)
+        .with_context(|| format!("Failed to listen on {}", args.listen))?;
+
+    println!(
+        "Proxying {} -> {} for daemon '{}' (started on first connection)",
+        listener.local_addr()?,
+        args.upstream,
+        args.id
+    );
+
+    // Serializes start-up so a burst of initial connections starts the daemon once
+    let start_lock = std::sync::Arc::new(std::sync::Mutex::new(()));
+
+    for client in listener.incoming() {
+        let client = match client {
+            Ok(client) => client,
+            Err(e) => {
+                tracing::warn!("Failed to accept connection: {}", e);
+                continue;
+            }
+        };
+
+        let start_lock = start_lock.clone();
+        let id = args.id.clone();
+        let command = args.command.clone();
+        let upstream = args.upstream.clone();
+        let root_dir = root_dir.to_path_buf();
+
+        thread::spawn(move || {
+            let result = (|| -> Result<()> {
+                {
+                    let _guard = start_lock.lock().unwrap_or_else(|e| e.into_inner());
+                    if !is_process_running(&root_dir, &id)? {
+                        tracing::info!("First connection for '{}', starting daemon", id);
+                        run_daemon(&id, &command, &root_dir, &RunOptions::default())?;
+                    }
+                }
+                let server = connect_upstream(&upstream, connect_timeout)?;
+                pipe_connection(client, server)
+            })();
+            if let Err(e) = result {
+                tracing::error!("Proxy connection for '{}' failed: {}", id, e);
+            }
+        });
+    }
+
+    Ok(())
+}
+
+/// Connect to the upstream address, retrying until the daemon starts accepting
+pub(crate) fn connect_upstream(upstream: &str, timeout: Duration) -> Result<std::net::TcpStream> {
+    let deadline = std::time::Instant::now() + timeout;
+    loop {
+        match std::net::TcpStream::connect(upstream) {
+            Ok(stream) => return Ok(stream),
+            Err(e) if std::time::Instant::now() >= deadline => {
+                return Err(anyhow::anyhow!(
+                    "Upstream {} did not accept connections within {}s: {}",
+                    upstream,
+                    timeout.as_secs(),
+                    e
+                ));
+            }
+            Err(_) => thread::sleep(Duration::from_millis(100)),
+        }
+    }
+}
+
+/// Copy bytes in both directions until either side closes its connection
+pub(crate) fn pipe_connection(
+    client: std::net::TcpStream,
+    server: std::net::TcpStream,
+) -> Result<()> {
+    let mut client_read = client.try_clone()?;
+    let mut server_write = server.try_clone()?;
+    let upload = thread::spawn(move || {
+        let _ = std::io::copy(&mut client_read, &mut server_write);
+        let _ = server_write.shutdown(std::net::Shutdown::Write);
+    });
+
+    let mut server_read = server;
+    let mut client_write = client;
+    let _ = std::io::copy(&mut server_read, &mut client_write);
+    let _ = client_write.shutdown(std::net::Shutdown::Write);
+
+    let _ = upload.join();
+    Ok(())
+}
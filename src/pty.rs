@@ -0,0 +1,442 @@
+//! Pseudo-terminals for `demon run --pty` and `demon attach`.
+//!
+//! A daemon started with `--pty` runs under a supervisor with a
+//! pseudo-terminal as its controlling terminal and stdin, stdout and stderr.
+//! The supervisor holds the master side: it copies everything the daemon
+//! writes to `<id>.stdout` (stderr is merged into it, and `\r\n` line endings
+//! become `\n`), and relays it to the clients attached on the socket
+//! `<id>.tty.sock`.
+//!
+//! As their output is a terminal, programs that buffer stdout when it is a
+//! pipe or a file (C stdio, Python, ...) flush every line, so the log can be
+//! followed with `demon tail -f` as the daemon writes it.
+//!
+//! Clients receive the raw terminal output and send framed messages: a kind
+//! byte, a big-endian u16 length and the payload, which is either input for
+//! the daemon ([`INPUT`]) or the client's window size ([`RESIZE`], rows and
+//! columns as big-endian u16).
+
+use crate::ansi::Stripper;
+use crate::pidfile::{DaemonMeta, PidFile, build_file_path};
+use anyhow::Result;
+use std::fs::File;
+use std::io::{IsTerminal, Read, Write};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Message carrying input for the daemon
+const INPUT: u8 = b'i';
+/// Message carrying the window size of a client
+const RESIZE: u8 = b'w';
+
+/// Key detaching `demon attach` (Ctrl-])
+const DETACH_KEY: u8 = 0x1d;
+
+/// How long the supervisor waits for a slow client before dropping it
+const CLIENT_WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Extension of the attach socket of a daemon
+pub(crate) const SOCKET_EXTENSION: &str = "tty.sock";
+
+/// A new pseudo-terminal pair
+pub(crate) struct Pty {
+    pub(crate) master: OwnedFd,
+    pub(crate) slave: OwnedFd,
+}
+
+impl Pty {
+    pub(crate) fn open() -> Result<Self> {
+        let (mut master, mut slave) = (-1, -1);
+        let size = libc::winsize {
+            ws_row: 24,
+            ws_col: 80,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        };
+        // SAFETY: the out pointers are valid and name may be null
+        let result = unsafe {
+            libc::openpty(
+                &mut master,
+                &mut slave,
+                std::ptr::null_mut(),
+                std::ptr::null(),
+                &size,
+            )
+        };
+        if result == -1 {
+            return Err(anyhow::anyhow!(
+                "Failed to allocate a pseudo-terminal: {}",
+                std::io::Error::last_os_error()
+            ));
+        }
+        // SAFETY: openpty returned two new descriptors that we now own
+        let (master, slave) =
+            unsafe { (OwnedFd::from_raw_fd(master), OwnedFd::from_raw_fd(slave)) };
+        // The daemon only gets the slave side
+        // SAFETY: fcntl on a valid descriptor
+        unsafe { libc::fcntl(master.as_raw_fd(), libc::F_SETFD, libc::FD_CLOEXEC) };
+        Ok(Self { master, slave })
+    }
+}
+
+fn window_size(fd: RawFd) -> Option<(u16, u16)> {
+    // SAFETY: size is plain data written by the kernel
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    // SAFETY: TIOCGWINSZ writes a winsize
+    if unsafe { libc::ioctl(fd, libc::TIOCGWINSZ, &mut size) } == -1 {
+        return None;
+    }
+    Some((size.ws_row, size.ws_col))
+}
+
+fn set_window_size(fd: RawFd, rows: u16, cols: u16) {
+    let size = libc::winsize {
+        ws_row: rows,
+        ws_col: cols,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    // SAFETY: TIOCSWINSZ reads a winsize; the kernel signals the foreground
+    // process group with SIGWINCH
+    unsafe { libc::ioctl(fd, libc::TIOCSWINSZ, &size) };
+}
+
+fn frame(kind: u8, payload: &[u8]) -> Vec<u8> {
+    let mut message = Vec::with_capacity(payload.len() + 3);
+    message.push(kind);
+    message.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+    message.extend_from_slice(payload);
+    message
+}
+
+fn poll_fds(fds: &mut [libc::pollfd], timeout: i32) -> Result<()> {
+    // SAFETY: fds is a valid array of pollfd for the duration of the call
+    let ready = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, timeout) };
+    if ready == -1 {
+        let err = std::io::Error::last_os_error();
+        if err.kind() != std::io::ErrorKind::Interrupted {
+            return Err(err.into());
+        }
+        for fd in fds {
+            fd.revents = 0;
+        }
+    }
+    Ok(())
+}
+
+fn pollfd(fd: RawFd) -> libc::pollfd {
+    libc::pollfd {
+        fd,
+        events: libc::POLLIN,
+        revents: 0,
+    }
+}
+
+/// An attached client of the supervisor
+struct Client {
+    stream: UnixStream,
+    /// Bytes of an incomplete message
+    pending: Vec<u8>,
+}
+
+/// Supervisor side: drains the master into the log and relays it to clients
+pub(crate) struct Relay {
+    master: Option<File>,
+    log: File,
+    listener: UnixListener,
+    socket_path: PathBuf,
+    clients: Vec<Client>,
+    /// The last output ended with a carriage return not yet written to the log
+    pending_cr: bool,
+    /// Removes escape sequences from the log, with `--strip-ansi`
+    stripper: Option<Stripper>,
+}
+
+impl Relay {
+    pub(crate) fn new(root_dir: &Path, id: &str, master: OwnedFd, log: File) -> Result<Self> {
+        let socket_path = build_file_path(root_dir, id, SOCKET_EXTENSION);
+        let _ = std::fs::remove_file(&socket_path);
+        let listener = UnixListener::bind(&socket_path)
+            .map_err(|e| anyhow::anyhow!("Failed to bind {}: {}", socket_path.display(), e))?;
+        listener.set_nonblocking(true)?;
+        Ok(Self {
+            master: Some(File::from(master)),
+            log,
+            listener,
+            socket_path,
+            clients: Vec::new(),
+            pending_cr: false,
+            stripper: None,
+        })
+    }
+
+    /// Write the output to the log without escape sequences; clients still
+    /// get it as it is
+    pub(crate) fn strip_ansi(&mut self) {
+        self.stripper = Some(Stripper::default());
+    }
+
+    /// Descriptors to wait on, in the order [`Relay::handle`] expects them
+    pub(crate) fn pollfds(&self) -> Vec<libc::pollfd> {
+        let mut fds = vec![
+            pollfd(self.master.as_ref().map_or(-1, |master| master.as_raw_fd())),
+            pollfd(self.listener.as_raw_fd()),
+        ];
+        fds.extend(
+            self.clients
+                .iter()
+                .map(|client| pollfd(client.stream.as_raw_fd())),
+        );
+        fds
+    }
+
+    /// Handle whatever [`Relay::pollfds`] reported as ready
+    pub(crate) fn handle(&mut self, fds: &[libc::pollfd]) {
+        // Clients first, while their indices still match the descriptors
+        let mut gone = Vec::new();
+        for (index, fd) in fds[2..].iter().enumerate() {
+            if fd.revents != 0 && !self.read_client(index) {
+                gone.push(index);
+            }
+        }
+        for index in gone.into_iter().rev() {
+            self.clients.remove(index);
+        }
+        if fds[0].revents != 0 {
+            self.read_output();
+        }
+        if fds[1].revents & libc::POLLIN != 0 {
+            while let Ok((stream, _)) = self.listener.accept() {
+                let _ = stream.set_nonblocking(false);
+                let _ = stream.set_write_timeout(Some(CLIENT_WRITE_TIMEOUT));
+                self.clients.push(Client {
+                    stream,
+                    pending: Vec::new(),
+                });
+            }
+        }
+    }
+
+    /// Copy the output of the daemon to the log and the clients. Returns false
+    /// once the terminal is closed (every process using it has exited).
+    fn read_output(&mut self) -> bool {
+        let Some(master) = &mut self.master else {
+            return false;
+        };
+        let mut buffer = [0; 8192];
+        let read = match master.read(&mut buffer) {
+            Ok(0) | Err(_) => {
+                // EIO once the last process holding the terminal exited
+                self.master = None;
+                return false;
+            }
+            Ok(read) => read,
+        };
+        let output = &buffer[..read];
+        let mut line = log_line_endings(output, &mut self.pending_cr);
+        if let Some(stripper) = &mut self.stripper {
+            line = stripper.strip(&line);
+        }
+        if let Err(e) = self.log.write_all(&line) {
+            tracing::warn!("Failed to write terminal output to the log: {}", e);
+        }
+        self.clients
+            .retain_mut(|client| client.stream.write_all(output).is_ok());
+        true
+    }
+
+    /// Read from a client, returning false once it is gone
+    fn read_client(&mut self, index: usize) -> bool {
+        let client = &mut self.clients[index];
+        let mut buffer = [0; 4096];
+        let read = match client.stream.read(&mut buffer) {
+            Ok(0) | Err(_) => return false,
+            Ok(read) => read,
+        };
+        client.pending.extend_from_slice(&buffer[..read]);
+
+        while client.pending.len() >= 3 {
+            let len = u16::from_be_bytes([client.pending[1], client.pending[2]]) as usize;
+            if client.pending.len() < 3 + len {
+                break;
+            }
+            let message: Vec<u8> = client.pending.drain(..3 + len).collect();
+            let payload = &message[3..];
+            let Some(master) = &mut self.master else {
+                continue;
+            };
+            match message[0] {
+                INPUT => {
+                    let _ = master.write_all(payload);
+                }
+                RESIZE if payload.len() == 4 => set_window_size(
+                    master.as_raw_fd(),
+                    u16::from_be_bytes([payload[0], payload[1]]),
+                    u16::from_be_bytes([payload[2], payload[3]]),
+                ),
+                _ => {}
+            }
+        }
+        true
+    }
+
+    /// Copy the last output once the daemon has exited, then disconnect the
+    /// clients and remove the socket
+    pub(crate) fn finish(mut self) {
+        let mut fds = [pollfd(self.master.as_ref().map_or(-1, |m| m.as_raw_fd()))];
+        while self.master.is_some() {
+            fds[0].revents = 0;
+            // Processes the daemon left behind may keep the terminal open
+            if poll_fds(&mut fds, 0).is_err() || fds[0].revents == 0 || !self.read_output() {
+                break;
+            }
+        }
+        if self.pending_cr {
+            let _ = self.log.write_all(b"\r");
+        }
+        let _ = std::fs::remove_file(&self.socket_path);
+    }
+}
+
+/// Terminal output with the `\r\n` line endings of the terminal turned into
+/// `\n` for the log, keeping lone carriage returns such as those of progress
+/// bars. A trailing `\r` is held back in `pending_cr` until the next output
+/// shows whether a newline follows.
+fn log_line_endings(output: &[u8], pending_cr: &mut bool) -> Vec<u8> {
+    let mut line = Vec::with_capacity(output.len() + 1);
+    for &byte in output {
+        if std::mem::take(pending_cr) && byte != b'\n' {
+            line.push(b'\r');
+        }
+        if byte == b'\r' {
+            *pending_cr = true;
+        } else {
+            line.push(byte);
+        }
+    }
+    line
+}
+
+/// Puts a terminal into raw mode until dropped
+struct RawMode {
+    fd: RawFd,
+    original: libc::termios,
+}
+
+impl RawMode {
+    fn enable(fd: RawFd) -> Result<Self> {
+        // SAFETY: termios is plain data written by tcgetattr
+        let mut original: libc::termios = unsafe { std::mem::zeroed() };
+        // SAFETY: tcgetattr and tcsetattr on a valid descriptor
+        unsafe {
+            if libc::tcgetattr(fd, &mut original) == -1 {
+                return Err(std::io::Error::last_os_error().into());
+            }
+            let mut raw = original;
+            libc::cfmakeraw(&mut raw);
+            if libc::tcsetattr(fd, libc::TCSANOW, &raw) == -1 {
+                return Err(std::io::Error::last_os_error().into());
+            }
+        }
+        Ok(Self { fd, original })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        // SAFETY: restores the settings read in enable
+        unsafe { libc::tcsetattr(self.fd, libc::TCSANOW, &self.original) };
+    }
+}
+
+/// Connect the current terminal to a daemon started with `--pty` until the
+/// detach key is pressed or the daemon exits
+pub(crate) fn attach(id: &str, root_dir: &Path) -> Result<()> {
+    if !PidFile::exists(root_dir, id) {
+        return Err(anyhow::anyhow!("Process '{}' not found (no PID file)", id));
+    }
+    let meta = DaemonMeta::read(root_dir, id);
+    if !meta.pty {
+        return Err(anyhow::anyhow!(
+            "Daemon '{}' was not started with --pty, nothing to attach to",
+            id
+        ));
+    }
+    let mut stream = UnixStream::connect(build_file_path(root_dir, id, SOCKET_EXTENSION))
+        .map_err(|e| anyhow::anyhow!("Cannot attach to '{}': {}", id, e))?;
+
+    let stdin = std::io::stdin();
+    let interactive = stdin.is_terminal();
+    if interactive {
+        eprintln!("Attached to '{id}'; press Ctrl-] to detach");
+    }
+    let raw = if interactive {
+        Some(RawMode::enable(stdin.as_raw_fd())?)
+    } else {
+        None
+    };
+
+    let mut size = None;
+    let mut stdin_open = true;
+    let mut stdout = std::io::stdout();
+    let mut buffer = [0; 8192];
+    let detached = loop {
+        if interactive {
+            let current = window_size(stdin.as_raw_fd());
+            if current != size
+                && let Some((rows, cols)) = current
+            {
+                let mut payload = rows.to_be_bytes().to_vec();
+                payload.extend_from_slice(&cols.to_be_bytes());
+                stream.write_all(&frame(RESIZE, &payload))?;
+                size = current;
+            }
+        }
+
+        let mut fds = [
+            pollfd(if stdin_open { stdin.as_raw_fd() } else { -1 }),
+            pollfd(stream.as_raw_fd()),
+        ];
+        // Wake up now and then to notice window size changes
+        poll_fds(&mut fds, 250)?;
+
+        if fds[1].revents != 0 {
+            let read = stream.read(&mut buffer).unwrap_or(0);
+            if read == 0 {
+                break false;
+            }
+            stdout.write_all(&buffer[..read])?;
+            stdout.flush()?;
+        }
+        if fds[0].revents != 0 {
+            let read = stdin.lock().read(&mut buffer)?;
+            if read == 0 {
+                // Keep showing output until the daemon exits
+                stdin_open = false;
+                continue;
+            }
+            let input = &buffer[..read];
+            let detach = interactive.then(|| input.iter().position(|&b| b == DETACH_KEY));
+            let input = match detach.flatten() {
+                Some(at) => &input[..at],
+                None => input,
+            };
+            for chunk in input.chunks(u16::MAX as usize) {
+                stream.write_all(&frame(INPUT, chunk))?;
+            }
+            if detach.flatten().is_some() {
+                break true;
+            }
+        }
+    };
+
+    drop(raw);
+    if detached {
+        eprintln!("\r\nDetached from '{id}'");
+    } else if interactive {
+        eprintln!("\r\nDaemon '{id}' has exited");
+    }
+    Ok(())
+}
@@ -0,0 +1,146 @@
+//! `demon up --wait-healthy`: wait until the daemons of the manifest are
+//! ready, the step a CI job needs before running integration tests.
+//!
+//! A daemon is ready once it runs, has sent READY=1 when it uses
+//! `sd_notify`, and its `health_cmd`, if it has one, has passed once. Health
+//! commands are run here, once a second until they pass, rather than by the
+//! agent, so none needs to be running. Every daemon is reported as it becomes
+//! ready, with the time since `up` started, and the ones still not ready at
+//! the timeout are listed with what they were waiting for.
+
+use crate::{
+    agent,
+    manifest::Service,
+    pidfile::{DaemonMeta, PidFile},
+    progress::Progress,
+    status::is_daemon_running,
+};
+use anyhow::Result;
+use std::path::Path;
+use std::process::Child;
+use std::time::{Duration, Instant};
+
+/// How often the daemons are looked at
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Time between a failed health check and the next one
+const HEALTH_RETRY: Duration = Duration::from_secs(1);
+
+/// A daemon being waited for
+struct Pending<'a> {
+    service: &'a Service,
+    /// Health check running now
+    check: Option<Child>,
+    next_check: Instant,
+    /// How the last health check failed
+    check_failure: Option<String>,
+    /// What the daemon is waiting for, for the report at the timeout
+    waiting_for: String,
+}
+
+/// Wait until `services` are ready or `timeout` has passed since `start`,
+/// printing each as it becomes ready. Returns the daemons that did not become
+/// ready, with what they were waiting for.
+pub(crate) fn wait_healthy(
+    root_dir: &Path,
+    services: &[&Service],
+    start: Instant,
+    timeout: Duration,
+) -> Result<Vec<(String, String)>> {
+    let mut pending: Vec<Pending> = services
+        .iter()
+        .map(|service| Pending {
+            service,
+            check: None,
+            next_check: Instant::now(),
+            check_failure: None,
+            waiting_for: "not started".to_string(),
+        })
+        .collect();
+    let mut progress = Progress::start("wait-healthy", None, Some(services.len() as u64));
+
+    loop {
+        let mut still_pending = Vec::new();
+        for mut daemon in pending {
+            match poll(root_dir, &mut daemon)? {
+                Some(how) => {
+                    println!(
+                        "{:>7.1}s  {} {}",
+                        start.elapsed().as_secs_f64(),
+                        daemon.service.id,
+                        how
+                    );
+                    progress.step(Some(&daemon.service.id), "ready");
+                }
+                None => still_pending.push(daemon),
+            }
+        }
+        pending = still_pending;
+        if pending.is_empty() || start.elapsed() >= timeout {
+            break;
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    }
+
+    if pending.is_empty() {
+        progress.finish(None, "healthy", true);
+    } else {
+        progress.finish(None, "timeout", false);
+    }
+    Ok(pending
+        .into_iter()
+        .map(|mut daemon| {
+            if let Some(mut check) = daemon.check.take() {
+                let _ = check.kill();
+                let _ = check.wait();
+            }
+            (daemon.service.id.clone(), daemon.waiting_for)
+        })
+        .collect())
+}
+
+/// Check on a daemon, returning how it became ready once it is
+fn poll(root_dir: &Path, daemon: &mut Pending) -> Result<Option<&'static str>> {
+    let id = &daemon.service.id;
+    let policy = &daemon.service.policy;
+    let Ok(data) = PidFile::read(root_dir, id) else {
+        daemon.waiting_for = "not running".to_string();
+        return Ok(None);
+    };
+    if !is_daemon_running(root_dir, id, data.pid) {
+        daemon.waiting_for = format!("exited; see 'demon cat {id}'");
+        return Ok(None);
+    }
+    if policy.uses_sd_notify() && DaemonMeta::read(root_dir, id).ready_at.is_none() {
+        daemon.waiting_for = "no READY=1 yet".to_string();
+        return Ok(None);
+    }
+    let Some(command) = &policy.health_cmd else {
+        return Ok(Some(match policy.uses_sd_notify() {
+            true => "is ready (READY=1)",
+            false => "is running",
+        }));
+    };
+
+    match daemon.check.as_mut().map(Child::try_wait).transpose()? {
+        Some(Some(status)) if status.success() => return Ok(Some("passed its health check")),
+        Some(Some(status)) => {
+            daemon.check = None;
+            daemon.next_check = Instant::now() + HEALTH_RETRY;
+            daemon.check_failure = Some(match status.code() {
+                Some(code) => format!("health check failing with exit code {code}"),
+                None => "health check failing".to_string(),
+            });
+        }
+        Some(None) => {}
+        None if Instant::now() >= daemon.next_check => {
+            daemon.check = Some(agent::spawn_health_check(command, id, data.pid)?);
+        }
+        None => {}
+    }
+    daemon.waiting_for = daemon
+        .check_failure
+        .clone()
+        .unwrap_or_else(|| "health check not passed yet".to_string());
+    Ok(None)
+}
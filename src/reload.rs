@@ -0,0 +1,125 @@
+//! `demon reload`: ask a running daemon to re-read its configuration.
+//!
+//! Like `systemctl reload` for nginx-style services, this only sends a signal,
+//! so the daemon keeps its PID, connections and state. The signal is SIGHUP
+//! unless another one was chosen with `demon run --reload-signal`, which is
+//! stored with the daemon's policies.
+
+use crate::{
+    events,
+    pidfile::{DaemonMeta, PidFile, PidFileReadError},
+    status::is_daemon_running,
+    stop::signal_daemon,
+};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// Signals known by name
+const SIGNALS: &[(&str, i32)] = &[
+    ("HUP", libc::SIGHUP),
+    ("INT", libc::SIGINT),
+    ("QUIT", libc::SIGQUIT),
+    ("ABRT", libc::SIGABRT),
+    ("KILL", libc::SIGKILL),
+    ("USR1", libc::SIGUSR1),
+    ("SEGV", libc::SIGSEGV),
+    ("USR2", libc::SIGUSR2),
+    ("ALRM", libc::SIGALRM),
+    ("TERM", libc::SIGTERM),
+    ("CONT", libc::SIGCONT),
+    ("TSTP", libc::SIGTSTP),
+    ("WINCH", libc::SIGWINCH),
+    ("PWR", libc::SIGPWR),
+];
+
+/// A signal, given as `HUP`, `SIGHUP` or a number
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub(crate) struct Signal(pub(crate) i32);
+
+impl Signal {
+    pub(crate) const HUP: Signal = Signal(libc::SIGHUP);
+}
+
+impl std::str::FromStr for Signal {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if let Ok(number) = value.parse::<i32>() {
+            return match number {
+                1..=64 => Ok(Signal(number)),
+                _ => Err(format!("invalid signal number {number}")),
+            };
+        }
+        let upper = value.to_ascii_uppercase();
+        let name = upper.strip_prefix("SIG").unwrap_or(&upper);
+        SIGNALS
+            .iter()
+            .find(|(known, _)| *known == name)
+            .map(|(_, number)| Signal(*number))
+            .ok_or_else(|| format!("unknown signal '{value}', expected e.g. HUP, USR1 or 10"))
+    }
+}
+
+impl std::fmt::Display for Signal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match SIGNALS.iter().find(|(_, number)| *number == self.0) {
+            Some((name, _)) => write!(f, "SIG{name}"),
+            None => write!(f, "signal {}", self.0),
+        }
+    }
+}
+
+impl TryFrom<String> for Signal {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<Signal> for String {
+    fn from(signal: Signal) -> Self {
+        match SIGNALS.iter().find(|(_, number)| *number == signal.0) {
+            Some((name, _)) => name.to_string(),
+            None => signal.0.to_string(),
+        }
+    }
+}
+
+/// Send the reload signal of a running daemon, returning its PID and the
+/// signal sent
+pub(crate) fn reload_daemon(id: &str, root_dir: &Path) -> Result<(u32, Signal)> {
+    let pid = match PidFile::read(root_dir, id) {
+        Ok(data) => data.pid,
+        Err(PidFileReadError::FileNotFound) => {
+            return Err(anyhow::anyhow!("Process '{}' not found (no PID file)", id));
+        }
+        Err(err) => {
+            return Err(anyhow::anyhow!(
+                "Cannot read PID file for '{}': {}",
+                id,
+                err
+            ));
+        }
+    };
+    if !is_daemon_running(root_dir, id, pid) {
+        return Err(anyhow::anyhow!("Process '{}' is not running", id));
+    }
+
+    let signal = DaemonMeta::read(root_dir, id)
+        .policy
+        .reload_signal
+        .unwrap_or(Signal::HUP);
+    tracing::info!("Sending {} to daemon '{}' (PID {})", signal, id, pid);
+    signal_daemon(root_dir, id, pid, signal.0)?;
+    events::emit(
+        root_dir,
+        id,
+        events::Kind::Reloaded,
+        Some(pid),
+        Some(signal.to_string()),
+    );
+    Ok((pid, signal))
+}
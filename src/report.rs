@@ -0,0 +1,297 @@
+//! `demon report`: the state of the daemons as a status page, for a standup
+//! note or the summary of a CI job.
+//!
+//! The page has a table of the daemons (status, uptime, resources, restarts
+//! and health), the daemons failing their health checks, the restarts,
+//! crashes and other disruptions since `--since`, and the last lines of every
+//! log, linked from the table. It is written in Markdown, which reads well in
+//! a terminal too, so the text format prints the same page. In JSON it is one
+//! record with all of that; CSV and plain print the rows of the table.
+
+use crate::{
+    agent,
+    events::{self, DaemonEvent, Kind},
+    format_bytes, format_duration, format_timestamp,
+    logs::{Stream, read_last_n_lines},
+    output::{self, Format},
+    pidfile::build_file_path,
+    status::daemons_json,
+    unix_now,
+};
+use anyhow::Result;
+use serde_json::Value;
+use std::fmt::Write;
+use std::path::Path;
+use std::time::Duration;
+
+/// Fields of the daemons in the CSV and plain formats
+const FIELDS: [&str; 9] = [
+    "id",
+    "status",
+    "pid",
+    "uptime_secs",
+    "cpu_percent",
+    "rss_bytes",
+    "restarts",
+    "healthy",
+    "error",
+];
+
+/// Events reported as disruptions
+const DISRUPTIONS: [Kind; 4] = [Kind::Restarted, Kind::Crashed, Kind::Unhealthy, Kind::Chaos];
+
+pub(crate) struct ReportOptions {
+    /// Daemons to report on, or empty for every daemon
+    pub(crate) ids: Vec<String>,
+    /// Unix time from which events are reported
+    pub(crate) since: u64,
+    /// Lines of each log to include
+    pub(crate) lines: usize,
+}
+
+/// The daemons reported on, with their restarts, health and log tails, and
+/// the disruptions since `options.since`
+pub(crate) fn collect(
+    root_dir: &Path,
+    options: &ReportOptions,
+) -> Result<(Vec<Value>, Vec<DaemonEvent>)> {
+    let mut daemons = daemons_json(root_dir)?;
+    if let Some(missing) = options
+        .ids
+        .iter()
+        .find(|id| !daemons.iter().any(|daemon| daemon["id"] == id.as_str()))
+    {
+        return Err(anyhow::anyhow!(
+            "Process '{}' not found (no PID file)",
+            missing
+        ));
+    }
+    if !options.ids.is_empty() {
+        daemons.retain(|daemon| options.ids.iter().any(|id| daemon["id"] == id.as_str()));
+    }
+
+    let filter = events::Filter {
+        id: None,
+        since: Some(options.since),
+    };
+    let events: Vec<DaemonEvent> = events::read(root_dir, &filter)?
+        .into_iter()
+        .filter(|event| DISRUPTIONS.contains(&event.event))
+        .filter(|event| {
+            daemons
+                .iter()
+                .any(|daemon| daemon["id"] == event.id.as_str())
+        })
+        .collect();
+    for daemon in &mut daemons {
+        let id = daemon["id"].as_str().unwrap_or_default().to_string();
+        let restarts = events
+            .iter()
+            .filter(|event| event.id == id && event.event == Kind::Restarted)
+            .count();
+        let healthy = agent::daemon_state(root_dir, &id).and_then(|state| state.healthy);
+        let tail = |stream: Stream| {
+            read_last_n_lines(
+                build_file_path(root_dir, &id, stream.extension()),
+                options.lines,
+            )
+            .ok()
+        };
+        if let Some(object) = daemon.as_object_mut() {
+            object.insert("restarts".to_string(), restarts.into());
+            object.insert("healthy".to_string(), healthy.into());
+            object.insert("stdout_tail".to_string(), tail(Stream::Stdout).into());
+            object.insert("stderr_tail".to_string(), tail(Stream::Stderr).into());
+        }
+    }
+    Ok((daemons, events))
+}
+
+pub(crate) fn print_report(root_dir: &Path, options: &ReportOptions, format: Format) -> Result<()> {
+    let (daemons, events) = collect(root_dir, options)?;
+    match format {
+        Format::Text | Format::Markdown => {
+            print!("{}", markdown(root_dir, options, &daemons, &events));
+            Ok(())
+        }
+        Format::Json => {
+            let record = json(root_dir, options, &daemons, &events);
+            println!("{}", serde_json::to_string_pretty(&record)?);
+            Ok(())
+        }
+        format => output::print_records(format, &FIELDS, &daemons),
+    }
+}
+
+/// The report as one JSON record
+pub(crate) fn json(
+    root_dir: &Path,
+    options: &ReportOptions,
+    daemons: &[Value],
+    events: &[DaemonEvent],
+) -> Value {
+    serde_json::json!({
+        "generated_at": unix_now(),
+        "root_dir": root_dir,
+        "since": options.since,
+        "daemons": daemons,
+        "events": events,
+    })
+}
+
+/// The status page
+pub(crate) fn markdown(
+    root_dir: &Path,
+    options: &ReportOptions,
+    daemons: &[Value],
+    events: &[DaemonEvent],
+) -> String {
+    let mut page = String::new();
+    let since = format_timestamp(options.since);
+    let _ = writeln!(page, "# Daemon report\n");
+    let _ = writeln!(
+        page,
+        "Generated {} for `{}`, with events since {}.\n",
+        format_timestamp(unix_now()),
+        root_dir.display(),
+        since
+    );
+    if daemons.is_empty() {
+        let _ = writeln!(page, "No daemons.");
+        return page;
+    }
+
+    let _ = writeln!(
+        page,
+        "| Daemon | Status | PID | Uptime | CPU | Memory | Restarts | Health | Logs |"
+    );
+    let _ = writeln!(
+        page,
+        "| --- | --- | ---: | ---: | ---: | ---: | ---: | --- | --- |"
+    );
+    for daemon in daemons {
+        let id = daemon["id"].as_str().unwrap_or_default();
+        let mut status = daemon["status"].as_str().unwrap_or_default().to_string();
+        if let Some(error) = daemon["error"].as_str() {
+            status.push_str(&format!(" ({error})"));
+        }
+        let number = |key: &str| daemon[key].as_u64();
+        let cells = [
+            format!("**{id}**"),
+            status,
+            number("pid").map(|pid| pid.to_string()).unwrap_or_default(),
+            number("uptime_secs")
+                .map(|secs| format_duration(Duration::from_secs(secs)))
+                .unwrap_or_default(),
+            daemon["cpu_percent"]
+                .as_f64()
+                .map(|cpu| format!("{cpu:.1}%"))
+                .unwrap_or_default(),
+            number("rss_bytes").map(format_bytes).unwrap_or_default(),
+            number("restarts").unwrap_or_default().to_string(),
+            match daemon["healthy"].as_bool() {
+                Some(true) => "healthy".to_string(),
+                Some(false) => "**unhealthy**".to_string(),
+                None => String::new(),
+            },
+            format!("[logs](#{})", anchor(&format!("Logs of {id}"))),
+        ];
+        let cells: Vec<String> = cells
+            .iter()
+            .map(|cell| output::markdown_cell(cell))
+            .collect();
+        let _ = writeln!(page, "| {} |", cells.join(" | "));
+    }
+
+    let _ = writeln!(page, "\n## Failing health checks\n");
+    let unhealthy: Vec<&str> = daemons
+        .iter()
+        .filter(|daemon| daemon["healthy"] == false)
+        .filter_map(|daemon| daemon["id"].as_str())
+        .collect();
+    if unhealthy.is_empty() {
+        let _ = writeln!(page, "None.");
+    }
+    for id in unhealthy {
+        let detail = events
+            .iter()
+            .rev()
+            .find(|event| event.id == id && event.event == Kind::Unhealthy)
+            .and_then(|event| event.detail.as_deref())
+            .map(|detail| format!(": {detail}"))
+            .unwrap_or_default();
+        let _ = writeln!(page, "- **{id}** is failing its health check{detail}");
+    }
+
+    let _ = writeln!(page, "\n## Recent restarts\n");
+    if events.is_empty() {
+        let _ = writeln!(page, "None since {since}.");
+    } else {
+        let _ = writeln!(page, "| Time | Daemon | Event | Detail |");
+        let _ = writeln!(page, "| --- | --- | --- | --- |");
+        for event in events.iter().rev() {
+            let _ = writeln!(
+                page,
+                "| {} | {} | {} | {} |",
+                format_timestamp(event.ts),
+                output::markdown_cell(&event.id),
+                event.event,
+                output::markdown_cell(event.detail.as_deref().unwrap_or_default())
+            );
+        }
+    }
+
+    let _ = writeln!(page, "\n## Logs");
+    for daemon in daemons {
+        let id = daemon["id"].as_str().unwrap_or_default();
+        let _ = writeln!(page, "\n### Logs of {id}\n");
+        let mut any = false;
+        for stream in [Stream::Stdout, Stream::Stderr] {
+            let Some(text) = daemon[format!("{}_tail", stream.extension())]
+                .as_str()
+                .filter(|text| !text.is_empty())
+            else {
+                continue;
+            };
+            any = true;
+            let fence = fence(text);
+            let _ = writeln!(
+                page,
+                "Last {} lines of `{}`:\n\n{fence}text\n{}\n{fence}\n",
+                options.lines,
+                build_file_path(root_dir, id, stream.extension()).display(),
+                text.trim_end_matches('\n')
+            );
+        }
+        if !any {
+            let _ = writeln!(page, "No output.");
+        }
+    }
+    page.truncate(page.trim_end().len());
+    page.push('\n');
+    page
+}
+
+/// The anchor GitHub gives a heading, to link to it
+fn anchor(heading: &str) -> String {
+    heading
+        .to_lowercase()
+        .chars()
+        .filter_map(|c| match c {
+            ' ' => Some('-'),
+            c if c.is_alphanumeric() || c == '-' || c == '_' => Some(c),
+            _ => None,
+        })
+        .collect()
+}
+
+/// A code fence longer than any run of backticks in `text`, so logs cannot
+/// end the block early
+fn fence(text: &str) -> String {
+    let longest = text
+        .split(|c| c != '`')
+        .map(str::len)
+        .max()
+        .unwrap_or_default();
+    "`".repeat(longest.max(2) + 1)
+}
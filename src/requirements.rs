@@ -0,0 +1,175 @@
+//! Start gating on system resources (`--require-free-memory`,
+//! `--require-free-disk`).
+//!
+//! The requirements are checked whenever the daemon is about to be spawned:
+//! by `demon run` and `demon up`, and by the agent when it restarts a daemon
+//! or starts a scheduled job. A daemon that would run out of memory or fill
+//! the disk halfway through is not started at all, with a message saying
+//! what is missing.
+
+use crate::{cgroup::deserialize_size, format_bytes, parse_bytes};
+use anyhow::Result;
+use clap::Args;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// Resource requirement flags of `run`
+#[derive(Args, Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct Requirements {
+    /// Do not start the daemon while less memory than this is available
+    /// (MemAvailable), e.g. 2G
+    #[arg(long, value_name = "SIZE", value_parser = parse_bytes)]
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "deserialize_size"
+    )]
+    pub(crate) require_free_memory: Option<u64>,
+
+    /// Do not start the daemon while the filesystem of PATH (default: the
+    /// current directory) has less free space than SIZE, given as
+    /// SIZE[@PATH], e.g. 5G@/var; repeatable
+    #[arg(long, value_name = "SIZE[@PATH]", value_parser = DiskRequirement::parse)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub(crate) require_free_disk: Vec<DiskRequirement>,
+}
+
+/// Free space needed on the filesystem of a path
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub(crate) struct DiskRequirement {
+    pub(crate) bytes: u64,
+    pub(crate) path: PathBuf,
+}
+
+impl DiskRequirement {
+    fn parse(value: &str) -> Result<DiskRequirement, String> {
+        let (size, path) = value.split_once('@').unwrap_or((value, "."));
+        if path.is_empty() {
+            return Err(format!(
+                "invalid requirement '{value}', expected e.g. 5G@/var"
+            ));
+        }
+        Ok(DiskRequirement {
+            bytes: parse_bytes(size)?,
+            path: PathBuf::from(path),
+        })
+    }
+}
+
+impl TryFrom<String> for DiskRequirement {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        DiskRequirement::parse(&value)
+    }
+}
+
+impl From<DiskRequirement> for String {
+    fn from(requirement: DiskRequirement) -> String {
+        requirement.to_string()
+    }
+}
+
+impl std::fmt::Display for DiskRequirement {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}@{}", self.bytes, self.path.display())
+    }
+}
+
+impl Requirements {
+    pub(crate) fn is_empty(&self) -> bool {
+        *self == Requirements::default()
+    }
+
+    /// Make the paths absolute, so the agent checks the same filesystems
+    /// from its own working directory
+    pub(crate) fn resolve(&mut self) -> Result<()> {
+        for requirement in &mut self.require_free_disk {
+            requirement.path = std::path::absolute(&requirement.path)?;
+        }
+        Ok(())
+    }
+
+    /// Command line flags that recreate these requirements
+    pub(crate) fn to_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if let Some(bytes) = self.require_free_memory {
+            args.extend(["--require-free-memory".to_string(), bytes.to_string()]);
+        }
+        for requirement in &self.require_free_disk {
+            args.extend(["--require-free-disk".to_string(), requirement.to_string()]);
+        }
+        args
+    }
+
+    /// Fail with what is missing when the system cannot give the daemon the
+    /// resources it needs
+    pub(crate) fn check(&self, id: &str) -> Result<()> {
+        if let Some(required) = self.require_free_memory {
+            let available = available_memory()?;
+            if available < required {
+                anyhow::bail!(
+                    "Not starting '{id}': {} of memory is available, --require-free-memory needs {}",
+                    format_bytes(available),
+                    format_bytes(required)
+                );
+            }
+        }
+        for requirement in &self.require_free_disk {
+            let free = free_space(&requirement.path)?;
+            if free < requirement.bytes {
+                anyhow::bail!(
+                    "Not starting '{id}': {} is free on the filesystem of {}, --require-free-disk needs {}",
+                    format_bytes(free),
+                    requirement.path.display(),
+                    format_bytes(requirement.bytes)
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// One line describing the requirements, for `demon status`
+    pub(crate) fn summary(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(bytes) = self.require_free_memory {
+            parts.push(format!("{} free memory", format_bytes(bytes)));
+        }
+        for requirement in &self.require_free_disk {
+            parts.push(format!(
+                "{} free on {}",
+                format_bytes(requirement.bytes),
+                requirement.path.display()
+            ));
+        }
+        parts.join(", ")
+    }
+}
+
+/// Memory the kernel estimates can be used without swapping
+fn available_memory() -> Result<u64> {
+    let meminfo = std::fs::read_to_string("/proc/meminfo")?;
+    meminfo
+        .lines()
+        .find_map(|line| line.strip_prefix("MemAvailable:"))
+        .and_then(|value| value.trim().strip_suffix("kB"))
+        .and_then(|kib| kib.trim().parse::<u64>().ok())
+        .map(|kib| kib * 1024)
+        .ok_or_else(|| anyhow::anyhow!("Cannot read the available memory from /proc/meminfo"))
+}
+
+/// Space unprivileged processes may still write on the filesystem of a path
+fn free_space(path: &Path) -> Result<u64> {
+    let c_path = std::ffi::CString::new(path.as_os_str().as_encoded_bytes())?;
+    // SAFETY: stat is plain data and both pointers are valid for the call
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } != 0 {
+        return Err(anyhow::anyhow!(
+            "Cannot check the free space of {}: {}",
+            path.display(),
+            std::io::Error::last_os_error()
+        ));
+    }
+    Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
@@ -0,0 +1,228 @@
+//! Every root directory of the user, for `demon list --all-roots`.
+//!
+//! `run` records its root directory in `roots`, in the directory shared by all
+//! root directories of the user (see [`crate::singleton`]), so one command can
+//! list the daemons of every project on the machine. Root directories that no
+//! longer exist are dropped.
+//!
+//! The same ID or the same command running in more than one root directory is
+//! flagged as a duplicate: two checkouts that both started `db` are usually
+//! about to fight over its port.
+
+use crate::{
+    color,
+    output::{self, Format},
+    singleton,
+    status::{ListOptions, daemons_json},
+    table::Table,
+};
+use anyhow::{Context, Result};
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+
+/// Fields of the records in the structured formats
+const FIELDS: [&str; 8] = [
+    "root",
+    "id",
+    "pid",
+    "status",
+    "uptime_secs",
+    "command",
+    "duplicate_of",
+    "error",
+];
+
+fn roots_path() -> PathBuf {
+    singleton::shared_dir().join("roots")
+}
+
+/// The recorded root directories that still exist
+pub(crate) fn load() -> Vec<PathBuf> {
+    std::fs::read_to_string(roots_path())
+        .unwrap_or_default()
+        .lines()
+        .map(PathBuf::from)
+        .filter(|root| root.is_dir())
+        .collect()
+}
+
+/// Record a root directory, once
+pub(crate) fn register(root_dir: &Path) -> Result<()> {
+    let root_dir = std::fs::canonicalize(root_dir)
+        .with_context(|| format!("Failed to resolve {}", root_dir.display()))?;
+    let _lock = singleton::lock_shared_dir()?;
+    let mut roots = load();
+    if roots.contains(&root_dir) {
+        return Ok(());
+    }
+    roots.push(root_dir);
+    let lines: String = roots
+        .iter()
+        .map(|root| format!("{}\n", root.display()))
+        .collect();
+    let path = roots_path();
+    let tmp = path.with_extension("tmp");
+    std::fs::write(&tmp, lines)?;
+    std::fs::rename(&tmp, &path).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// A running daemon of another root directory with the same ID or command
+struct Duplicate {
+    root: String,
+    id: String,
+}
+
+/// `demon list --all-roots`: the daemons of every root directory, with the
+/// ones running in more than one flagged
+pub(crate) fn list_all(root_dir: &Path, options: &ListOptions, format: Format) -> Result<()> {
+    let mut roots = load();
+    if let Ok(root_dir) = std::fs::canonicalize(root_dir)
+        && !roots.contains(&root_dir)
+    {
+        roots.push(root_dir);
+    }
+    roots.sort();
+
+    let mut records = Vec::new();
+    for root in &roots {
+        let daemons = match daemons_json(root) {
+            Ok(daemons) => daemons,
+            Err(e) => {
+                tracing::warn!("Could not list the daemons in {}: {}", root.display(), e);
+                continue;
+            }
+        };
+        for mut daemon in daemons {
+            if !options.matches_record(&daemon) {
+                continue;
+            }
+            if let Some(object) = daemon.as_object_mut() {
+                object.insert("root".to_string(), root.display().to_string().into());
+            }
+            records.push(daemon);
+        }
+    }
+
+    let duplicates: Vec<Vec<Duplicate>> = records
+        .iter()
+        .map(|daemon| duplicates_of(daemon, &records))
+        .collect();
+    for (daemon, duplicates) in records.iter_mut().zip(&duplicates) {
+        let names: Vec<String> = duplicates
+            .iter()
+            .map(|duplicate| format!("{}@{}", duplicate.id, duplicate.root))
+            .collect();
+        daemon["duplicate_of"] = names.into();
+    }
+
+    if !format.is_text() {
+        return output::print_records(format, &FIELDS, &records);
+    }
+    if records.is_empty() {
+        println!(
+            "No daemon processes found in {} root directories.",
+            roots.len()
+        );
+        return Ok(());
+    }
+    let rows: Vec<Vec<String>> = records
+        .iter()
+        .zip(&duplicates)
+        .map(|(daemon, duplicates)| {
+            let mut id = daemon["id"].as_str().unwrap_or_default().to_string();
+            if !duplicates.is_empty() {
+                id.push_str(" *");
+            }
+            let command = match daemon["error"].as_str() {
+                Some(error) => error.to_string(),
+                None => command(daemon),
+            };
+            vec![
+                daemon["root"].as_str().unwrap_or_default().to_string(),
+                id,
+                daemon["pid"]
+                    .as_u64()
+                    .map(|pid| pid.to_string())
+                    .unwrap_or_default(),
+                daemon["status"].as_str().unwrap_or_default().to_string(),
+                command,
+            ]
+        })
+        .collect();
+    let table = Table::new(
+        &[
+            ("ROOT", 0),
+            ("ID", 20),
+            ("PID", 8),
+            ("STATUS", 10),
+            ("COMMAND", 0),
+        ],
+        &rows,
+        options.widths,
+    );
+    table.print_header();
+    for row in &rows {
+        // Colored after padding, so escape codes do not upset the widths
+        table.print_row(row, |index, text| match index {
+            3 => color::status(options.color, text),
+            _ => text.to_string(),
+        });
+    }
+
+    let mut warned = Vec::new();
+    for (daemon, duplicates) in records.iter().zip(&duplicates) {
+        if duplicates.is_empty() {
+            continue;
+        }
+        let id = daemon["id"].as_str().unwrap_or_default();
+        let root = daemon["root"].as_str().unwrap_or_default();
+        // Listed once per group of duplicates
+        let mut places: Vec<String> = duplicates
+            .iter()
+            .map(|duplicate| format!("'{}' in {}", duplicate.id, duplicate.root))
+            .collect();
+        places.insert(0, format!("'{id}' in {root}"));
+        places.sort();
+        if warned.contains(&places) {
+            continue;
+        }
+        let warning = format!(
+            "* Running in {} root directories: {}; they may conflict, e.g. over a port",
+            places.len(),
+            places.join(", ")
+        );
+        if warned.is_empty() {
+            println!();
+        }
+        println!("{}", color::paint(options.color, &warning, color::YELLOW));
+        warned.push(places);
+    }
+    Ok(())
+}
+
+/// The running daemons of other root directories with the same ID or command
+/// as a running daemon
+fn duplicates_of(daemon: &Value, records: &[Value]) -> Vec<Duplicate> {
+    if daemon["status"] != "RUNNING" {
+        return Vec::new();
+    }
+    records
+        .iter()
+        .filter(|other| other["status"] == "RUNNING" && other["root"] != daemon["root"])
+        .filter(|other| other["id"] == daemon["id"] || other["command"] == daemon["command"])
+        .map(|other| Duplicate {
+            root: other["root"].as_str().unwrap_or_default().to_string(),
+            id: other["id"].as_str().unwrap_or_default().to_string(),
+        })
+        .collect()
+}
+
+fn command(daemon: &Value) -> String {
+    let words: Vec<&str> = daemon["command"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_str)
+        .collect();
+    words.join(" ")
+}
@@ -0,0 +1,465 @@
+//! Starting daemons, detached from the terminal with their output redirected
+//! to log files.
+
+use crate::{
+    agent::{self, Policy},
+    allowlist::Allowlist,
+    cgroup::{self, Cgroup},
+    events, history, hooks, layout,
+    limits::{self, Limits},
+    otel,
+    pidfile::{DaemonMeta, PidFile, RootLock, build_file_path},
+    priority::{self, Priority},
+    proc::{self, ProcStat},
+    roots, runs,
+    sandbox::{self, Sandbox},
+    ship,
+    singleton::Claim,
+    status::is_process_running,
+    stdin, store, supervisor, systemd, timeline, unix_now,
+};
+use anyhow::Result;
+use std::ffi::CString;
+use std::fs::File;
+use std::os::fd::OwnedFd;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+
+/// Options controlling how a daemon is spawned
+#[derive(Debug, Clone, Default)]
+pub struct RunOptions {
+    /// Run under a supervisor process that holds a pidfd for the daemon and
+    /// records its exit status
+    pub supervise: bool,
+    /// Give the daemon a pseudo-terminal, held by its supervisor, that
+    /// `demon attach` connects to; implies `supervise`
+    pub pty: bool,
+    /// Where the daemon reads its standard input from
+    pub(crate) stdin: stdin::Stdin,
+    /// Hooks to run on lifecycle events
+    pub(crate) hooks: hooks::Hooks,
+    /// Policies applied by the agent
+    pub(crate) policy: agent::Policy,
+}
+
+pub(crate) fn run_daemon(
+    id: &str,
+    command: &[String],
+    root_dir: &Path,
+    options: &RunOptions,
+) -> Result<u32> {
+    layout::check_id(id)?;
+    let stdout_file = build_file_path(root_dir, id, "stdout");
+    let stderr_file = build_file_path(root_dir, id, "stderr");
+
+    // Hold the root lock until the PID file is written so a concurrent
+    // `clean` cannot remove the files of a daemon that is just starting
+    let _lock = RootLock::exclusive(root_dir)?;
+
+    // Check if process is already running
+    if is_process_running(root_dir, id)? {
+        return Err(anyhow::anyhow!("Process '{}' is already running", id));
+    }
+    let supervised = options.supervise || options.pty || options.policy.needs_supervisor();
+    if supervised && !store::is_shared(root_dir) {
+        return Err(anyhow::anyhow!(
+            "Daemon '{}' needs a supervisor, which cannot see the state store of {}",
+            id,
+            root_dir.display()
+        ));
+    }
+    if let Some(allowlist) = Allowlist::load(root_dir)? {
+        allowlist.check(root_dir, id, command, options)?;
+    }
+    options.policy.limits.check()?;
+    options.policy.priority.check()?;
+    options.policy.sandbox.check()?;
+    options.policy.requirements.check(id)?;
+    options.policy.autorestart.check()?;
+    options.policy.labels.check()?;
+    if options.policy.systemd_scope {
+        if !options.policy.sandbox.is_empty() {
+            return Err(anyhow::anyhow!(
+                "--systemd-scope cannot be combined with the sandbox, which would confine systemd-run"
+            ));
+        }
+        systemd::check()?;
+    } else {
+        options.policy.resources.check()?;
+    }
+    // Held until the new run is recorded as the owner, so two root
+    // directories cannot both start a singleton
+    let claim = match options.policy.singleton {
+        Some(_) => Some(Claim::acquire(root_dir, id)?),
+        None => None,
+    };
+    if let Ok(previous) = PidFile::read(root_dir, id) {
+        history::record_retired(root_dir, id, &previous, None, None);
+        // Last chance to ship the logs of the previous run before they are
+        // moved into runs/<id>/
+        ship::ship_logs(root_dir, id, &DaemonMeta::read(root_dir, id).hooks);
+        if let Err(e) = runs::archive(root_dir, id, &previous) {
+            tracing::warn!(
+                "Failed to keep the logs of the last run of '{}': {:#}",
+                id,
+                e
+            );
+        }
+    }
+
+    tracing::info!("Starting daemon '{}' with command: {:?}", id, command);
+
+    // Create the output files, truncating logs that were not kept as a past run
+    layout::create_daemon_dir(root_dir, id)?;
+    File::create(&stdout_file)?;
+    File::create(&stderr_file)?;
+    // A supervisor starts a new timeline; without one the old one is wrong
+    let _ = std::fs::remove_file(build_file_path(root_dir, id, timeline::EXTENSION));
+
+    let pid = if supervised {
+        // The supervisor spawns the daemon and writes the PID file itself
+        supervisor::spawn_supervised(id, command, root_dir, options)?
+    } else {
+        // Open files for redirection
+        let stdout_redirect = File::create(&stdout_file)?;
+        let stderr_redirect = File::create(&stderr_file)?;
+
+        let stdin = stdin::open(root_dir, id, &options.stdin)?;
+        let (trace, env) = otel::new_run(id, &options.hooks);
+        let confinement = Confinement::prepare(root_dir, id, &options.policy)?;
+        let child = spawn_daemon(
+            command,
+            &env,
+            stdin,
+            stdout_redirect,
+            stderr_redirect,
+            &confinement,
+        )?;
+        let pid = child.id();
+
+        // Write PID and command to file
+        let pid_file_data = PidFile::new(pid, command.to_vec());
+        pid_file_data.write(root_dir, id)?;
+
+        let meta = DaemonMeta {
+            started_at: Some(unix_now()),
+            start_ticks: ProcStat::read(pid).map(|stat| stat.starttime),
+            boot_id: proc::boot_id(),
+            stdin: options.stdin.clone(),
+            hooks: options.hooks.clone(),
+            policy: options.policy.clone(),
+            trace,
+            cgroup: confinement.cgroup_path(),
+            cwd: std::env::current_dir().ok(),
+            ..Default::default()
+        };
+        meta.write(root_dir, id)?;
+
+        // Don't wait for the child - let it run detached
+        std::mem::forget(child);
+        pid
+    };
+    if let Some(claim) = claim {
+        claim.record(pid)?;
+    }
+    // For `list --all-roots`
+    if let Err(e) = roots::register(root_dir) {
+        tracing::warn!("Failed to record the root directory: {}", e);
+    }
+
+    events::emit(
+        root_dir,
+        id,
+        events::Kind::Started,
+        Some(pid),
+        Some(command.join(" ")),
+    );
+    options.hooks.fire(
+        root_dir,
+        id,
+        &hooks::HookEvent {
+            event: events::Kind::Started,
+            pid,
+            command,
+            exit_code: None,
+            exit_signal: None,
+        },
+    );
+
+    Ok(pid)
+}
+
+/// Find the program to execute like a shell does: as given if it contains a
+/// slash, otherwise in the first `PATH` directory with an executable file of
+/// that name
+pub(crate) fn resolve_program(program: &str) -> Result<PathBuf> {
+    if program.contains('/') {
+        return Ok(PathBuf::from(program));
+    }
+    let path = std::env::var_os("PATH").unwrap_or_else(|| "/usr/local/bin:/usr/bin:/bin".into());
+    std::env::split_paths(&path)
+        .map(|dir| dir.join(program))
+        .find(|candidate| {
+            std::fs::metadata(candidate).is_ok_and(|metadata| {
+                metadata.is_file() && metadata.permissions().mode() & 0o111 != 0
+            })
+        })
+        .ok_or_else(|| anyhow::anyhow!("Failed to start process '{}': not found in PATH", program))
+}
+
+/// Explain why executing `path` failed
+fn exec_error(program: &str, path: &Path, err: &std::io::Error) -> anyhow::Error {
+    let reason = match err.raw_os_error() {
+        Some(libc::ENOEXEC) => format!(
+            "{} is not a valid executable and has no #! line; start it with e.g. #!/bin/sh",
+            path.display()
+        ),
+        Some(libc::ENOENT) if path.exists() => format!(
+            "the interpreter on the #! line of {} does not exist",
+            path.display()
+        ),
+        Some(libc::EACCES) => format!("{}: permission denied", path.display()),
+        _ => err.to_string(),
+    };
+    anyhow::anyhow!("Failed to start process '{}': {}", program, reason)
+}
+
+/// Arguments and environment of execve, allocated before forking since the
+/// child of a multi-threaded process must not allocate
+struct Exec {
+    path: CString,
+    _args: Vec<CString>,
+    argv: Vec<*const libc::c_char>,
+    _vars: Vec<CString>,
+    envp: Vec<*const libc::c_char>,
+}
+
+// SAFETY: the pointers in argv and envp point into the CStrings owned
+// alongside them, which are never modified
+unsafe impl Send for Exec {}
+unsafe impl Sync for Exec {}
+
+impl Exec {
+    /// Execute `command` with our environment plus `env`
+    fn new(path: &Path, command: &[String], env: &[(String, String)]) -> Result<Self> {
+        let path = CString::new(path.as_os_str().as_bytes())?;
+        let args = command
+            .iter()
+            .map(|arg| CString::new(arg.as_bytes()))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut vars: Vec<(Vec<u8>, Vec<u8>)> = std::env::vars_os()
+            .filter(|(key, _)| !env.iter().any(|(k, _)| key.as_bytes() == k.as_bytes()))
+            .map(|(key, value)| (key.as_bytes().to_vec(), value.as_bytes().to_vec()))
+            .collect();
+        vars.extend(
+            env.iter()
+                .map(|(key, value)| (key.as_bytes().to_vec(), value.as_bytes().to_vec())),
+        );
+        let vars = vars
+            .into_iter()
+            .map(|(key, value)| CString::new([key, b"=".to_vec(), value].concat()))
+            .collect::<Result<Vec<_>, _>>()?;
+        let null_terminated = |strings: &[CString]| -> Vec<*const libc::c_char> {
+            strings
+                .iter()
+                .map(|s| s.as_ptr())
+                .chain(std::iter::once(std::ptr::null()))
+                .collect()
+        };
+        Ok(Self {
+            path,
+            argv: null_terminated(&args),
+            _args: args,
+            envp: null_terminated(&vars),
+            _vars: vars,
+        })
+    }
+
+    /// Replace the current process, returning only if that failed
+    fn execve(&self) -> std::io::Error {
+        // SAFETY: path, argv and envp are NUL-terminated and the arrays end
+        // with null
+        unsafe { libc::execve(self.path.as_ptr(), self.argv.as_ptr(), self.envp.as_ptr()) };
+        std::io::Error::last_os_error()
+    }
+}
+
+/// What binds a daemon besides its command: resource limits, and a cgroup of
+/// its own or a systemd scope
+pub(crate) struct Confinement {
+    limits: Limits,
+    priority: Priority,
+    sandbox: Sandbox,
+    cgroup: Option<Cgroup>,
+    /// `systemd-run` command line the daemon's command is run with
+    scope: Option<Vec<String>>,
+}
+
+impl Confinement {
+    /// Create the cgroup of a daemon with this policy; systemd creates scopes
+    /// itself
+    pub(crate) fn prepare(root_dir: &Path, id: &str, policy: &Policy) -> Result<Self> {
+        let (cgroup, scope) = match policy.systemd_scope {
+            true => (
+                None,
+                Some(systemd::scope_command(root_dir, id, &policy.resources)),
+            ),
+            false => (Cgroup::create(root_dir, id, &policy.resources)?, None),
+        };
+        Ok(Self {
+            limits: policy.limits.clone(),
+            priority: policy.priority.clone(),
+            sandbox: policy.sandbox.clone(),
+            cgroup,
+            scope,
+        })
+    }
+
+    /// The cgroup to record in the daemon's metadata
+    pub(crate) fn cgroup_path(&self) -> Option<PathBuf> {
+        self.cgroup.as_ref().map(|cgroup| cgroup.path.clone())
+    }
+}
+
+/// Spawn a daemon process with its output redirected to the given log files.
+///
+/// The child executes the program itself, so a failed exec is reported by
+/// the standard library's close-on-exec error pipe and returned here with its
+/// precise cause, instead of showing up (if at all) in the daemon's stderr.
+/// In particular a script without a #! line fails with ENOEXEC rather than
+/// being retried under `/bin/sh`, as `execvp` would do.
+pub(crate) fn spawn_daemon(
+    command: &[String],
+    env: &[(String, String)],
+    stdin: Stdio,
+    stdout: File,
+    stderr: File,
+    confinement: &Confinement,
+) -> Result<Child> {
+    spawn(
+        command,
+        env,
+        stdin,
+        Stdio::from(stdout),
+        Stdio::from(stderr),
+        false,
+        confinement,
+    )
+}
+
+/// Spawn a daemon process with the slave side of a pseudo-terminal as its
+/// controlling terminal, stdin, stdout and stderr
+pub(crate) fn spawn_daemon_on_terminal(
+    command: &[String],
+    env: &[(String, String)],
+    terminal: &OwnedFd,
+    confinement: &Confinement,
+) -> Result<Child> {
+    let stdio = || -> Result<Stdio> { Ok(Stdio::from(terminal.try_clone()?)) };
+    spawn(
+        command,
+        env,
+        stdio()?,
+        stdio()?,
+        stdio()?,
+        true,
+        confinement,
+    )
+}
+
+fn spawn(
+    command: &[String],
+    env: &[(String, String)],
+    stdin: Stdio,
+    stdout: Stdio,
+    stderr: Stdio,
+    controlling_terminal: bool,
+    confinement: &Confinement,
+) -> Result<Child> {
+    let command: Vec<String> = match &confinement.scope {
+        Some(scope) => scope.iter().chain(command).cloned().collect(),
+        None => command.to_vec(),
+    };
+    let program = &command[0];
+    let path = resolve_program(program)?;
+    tracing::debug!("Executing {} for '{}'", path.display(), program);
+    let exec = Exec::new(&path, &command, env)
+        .map_err(|e| anyhow::anyhow!("Invalid command '{}': {}", program, e))?;
+    let limits = confinement.limits.prepare();
+    let priority = confinement.priority.prepare();
+    let sandbox = confinement.sandbox.prepare(&path)?;
+    let procs = confinement.cgroup.as_ref().map(Cgroup::procs).transpose()?;
+
+    let mut cmd = Command::new(&path);
+    cmd.args(&command[1..])
+        .stdout(stdout)
+        .stderr(stderr)
+        .stdin(stdin);
+
+    // Start the child in a new session so it has no controlling terminal and
+    // does not receive SIGHUP when the terminal that launched it is closed,
+    // or only its own pseudo-terminal
+    // SAFETY: setsid, ioctl, setrlimit, setpriority, ioprio_set,
+    // sched_setaffinity, write, unshare, prctl, landlock_restrict_self and
+    // execve are async-signal-safe, and everything they need was allocated
+    // before forking
+    unsafe {
+        cmd.pre_exec(move || {
+            if libc::setsid() == -1 {
+                return Err(std::io::Error::last_os_error());
+            }
+            if controlling_terminal && libc::ioctl(libc::STDIN_FILENO, libc::TIOCSCTTY, 0) == -1 {
+                return Err(std::io::Error::last_os_error());
+            }
+            limits::apply(&limits)?;
+            priority::apply(&priority)?;
+            // Before executing, so everything the daemon starts is in it too
+            if let Some(procs) = &procs {
+                cgroup::enter(procs)?;
+            }
+            // Last, since it keeps the child from writing to cgroup files
+            sandbox::apply(&sandbox)?;
+            Err(exec.execve())
+        });
+    }
+
+    cmd.spawn().map_err(|e| exec_error(program, &path, &e))
+}
+
+/// Confirm that a freshly spawned daemon is fully detached from our terminal
+pub(crate) fn verify_detach(pid: u32) -> Result<()> {
+    let stat = ProcStat::read(pid).ok_or_else(|| {
+        anyhow::anyhow!(
+            "Cannot verify detach: process {} has already been reaped",
+            pid
+        )
+    })?;
+
+    let mut problems = Vec::new();
+    if stat.session != pid {
+        problems.push(format!("not a session leader (session {})", stat.session));
+    }
+    if stat.pgrp != pid {
+        problems.push(format!("not a process group leader (group {})", stat.pgrp));
+    }
+    if stat.tty_nr != 0 {
+        problems.push(format!("has a controlling terminal (tty {})", stat.tty_nr));
+    }
+
+    if !problems.is_empty() {
+        return Err(anyhow::anyhow!(
+            "Process {} is not detached: {}",
+            pid,
+            problems.join(", ")
+        ));
+    }
+
+    println!(
+        "Detach verified: session {}, process group {}, no controlling terminal",
+        stat.session, stat.pgrp
+    );
+    Ok(())
+}
@@ -0,0 +1,346 @@
+//! Logs of past runs (`demon runs <id>`, `demon cat <id> --run previous`).
+//!
+//! Starting a daemon again moves the logs of its last run into a directory of
+//! their own, `runs/<id>/<start>.<pid>/` in the root directory, next to a
+//! `run.json` with the run's record from the history. The logs and metadata
+//! keep their place (`<id>/<id>.stdout`, `<id>/<id>.stderr`, the timeline and
+//! `<id>/<id>.meta`), so `cat`, `tail` and `grep` read such a directory like the
+//! root directory. Logs that are symbolic links, e.g. onto another disk, are
+//! copied so the link stays.
+//!
+//! Runs are numbered from the newest: 0 is the current or last run, whose logs
+//! are in the root directory, 1 the one before, and so on. `runs.keep` limits how
+//! many past runs are kept per daemon; at 0 the logs of the last run are
+//! truncated by the next one instead.
+
+use crate::{
+    config::Config,
+    events::parse_time,
+    exit::{self, Exit},
+    format_bytes, format_duration, format_timestamp,
+    history::{self, RunRecord},
+    layout,
+    output::{self, Format},
+    pidfile::{DaemonMeta, PidFile, build_file_path},
+    timeline,
+};
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Directory of the past runs in the root directory
+const DIR: &str = "runs";
+
+/// The record of a run in its directory
+const RECORD: &str = "run.json";
+
+/// Files of a run, by extension
+const EXTENSIONS: [&str; 3] = ["stdout", "stderr", timeline::EXTENSION];
+
+/// Highest number taken as the index of a run rather than a Unix timestamp
+const MAX_INDEX: u64 = 1_000_000;
+
+/// Fields of the records `runs` prints
+const FIELDS: [&str; 10] = [
+    "id",
+    "run",
+    "started_at",
+    "ended_at",
+    "pid",
+    "exit_code",
+    "exit_signal",
+    "stdout_bytes",
+    "stderr_bytes",
+    "dir",
+];
+
+/// A run of a daemon and where its logs are
+struct Run {
+    record: RunRecord,
+    /// Directory holding its logs; the root directory for the current run
+    dir: PathBuf,
+}
+
+fn daemon_dir(root_dir: &Path, id: &str) -> PathBuf {
+    root_dir.join(DIR).join(id)
+}
+
+/// Move the logs of the run recorded in `previous` out of the way of a new
+/// run, then drop the oldest runs `runs.keep` no longer keeps
+pub(crate) fn archive(root_dir: &Path, id: &str, previous: &PidFile) -> Result<()> {
+    let keep = Config::load(root_dir)?.integer("runs.keep");
+    if keep == 0 {
+        return Ok(());
+    }
+    let meta = DaemonMeta::read(root_dir, id);
+    let record = history::read(root_dir, id)
+        .unwrap_or_default()
+        .into_iter()
+        .rfind(|record| record.pid == previous.pid && record.started_at == meta.started_at)
+        .unwrap_or_else(|| RunRecord {
+            id: id.to_string(),
+            command: previous.command.clone(),
+            pid: previous.pid,
+            started_at: meta.started_at,
+            ended_at: None,
+            exit_code: meta.exit_code,
+            exit_signal: meta.exit_signal,
+            timed_out: meta.timed_out,
+            peak_rss: None,
+            wall_ms: None,
+            cpu_ms: None,
+        });
+
+    let dir = daemon_dir(root_dir, id).join(format!(
+        "{}.{}",
+        record.started_at.unwrap_or_default(),
+        record.pid
+    ));
+    layout::create_daemon_dir(&dir, id)?;
+    for extension in EXTENSIONS {
+        let source = build_file_path(root_dir, id, extension);
+        let Ok(metadata) = source.symlink_metadata() else {
+            continue;
+        };
+        let dest = build_file_path(&dir, id, extension);
+        match metadata.is_symlink() {
+            true => std::fs::copy(&source, &dest).map(|_| ()),
+            false => std::fs::rename(&source, &dest),
+        }
+        .with_context(|| format!("Cannot keep {}", source.display()))?;
+    }
+    std::fs::write(dir.join(RECORD), serde_json::to_string_pretty(&record)?)?;
+    // Dates the lines in `--output json` like for the current run
+    meta.write(&dir, id)?;
+
+    for run in past_runs(root_dir, id)?.into_iter().skip(keep as usize) {
+        std::fs::remove_dir_all(&run.dir)
+            .with_context(|| format!("Cannot remove {}", run.dir.display()))?;
+    }
+    Ok(())
+}
+
+/// IDs of the daemons with kept past runs, sorted
+pub(crate) fn ids(root_dir: &Path) -> Vec<String> {
+    let Ok(entries) = std::fs::read_dir(root_dir.join(DIR)) else {
+        return Vec::new();
+    };
+    let mut ids: Vec<String> = entries
+        .flatten()
+        .filter(|entry| entry.file_type().is_ok_and(|kind| kind.is_dir()))
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .filter(|id| layout::check_id(id).is_ok())
+        .collect();
+    ids.sort();
+    ids
+}
+
+/// A kept past run, as `demon gc` sees it
+pub(crate) struct Archived {
+    pub(crate) id: String,
+    /// Directory of the run, `runs/<id>/<start>.<pid>/`
+    pub(crate) dir: PathBuf,
+    /// Bytes of its logs, timeline and record
+    pub(crate) bytes: u64,
+    /// Unix time the run ended, or started when the end is not known
+    pub(crate) time: Option<u64>,
+}
+
+/// Every kept past run of every daemon, the oldest of each first
+pub(crate) fn archived(root_dir: &Path) -> Result<Vec<Archived>> {
+    let mut archived = Vec::new();
+    for id in ids(root_dir) {
+        for run in past_runs(root_dir, &id)?.into_iter().rev() {
+            let bytes = EXTENSIONS
+                .iter()
+                .map(|extension| build_file_path(&run.dir, &id, extension))
+                .chain([run.dir.join(RECORD)])
+                .filter_map(|path| file_size(&path))
+                .sum();
+            archived.push(Archived {
+                id: id.clone(),
+                bytes,
+                time: run.record.ended_at.or(run.record.started_at),
+                dir: run.dir,
+            });
+        }
+    }
+    Ok(archived)
+}
+
+/// Remove one past run, and the directory of the daemon's past runs once it
+/// was the last
+pub(crate) fn remove_run(root_dir: &Path, id: &str, dir: &Path) -> Result<()> {
+    std::fs::remove_dir_all(dir).with_context(|| format!("Cannot remove {}", dir.display()))?;
+    let _ = std::fs::remove_dir(daemon_dir(root_dir, id));
+    Ok(())
+}
+
+/// Remove the past runs of a daemon
+pub(crate) fn remove(root_dir: &Path, id: &str) {
+    let _ = std::fs::remove_dir_all(daemon_dir(root_dir, id));
+}
+
+/// The kept past runs of a daemon, newest first
+fn past_runs(root_dir: &Path, id: &str) -> Result<Vec<Run>> {
+    let entries = match std::fs::read_dir(daemon_dir(root_dir, id)) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut runs: Vec<Run> = entries
+        .filter_map(|entry| {
+            let dir = entry.ok()?.path();
+            let record = std::fs::read_to_string(dir.join(RECORD)).ok()?;
+            let record = serde_json::from_str(&record).ok()?;
+            Some(Run { record, dir })
+        })
+        .collect();
+    runs.sort_by_key(|run| std::cmp::Reverse((run.record.started_at, run.record.pid)));
+    Ok(runs)
+}
+
+/// The directories holding the logs of every kept run of a daemon, the
+/// oldest first and the current run last
+pub(crate) fn dirs(root_dir: &Path, id: &str) -> Result<Vec<PathBuf>> {
+    let mut dirs: Vec<PathBuf> = past_runs(root_dir, id)?
+        .into_iter()
+        .rev()
+        .map(|run| run.dir)
+        .collect();
+    dirs.push(root_dir.to_path_buf());
+    Ok(dirs)
+}
+
+/// Every run of a daemon with logs, newest first, the current one included
+fn runs(root_dir: &Path, id: &str) -> Result<Vec<Run>> {
+    let mut runs = Vec::new();
+    if let Ok(data) = PidFile::read(root_dir, id) {
+        let meta = DaemonMeta::read(root_dir, id);
+        runs.push(Run {
+            record: RunRecord {
+                id: id.to_string(),
+                command: data.command,
+                pid: data.pid,
+                started_at: meta.started_at,
+                ended_at: None,
+                exit_code: meta.exit_code,
+                exit_signal: meta.exit_signal,
+                timed_out: meta.timed_out,
+                peak_rss: None,
+                wall_ms: None,
+                cpu_ms: None,
+            },
+            dir: root_dir.to_path_buf(),
+        });
+    }
+    runs.extend(past_runs(root_dir, id)?);
+    Ok(runs)
+}
+
+/// The directory holding the logs of the run `selector` picks: `latest` or
+/// 0 for the current run, `previous` or an index counting back from it, or a
+/// time, given like `--since`, picking the run that was live then
+pub(crate) fn select(root_dir: &Path, id: &str, selector: &str) -> Result<PathBuf> {
+    let runs = runs(root_dir, id)?;
+    let index = match selector.trim() {
+        "latest" | "current" => Some(0),
+        "previous" => Some(1),
+        value => value
+            .parse::<u64>()
+            .ok()
+            .filter(|index| *index < MAX_INDEX)
+            .map(|index| index as usize),
+    };
+    let run = match index {
+        Some(index) => runs.get(index).ok_or_else(|| {
+            let message = format!(
+                "No run {} of '{}' ({} kept, see 'demon runs {}')",
+                index,
+                id,
+                runs.len(),
+                id
+            );
+            Exit::new(exit::NOT_FOUND, message)
+        })?,
+        None => {
+            let time = parse_time("--run", selector)?;
+            runs.iter()
+                .find(|run| run.record.started_at.is_some_and(|start| start <= time))
+                .ok_or_else(|| {
+                    let message = format!(
+                        "No run of '{}' was started by {}",
+                        id,
+                        format_timestamp(time)
+                    );
+                    Exit::new(exit::NOT_FOUND, message)
+                })?
+        }
+    };
+    Ok(run.dir.clone())
+}
+
+fn file_size(path: &Path) -> Option<u64> {
+    std::fs::metadata(path).ok().map(|metadata| metadata.len())
+}
+
+/// `demon runs <id>`: the runs whose logs are kept, newest first
+pub(crate) fn print_runs(root_dir: &Path, id: &str, format: Format) -> Result<()> {
+    let records: Vec<serde_json::Value> = runs(root_dir, id)?
+        .into_iter()
+        .enumerate()
+        .map(|(index, run)| {
+            serde_json::json!({
+                "id": id,
+                "run": index,
+                "started_at": run.record.started_at,
+                "ended_at": run.record.ended_at,
+                "pid": run.record.pid,
+                "exit_code": run.record.exit_code,
+                "exit_signal": run.record.exit_signal,
+                "stdout_bytes": file_size(&build_file_path(&run.dir, id, "stdout")),
+                "stderr_bytes": file_size(&build_file_path(&run.dir, id, "stderr")),
+                "dir": run.dir,
+            })
+        })
+        .collect();
+
+    if !format.is_text() {
+        return output::print_records(format, &FIELDS, &records);
+    }
+    if records.is_empty() {
+        println!("No runs of '{id}'");
+        return Ok(());
+    }
+    println!(
+        "{:<4} {:<20} {:<10} {:<8} {:<10} {:<8} STDERR",
+        "RUN", "STARTED", "DURATION", "PID", "EXIT", "STDOUT"
+    );
+    println!("{}", "-".repeat(74));
+    for record in &records {
+        let started_at = record["started_at"].as_u64();
+        let duration = match (started_at, record["ended_at"].as_u64()) {
+            (Some(start), Some(end)) => {
+                format_duration(Duration::from_secs(end.saturating_sub(start)))
+            }
+            _ => "-".to_string(),
+        };
+        let exit = match (record["exit_code"].as_i64(), record["exit_signal"].as_i64()) {
+            (Some(code), _) => code.to_string(),
+            (None, Some(signal)) => format!("signal {signal}"),
+            (None, None) => "-".to_string(),
+        };
+        let size = |field: &str| record[field].as_u64().map_or("-".to_string(), format_bytes);
+        println!(
+            "{:<4} {:<20} {:<10} {:<8} {:<10} {:<8} {}",
+            record["run"].as_u64().unwrap_or_default(),
+            started_at.map_or("-".to_string(), format_timestamp),
+            duration,
+            record["pid"].as_u64().unwrap_or_default(),
+            exit,
+            size("stdout_bytes"),
+            size("stderr_bytes")
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,381 @@
+//! Sandboxed daemons (`demon run --sandbox --rw-path out --no-network`), for
+//! commands that should not be trusted with the whole machine.
+//!
+//! `--sandbox` confines the daemon with Landlock: it may read and execute the
+//! system directories, its working directory and the `--ro-path`s, and write
+//! only to the `--rw-path`s, so it cannot read keys from the home directory or
+//! change files it was not given. `--no-network` runs it in a network
+//! namespace of its own, which has no interfaces but a down loopback, inside
+//! a user namespace when demon is not root. Both are set up in the forked
+//! child right before it executes the daemon, after everything else, so they
+//! bind the daemon and everything it starts, for good.
+//!
+//! The logs, stdin and sd_notify socket are opened before the sandbox is
+//! entered, so they keep working. Like the limits, the sandbox is part of the
+//! daemon's policy and applied again on restarts.
+
+use anyhow::{Context, Result};
+use clap::Args;
+use serde::{Deserialize, Serialize};
+use std::ffi::CStr;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+
+/// System directories a sandboxed daemon may read and execute from
+const SYSTEM_PATHS: &[&str] = &[
+    "/bin",
+    "/sbin",
+    "/usr",
+    "/lib",
+    "/lib32",
+    "/lib64",
+    "/etc",
+    "/opt",
+    "/nix/store",
+    "/proc",
+    "/sys",
+    "/dev",
+];
+
+/// Devices a sandboxed daemon may also write to
+const DEVICES: &[&str] = &["/dev/null", "/dev/zero", "/dev/full", "/dev/tty"];
+
+/// `landlock_create_ruleset` flag asking for the ABI version
+const LANDLOCK_CREATE_RULESET_VERSION: u32 = 1;
+
+/// `landlock_add_rule` rule type for a file hierarchy
+const LANDLOCK_RULE_PATH_BENEATH: libc::c_int = 1;
+
+/// Landlock filesystem rights, by ABI version
+const ACCESS_FS_EXECUTE: u64 = 1 << 0;
+const ACCESS_FS_WRITE_FILE: u64 = 1 << 1;
+const ACCESS_FS_READ_FILE: u64 = 1 << 2;
+const ACCESS_FS_READ_DIR: u64 = 1 << 3;
+/// Every right of ABI 1, up to making symbolic links
+const ACCESS_FS_V1: u64 = (1 << 13) - 1;
+const ACCESS_FS_REFER: u64 = 1 << 13;
+const ACCESS_FS_TRUNCATE: u64 = 1 << 14;
+const ACCESS_FS_IOCTL_DEV: u64 = 1 << 15;
+
+/// Rights that apply to files rather than directories
+const ACCESS_FS_FILE: u64 = ACCESS_FS_EXECUTE
+    | ACCESS_FS_WRITE_FILE
+    | ACCESS_FS_READ_FILE
+    | ACCESS_FS_TRUNCATE
+    | ACCESS_FS_IOCTL_DEV;
+const ACCESS_FS_READ: u64 = ACCESS_FS_EXECUTE | ACCESS_FS_READ_FILE | ACCESS_FS_READ_DIR;
+
+#[repr(C)]
+struct RulesetAttr {
+    handled_access_fs: u64,
+}
+
+#[repr(C, packed)]
+struct PathBeneathAttr {
+    allowed_access: u64,
+    parent_fd: i32,
+}
+
+/// Sandbox flags of `run`
+#[derive(Args, Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct Sandbox {
+    /// Confine the daemon with Landlock: it may read the system, its working
+    /// directory and --ro-path, and write only to --rw-path
+    #[arg(long)]
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub(crate) sandbox: bool,
+
+    /// Also let the sandboxed daemon read this path (implies --sandbox;
+    /// repeatable)
+    #[arg(long, value_name = "PATH")]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub(crate) ro_path: Vec<PathBuf>,
+
+    /// Let the sandboxed daemon read and write this path (implies --sandbox;
+    /// repeatable)
+    #[arg(long, value_name = "PATH")]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub(crate) rw_path: Vec<PathBuf>,
+
+    /// Run the daemon in a network namespace of its own, without network
+    /// access, not even to localhost
+    #[arg(long)]
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub(crate) no_network: bool,
+}
+
+/// The sandbox as the forked child enters it
+pub(crate) struct Prepared {
+    /// Landlock ruleset with the rules, built before forking
+    ruleset: Option<OwnedFd>,
+    network: Option<Network>,
+}
+
+/// What the child writes to map its IDs into a new user namespace
+struct Network {
+    user_namespace: bool,
+    uid_map: Vec<u8>,
+    gid_map: Vec<u8>,
+}
+
+impl Sandbox {
+    pub(crate) fn is_empty(&self) -> bool {
+        *self == Sandbox::default()
+    }
+
+    /// Whether the filesystem is confined
+    pub(crate) fn confines_files(&self) -> bool {
+        self.sandbox || !self.ro_path.is_empty() || !self.rw_path.is_empty()
+    }
+
+    /// Command line flags that recreate this sandbox
+    pub(crate) fn to_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if self.sandbox {
+            args.push("--sandbox".to_string());
+        }
+        for path in &self.ro_path {
+            args.extend(["--ro-path".to_string(), path.display().to_string()]);
+        }
+        for path in &self.rw_path {
+            args.extend(["--rw-path".to_string(), path.display().to_string()]);
+        }
+        if self.no_network {
+            args.push("--no-network".to_string());
+        }
+        args
+    }
+
+    /// What `demon status` shows, e.g. `files (read-write /srv/out), no
+    /// network`
+    pub(crate) fn summary(&self) -> String {
+        let paths = |paths: &[PathBuf]| -> Vec<String> {
+            paths.iter().map(|p| p.display().to_string()).collect()
+        };
+        let mut parts = Vec::new();
+        if self.confines_files() {
+            let mut granted = Vec::new();
+            if !self.ro_path.is_empty() {
+                granted.push(format!("read-only {}", paths(&self.ro_path).join(" ")));
+            }
+            if !self.rw_path.is_empty() {
+                granted.push(format!("read-write {}", paths(&self.rw_path).join(" ")));
+            }
+            match granted.is_empty() {
+                true => parts.push("files".to_string()),
+                false => parts.push(format!("files ({})", granted.join(", "))),
+            }
+        }
+        if self.no_network {
+            parts.push("no network".to_string());
+        }
+        parts.join(", ")
+    }
+
+    /// Make the paths absolute, so they do not depend on where the daemon is
+    /// restarted from
+    pub(crate) fn resolve(&mut self) -> Result<()> {
+        for path in self.ro_path.iter_mut().chain(&mut self.rw_path) {
+            *path = std::path::absolute(&*path)?;
+        }
+        Ok(())
+    }
+
+    /// Fail early when the kernel cannot sandbox the daemon, rather than
+    /// running it unconfined
+    pub(crate) fn check(&self) -> Result<()> {
+        if self.confines_files() {
+            let abi = landlock_abi();
+            if abi < 1 {
+                return Err(anyhow::anyhow!(
+                    "--sandbox needs Landlock, which this kernel does not provide or has disabled"
+                ));
+            }
+            for path in self.ro_path.iter().chain(&self.rw_path) {
+                if !path.exists() {
+                    return Err(anyhow::anyhow!(
+                        "Sandbox path {} does not exist",
+                        path.display()
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Build the Landlock ruleset and the ID maps before forking, since the
+    /// child must not allocate. `program` is the daemon's executable, which
+    /// it may always run.
+    pub(crate) fn prepare(&self, program: &Path) -> Result<Prepared> {
+        let ruleset = match self.confines_files() {
+            true => Some(self.ruleset(program)?),
+            false => None,
+        };
+        let network = self.no_network.then(|| {
+            // SAFETY: getuid, getgid and geteuid cannot fail
+            let (uid, gid, euid) = unsafe { (libc::getuid(), libc::getgid(), libc::geteuid()) };
+            Network {
+                user_namespace: euid != 0,
+                uid_map: format!("{uid} {uid} 1").into_bytes(),
+                gid_map: format!("{gid} {gid} 1").into_bytes(),
+            }
+        });
+        Ok(Prepared { ruleset, network })
+    }
+
+    fn ruleset(&self, program: &Path) -> Result<OwnedFd> {
+        let abi = landlock_abi();
+        let mut handled = ACCESS_FS_V1;
+        if abi >= 2 {
+            handled |= ACCESS_FS_REFER;
+        }
+        if abi >= 3 {
+            handled |= ACCESS_FS_TRUNCATE;
+        }
+        if abi >= 5 {
+            handled |= ACCESS_FS_IOCTL_DEV;
+        }
+        let attr = RulesetAttr {
+            handled_access_fs: handled,
+        };
+        // SAFETY: the attribute is a valid landlock_ruleset_attr prefix of
+        // the size given
+        let fd = unsafe {
+            libc::syscall(
+                libc::SYS_landlock_create_ruleset,
+                &attr as *const RulesetAttr,
+                std::mem::size_of::<RulesetAttr>(),
+                0,
+            )
+        };
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error())
+                .context("Cannot create a Landlock ruleset");
+        }
+        // SAFETY: the system call returned a new descriptor that we now own
+        let ruleset = unsafe { OwnedFd::from_raw_fd(fd as i32) };
+
+        let cwd = std::env::current_dir()?;
+        let mut rules: Vec<(&Path, u64)> = SYSTEM_PATHS
+            .iter()
+            .map(|path| (Path::new(path), ACCESS_FS_READ))
+            .collect();
+        rules.push((program, ACCESS_FS_READ));
+        rules.push((&cwd, ACCESS_FS_READ));
+        rules.extend(
+            self.ro_path
+                .iter()
+                .map(|path| (path.as_path(), ACCESS_FS_READ)),
+        );
+        rules.extend(DEVICES.iter().map(|path| (Path::new(path), handled)));
+        rules.extend(self.rw_path.iter().map(|path| (path.as_path(), handled)));
+        for (path, access) in rules {
+            add_rule(&ruleset, path, access & handled)?;
+        }
+        Ok(ruleset)
+    }
+}
+
+/// Landlock ABI version of the kernel, 0 without Landlock
+fn landlock_abi() -> i64 {
+    // SAFETY: asking for the version takes no attribute
+    let abi = unsafe {
+        libc::syscall(
+            libc::SYS_landlock_create_ruleset,
+            std::ptr::null::<RulesetAttr>(),
+            0,
+            LANDLOCK_CREATE_RULESET_VERSION,
+        )
+    };
+    abi.max(0)
+}
+
+/// Allow `access` beneath `path`; paths that do not exist are skipped
+fn add_rule(ruleset: &OwnedFd, path: &Path, access: u64) -> Result<()> {
+    let Ok(file) = std::fs::File::options()
+        .read(true)
+        .custom_flags(libc::O_PATH)
+        .open(path)
+    else {
+        return Ok(());
+    };
+    let access = match file.metadata()?.is_dir() {
+        true => access,
+        false => access & ACCESS_FS_FILE,
+    };
+    let attr = PathBeneathAttr {
+        allowed_access: access,
+        parent_fd: file.as_raw_fd(),
+    };
+    // SAFETY: the attribute is a valid landlock_path_beneath_attr and both
+    // descriptors are open
+    let result = unsafe {
+        libc::syscall(
+            libc::SYS_landlock_add_rule,
+            ruleset.as_raw_fd(),
+            LANDLOCK_RULE_PATH_BENEATH,
+            &attr as *const PathBeneathAttr,
+            0,
+        )
+    };
+    if result != 0 {
+        return Err(std::io::Error::last_os_error())
+            .with_context(|| format!("Cannot add {} to the sandbox", path.display()));
+    }
+    Ok(())
+}
+
+/// Write a prepared ID map; called in the forked child
+///
+/// SAFETY: `path` must be a NUL-terminated path
+unsafe fn write_map(path: &CStr, contents: &[u8]) -> std::io::Result<()> {
+    // SAFETY: open, write and close are async-signal-safe and only read what
+    // they are given
+    unsafe {
+        let fd = libc::open(path.as_ptr(), libc::O_WRONLY);
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let written = libc::write(fd, contents.as_ptr().cast(), contents.len());
+        let error = std::io::Error::last_os_error();
+        libc::close(fd);
+        if written < 0 {
+            return Err(error);
+        }
+    }
+    Ok(())
+}
+
+/// Enter the prepared sandbox; called in the forked child, last before
+/// executing the daemon
+pub(crate) fn apply(prepared: &Prepared) -> std::io::Result<()> {
+    // SAFETY: unshare, prctl and the Landlock system calls are
+    // async-signal-safe and only read what they are given
+    unsafe {
+        if let Some(network) = &prepared.network {
+            let flags = match network.user_namespace {
+                true => libc::CLONE_NEWUSER | libc::CLONE_NEWNET,
+                false => libc::CLONE_NEWNET,
+            };
+            if libc::unshare(flags) != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            if network.user_namespace {
+                write_map(c"/proc/self/uid_map", &network.uid_map)?;
+                write_map(c"/proc/self/setgroups", b"deny")?;
+                write_map(c"/proc/self/gid_map", &network.gid_map)?;
+            }
+        }
+        if let Some(ruleset) = &prepared.ruleset {
+            if libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            if libc::syscall(libc::SYS_landlock_restrict_self, ruleset.as_raw_fd(), 0) != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+        }
+    }
+    Ok(())
+}
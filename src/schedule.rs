@@ -0,0 +1,442 @@
+//! State of scheduled jobs (`--every`), kept in `<root>/schedule.json`.
+//!
+//! The agent records for every job when it last started, when it starts next
+//! and how many triggers were missed, because the machine was asleep or no
+//! agent was running. The file outlives the agent, so a restarted agent goes on
+//! where the last one stopped, and `demon schedule list` shows it.
+//!
+//! A job is behind when its next start was due a whole interval ago or more;
+//! every trigger that passed in the meantime counts as missed. Its catch-up
+//! policy (`--catch-up`) decides what happens then: `run-once` (the default)
+//! starts it once for all of them, `skip` waits for the next trigger, and
+//! `run-all` starts it once per missed trigger, each run right after the
+//! previous one has finished.
+//!
+//! Cron jobs (`demon schedule add --cron`) are defined here rather than by a
+//! running daemon: the agent starts them, supervised, at every trigger of
+//! their expression, so each run gets its logs and an entry in the history.
+//! Triggers missed while no agent was running are made up for with one run,
+//! and a trigger that comes while the last run is still going is skipped.
+
+use crate::{
+    agent,
+    cron::Cron,
+    exit::{self, Exit},
+    format_duration, format_timestamp,
+    output::{self, Format},
+    pidfile::{DaemonMeta, PidFile, daemon_ids},
+    requirements::Requirements,
+    status::is_daemon_running,
+    unix_now,
+};
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Fields of a job in the structured formats
+const FIELDS: [&str; 10] = [
+    "id",
+    "every_secs",
+    "cron",
+    "catch_up",
+    "running",
+    "last_run",
+    "next_run",
+    "missed",
+    "owed",
+    "command",
+];
+
+/// Missed cron triggers counted one by one before skipping to the next one
+const MAX_COUNTED: u64 = 10_000;
+
+/// What to do about the triggers a scheduled job missed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum CatchUp {
+    /// Wait for the next trigger
+    Skip,
+    /// Run the job once for all of them
+    #[default]
+    RunOnce,
+    /// Run the job once for every missed trigger
+    RunAll,
+}
+
+impl CatchUp {
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            CatchUp::Skip => "skip",
+            CatchUp::RunOnce => "run-once",
+            CatchUp::RunAll => "run-all",
+        }
+    }
+}
+
+/// What the agent knows about a scheduled job
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct JobState {
+    /// Unix time of the last start of the job
+    pub(crate) last_run: Option<u64>,
+    /// Unix time the job is started next
+    pub(crate) next_run: Option<u64>,
+    /// Triggers missed since the job was first scheduled
+    pub(crate) missed: u64,
+    /// Missed triggers still to be run, with `--catch-up run-all`
+    pub(crate) owed: u64,
+    /// Expression of a cron job added with `demon schedule add`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) cron: Option<String>,
+    /// Command of a cron job
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub(crate) command: Vec<String>,
+    /// Unix time a cron job was added, when it has not run yet
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) added_at: Option<u64>,
+    /// Free memory and disk space a cron job needs to be started
+    #[serde(skip_serializing_if = "Requirements::is_empty")]
+    pub(crate) requirements: Requirements,
+}
+
+fn schedule_path(root_dir: &Path) -> PathBuf {
+    root_dir.join("schedule.json")
+}
+
+/// The state of every job, empty when nothing was ever scheduled
+pub(crate) fn load(root_dir: &Path) -> Result<BTreeMap<String, JobState>> {
+    let path = schedule_path(root_dir);
+    match std::fs::read_to_string(&path) {
+        Ok(contents) => serde_json::from_str(&contents)
+            .with_context(|| format!("Invalid schedule state {}", path.display())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+        Err(e) => Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    }
+}
+
+/// Atomically replace the state file
+fn save(root_dir: &Path, jobs: &BTreeMap<String, JobState>) -> Result<()> {
+    let path = schedule_path(root_dir);
+    let tmp = root_dir.join("schedule.json.tmp");
+    std::fs::write(&tmp, serde_json::to_string_pretty(jobs)?)?;
+    std::fs::rename(&tmp, &path).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// Unix time a job that has finished is to be started again, applying its
+/// catch-up policy to the triggers it missed. Called by the agent on every
+/// scan while the job is not running; the state is only written when it
+/// changes.
+pub(crate) fn next_run(
+    root_dir: &Path,
+    id: &str,
+    started_at: u64,
+    every: u64,
+    catch_up: CatchUp,
+    now: u64,
+) -> Result<u64> {
+    let mut jobs = load(root_dir)?;
+    let state = jobs.entry(id.to_string()).or_default();
+    let before = state.clone();
+
+    if state.last_run != Some(started_at) {
+        // A new run: the next one is due an interval later, or right away
+        // while missed triggers are owed
+        state.last_run = Some(started_at);
+        state.next_run = Some(match state.owed {
+            0 => started_at + every,
+            _ => {
+                state.owed -= 1;
+                started_at
+            }
+        });
+    }
+    let next = state.next_run.unwrap_or(started_at + every);
+    let behind = now.saturating_sub(next) / every;
+    let next = if behind > 0 {
+        let missed = behind + 1;
+        state.missed += missed;
+        tracing::info!(
+            "'{}' missed {} scheduled runs, catching up with {}",
+            id,
+            missed,
+            catch_up.name()
+        );
+        match catch_up {
+            CatchUp::Skip => next + missed * every,
+            CatchUp::RunOnce => now,
+            CatchUp::RunAll => {
+                state.owed += missed - 1;
+                now
+            }
+        }
+    } else {
+        next
+    };
+    state.next_run = Some(next);
+
+    if *state != before {
+        save(root_dir, &jobs)?;
+    }
+    Ok(next)
+}
+
+/// Drop the state of jobs whose daemon is gone; cron jobs stay until removed
+pub(crate) fn retain(root_dir: &Path, keep: impl Fn(&str) -> bool) -> Result<()> {
+    let mut jobs = load(root_dir)?;
+    let count = jobs.len();
+    jobs.retain(|id, state| state.cron.is_some() || keep(id));
+    if jobs.len() != count {
+        save(root_dir, &jobs)?;
+    }
+    Ok(())
+}
+
+/// `demon schedule add`: define a cron job, or change the schedule and
+/// command of an existing one
+pub(crate) fn add(
+    root_dir: &Path,
+    id: &str,
+    expr: &str,
+    command: &[String],
+    mut requirements: Requirements,
+) -> Result<()> {
+    let cron = Cron::parse(expr)?;
+    requirements.resolve()?;
+    let now = unix_now();
+    let next = cron
+        .next_after(now)
+        .ok_or_else(|| anyhow::anyhow!("Cron expression '{expr}' never matches"))?;
+    if let Ok(data) = PidFile::read(root_dir, id)
+        && DaemonMeta::read(root_dir, id).policy.every.is_some()
+    {
+        anyhow::bail!(
+            "'{id}' is already scheduled with --every ({}); stop it first",
+            data.command.join(" ")
+        );
+    }
+
+    let mut jobs = load(root_dir)?;
+    let state = jobs.entry(id.to_string()).or_default();
+    let replaced = state.cron.is_some();
+    state.cron = Some(expr.trim().to_string());
+    state.command = command.to_vec();
+    state.added_at = Some(now);
+    state.next_run = Some(next);
+    state.requirements = requirements;
+    save(root_dir, &jobs)?;
+
+    println!(
+        "{} cron job '{id}' ({}), next run {}",
+        if replaced { "Updated" } else { "Added" },
+        expr.trim(),
+        format_timestamp(next)
+    );
+    if agent::running(root_dir).is_none() {
+        tracing::warn!(
+            "Cron jobs are started by the agent, which is not running; start it with `demon agent --detach`"
+        );
+    }
+    Ok(())
+}
+
+/// `demon schedule remove`: drop a cron job. A run in progress goes on, and
+/// its logs and history stay.
+pub(crate) fn remove(root_dir: &Path, id: &str) -> Result<()> {
+    let mut jobs = load(root_dir)?;
+    if jobs.get(id).is_none_or(|state| state.cron.is_none()) {
+        return Err(Exit::new(exit::NOT_FOUND, format!("No cron job '{id}'")).into());
+    }
+    jobs.remove(id);
+    save(root_dir, &jobs)?;
+    println!("Removed cron job '{id}'");
+    Ok(())
+}
+
+/// The cron jobs the agent is to start now, with their commands and
+/// requirements. Called on every scan; moves each returned job on to its next
+/// trigger.
+pub(crate) fn due_cron_jobs(
+    root_dir: &Path,
+    now: u64,
+) -> Result<Vec<(String, Vec<String>, Requirements)>> {
+    let mut jobs = load(root_dir)?;
+    let mut due = Vec::new();
+    let mut changed = false;
+    for (id, state) in jobs.iter_mut() {
+        let Some(expr) = &state.cron else {
+            continue;
+        };
+        let cron = match Cron::parse(expr) {
+            Ok(cron) => cron,
+            Err(e) => {
+                tracing::warn!("Not starting cron job '{}': {}", id, e);
+                continue;
+            }
+        };
+        let from = state.last_run.or(state.added_at).unwrap_or(now);
+        let Some(next) = state.next_run.or_else(|| cron.next_after(from)) else {
+            continue;
+        };
+        if next > now {
+            continue;
+        }
+        // Every trigger up to now is made up for by this one run
+        let mut triggers = 0;
+        let mut trigger = Some(next);
+        while let Some(at) = trigger.filter(|at| *at <= now) {
+            triggers += 1;
+            // A minutely job left alone for months is not counted one by one
+            trigger = cron.next_after(if triggers < MAX_COUNTED { at } else { now });
+        }
+        state.next_run = trigger;
+        changed = true;
+        let running =
+            PidFile::read(root_dir, id).is_ok_and(|data| is_daemon_running(root_dir, id, data.pid));
+        if running {
+            tracing::info!("'{}' is still running, skipping its cron trigger", id);
+            state.missed += triggers;
+            continue;
+        }
+        if triggers > 1 {
+            tracing::info!(
+                "'{}' missed {} cron triggers, running it once",
+                id,
+                triggers - 1
+            );
+            state.missed += triggers - 1;
+        }
+        state.last_run = Some(now);
+        due.push((
+            id.clone(),
+            state.command.clone(),
+            state.requirements.clone(),
+        ));
+    }
+    if changed {
+        save(root_dir, &jobs)?;
+    }
+    Ok(due)
+}
+
+/// `demon schedule list`: every scheduled job with its last and next run
+pub(crate) fn print_list(root_dir: &Path, format: Format) -> Result<()> {
+    let jobs = load(root_dir)?;
+    let mut records = Vec::new();
+    for id in daemon_ids(root_dir)? {
+        let id = id.as_str();
+        let meta = DaemonMeta::read(root_dir, id);
+        let Some(every) = meta.policy.every else {
+            continue;
+        };
+        let Ok(data) = PidFile::read(root_dir, id) else {
+            continue;
+        };
+        let running = is_daemon_running(root_dir, id, data.pid);
+        let state = jobs.get(id).cloned().unwrap_or_default();
+        let last_run = meta.started_at.or(state.last_run);
+        // Until the agent has seen the last run finish, the next one is due an
+        // interval after its start
+        let next_run = match state.next_run {
+            Some(next) if !running && state.last_run == meta.started_at => Some(next),
+            _ => last_run.map(|last| last + every),
+        };
+        records.push(serde_json::json!({
+            "id": id,
+            "every_secs": every,
+            "cron": null,
+            "catch_up": meta.policy.catch_up.unwrap_or_default(),
+            "running": running,
+            "last_run": last_run,
+            "next_run": next_run,
+            "missed": state.missed,
+            "owed": state.owed,
+            "command": data.command,
+        }));
+    }
+    for (id, state) in &jobs {
+        let Some(cron) = &state.cron else {
+            continue;
+        };
+        let running =
+            PidFile::read(root_dir, id).is_ok_and(|data| is_daemon_running(root_dir, id, data.pid));
+        records.push(serde_json::json!({
+            "id": id,
+            "every_secs": null,
+            "cron": cron,
+            "catch_up": null,
+            "running": running,
+            "last_run": state.last_run,
+            "next_run": state.next_run,
+            "missed": state.missed,
+            "owed": state.owed,
+            "command": state.command,
+        }));
+    }
+    records.sort_by(|a, b| a["id"].as_str().cmp(&b["id"].as_str()));
+
+    if !format.is_text() {
+        return output::print_records(format, &FIELDS, &records);
+    }
+    if records.is_empty() {
+        println!("No scheduled jobs");
+        return Ok(());
+    }
+    println!(
+        "{:<20} {:<16} {:<10} {:<20} {:<24} {:<8} COMMAND",
+        "ID", "SCHEDULE", "CATCH-UP", "LAST RUN", "NEXT RUN", "MISSED"
+    );
+    println!("{}", "-".repeat(118));
+    let now = unix_now();
+    for record in &records {
+        let next_run = match (record["running"].as_bool(), record["next_run"].as_u64()) {
+            (Some(true), _) => "running".to_string(),
+            (_, Some(next)) if next <= now => "due now".to_string(),
+            (_, Some(next)) => format!(
+                "{} (in {})",
+                format_timestamp(next),
+                format_duration(Duration::from_secs(next - now))
+            ),
+            (_, None) => "-".to_string(),
+        };
+        let missed = match record["owed"].as_u64().unwrap_or_default() {
+            0 => record["missed"].to_string(),
+            owed => format!("{} ({owed} owed)", record["missed"]),
+        };
+        let command: Vec<&str> = record["command"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(Value::as_str)
+            .collect();
+        let schedule = match record["cron"].as_str() {
+            Some(cron) => cron.to_string(),
+            None => format!(
+                "every {}",
+                format_duration(Duration::from_secs(
+                    record["every_secs"].as_u64().unwrap_or_default()
+                ))
+            ),
+        };
+        println!(
+            "{:<20} {:<16} {:<10} {:<20} {:<24} {:<8} {}",
+            record["id"].as_str().unwrap_or_default(),
+            schedule,
+            record["catch_up"].as_str().unwrap_or("-"),
+            record["last_run"]
+                .as_u64()
+                .map(format_timestamp)
+                .unwrap_or_else(|| "-".to_string()),
+            next_run,
+            missed,
+            command.join(" ")
+        );
+    }
+    if agent::running(root_dir).is_none() {
+        println!("\nAgent: not running, scheduled jobs are not started");
+    }
+    Ok(())
+}
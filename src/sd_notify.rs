@@ -0,0 +1,178 @@
+//! systemd's readiness protocol (sd_notify) for `demon run --sd-notify`.
+//!
+//! The supervisor binds a datagram socket at `<id>.notify.sock` and passes it
+//! to the daemon in `NOTIFY_SOCKET`, like systemd does for `Type=notify`
+//! services, so daemons that already call `sd_notify(3)` work unchanged. Each
+//! datagram holds newline-separated `KEY=VALUE` assignments:
+//!
+//! - `READY=1` marks the daemon as ready; `demon run` and `demon up` wait for it
+//! - `STATUS=<text>` is shown by `demon status`
+//! - `WATCHDOG=1` resets the watchdog, `WATCHDOG=trigger` fires it right away
+//!
+//! With `--watchdog <interval>` the daemon also gets `WATCHDOG_USEC`; when no
+//! `WATCHDOG=1` arrives within the interval the daemon is considered hung and
+//! the supervisor kills it with SIGABRT, so it crashes and `--restart` applies.
+//! Any process that can reach the socket may send messages, like systemd's
+//! `NotifyAccess=all`.
+
+use crate::{
+    pidfile::{DaemonMeta, PidFile, RootLock, build_file_path},
+    status::is_daemon_running,
+    unix_now,
+};
+use anyhow::Result;
+use std::os::fd::{AsRawFd, RawFd};
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// Extension of the notification socket of a daemon
+pub(crate) const SOCKET_EXTENSION: &str = "notify.sock";
+
+/// How long `demon run` and `demon up` wait for READY=1, like systemd's
+/// default start timeout
+pub(crate) const READY_TIMEOUT: Duration = Duration::from_secs(90);
+
+/// What a batch of notifications changed
+#[derive(Debug, Default)]
+pub(crate) struct Update {
+    /// READY=1 was received for the first time
+    pub(crate) ready: bool,
+    /// New STATUS= text
+    pub(crate) status: Option<String>,
+}
+
+/// The notification socket of a daemon, held by its supervisor
+pub(crate) struct NotifySocket {
+    socket: UnixDatagram,
+    path: PathBuf,
+    ready: bool,
+    watchdog: Option<Duration>,
+    /// When the watchdog fires unless the daemon pings it first
+    deadline: Option<Instant>,
+}
+
+impl NotifySocket {
+    pub(crate) fn bind(root_dir: &Path, id: &str, watchdog: Option<Duration>) -> Result<Self> {
+        let path = std::path::absolute(build_file_path(root_dir, id, SOCKET_EXTENSION))?;
+        let _ = std::fs::remove_file(&path);
+        let socket = UnixDatagram::bind(&path)
+            .map_err(|e| anyhow::anyhow!("Failed to bind {}: {}", path.display(), e))?;
+        socket.set_nonblocking(true)?;
+        Ok(Self {
+            socket,
+            path,
+            ready: false,
+            watchdog,
+            deadline: watchdog.map(|interval| Instant::now() + interval),
+        })
+    }
+
+    /// Environment telling the daemon where to send notifications
+    pub(crate) fn env(&self) -> Vec<(String, String)> {
+        let mut env = vec![(
+            "NOTIFY_SOCKET".to_string(),
+            self.path.to_string_lossy().to_string(),
+        )];
+        if let Some(interval) = self.watchdog {
+            env.push((
+                "WATCHDOG_USEC".to_string(),
+                interval.as_micros().to_string(),
+            ));
+        }
+        env
+    }
+
+    pub(crate) fn as_raw_fd(&self) -> RawFd {
+        self.socket.as_raw_fd()
+    }
+
+    /// Time left before the watchdog fires, if it is armed
+    pub(crate) fn watchdog_timeout(&self) -> Option<Duration> {
+        self.deadline
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()))
+    }
+
+    /// Whether the watchdog fired; it is disarmed until the next run
+    pub(crate) fn watchdog_expired(&mut self) -> bool {
+        let expired = self
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline);
+        if expired {
+            self.deadline = None;
+        }
+        expired
+    }
+
+    /// Read every pending notification
+    pub(crate) fn receive(&mut self) -> Update {
+        let mut update = Update::default();
+        let mut buffer = [0; 4096];
+        while let Ok(read) = self.socket.recv(&mut buffer) {
+            let message = String::from_utf8_lossy(&buffer[..read]);
+            for (key, value) in message.lines().filter_map(|line| line.split_once('=')) {
+                match (key, value) {
+                    ("READY", "1") if !self.ready => {
+                        self.ready = true;
+                        update.ready = true;
+                    }
+                    ("STATUS", text) => update.status = Some(text.to_string()),
+                    ("WATCHDOG", "1") => {
+                        if let (Some(_), Some(interval)) = (self.deadline, self.watchdog) {
+                            self.deadline = Some(Instant::now() + interval);
+                        }
+                    }
+                    ("WATCHDOG", "trigger") if self.deadline.is_some() => {
+                        self.deadline = Some(Instant::now());
+                    }
+                    _ => {}
+                }
+            }
+        }
+        update
+    }
+}
+
+/// Record what the daemon reported in its metadata, unless the run with this
+/// PID was already removed or replaced
+pub(crate) fn record(root_dir: &Path, id: &str, pid: u32, update: &Update) -> Result<()> {
+    let _lock = RootLock::exclusive(root_dir)?;
+    match PidFile::read(root_dir, id) {
+        Ok(data) if data.pid == pid => {}
+        _ => return Ok(()),
+    }
+    let mut meta = DaemonMeta::read(root_dir, id);
+    if update.ready {
+        meta.ready_at = Some(unix_now());
+    }
+    if let Some(status) = &update.status {
+        meta.notify_status = Some(status.clone()).filter(|status| !status.is_empty());
+    }
+    meta.write(root_dir, id)
+}
+
+/// Wait until a daemon started with `--sd-notify` reports READY=1. Fails if
+/// it exits first or is not ready within `timeout`; it is left running then.
+pub(crate) fn wait_ready(root_dir: &Path, id: &str, pid: u32, timeout: Duration) -> Result<()> {
+    let start = Instant::now();
+    loop {
+        if DaemonMeta::read(root_dir, id).ready_at.is_some() {
+            return Ok(());
+        }
+        if !is_daemon_running(root_dir, id, pid) {
+            return Err(anyhow::anyhow!(
+                "Daemon '{}' exited before it was ready; see 'demon cat {}'",
+                id,
+                id
+            ));
+        }
+        if start.elapsed() >= timeout {
+            return Err(anyhow::anyhow!(
+                "Daemon '{}' did not send READY=1 within {}s; it is still running",
+                id,
+                timeout.as_secs()
+            ));
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+}
@@ -0,0 +1,782 @@
+//! `demon serve`: a small HTTP+JSON API so dashboards and other tools can
+//! control daemons remotely.
+//!
+//! | Method and path                  | Action                                         |
+//! |----------------------------------|------------------------------------------------|
+//! | `GET /daemons`                   | list every daemon, like `list` in JSON format  |
+//! | `GET /daemons/<id>`              | status, metadata and the agent's view          |
+//! | `POST /daemons/<id>/run`         | start it; body `{"command": [...], ...}`       |
+//! | `POST /daemons/<id>/stop`        | stop it (`?timeout=<seconds>`)                 |
+//! | `GET /daemons/<id>/logs`         | last lines (`?lines=<n>&stream=stdout|stderr`) |
+//! | `GET /daemons/<id>/tail`         | new lines as server-sent events                |
+//! | `GET /llms.txt`                  | this API and the `demon llm` guide             |
+//! | `GET /tools.json`                | `demon llm --format json` and the endpoints    |
+//!
+//! The server speaks just enough HTTP/1.1 for these endpoints: one request per
+//! connection, each handled on its own thread. With `--token`, every request
+//! but the two guides, which only describe the API, must carry
+//! `Authorization: Bearer <token>`.
+//!
+//! Upgrades: on SIGUSR2 (sent by `demon serve --upgrade`) the server stops
+//! accepting, lets the requests in progress finish and executes the demon
+//! binary now installed in its place, with the same arguments and PID. The
+//! listening socket is left open across the exec and its descriptor passed in
+//! `DEMON_SERVE_FD`, so clients never find the port closed; connections made
+//! meanwhile wait in its backlog. Daemons are not touched: they run detached,
+//! each with its own supervisor. `serve.json` in the root directory records
+//! the server for `--upgrade`.
+
+use crate::follow::Follower;
+use crate::{
+    agent, checks, hooks, layout, llm,
+    logs::read_last_n_lines,
+    pidfile::{DaemonMeta, PidFile, PidFileReadError, build_file_path},
+    runner::{RunOptions, run_daemon},
+    status::{daemons_json, is_daemon_running},
+    stdin::Stdin,
+    stop::stop_daemon,
+};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::os::fd::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+/// Largest request body accepted
+const MAX_BODY: usize = 1024 * 1024;
+
+/// Interval of SSE comments that keep idle streams open and detect closed ones
+const KEEPALIVE: Duration = Duration::from_secs(15);
+
+/// Variable passing the listening socket to the upgraded binary
+const LISTENER_FD_VAR: &str = "DEMON_SERVE_FD";
+
+/// How long an upgrade waits for the requests in progress; streams from
+/// `tail` are cut after it and clients reconnect to the new binary
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long `--upgrade` waits for the new binary to serve
+const UPGRADE_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Set by SIGUSR2
+static UPGRADE: AtomicBool = AtomicBool::new(false);
+
+/// Method, path and description of every endpoint, for the guides
+const ENDPOINTS: [(&str, &str, &str); 8] = [
+    (
+        "GET",
+        "/daemons",
+        "List every daemon, like `demon list --format json`",
+    ),
+    (
+        "GET",
+        "/daemons/<id>",
+        "Status, metadata, policies, check plugin results and the agent's view of a daemon",
+    ),
+    (
+        "POST",
+        "/daemons/<id>/run",
+        "Start a daemon; body `{\"command\": [...], \"supervise\": false}` plus the policies of `demon run` with underscores, e.g. `\"restart\": \"on-failure\"`",
+    ),
+    (
+        "POST",
+        "/daemons/<id>/stop?timeout=<seconds>",
+        "Stop a daemon, with SIGKILL after the timeout",
+    ),
+    (
+        "GET",
+        "/daemons/<id>/logs?lines=<n>&stream=stdout|stderr",
+        "Last lines of the logs, as `{\"stdout\": ..., \"stderr\": ...}`",
+    ),
+    (
+        "GET",
+        "/daemons/<id>/tail?stream=stdout|stderr",
+        "New log lines as server-sent events named `stdout` and `stderr`",
+    ),
+    ("GET", "/llms.txt", "This guide"),
+    (
+        "GET",
+        "/tools.json",
+        "The commands of `demon llm --format json`, with these endpoints in `http_api`",
+    ),
+];
+
+pub(crate) struct ServeOptions {
+    pub(crate) listen: String,
+    pub(crate) token: Option<String>,
+    /// Default `stop` timeout in seconds
+    pub(crate) stop_timeout: u64,
+    /// Default number of lines returned by `logs`
+    pub(crate) lines: usize,
+    /// `--read-only`: refuse `run` and `stop`
+    pub(crate) read_only: bool,
+}
+
+/// `serve.json`: the server of a root directory
+#[derive(Debug, Serialize, Deserialize)]
+struct ServeState {
+    pid: u32,
+    address: String,
+    version: String,
+    /// Binaries that served so far, 1 before the first upgrade
+    generation: u32,
+}
+
+fn state_path(root_dir: &Path) -> PathBuf {
+    root_dir.join("serve.json")
+}
+
+fn read_state(root_dir: &Path) -> Option<ServeState> {
+    let contents = std::fs::read_to_string(state_path(root_dir)).ok()?;
+    serde_json::from_str(&contents).ok()
+}
+
+/// Body of `POST /daemons/<id>/run`
+#[derive(Deserialize)]
+struct RunRequest {
+    command: Vec<String>,
+    #[serde(default)]
+    supervise: bool,
+    #[serde(flatten)]
+    policy: agent::Policy,
+}
+
+struct Request {
+    method: String,
+    path: String,
+    query: Vec<(String, String)>,
+    authorization: Option<String>,
+    body: Vec<u8>,
+}
+
+impl Request {
+    fn read(stream: &TcpStream) -> Result<Self> {
+        let mut reader = BufReader::new(stream);
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        let mut parts = line.split_whitespace();
+        let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+            return Err(anyhow::anyhow!("Malformed request line"));
+        };
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        let query = query
+            .split('&')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| {
+                let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+                (key.to_string(), value.to_string())
+            })
+            .collect();
+
+        let mut content_length = 0;
+        let mut authorization = None;
+        loop {
+            let mut header = String::new();
+            if reader.read_line(&mut header)? == 0 {
+                break;
+            }
+            let header = header.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            let Some((name, value)) = header.split_once(':') else {
+                continue;
+            };
+            let value = value.trim();
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.parse().context("Invalid Content-Length")?;
+            } else if name.eq_ignore_ascii_case("authorization") {
+                authorization = Some(value.to_string());
+            }
+        }
+        if content_length > MAX_BODY {
+            return Err(anyhow::anyhow!("Request body too large"));
+        }
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body)?;
+
+        Ok(Self {
+            method: method.to_string(),
+            path: path.to_string(),
+            query,
+            authorization,
+            body,
+        })
+    }
+
+    fn query(&self, key: &str) -> Option<&str> {
+        self.query
+            .iter()
+            .find(|(name, _)| name == key)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// A JSON response, or markdown for `/llms.txt`
+struct Response {
+    status: u16,
+    body: serde_json::Value,
+    markdown: Option<String>,
+}
+
+impl Response {
+    fn ok(body: serde_json::Value) -> Self {
+        Self::with_status(200, body)
+    }
+
+    fn with_status(status: u16, body: serde_json::Value) -> Self {
+        Self {
+            status,
+            body,
+            markdown: None,
+        }
+    }
+
+    fn markdown(text: String) -> Self {
+        Self {
+            status: 200,
+            body: serde_json::Value::Null,
+            markdown: Some(text),
+        }
+    }
+
+    fn error(status: u16, message: impl std::fmt::Display) -> Self {
+        Self::with_status(status, serde_json::json!({ "error": message.to_string() }))
+    }
+
+    fn write(&self, mut stream: &TcpStream) -> Result<()> {
+        let (content_type, body) = match &self.markdown {
+            Some(text) => ("text/markdown; charset=utf-8", text.clone()),
+            None => (
+                "application/json",
+                serde_json::to_string_pretty(&self.body)?,
+            ),
+        };
+        write!(
+            stream,
+            "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            self.status,
+            reason(self.status),
+            content_type,
+            body.len(),
+            body
+        )?;
+        Ok(())
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        201 => "Created",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        _ => "Internal Server Error",
+    }
+}
+
+/// Serve the API until Ctrl+C
+pub(crate) fn serve(root_dir: &Path, options: ServeOptions) -> Result<()> {
+    let (listener, generation) = match inherited_listener()? {
+        Some(listener) => {
+            let generation = read_state(root_dir)
+                .filter(|state| state.pid == std::process::id())
+                .map_or(1, |state| state.generation + 1);
+            (listener, generation)
+        }
+        None => {
+            let listener = TcpListener::bind(&options.listen)
+                .with_context(|| format!("Failed to listen on {}", options.listen))?;
+            (listener, 1)
+        }
+    };
+    listener.set_nonblocking(true)?;
+    let address = listener.local_addr()?;
+    if options.token.is_none() && !address.ip().is_loopback() {
+        tracing::warn!(
+            "Serving on {} without --token: anyone who can reach it can run commands",
+            address
+        );
+    }
+    if agent::running(root_dir).is_none() {
+        tracing::info!("No agent is running; restart policies given to run are not applied");
+    }
+
+    let running = Arc::new(AtomicBool::new(true));
+    let r = running.clone();
+    ctrlc::set_handler(move || {
+        r.store(false, Ordering::SeqCst);
+    })?;
+    // SAFETY: the handler only stores to an atomic, which is async-signal-safe
+    unsafe {
+        libc::signal(
+            libc::SIGUSR2,
+            on_upgrade_signal as extern "C" fn(libc::c_int) as libc::sighandler_t,
+        )
+    };
+    let state = ServeState {
+        pid: std::process::id(),
+        address: address.to_string(),
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        generation,
+    };
+    std::fs::write(state_path(root_dir), serde_json::to_string(&state)?)
+        .context("Failed to write serve.json")?;
+
+    match generation {
+        1 => println!("Serving the demon API on http://{address}"),
+        _ => println!(
+            "Upgraded to demon {}, serving the demon API on http://{address}",
+            state.version
+        ),
+    }
+    let options = Arc::new(options);
+    let active = Arc::new(AtomicUsize::new(0));
+    while running.load(Ordering::SeqCst) {
+        if UPGRADE.swap(false, Ordering::SeqCst)
+            && let Err(e) = upgrade(&listener, &active)
+        {
+            tracing::error!("Upgrade failed, still serving: {:#}", e);
+        }
+        match listener.accept() {
+            Ok((stream, peer)) => {
+                let root_dir = root_dir.to_path_buf();
+                let options = options.clone();
+                let active = active.clone();
+                active.fetch_add(1, Ordering::SeqCst);
+                std::thread::spawn(move || {
+                    if let Err(e) = handle_connection(stream, &root_dir, &options) {
+                        tracing::warn!("Request from {} failed: {}", peer, e);
+                    }
+                    active.fetch_sub(1, Ordering::SeqCst);
+                });
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                std::thread::sleep(Duration::from_millis(50));
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+    if read_state(root_dir).is_some_and(|state| state.pid == std::process::id()) {
+        let _ = std::fs::remove_file(state_path(root_dir));
+    }
+    Ok(())
+}
+
+extern "C" fn on_upgrade_signal(_: libc::c_int) {
+    UPGRADE.store(true, Ordering::SeqCst);
+}
+
+/// The listening socket left open by the binary this one upgraded
+fn inherited_listener() -> Result<Option<TcpListener>> {
+    let Some(fd) = std::env::var_os(LISTENER_FD_VAR) else {
+        return Ok(None);
+    };
+    // SAFETY: no other thread runs yet; daemons started later must not see it
+    unsafe { std::env::remove_var(LISTENER_FD_VAR) };
+    let fd: RawFd = fd
+        .to_str()
+        .and_then(|fd| fd.parse().ok())
+        .with_context(|| format!("Invalid {LISTENER_FD_VAR}"))?;
+    // SAFETY: the previous binary left its listening socket open under this
+    // number and nothing else owns it
+    let listener = unsafe { TcpListener::from_raw_fd(fd) };
+    listener
+        .local_addr()
+        .with_context(|| format!("{LISTENER_FD_VAR} is not a listening socket"))?;
+    // SAFETY: fcntl on a descriptor we own; daemons must not inherit it
+    unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) };
+    Ok(Some(listener))
+}
+
+/// The demon binary installed now: after an upgrade replaced the file, the
+/// kernel reports the running one as deleted
+fn installed_exe() -> Result<PathBuf> {
+    let exe = std::env::current_exe().context("Cannot locate demon binary")?;
+    let path = exe.to_string_lossy();
+    Ok(PathBuf::from(
+        path.strip_suffix(" (deleted)").unwrap_or(&path),
+    ))
+}
+
+/// Execute the installed binary in place of this one, handing it the
+/// listening socket once the requests in progress are done. Only returns if
+/// the new binary cannot be run, in which case this one keeps serving.
+fn upgrade(listener: &TcpListener, active: &AtomicUsize) -> Result<()> {
+    let exe = installed_exe()?;
+    // A binary that cannot even start must not get the socket
+    let status = Command::new(&exe)
+        .arg("--version")
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .with_context(|| format!("Failed to run {}", exe.display()))?;
+    if !status.success() {
+        return Err(anyhow::anyhow!(
+            "{} --version exited with {}",
+            exe.display(),
+            status
+        ));
+    }
+
+    tracing::info!(
+        "Upgrading to {}, waiting for {} request(s) in progress",
+        exe.display(),
+        active.load(Ordering::SeqCst)
+    );
+    let deadline = Instant::now() + DRAIN_TIMEOUT;
+    while active.load(Ordering::SeqCst) > 0 && Instant::now() < deadline {
+        std::thread::sleep(Duration::from_millis(50));
+    }
+
+    let fd = listener.as_raw_fd();
+    // SAFETY: fcntl on a descriptor we own, so the new binary inherits it
+    unsafe { libc::fcntl(fd, libc::F_SETFD, 0) };
+    let e = Command::new(&exe)
+        .args(std::env::args_os().skip(1))
+        .env(LISTENER_FD_VAR, fd.to_string())
+        .exec();
+    // SAFETY: as above, taking the descriptor back
+    unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) };
+    Err(e).with_context(|| format!("Failed to execute {}", exe.display()))
+}
+
+/// `demon serve --upgrade`: have the server of a root directory replace
+/// itself with the binary installed now, and wait until it serves again
+pub(crate) fn request_upgrade(root_dir: &Path) -> Result<()> {
+    let alive = |pid: u32| {
+        // SAFETY: kill with signal 0 only checks that the process exists
+        unsafe { libc::kill(pid as libc::pid_t, 0) == 0 }
+    };
+    let state = read_state(root_dir)
+        .filter(|state| alive(state.pid))
+        .ok_or_else(|| anyhow::anyhow!("No demon serve is running in {}", root_dir.display()))?;
+    // SAFETY: kill takes no pointers
+    if unsafe { libc::kill(state.pid as libc::pid_t, libc::SIGUSR2) } != 0 {
+        return Err(std::io::Error::last_os_error())
+            .with_context(|| format!("Failed to signal demon serve (PID {})", state.pid));
+    }
+
+    let deadline = Instant::now() + UPGRADE_TIMEOUT;
+    while Instant::now() < deadline {
+        std::thread::sleep(Duration::from_millis(100));
+        if !alive(state.pid) {
+            return Err(anyhow::anyhow!(
+                "demon serve (PID {}) exited during the upgrade",
+                state.pid
+            ));
+        }
+        if let Some(new) = read_state(root_dir).filter(|new| new.generation > state.generation) {
+            println!(
+                "demon serve (PID {}) on http://{} upgraded from {} to {}",
+                new.pid, new.address, state.version, new.version
+            );
+            return Ok(());
+        }
+    }
+    Err(anyhow::anyhow!(
+        "demon serve (PID {}) did not upgrade within {}s; its log says why",
+        state.pid,
+        UPGRADE_TIMEOUT.as_secs()
+    ))
+}
+
+fn handle_connection(stream: TcpStream, root_dir: &Path, options: &ServeOptions) -> Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+    let request = match Request::read(&stream) {
+        Ok(request) => request,
+        Err(e) => return Response::error(400, e).write(&stream),
+    };
+    tracing::info!("{} {}", request.method, request.path);
+
+    // The guides only describe the API, so agents can read them before
+    // they have a token
+    match (request.method.as_str(), request.path.as_str()) {
+        ("GET", "/llms.txt") => return Response::markdown(llms_txt(options)).write(&stream),
+        ("GET", "/tools.json") => return Response::ok(tools_json(options)).write(&stream),
+        _ => {}
+    }
+
+    if let Some(token) = &options.token
+        && request.authorization.as_deref() != Some(&format!("Bearer {token}"))
+    {
+        return Response::error(401, "Missing or invalid bearer token").write(&stream);
+    }
+
+    let segments: Vec<&str> = request
+        .path
+        .trim_matches('/')
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .collect();
+    let response = match (request.method.as_str(), segments.as_slice()) {
+        ("GET", ["daemons"]) => daemons_json(root_dir)
+            .map(|daemons| Response::ok(daemons.into()))
+            .unwrap_or_else(|e| Response::error(500, e)),
+        (_, ["daemons", id, ..]) if !valid_id(id) => {
+            Response::error(400, format!("Invalid daemon ID '{id}'"))
+        }
+        ("GET", ["daemons", id]) => status(root_dir, id),
+        ("POST", ["daemons", _, "run" | "stop"]) if options.read_only => {
+            Response::error(403, "Read-only mode: run and stop are disabled")
+        }
+        ("POST", ["daemons", id, "run"]) => run(root_dir, id, &request.body),
+        ("POST", ["daemons", id, "stop"]) => {
+            match request.query("timeout").map(str::parse::<u64>) {
+                Some(Err(_)) => Response::error(400, "Invalid timeout"),
+                timeout => stop(
+                    root_dir,
+                    id,
+                    timeout.and_then(Result::ok).unwrap_or(options.stop_timeout),
+                ),
+            }
+        }
+        ("GET", ["daemons", id, "logs"]) => match request.query("lines").map(str::parse::<usize>) {
+            Some(Err(_)) => Response::error(400, "Invalid line count"),
+            lines => logs(
+                root_dir,
+                id,
+                request.query("stream"),
+                lines.and_then(Result::ok).unwrap_or(options.lines),
+            ),
+        },
+        ("GET", ["daemons", id, "tail"]) => {
+            return tail(stream, root_dir, id, request.query("stream"));
+        }
+        (_, ["daemons", ..]) => Response::error(405, "Method not allowed"),
+        _ => Response::error(404, "Not found"),
+    };
+    response.write(&stream)
+}
+
+/// How to call the API, for agents without a shell
+fn access_notes(options: &ServeOptions) -> Vec<&'static str> {
+    let mut notes = vec![
+        "Requests and responses are JSON; errors are `{\"error\": \"...\"}` with a matching status code",
+    ];
+    if options.token.is_some() {
+        notes.push("Every request but the guides needs `Authorization: Bearer <token>`");
+    }
+    if options.read_only {
+        notes.push("Read-only mode: `run` and `stop` answer 403");
+    }
+    notes
+}
+
+/// `/llms.txt`: the endpoints, then the `demon llm` guide to the commands
+/// they mirror
+fn llms_txt(options: &ServeOptions) -> String {
+    let mut out = String::from(
+        "# Demon HTTP API\n\n> Control the daemons of one root directory over HTTP: start, stop, inspect and read their logs.\n\n## Endpoints\n",
+    );
+    for (method, path, description) in ENDPOINTS {
+        out.push_str(&format!("- `{method} {path}`: {description}\n"));
+    }
+    out.push('\n');
+    for note in access_notes(options) {
+        out.push_str(&format!("- {note}\n"));
+    }
+    out.push_str("\nThe endpoints do what these commands do:\n\n");
+    out.push_str(&llm::render_guide());
+    out
+}
+
+/// `/tools.json`: `demon llm --format json` with the endpoints in `http_api`
+fn tools_json(options: &ServeOptions) -> serde_json::Value {
+    let mut schema = llm::render_schema();
+    let endpoints: Vec<serde_json::Value> = ENDPOINTS
+        .iter()
+        .map(|(method, path, description)| {
+            serde_json::json!({ "method": method, "path": path, "description": description })
+        })
+        .collect();
+    schema["http_api"] = serde_json::json!({
+        "endpoints": endpoints,
+        "auth": options.token.as_ref().map(|_| "bearer"),
+        "read_only": options.read_only,
+        "notes": access_notes(options),
+    });
+    schema
+}
+
+/// Daemon IDs name directories in the root directory, so keep them to plain
+/// names
+fn valid_id(id: &str) -> bool {
+    layout::check_id(id).is_ok()
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+fn read_pid_file(root_dir: &Path, id: &str) -> Result<PidFile, Response> {
+    match PidFile::read(root_dir, id) {
+        Ok(data) => Ok(data),
+        Err(PidFileReadError::FileNotFound) => {
+            Err(Response::error(404, format!("No daemon '{id}'")))
+        }
+        Err(e) => Err(Response::error(500, e)),
+    }
+}
+
+fn status(root_dir: &Path, id: &str) -> Response {
+    let data = match read_pid_file(root_dir, id) {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+    let running = is_daemon_running(root_dir, id, data.pid);
+    let meta = DaemonMeta::read(root_dir, id);
+    Response::ok(serde_json::json!({
+        "id": id,
+        "pid": data.pid,
+        "status": if running { "RUNNING" } else { "DEAD" },
+        "command": data.command,
+        "started_at": meta.started_at,
+        "supervised": meta.supervised,
+        "exit_code": meta.exit_code,
+        "exit_signal": meta.exit_signal,
+        "exit_reason": meta.timed_out.then_some("TIMEOUT"),
+        "policy": meta.policy,
+        "agent": agent::daemon_state(root_dir, id),
+        "checks": (running && checks::has_checks(root_dir, id)).then(|| {
+            checks::run(root_dir, id, data.pid)
+                .iter()
+                .map(|check| check.to_json())
+                .collect::<Vec<_>>()
+        }),
+    }))
+}
+
+fn run(root_dir: &Path, id: &str, body: &[u8]) -> Response {
+    let request: RunRequest = match serde_json::from_slice(body) {
+        Ok(request) => request,
+        Err(e) => return Response::error(400, format!("Invalid body: {e}")),
+    };
+    if request.command.is_empty() {
+        return Response::error(400, "Command cannot be empty");
+    }
+    if let Ok(data) = PidFile::read(root_dir, id)
+        && is_daemon_running(root_dir, id, data.pid)
+    {
+        return Response::error(409, format!("Process '{id}' is already running"));
+    }
+
+    let options = RunOptions {
+        supervise: request.supervise,
+        pty: false,
+        stdin: Stdin::Null,
+        hooks: hooks::Hooks::default(),
+        policy: request.policy,
+    };
+    match run_daemon(id, &request.command, root_dir, &options) {
+        Ok(pid) => Response::with_status(201, serde_json::json!({ "id": id, "pid": pid })),
+        Err(e) => Response::error(500, e),
+    }
+}
+
+fn stop(root_dir: &Path, id: &str, timeout: u64) -> Response {
+    if let Err(response) = read_pid_file(root_dir, id) {
+        return response;
+    }
+    match stop_daemon(id, timeout, root_dir) {
+        Ok(_) => Response::ok(serde_json::json!({ "id": id, "stopped": true })),
+        Err(e) => Response::error(500, e),
+    }
+}
+
+/// Log streams selected by a `stream` query parameter
+fn streams(stream: Option<&str>) -> Result<&'static [&'static str], Response> {
+    match stream {
+        None | Some("both") => Ok(&["stdout", "stderr"]),
+        Some("stdout") => Ok(&["stdout"]),
+        Some("stderr") => Ok(&["stderr"]),
+        Some(other) => Err(Response::error(400, format!("Invalid stream '{other}'"))),
+    }
+}
+
+fn logs(root_dir: &Path, id: &str, stream: Option<&str>, lines: usize) -> Response {
+    let streams = match streams(stream) {
+        Ok(streams) => streams,
+        Err(response) => return response,
+    };
+    let mut body = serde_json::Map::new();
+    for stream in streams {
+        let path = build_file_path(root_dir, id, stream);
+        let content = path
+            .exists()
+            .then(|| read_last_n_lines(&path, lines))
+            .transpose();
+        match content {
+            Ok(content) => {
+                body.insert(stream.to_string(), content.into());
+            }
+            Err(e) => return Response::error(500, e),
+        }
+    }
+    if body.values().all(serde_json::Value::is_null) {
+        return Response::error(404, format!("No log files found for daemon '{id}'"));
+    }
+    Response::ok(body.into())
+}
+
+/// Stream lines written from now on as server-sent events named after the
+/// log, until the client disconnects
+fn tail(mut stream: TcpStream, root_dir: &Path, id: &str, selected: Option<&str>) -> Result<()> {
+    let names = match streams(selected) {
+        Ok(names) => names,
+        Err(response) => return response.write(&stream),
+    };
+    let mut followers: Vec<(&str, Follower)> = Vec::new();
+    for name in names {
+        let path: PathBuf = build_file_path(root_dir, id, name);
+        let mut follower = Follower::new(path);
+        follower.poll_lines()?;
+        followers.push((name, follower));
+    }
+
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n"
+    )?;
+    stream.flush()?;
+
+    let mut last_write = Instant::now();
+    loop {
+        let mut events = String::new();
+        for (name, follower) in &mut followers {
+            match follower.poll_lines() {
+                Ok((rotated, lines)) => {
+                    if rotated {
+                        events.push_str(&format!("event: rotated\ndata: {name}\n\n"));
+                    }
+                    for line in lines {
+                        events.push_str(&format!("event: {name}\ndata: {line}\n\n"));
+                    }
+                }
+                Err(e) => tracing::warn!("Failed to read {}: {}", follower.path().display(), e),
+            }
+        }
+        // Writing is the only way to notice that the client went away
+        if events.is_empty() && last_write.elapsed() >= KEEPALIVE {
+            events.push_str(": keepalive\n\n");
+        }
+        if !events.is_empty() {
+            if stream.write_all(events.as_bytes()).is_err() {
+                return Ok(());
+            }
+            last_write = Instant::now();
+        }
+        std::thread::sleep(Duration::from_millis(200));
+    }
+}
@@ -0,0 +1,178 @@
+//! `demon shell-hook`: start the daemons of a project when a shell enters it.
+//!
+//! Like direnv, the command prints a script for the shell to `eval` at start
+//! up. Before every prompt after a change of directory, the script runs
+//! `demon shell-hook --state`, which prints the root directory, its manifest
+//! and the daemons of the manifest that are not running, or nothing outside a
+//! project with a `demon.toml`. On entering a project the script exports
+//! `DEMON_SHELL_ROOT` for the prompt, and offers to `demon up` the daemons
+//! that are not running (`--up`).
+//!
+//! Daemons the shell started are session-scoped: with `--stop leave` the
+//! shell stops them with `demon down` when it leaves the project or exits,
+//! with `--stop exit` only when it exits. Daemons that were already running
+//! are never stopped.
+
+use crate::{
+    manifest::{self, Manifest},
+    status::is_process_running,
+};
+use anyhow::Result;
+use clap::ValueEnum;
+use std::path::Path;
+
+/// Shells the hook is written for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub(crate) enum Shell {
+    Bash,
+    Zsh,
+}
+
+/// Whether to start the daemons of a project on entering it
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub(crate) enum Up {
+    /// Ask before starting them
+    #[default]
+    Ask,
+    /// Start them without asking
+    Always,
+    /// Only say which are not running
+    Never,
+}
+
+/// When to stop the daemons the shell started
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub(crate) enum Stop {
+    /// Keep them running
+    #[default]
+    Never,
+    /// On leaving the project, and when the shell exits
+    Leave,
+    /// When the shell exits
+    Exit,
+}
+
+/// Functions shared by both shells. `@UP@`, `@LEAVE@` and `@RECORD@` are
+/// filled in from the options, `@IDS@` with the shell's way of splitting a
+/// list of IDs into words.
+const FUNCTIONS: &str = r#"_demon_hook() {
+  [[ "$PWD" == "${_DEMON_PWD-}" ]] && return
+  _DEMON_PWD="$PWD"
+  local state root manifest ids answer
+  state="$(command demon shell-hook --state 2>/dev/null)"
+  IFS=$'\t' read -r root manifest ids <<< "$state"
+  [[ "$root" == "${DEMON_SHELL_ROOT-}" ]] && return
+@LEAVE@  if [[ -z "$root" ]]; then
+    unset DEMON_SHELL_ROOT
+    return
+  fi
+  export DEMON_SHELL_ROOT="$root"
+  [[ -n "$ids" ]] || return
+@UP@}
+
+_demon_up() {
+  command demon up @IDS@ || return
+@RECORD@}
+
+# Stop the daemons this shell started in the root directory $1, or in every
+# root directory when $1 is empty
+_demon_down() {
+  local rest= root ids
+  while IFS=$'\t' read -r root ids; do
+    [[ -n "$root" ]] || continue
+    if [[ -z "$1" || "$root" == "$1" ]]; then
+      command demon down --root-dir "$root" @IDS@
+    else
+      rest+="$root"$'\t'"$ids"$'\n'
+    fi
+  done <<< "${_DEMON_STARTED-}"
+  _DEMON_STARTED="$rest"
+}
+"#;
+
+/// The script to `eval` in the shell
+pub(crate) fn script(shell: Shell, up: Up, stop: Stop) -> String {
+    let ask = "  printf \"demon: %s not running (%s). Start with 'demon up'? [y/N] \" \"$ids\" \"$manifest\"\n  read -r answer\n  [[ \"$answer\" == [yY]* ]] && _demon_up\n";
+    let up = match up {
+        Up::Ask => ask,
+        Up::Always => "  _demon_up\n",
+        Up::Never => {
+            "  printf \"demon: %s not running (%s), start with 'demon up'\\n\" \"$ids\" \"$manifest\" >&2\n"
+        }
+    };
+    let leave = match stop {
+        Stop::Leave => "  [[ -n \"${DEMON_SHELL_ROOT-}\" ]] && _demon_down \"$DEMON_SHELL_ROOT\"\n",
+        Stop::Never | Stop::Exit => "",
+    };
+    let record = match stop {
+        Stop::Never => "  :\n",
+        Stop::Leave | Stop::Exit => "  _DEMON_STARTED+=\"$DEMON_SHELL_ROOT\"$'\\t'\"$ids\"$'\\n'\n",
+    };
+    let ids = match shell {
+        Shell::Bash => "$ids",
+        Shell::Zsh => "${=ids}",
+    };
+    let functions = FUNCTIONS
+        .replace("@UP@", up)
+        .replace("@LEAVE@", leave)
+        .replace("@RECORD@", record)
+        .replace("@IDS@", ids);
+
+    let mut script = match shell {
+        Shell::Bash => "# demon shell hook for bash: eval \"$(demon shell-hook bash)\"\n",
+        Shell::Zsh => "# demon shell hook for zsh: eval \"$(demon shell-hook zsh)\"\n",
+    }
+    .to_string();
+    script.push_str(&functions);
+    script.push('\n');
+    match shell {
+        Shell::Bash => {
+            // The prompt may show the exit status of the last command
+            script.push_str(
+                "_demon_prompt() {\n  local status=$?\n  _demon_hook\n  return $status\n}\n\
+                 if [[ \";${PROMPT_COMMAND[*]:-};\" != *\";_demon_prompt;\"* ]]; then\n  \
+                 PROMPT_COMMAND=\"_demon_prompt${PROMPT_COMMAND:+;$PROMPT_COMMAND}\"\nfi\n",
+            );
+            if stop != Stop::Never {
+                script.push_str("trap '_demon_down \"\"' EXIT\n");
+            }
+        }
+        Shell::Zsh => {
+            script.push_str("autoload -Uz add-zsh-hook\nadd-zsh-hook precmd _demon_hook\n");
+            if stop != Stop::Never {
+                script.push_str(
+                    "_demon_exit() {\n  _demon_down \"\"\n}\nadd-zsh-hook zshexit _demon_exit\n",
+                );
+            }
+        }
+    }
+    script
+}
+
+/// `demon shell-hook --state`: the root directory, its manifest and the IDs of
+/// the manifest's daemons that are not running, separated by tabs, or nothing
+/// when the root directory has no manifest. `.demon` is not created.
+pub(crate) fn print_state(root_dir: Option<&Path>) -> Result<()> {
+    let Some(root_dir) = root_dir else {
+        return Ok(());
+    };
+    let path = manifest::path(root_dir);
+    if !path.is_file() {
+        return Ok(());
+    }
+    let root_dir = std::path::absolute(root_dir)?;
+    let manifest = Manifest::load(&root_dir)?;
+    let mut stopped = Vec::new();
+    for service in &manifest.services {
+        if !is_process_running(&root_dir, &service.id)? {
+            stopped.push(service.id.as_str());
+        }
+    }
+    println!(
+        "{}\t{}\t{}",
+        root_dir.display(),
+        path.display(),
+        stopped.join(" ")
+    );
+    Ok(())
+}
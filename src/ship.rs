@@ -0,0 +1,177 @@
+//! Log shipping: gzip-compressed logs of finished runs, uploaded so they
+//! outlive an ephemeral machine.
+//!
+//! The destination is `run --ship-logs <url>` for one daemon or `logs.ship_url`
+//! for every daemon of a root, and takes the same directories and URLs as
+//! `cp-logs` (see [`crate::snapshot`]). The logs of a run are shipped once, as
+//! soon as someone sees it finish: the supervisor of a supervised daemon,
+//! `demon stop`, or the agent. At the latest they are shipped by the next
+//! `demon run` of the same ID, before it moves them into `runs/<id>/` (see
+//! [`crate::runs`]), or truncates them when `runs.keep` is 0.
+//!
+//! Segments are named after the start of their run, e.g.
+//! `web-20250601T140322.stdout.gz`, and recorded in the history with their
+//! SHA-256 like any other log copy.
+//!
+//! Compression needs the `log-shipping` feature, which is enabled by default.
+
+use crate::history::{self, CopiedFile, LogCopy};
+use crate::hooks::Hooks;
+use crate::snapshot::{self, file_stamp};
+use crate::{
+    config,
+    pidfile::{DaemonMeta, build_file_path},
+    unix_now,
+};
+use anyhow::Result;
+use std::path::Path;
+
+/// Whether this build can ship logs
+pub(crate) const ENABLED: bool = cfg!(feature = "log-shipping");
+
+/// Ship the logs of a finished unsupervised run; supervised runs are shipped
+/// by their supervisor
+pub(crate) fn ship_finished(root_dir: &Path, id: &str) {
+    let meta = DaemonMeta::read(root_dir, id);
+    if !meta.supervised {
+        ship_logs(root_dir, id, &meta.hooks);
+    }
+}
+
+/// Ship the logs of the last run of a daemon, unless they were already
+/// shipped or no destination is configured. Failures are only logged.
+pub(crate) fn ship_logs(root_dir: &Path, id: &str, hooks: &Hooks) {
+    let config = config::Config::load(root_dir).ok();
+    let url = hooks
+        .ship_logs
+        .clone()
+        .or_else(|| config.as_ref()?.text("logs.ship_url").map(str::to_string));
+    let Some(url) = url else {
+        return;
+    };
+    let meta = DaemonMeta::read(root_dir, id);
+    if meta.logs_shipped {
+        return;
+    }
+    if !ENABLED {
+        tracing::warn!(
+            "Not shipping logs of '{}': demon was built without the log-shipping feature",
+            id
+        );
+        return;
+    }
+
+    let uploader = config
+        .as_ref()
+        .and_then(|config| config.text("logs.uploader"));
+    let shipped = snapshot::uploader_for(&url, uploader)
+        .and_then(|uploader| ship(root_dir, id, meta.started_at, uploader.as_ref()));
+    match shipped {
+        Ok(files) if files.is_empty() => {}
+        Ok(files) => {
+            for file in &files {
+                tracing::info!(
+                    "Shipped {} log of '{}' to {}",
+                    file.stream,
+                    id,
+                    file.location
+                );
+            }
+            let record = LogCopy {
+                id: id.to_string(),
+                copied_at: unix_now(),
+                files,
+            };
+            if let Err(e) = history::append(root_dir, &record) {
+                tracing::warn!("Failed to record shipped logs of '{}': {}", id, e);
+            }
+            // The metadata is gone if the daemon was removed meanwhile
+            if DaemonMeta::exists(root_dir, id) {
+                let mut meta = DaemonMeta::read(root_dir, id);
+                meta.logs_shipped = true;
+                if let Err(e) = meta.write(root_dir, id) {
+                    tracing::warn!("Failed to update metadata of '{}': {}", id, e);
+                }
+            }
+        }
+        Err(e) => tracing::warn!("Failed to ship logs of '{}' to {}: {}", id, url, e),
+    }
+}
+
+fn ship(
+    root_dir: &Path,
+    id: &str,
+    started_at: Option<u64>,
+    uploader: &dyn snapshot::Uploader,
+) -> Result<Vec<CopiedFile>> {
+    let stamp = file_stamp(started_at.unwrap_or_else(unix_now));
+    let mut files = Vec::new();
+    for stream in ["stdout", "stderr"] {
+        let source = build_file_path(root_dir, id, stream);
+        if std::fs::metadata(&source).map_or(true, |metadata| metadata.len() == 0) {
+            continue;
+        }
+        let segment = build_file_path(root_dir, id, &format!("{stream}.gz"));
+        let compressed = compress(&source, &segment);
+        let uploaded = compressed.and_then(|(bytes, sha256)| {
+            let location = uploader.upload(&segment, &format!("{id}-{stamp}.{stream}.gz"))?;
+            Ok(CopiedFile {
+                stream: stream.to_string(),
+                location,
+                bytes,
+                sha256,
+            })
+        });
+        let _ = std::fs::remove_file(&segment);
+        files.push(uploaded?);
+    }
+    Ok(files)
+}
+
+/// Gzip a log into `target`, returning the compressed size and SHA-256
+#[cfg(feature = "log-shipping")]
+fn compress(source: &Path, target: &Path) -> Result<(u64, String)> {
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
+    use sha2::{Digest, Sha256};
+    use std::fs::File;
+    use std::io::Write;
+
+    /// Hashes and counts everything written through it
+    struct Hashing<W> {
+        inner: W,
+        hasher: Sha256,
+        bytes: u64,
+    }
+
+    impl<W: Write> Write for Hashing<W> {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            let written = self.inner.write(buf)?;
+            self.hasher.update(&buf[..written]);
+            self.bytes += written as u64;
+            Ok(written)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            self.inner.flush()
+        }
+    }
+
+    let out = Hashing {
+        inner: File::create(target)?,
+        hasher: Sha256::new(),
+        bytes: 0,
+    };
+    let mut encoder = GzEncoder::new(out, Compression::default());
+    std::io::copy(&mut File::open(source)?, &mut encoder)?;
+    let mut out = encoder.finish()?;
+    out.flush()?;
+    Ok((out.bytes, format!("{:x}", out.hasher.finalize())))
+}
+
+#[cfg(not(feature = "log-shipping"))]
+fn compress(_source: &Path, _target: &Path) -> Result<(u64, String)> {
+    Err(anyhow::anyhow!(
+        "demon was built without the log-shipping feature"
+    ))
+}
@@ -0,0 +1,123 @@
+//! Machine-wide singleton daemons (`demon run --singleton machine`).
+//!
+//! A daemon ID is normally unique within one root directory only, so two
+//! projects can each start their own `db`. A singleton daemon is a shared
+//! service instead: it is recorded as `<id>.owner` in a directory shared by
+//! every root directory of the user (`$XDG_RUNTIME_DIR/demon`, or
+//! `/run/user/<uid>/demon`), and starting it from another root fails while the
+//! owner's run is alive. Owner records of daemons that are gone are ignored,
+//! so nothing has to release them.
+
+use crate::status::is_daemon_running;
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::os::unix::fs::DirBuilderExt;
+use std::path::{Path, PathBuf};
+
+/// Scope in which only one daemon with a given ID may run
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum Singleton {
+    /// Across every root directory of the user on this machine
+    Machine,
+}
+
+/// The root directory running a singleton daemon
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Owner {
+    pub(crate) root_dir: PathBuf,
+    pub(crate) pid: u32,
+}
+
+/// Directory shared by all root directories of the user
+pub(crate) fn shared_dir() -> PathBuf {
+    if let Some(runtime_dir) = std::env::var_os("XDG_RUNTIME_DIR").filter(|dir| !dir.is_empty()) {
+        return PathBuf::from(runtime_dir).join("demon");
+    }
+    // SAFETY: getuid cannot fail
+    let uid = unsafe { libc::getuid() };
+    let run_user = PathBuf::from(format!("/run/user/{uid}"));
+    if run_user.is_dir() {
+        run_user.join("demon")
+    } else {
+        std::env::temp_dir().join(format!("demon-{uid}"))
+    }
+}
+
+/// Create the shared directory if needed and lock it, until the returned
+/// file is dropped
+pub(crate) fn lock_shared_dir() -> Result<File> {
+    let dir = shared_dir();
+    std::fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(&dir)
+        .with_context(|| format!("Failed to create {}", dir.display()))?;
+    let lock_path = dir.join(".lock");
+    let lock = std::fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&lock_path)
+        .with_context(|| format!("Failed to open lock file {}", lock_path.display()))?;
+    lock.lock().context("Failed to lock the shared directory")?;
+    Ok(lock)
+}
+
+/// File recording the owner of a singleton daemon
+pub(crate) fn owner_path(id: &str) -> PathBuf {
+    shared_dir().join(format!("{id}.owner"))
+}
+
+/// The owner of a singleton daemon, if its run is still alive
+pub(crate) fn owner(id: &str) -> Option<Owner> {
+    let contents = std::fs::read_to_string(owner_path(id)).ok()?;
+    let owner: Owner = serde_json::from_str(&contents).ok()?;
+    is_daemon_running(&owner.root_dir, id, owner.pid).then_some(owner)
+}
+
+/// The right to start a singleton daemon from one root directory, held
+/// (with the machine-wide lock) until the new run is recorded
+pub(crate) struct Claim {
+    _lock: File,
+    id: String,
+    root_dir: PathBuf,
+}
+
+impl Claim {
+    /// Claim a singleton daemon for `root_dir`, failing if another root
+    /// directory runs it
+    pub(crate) fn acquire(root_dir: &Path, id: &str) -> Result<Self> {
+        let lock = lock_shared_dir()?;
+        let root_dir = std::fs::canonicalize(root_dir)
+            .with_context(|| format!("Failed to resolve {}", root_dir.display()))?;
+        if let Some(owner) = owner(id)
+            && owner.root_dir != root_dir
+        {
+            return Err(anyhow::anyhow!(
+                "Singleton daemon '{}' is already running from {} (PID {})",
+                id,
+                owner.root_dir.display(),
+                owner.pid
+            ));
+        }
+        Ok(Self {
+            _lock: lock,
+            id: id.to_string(),
+            root_dir,
+        })
+    }
+
+    /// Record the started run as the owner
+    pub(crate) fn record(self, pid: u32) -> Result<()> {
+        let path = owner_path(&self.id);
+        let owner = Owner {
+            root_dir: self.root_dir,
+            pid,
+        };
+        std::fs::write(&path, serde_json::to_string(&owner)?)
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+}
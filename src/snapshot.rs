@@ -0,0 +1,274 @@
+//! `demon cp-logs`: copies of a daemon's logs that are safe to take while it
+//! keeps writing.
+//!
+//! Both logs are measured at the same moment and exactly that many bytes are
+//! copied, so a copy is the log as it was when `cp-logs` started even if the
+//! daemon writes more meanwhile. The copy reads from the file it opened, so a
+//! log replaced during the copy does not matter; a log truncated during the
+//! copy (the daemon was restarted) is detected and copied again.
+//!
+//! The snapshot is first written next to the logs, then handed to an
+//! [`Uploader`] picked from the destination: a local directory, or a URL
+//! uploaded by a shell command (`aws s3 cp` for `s3://`, or `logs.uploader`).
+//! Every copy is recorded in the history with its SHA-256.
+
+use crate::history::{self, CopiedFile, LogCopy};
+use crate::{format_bytes, format_timestamp, pidfile::build_file_path, unix_now};
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::Write;
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// Leading bytes compared to detect a log truncated and rewritten during a copy
+const HEAD_LEN: usize = 64;
+
+/// Attempts at copying a log that keeps being truncated
+const ATTEMPTS: usize = 3;
+
+/// Built-in upload commands by URL scheme
+const BUILTIN_UPLOADERS: &[(&str, &str)] = &[(
+    "s3://",
+    "aws s3 cp --only-show-errors \"$DEMON_FILE\" \"$DEMON_URL\"",
+)];
+
+/// Stores a finished snapshot at its destination
+pub(crate) trait Uploader {
+    /// Store `file` under `name` and return where it ended up
+    fn upload(&self, file: &Path, name: &str) -> Result<String>;
+}
+
+/// Copies into a local directory, created if needed
+struct Directory {
+    dir: PathBuf,
+}
+
+impl Uploader for Directory {
+    fn upload(&self, file: &Path, name: &str) -> Result<String> {
+        std::fs::create_dir_all(&self.dir)
+            .with_context(|| format!("Failed to create {}", self.dir.display()))?;
+        let target = self.dir.join(name);
+        let tmp = self.dir.join(format!(".{name}.tmp"));
+        std::fs::copy(file, &tmp).with_context(|| format!("Failed to write {}", tmp.display()))?;
+        std::fs::rename(&tmp, &target)
+            .with_context(|| format!("Failed to write {}", target.display()))?;
+        Ok(target.display().to_string())
+    }
+}
+
+/// Uploads by running a shell command with `$DEMON_FILE` and `$DEMON_URL` set
+struct ShellCommand {
+    command: String,
+    url: String,
+}
+
+impl Uploader for ShellCommand {
+    fn upload(&self, file: &Path, name: &str) -> Result<String> {
+        let url = format!("{}/{}", self.url.trim_end_matches('/'), name);
+        let output = Command::new("sh")
+            .arg("-c")
+            .arg(&self.command)
+            .env("DEMON_FILE", file)
+            .env("DEMON_URL", &url)
+            .stdin(Stdio::null())
+            .output()
+            .with_context(|| format!("Failed to run uploader '{}'", self.command))?;
+        if !output.status.success() {
+            return Err(anyhow::anyhow!(
+                "Uploader '{}' failed with {}: {}",
+                self.command,
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        Ok(url)
+    }
+}
+
+/// Pick the uploader for a destination: a URL (`scheme://...`) is uploaded by
+/// the configured command or the built-in one for its scheme, anything else is
+/// a local directory
+pub(crate) fn uploader_for(destination: &str, command: Option<&str>) -> Result<Box<dyn Uploader>> {
+    let Some((scheme, _)) = destination.split_once("://") else {
+        return Ok(Box::new(Directory {
+            dir: PathBuf::from(destination),
+        }));
+    };
+    let command = command
+        .map(str::to_string)
+        .or_else(|| {
+            BUILTIN_UPLOADERS
+                .iter()
+                .find(|(prefix, _)| destination.starts_with(prefix))
+                .map(|(_, command)| command.to_string())
+        })
+        .ok_or_else(|| {
+            anyhow::anyhow!(
+                "No uploader for {}:// URLs, set one with 'demon config set logs.uploader <command>'",
+                scheme
+            )
+        })?;
+    Ok(Box::new(ShellCommand {
+        command,
+        url: destination.to_string(),
+    }))
+}
+
+/// A log file opened and measured at snapshot time
+struct Source {
+    stream: &'static str,
+    file: File,
+    len: u64,
+    head: Vec<u8>,
+}
+
+impl Source {
+    fn open(path: &Path, stream: &'static str) -> Result<Option<Self>> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("Failed to open {}", path.display())),
+        };
+        let len = file.metadata()?.len();
+        let head = read_head(&file, len)?;
+        Ok(Some(Self {
+            stream,
+            file,
+            len,
+            head,
+        }))
+    }
+
+    /// Copy the measured bytes into `target`, returning their SHA-256, or None
+    /// if the log was truncated while copying
+    fn copy_to(&self, target: &Path) -> Result<Option<String>> {
+        let mut out = File::create(target)
+            .with_context(|| format!("Failed to create {}", target.display()))?;
+        let mut hasher = Sha256::new();
+        let mut buffer = vec![0; 64 * 1024];
+        let mut offset = 0;
+        while offset < self.len {
+            let wanted = buffer.len().min((self.len - offset) as usize);
+            let read = self.file.read_at(&mut buffer[..wanted], offset)?;
+            if read == 0 {
+                return Ok(None);
+            }
+            hasher.update(&buffer[..read]);
+            out.write_all(&buffer[..read])?;
+            offset += read as u64;
+        }
+        out.sync_all()?;
+
+        // Still the same log: not shrunk, and not truncated and rewritten
+        let len = self.file.metadata()?.len();
+        if len < self.len || read_head(&self.file, self.len)? != self.head {
+            return Ok(None);
+        }
+        Ok(Some(format!("{:x}", hasher.finalize())))
+    }
+}
+
+fn read_head(file: &File, len: u64) -> Result<Vec<u8>> {
+    let mut head = vec![0; HEAD_LEN.min(len as usize)];
+    let mut filled = 0;
+    while filled < head.len() {
+        match file.read_at(&mut head[filled..], filled as u64)? {
+            0 => break,
+            n => filled += n,
+        }
+    }
+    head.truncate(filled);
+    Ok(head)
+}
+
+/// Local time for file names, e.g. `20250601T140322` in `web-20250601T140322.stdout`
+pub(crate) fn file_stamp(secs: u64) -> String {
+    format_timestamp(secs)
+        .chars()
+        .filter_map(|c| match c {
+            ' ' => Some('T'),
+            '-' | ':' => None,
+            c => Some(c),
+        })
+        .collect()
+}
+
+/// Copy the logs of a daemon to a directory or URL and record the copy
+pub(crate) fn copy_logs(
+    id: &str,
+    streams: &[&'static str],
+    destination: &str,
+    uploader: &dyn Uploader,
+    root_dir: &Path,
+) -> Result<()> {
+    let copied_at = unix_now();
+    // Measure every log before copying any, so they match each other
+    let mut sources = Vec::new();
+    for stream in streams {
+        if let Some(source) = Source::open(&build_file_path(root_dir, id, stream), stream)? {
+            sources.push(source);
+        }
+    }
+    if sources.is_empty() {
+        return Err(anyhow::anyhow!("No log files found for daemon '{}'", id));
+    }
+
+    let stamp = file_stamp(copied_at);
+
+    let mut files = Vec::new();
+    for mut source in sources {
+        let path = build_file_path(root_dir, id, source.stream);
+        let snapshot = build_file_path(root_dir, id, &format!("{}.snapshot", source.stream));
+        let mut sha256 = None;
+        for _ in 0..ATTEMPTS {
+            sha256 = source.copy_to(&snapshot)?;
+            if sha256.is_some() {
+                break;
+            }
+            tracing::info!(
+                "{} was truncated while copying, copying again",
+                path.display()
+            );
+            source = Source::open(&path, source.stream)?
+                .ok_or_else(|| anyhow::anyhow!("{} was removed while copying", path.display()))?;
+        }
+        let Some(sha256) = sha256 else {
+            let _ = std::fs::remove_file(&snapshot);
+            return Err(anyhow::anyhow!(
+                "{} kept being truncated while copying",
+                path.display()
+            ));
+        };
+
+        let name = format!("{id}-{stamp}.{}", source.stream);
+        let location = uploader.upload(&snapshot, &name);
+        let _ = std::fs::remove_file(&snapshot);
+        let location = location
+            .map_err(|e| anyhow::anyhow!("Failed to copy {} to {}: {}", name, destination, e))?;
+
+        println!(
+            "Copied {} ({}, sha256 {}) to {}",
+            path.display(),
+            format_bytes(source.len),
+            sha256,
+            location
+        );
+        files.push(CopiedFile {
+            stream: source.stream.to_string(),
+            location,
+            bytes: source.len,
+            sha256,
+        });
+    }
+
+    history::append(
+        root_dir,
+        &LogCopy {
+            id: id.to_string(),
+            copied_at,
+            files,
+        },
+    )
+}
@@ -0,0 +1,1278 @@
+//! Liveness checks and the `list`, `status`, `stats` and `wait` commands.
+
+use crate::{
+    agent,
+    cgroup::Cgroup,
+    checks, color, coredump,
+    exit::{self, Exit},
+    format_bytes, format_duration, format_timestamp, growth,
+    labels::{Labels, Selector},
+    logs,
+    output::{self, Format},
+    pidfile::{DaemonMeta, PidFile, PidFileReadError, RootLock, build_file_path, daemon_ids},
+    proc::{
+        ProcStat, ProcStatus, clock_ticks_per_second, is_process_running_by_pid, proc_cmdline,
+        process_descendants,
+    },
+    progress::Progress,
+    singleton,
+    stdin::{self, Stdin},
+    supervisor, systemd,
+    table::{MaxWidths, Table},
+    unix_now,
+};
+use anyhow::Result;
+use clap::ValueEnum;
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub(crate) fn is_process_running(root_dir: &Path, id: &str) -> Result<bool> {
+    let pid_file_data = match PidFile::read(root_dir, id) {
+        Ok(data) => data,
+        Err(PidFileReadError::FileNotFound) => return Ok(false), // No PID file means no running process
+        Err(PidFileReadError::FileInvalid(_)) => return Ok(false), // Invalid PID file means no running process
+        Err(PidFileReadError::IoError(err)) => return Err(err.into()), // Propagate IO errors
+    };
+
+    Ok(is_daemon_running(root_dir, id, pid_file_data.pid))
+}
+
+/// Check whether the daemon `id` with the recorded `pid` is still alive.
+///
+/// Supervised daemons are asked through their supervisor, which holds a pidfd
+/// and therefore can never confuse the daemon with an unrelated process that
+/// reused its PID. For unsupervised daemons (or when the supervisor is gone) the
+/// PID is checked directly, comparing the process start time against the one
+/// recorded at spawn to detect reuse.
+pub(crate) fn is_daemon_running(root_dir: &Path, id: &str, pid: u32) -> bool {
+    is_running_with(root_dir, id, pid, &DaemonMeta::read(root_dir, id))
+}
+
+/// [`is_daemon_running`] with the metadata already read
+fn is_running_with(root_dir: &Path, id: &str, pid: u32, meta: &DaemonMeta) -> bool {
+    if meta.supervised
+        && let Ok(reply) = supervisor::request(root_dir, id, "status")
+    {
+        return reply.starts_with("running");
+    }
+
+    is_process_running_by_pid(pid)
+        && meta
+            .start_ticks
+            .is_none_or(|ticks| ProcStat::read(pid).is_some_and(|stat| stat.starttime == ticks))
+}
+
+/// `list --status`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub(crate) enum StatusFilter {
+    Running,
+    /// Not running: failed, completed or dead
+    Dead,
+    #[default]
+    All,
+}
+
+impl StatusFilter {
+    fn matches(self, section: Section) -> bool {
+        match self {
+            StatusFilter::Running => section == Section::Running,
+            StatusFilter::Dead => {
+                matches!(
+                    section,
+                    Section::Failed | Section::Completed | Section::Dead
+                )
+            }
+            StatusFilter::All => true,
+        }
+    }
+}
+
+/// `list --sort`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub(crate) enum SortKey {
+    #[default]
+    Id,
+    Pid,
+    /// Longest-running first
+    Uptime,
+    /// Largest first
+    Rss,
+}
+
+/// A column of `list`, selected with `--columns`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub(crate) enum Column {
+    Id,
+    Pid,
+    Status,
+    Uptime,
+    Cpu,
+    Rss,
+    Threads,
+    Command,
+    Group,
+}
+
+impl Column {
+    const DEFAULT: [Column; 7] = [
+        Column::Id,
+        Column::Pid,
+        Column::Status,
+        Column::Cpu,
+        Column::Rss,
+        Column::Threads,
+        Column::Command,
+    ];
+
+    fn title(self) -> &'static str {
+        match self {
+            Column::Id => "ID",
+            Column::Pid => "PID",
+            Column::Status => "STATUS",
+            Column::Uptime => "UPTIME",
+            Column::Cpu => "CPU%",
+            Column::Rss => "RSS",
+            Column::Threads => "THR",
+            Column::Command => "COMMAND",
+            Column::Group => "GROUP",
+        }
+    }
+
+    /// Width the column has at least, so short lists keep their shape
+    fn min_width(self) -> usize {
+        match self {
+            Column::Id => 20,
+            Column::Pid | Column::Rss => 8,
+            Column::Status | Column::Uptime | Column::Group => 10,
+            Column::Cpu => 6,
+            Column::Threads => 4,
+            Column::Command => 40,
+        }
+    }
+
+    /// Key of the column in the JSON objects
+    fn json_key(self) -> &'static str {
+        match self {
+            Column::Id => "id",
+            Column::Pid => "pid",
+            Column::Status => "status",
+            Column::Uptime => "uptime_secs",
+            Column::Cpu => "cpu_percent",
+            Column::Rss => "rss_bytes",
+            Column::Threads => "threads",
+            Column::Command => "command",
+            Column::Group => "group",
+        }
+    }
+}
+
+/// Which daemons `list` shows, in which order and with which columns
+#[derive(Debug, Clone)]
+pub(crate) struct ListOptions {
+    pub(crate) status: StatusFilter,
+    /// Glob the IDs must match
+    pub(crate) filter: Option<glob::Pattern>,
+    /// Group and labels the daemons must have
+    pub(crate) selector: Selector,
+    pub(crate) sort: SortKey,
+    /// Empty for the default columns
+    pub(crate) columns: Vec<Column>,
+    /// `id:pid:status` lines (or the chosen columns) for scripts
+    pub(crate) quiet: bool,
+    /// One table instead of sections by state
+    pub(crate) flat: bool,
+    /// Color the statuses
+    pub(crate) color: bool,
+    /// How wide the columns of the table may get
+    pub(crate) widths: MaxWidths,
+}
+
+impl ListOptions {
+    fn columns(&self) -> &[Column] {
+        match self.columns.is_empty() {
+            true => &Column::DEFAULT,
+            false => &self.columns,
+        }
+    }
+
+    fn matches(&self, id: &str, labels: &Labels) -> bool {
+        self.filter.as_ref().is_none_or(|filter| filter.matches(id))
+            && self.selector.matches(labels)
+    }
+
+    /// Whether a record of [`daemons_json`] is listed
+    pub(crate) fn matches_record(&self, daemon: &serde_json::Value) -> bool {
+        self.matches(
+            daemon["id"].as_str().unwrap_or_default(),
+            &Labels::from_record(daemon),
+        ) && match self.status {
+            StatusFilter::Running => daemon["status"] == "RUNNING",
+            StatusFilter::Dead => daemon["status"] == "DEAD",
+            StatusFilter::All => true,
+        }
+    }
+}
+
+/// Fields of the records of `list` in the structured formats
+const LIST_FIELDS: [&str; 11] = [
+    "id",
+    "pid",
+    "status",
+    "uptime_secs",
+    "command",
+    "group",
+    "labels",
+    "cpu_percent",
+    "rss_bytes",
+    "threads",
+    "error",
+];
+
+/// List daemons as records in a structured format (`--format json|csv|plain`)
+pub(crate) fn list_daemons_records(
+    options: &ListOptions,
+    format: Format,
+    root_dir: &Path,
+) -> Result<()> {
+    let mut daemons: Vec<serde_json::Value> = daemons_json(root_dir)?
+        .into_iter()
+        .filter(|daemon| options.matches_record(daemon))
+        .collect();
+    match options.sort {
+        SortKey::Id => {}
+        SortKey::Pid => daemons.sort_by_key(|daemon| daemon["pid"].as_u64().unwrap_or(u64::MAX)),
+        SortKey::Uptime => daemons.sort_by_key(|daemon| Reverse(daemon["uptime_secs"].as_u64())),
+        SortKey::Rss => daemons.sort_by_key(|daemon| Reverse(daemon["rss_bytes"].as_u64())),
+    }
+    let mut fields = LIST_FIELDS.to_vec();
+    if !options.columns.is_empty() {
+        fields = options
+            .columns
+            .iter()
+            .map(|column| column.json_key())
+            .collect();
+        fields.push("error");
+        for daemon in &mut daemons {
+            if let Some(object) = daemon.as_object_mut() {
+                object.retain(|key, _| fields.contains(&key.as_str()) || key == "generated_at");
+            }
+        }
+    }
+    output::print_records(format, &fields, &daemons)
+}
+
+/// The PID file and metadata of a daemon, as read by [`snapshot`]
+pub(crate) struct DaemonFiles {
+    pub(crate) id: String,
+    pub(crate) pid_file: Result<PidFile, PidFileReadError>,
+    pub(crate) meta: DaemonMeta,
+}
+
+impl DaemonFiles {
+    /// Read the state files of one daemon under the root lock, like
+    /// [`snapshot`]; None when it has no PID file
+    pub(crate) fn read(root_dir: &Path, id: &str) -> Result<Option<Self>> {
+        let _lock = RootLock::shared(root_dir)?;
+        match PidFile::read(root_dir, id) {
+            Err(PidFileReadError::FileNotFound) => Ok(None),
+            pid_file => Ok(Some(Self {
+                id: id.to_string(),
+                pid_file,
+                meta: DaemonMeta::read(root_dir, id),
+            })),
+        }
+    }
+
+    /// Whether the daemon runs, checked now
+    pub(crate) fn running(&self, root_dir: &Path) -> bool {
+        self.pid_file
+            .as_ref()
+            .is_ok_and(|data| is_running_with(root_dir, &self.id, data.pid, &self.meta))
+    }
+
+    /// State as `list` shows it: RUNNING, FAILED, COMPLETED, DEAD or ERROR
+    pub(crate) fn state(&self, root_dir: &Path) -> &'static str {
+        list_row(root_dir, self).section.title()
+    }
+}
+
+/// The state files of every daemon at one point in time
+pub(crate) struct Snapshot {
+    /// Unix time the files were read
+    pub(crate) taken_at: u64,
+    /// One per daemon, sorted by ID
+    pub(crate) daemons: Vec<DaemonFiles>,
+}
+
+/// Read the state files of every daemon in one pass, under the root lock, so
+/// a daemon starting or stopping meanwhile is either in it whole or not at
+/// all, and never twice. Liveness is checked by the callers afterwards.
+pub(crate) fn snapshot(root_dir: &Path) -> Result<Snapshot> {
+    let _lock = RootLock::shared(root_dir)?;
+    let taken_at = unix_now();
+    let mut daemons = BTreeMap::new();
+    for id in daemon_ids(root_dir)? {
+        let pid_file = match PidFile::read(root_dir, &id) {
+            // Removed since the directory was read, e.g. by a concurrent stop
+            Err(PidFileReadError::FileNotFound) => continue,
+            pid_file => pid_file,
+        };
+        daemons.insert(
+            id.clone(),
+            DaemonFiles {
+                meta: DaemonMeta::read(root_dir, &id),
+                id,
+                pid_file,
+            },
+        );
+    }
+    Ok(Snapshot {
+        taken_at,
+        daemons: daemons.into_values().collect(),
+    })
+}
+
+/// Every daemon as the records printed by `list` in the structured formats,
+/// each with the `generated_at` time of the snapshot they come from
+pub(crate) fn daemons_json(root_dir: &Path) -> Result<Vec<serde_json::Value>> {
+    let snapshot = snapshot(root_dir)?;
+    let mut daemons = Vec::new();
+    for files in &snapshot.daemons {
+        let id = files.id.as_str();
+        let labels = &files.meta.policy.labels;
+        let daemon = match &files.pid_file {
+            Ok(data) => {
+                let running = files.running(root_dir);
+                let stat = running.then(|| ProcStat::read(data.pid)).flatten();
+                let uptime = running
+                    .then(|| files.meta.uptime())
+                    .flatten()
+                    .map(|uptime| uptime.as_secs());
+                serde_json::json!({
+                    "id": id,
+                    "pid": data.pid,
+                    "status": if running { "RUNNING" } else { "DEAD" },
+                    "uptime_secs": uptime,
+                    "command": data.command,
+                    "group": labels.group,
+                    "labels": labels.to_json(),
+                    "cpu_percent": stat.as_ref().and_then(ProcStat::cpu_percent),
+                    "rss_bytes": stat.as_ref().map(ProcStat::rss_bytes),
+                    "threads": stat.as_ref().map(|stat| stat.num_threads),
+                    "error": null,
+                    "generated_at": snapshot.taken_at,
+                })
+            }
+            Err(err) => serde_json::json!({
+                "id": id,
+                "pid": null,
+                "status": "ERROR",
+                "uptime_secs": null,
+                "command": null,
+                "group": labels.group,
+                "labels": labels.to_json(),
+                "cpu_percent": null,
+                "rss_bytes": null,
+                "threads": null,
+                "error": err.to_string(),
+                "generated_at": snapshot.taken_at,
+            }),
+        };
+        daemons.push(daemon);
+    }
+
+    Ok(daemons)
+}
+
+/// Where a daemon is listed by `list`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Section {
+    Running,
+    /// Exited with a non-zero code or killed by a signal
+    Failed,
+    /// Exited with code 0, e.g. a oneshot job
+    Completed,
+    /// Gone without a recorded exit status (not supervised)
+    Dead,
+    /// Unreadable or invalid PID file
+    Error,
+}
+
+impl Section {
+    const ALL: [Section; 5] = [
+        Section::Running,
+        Section::Failed,
+        Section::Completed,
+        Section::Dead,
+        Section::Error,
+    ];
+
+    fn title(self) -> &'static str {
+        match self {
+            Section::Running => "RUNNING",
+            Section::Failed => "FAILED",
+            Section::Completed => "COMPLETED",
+            Section::Dead => "DEAD",
+            Section::Error => "ERROR",
+        }
+    }
+
+    /// Status of the flat and quiet formats, which predate the sections
+    fn flat_status(self) -> &'static str {
+        match self {
+            Section::Running => "RUNNING",
+            Section::Failed | Section::Completed | Section::Dead => "DEAD",
+            Section::Error => "ERROR",
+        }
+    }
+}
+
+/// A line of the `list` table
+struct ListRow {
+    id: String,
+    pid: String,
+    section: Section,
+    /// Status column of the sectioned format, e.g. `exit 1`
+    status: String,
+    /// Seconds since the run started, while running
+    uptime: Option<u64>,
+    cpu: String,
+    rss: String,
+    rss_bytes: Option<u64>,
+    threads: String,
+    /// Command, or what is wrong with the PID file
+    detail: String,
+    group: String,
+}
+
+impl ListRow {
+    /// Text of a column, with `status` as the status column
+    fn cell(&self, column: Column, status: &str) -> String {
+        match column {
+            Column::Id => self.id.clone(),
+            Column::Pid => self.pid.clone(),
+            Column::Status => status.to_string(),
+            Column::Uptime => self
+                .uptime
+                .map(|secs| format_duration(Duration::from_secs(secs)))
+                .unwrap_or_default(),
+            Column::Cpu => self.cpu.clone(),
+            Column::Rss => self.rss.clone(),
+            Column::Threads => self.threads.clone(),
+            Column::Command => self.detail.clone(),
+            Column::Group => self.group.clone(),
+        }
+    }
+}
+
+fn list_row(root_dir: &Path, files: &DaemonFiles) -> ListRow {
+    let id = files.id.as_str();
+    let row = |pid: &str, section: Section, detail: &str| ListRow {
+        id: id.to_string(),
+        pid: pid.to_string(),
+        section,
+        status: section.title().to_string(),
+        uptime: None,
+        cpu: String::new(),
+        rss: String::new(),
+        rss_bytes: None,
+        threads: String::new(),
+        detail: detail.to_string(),
+        group: files.meta.policy.labels.group.clone().unwrap_or_default(),
+    };
+    let pid_file_data = match &files.pid_file {
+        Ok(data) => data,
+        Err(PidFileReadError::FileInvalid(reason)) => {
+            return row("INVALID", Section::Error, reason);
+        }
+        Err(_) => return row("ERROR", Section::Error, "Cannot read PID file"),
+    };
+    let pid = pid_file_data.pid.to_string();
+    let command = pid_file_data.command_string();
+    let meta = &files.meta;
+
+    if !files.running(root_dir) {
+        let mut row = match (meta.exit_code, meta.exit_signal) {
+            (Some(0), _) => row(&pid, Section::Completed, &command),
+            (None, None) => row(&pid, Section::Dead, &command),
+            _ => row(&pid, Section::Failed, &command),
+        };
+        if meta.timed_out {
+            row.status = "TIMEOUT".to_string();
+        } else if let Some(code) = meta.exit_code {
+            row.status = format!("exit {code}");
+        } else if let Some(signal) = meta.exit_signal {
+            row.status = format!("signal {signal}");
+        }
+        return row;
+    }
+
+    let mut row = row(&pid, Section::Running, &command);
+    row.uptime = meta.uptime().map(|uptime| uptime.as_secs());
+    // Only the agent knows whether the health checks pass
+    if meta.policy.health_cmd.is_some()
+        && agent::daemon_state(root_dir, id).is_some_and(|state| state.healthy == Some(false))
+    {
+        row.status = "UNHEALTHY".to_string();
+    }
+    if let Some(stat) = ProcStat::read(pid_file_data.pid) {
+        row.cpu = stat
+            .cpu_percent()
+            .map(|cpu| format!("{cpu:.1}"))
+            .unwrap_or_default();
+        row.rss = format_bytes(stat.rss_bytes());
+        row.rss_bytes = Some(stat.rss_bytes());
+        row.threads = stat.num_threads.to_string();
+    }
+    row
+}
+
+/// The table of the rows of `list`, with the status column as it is shown
+fn list_table(
+    rows: &[ListRow],
+    columns: &[Column],
+    options: &ListOptions,
+) -> (Table, Vec<Vec<String>>) {
+    let cells: Vec<Vec<String>> = rows
+        .iter()
+        .map(|row| {
+            let status = match options.flat {
+                true => row.section.flat_status(),
+                false => &row.status,
+            };
+            columns
+                .iter()
+                .map(|column| row.cell(*column, status))
+                .collect()
+        })
+        .collect();
+    let titles: Vec<(&str, usize)> = columns
+        .iter()
+        .map(|column| (column.title(), column.min_width()))
+        .collect();
+    (Table::new(&titles, &cells, options.widths), cells)
+}
+
+/// List daemons grouped into sections by state, or as one table with `flat`;
+/// `quiet` prints `id:pid:status` lines for scripts
+pub(crate) fn list_daemons(options: &ListOptions, root_dir: &Path) -> Result<()> {
+    let mut rows: Vec<ListRow> = snapshot(root_dir)?
+        .daemons
+        .iter()
+        .filter(|files| options.matches(&files.id, &files.meta.policy.labels))
+        .map(|files| list_row(root_dir, files))
+        .filter(|row| options.status.matches(row.section))
+        .collect();
+    match options.sort {
+        SortKey::Id => {}
+        SortKey::Pid => rows.sort_by_key(|row| row.pid.parse::<u32>().unwrap_or(u32::MAX)),
+        SortKey::Uptime => rows.sort_by_key(|row| Reverse(row.uptime)),
+        SortKey::Rss => rows.sort_by_key(|row| Reverse(row.rss_bytes)),
+    }
+    let columns = options.columns();
+
+    if options.quiet {
+        for row in &rows {
+            let status = row.section.flat_status();
+            let cells: Vec<String> = match options.columns.is_empty() {
+                true => vec![row.id.clone(), row.pid.clone(), status.to_string()],
+                false => columns
+                    .iter()
+                    .map(|column| row.cell(*column, status))
+                    .collect(),
+            };
+            println!("{}", cells.join(":"));
+        }
+        return Ok(());
+    }
+
+    // The status is colored after it is padded, so escape codes do not
+    // upset the widths
+    let (table, cells) = list_table(&rows, columns, options);
+    let style = |index: usize, text: &str| match columns[index] {
+        Column::Status => color::status(options.color, text),
+        _ => text.to_string(),
+    };
+    if options.flat || rows.is_empty() {
+        table.print_header();
+        for row in &cells {
+            table.print_row(row, style);
+        }
+        if rows.is_empty() {
+            println!("No daemon processes found.");
+        }
+        return Ok(());
+    }
+
+    let mut first = true;
+    for section in Section::ALL {
+        let members: Vec<&Vec<String>> = rows
+            .iter()
+            .zip(&cells)
+            .filter(|(row, _)| row.section == section)
+            .map(|(_, cells)| cells)
+            .collect();
+        if members.is_empty() {
+            continue;
+        }
+        if !first {
+            println!();
+        }
+        first = false;
+        println!(
+            "{} ({})",
+            color::status(options.color, section.title()),
+            members.len()
+        );
+        table.print_header();
+        for row in members {
+            table.print_row(row, style);
+        }
+    }
+
+    Ok(())
+}
+
+/// Print the status of a daemon for people, with the status colored when
+/// `colored`
+pub(crate) fn status_daemon(id: &str, root_dir: &Path, colored: bool) -> Result<()> {
+    let pid_file = build_file_path(root_dir, id, "pid");
+    let stdout_file = build_file_path(root_dir, id, "stdout");
+    let stderr_file = build_file_path(root_dir, id, "stderr");
+
+    println!("Daemon: {id}");
+    println!("PID file: {}", pid_file.display());
+
+    // Read PID data from file
+    match PidFile::read(root_dir, id) {
+        Ok(pid_file_data) => {
+            println!("PID: {}", pid_file_data.pid);
+            println!("Command: {}", pid_file_data.command_string());
+            let labels = DaemonMeta::read(root_dir, id).policy.labels;
+            if let Some(group) = &labels.group {
+                println!("Group: {group}");
+            }
+            if !labels.label.is_empty() {
+                println!("Labels: {}", labels.summary());
+            }
+
+            if is_daemon_running(root_dir, id, pid_file_data.pid) {
+                println!("Status: {}", color::status(colored, "RUNNING"));
+                let meta = DaemonMeta::read(root_dir, id);
+                if let Some(supervisor_pid) = meta.supervisor_pid {
+                    println!("Supervisor: PID {supervisor_pid} (pidfd)");
+                }
+                if meta.pty {
+                    println!("Terminal: pseudo-terminal, connect with 'demon attach {id}'");
+                }
+                match &meta.stdin {
+                    Stdin::Null => {}
+                    Stdin::Fifo => println!(
+                        "Stdin: {} (FIFO), write to it with 'demon input {id}'",
+                        stdin::fifo_path(root_dir, id).display()
+                    ),
+                    Stdin::File(file) => println!("Stdin: {} (file)", file.display()),
+                }
+                if let Some(signal) = meta.policy.reload_signal {
+                    println!("Reload signal: {signal}");
+                }
+                if let Some(max_runtime) = meta.policy.max_runtime {
+                    let left = meta
+                        .started_at
+                        .map(|started_at| (started_at + max_runtime).saturating_sub(unix_now()));
+                    println!(
+                        "Max runtime: {}{}",
+                        format_duration(Duration::from_secs(max_runtime)),
+                        left.map_or(String::new(), |left| format!(
+                            " (terminated in {})",
+                            format_duration(Duration::from_secs(left))
+                        ))
+                    );
+                }
+                if !meta.policy.limits.is_empty() {
+                    let limits: Vec<String> = meta
+                        .policy
+                        .limits
+                        .set()
+                        .map(|(name, limit)| format!("{name}={limit}"))
+                        .collect();
+                    println!("Limits: {}", limits.join(", "));
+                }
+                if !meta.policy.requirements.is_empty() {
+                    println!("Requires: {}", meta.policy.requirements.summary());
+                }
+                if !meta.policy.autorestart.is_empty() {
+                    println!("Watch: {}", meta.policy.autorestart.summary());
+                }
+                if !meta.policy.priority.is_empty() {
+                    println!("Priority: {}", meta.policy.priority.summary());
+                }
+                if let Some(umask) = meta.policy.limits.umask {
+                    println!("Umask: {umask}");
+                }
+                if let Some(dir) = &meta.policy.limits.core_dump_dir {
+                    println!("Core dumps: {}", coredump::describe(dir));
+                }
+                let mut resources = Vec::new();
+                if let Some(memory) = meta.policy.resources.memory {
+                    resources.push(format!("memory={}", format_bytes(memory)));
+                }
+                if let Some(cpus) = meta.policy.resources.cpus {
+                    resources.push(format!("cpus={cpus}"));
+                }
+                let resources = match resources.is_empty() {
+                    true => String::new(),
+                    false => format!(" ({})", resources.join(", ")),
+                };
+                if let Some(cgroup) = &meta.cgroup {
+                    println!("Cgroup: {}{resources}", cgroup.display());
+                }
+                if meta.policy.systemd_scope {
+                    let unit = systemd::unit_name(root_dir, id);
+                    println!("Systemd scope: {unit}{resources}");
+                }
+                if !meta.policy.sandbox.is_empty() {
+                    println!("Sandbox: {}", meta.policy.sandbox.summary());
+                }
+                for (name, hook) in [
+                    ("on-start", &meta.hooks.on_start),
+                    ("on-exit", &meta.hooks.on_exit),
+                    ("on-crash", &meta.hooks.on_crash),
+                ] {
+                    if let Some(path) = hook {
+                        println!("Hook {name}: {}", path.display());
+                    }
+                }
+                if meta.policy.uses_sd_notify() {
+                    let ready = match meta.ready_at {
+                        Some(ready_at) => format!("yes, since {}", format_timestamp(ready_at)),
+                        None => "waiting for READY=1".to_string(),
+                    };
+                    println!("Ready: {ready} (sd_notify)");
+                    if let Some(status) = &meta.notify_status {
+                        println!("Notify status: {status}");
+                    }
+                    if let Some(watchdog) = meta.policy.watchdog {
+                        println!(
+                            "Watchdog: {}",
+                            format_duration(Duration::from_secs(watchdog))
+                        );
+                    }
+                }
+                if meta.policy.singleton.is_some() {
+                    println!(
+                        "Singleton: machine-wide, recorded in {}",
+                        singleton::owner_path(id).display()
+                    );
+                }
+                agent::print_status(root_dir, id, &meta.policy, colored);
+                for check in checks::run(root_dir, id, pid_file_data.pid) {
+                    let metrics: Vec<String> = check
+                        .metrics
+                        .iter()
+                        .map(|(name, value)| format!("{name}={value}"))
+                        .collect();
+                    println!(
+                        "Check {}: {}{}{}",
+                        check.name,
+                        color::status(colored, &check.state.to_string()),
+                        match check.message.is_empty() {
+                            true => String::new(),
+                            false => format!(" - {}", check.message),
+                        },
+                        match metrics.is_empty() {
+                            true => String::new(),
+                            false => format!(" ({})", metrics.join(", ")),
+                        }
+                    );
+                }
+
+                if let Some(stat) = ProcStat::read(pid_file_data.pid) {
+                    let leader = if stat.session == pid_file_data.pid {
+                        " (leader)"
+                    } else {
+                        ""
+                    };
+                    println!("Session: {}{}", stat.session, leader);
+                    println!("Process group: {}", stat.pgrp);
+                    println!("Controlling TTY: {}", stat.tty_display());
+                    if let Some(uptime) = stat.uptime() {
+                        println!("Uptime: {}", format_duration(uptime));
+                    }
+                    if let Some(skew) = meta.clock_skew() {
+                        println!(
+                            "Clock: the wall clock moved {} {} since the start (NTP or a manual change); uptime is measured from boot",
+                            if skew < 0 { "back" } else { "forward" },
+                            format_duration(Duration::from_secs(skew.unsigned_abs()))
+                        );
+                    }
+                    println!(
+                        "CPU: {} ({:.2}s total)",
+                        stat.cpu_percent()
+                            .map(|cpu| format!("{cpu:.1}%"))
+                            .unwrap_or_else(|| "-".to_string()),
+                        stat.cpu_time().as_secs_f64()
+                    );
+                    println!("Memory (RSS): {}", format_bytes(stat.rss_bytes()));
+                    println!("Threads: {}", stat.num_threads);
+                }
+
+                // Show file information
+                print_log_file("Stdout", &stdout_file);
+                print_log_file("Stderr", &stderr_file);
+                match growth::rate(root_dir, id, meta.started_at) {
+                    Some(rate) => println!(
+                        "Log growth: stdout {}/min, stderr {}/min (over {})",
+                        format_bytes(rate.stdout.round() as u64),
+                        format_bytes(rate.stderr.round() as u64),
+                        format_duration(rate.over)
+                    ),
+                    None => println!("Log growth: measuring"),
+                }
+            } else {
+                println!(
+                    "Status: {} (process not running)",
+                    color::status(colored, "DEAD")
+                );
+                let meta = DaemonMeta::read(root_dir, id);
+                if let Some(code) = meta.exit_code {
+                    println!("Exit code: {code}");
+                }
+                if let Some(signal) = meta.exit_signal {
+                    println!("Exit signal: {signal}");
+                }
+                if meta.timed_out
+                    && let Some(max_runtime) = meta.policy.max_runtime
+                {
+                    println!(
+                        "Exit reason: TIMEOUT (ran longer than --max-runtime {})",
+                        format_duration(Duration::from_secs(max_runtime))
+                    );
+                }
+                if let Some(core_dump) = &meta.core_dump {
+                    println!("Core dump: {}", core_dump.display());
+                }
+                if let Stdin::File(file) = &meta.stdin {
+                    println!("Stdin: {} (file)", file.display());
+                }
+                agent::print_status(root_dir, id, &meta.policy, colored);
+                print_singleton_owner(id);
+                println!("Note: Use 'demon clean' to remove orphaned files");
+            }
+        }
+        Err(PidFileReadError::FileNotFound) => {
+            println!("Status: NOT FOUND (no PID file)");
+            print_singleton_owner(id);
+        }
+        Err(PidFileReadError::FileInvalid(reason)) => {
+            println!(
+                "Status: {} (invalid PID file: {reason})",
+                color::status(colored, "ERROR")
+            );
+        }
+        Err(PidFileReadError::IoError(err)) => {
+            println!(
+                "Status: {} (cannot read PID file: {err})",
+                color::status(colored, "ERROR")
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// The record `status` prints in the structured formats; every field is
+/// present, null when it does not apply
+pub(crate) fn status_record(id: &str, root_dir: &Path) -> Result<serde_json::Value> {
+    let pid_file = build_file_path(root_dir, id, "pid");
+    let stdout_file = build_file_path(root_dir, id, "stdout");
+    let stderr_file = build_file_path(root_dir, id, "stderr");
+    let file_size = |path: &Path| std::fs::metadata(path).ok().map(|metadata| metadata.len());
+
+    let mut record = serde_json::json!({
+        "id": id,
+        "pid": null,
+        "status": "NOT FOUND",
+        "command": null,
+        "group": null,
+        "labels": null,
+        "pid_file": pid_file,
+        "supervised": null,
+        "supervisor_pid": null,
+        "started_at": null,
+        "uptime_secs": null,
+        "clock_skew_secs": null,
+        "exit_code": null,
+        "exit_signal": null,
+        "exit_reason": null,
+        "core_dump": null,
+        "cpu_percent": null,
+        "rss_bytes": null,
+        "threads": null,
+        "stdout_file": stdout_file,
+        "stdout_target": logs::link_target(&stdout_file),
+        "stdout_bytes": file_size(&stdout_file),
+        "stdout_lines": logs::count_lines(&stdout_file).ok(),
+        "stdout_bytes_per_min": null,
+        "stderr_file": stderr_file,
+        "stderr_target": logs::link_target(&stderr_file),
+        "stderr_bytes": file_size(&stderr_file),
+        "stderr_lines": logs::count_lines(&stderr_file).ok(),
+        "stderr_bytes_per_min": null,
+        "checks": null,
+        "error": null,
+    });
+    let data = match PidFile::read(root_dir, id) {
+        Ok(data) => data,
+        Err(PidFileReadError::FileNotFound) => return Ok(record),
+        Err(err) => {
+            record["status"] = "ERROR".into();
+            record["error"] = err.to_string().into();
+            return Ok(record);
+        }
+    };
+
+    let running = is_daemon_running(root_dir, id, data.pid);
+    let meta = DaemonMeta::read(root_dir, id);
+    record["pid"] = data.pid.into();
+    record["status"] = if running { "RUNNING" } else { "DEAD" }.into();
+    record["command"] = data.command.clone().into();
+    record["group"] = meta.policy.labels.group.clone().into();
+    record["labels"] = meta.policy.labels.to_json();
+    record["supervised"] = meta.supervised.into();
+    record["started_at"] = meta.started_at.into();
+    if running {
+        record["supervisor_pid"] = meta.supervisor_pid.into();
+        record["uptime_secs"] = meta.uptime().map(|uptime| uptime.as_secs()).into();
+        record["clock_skew_secs"] = meta.clock_skew().into();
+        if let Some(rate) = growth::rate(root_dir, id, meta.started_at) {
+            record["stdout_bytes_per_min"] = (rate.stdout.round() as u64).into();
+            record["stderr_bytes_per_min"] = (rate.stderr.round() as u64).into();
+        }
+        if let Some(stat) = ProcStat::read(data.pid) {
+            record["cpu_percent"] = stat.cpu_percent().into();
+            record["rss_bytes"] = stat.rss_bytes().into();
+            record["threads"] = stat.num_threads.into();
+        }
+        if checks::has_checks(root_dir, id) {
+            record["checks"] = checks::run(root_dir, id, data.pid)
+                .iter()
+                .map(|check| check.to_json())
+                .collect();
+        }
+    } else {
+        record["exit_code"] = meta.exit_code.into();
+        record["exit_signal"] = meta.exit_signal.into();
+        record["exit_reason"] = meta.timed_out.then_some("TIMEOUT").into();
+        record["core_dump"] = serde_json::to_value(&meta.core_dump)?;
+    }
+    Ok(record)
+}
+
+/// `status --all`: the status of every daemon, sorted by ID, as blocks of
+/// text or as records
+pub(crate) fn status_all(root_dir: &Path, format: Format, colored: bool) -> Result<()> {
+    let ids: Vec<String> = snapshot(root_dir)?
+        .daemons
+        .into_iter()
+        .map(|files| files.id)
+        .collect();
+    if format.is_text() {
+        if ids.is_empty() {
+            println!("No daemon processes found.");
+        }
+        for (index, id) in ids.iter().enumerate() {
+            if index > 0 {
+                println!();
+            }
+            status_daemon(id, root_dir, colored)?;
+        }
+        return Ok(());
+    }
+    let records = ids
+        .iter()
+        .map(|id| status_record(id, root_dir))
+        .collect::<Result<Vec<_>>>()?;
+    let fields: Vec<&str> = records
+        .first()
+        .and_then(|record| record.as_object())
+        .map(|record| record.keys().map(String::as_str).collect())
+        .unwrap_or_default();
+    output::print_records(format, &fields, &records)
+}
+
+/// Exit code of `status` for the state of a daemon (see [`exit`])
+pub(crate) fn status_exit_code(root_dir: &Path, id: &str) -> i32 {
+    match PidFile::read(root_dir, id) {
+        Ok(data) if is_daemon_running(root_dir, id, data.pid) => 0,
+        Ok(_) => exit::NOT_RUNNING,
+        Err(PidFileReadError::FileNotFound) => exit::NOT_FOUND,
+        Err(_) => exit::FAILURE,
+    }
+}
+
+/// Print where a log is and its size, following a symbolic link to the file
+/// it is written to
+fn print_log_file(name: &str, path: &Path) {
+    let shown = match logs::link_target(path) {
+        Some(target) => format!("{} -> {}", path.display(), target.display()),
+        None => path.display().to_string(),
+    };
+    match (std::fs::metadata(path), logs::count_lines(path)) {
+        (Ok(metadata), Ok(lines)) => {
+            println!(
+                "{name} file: {shown} ({} bytes, {lines} lines)",
+                metadata.len()
+            )
+        }
+        (Ok(metadata), Err(_)) => println!("{name} file: {shown} ({} bytes)", metadata.len()),
+        (Err(_), _) => println!("{name} file: {shown} (not found)"),
+    }
+}
+
+/// Point at the root directory running a singleton daemon that is not
+/// running from here
+fn print_singleton_owner(id: &str) {
+    if let Some(owner) = singleton::owner(id) {
+        println!(
+            "Singleton: running from {} (PID {})",
+            owner.root_dir.display(),
+            owner.pid
+        );
+    }
+}
+
+pub(crate) fn stats_daemon(id: &str, root_dir: &Path) -> Result<()> {
+    let pid = match PidFile::read(root_dir, id) {
+        Ok(data) => data.pid,
+        Err(PidFileReadError::FileNotFound) => {
+            return Err(anyhow::anyhow!("Process '{}' not found (no PID file)", id));
+        }
+        Err(err) => {
+            return Err(anyhow::anyhow!(
+                "Cannot read PID file for '{}': {}",
+                id,
+                err
+            ));
+        }
+    };
+
+    let stat = match ProcStat::read(pid) {
+        Some(stat) if is_daemon_running(root_dir, id, pid) => stat,
+        _ => return Err(anyhow::anyhow!("Process '{}' is not running", id)),
+    };
+    let status = ProcStatus::read(pid).unwrap_or_default();
+    let ticks = clock_ticks_per_second();
+    let optional_bytes = |bytes: Option<u64>| bytes.map(format_bytes).unwrap_or("-".into());
+
+    println!("Daemon: {id} (PID {pid})");
+    if let Some(uptime) = stat.uptime() {
+        println!("Uptime: {}", format_duration(uptime));
+    }
+    println!(
+        "CPU time: {:.2}s user, {:.2}s system ({} average)",
+        stat.utime as f64 / ticks,
+        stat.stime as f64 / ticks,
+        stat.cpu_percent()
+            .map(|cpu| format!("{cpu:.1}%"))
+            .unwrap_or_else(|| "-".to_string())
+    );
+    println!(
+        "Memory: RSS {}, peak RSS {}, peak virtual {}",
+        format_bytes(stat.rss_bytes()),
+        optional_bytes(status.vm_hwm),
+        optional_bytes(status.vm_peak)
+    );
+    println!("Threads: {}", stat.num_threads);
+    if let (Some(voluntary), Some(involuntary)) = (
+        status.voluntary_ctxt_switches,
+        status.nonvoluntary_ctxt_switches,
+    ) {
+        println!("Context switches: {voluntary} voluntary, {involuntary} involuntary");
+    }
+
+    println!();
+    println!("Process tree:");
+    println!(
+        "{:<8} {:<8} {:<4} {:<8} {:<9} COMMAND",
+        "PID", "PPID", "THR", "RSS", "CPU TIME"
+    );
+
+    let mut total_rss = 0;
+    let mut total_cpu = Duration::ZERO;
+    let mut total_threads = 0;
+    let mut count = 0;
+    // A cgroup also holds the children that were reparented away from the daemon
+    let cgroup = Cgroup::of(&DaemonMeta::read(root_dir, id));
+    let members = match &cgroup {
+        Some(cgroup) => cgroup.pids(),
+        None => std::iter::once(pid)
+            .chain(process_descendants(pid))
+            .collect(),
+    };
+    for member in members {
+        let Some(member_stat) = ProcStat::read(member) else {
+            continue;
+        };
+        count += 1;
+        total_rss += member_stat.rss_bytes();
+        total_cpu += member_stat.cpu_time();
+        total_threads += member_stat.num_threads;
+        println!(
+            "{:<8} {:<8} {:<4} {:<8} {:<9} {}",
+            member,
+            member_stat.ppid,
+            member_stat.num_threads,
+            format_bytes(member_stat.rss_bytes()),
+            format!("{:.2}s", member_stat.cpu_time().as_secs_f64()),
+            proc_cmdline(member).unwrap_or_default()
+        );
+    }
+
+    println!();
+    println!(
+        "Total ({count} process{}): RSS {}, CPU time {:.2}s, threads {}",
+        if count == 1 { "" } else { "es" },
+        format_bytes(total_rss),
+        total_cpu.as_secs_f64(),
+        total_threads
+    );
+    if let Some(cgroup) = cgroup {
+        let usage = cgroup.usage();
+        let seconds = |usec: Option<u64>| {
+            usec.map(|usec| format!("{:.2}s", usec as f64 / 1e6))
+                .unwrap_or("-".into())
+        };
+        println!();
+        println!("Cgroup: {}", cgroup.path.display());
+        println!(
+            "Memory: {} of {} (peak {}), OOM kills {}",
+            optional_bytes(usage.memory),
+            usage
+                .memory_max
+                .map(format_bytes)
+                .unwrap_or("unlimited".into()),
+            optional_bytes(usage.memory_peak),
+            usage.oom_kills.unwrap_or(0)
+        );
+        println!(
+            "CPU time: {} (throttled {})",
+            seconds(usage.cpu_usec),
+            seconds(usage.throttled_usec)
+        );
+    }
+
+    Ok(())
+}
+
+/// State of a daemon as recorded in its files
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DaemonStatus {
+    pub id: String,
+    pub pid: u32,
+    pub command: Vec<String>,
+    pub running: bool,
+    /// Unix time the daemon was started, unless it was started by an old version
+    pub started_at: Option<u64>,
+    /// Whether a supervisor process owns the daemon
+    pub supervised: bool,
+    /// Exit code, once a supervised daemon has exited normally
+    pub exit_code: Option<i32>,
+    /// Terminating signal, once a supervised daemon was killed by a signal
+    pub exit_signal: Option<i32>,
+}
+
+/// The status of a daemon, or None if it has no PID file
+pub(crate) fn daemon_status(root_dir: &Path, id: &str) -> Result<Option<DaemonStatus>> {
+    let data = match PidFile::read(root_dir, id) {
+        Ok(data) => data,
+        Err(PidFileReadError::FileNotFound) => return Ok(None),
+        Err(err) => return Err(anyhow::anyhow!("Daemon '{}': {}", id, err)),
+    };
+    let meta = DaemonMeta::read(root_dir, id);
+    Ok(Some(DaemonStatus {
+        id: id.to_string(),
+        running: is_daemon_running(root_dir, id, data.pid),
+        pid: data.pid,
+        command: data.command,
+        started_at: meta.started_at,
+        supervised: meta.supervised,
+        exit_code: meta.exit_code,
+        exit_signal: meta.exit_signal,
+    }))
+}
+
+/// Wait until a running daemon terminates, polling every `interval`, for at
+/// most `timeout` (forever if None), returning its PID
+pub(crate) fn wait_daemon(
+    id: &str,
+    timeout: Option<Duration>,
+    interval: Duration,
+    root_dir: &Path,
+) -> Result<u32> {
+    let mut progress = Progress::start("wait", Some(id), timeout.map(|timeout| timeout.as_secs()));
+
+    // Check if PID file exists and read PID data
+    let pid_file_data = match PidFile::read(root_dir, id) {
+        Ok(data) => data,
+        Err(PidFileReadError::FileNotFound) => {
+            return Err(Exit::new(
+                exit::NOT_FOUND,
+                format!("Process '{id}' not found (no PID file)"),
+            )
+            .into());
+        }
+        Err(PidFileReadError::FileInvalid(reason)) => {
+            return Err(anyhow::anyhow!(
+                "Process '{}' has invalid PID file: {}",
+                id,
+                reason
+            ));
+        }
+        Err(PidFileReadError::IoError(err)) => {
+            return Err(anyhow::anyhow!(
+                "Failed to read PID file for '{}': {}",
+                id,
+                err
+            ));
+        }
+    };
+
+    let pid = pid_file_data.pid;
+
+    // Check if process is currently running
+    if !is_daemon_running(root_dir, id, pid) {
+        return Err(Exit::new(exit::NOT_RUNNING, format!("Process '{id}' is not running")).into());
+    }
+
+    tracing::info!("Waiting for process '{}' (PID: {}) to terminate", id, pid);
+    let mut progress_secs = 0;
+
+    let start = Instant::now();
+    let deadline = timeout.map(|timeout| start + timeout);
+    while deadline.is_none_or(|deadline| Instant::now() < deadline) {
+        if !is_daemon_running(root_dir, id, pid) {
+            tracing::info!("Process '{}' (PID: {}) has terminated", id, pid);
+            progress.finish(Some(id), "exited", true);
+            return Ok(pid);
+        }
+        thread::sleep(interval);
+        // Once a second at most, however short the interval
+        let waited = start.elapsed().as_secs();
+        if waited > progress_secs {
+            progress_secs = waited;
+            progress.update(Some(id), "waiting", waited);
+        }
+    }
+
+    // Timeout reached
+    progress.finish(Some(id), "timeout", false);
+    Err(Exit::new(
+        exit::TIMEOUT,
+        format!("Timeout reached waiting for process '{id}' to terminate"),
+    )
+    .into())
+}
@@ -0,0 +1,171 @@
+//! Standard input of daemons: `/dev/null` by default, a file given with
+//! `--stdin-file`, or with `--stdin fifo` the named pipe `<id>.stdin` that
+//! `demon input` writes to.
+//!
+//! The daemon gets the FIFO opened for reading and writing, so opening it never
+//! blocks and the daemon does not see end-of-file when a writer goes away;
+//! input can be sent any number of times for as long as the daemon runs.
+
+use crate::pidfile::{PidFile, build_file_path};
+use anyhow::Result;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::ffi::CString;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::os::fd::AsRawFd;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{FileTypeExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+
+/// `--stdin` modes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum StdinMode {
+    /// /dev/null
+    #[default]
+    Null,
+    /// The named pipe `<id>.stdin`, written to with `demon input`
+    Fifo,
+}
+
+/// Where the standard input of a daemon comes from
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum Stdin {
+    /// /dev/null
+    #[default]
+    Null,
+    /// The named pipe `<id>.stdin`, written to with `demon input`
+    Fifo,
+    /// A file read from the start, like `command < file`
+    File(PathBuf),
+}
+
+impl Stdin {
+    /// Combine `--stdin` and `--stdin-file`, making the file path absolute
+    pub(crate) fn new(mode: StdinMode, file: Option<&Path>) -> Result<Self> {
+        Ok(match (mode, file) {
+            (_, Some(file)) => Stdin::File(std::path::absolute(file)?),
+            (StdinMode::Null, None) => Stdin::Null,
+            (StdinMode::Fifo, None) => Stdin::Fifo,
+        })
+    }
+
+    pub(crate) fn is_null(&self) -> bool {
+        *self == Stdin::Null
+    }
+
+    /// Flags for `demon supervise` recreating this
+    pub(crate) fn to_args(&self) -> Vec<String> {
+        match self {
+            Stdin::Null => Vec::new(),
+            Stdin::Fifo => vec!["--stdin".to_string(), "fifo".to_string()],
+            Stdin::File(file) => vec![
+                "--stdin-file".to_string(),
+                file.to_string_lossy().to_string(),
+            ],
+        }
+    }
+}
+
+/// Path of the FIFO of a daemon
+pub(crate) fn fifo_path(root_dir: &Path, id: &str) -> PathBuf {
+    build_file_path(root_dir, id, "stdin")
+}
+
+/// Standard input for a new run of a daemon, creating a fresh FIFO if needed
+pub(crate) fn open(root_dir: &Path, id: &str, stdin: &Stdin) -> Result<Stdio> {
+    let path = match stdin {
+        Stdin::Null => return Ok(Stdio::null()),
+        Stdin::File(file) => {
+            let file = File::open(file)
+                .map_err(|e| anyhow::anyhow!("Failed to open {}: {}", file.display(), e))?;
+            return Ok(Stdio::from(file));
+        }
+        Stdin::Fifo => fifo_path(root_dir, id),
+    };
+    let _ = std::fs::remove_file(&path);
+    let c_path = CString::new(path.as_os_str().as_bytes())?;
+    // SAFETY: c_path is a valid NUL-terminated string
+    if unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) } == -1 {
+        let err = std::io::Error::last_os_error();
+        return Err(anyhow::anyhow!(
+            "Failed to create {}: {}",
+            path.display(),
+            err
+        ));
+    }
+    let fifo = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(&path)
+        .map_err(|e| anyhow::anyhow!("Failed to open {}: {}", path.display(), e))?;
+    Ok(Stdio::from(fifo))
+}
+
+/// What `demon input` sends to a daemon
+pub(crate) enum Input {
+    /// A line of text, sent with a trailing newline
+    Line(String),
+    /// The contents of a file, as they are
+    File(PathBuf),
+    /// Everything read from our own standard input
+    Stdin,
+}
+
+/// Write input to the FIFO of a running daemon started with `--stdin fifo`
+pub(crate) fn send_input(id: &str, root_dir: &Path, input: Input) -> Result<()> {
+    let path = fifo_path(root_dir, id);
+    match std::fs::metadata(&path) {
+        Ok(metadata) if metadata.file_type().is_fifo() => {}
+        _ if !PidFile::exists(root_dir, id) => {
+            return Err(anyhow::anyhow!("Process '{}' not found (no PID file)", id));
+        }
+        _ => {
+            return Err(anyhow::anyhow!(
+                "Daemon '{}' has no stdin pipe; start it with 'demon run --stdin fifo'",
+                id
+            ));
+        }
+    }
+
+    // Without O_NONBLOCK, opening a FIFO nobody reads would block forever
+    let mut fifo = OpenOptions::new()
+        .write(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open(&path)
+        .map_err(|e| match e.raw_os_error() {
+            Some(libc::ENXIO) => anyhow::anyhow!("Daemon '{}' is not running", id),
+            _ => anyhow::anyhow!("Failed to open {}: {}", path.display(), e),
+        })?;
+    set_blocking(&fifo)?;
+
+    let written = match input {
+        Input::Line(line) => fifo.write_all(format!("{line}\n").as_bytes()),
+        Input::File(file) => {
+            let mut file = File::open(&file)
+                .map_err(|e| anyhow::anyhow!("Failed to open {}: {}", file.display(), e))?;
+            std::io::copy(&mut file, &mut fifo).map(|_| ())
+        }
+        Input::Stdin => std::io::copy(&mut std::io::stdin().lock(), &mut fifo).map(|_| ()),
+    };
+    written.map_err(|e| match e.kind() {
+        std::io::ErrorKind::BrokenPipe => anyhow::anyhow!("Daemon '{}' is not running", id),
+        _ => anyhow::anyhow!("Failed to write to {}: {}", path.display(), e),
+    })
+}
+
+fn set_blocking(file: &File) -> Result<()> {
+    // SAFETY: fcntl on a valid descriptor
+    unsafe {
+        let flags = libc::fcntl(file.as_raw_fd(), libc::F_GETFL);
+        if flags == -1
+            || libc::fcntl(file.as_raw_fd(), libc::F_SETFL, flags & !libc::O_NONBLOCK) == -1
+        {
+            return Err(std::io::Error::last_os_error().into());
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,367 @@
+//! Stopping daemons and retiring their files.
+
+use crate::{
+    agent,
+    cgroup::Cgroup,
+    events, exit, growth, history,
+    pidfile::{DaemonMeta, PidFile, PidFileReadError, RootLock, build_file_path},
+    proc::{ProcStat, ProcStatus},
+    progress::Progress,
+    pty, sd_notify, ship,
+    status::is_daemon_running,
+    supervisor, systemd, unix_now,
+};
+use anyhow::Result;
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+/// Send a signal to a daemon, through its supervisor when it has one. A
+/// daemon that is already gone is not an error.
+pub(crate) fn signal_daemon(root_dir: &Path, id: &str, pid: u32, signal: i32) -> Result<()> {
+    let meta = DaemonMeta::read(root_dir, id);
+    if meta.supervised
+        && let Ok(reply) = supervisor::request(root_dir, id, &format!("signal {signal}"))
+    {
+        return match reply.strip_prefix("error ") {
+            Some(err) => Err(anyhow::anyhow!(
+                "Supervisor failed to signal PID {}: {}",
+                pid,
+                err
+            )),
+            None => Ok(()),
+        };
+    }
+
+    // Without its supervisor, the PID of a supervised daemon may have been
+    // reused by now; only the start time recorded at spawn proves it was not
+    if meta.supervised
+        && meta
+            .start_ticks
+            .is_none_or(|ticks| ProcStat::read(pid).is_none_or(|stat| stat.starttime != ticks))
+    {
+        tracing::warn!(
+            "The supervisor of '{}' is gone and PID {} cannot be told to be its daemon, not signalling it",
+            id,
+            pid
+        );
+        return Ok(());
+    }
+
+    // SAFETY: kill has no memory safety preconditions
+    if unsafe { libc::kill(pid as libc::pid_t, signal) } == -1 {
+        let err = std::io::Error::last_os_error();
+        if err.raw_os_error() == Some(libc::ESRCH) {
+            return Ok(());
+        }
+        return Err(anyhow::anyhow!(
+            "Failed to send signal {} to PID {}: {}",
+            signal,
+            pid,
+            err
+        ));
+    }
+    Ok(())
+}
+
+/// How a daemon was stopped, see [`DaemonManager::stop`](crate::DaemonManager::stop)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stopped {
+    /// There was no PID file, so nothing to stop
+    NoPidFile,
+    /// The PID file was invalid and has been removed
+    InvalidPidFile,
+    /// The daemon had already exited; its PID file has been removed
+    AlreadyExited { pid: u32 },
+    /// The daemon exited after SIGTERM
+    Terminated { pid: u32 },
+    /// The daemon ignored SIGTERM until the timeout and was killed with SIGKILL
+    Killed { pid: u32 },
+}
+
+impl Stopped {
+    /// The line `demon stop` prints for the outcome
+    pub(crate) fn message(&self, id: &str) -> String {
+        match self {
+            Stopped::NoPidFile => format!("Process '{id}' is not running (no PID file found)"),
+            Stopped::InvalidPidFile => format!("Process '{id}': invalid PID file, removed it"),
+            Stopped::AlreadyExited { pid } => {
+                format!("Process '{id}' (PID: {pid}) was not running, cleaned up PID file")
+            }
+            Stopped::Terminated { pid } => {
+                format!("Process '{id}' (PID: {pid}) terminated gracefully")
+            }
+            Stopped::Killed { pid } => format!("Process '{id}' (PID: {pid}) terminated forcefully"),
+        }
+    }
+
+    /// The `result` field of `demon stop` in the structured formats
+    pub(crate) fn result(&self) -> &'static str {
+        match self {
+            Stopped::NoPidFile => "not-running",
+            Stopped::InvalidPidFile => "invalid-pid-file",
+            Stopped::AlreadyExited { .. } => "already-exited",
+            Stopped::Terminated { .. } => "terminated",
+            Stopped::Killed { .. } => "killed",
+        }
+    }
+
+    /// Exit code of `demon stop` for the outcome (see [`exit`])
+    pub(crate) fn exit_code(&self) -> i32 {
+        match self {
+            Stopped::NoPidFile => exit::NOT_FOUND,
+            Stopped::InvalidPidFile | Stopped::AlreadyExited { .. } => exit::NOT_RUNNING,
+            Stopped::Terminated { .. } | Stopped::Killed { .. } => 0,
+        }
+    }
+
+    /// PID of the daemon, if there was one
+    pub(crate) fn pid(&self) -> Option<u32> {
+        match self {
+            Stopped::NoPidFile | Stopped::InvalidPidFile => None,
+            Stopped::AlreadyExited { pid }
+            | Stopped::Terminated { pid }
+            | Stopped::Killed { pid } => Some(*pid),
+        }
+    }
+}
+
+/// Stop a daemon with SIGTERM, then SIGKILL once `timeout` seconds have passed,
+/// and retire its PID file
+pub(crate) fn stop_daemon(id: &str, timeout: u64, root_dir: &Path) -> Result<Stopped> {
+    let mut progress = Progress::start("stop", Some(id), Some(timeout));
+    let stopped = terminate(id, timeout, root_dir, &mut progress)?;
+    progress.finish(Some(id), stopped.result(), true);
+    Ok(stopped)
+}
+
+fn terminate(id: &str, timeout: u64, root_dir: &Path, progress: &mut Progress) -> Result<Stopped> {
+    let stopping = match send_sigterm(id, timeout, root_dir)? {
+        Sigterm::Sent(stopping) => stopping,
+        Sigterm::Skipped(stopped) => return Ok(stopped),
+    };
+    progress.update(Some(id), "sigterm", 0);
+
+    // Wait for the process to terminate, checking once more at the timeout so
+    // a daemon that exited meanwhile is not sent SIGKILL
+    for i in 0..=timeout {
+        if let Some(stopped) = stopping.terminated(root_dir)? {
+            return Ok(stopped);
+        }
+        if i == timeout {
+            break;
+        }
+
+        if i == 0 {
+            tracing::info!("Waiting for process to terminate gracefully...");
+        }
+
+        thread::sleep(Duration::from_secs(1));
+        progress.update(Some(id), "waiting", i + 1);
+    }
+
+    stopping.send_sigkill(root_dir)?;
+    progress.update(Some(id), "sigkill", timeout);
+
+    // Wait a bit more for SIGKILL to take effect
+    thread::sleep(SIGKILL_WAIT);
+    stopping
+        .killed(root_dir)?
+        .ok_or_else(|| stopping.still_running())
+}
+
+/// How long SIGKILL is given to take effect
+pub(crate) const SIGKILL_WAIT: Duration = Duration::from_secs(1);
+
+/// Outcome of the first step of a stop
+pub(crate) enum Sigterm {
+    /// SIGTERM was sent, the daemon has yet to exit
+    Sent(Stopping),
+    /// Nothing was running, so no signal was sent
+    Skipped(Stopped),
+}
+
+/// A daemon sent SIGTERM, between the steps of a stop.
+///
+/// [`stop_daemon`] sleeps between them on its thread; the async manager
+/// polls them from a task.
+#[derive(Debug, Clone)]
+pub(crate) struct Stopping {
+    id: String,
+    pid: u32,
+    timeout: u64,
+    peak_rss: Option<u64>,
+}
+
+/// Start stopping a daemon: send SIGTERM, or retire its PID file when it is
+/// not running
+pub(crate) fn send_sigterm(id: &str, timeout: u64, root_dir: &Path) -> Result<Sigterm> {
+    // Check if PID file exists and read PID data
+    let pid_file_data = match PidFile::read(root_dir, id) {
+        Ok(data) => data,
+        Err(PidFileReadError::FileNotFound) => return Ok(Sigterm::Skipped(Stopped::NoPidFile)),
+        Err(PidFileReadError::FileInvalid(_)) => {
+            let _lock = RootLock::exclusive(root_dir)?;
+            PidFile::remove(root_dir, id)?;
+            remove_state_files(root_dir, id);
+            return Ok(Sigterm::Skipped(Stopped::InvalidPidFile));
+        }
+        Err(PidFileReadError::IoError(err)) => {
+            return Err(anyhow::anyhow!("Failed to read PID file: {}", err));
+        }
+    };
+
+    let pid = pid_file_data.pid;
+
+    tracing::info!(
+        "Stopping daemon '{}' (PID: {}) with timeout {}s",
+        id,
+        pid,
+        timeout
+    );
+
+    // Check if process is running
+    if !is_daemon_running(root_dir, id, pid) {
+        ship::ship_finished(root_dir, id);
+        remove_pid_file_if_owned(root_dir, id, pid, None, None)?;
+        return Ok(Sigterm::Skipped(Stopped::AlreadyExited { pid }));
+    }
+
+    // Sample the peak RSS for the run history while the process is still around
+    let peak_rss = ProcStatus::read(pid).and_then(|status| status.vm_hwm);
+
+    // A stopped daemon must not be started again by its restart policy
+    agent::hold(root_dir, id, pid);
+
+    tracing::info!("Sending SIGTERM to PID {}", pid);
+    signal_daemon(root_dir, id, pid, libc::SIGTERM)?;
+    Ok(Sigterm::Sent(Stopping {
+        id: id.to_string(),
+        pid,
+        timeout,
+        peak_rss,
+    }))
+}
+
+impl Stopping {
+    /// Retire the daemon once it has exited after SIGTERM; None while it is
+    /// still running
+    pub(crate) fn terminated(&self, root_dir: &Path) -> Result<Option<Stopped>> {
+        if is_daemon_running(root_dir, &self.id, self.pid) {
+            return Ok(None);
+        }
+        self.retire(root_dir, "SIGTERM".to_string())?;
+        Ok(Some(Stopped::Terminated { pid: self.pid }))
+    }
+
+    /// Send SIGKILL once the timeout has passed
+    pub(crate) fn send_sigkill(&self, root_dir: &Path) -> Result<()> {
+        tracing::warn!(
+            "Process {} didn't terminate after {}s, sending SIGKILL",
+            self.pid,
+            self.timeout
+        );
+        signal_daemon(root_dir, &self.id, self.pid, libc::SIGKILL)
+    }
+
+    /// Retire the daemon once SIGKILL has taken effect; None while it is
+    /// still running
+    pub(crate) fn killed(&self, root_dir: &Path) -> Result<Option<Stopped>> {
+        if is_daemon_running(root_dir, &self.id, self.pid) {
+            return Ok(None);
+        }
+        self.retire(root_dir, format!("SIGKILL after {}s", self.timeout))?;
+        Ok(Some(Stopped::Killed { pid: self.pid }))
+    }
+
+    /// The error once SIGKILL had [`SIGKILL_WAIT`] to take effect
+    pub(crate) fn still_running(&self) -> anyhow::Error {
+        anyhow::anyhow!("Process {} is still running after SIGKILL", self.pid)
+    }
+
+    fn retire(&self, root_dir: &Path, signal: String) -> Result<()> {
+        events::emit(
+            root_dir,
+            &self.id,
+            events::Kind::Stopped,
+            Some(self.pid),
+            Some(signal),
+        );
+        ship::ship_finished(root_dir, &self.id);
+        remove_pid_file_if_owned(
+            root_dir,
+            &self.id,
+            self.pid,
+            Some(unix_now()),
+            self.peak_rss,
+        )
+    }
+}
+
+/// Remove a PID file under the root lock, but only if it still refers to `pid`.
+///
+/// A new daemon with the same ID may have been started while we were waiting
+/// for the old one to terminate; its PID file must be left alone. The retired
+/// run is recorded in the history with the given end time and peak RSS.
+pub(crate) fn remove_pid_file_if_owned(
+    root_dir: &Path,
+    id: &str,
+    pid: u32,
+    ended_at: Option<u64>,
+    peak_rss: Option<u64>,
+) -> Result<()> {
+    let _lock = RootLock::exclusive(root_dir)?;
+    match PidFile::read(root_dir, id) {
+        Ok(data) if data.pid != pid => {
+            tracing::info!(
+                "PID file of '{}' now belongs to PID {}, leaving it in place",
+                id,
+                data.pid
+            );
+            Ok(())
+        }
+        Err(PidFileReadError::FileNotFound) => Ok(()),
+        current => {
+            if let Ok(data) = current {
+                history::record_retired(root_dir, id, &data, ended_at, peak_rss);
+            }
+            PidFile::remove(root_dir, id)?;
+            remove_state_files(root_dir, id);
+            Ok(())
+        }
+    }
+}
+
+/// Extensions of the sockets and other files that accompany a PID file
+pub(crate) const STATE_EXTENSIONS: [&str; 5] = [
+    "sock",
+    "stdin",
+    growth::EXTENSION,
+    pty::SOCKET_EXTENSION,
+    sd_notify::SOCKET_EXTENSION,
+];
+
+/// Remove the metadata and control socket that accompany a PID file, and the
+/// cgroup or systemd scope of the daemon after killing whatever it left
+/// running in it
+pub(crate) fn remove_state_files(root_dir: &Path, id: &str) {
+    let meta = DaemonMeta::read(root_dir, id);
+    if let Some(cgroup) = Cgroup::of(&meta) {
+        cgroup.kill();
+        cgroup.remove();
+    }
+    if meta.policy.systemd_scope {
+        systemd::stop_scope(root_dir, id);
+    }
+    if let Err(e) = DaemonMeta::remove(root_dir, id) {
+        tracing::warn!("Failed to remove the metadata of '{}': {:#}", id, e);
+    }
+    for extension in STATE_EXTENSIONS {
+        let path = build_file_path(root_dir, id, extension);
+        if let Err(e) = std::fs::remove_file(&path)
+            && e.kind() != std::io::ErrorKind::NotFound
+        {
+            tracing::warn!("Failed to remove {}: {}", path.display(), e);
+        }
+    }
+}
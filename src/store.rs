@@ -0,0 +1,319 @@
+//! Where the state of daemons lives: the [`StateStore`] holding their PID and
+//! metadata records.
+//!
+//! By default the records are the `<id>.pid` and `<id>.meta` files in the
+//! directory of each daemon, `<root>/<id>/` ([`FileStore`]). With the `sqlite` feature, a root directory
+//! holding `state.db` keeps them in that database instead ([`SqliteStore`]).
+//! Programs embedding demon can give a root directory any other store with
+//! [`DaemonManager::with_store`](crate::DaemonManager::with_store), such as a
+//! [`MemoryStore`] in tests or a client of an internal service.
+//!
+//! Such a store only exists in the embedding process. Supervisors and the
+//! agent are processes of their own, so the daemons it holds cannot be
+//! supervised, and the `demon` command line does not see them. Logs, locks and
+//! sockets are files in the root directory whatever the store.
+
+use crate::{layout, pidfile::build_file_path};
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// The database of a root directory using [`SqliteStore`]
+pub(crate) const SQLITE_FILE: &str = "state.db";
+
+/// Stores given to root directories in this process, by canonical path
+static STORES: Mutex<Vec<(PathBuf, Arc<dyn StateStore>)>> = Mutex::new(Vec::new());
+
+/// The records kept for each daemon
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Record {
+    /// The PID of the daemon on the first line, then its command, one
+    /// argument per line
+    Pid,
+    /// Extra state of the daemon, as JSON
+    Meta,
+}
+
+impl Record {
+    /// Extension of the record's file in a [`FileStore`]
+    pub fn extension(self) -> &'static str {
+        match self {
+            Record::Pid => "pid",
+            Record::Meta => "meta",
+        }
+    }
+}
+
+/// Persistence of the records of the daemons of one root directory.
+///
+/// Records are opaque strings, so a store needs no knowledge of their format.
+/// Changes are serialized by a lock on the root directory, so a store only has
+/// to make each write atomic.
+pub trait StateStore: Send + Sync {
+    /// A record of daemon `id`, or None when there is none
+    fn read(&self, id: &str, record: Record) -> Result<Option<String>>;
+
+    /// Create or replace a record of daemon `id`
+    fn write(&self, id: &str, record: Record, contents: &str) -> Result<()>;
+
+    /// Remove a record of daemon `id`; removing a missing record is fine
+    fn remove(&self, id: &str, record: Record) -> Result<()>;
+
+    /// IDs of the daemons with a PID record, sorted
+    fn ids(&self) -> Result<Vec<String>>;
+
+    /// Whether other processes, such as supervisors, find the same records
+    /// for the root directory, which supervised daemons need
+    fn shared(&self) -> bool {
+        false
+    }
+}
+
+/// Records as `<id>/<id>.pid` and `<id>/<id>.meta` files in the root
+/// directory, the layout of the `demon` command line
+#[derive(Debug, Clone)]
+pub struct FileStore {
+    root_dir: PathBuf,
+}
+
+impl FileStore {
+    pub fn new(root_dir: impl Into<PathBuf>) -> Self {
+        Self {
+            root_dir: root_dir.into(),
+        }
+    }
+
+    fn path(&self, id: &str, record: Record) -> PathBuf {
+        build_file_path(&self.root_dir, id, record.extension())
+    }
+}
+
+impl StateStore for FileStore {
+    fn read(&self, id: &str, record: Record) -> Result<Option<String>> {
+        let path = self.path(id, record);
+        match std::fs::read_to_string(&path) {
+            Ok(contents) => Ok(Some(contents)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        }
+    }
+
+    /// Write a temporary file and rename it over the record, so readers
+    /// never see it half-written
+    fn write(&self, id: &str, record: Record, contents: &str) -> Result<()> {
+        let path = self.path(id, record);
+        let tmp = build_file_path(&self.root_dir, id, &format!("{}.tmp", record.extension()));
+        layout::create_daemon_dir(&self.root_dir, id)?;
+        std::fs::write(&tmp, contents)
+            .with_context(|| format!("Failed to write {}", tmp.display()))?;
+        std::fs::rename(&tmp, &path).with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    fn remove(&self, id: &str, record: Record) -> Result<()> {
+        let path = self.path(id, record);
+        match std::fs::remove_file(&path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                Err(e).with_context(|| format!("Failed to remove {}", path.display()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn ids(&self) -> Result<Vec<String>> {
+        layout::ids_with(&self.root_dir, &[Record::Pid.extension()])
+    }
+
+    fn shared(&self) -> bool {
+        true
+    }
+}
+
+/// Records in memory, gone with the process; for tests of code managing
+/// daemons
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    records: Mutex<BTreeMap<(String, Record), String>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn records(&self) -> std::sync::MutexGuard<'_, BTreeMap<(String, Record), String>> {
+        self.records
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl StateStore for MemoryStore {
+    fn read(&self, id: &str, record: Record) -> Result<Option<String>> {
+        Ok(self.records().get(&(id.to_string(), record)).cloned())
+    }
+
+    fn write(&self, id: &str, record: Record, contents: &str) -> Result<()> {
+        self.records()
+            .insert((id.to_string(), record), contents.to_string());
+        Ok(())
+    }
+
+    fn remove(&self, id: &str, record: Record) -> Result<()> {
+        self.records().remove(&(id.to_string(), record));
+        Ok(())
+    }
+
+    fn ids(&self) -> Result<Vec<String>> {
+        Ok(self
+            .records()
+            .keys()
+            .filter(|(_, record)| *record == Record::Pid)
+            .map(|(id, _)| id.clone())
+            .collect())
+    }
+}
+
+/// Records in a SQLite database, `state.db` in the root directory. The
+/// `demon` command line uses it for every root directory that has one, so
+/// daemons spawned by a library through it show up in `demon list`.
+#[cfg(feature = "sqlite")]
+#[derive(Debug, Clone)]
+pub struct SqliteStore {
+    path: PathBuf,
+}
+
+#[cfg(feature = "sqlite")]
+impl SqliteStore {
+    /// Use the database of a root directory, creating it if needed
+    pub fn open(root_dir: impl AsRef<Path>) -> Result<Self> {
+        let store = Self {
+            path: root_dir.as_ref().join(SQLITE_FILE),
+        };
+        store.connect()?;
+        Ok(store)
+    }
+
+    /// A connection per call, since the store is shared between processes
+    fn connect(&self) -> Result<rusqlite::Connection> {
+        let connection = rusqlite::Connection::open(&self.path)
+            .with_context(|| format!("Failed to open {}", self.path.display()))?;
+        connection.busy_timeout(std::time::Duration::from_secs(5))?;
+        connection.execute(
+            "CREATE TABLE IF NOT EXISTS records (
+                id TEXT NOT NULL,
+                record TEXT NOT NULL,
+                contents TEXT NOT NULL,
+                PRIMARY KEY (id, record)
+            )",
+            (),
+        )?;
+        Ok(connection)
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl StateStore for SqliteStore {
+    fn read(&self, id: &str, record: Record) -> Result<Option<String>> {
+        use rusqlite::OptionalExtension;
+        Ok(self
+            .connect()?
+            .query_row(
+                "SELECT contents FROM records WHERE id = ?1 AND record = ?2",
+                (id, record.extension()),
+                |row| row.get(0),
+            )
+            .optional()?)
+    }
+
+    fn write(&self, id: &str, record: Record, contents: &str) -> Result<()> {
+        self.connect()?.execute(
+            "INSERT OR REPLACE INTO records (id, record, contents) VALUES (?1, ?2, ?3)",
+            (id, record.extension(), contents),
+        )?;
+        Ok(())
+    }
+
+    fn remove(&self, id: &str, record: Record) -> Result<()> {
+        self.connect()?.execute(
+            "DELETE FROM records WHERE id = ?1 AND record = ?2",
+            (id, record.extension()),
+        )?;
+        Ok(())
+    }
+
+    fn ids(&self) -> Result<Vec<String>> {
+        let connection = self.connect()?;
+        let mut statement =
+            connection.prepare("SELECT id FROM records WHERE record = 'pid' ORDER BY id")?;
+        let ids = statement
+            .query_map((), |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<String>>>()?;
+        Ok(ids)
+    }
+
+    fn shared(&self) -> bool {
+        true
+    }
+}
+
+/// Give a root directory a store for the rest of the process, replacing any
+/// store given before
+pub(crate) fn register(root_dir: &Path, store: Arc<dyn StateStore>) -> Result<()> {
+    let root_dir = root_dir
+        .canonicalize()
+        .with_context(|| format!("Cannot resolve {}", root_dir.display()))?;
+    let mut stores = STORES
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    stores.retain(|(dir, _)| *dir != root_dir);
+    stores.push((root_dir, store));
+    Ok(())
+}
+
+/// The store given to a root directory in this process, if any
+fn registered(root_dir: &Path) -> Option<Arc<dyn StateStore>> {
+    let stores = STORES
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    if stores.is_empty() {
+        return None;
+    }
+    let root_dir = root_dir.canonicalize().ok()?;
+    stores
+        .iter()
+        .find(|(dir, _)| *dir == root_dir)
+        .map(|(_, store)| store.clone())
+}
+
+/// The store of a root directory: the one given to it in this process, its
+/// SQLite database or its files
+pub(crate) fn open(root_dir: &Path) -> Arc<dyn StateStore> {
+    if let Some(store) = registered(root_dir) {
+        return store;
+    }
+    if root_dir.join(SQLITE_FILE).is_file() {
+        #[cfg(feature = "sqlite")]
+        return Arc::new(SqliteStore {
+            path: root_dir.join(SQLITE_FILE),
+        });
+        #[cfg(not(feature = "sqlite"))]
+        {
+            static WARNED: std::sync::Once = std::sync::Once::new();
+            WARNED.call_once(|| {
+                tracing::warn!(
+                    "{} keeps its daemons in {}, which this demon was built without support for",
+                    root_dir.display(),
+                    SQLITE_FILE
+                )
+            });
+        }
+    }
+    Arc::new(FileStore::new(root_dir))
+}
+
+/// Whether other processes, such as supervisors, see the same records for
+/// this root directory
+pub(crate) fn is_shared(root_dir: &Path) -> bool {
+    registered(root_dir).is_none_or(|store| store.shared())
+}
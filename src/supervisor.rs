@@ -0,0 +1,920 @@
+//! Supervisor process for `demon run --supervise`.
+//!
+//! The supervisor is a small resident process (our own binary running the
+//! hidden `supervise` subcommand) that spawns the daemon as its child and holds
+//! a pidfd for it. Because the daemon is its child and the pidfd refers to that
+//! exact process, signals and liveness checks routed through the supervisor can
+//! never hit an unrelated process that reused the PID. Other demon invocations
+//! talk to it over a Unix socket at `<root>/<id>/<id>.sock` using a line protocol:
+//!
+//! - `status` replies `running <pid>`
+//! - `signal <number>` replies `ok` or `error <message>`
+//!
+//! Daemons started with `--sd-notify` also get a notification socket (see
+//! [`crate::sd_notify`]) that the supervisor reads alongside the control socket.
+//! Daemons started with `--watch` are restarted in place by their supervisor
+//! when their files change (see [`crate::autorestart`]).
+
+use crate::agent::Policy;
+use crate::autorestart::Changes;
+use crate::history::{self, RunRecord};
+use crate::hooks::{HookEvent, Hooks};
+use crate::singleton::Claim;
+use crate::timeline::{self, Sampler};
+use crate::{
+    ansi::Capture,
+    config::Config,
+    coredump, demon_exe, events, format_duration, growth, otel,
+    pidfile::{DaemonMeta, PidFile, PidFileReadError, RootLock, build_file_path},
+    proc::{self, ProcStat},
+    pty::{Pty, Relay},
+    runner::{Confinement, RunOptions, spawn_daemon, spawn_daemon_on_terminal},
+    runs,
+    sd_notify::{self, NotifySocket},
+    ship,
+    stdin::{self, Stdin},
+    unix_now,
+};
+use anyhow::{Context, Result};
+use std::cell::Cell;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::time::{Duration, Instant};
+
+/// How long clients wait for the supervisor to answer a request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Spawn a supervisor for the daemon and wait until it has started the daemon.
+///
+/// Returns the PID of the daemon (not of the supervisor). The supervisor writes
+/// the PID file and metadata itself before reporting back, while the caller
+/// still holds the root lock.
+pub(crate) fn spawn_supervised(
+    id: &str,
+    command: &[String],
+    root_dir: &Path,
+    options: &RunOptions,
+) -> Result<u32> {
+    let exe = demon_exe()?;
+    let mut cmd = Command::new(exe);
+    cmd.arg("supervise")
+        .arg("--root-dir")
+        .arg(root_dir)
+        .args(options.hooks.to_args())
+        .args(options.policy.to_args());
+    if options.pty {
+        cmd.arg("--pty");
+    }
+    cmd.args(options.stdin.to_args());
+    cmd.arg(id)
+        .arg("--")
+        .args(command)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null());
+
+    // Detach the supervisor from our terminal, like any other daemon
+    // SAFETY: setsid is async-signal-safe and touches no memory
+    unsafe {
+        std::os::unix::process::CommandExt::pre_exec(&mut cmd, || {
+            if libc::setsid() == -1 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+
+    let mut supervisor = cmd.spawn().context("Failed to start supervisor")?;
+    let stdout = supervisor.stdout.take().expect("stdout is piped");
+
+    let mut line = String::new();
+    BufReader::new(stdout).read_line(&mut line)?;
+    // The supervisor keeps running; it is reparented once we exit
+    std::mem::forget(supervisor);
+
+    let line = line.trim();
+    if let Some(pid) = line.strip_prefix("ok ") {
+        return pid
+            .parse()
+            .with_context(|| format!("Invalid supervisor response: {line}"));
+    }
+    match line.strip_prefix("error ") {
+        Some(err) => Err(anyhow::anyhow!("{}", err)),
+        None => Err(anyhow::anyhow!(
+            "Supervisor exited before starting the daemon"
+        )),
+    }
+}
+
+/// Send a request to the supervisor of a daemon and return its reply
+pub(crate) fn request(root_dir: &Path, id: &str, request: &str) -> Result<String> {
+    let mut stream = UnixStream::connect(build_file_path(root_dir, id, "sock"))?;
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    stream.set_write_timeout(Some(REQUEST_TIMEOUT))?;
+    writeln!(stream, "{request}")?;
+
+    let mut reply = String::new();
+    BufReader::new(stream).read_line(&mut reply)?;
+    if reply.is_empty() {
+        return Err(anyhow::anyhow!("Supervisor closed the connection"));
+    }
+    Ok(reply.trim_end().to_string())
+}
+
+/// Entry point of the hidden `supervise` subcommand.
+///
+/// Reports the outcome of starting the daemon on stdout (`ok <pid>` or
+/// `error <message>`) for `spawn_supervised`, then supervises it until it exits.
+pub(crate) fn run_supervisor(
+    id: &str,
+    command: &[String],
+    root_dir: &Path,
+    hooks: Hooks,
+    policy: Policy,
+    pty: bool,
+    stdin: Stdin,
+) -> Result<()> {
+    let started = Changes::watch(root_dir, &policy.autorestart).and_then(|changes| {
+        let supervisor = Supervisor::start(
+            id,
+            command,
+            root_dir,
+            hooks.clone(),
+            policy.clone(),
+            pty,
+            stdin.clone(),
+        )?;
+        Ok((supervisor, changes))
+    });
+
+    let mut stdout = std::io::stdout();
+    match &started {
+        Ok((supervisor, _)) => writeln!(stdout, "ok {}", supervisor.child.id())?,
+        Err(e) => writeln!(stdout, "error {}", format!("{e:#}").replace('\n', " "))?,
+    }
+    stdout.flush()?;
+    detach_stdout();
+
+    let (mut supervisor, mut changes) = started?;
+    loop {
+        let pid = supervisor.child.id();
+        let mut change = supervisor.supervise(changes.as_mut())?;
+        // Only daemons started with --watch come back, after a change
+        supervisor = loop {
+            let (Some(path), Some(changes)) = (change, changes.as_mut()) else {
+                return Ok(());
+            };
+            let restarted = restart(id, command, root_dir, pid, &hooks, &policy, pty, &stdin);
+            match restarted {
+                Ok(Some(supervisor)) => {
+                    events::emit(
+                        root_dir,
+                        id,
+                        events::Kind::Restarted,
+                        Some(supervisor.child.id()),
+                        Some(format!("{} changed", path.display())),
+                    );
+                    break supervisor;
+                }
+                Ok(None) => return Ok(()),
+                Err(e) => {
+                    tracing::warn!("Failed to restart '{}': {:#}", id, e);
+                    change = changes.next(root_dir, id, pid);
+                }
+            }
+        };
+    }
+}
+
+/// Start the daemon again after a change to its files, or None when it was
+/// stopped or replaced in the meantime
+#[allow(clippy::too_many_arguments)]
+fn restart(
+    id: &str,
+    command: &[String],
+    root_dir: &Path,
+    pid: u32,
+    hooks: &Hooks,
+    policy: &Policy,
+    pty: bool,
+    stdin: &Stdin,
+) -> Result<Option<Supervisor>> {
+    let _lock = RootLock::exclusive(root_dir)?;
+    let previous = match PidFile::read(root_dir, id) {
+        Ok(previous) if previous.pid == pid => previous,
+        _ => return Ok(None),
+    };
+    policy.requirements.check(id)?;
+    let claim = match policy.singleton {
+        Some(_) => Some(Claim::acquire(root_dir, id)?),
+        None => None,
+    };
+    // Like a new `demon run`, each run gets fresh logs
+    if let Err(e) = runs::archive(root_dir, id, &previous) {
+        tracing::warn!(
+            "Failed to keep the logs of the last run of '{}': {:#}",
+            id,
+            e
+        );
+    }
+    for extension in ["stdout", "stderr"] {
+        File::create(build_file_path(root_dir, id, extension))?;
+    }
+    let _ = std::fs::remove_file(build_file_path(root_dir, id, timeline::EXTENSION));
+
+    let supervisor = Supervisor::start(
+        id,
+        command,
+        root_dir,
+        hooks.clone(),
+        policy.clone(),
+        pty,
+        stdin.clone(),
+    )?;
+    if let Some(claim) = claim {
+        claim.record(supervisor.child.id())?;
+    }
+    tracing::info!(
+        "Restarted '{}' (PID {}) after a change",
+        id,
+        supervisor.child.id()
+    );
+    Ok(Some(supervisor))
+}
+
+/// Point stdout at /dev/null so nothing fails once `demon run` stops reading it
+fn detach_stdout() {
+    if let Ok(null) = File::open("/dev/null") {
+        // SAFETY: both descriptors are valid for the duration of the call
+        unsafe {
+            libc::dup2(null.as_raw_fd(), libc::STDOUT_FILENO);
+        }
+    }
+}
+
+/// Resources used by a run, as reported by wait4
+struct Usage {
+    /// Peak resident set size in bytes
+    peak_rss: Option<u64>,
+    wall_ms: u64,
+    /// User plus system CPU time
+    cpu_ms: u64,
+}
+
+struct Supervisor {
+    id: String,
+    root_dir: PathBuf,
+    command: Vec<String>,
+    started_at: u64,
+    /// When the child was spawned, for the exact wall-clock time of the run
+    spawned: Instant,
+    hooks: Hooks,
+    child: Child,
+    /// pidfd of the child, None on kernels without pidfd support
+    pidfd: Option<OwnedFd>,
+    /// Whether a signal was delivered on request, i.e. the exit was asked for
+    signalled: Cell<bool>,
+    /// Control socket, closed as soon as the daemon exits
+    listener: Option<UnixListener>,
+    /// Pseudo-terminal of a daemon started with `--pty`
+    relay: Option<Relay>,
+    /// Pipes of stdout and stderr, for a daemon started with `--strip-ansi`
+    captures: Vec<Capture>,
+    /// Notification socket of a daemon started with `--sd-notify`
+    notify: Option<NotifySocket>,
+    socket_path: PathBuf,
+    /// When the log sizes are sampled next
+    next_sample: Instant,
+    /// When the output was written
+    timeline: Sampler,
+    /// Where core dumps are collected, with `--core-dump-dir`
+    core_dump_dir: Option<PathBuf>,
+    /// How long the daemon may run, with `--max-runtime`
+    max_runtime: Option<Duration>,
+    /// How long a daemon that ran out of time gets to exit after SIGTERM
+    stop_timeout: Duration,
+    /// When the daemon is sent SIGKILL, between SIGTERM and SIGKILL
+    kill_at: Option<Instant>,
+    /// Whether the daemon was terminated for running out of time
+    timed_out: bool,
+    /// The file whose change the daemon is being terminated for, with --watch
+    changed: Option<PathBuf>,
+}
+
+impl Supervisor {
+    fn start(
+        id: &str,
+        command: &[String],
+        root_dir: &Path,
+        hooks: Hooks,
+        policy: Policy,
+        pty: bool,
+        stdin: Stdin,
+    ) -> Result<Self> {
+        let open_log = |extension: &str| -> Result<File> {
+            let path = build_file_path(root_dir, id, extension);
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .with_context(|| format!("Failed to open {}", path.display()))
+        };
+
+        let notify = if policy.uses_sd_notify() {
+            let watchdog = policy.watchdog.map(Duration::from_secs);
+            Some(NotifySocket::bind(root_dir, id, watchdog)?)
+        } else {
+            None
+        };
+        let mut env = notify.as_ref().map(NotifySocket::env).unwrap_or_default();
+        let (trace, trace_env) = otel::new_run(id, &hooks);
+        env.extend(trace_env);
+
+        let timeline = Sampler::new(root_dir, id)?;
+        let confinement = Confinement::prepare(root_dir, id, &policy)?;
+        let spawned = Instant::now();
+        let mut captures = Vec::new();
+        let (child, relay) = if pty {
+            let pty = Pty::open()?;
+            let child = spawn_daemon_on_terminal(command, &env, &pty.slave, &confinement)?;
+            // Only the daemon holds the slave side, so reads fail once it exits
+            drop(pty.slave);
+            let mut relay = Relay::new(root_dir, id, pty.master, open_log("stdout")?)?;
+            if policy.strip_ansi {
+                relay.strip_ansi();
+            }
+            (child, Some(relay))
+        } else if policy.strip_ansi {
+            // The output goes through the supervisor, which strips it
+            let mut pipe = |extension: &str| -> Result<File> {
+                let (reader, writer) = std::io::pipe()?;
+                let log = open_log(extension)?;
+                captures.push(Capture::new(File::from(OwnedFd::from(reader)), log));
+                Ok(File::from(OwnedFd::from(writer)))
+            };
+            let (stdout, stderr) = (pipe("stdout")?, pipe("stderr")?);
+            let child = spawn_daemon(
+                command,
+                &env,
+                stdin::open(root_dir, id, &stdin)?,
+                stdout,
+                stderr,
+                &confinement,
+            )?;
+            (child, None)
+        } else {
+            let child = spawn_daemon(
+                command,
+                &env,
+                stdin::open(root_dir, id, &stdin)?,
+                open_log("stdout")?,
+                open_log("stderr")?,
+                &confinement,
+            )?;
+            (child, None)
+        };
+        let started_at = unix_now();
+        let pid = child.id();
+        let pidfd = pidfd_open(pid);
+        if pidfd.is_none() {
+            tracing::warn!("pidfd unavailable, falling back to waitpid polling");
+        }
+
+        let socket_path = build_file_path(root_dir, id, "sock");
+        let _ = std::fs::remove_file(&socket_path);
+        let listener = UnixListener::bind(&socket_path)
+            .with_context(|| format!("Failed to bind {}", socket_path.display()))?;
+        listener.set_nonblocking(true)?;
+
+        PidFile::new(pid, command.to_vec()).write(root_dir, id)?;
+        let core_dump_dir = policy.limits.core_dump_dir.clone();
+        let max_runtime = policy.max_runtime.map(Duration::from_secs);
+        let config = Config::load(root_dir)?;
+        let stop_timeout = config
+            .daemon(id)
+            .stop_timeout
+            .unwrap_or_else(|| config.integer("stop.timeout"));
+        let meta = DaemonMeta {
+            started_at: Some(started_at),
+            start_ticks: ProcStat::read(pid).map(|stat| stat.starttime),
+            boot_id: proc::boot_id(),
+            supervised: true,
+            supervisor_pid: Some(std::process::id()),
+            pty,
+            stdin,
+            hooks: hooks.clone(),
+            trace,
+            cgroup: confinement.cgroup_path(),
+            cwd: std::env::current_dir().ok(),
+            policy,
+            ..Default::default()
+        };
+        meta.write(root_dir, id)?;
+
+        Ok(Self {
+            id: id.to_string(),
+            root_dir: root_dir.to_path_buf(),
+            command: command.to_vec(),
+            started_at,
+            spawned,
+            hooks,
+            child,
+            pidfd,
+            signalled: Cell::new(false),
+            listener: Some(listener),
+            relay,
+            captures,
+            notify,
+            socket_path,
+            next_sample: Instant::now(),
+            timeline,
+            core_dump_dir,
+            max_runtime,
+            stop_timeout: Duration::from_secs(stop_timeout),
+            kill_at: None,
+            timed_out: false,
+            changed: None,
+        })
+    }
+
+    /// Serve control requests until the daemon exits, then record its status.
+    ///
+    /// With --watch, returns the file that changed when the daemon is to be
+    /// started again: right away when it was terminated for the change, or
+    /// on the next change when it exited on its own.
+    fn supervise(mut self, mut changes: Option<&mut Changes>) -> Result<Option<PathBuf>> {
+        while let Some(listener) = &self.listener {
+            let mut fds = vec![
+                libc::pollfd {
+                    fd: listener.as_raw_fd(),
+                    events: libc::POLLIN,
+                    revents: 0,
+                },
+                libc::pollfd {
+                    fd: self.pidfd.as_ref().map_or(-1, |fd| fd.as_raw_fd()),
+                    events: libc::POLLIN,
+                    revents: 0,
+                },
+                libc::pollfd {
+                    fd: self.notify.as_ref().map_or(-1, NotifySocket::as_raw_fd),
+                    events: libc::POLLIN,
+                    revents: 0,
+                },
+            ];
+            fds.extend(self.captures.iter().map(Capture::pollfd));
+            if let Some(relay) = &self.relay {
+                fds.extend(relay.pollfds());
+            }
+            // Without a pidfd we cannot be woken by the exit, and file changes
+            // arrive on a channel, so poll periodically
+            let mut timeout = if self.pidfd.is_some() && changes.is_none() {
+                -1
+            } else {
+                100
+            };
+            if let Some(left) = self
+                .notify
+                .as_ref()
+                .and_then(NotifySocket::watchdog_timeout)
+            {
+                // One more millisecond so the deadline has passed when poll returns
+                let left =
+                    i32::try_from(left.as_millis()).map_or(i32::MAX, |ms| ms.saturating_add(1));
+                timeout = if timeout == -1 {
+                    left
+                } else {
+                    timeout.min(left)
+                };
+            }
+            let until_sample = self
+                .next_sample
+                .min(self.timeline.next)
+                .saturating_duration_since(Instant::now());
+            let until_sample = i32::try_from(until_sample.as_millis()).unwrap_or(i32::MAX);
+            timeout = if timeout == -1 {
+                until_sample
+            } else {
+                timeout.min(until_sample)
+            };
+            // SAFETY: fds is a valid array of pollfd for the duration of the call
+            let ready = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, timeout) };
+            if ready == -1 {
+                let err = std::io::Error::last_os_error();
+                if err.kind() == std::io::ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(err.into());
+            }
+
+            for (capture, fd) in self.captures.iter_mut().zip(&fds[3..]) {
+                if fd.revents != 0 {
+                    capture.read();
+                }
+            }
+            if let Some(relay) = &mut self.relay {
+                relay.handle(&fds[3 + self.captures.len()..]);
+            }
+            if self.notify.is_some() {
+                self.handle_notifications();
+            }
+            if Instant::now() >= self.next_sample {
+                growth::sample(&self.root_dir, &self.id);
+                self.next_sample = Instant::now() + growth::SAMPLE_INTERVAL;
+            }
+            if Instant::now() >= self.timeline.next {
+                self.timeline.sample();
+            }
+            // Checked as often as the logs are sampled, which is precise enough
+            self.enforce_max_runtime();
+            if let Some(changes) = changes.as_mut()
+                && let Some(path) = changes.settled()
+            {
+                self.restart_for(path);
+            }
+            self.enforce_kill();
+            if fds[1].revents != 0 || (self.pidfd.is_none() && self.child_exited()?) {
+                // Stop accepting requests before reaping so clients fall back
+                // to checking the (now zombie) PID directly
+                self.listener = None;
+            } else if fds[0].revents & libc::POLLIN != 0
+                && let Some(listener) = &self.listener
+            {
+                self.accept_requests(listener);
+            }
+        }
+        // The last output, before the logs are shipped
+        if let Some(relay) = self.relay.take() {
+            relay.finish();
+        }
+        for capture in std::mem::take(&mut self.captures) {
+            capture.finish();
+        }
+        self.timeline.sample();
+
+        let (status, usage) = self.reap()?;
+        tracing::info!("Daemon '{}' exited with {}", self.id, status);
+        let (kind, detail) = match (status.code(), status.signal()) {
+            _ if self.signalled.get() || self.changed.is_some() => {
+                (events::Kind::Stopped, String::new())
+            }
+            _ if self.timed_out => (
+                events::Kind::Crashed,
+                format!(
+                    "TIMEOUT after {}",
+                    format_duration(self.max_runtime.unwrap_or_default())
+                ),
+            ),
+            (Some(0), _) => (events::Kind::Exited, "exit code 0".to_string()),
+            (Some(code), _) => (events::Kind::Crashed, format!("exit code {code}")),
+            (None, signal) => (
+                events::Kind::Crashed,
+                format!("signal {}", signal.unwrap_or_default()),
+            ),
+        };
+        // Requested exits are reported by the command that asked for them
+        if kind != events::Kind::Stopped {
+            events::emit(
+                &self.root_dir,
+                &self.id,
+                kind,
+                Some(self.child.id()),
+                Some(detail),
+            );
+        }
+        let recorded = self.record_exit(status, &usage);
+        ship::ship_logs(&self.root_dir, &self.id, &self.hooks);
+        self.hooks.fire(
+            &self.root_dir,
+            &self.id,
+            &HookEvent {
+                event: kind,
+                pid: self.child.id(),
+                command: &self.command,
+                exit_code: status.code(),
+                exit_signal: status.signal(),
+            },
+        );
+        recorded?;
+
+        // A daemon stopped on request stays stopped
+        let Some(changes) = changes.filter(|_| !self.signalled.get()) else {
+            return Ok(None);
+        };
+        if let Some(path) = self.changed.take() {
+            return Ok(Some(path));
+        }
+        tracing::info!("Daemon '{}' exited, waiting for changes", self.id);
+        Ok(changes.next(&self.root_dir, &self.id, self.child.id()))
+    }
+
+    /// Terminate the daemon's process group for a change to its files, so
+    /// wrappers like `cargo run` do not leave the real program behind; it is
+    /// started again once it has exited
+    fn restart_for(&mut self, path: PathBuf) {
+        if self.changed.is_some() || self.signalled.get() {
+            return;
+        }
+        tracing::info!("{} changed, restarting '{}'", path.display(), self.id);
+        // The daemon leads its own process group, which cannot be reused
+        // before the daemon is reaped
+        // SAFETY: kill has no memory safety preconditions
+        unsafe {
+            libc::kill(-(self.child.id() as libc::pid_t), libc::SIGTERM);
+        }
+        self.changed = Some(path);
+        self.kill_at = Some(Instant::now() + self.stop_timeout);
+    }
+
+    /// Record what the daemon sent over sd_notify, and kill it with SIGABRT
+    /// once its watchdog expired
+    fn handle_notifications(&mut self) {
+        let Some(notify) = &mut self.notify else {
+            return;
+        };
+        let update = notify.receive();
+        let expired = notify.watchdog_expired();
+        let pid = self.child.id();
+
+        if (update.ready || update.status.is_some())
+            && let Err(e) = sd_notify::record(&self.root_dir, &self.id, pid, &update)
+        {
+            tracing::warn!("Failed to record notification from '{}': {}", self.id, e);
+        }
+        if update.ready {
+            tracing::info!("Daemon '{}' is ready", self.id);
+            events::emit(
+                &self.root_dir,
+                &self.id,
+                events::Kind::Ready,
+                Some(pid),
+                None,
+            );
+        }
+        if expired {
+            tracing::warn!("Watchdog of '{}' expired, sending SIGABRT", self.id);
+            events::emit(
+                &self.root_dir,
+                &self.id,
+                events::Kind::Unhealthy,
+                Some(pid),
+                Some("watchdog timeout".to_string()),
+            );
+            self.hooks.fire(
+                &self.root_dir,
+                &self.id,
+                &HookEvent {
+                    event: events::Kind::Unhealthy,
+                    pid,
+                    command: &self.command,
+                    exit_code: None,
+                    exit_signal: None,
+                },
+            );
+            if let Err(e) = self.send_signal(libc::SIGABRT) {
+                tracing::warn!("Failed to kill hung daemon '{}': {}", self.id, e);
+            }
+        }
+    }
+
+    /// Send SIGTERM to a daemon that ran longer than `--max-runtime`
+    fn enforce_max_runtime(&mut self) {
+        let Some(max_runtime) = self.max_runtime else {
+            return;
+        };
+        if self.timed_out || self.kill_at.is_some() || self.spawned.elapsed() < max_runtime {
+            return;
+        }
+        tracing::warn!(
+            "Daemon '{}' ran longer than {}, sending SIGTERM",
+            self.id,
+            format_duration(max_runtime)
+        );
+        if let Err(e) = self.send_signal(libc::SIGTERM) {
+            tracing::warn!("Failed to terminate '{}': {}", self.id, e);
+        }
+        self.timed_out = true;
+        self.kill_at = Some(Instant::now() + self.stop_timeout);
+    }
+
+    /// Send SIGKILL to a daemon terminated by the supervisor that is still
+    /// running after the stop timeout
+    fn enforce_kill(&mut self) {
+        let Some(kill_at) = self.kill_at else {
+            return;
+        };
+        if Instant::now() < kill_at {
+            return;
+        }
+        tracing::warn!(
+            "Daemon '{}' is still running {}s after SIGTERM, sending SIGKILL",
+            self.id,
+            self.stop_timeout.as_secs()
+        );
+        let result = if self.changed.is_some() {
+            // SAFETY: kill has no memory safety preconditions
+            match unsafe { libc::kill(-(self.child.id() as libc::pid_t), libc::SIGKILL) } {
+                -1 => Err(std::io::Error::last_os_error()),
+                _ => Ok(()),
+            }
+        } else {
+            self.send_signal(libc::SIGKILL)
+        };
+        if let Err(e) = result {
+            tracing::warn!("Failed to kill '{}': {}", self.id, e);
+        }
+        self.kill_at = None;
+    }
+
+    /// Check whether the child has exited, without reaping it
+    fn child_exited(&self) -> Result<bool> {
+        // SAFETY: info is plain data written by the kernel
+        let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+        let result = unsafe {
+            libc::waitid(
+                libc::P_PID,
+                self.child.id(),
+                &mut info,
+                libc::WEXITED | libc::WNOHANG | libc::WNOWAIT,
+            )
+        };
+        if result == -1 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        // SAFETY: si_pid is valid for the SIGCHLD info filled in by waitid
+        Ok(unsafe { info.si_pid() } != 0)
+    }
+
+    /// Reap the child, returning its exit status and resource usage
+    fn reap(&self) -> Result<(ExitStatus, Usage)> {
+        let mut status = 0;
+        // SAFETY: usage is plain data written by the kernel
+        let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+        loop {
+            let result =
+                unsafe { libc::wait4(self.child.id() as libc::pid_t, &mut status, 0, &mut usage) };
+            if result != -1 {
+                break;
+            }
+            let err = std::io::Error::last_os_error();
+            if err.kind() != std::io::ErrorKind::Interrupted {
+                return Err(err.into());
+            }
+        }
+        let wall_ms = self.spawned.elapsed().as_millis() as u64;
+        let millis = |time: libc::timeval| time.tv_sec as u64 * 1000 + time.tv_usec as u64 / 1000;
+        let usage = Usage {
+            // ru_maxrss is reported in kilobytes
+            peak_rss: u64::try_from(usage.ru_maxrss).ok().map(|kb| kb * 1024),
+            wall_ms,
+            cpu_ms: millis(usage.ru_utime) + millis(usage.ru_stime),
+        };
+        Ok((ExitStatus::from_raw(status), usage))
+    }
+
+    fn accept_requests(&self, listener: &UnixListener) {
+        while let Ok((stream, _)) = listener.accept() {
+            if let Err(e) = self.handle_request(stream) {
+                tracing::warn!("Failed to handle supervisor request: {}", e);
+            }
+        }
+    }
+
+    fn handle_request(&self, stream: UnixStream) -> Result<()> {
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+        let mut line = String::new();
+        BufReader::new(&stream).read_line(&mut line)?;
+
+        let mut parts = line.split_whitespace();
+        let reply = match (parts.next(), parts.next()) {
+            (Some("status"), None) => format!("running {}", self.child.id()),
+            (Some("signal"), Some(signal)) => match signal.parse::<i32>() {
+                Ok(signal) => match self.send_signal(signal) {
+                    Ok(()) => {
+                        self.signalled.set(true);
+                        "ok".to_string()
+                    }
+                    Err(e) => format!("error {e}"),
+                },
+                Err(_) => format!("error invalid signal '{signal}'"),
+            },
+            _ => format!("error unknown request '{}'", line.trim()),
+        };
+        writeln!(&stream, "{reply}")?;
+        Ok(())
+    }
+
+    fn send_signal(&self, signal: i32) -> std::io::Result<()> {
+        let result = match &self.pidfd {
+            // SAFETY: the pidfd is valid and siginfo may be null
+            Some(pidfd) => unsafe {
+                libc::syscall(
+                    libc::SYS_pidfd_send_signal,
+                    pidfd.as_raw_fd(),
+                    signal,
+                    std::ptr::null::<libc::siginfo_t>(),
+                    0,
+                )
+            },
+            // The child has not been reaped, so its PID cannot have been reused
+            // SAFETY: kill has no memory safety preconditions
+            None => unsafe { libc::kill(self.child.id() as libc::pid_t, signal) as libc::c_long },
+        };
+        if result == -1 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Record the run in the history, then record the exit status in the
+    /// metadata and remove the socket, unless the daemon was already removed
+    /// (e.g. by `stop`) or replaced by a newer run
+    fn record_exit(&self, status: ExitStatus, usage: &Usage) -> Result<()> {
+        let _lock = RootLock::exclusive(&self.root_dir)?;
+        let record = RunRecord {
+            id: self.id.clone(),
+            command: self.command.clone(),
+            pid: self.child.id(),
+            started_at: Some(self.started_at),
+            ended_at: Some(unix_now()),
+            exit_code: status.code(),
+            exit_signal: status.signal(),
+            timed_out: self.timed_out,
+            peak_rss: usage.peak_rss,
+            wall_ms: Some(usage.wall_ms),
+            cpu_ms: Some(usage.cpu_ms),
+        };
+        if let Err(e) = history::append(&self.root_dir, &record) {
+            tracing::warn!("Failed to record run history for '{}': {}", self.id, e);
+        }
+        let core_dump = self.collect_core_dump(status);
+
+        match PidFile::read(&self.root_dir, &self.id) {
+            Ok(data) if data.pid == self.child.id() => {}
+            Err(PidFileReadError::FileNotFound) => {
+                self.remove_sockets();
+                return Ok(());
+            }
+            Ok(_) => return Ok(()),
+            Err(e) => return Err(e.into()),
+        }
+
+        self.remove_sockets();
+
+        let mut meta = DaemonMeta::read(&self.root_dir, &self.id);
+        meta.exit_code = status.code();
+        meta.exit_signal = status.signal();
+        meta.timed_out = self.timed_out;
+        meta.core_dump = core_dump;
+        meta.write(&self.root_dir, &self.id)
+    }
+
+    /// Move the core dump of a daemon started with `--core-dump-dir` into
+    /// its directory
+    fn collect_core_dump(&self, status: ExitStatus) -> Option<PathBuf> {
+        let dir = self.core_dump_dir.as_ref()?;
+        if !status.core_dumped() {
+            return None;
+        }
+        let cwd = std::env::current_dir().ok()?;
+        let signal = status.signal().unwrap_or_default();
+        let pid = self.child.id();
+        match coredump::collect(dir, &self.id, pid, &self.command, &cwd, signal) {
+            Ok(Some(path)) => {
+                tracing::info!("Core dump of '{}' is {}", self.id, path.display());
+                Some(path)
+            }
+            Ok(None) => {
+                tracing::warn!("Daemon '{}' dumped core, but it was not found", self.id);
+                None
+            }
+            Err(e) => {
+                tracing::warn!("Failed to collect the core dump of '{}': {}", self.id, e);
+                None
+            }
+        }
+    }
+
+    fn remove_sockets(&self) {
+        let _ = std::fs::remove_file(&self.socket_path);
+        if self.notify.is_some() {
+            let path = build_file_path(&self.root_dir, &self.id, sd_notify::SOCKET_EXTENSION);
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+fn pidfd_open(pid: u32) -> Option<OwnedFd> {
+    // SAFETY: pidfd_open takes no pointers
+    let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid as libc::pid_t, 0) };
+    if fd < 0 {
+        return None;
+    }
+    // SAFETY: the syscall returned a new descriptor that we now own
+    Some(unsafe { OwnedFd::from_raw_fd(fd as i32) })
+}
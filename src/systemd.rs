@@ -0,0 +1,100 @@
+//! Daemons in systemd scopes (`demon run --systemd-scope`).
+//!
+//! The daemon's command is run by `systemd-run --user --scope`, which asks the
+//! user's service manager for a transient scope unit, `demon-<id>-<hash>.scope`
+//! (the name of its [cgroup](crate::cgroup)), moves itself into it and then
+//! executes the command in its place, so the daemon keeps the PID demon
+//! recorded. The scope belongs to the user manager rather than the login
+//! session, so logind does not kill the daemon when the session ends, and
+//! `systemctl --user` and `journalctl --user -u` show it like any unit.
+//! `--memory` and `--cpus` become the scope's `MemoryMax` and `CPUQuota`.
+//!
+//! When the daemon's files are retired, the scope is stopped, which kills
+//! anything the daemon left running in it.
+
+use crate::{cgroup, cgroup::Resources, runner::resolve_program};
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// Name of the scope unit of a daemon, escaped like `systemd-escape` does
+pub(crate) fn unit_name(root_dir: &Path, id: &str) -> String {
+    let name: String = cgroup::name(root_dir, id)
+        .bytes()
+        .map(|byte| match byte {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'_' | b'.' | b'-' => {
+                (byte as char).to_string()
+            }
+            byte => format!("\\x{byte:02x}"),
+        })
+        .collect();
+    format!("demon-{name}.scope")
+}
+
+/// Fail early when there is no user manager to ask for a scope
+pub(crate) fn check() -> Result<()> {
+    resolve_program("systemd-run").map_err(|_| {
+        anyhow::anyhow!("--systemd-scope needs systemd-run, which is not installed")
+    })?;
+    if std::env::var_os("DBUS_SESSION_BUS_ADDRESS").is_none()
+        && !user_bus().is_some_and(|bus| bus.exists())
+    {
+        return Err(anyhow::anyhow!(
+            "--systemd-scope needs a systemd user manager, and there is no user bus \
+             (DBUS_SESSION_BUS_ADDRESS or $XDG_RUNTIME_DIR/bus); run demon from a user session"
+        ));
+    }
+    Ok(())
+}
+
+fn user_bus() -> Option<PathBuf> {
+    std::env::var_os("XDG_RUNTIME_DIR")
+        .filter(|dir| !dir.is_empty())
+        .map(|dir| PathBuf::from(dir).join("bus"))
+}
+
+/// The `systemd-run` command line that runs a daemon's command in its scope
+pub(crate) fn scope_command(root_dir: &Path, id: &str, resources: &Resources) -> Vec<String> {
+    let mut args: Vec<String> = [
+        "systemd-run",
+        "--user",
+        "--scope",
+        "--quiet",
+        "--collect",
+        "--unit",
+    ]
+    .map(str::to_string)
+    .to_vec();
+    args.push(unit_name(root_dir, id));
+    args.push(format!("--description=demon daemon '{id}'"));
+    if let Some(memory) = resources.memory {
+        args.push(format!("--property=MemoryMax={memory}"));
+    }
+    if let Some(cpus) = resources.cpus {
+        args.push(format!(
+            "--property=CPUQuota={}%",
+            (cpus * 100.0).round() as u64
+        ));
+    }
+    args.push("--".to_string());
+    args
+}
+
+/// Stop the scope of a daemon, killing whatever is left in it; a scope
+/// that already ended is fine
+pub(crate) fn stop_scope(root_dir: &Path, id: &str) {
+    let unit = unit_name(root_dir, id);
+    match Command::new("systemctl")
+        .args(["--user", "stop", &unit])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+    {
+        Ok(status) if !status.success() => {
+            tracing::debug!("systemctl --user stop {} failed: {}", unit, status)
+        }
+        Err(e) => tracing::debug!("Failed to run systemctl: {}", e),
+        Ok(_) => {}
+    }
+}
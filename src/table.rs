@@ -0,0 +1,121 @@
+//! Tables for people, as printed by `list` and `list --all-roots`.
+//!
+//! Every column is as wide as its widest cell, measured in terminal columns
+//! so that wide characters (CJK, emoji) and combining marks line up, but at
+//! most `table.max_width` columns; longer cells are cut with `…`. The last
+//! column is not padded and is only cut at `table.max_command_width`. The
+//! machine formats (`--quiet`, `--format`) are not tables and never cut.
+
+use crate::config::Config;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+/// Length of the rule under the titles, unless the titles take more
+const RULE: usize = 70;
+
+/// How wide columns may get, from the `table.*` settings; 0 is no limit
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct MaxWidths {
+    pub(crate) column: usize,
+    pub(crate) last: usize,
+}
+
+impl MaxWidths {
+    pub(crate) fn from_config(config: &Config) -> Self {
+        Self {
+            column: config.integer("table.max_width") as usize,
+            last: config.integer("table.max_command_width") as usize,
+        }
+    }
+}
+
+/// The titles and widths of the columns of a table
+pub(crate) struct Table {
+    titles: Vec<String>,
+    widths: Vec<usize>,
+    /// Limit of every column, the last one included
+    limits: Vec<usize>,
+}
+
+impl Table {
+    /// A table of `columns`, given as titles and the width they have at
+    /// least, fitting every row of `rows`
+    pub(crate) fn new(columns: &[(&str, usize)], rows: &[Vec<String>], max: MaxWidths) -> Self {
+        let limits: Vec<usize> = (0..columns.len())
+            .map(|index| match index + 1 == columns.len() {
+                true => max.last,
+                false => max.column,
+            })
+            .map(|limit| if limit == 0 { usize::MAX } else { limit })
+            .collect();
+        let widths = columns
+            .iter()
+            .enumerate()
+            .map(|(index, (title, min))| {
+                rows.iter()
+                    .filter_map(|row| row.get(index))
+                    .map(|cell| width(cell))
+                    .chain([width(title), *min])
+                    .max()
+                    .unwrap_or_default()
+                    .min(limits[index])
+            })
+            .collect();
+        Self {
+            titles: columns.iter().map(|(title, _)| title.to_string()).collect(),
+            widths,
+            limits,
+        }
+    }
+
+    /// Print the titles and a rule under them
+    pub(crate) fn print_header(&self) {
+        self.print_row(&self.titles, |_, text| text.to_string());
+        let count = self.widths.len().saturating_sub(1);
+        let padded: usize = self.widths[..count].iter().map(|width| width + 1).sum();
+        let last = self.titles.last().map_or(0, |title| width(title));
+        println!("{}", "-".repeat(RULE.max(padded + last)));
+    }
+
+    /// Print a row, padding every cell but the last; `style` gets the index
+    /// of the column and the cut text, and may add escape codes such as
+    /// colors, which do not count towards the width
+    pub(crate) fn print_row(&self, cells: &[String], style: impl Fn(usize, &str) -> String) {
+        let mut line = String::new();
+        for (index, cell) in cells.iter().enumerate() {
+            let limit = self.limits.get(index).copied().unwrap_or(usize::MAX);
+            let text = truncate(cell, limit);
+            line.push_str(&style(index, &text));
+            if index + 1 < cells.len() {
+                let padding = self.widths[index].saturating_sub(width(&text));
+                line.push_str(&" ".repeat(padding + 1));
+            }
+        }
+        println!("{line}");
+    }
+}
+
+/// Width of text in terminal columns
+pub(crate) fn width(text: &str) -> usize {
+    text.width()
+}
+
+/// `text` cut to at most `max` terminal columns, ending with `…` when cut
+pub(crate) fn truncate(text: &str, max: usize) -> String {
+    if width(text) <= max {
+        return text.to_string();
+    }
+    let mut cut = String::new();
+    let mut used = 0;
+    for c in text.chars() {
+        let c_width = c.width().unwrap_or(0);
+        if used + c_width + 1 > max {
+            break;
+        }
+        cut.push(c);
+        used += c_width;
+    }
+    if max > 0 {
+        cut.push('…');
+    }
+    cut
+}
@@ -0,0 +1,210 @@
+//! When the output of a daemon was written, kept in `<id>.times`.
+//!
+//! Daemons write their logs themselves, straight to the files, so the logs
+//! hold no timestamps. Instead the supervisor samples the sizes of both logs
+//! every [`SAMPLE_INTERVAL`] and appends a line `<unix ms> <stdout size>
+//! <stderr size>` to `<id>.times` whenever one of them changed. The bytes
+//! between two samples were written in the interval before the later one, so
+//! the time of any line is known to within the interval, which is what
+//! `cat --since` and friends need to find the output around an incident, and
+//! what `logs --merge` orders the lines of several logs by.
+//!
+//! Each sample also has the milliseconds since boot, after a `# boot <id>`
+//! header naming the boot. Samples of the current boot are placed by those
+//! rather than by the Unix time, so `--since 10m` still finds the last ten
+//! minutes of output after NTP or an administrator moved the clock.
+//!
+//! The file starts over with every run, like the logs. Daemons without a
+//! supervisor have no timeline.
+
+use crate::{logs::Stream, pidfile::build_file_path, proc};
+use anyhow::{Context, Result};
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// How often the supervisor samples the log sizes
+pub(crate) const SAMPLE_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Extension of the file with the samples
+pub(crate) const EXTENSION: &str = "times";
+
+/// Sizes of the logs at a point in time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Sample {
+    /// Unix time in milliseconds
+    ms: u64,
+    stdout: u64,
+    stderr: u64,
+}
+
+impl Sample {
+    fn size(&self, stream: Stream) -> u64 {
+        match stream {
+            Stream::Stdout => self.stdout,
+            Stream::Stderr => self.stderr,
+        }
+    }
+}
+
+/// Appends samples of the log sizes of a run, in the supervisor
+pub(crate) struct Sampler {
+    file: File,
+    stdout: PathBuf,
+    stderr: PathBuf,
+    last: Option<(u64, u64)>,
+    /// When the sizes are sampled next
+    pub(crate) next: Instant,
+}
+
+impl Sampler {
+    /// Start the timeline of a new run, replacing the one of the last run
+    pub(crate) fn new(root_dir: &Path, id: &str) -> Result<Self> {
+        let path = build_file_path(root_dir, id, EXTENSION);
+        let mut file =
+            File::create(&path).with_context(|| format!("Failed to create {}", path.display()))?;
+        if let Some(boot_id) = proc::boot_id()
+            && let Err(e) = writeln!(file, "# boot {boot_id}")
+        {
+            tracing::warn!("Failed to record the log sizes: {}", e);
+        }
+        let mut sampler = Self {
+            file,
+            stdout: build_file_path(root_dir, id, Stream::Stdout.extension()),
+            stderr: build_file_path(root_dir, id, Stream::Stderr.extension()),
+            last: None,
+            next: Instant::now(),
+        };
+        sampler.sample();
+        Ok(sampler)
+    }
+
+    /// Record the sizes of the logs if they changed since the last sample
+    pub(crate) fn sample(&mut self) {
+        self.next = Instant::now() + SAMPLE_INTERVAL;
+        let size = |path: &Path| std::fs::metadata(path).map_or(0, |metadata| metadata.len());
+        let sizes = (size(&self.stdout), size(&self.stderr));
+        if self.last == Some(sizes) {
+            return;
+        }
+        self.last = Some(sizes);
+        let since_boot = boot_ms().map_or(String::new(), |ms| format!(" {ms}"));
+        if let Err(e) = writeln!(
+            self.file,
+            "{} {} {}{}",
+            now_ms(),
+            sizes.0,
+            sizes.1,
+            since_boot
+        ) {
+            tracing::warn!("Failed to record the log sizes: {}", e);
+        }
+    }
+}
+
+/// The samples of the current run of a daemon
+pub(crate) struct Timeline {
+    samples: Vec<Sample>,
+}
+
+impl Timeline {
+    /// The timeline of a daemon, or None when it has none
+    pub(crate) fn load(root_dir: &Path, id: &str) -> Result<Option<Self>> {
+        let path = build_file_path(root_dir, id, EXTENSION);
+        let contents = match std::fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+        let mut samples: Vec<Sample> = Vec::new();
+        // Unix time of boot by the current wall clock, when the samples are
+        // from this boot
+        let mut booted_at: Option<u64> = None;
+        for line in contents.lines() {
+            if let Some(boot_id) = line.strip_prefix("# boot ") {
+                booted_at = (proc::boot_id().as_deref() == Some(boot_id.trim()))
+                    .then(|| Some(now_ms().saturating_sub(boot_ms()?)))
+                    .flatten();
+                continue;
+            }
+            let numbers: Vec<u64> = line
+                .split(' ')
+                .filter_map(|number| number.parse().ok())
+                .collect();
+            let (ms, stdout, stderr) = match (numbers.as_slice(), booted_at) {
+                (&[_, stdout, stderr, since_boot], Some(booted_at)) => {
+                    (booted_at + since_boot, stdout, stderr)
+                }
+                (&[ms, stdout, stderr] | &[ms, stdout, stderr, _], _) => (ms, stdout, stderr),
+                _ => continue,
+            };
+            let sample = Sample { ms, stdout, stderr };
+            // A log that shrank was truncated, which makes the earlier
+            // samples meaningless
+            if samples
+                .last()
+                .is_some_and(|last| sample.stdout < last.stdout || sample.stderr < last.stderr)
+            {
+                samples.clear();
+            }
+            samples.push(sample);
+        }
+        Ok(Some(Self { samples }))
+    }
+
+    /// Offsets of a log between which lines written from `since` until
+    /// `until` (Unix times in milliseconds) are: the size sampled last before
+    /// `since`, and the size sampled last before `until` or within an interval
+    /// after it, or None when the log has not been sampled since, for its end.
+    /// Lines are known to within [`SAMPLE_INTERVAL`], so the range may include
+    /// some just outside.
+    pub(crate) fn range(
+        &self,
+        stream: Stream,
+        since: Option<u64>,
+        until: Option<u64>,
+    ) -> (u64, Option<u64>) {
+        let start = since
+            .map(|since| self.samples.partition_point(|sample| sample.ms < since))
+            .and_then(|after| after.checked_sub(1))
+            .map_or(0, |before| self.samples[before].size(stream));
+        let end = until.and_then(|until| {
+            // Samples are written within an interval after the growth they
+            // record, so one later than that only has output from after
+            // `until`
+            let interval = SAMPLE_INTERVAL.as_millis() as u64;
+            let at = self
+                .samples
+                .partition_point(|sample| sample.ms < until + interval);
+            self.samples.get(at)?;
+            Some(
+                at.checked_sub(1)
+                    .map_or(0, |before| self.samples[before].size(stream)),
+            )
+        });
+        (start, end)
+    }
+
+    /// Unix time in milliseconds by which a log had grown to `offset`: that
+    /// of the first sample with at least that size, or now for output not
+    /// sampled yet, which was written within the last interval
+    pub(crate) fn time_of(&self, stream: Stream, offset: u64) -> u64 {
+        let at = self
+            .samples
+            .partition_point(|sample| sample.size(stream) < offset);
+        self.samples.get(at).map_or_else(now_ms, |sample| sample.ms)
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// Milliseconds since boot, counting suspend
+fn boot_ms() -> Option<u64> {
+    proc::system_uptime().map(|uptime| uptime.as_millis() as u64)
+}
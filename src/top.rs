@@ -0,0 +1,364 @@
+//! Interactive `demon top` dashboard.
+
+use crate::{
+    demon_exe, events, format_bytes, format_duration, hooks,
+    logs::read_last_n_lines,
+    pidfile::{DaemonMeta, PidFile, PidFileReadError, build_file_path, daemon_ids},
+    proc::ProcStat,
+    status::is_daemon_running,
+};
+use anyhow::Result;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Borders, Cell, Paragraph, Row, Table, TableState};
+use ratatui::{DefaultTerminal, Frame};
+use std::collections::HashMap;
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::mpsc::{Receiver, Sender, channel};
+use std::time::{Duration, Instant};
+
+/// Number of log lines shown in the preview pane when not tailing
+const PREVIEW_LINES: usize = 8;
+
+/// One row of the dashboard
+struct DaemonRow {
+    id: String,
+    pid: Option<u32>,
+    status: &'static str,
+    uptime: Option<Duration>,
+    cpu_percent: Option<f64>,
+    rss: Option<u64>,
+    command: String,
+}
+
+struct App {
+    root_dir: PathBuf,
+    rows: Vec<DaemonRow>,
+    table: TableState,
+    /// Show a full-height live tail of the selected daemon instead of a preview
+    tailing: bool,
+    /// Last CPU time sample per PID, used to compute CPU% between refreshes
+    cpu_samples: HashMap<u32, (Duration, Instant)>,
+    message: String,
+    actions_tx: Sender<String>,
+    actions_rx: Receiver<String>,
+    /// `--read-only`: no stopping or restarting
+    read_only: bool,
+}
+
+pub(crate) fn run_top(root_dir: &Path, interval: Duration, read_only: bool) -> Result<()> {
+    if !std::io::stdout().is_terminal() {
+        return Err(anyhow::anyhow!(
+            "demon top requires an interactive terminal; use 'demon list' in scripts"
+        ));
+    }
+
+    let (actions_tx, actions_rx) = channel();
+    let mut app = App {
+        root_dir: root_dir.to_path_buf(),
+        rows: Vec::new(),
+        table: TableState::default(),
+        tailing: false,
+        cpu_samples: HashMap::new(),
+        message: String::new(),
+        actions_tx,
+        actions_rx,
+        read_only,
+    };
+    app.refresh()?;
+
+    let mut terminal = ratatui::init();
+    let result = event_loop(&mut terminal, &mut app, interval);
+    ratatui::restore();
+    result
+}
+
+fn event_loop(terminal: &mut DefaultTerminal, app: &mut App, interval: Duration) -> Result<()> {
+    let mut last_refresh = Instant::now();
+    loop {
+        terminal.draw(|frame| draw(frame, app))?;
+
+        let timeout = interval.saturating_sub(last_refresh.elapsed());
+        if event::poll(timeout)?
+            && let Event::Key(key) = event::read()?
+            && key.kind == KeyEventKind::Press
+        {
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                KeyCode::Down | KeyCode::Char('j') => app.table.select_next(),
+                KeyCode::Up | KeyCode::Char('k') => app.table.select_previous(),
+                KeyCode::Char('t') | KeyCode::Enter => app.tailing = !app.tailing,
+                KeyCode::Char('s') => app.spawn_action(Action::Stop),
+                KeyCode::Char('r') => app.spawn_action(Action::Restart),
+                _ => {}
+            }
+        }
+
+        while let Ok(message) = app.actions_rx.try_recv() {
+            app.message = message;
+        }
+
+        if last_refresh.elapsed() >= interval {
+            app.refresh()?;
+            last_refresh = Instant::now();
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Action {
+    Stop,
+    Restart,
+}
+
+impl App {
+    fn selected(&self) -> Option<&DaemonRow> {
+        self.table.selected().and_then(|i| self.rows.get(i))
+    }
+
+    fn refresh(&mut self) -> Result<()> {
+        let mut rows = Vec::new();
+        let ids = daemon_ids(&self.root_dir)?;
+
+        for id in ids {
+            let row = match PidFile::read(&self.root_dir, &id) {
+                Ok(data) => {
+                    let running = is_daemon_running(&self.root_dir, &id, data.pid);
+                    let stat = if running {
+                        ProcStat::read(data.pid)
+                    } else {
+                        None
+                    };
+                    DaemonRow {
+                        id,
+                        pid: Some(data.pid),
+                        status: if running { "RUNNING" } else { "DEAD" },
+                        uptime: stat.as_ref().and_then(ProcStat::uptime),
+                        cpu_percent: stat.as_ref().and_then(|s| self.sample_cpu(data.pid, s)),
+                        rss: stat.as_ref().map(ProcStat::rss_bytes),
+                        command: data.command_string(),
+                    }
+                }
+                Err(PidFileReadError::FileNotFound) => continue,
+                Err(err) => DaemonRow {
+                    id,
+                    pid: None,
+                    status: "ERROR",
+                    uptime: None,
+                    cpu_percent: None,
+                    rss: None,
+                    command: err.to_string(),
+                },
+            };
+            rows.push(row);
+        }
+
+        // Keep the selection on the same daemon across refreshes
+        let selected_id = self.selected().map(|row| row.id.clone());
+        self.rows = rows;
+        let index = selected_id
+            .and_then(|id| self.rows.iter().position(|row| row.id == id))
+            .or(if self.rows.is_empty() { None } else { Some(0) });
+        self.table.select(index);
+
+        let live: Vec<u32> = self.rows.iter().filter_map(|row| row.pid).collect();
+        self.cpu_samples.retain(|pid, _| live.contains(pid));
+        Ok(())
+    }
+
+    /// Compute CPU% since the previous sample for this PID
+    fn sample_cpu(&mut self, pid: u32, stat: &ProcStat) -> Option<f64> {
+        let now = Instant::now();
+        let cpu = stat.cpu_time();
+        let previous = self.cpu_samples.insert(pid, (cpu, now));
+        let (prev_cpu, prev_at) = previous?;
+        let wall = now.duration_since(prev_at).as_secs_f64();
+        if wall <= 0.0 {
+            return None;
+        }
+        Some(cpu.saturating_sub(prev_cpu).as_secs_f64() / wall * 100.0)
+    }
+
+    /// Run a stop/restart in the background by invoking our own binary, so the
+    /// UI stays responsive while the daemon shuts down
+    fn spawn_action(&mut self, action: Action) {
+        if self.read_only {
+            self.message = "Read-only mode: stop and restart are disabled".to_string();
+            return;
+        }
+        let Some(row) = self.selected() else {
+            return;
+        };
+        let id = row.id.clone();
+        let root_dir = self.root_dir.clone();
+        let tx = self.actions_tx.clone();
+
+        self.message = match action {
+            Action::Stop => format!("Stopping '{id}'..."),
+            Action::Restart => format!("Restarting '{id}'..."),
+        };
+
+        std::thread::spawn(move || {
+            let message = (|| -> Result<String> {
+                let exe = demon_exe()?;
+                let root = root_dir.to_string_lossy().to_string();
+                // Read the command before stopping, since stop removes the PID file
+                let command = PidFile::read(&root_dir, &id).map(|data| data.command).ok();
+                let meta = DaemonMeta::read(&root_dir, &id);
+
+                run_self(&exe, &["stop", &id, "--root-dir", &root])?;
+                if let Action::Restart = action {
+                    let command = command.ok_or_else(|| {
+                        anyhow::anyhow!("Cannot restart '{}': command unknown", id)
+                    })?;
+                    let mut args = vec!["run", &id, "--root-dir", &root];
+                    if meta.supervised {
+                        args.push("--supervise");
+                    }
+                    let hooks = meta.hooks.to_args();
+                    args.extend(hooks.iter().map(String::as_str));
+                    let policy = meta.policy.to_args();
+                    args.extend(policy.iter().map(String::as_str));
+                    args.push("--");
+                    args.extend(command.iter().map(String::as_str));
+                    run_self(&exe, &args)?;
+                    let pid = PidFile::read(&root_dir, &id).ok().map(|data| data.pid);
+                    events::emit(&root_dir, &id, events::Kind::Restarted, pid, None);
+                    if let Some(pid) = pid {
+                        DaemonMeta::read(&root_dir, &id).hooks.fire(
+                            &root_dir,
+                            &id,
+                            &hooks::HookEvent {
+                                event: events::Kind::Restarted,
+                                pid,
+                                command: &command,
+                                exit_code: None,
+                                exit_signal: None,
+                            },
+                        );
+                    }
+                    Ok(format!("Restarted '{id}'"))
+                } else {
+                    Ok(format!("Stopped '{id}'"))
+                }
+            })();
+            let _ = tx.send(message.unwrap_or_else(|e| format!("Error: {e}")));
+        });
+    }
+}
+
+fn run_self(exe: &Path, args: &[&str]) -> Result<()> {
+    let output = Command::new(exe).args(args).output()?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(anyhow::anyhow!(
+            "{}",
+            stderr.lines().last().unwrap_or("command failed").trim()
+        ));
+    }
+    Ok(())
+}
+
+fn draw(frame: &mut Frame, app: &mut App) {
+    let log_height = if app.tailing {
+        Constraint::Percentage(70)
+    } else {
+        Constraint::Length(PREVIEW_LINES as u16 * 2 + 2)
+    };
+    let [table_area, log_area, help_area] =
+        Layout::vertical([Constraint::Min(5), log_height, Constraint::Length(1)])
+            .areas(frame.area());
+
+    let header = Row::new(["ID", "PID", "STATUS", "UPTIME", "CPU%", "RSS", "COMMAND"])
+        .style(Style::default().add_modifier(Modifier::BOLD));
+    let rows = app.rows.iter().map(|row| {
+        let status_style = match row.status {
+            "RUNNING" => Style::default().fg(Color::Green),
+            _ => Style::default().fg(Color::Red),
+        };
+        Row::new([
+            Cell::from(row.id.clone()),
+            Cell::from(row.pid.map(|p| p.to_string()).unwrap_or_default()),
+            Cell::from(row.status).style(status_style),
+            Cell::from(row.uptime.map(format_duration).unwrap_or_default()),
+            Cell::from(
+                row.cpu_percent
+                    .map(|c| format!("{c:.1}"))
+                    .unwrap_or_default(),
+            ),
+            Cell::from(row.rss.map(format_bytes).unwrap_or_default()),
+            Cell::from(row.command.clone()),
+        ])
+    });
+    let table = Table::new(
+        rows,
+        [
+            Constraint::Length(20),
+            Constraint::Length(8),
+            Constraint::Length(8),
+            Constraint::Length(8),
+            Constraint::Length(6),
+            Constraint::Length(8),
+            Constraint::Min(10),
+        ],
+    )
+    .header(header)
+    .block(
+        Block::default()
+            .borders(Borders::ALL)
+            .title(format!(" demon top - {} ", app.root_dir.display())),
+    )
+    .row_highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+    frame.render_stateful_widget(table, table_area, &mut app.table);
+
+    let (title, lines) = match app.selected() {
+        Some(row) => {
+            let per_stream = if app.tailing {
+                log_area.height.saturating_sub(2) as usize
+            } else {
+                PREVIEW_LINES
+            };
+            (
+                format!(" {} logs ", row.id),
+                log_lines(&app.root_dir, &row.id, per_stream),
+            )
+        }
+        None => (" logs ".to_string(), vec![Line::from("No daemons")]),
+    };
+    // Show the newest lines when there are more than fit
+    let visible = log_area.height.saturating_sub(2) as usize;
+    let skip = lines.len().saturating_sub(visible);
+    let logs = Paragraph::new(lines.into_iter().skip(skip).collect::<Vec<_>>())
+        .block(Block::default().borders(Borders::ALL).title(title));
+    frame.render_widget(logs, log_area);
+
+    let keys = match app.read_only {
+        true => " q quit  j/k select  t tail",
+        false => " q quit  j/k select  s stop  r restart  t tail",
+    };
+    let help = format!("{keys}  {}", app.message);
+    frame.render_widget(Paragraph::new(help), help_area);
+}
+
+/// Last lines of both streams of a daemon, stderr highlighted in red
+fn log_lines(root_dir: &Path, id: &str, per_stream: usize) -> Vec<Line<'static>> {
+    let mut lines = Vec::new();
+    for (extension, style) in [
+        ("stdout", Style::default()),
+        ("stderr", Style::default().fg(Color::Red)),
+    ] {
+        let path = build_file_path(root_dir, id, extension);
+        if let Ok(content) = read_last_n_lines(&path, per_stream) {
+            lines.extend(
+                content
+                    .lines()
+                    .map(|line| Line::from(Span::styled(line.to_string(), style))),
+            );
+        }
+    }
+    lines
+}
@@ -0,0 +1,515 @@
+//! Interactive `demon view` log viewer.
+//!
+//! A pager for both logs of one daemon. The last lines of each log are read
+//! into memory and, for supervised daemons, interleaved in the order they were
+//! written using the timeline, which also lets the view jump to a time. Other
+//! daemons have no times, so their stdout is shown before their stderr.
+
+use crate::{
+    ansi,
+    events::parse_time,
+    exit::{self, Exit},
+    format_timestamp,
+    logs::{Stream, tail_offset},
+    pidfile::build_file_path,
+    timeline::{SAMPLE_INTERVAL, Timeline},
+    unix_now,
+};
+use anyhow::{Context, Result};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Borders, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+use regex::{Regex, RegexBuilder};
+use std::io::{IsTerminal, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+/// What `demon view` loads and how it starts
+#[derive(Debug, Clone)]
+pub(crate) struct ViewOptions {
+    /// Lines read from the end of each log, and kept of each while following
+    pub(crate) lines: usize,
+    /// Start following new output
+    pub(crate) follow: bool,
+}
+
+/// A line of one of the logs
+struct ViewLine {
+    stream: Stream,
+    /// Unix time in milliseconds it was written by, for supervised daemons
+    ms: Option<u64>,
+    text: String,
+}
+
+/// A log and how far it has been read
+struct Source {
+    stream: Stream,
+    path: PathBuf,
+    offset: u64,
+}
+
+/// What the bottom line is asking for
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Prompt {
+    Search,
+    Time,
+}
+
+struct App {
+    id: String,
+    root_dir: PathBuf,
+    max_lines: usize,
+    sources: [Source; 2],
+    timeline: Option<Timeline>,
+    lines: Vec<ViewLine>,
+    show_stdout: bool,
+    show_stderr: bool,
+    /// Indices in `lines` of the lines of the shown streams
+    visible: Vec<usize>,
+    /// Index in `visible` of the first line on screen
+    top: usize,
+    /// Lines that fit on screen, as of the last draw
+    height: usize,
+    follow: bool,
+    search: Option<Regex>,
+    prompt: Option<(Prompt, String)>,
+    message: String,
+}
+
+pub(crate) fn run_view(id: &str, root_dir: &Path, options: &ViewOptions) -> Result<()> {
+    let sources = [Stream::Stdout, Stream::Stderr].map(|stream| Source {
+        stream,
+        path: build_file_path(root_dir, id, stream.extension()),
+        offset: 0,
+    });
+    if !sources.iter().any(|source| source.path.exists()) {
+        let message = format!("No log files found for daemon '{id}'");
+        return Err(Exit::new(exit::NOT_FOUND, message).into());
+    }
+    if !std::io::stdout().is_terminal() {
+        return Err(anyhow::anyhow!(
+            "demon view requires an interactive terminal; use 'demon cat' or 'demon grep' in scripts"
+        ));
+    }
+
+    let mut app = App {
+        id: id.to_string(),
+        root_dir: root_dir.to_path_buf(),
+        max_lines: options.lines,
+        sources,
+        timeline: None,
+        lines: Vec::new(),
+        show_stdout: true,
+        show_stderr: true,
+        visible: Vec::new(),
+        top: 0,
+        height: 0,
+        follow: options.follow,
+        search: None,
+        prompt: None,
+        message: String::new(),
+    };
+    app.load()?;
+
+    let mut terminal = ratatui::init();
+    let result = event_loop(&mut terminal, &mut app);
+    ratatui::restore();
+    result
+}
+
+fn event_loop(terminal: &mut DefaultTerminal, app: &mut App) -> Result<()> {
+    loop {
+        terminal.draw(|frame| draw(frame, app))?;
+
+        if event::poll(SAMPLE_INTERVAL)?
+            && let Event::Key(key) = event::read()?
+            && key.kind == KeyEventKind::Press
+        {
+            if app.prompt.is_some() {
+                app.prompt_key(key.code);
+                continue;
+            }
+            let page = app.height.max(1) as isize;
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    return Ok(());
+                }
+                KeyCode::Down | KeyCode::Char('j') | KeyCode::Enter => app.scroll(1),
+                KeyCode::Up | KeyCode::Char('k') => app.scroll(-1),
+                KeyCode::PageDown | KeyCode::Char(' ') => app.scroll(page),
+                KeyCode::PageUp | KeyCode::Char('b') => app.scroll(-page),
+                KeyCode::Home | KeyCode::Char('g') => app.scroll(isize::MIN),
+                KeyCode::End | KeyCode::Char('G') => app.scroll(isize::MAX),
+                KeyCode::Char('/') => app.prompt = Some((Prompt::Search, String::new())),
+                KeyCode::Char('t') | KeyCode::Char(':') => {
+                    app.prompt = Some((Prompt::Time, String::new()));
+                }
+                KeyCode::Char('n') => app.next_match(true, false),
+                KeyCode::Char('N') => app.next_match(false, false),
+                KeyCode::Char('o') => app.toggle(Stream::Stdout),
+                KeyCode::Char('e') => app.toggle(Stream::Stderr),
+                KeyCode::Char('f') | KeyCode::Char('F') => {
+                    app.follow = !app.follow;
+                    if app.follow {
+                        app.poll()?;
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        if app.follow {
+            app.poll()?;
+        }
+    }
+}
+
+impl App {
+    /// Read the last lines of both logs again
+    fn load(&mut self) -> Result<()> {
+        self.timeline = Timeline::load(&self.root_dir, &self.id)?;
+        let mut batches = Vec::new();
+        for source in &mut self.sources {
+            source.offset = match std::fs::File::open(&source.path) {
+                Ok(mut file) => tail_offset(&mut file, self.max_lines)
+                    .with_context(|| format!("Failed to read {}", source.path.display()))?,
+                Err(_) => 0,
+            };
+            batches.push(read_new(source, self.timeline.as_ref())?);
+        }
+        self.lines = merge(batches, self.timeline.is_some());
+        self.top = 0;
+        self.rebuild(0);
+        if self.follow {
+            self.scroll(isize::MAX);
+        }
+        Ok(())
+    }
+
+    /// Read what the logs gained since they were last read, starting over
+    /// when one was truncated by a new run of the daemon
+    fn poll(&mut self) -> Result<()> {
+        let truncated = self.sources.iter().any(|source| {
+            std::fs::metadata(&source.path).map_or(0, |metadata| metadata.len()) < source.offset
+        });
+        if truncated {
+            self.message = "The logs started over: the daemon was restarted".to_string();
+            return self.load();
+        }
+        if self.timeline.is_some() {
+            self.timeline = Timeline::load(&self.root_dir, &self.id)?;
+        }
+        let mut batches = Vec::new();
+        for source in &mut self.sources {
+            batches.push(read_new(source, self.timeline.as_ref())?);
+        }
+        let new = merge(batches, self.timeline.is_some());
+        if new.is_empty() {
+            return Ok(());
+        }
+        self.lines.extend(new);
+        // Keep as many lines as were loaded, dropping the oldest
+        let excess = self.lines.len().saturating_sub(self.max_lines * 2);
+        self.lines.drain(..excess);
+        self.rebuild(0);
+        self.scroll(isize::MAX);
+        Ok(())
+    }
+
+    /// Recompute the shown lines, keeping `anchor`, an index in `lines`, on
+    /// top of the screen
+    fn rebuild(&mut self, anchor: usize) {
+        self.visible = (0..self.lines.len())
+            .filter(|&index| self.shows(self.lines[index].stream))
+            .collect();
+        self.top = self.visible.partition_point(|&index| index < anchor);
+        self.scroll(0);
+    }
+
+    fn shows(&self, stream: Stream) -> bool {
+        match stream {
+            Stream::Stdout => self.show_stdout,
+            Stream::Stderr => self.show_stderr,
+        }
+    }
+
+    /// Move the screen by `lines`, stopping at either end
+    fn scroll(&mut self, lines: isize) {
+        let bottom = self.visible.len().saturating_sub(self.height.max(1));
+        self.top = self.top.saturating_add_signed(lines).min(bottom);
+        if lines < 0 {
+            self.follow = false;
+        }
+    }
+
+    fn toggle(&mut self, stream: Stream) {
+        match stream {
+            Stream::Stdout if self.show_stderr || !self.show_stdout => {
+                self.show_stdout = !self.show_stdout;
+            }
+            Stream::Stderr if self.show_stdout || !self.show_stderr => {
+                self.show_stderr = !self.show_stderr;
+            }
+            _ => {
+                self.message = "One of the logs has to be shown".to_string();
+                return;
+            }
+        }
+        let anchor = self.visible.get(self.top).copied().unwrap_or_default();
+        self.rebuild(anchor);
+        if self.follow {
+            self.scroll(isize::MAX);
+        }
+    }
+
+    fn prompt_key(&mut self, code: KeyCode) {
+        let Some((prompt, input)) = &mut self.prompt else {
+            return;
+        };
+        match code {
+            KeyCode::Esc => self.prompt = None,
+            // Backspace on an empty prompt leaves it, like in less
+            KeyCode::Backspace if input.is_empty() => self.prompt = None,
+            KeyCode::Backspace => {
+                input.pop();
+            }
+            KeyCode::Char(c) => input.push(c),
+            KeyCode::Enter => {
+                let (prompt, input) = (*prompt, input.clone());
+                self.prompt = None;
+                match prompt {
+                    Prompt::Search => self.set_search(&input),
+                    Prompt::Time => self.jump_to_time(&input),
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Search for a regular expression, ignoring case unless it has
+    /// uppercase letters, and go to its first match from the top of the
+    /// screen; an empty one clears the search
+    fn set_search(&mut self, pattern: &str) {
+        if pattern.is_empty() {
+            self.search = None;
+            self.message.clear();
+            return;
+        }
+        match RegexBuilder::new(pattern)
+            .case_insensitive(!pattern.chars().any(char::is_uppercase))
+            .build()
+        {
+            Ok(regex) => {
+                self.search = Some(regex);
+                self.next_match(true, true);
+            }
+            Err(e) => self.message = format!("Invalid pattern: {e}"),
+        }
+    }
+
+    /// Put the next (or previous) matching line on top of the screen,
+    /// wrapping around at the ends
+    fn next_match(&mut self, forward: bool, from_top: bool) {
+        let Some(regex) = &self.search else {
+            self.message = "No search: press / to search".to_string();
+            return;
+        };
+        let matches: Vec<usize> = self
+            .visible
+            .iter()
+            .enumerate()
+            .filter(|(_, index)| regex.is_match(&self.lines[**index].text))
+            .map(|(position, _)| position)
+            .collect();
+        if matches.is_empty() {
+            self.message = format!("Pattern not found: {}", regex.as_str());
+            return;
+        }
+        let found = match forward {
+            true => {
+                let start = if from_top { self.top } else { self.top + 1 };
+                let after = matches.partition_point(|&position| position < start);
+                matches.get(after).map_or((matches[0], 0), |&m| (m, after))
+            }
+            false => {
+                let before = matches.partition_point(|&position| position < self.top);
+                match before.checked_sub(1) {
+                    Some(at) => (matches[at], at),
+                    None => (matches[matches.len() - 1], matches.len() - 1),
+                }
+            }
+        };
+        self.top = found.0;
+        self.follow = false;
+        self.message = format!(
+            "Match {} of {} for /{}",
+            found.1 + 1,
+            matches.len(),
+            regex.as_str()
+        );
+    }
+
+    /// Put the first line written at or after a time on top of the screen
+    fn jump_to_time(&mut self, input: &str) {
+        if self.timeline.is_none() {
+            self.message = format!(
+                "'{}' has no times: only supervised daemons (--supervise) do",
+                self.id
+            );
+            return;
+        }
+        // A time of day alone is today's
+        let input = match input.contains(':') && !input.contains('-') {
+            true => format!("{} {}", &format_timestamp(unix_now())[..10], input.trim()),
+            false => input.to_string(),
+        };
+        let secs = match parse_time("time", &input) {
+            Ok(secs) => secs,
+            Err(e) => {
+                self.message = e.to_string();
+                return;
+            }
+        };
+        let position = self
+            .visible
+            .iter()
+            .position(|&index| self.lines[index].ms.is_some_and(|ms| ms >= secs * 1000));
+        match position {
+            Some(position) => {
+                self.top = position;
+                self.follow = false;
+                self.scroll(0);
+                self.message = format!("Output since {}", format_timestamp(secs));
+            }
+            None => self.message = format!("No output since {}", format_timestamp(secs)),
+        }
+    }
+}
+
+/// The complete lines a log gained since `source.offset`, moving it past them
+fn read_new(source: &mut Source, timeline: Option<&Timeline>) -> Result<Vec<ViewLine>> {
+    let Ok(mut file) = std::fs::File::open(&source.path) else {
+        return Ok(Vec::new());
+    };
+    let mut data = Vec::new();
+    file.seek(SeekFrom::Start(source.offset))
+        .and_then(|_| file.read_to_end(&mut data))
+        .with_context(|| format!("Failed to read {}", source.path.display()))?;
+    // A line still being written is read once it is complete
+    let complete = data
+        .iter()
+        .rposition(|&byte| byte == b'\n')
+        .map_or(0, |at| at + 1);
+    let mut lines = Vec::new();
+    for line in data[..complete].split_inclusive(|&byte| byte == b'\n') {
+        source.offset += line.len() as u64;
+        let line = line.strip_suffix(b"\n").unwrap_or(line);
+        lines.push(ViewLine {
+            stream: source.stream,
+            ms: timeline.map(|timeline| timeline.time_of(source.stream, source.offset)),
+            text: String::from_utf8_lossy(&ansi::strip(line))
+                .replace('\t', "    ")
+                .replace('\r', ""),
+        });
+    }
+    Ok(lines)
+}
+
+/// The lines of both logs in one list: in the order they were written when
+/// their times are known, stdout first within the same interval, else stdout
+/// then stderr
+fn merge(batches: Vec<Vec<ViewLine>>, timed: bool) -> Vec<ViewLine> {
+    let mut lines: Vec<ViewLine> = batches.into_iter().flatten().collect();
+    if timed {
+        // Stable, so each log keeps its order and stdout goes first
+        lines.sort_by_key(|line| line.ms);
+    }
+    lines
+}
+
+fn draw(frame: &mut Frame, app: &mut App) {
+    let [log_area, status_area, help_area] = Layout::vertical([
+        Constraint::Min(3),
+        Constraint::Length(1),
+        Constraint::Length(1),
+    ])
+    .areas(frame.area());
+
+    app.height = log_area.height.saturating_sub(2) as usize;
+    if app.follow {
+        app.scroll(isize::MAX);
+    }
+
+    let time_style = Style::default().add_modifier(Modifier::DIM);
+    let match_style = Style::default().fg(Color::Black).bg(Color::Yellow);
+    let lines: Vec<Line> = app
+        .visible
+        .iter()
+        .skip(app.top)
+        .take(app.height)
+        .map(|&index| {
+            let line = &app.lines[index];
+            let style = match line.stream {
+                Stream::Stdout => Style::default(),
+                Stream::Stderr => Style::default().fg(Color::Red),
+            };
+            let mut spans = Vec::new();
+            if let Some(ms) = line.ms {
+                spans.push(Span::styled(
+                    format!("{}.{:03} ", format_timestamp(ms / 1000), ms % 1000),
+                    time_style,
+                ));
+            }
+            let mut end = 0;
+            if let Some(regex) = &app.search {
+                for found in regex.find_iter(&line.text) {
+                    spans.push(Span::styled(
+                        line.text[end..found.start()].to_string(),
+                        style,
+                    ));
+                    spans.push(Span::styled(found.as_str().to_string(), match_style));
+                    end = found.end();
+                }
+            }
+            spans.push(Span::styled(line.text[end..].to_string(), style));
+            Line::from(spans)
+        })
+        .collect();
+    let title = format!(" demon view - {} ", app.id);
+    frame.render_widget(
+        Paragraph::new(lines).block(Block::default().borders(Borders::ALL).title(title)),
+        log_area,
+    );
+
+    let shown = app.visible.len();
+    let position = match shown {
+        0 => "no lines".to_string(),
+        _ => format!(
+            "lines {}-{} of {}",
+            app.top + 1,
+            (app.top + app.height).min(shown),
+            shown
+        ),
+    };
+    let streams = match (app.show_stdout, app.show_stderr) {
+        (true, true) => "stdout+stderr",
+        (true, false) => "stdout",
+        _ => "stderr",
+    };
+    let mode = if app.follow { "following" } else { "paused" };
+    let status = format!(" {position}  {streams}  {mode}  {}", app.message);
+    frame.render_widget(
+        Paragraph::new(status).style(Style::default().add_modifier(Modifier::REVERSED)),
+        status_area,
+    );
+
+    let help = match &app.prompt {
+        Some((Prompt::Search, input)) => format!("/{input}"),
+        Some((Prompt::Time, input)) => format!("Jump to (10m, 14:05, 2024-05-01 14:05): {input}"),
+        None => " q quit  j/k scroll  g/G top/bottom  / search  n/N next/prev  t time  o/e stdout/stderr  f follow".to_string(),
+    };
+    frame.render_widget(Paragraph::new(help), help_area);
+}
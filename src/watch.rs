@@ -0,0 +1,248 @@
+//! Change notifications for the commands that follow files (`tail -f`,
+//! `events -f`, `dev` and `list`/`status --watch`), with a polling fallback.
+//!
+//! inotify cannot always be relied on: changes made by other machines are
+//! never reported on network and shared file systems (NFS, SMB, 9p, FUSE
+//! mounts of containers and VMs), WSL1 only emulates it, and creating a watcher
+//! fails once the per-user inotify limits are used up. In those cases, and
+//! whenever `--no-notify-watch` or `watch.poll` asks for it, files are polled
+//! instead, so the commands behave the same everywhere, only with a little
+//! more latency.
+
+use crate::{format_duration, format_timestamp, growth, timeline, unix_now};
+use anyhow::Result;
+use notify::event::ModifyKind;
+use notify::{Config, Event, EventKind, PollWatcher, RecommendedWatcher, RecursiveMode, Watcher};
+use std::ffi::CString;
+use std::io::{IsTerminal, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender, TryRecvError, channel};
+use std::time::{Duration, Instant};
+
+/// How often polled files are checked
+pub(crate) const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// File system magic numbers (see statfs(2)) of file systems where inotify
+/// misses changes made elsewhere
+const UNRELIABLE_FILE_SYSTEMS: &[(i64, &str)] = &[
+    (0x6969, "NFS"),
+    (0x517b, "SMB"),
+    (0xff53_4d42, "CIFS"),
+    (0xfe53_4d42, "SMB2"),
+    (0x0102_1997, "9p"),
+    (0x6573_5546, "FUSE"),
+];
+
+/// Why files are polled instead of watched with inotify, if they are
+fn polling_reason<'a>(poll: bool, dirs: impl IntoIterator<Item = &'a Path>) -> Option<String> {
+    if poll {
+        return Some("--no-notify-watch or watch.poll is set".to_string());
+    }
+    dirs.into_iter().find_map(unreliable)
+}
+
+/// Why change notifications cannot be relied on for `dir`, if they cannot
+fn unreliable(dir: &Path) -> Option<String> {
+    // WSL2 reports "microsoft-standard", WSL1 a capitalized "Microsoft"
+    if std::fs::read_to_string("/proc/sys/kernel/osrelease")
+        .is_ok_and(|release| release.contains("Microsoft"))
+    {
+        return Some("WSL1 does not support inotify fully".to_string());
+    }
+    let path = CString::new(dir.as_os_str().as_bytes()).ok()?;
+    // SAFETY: buf is plain data written by statfs
+    let mut buf: libc::statfs = unsafe { std::mem::zeroed() };
+    // SAFETY: path is NUL-terminated and buf is valid for writes
+    if unsafe { libc::statfs(path.as_ptr(), &mut buf) } == -1 {
+        return None;
+    }
+    let magic = buf.f_type as i64 & 0xffff_ffff;
+    UNRELIABLE_FILE_SYSTEMS
+        .iter()
+        .find(|(known, _)| *known == magic)
+        .map(|(_, name)| format!("{} is on {}", dir.display(), name))
+}
+
+/// A watcher sending events for `paths` to `tx`: inotify, or notify's
+/// polling watcher when `poll` is set or inotify is not usable there
+pub(crate) fn watcher(
+    paths: &[(&Path, RecursiveMode)],
+    tx: Sender<notify::Result<Event>>,
+    poll: bool,
+) -> Result<Box<dyn Watcher>> {
+    match polling_reason(poll, paths.iter().map(|(path, _)| *path)) {
+        Some(reason) => tracing::info!("Polling for changes: {}", reason),
+        None => {
+            let watched =
+                RecommendedWatcher::new(tx.clone(), Config::default()).and_then(|mut watcher| {
+                    for (path, mode) in paths {
+                        watcher.watch(path, *mode)?;
+                    }
+                    Ok(watcher)
+                });
+            match watched {
+                Ok(watcher) => return Ok(Box::new(watcher)),
+                Err(e) => tracing::info!("inotify unavailable ({}), polling for changes", e),
+            }
+        }
+    }
+
+    let config = Config::default().with_poll_interval(POLL_INTERVAL);
+    let mut watcher = PollWatcher::new(tx, config)?;
+    for (path, mode) in paths {
+        watcher.watch(path, *mode)?;
+    }
+    Ok(Box::new(watcher))
+}
+
+/// Wakes up a follower of files in a few directories when they may have
+/// changed
+pub(crate) enum DirWatch {
+    Notify {
+        _watcher: RecommendedWatcher,
+        rx: Receiver<notify::Result<Event>>,
+    },
+    /// Check the files at every poll interval
+    Poll,
+}
+
+impl DirWatch {
+    /// Watch the files directly in `dirs`, polling if `poll` is set or
+    /// inotify is not usable in one of them
+    pub(crate) fn new(dirs: &[&Path], poll: bool) -> Self {
+        if let Some(reason) = polling_reason(poll, dirs.iter().copied()) {
+            tracing::info!("Polling for changes: {}", reason);
+            return DirWatch::Poll;
+        }
+        let (tx, rx) = channel();
+        let watched = RecommendedWatcher::new(tx, Config::default()).and_then(|mut watcher| {
+            for dir in dirs {
+                watcher.watch(dir, RecursiveMode::NonRecursive)?;
+            }
+            Ok(watcher)
+        });
+        match watched {
+            Ok(watcher) => DirWatch::Notify {
+                _watcher: watcher,
+                rx,
+            },
+            Err(e) => {
+                tracing::info!("inotify unavailable ({}), polling for changes", e);
+                DirWatch::Poll
+            }
+        }
+    }
+
+    /// Wait up to `timeout` for one of `paths` to be created or written to.
+    /// When polling this waits the whole poll interval and always returns
+    /// true, leaving it to the caller to look for changes.
+    pub(crate) fn changed(&mut self, paths: &[&Path], timeout: Duration) -> bool {
+        let DirWatch::Notify { rx, .. } = self else {
+            std::thread::sleep(POLL_INTERVAL);
+            return true;
+        };
+        // A daemon writing quickly queues an event for every write, and other
+        // readers of the files queue more, so take all the queued events at
+        // once instead of falling behind one event per call
+        let mut changed = false;
+        let mut next = rx.recv_timeout(timeout);
+        loop {
+            match next {
+                Ok(Ok(Event {
+                    kind: EventKind::Modify(_) | EventKind::Create(_),
+                    paths: written,
+                    ..
+                })) => changed |= written.iter().any(|path| paths.contains(&path.as_path())),
+                Ok(Ok(_)) => {}
+                Ok(Err(e)) => tracing::error!("Watch error: {:?}", e),
+                Err(RecvTimeoutError::Timeout) => return changed,
+                Err(RecvTimeoutError::Disconnected) => {
+                    tracing::warn!("File watcher stopped, polling for changes");
+                    *self = DirWatch::Poll;
+                    return true;
+                }
+            }
+            next = rx.try_recv().map_err(|e| match e {
+                TryRecvError::Empty => RecvTimeoutError::Timeout,
+                TryRecvError::Disconnected => RecvTimeoutError::Disconnected,
+            });
+        }
+    }
+}
+
+/// Whether an event in the root directory may change what `list` and
+/// `status` show. Log writes are left to the interval, as a chatty daemon
+/// would otherwise cause constant redraws, and so are reads, metadata changes,
+/// the root lock and the log size samples, which drawing causes itself.
+fn changes_state(event: &Event) -> bool {
+    let relevant_kind = match event.kind {
+        EventKind::Create(_) | EventKind::Remove(_) => true,
+        EventKind::Modify(ModifyKind::Metadata(_)) => false,
+        EventKind::Modify(_) => true,
+        _ => false,
+    };
+    relevant_kind
+        && event.paths.iter().any(|path| {
+            let log = matches!(
+                path.extension().and_then(|extension| extension.to_str()),
+                Some("stdout" | "stderr" | growth::EXTENSION | timeline::EXTENSION | "tmp")
+            );
+            !log && path
+                .file_name()
+                .is_some_and(|name| name != ".lock" && name != ".up.lock")
+        })
+}
+
+/// Redraw the output of `draw` every `interval` and as soon as daemons in
+/// `root_dir` change, like `watch`, until interrupted. On a terminal the
+/// screen is cleared before every redraw.
+pub(crate) fn redraw(
+    root_dir: &Path,
+    title: &str,
+    interval: Duration,
+    poll: bool,
+    mut draw: impl FnMut() -> Result<()>,
+) -> Result<()> {
+    let (tx, rx) = channel();
+    let _watcher = watcher(&[(root_dir, RecursiveMode::Recursive)], tx, poll)?;
+    let terminal = std::io::stdout().is_terminal();
+
+    loop {
+        if terminal {
+            print!("\x1b[H\x1b[2J");
+        }
+        println!(
+            "Every {}: {}    {}",
+            format_duration(interval),
+            title,
+            format_timestamp(unix_now())
+        );
+        println!();
+        draw()?;
+        if !terminal {
+            println!();
+        }
+        std::io::stdout().flush()?;
+
+        let deadline = Instant::now() + interval;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match rx.recv_timeout(remaining) {
+                Ok(Ok(event)) if changes_state(&event) => {
+                    // Let the rest of the change land before drawing it
+                    std::thread::sleep(Duration::from_millis(100));
+                    while rx.try_recv().is_ok() {}
+                    break;
+                }
+                Ok(Ok(_)) => {}
+                Ok(Err(e)) => tracing::error!("Watch error: {:?}", e),
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => {
+                    std::thread::sleep(remaining);
+                    break;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,92 @@
+//! Webhook notifications for daemon crashes and restarts.
+//!
+//! The URL comes from `run --notify-url` or the `notify.url` setting. Slack and
+//! Discord webhook URLs get the message in the shape they expect; any other URL
+//! receives the event as JSON with a human readable `text` field. Requests are
+//! sent by `curl` in the background, so a slow endpoint never holds up demon.
+
+use crate::{events, pidfile::build_file_path, unix_now};
+use anyhow::Result;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// Seconds before an unanswered request is abandoned
+const REQUEST_TIMEOUT: &str = "10";
+
+/// Build the JSON payload for an event, in the format the endpoint expects
+fn payload(url: &str, id: &str, event: events::Kind, pid: u32, detail: Option<&str>) -> String {
+    let host = hostname();
+    let mut text = format!("demon: '{id}' {event} on {host} (PID {pid})");
+    if let Some(detail) = detail {
+        text.push_str(&format!(": {detail}"));
+    }
+
+    let body = if url.contains("hooks.slack.com") {
+        serde_json::json!({ "text": text })
+    } else if url.contains("discord.com/api/webhooks")
+        || url.contains("discordapp.com/api/webhooks")
+    {
+        serde_json::json!({ "content": text })
+    } else {
+        serde_json::json!({
+            "ts": unix_now(),
+            "id": id,
+            "event": event,
+            "pid": pid,
+            "detail": detail,
+            "host": host,
+            "text": text,
+        })
+    };
+    body.to_string()
+}
+
+/// POST an event to a webhook without waiting for the response. Failures of
+/// the request are appended to the daemon's stderr log by curl.
+pub(crate) fn send(
+    url: &str,
+    root_dir: &Path,
+    id: &str,
+    event: events::Kind,
+    pid: u32,
+    detail: Option<&str>,
+) -> Result<()> {
+    post(url, root_dir, id, &payload(url, id, event, pid, detail))
+}
+
+/// POST a JSON body without waiting for the response, with failures appended
+/// to the daemon's stderr log
+pub(crate) fn post(url: &str, root_dir: &Path, id: &str, body: &str) -> Result<()> {
+    let log = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(build_file_path(root_dir, id, "stderr"))?;
+
+    let mut child = Command::new("curl")
+        .args(["--silent", "--show-error", "--fail", "--max-time"])
+        .arg(REQUEST_TIMEOUT)
+        .args(["--header", "Content-Type: application/json"])
+        .args(["--data-binary", "@-", "--output", "/dev/null"])
+        .arg(url)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::from(log))
+        .spawn()
+        .map_err(|e| anyhow::anyhow!("Failed to run curl: {}", e))?;
+
+    // Dropping stdin after the write closes it, ending the request body
+    child
+        .stdin
+        .take()
+        .expect("stdin is piped")
+        .write_all(body.as_bytes())?;
+    Ok(())
+}
+
+pub(crate) fn hostname() -> String {
+    std::fs::read_to_string("/proc/sys/kernel/hostname")
+        .map(|name| name.trim().to_string())
+        .unwrap_or_else(|_| "unknown host".to_string())
+}
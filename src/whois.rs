@@ -0,0 +1,208 @@
+//! `demon whois`: find the daemon a PID, port or file belongs to.
+//!
+//! Every running daemon is searched together with its descendants and its
+//! supervisor. Ports are matched by looking up the inodes of the listening
+//! sockets in `/proc/net` and then the `socket:[<inode>]` descriptors of each
+//! process, files by the targets of `/proc/<pid>/fd`, the working directory
+//! and the executable, so the answer is what `lsof` and `ps` would give,
+//! limited to managed processes.
+
+use crate::{
+    pidfile::{DaemonMeta, PidFile, RootLock, daemon_ids},
+    proc::{proc_cmdline, process_descendants},
+    status::is_daemon_running,
+};
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+
+/// What `demon whois` looks for
+#[derive(Debug, Clone)]
+pub(crate) enum Query {
+    Pid(u32),
+    Port(u16),
+    File(PathBuf),
+}
+
+/// How a process relates to a daemon
+#[derive(Debug, Clone, Copy)]
+enum Role {
+    Daemon,
+    /// Started by the daemon with this PID, directly or not
+    Descendant(u32),
+    Supervisor,
+}
+
+/// A process of a running daemon
+struct Process {
+    id: String,
+    pid: u32,
+    role: Role,
+}
+
+/// The processes of every running daemon of a root directory
+fn managed_processes(root_dir: &Path) -> Result<Vec<Process>> {
+    let _lock = RootLock::shared(root_dir)?;
+    let ids = daemon_ids(root_dir)?;
+
+    let mut processes = Vec::new();
+    for id in &ids {
+        let Ok(data) = PidFile::read(root_dir, id) else {
+            continue;
+        };
+        if !is_daemon_running(root_dir, id, data.pid) {
+            continue;
+        }
+        let process = |pid, role| Process {
+            id: id.to_string(),
+            pid,
+            role,
+        };
+        processes.push(process(data.pid, Role::Daemon));
+        if let Some(supervisor_pid) = DaemonMeta::read(root_dir, id).supervisor_pid {
+            processes.push(process(supervisor_pid, Role::Supervisor));
+        }
+        for pid in process_descendants(data.pid) {
+            processes.push(process(pid, Role::Descendant(data.pid)));
+        }
+    }
+    Ok(processes)
+}
+
+/// Open file descriptors of a process and what they point to
+fn descriptors(pid: u32) -> Vec<(u32, PathBuf)> {
+    let Ok(entries) = std::fs::read_dir(format!("/proc/{pid}/fd")) else {
+        return Vec::new();
+    };
+    let mut fds: Vec<(u32, PathBuf)> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let fd = entry.file_name().to_str()?.parse().ok()?;
+            Some((fd, std::fs::read_link(entry.path()).ok()?))
+        })
+        .collect();
+    fds.sort_unstable_by_key(|(fd, _)| *fd);
+    fds
+}
+
+/// Inodes of the TCP sockets listening on `port` and the UDP sockets bound
+/// to it, with their protocol
+fn port_sockets(port: u16) -> Vec<(u64, &'static str)> {
+    let mut sockets = Vec::new();
+    for (table, protocol) in [
+        ("tcp", "tcp"),
+        ("tcp6", "tcp"),
+        ("udp", "udp"),
+        ("udp6", "udp"),
+    ] {
+        let Ok(contents) = std::fs::read_to_string(format!("/proc/net/{table}")) else {
+            continue;
+        };
+        for line in contents.lines().skip(1) {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let (Some(local), Some(state), Some(inode)) =
+                (fields.get(1), fields.get(3), fields.get(9))
+            else {
+                continue;
+            };
+            let local_port = local
+                .rsplit_once(':')
+                .and_then(|(_, port)| u16::from_str_radix(port, 16).ok());
+            // 0A is TCP_LISTEN
+            let listening = protocol == "udp" || *state == "0A";
+            if local_port == Some(port)
+                && listening
+                && let Ok(inode) = inode.parse()
+                && inode != 0
+            {
+                sockets.push((inode, protocol));
+            }
+        }
+    }
+    sockets
+}
+
+/// What about a process matches the query, if anything does
+fn matches(query: &Query, pid: u32, sockets: &[(u64, &str)]) -> Option<String> {
+    match query {
+        Query::Pid(wanted) => (pid == *wanted).then(String::new),
+        Query::Port(port) => descriptors(pid).into_iter().find_map(|(_, target)| {
+            let inode: u64 = target
+                .to_str()?
+                .strip_prefix("socket:[")?
+                .strip_suffix(']')?
+                .parse()
+                .ok()?;
+            let (_, protocol) = sockets.iter().find(|(socket, _)| *socket == inode)?;
+            Some(format!(", listening on {protocol} port {port}"))
+        }),
+        Query::File(path) => {
+            let within = |target: &Path| target == path || target.starts_with(path);
+            let mut found: Vec<String> = descriptors(pid)
+                .into_iter()
+                .filter(|(_, target)| within(target))
+                .map(|(fd, target)| match target == *path {
+                    true => format!("fd {fd}"),
+                    false => format!("fd {fd} ({})", target.display()),
+                })
+                .collect();
+            for (link, name) in [("cwd", "working directory"), ("exe", "executable")] {
+                if std::fs::read_link(format!("/proc/{pid}/{link}"))
+                    .is_ok_and(|target| within(&target))
+                {
+                    found.push(name.to_string());
+                }
+            }
+            (!found.is_empty()).then(|| format!(", {}", found.join(", ")))
+        }
+    }
+}
+
+/// Print the daemons whose processes match `query`, failing if none does
+pub(crate) fn whois(root_dir: &Path, query: &Query) -> Result<()> {
+    let query = match query {
+        Query::File(path) => Query::File(
+            std::fs::canonicalize(path)
+                .map_err(|e| anyhow::anyhow!("Cannot resolve {}: {}", path.display(), e))?,
+        ),
+        query => query.clone(),
+    };
+    let sockets = match query {
+        Query::Port(port) => port_sockets(port),
+        _ => Vec::new(),
+    };
+
+    let mut found = false;
+    for process in managed_processes(root_dir)? {
+        let Some(detail) = matches(&query, process.pid, &sockets) else {
+            continue;
+        };
+        found = true;
+        let role = match process.role {
+            Role::Daemon => "the daemon".to_string(),
+            Role::Descendant(daemon) => format!("started by daemon PID {daemon}"),
+            Role::Supervisor => "its supervisor".to_string(),
+        };
+        println!(
+            "{}: PID {}, {}{}: {}",
+            process.id,
+            process.pid,
+            role,
+            detail,
+            proc_cmdline(process.pid).unwrap_or_default()
+        );
+    }
+
+    if found {
+        return Ok(());
+    }
+    let what = match &query {
+        Query::Pid(pid) => format!("PID {pid}"),
+        Query::Port(port) => format!("port {port}"),
+        Query::File(path) => path.display().to_string(),
+    };
+    Err(anyhow::anyhow!(
+        "No running daemon in {} owns {}",
+        root_dir.display(),
+        what
+    ))
+}
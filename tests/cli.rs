@@ -8,7 +8,7 @@ use tempfile::TempDir;
 #[test]
 fn test_help_output() {
     let mut cmd = Command::cargo_bin("demon").unwrap();
-    cmd.args(&["--help"])
+    cmd.args(["--help"])
         .assert()
         .success()
         .stdout(predicate::str::contains("daemon process management"))
@@ -25,7 +25,7 @@ fn test_help_output() {
 #[test]
 fn test_version_output() {
     let mut cmd = Command::cargo_bin("demon").unwrap();
-    cmd.args(&["--version"])
+    cmd.args(["--version"])
         .assert()
         .success()
         .stdout(predicate::str::contains("demon 0.1.0"));
@@ -37,7 +37,7 @@ fn test_run_missing_command() {
 
     let mut cmd = Command::cargo_bin("demon").unwrap();
     cmd.env("DEMON_ROOT_DIR", temp_dir.path())
-        .args(&["run", "test"])
+        .args(["run", "test"])
         .assert()
         .failure()
         .stderr(predicate::str::contains("Command cannot be empty"));
@@ -49,21 +49,21 @@ fn test_run_creates_files() {
 
     let mut cmd = Command::cargo_bin("demon").unwrap();
     cmd.env("DEMON_ROOT_DIR", temp_dir.path())
-        .args(&["run", "test", "echo", "hello"])
+        .args(["run", "test", "echo", "hello"])
         .assert()
         .success()
         .stdout(predicate::str::contains("Started daemon 'test'"));
 
     // Verify files were created
-    assert!(temp_dir.path().join("test.pid").exists());
-    assert!(temp_dir.path().join("test.stdout").exists());
-    assert!(temp_dir.path().join("test.stderr").exists());
+    assert!(temp_dir.path().join("test/test.pid").exists());
+    assert!(temp_dir.path().join("test/test.stdout").exists());
+    assert!(temp_dir.path().join("test/test.stderr").exists());
 
     // Give the process a moment to complete
     std::thread::sleep(Duration::from_millis(100));
 
     // Check that stdout contains our output
-    let stdout_content = fs::read_to_string(temp_dir.path().join("test.stdout")).unwrap();
+    let stdout_content = fs::read_to_string(temp_dir.path().join("test/test.stdout")).unwrap();
     assert_eq!(stdout_content.trim(), "hello");
 }
 
@@ -74,14 +74,14 @@ fn test_run_duplicate_process() {
     // Start a long-running process
     let mut cmd = Command::cargo_bin("demon").unwrap();
     cmd.env("DEMON_ROOT_DIR", temp_dir.path())
-        .args(&["run", "long", "sleep", "30"])
+        .args(["run", "long", "sleep", "30"])
         .assert()
         .success();
 
     // Try to start another with the same ID
     let mut cmd = Command::cargo_bin("demon").unwrap();
     cmd.env("DEMON_ROOT_DIR", temp_dir.path())
-        .args(&["run", "long", "sleep", "5"])
+        .args(["run", "long", "sleep", "5"])
         .assert()
         .failure()
         .stderr(predicate::str::contains("already running"));
@@ -89,7 +89,7 @@ fn test_run_duplicate_process() {
     // Clean up the running process
     let mut cmd = Command::cargo_bin("demon").unwrap();
     cmd.env("DEMON_ROOT_DIR", temp_dir.path())
-        .args(&["stop", "long"])
+        .args(["stop", "long"])
         .assert()
         .success();
 }
@@ -100,7 +100,7 @@ fn test_list_empty() {
 
     let mut cmd = Command::cargo_bin("demon").unwrap();
     cmd.env("DEMON_ROOT_DIR", temp_dir.path())
-        .args(&["list"])
+        .args(["list"])
         .assert()
         .success()
         .stdout(predicate::str::contains("ID"))
@@ -116,14 +116,14 @@ fn test_list_with_processes() {
     // Start a process
     let mut cmd = Command::cargo_bin("demon").unwrap();
     cmd.env("DEMON_ROOT_DIR", temp_dir.path())
-        .args(&["run", "test", "echo", "done"])
+        .args(["run", "test", "echo", "done"])
         .assert()
         .success();
 
     // List processes
     let mut cmd = Command::cargo_bin("demon").unwrap();
     cmd.env("DEMON_ROOT_DIR", temp_dir.path())
-        .args(&["list"])
+        .args(["list"])
         .assert()
         .success()
         .stdout(predicate::str::contains("test"))
@@ -131,360 +131,3007 @@ fn test_list_with_processes() {
 }
 
 #[test]
-fn test_cat_output() {
+fn test_list_filter_sort_and_columns() {
     let temp_dir = TempDir::new().unwrap();
+    let demon = || {
+        let mut cmd = Command::cargo_bin("demon").unwrap();
+        cmd.env("DEMON_ROOT_DIR", temp_dir.path());
+        cmd
+    };
 
-    // Create a process with output
-    let mut cmd = Command::cargo_bin("demon").unwrap();
-    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
-        .args(&[
-            "run",
-            "test",
-            "--",
-            "sh",
-            "-c",
-            "echo 'stdout line'; echo 'stderr line' >&2",
-        ])
+    demon()
+        .args(["run", "web-b", "sleep", "30"])
+        .assert()
+        .success();
+    demon()
+        .args(["run", "web-a", "sleep", "30"])
+        .assert()
+        .success();
+    demon()
+        .args(["run", "db", "sleep", "30"])
         .assert()
         .success();
+    demon().args(["run", "job", "true"]).assert().success();
+    std::thread::sleep(Duration::from_millis(300));
 
-    // Cat the output
-    let mut cmd = Command::cargo_bin("demon").unwrap();
-    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
-        .args(&["cat", "test"])
+    demon()
+        .args(["list", "--status", "running", "--filter", "web-*", "-q"])
         .assert()
         .success()
-        .stdout(predicate::str::contains("stdout line"))
-        .stdout(predicate::str::contains("stderr line"));
-}
-
-#[test]
-fn test_cat_stdout_only() {
-    let temp_dir = TempDir::new().unwrap();
+        .stdout(
+            predicate::str::is_match("^web-a:[0-9]+:RUNNING\nweb-b:[0-9]+:RUNNING\n$").unwrap(),
+        );
 
-    // Create a process with output
-    let mut cmd = Command::cargo_bin("demon").unwrap();
-    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
-        .args(&[
-            "run",
-            "test",
-            "--",
-            "sh",
-            "-c",
-            "echo 'stdout line'; echo 'stderr line' >&2",
+    // web-b was started first, so it has the lowest PID
+    demon()
+        .args([
+            "list",
+            "--filter",
+            "web-*",
+            "--sort",
+            "pid",
+            "--columns",
+            "id",
+            "-q",
         ])
         .assert()
-        .success();
+        .success()
+        .stdout("web-b\nweb-a\n");
 
-    // Cat only stdout
-    let mut cmd = Command::cargo_bin("demon").unwrap();
-    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
-        .args(&["cat", "test", "--stdout"])
+    demon()
+        .args(["list", "--status", "dead", "--columns", "id,status", "-q"])
         .assert()
         .success()
-        .stdout(predicate::str::contains("stdout line"))
-        .stdout(predicate::str::contains("stderr line").not());
+        .stdout("job:DEAD\n");
+
+    demon()
+        .args(["list", "--columns", "id,pid,uptime", "--flat"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("UPTIME"))
+        .stdout(predicate::str::contains("COMMAND").not())
+        .stdout(predicate::str::contains("sleep").not());
+
+    demon()
+        .args(["list", "--filter", "["])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Invalid --filter pattern"));
+
+    for id in ["web-a", "web-b", "db"] {
+        demon().args(["stop", id]).assert().success();
+    }
 }
 
 #[test]
-fn test_status_nonexistent() {
+fn test_run_reports_exec_failures() {
+    use std::os::unix::fs::PermissionsExt;
+
     let temp_dir = TempDir::new().unwrap();
+    let script = temp_dir.path().join("script.sh");
+    fs::write(&script, "echo hello\n").unwrap();
+    fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+
+    for supervise in [false, true] {
+        let mut cmd = Command::cargo_bin("demon").unwrap();
+        cmd.env("DEMON_ROOT_DIR", temp_dir.path()).arg("run");
+        if supervise {
+            cmd.arg("--supervise");
+        }
+        cmd.arg("script")
+            .arg(&script)
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("has no #! line"));
+    }
 
     let mut cmd = Command::cargo_bin("demon").unwrap();
     cmd.env("DEMON_ROOT_DIR", temp_dir.path())
-        .args(&["status", "nonexistent"])
+        .args(["run", "missing", "no-such-program-demon-test"])
         .assert()
-        .success()
-        .stdout(predicate::str::contains("NOT FOUND"));
+        .failure()
+        .stderr(predicate::str::contains("not found in PATH"));
+    assert!(!temp_dir.path().join("script/script.pid").exists());
 }
 
 #[test]
-fn test_status_dead_process() {
+fn test_input_writes_to_stdin_fifo() {
     let temp_dir = TempDir::new().unwrap();
 
-    // Create a short-lived process
     let mut cmd = Command::cargo_bin("demon").unwrap();
     cmd.env("DEMON_ROOT_DIR", temp_dir.path())
-        .args(&["run", "dead", "echo", "hello"])
+        .args(["run", "--stdin", "fifo", "repl", "--"])
+        .args(["sh", "-c", "while read line; do echo got:$line; done"])
         .assert()
         .success();
 
-    // Check its status (should be dead)
     let mut cmd = Command::cargo_bin("demon").unwrap();
     cmd.env("DEMON_ROOT_DIR", temp_dir.path())
-        .args(&["status", "dead"])
+        .args(["input", "repl", "hello", "world"])
         .assert()
-        .success()
-        .stdout(predicate::str::contains("DEAD"));
-}
+        .success();
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["input", "repl"])
+        .write_stdin("piped\n")
+        .assert()
+        .success();
 
-#[test]
-fn test_stop_nonexistent() {
-    let temp_dir = TempDir::new().unwrap();
+    std::thread::sleep(Duration::from_millis(300));
+    let log = fs::read_to_string(temp_dir.path().join("repl/repl.stdout")).unwrap();
+    assert_eq!(log, "got:hello world\ngot:piped\n");
 
     let mut cmd = Command::cargo_bin("demon").unwrap();
     cmd.env("DEMON_ROOT_DIR", temp_dir.path())
-        .args(&["stop", "nonexistent"])
+        .args(["stop", "repl"])
         .assert()
-        .success()
-        .stdout(predicate::str::contains("not running"));
+        .success();
+    assert!(!temp_dir.path().join("repl/repl.stdin").exists());
+
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["run", "plain", "sleep", "30"])
+        .assert()
+        .success();
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["input", "plain", "hello"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("--stdin fifo"));
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["stop", "plain"])
+        .assert()
+        .success();
 }
 
 #[test]
-fn test_stop_process() {
+fn test_run_stdin_file() {
     let temp_dir = TempDir::new().unwrap();
+    let input = temp_dir.path().join("dump.sql");
+    fs::write(&input, "one\ntwo\n").unwrap();
 
-    // Start a long-running process
     let mut cmd = Command::cargo_bin("demon").unwrap();
     cmd.env("DEMON_ROOT_DIR", temp_dir.path())
-        .args(&["run", "long", "sleep", "10"])
+        .args(["run", "--supervise", "restore", "--stdin-file"])
+        .arg(&input)
+        .args(["--", "wc", "-l"])
         .assert()
         .success();
 
-    // Stop it
+    std::thread::sleep(Duration::from_millis(300));
+    let log = fs::read_to_string(temp_dir.path().join("restore/restore.stdout")).unwrap();
+    assert_eq!(log.trim(), "2");
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["status", "restore"])
+        .assert()
+        .code(3)
+        .stdout(predicate::str::contains(format!(
+            "Stdin: {} (file)",
+            input.display()
+        )));
+
     let mut cmd = Command::cargo_bin("demon").unwrap();
     cmd.env("DEMON_ROOT_DIR", temp_dir.path())
-        .args(&["stop", "long"])
+        .args([
+            "run",
+            "missing",
+            "--stdin-file",
+            "/nonexistent/input",
+            "cat",
+        ])
         .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "Failed to open /nonexistent/input",
+        ));
+}
+
+#[test]
+fn test_singleton_machine() {
+    let runtime_dir = TempDir::new().unwrap();
+    let first = TempDir::new().unwrap();
+    let second = TempDir::new().unwrap();
+    let demon = |root: &TempDir, args: &[&str]| {
+        let mut cmd = Command::cargo_bin("demon").unwrap();
+        cmd.env("DEMON_ROOT_DIR", root.path())
+            .env("XDG_RUNTIME_DIR", runtime_dir.path())
+            .args(args);
+        cmd.assert()
+    };
+    let run = ["run", "db", "--singleton", "machine", "--", "sleep", "30"];
+
+    demon(&first, &run).success();
+    let owner = first.path().canonicalize().unwrap();
+    demon(&second, &run)
+        .failure()
+        .stderr(predicate::str::contains(format!(
+            "already running from {}",
+            owner.display()
+        )));
+    demon(&second, &["status", "db"])
+        .code(4)
+        .stdout(predicate::str::contains(format!(
+            "Singleton: running from {}",
+            owner.display()
+        )));
+    demon(&first, &["status", "db"])
         .success()
-        .stdout(predicate::str::contains("terminated gracefully"));
+        .stdout(predicate::str::contains("Singleton: machine-wide"));
 
-    // Verify PID file is gone
-    assert!(!temp_dir.path().join("long.pid").exists());
+    // Once the owner's run is gone, any root directory may start it
+    demon(&first, &["stop", "db"]).success();
+    demon(&second, &run).success();
+    demon(&second, &["stop", "db"]).success();
 }
 
 #[test]
-fn test_clean_no_orphans() {
+fn test_list_snapshot_under_churn() {
     let temp_dir = TempDir::new().unwrap();
+    let root = temp_dir.path().to_path_buf();
+    let demon = |root: &PathBuf| {
+        let mut cmd = Command::cargo_bin("demon").unwrap();
+        cmd.env("DEMON_ROOT_DIR", root);
+        cmd
+    };
+    for id in ["a", "c", "e"] {
+        demon(&root)
+            .args(["run", id, "sleep", "30"])
+            .assert()
+            .success();
+    }
 
-    let mut cmd = Command::cargo_bin("demon").unwrap();
-    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
-        .args(&["clean"])
-        .assert()
-        .success()
-        .stdout(predicate::str::contains("No orphaned files found"));
+    // Daemons come and go while listing
+    let churn = {
+        let root = root.clone();
+        std::thread::spawn(move || {
+            for _ in 0..5 {
+                for id in ["b", "d"] {
+                    demon(&root)
+                        .args(["run", id, "sleep", "30"])
+                        .assert()
+                        .success();
+                }
+                for id in ["b", "d"] {
+                    demon(&root).args(["stop", id]).assert().success();
+                }
+            }
+        })
+    };
+    while !churn.is_finished() {
+        let output = demon(&root)
+            .args(["list", "--format", "json"])
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        let daemons: Vec<serde_json::Value> = serde_json::from_slice(&output.stdout).unwrap();
+        let ids: Vec<&str> = daemons.iter().map(|d| d["id"].as_str().unwrap()).collect();
+        let mut sorted = ids.clone();
+        sorted.sort();
+        sorted.dedup();
+        assert_eq!(ids, sorted);
+        for id in ["a", "c", "e"] {
+            assert!(ids.contains(&id), "{id} missing from {ids:?}");
+        }
+        let generated_at = daemons[0]["generated_at"].as_u64().unwrap();
+        assert!(daemons.iter().all(|d| d["generated_at"] == generated_at));
+    }
+    churn.join().unwrap();
+
+    for id in ["a", "c", "e"] {
+        demon(&root).args(["stop", id]).assert().success();
+    }
 }
 
 #[test]
-fn test_clean_with_orphans() {
+fn test_list_aligns_by_display_width() {
     let temp_dir = TempDir::new().unwrap();
+    let long_id = "abcdefghijklmnopqrstuvwxyz";
+    for id in ["日本語", long_id] {
+        let mut cmd = Command::cargo_bin("demon").unwrap();
+        cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+            .args(["run", id, "sleep", "30"])
+            .assert()
+            .success();
+    }
 
-    // Create a dead process
+    // Six columns wide, padded to the cut ID's ten
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    let output = cmd
+        .env("DEMON_ROOT_DIR", temp_dir.path())
+        .env("DEMON_TABLE_MAX_WIDTH", "10")
+        .args(["list", "--flat"])
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let aligned = |prefix: &str| {
+        stdout.lines().any(|line| {
+            line.strip_prefix(prefix)
+                .is_some_and(|rest| rest.starts_with(|c: char| c.is_ascii_digit()))
+        })
+    };
+    assert!(stdout.starts_with("ID         PID "), "{stdout}");
+    assert!(aligned("日本語     "), "{stdout}");
+    assert!(aligned("abcdefghi… "), "{stdout}");
+    assert!(!stdout.contains(long_id));
+
+    // Machine output is never cut
     let mut cmd = Command::cargo_bin("demon").unwrap();
     cmd.env("DEMON_ROOT_DIR", temp_dir.path())
-        .args(&["run", "dead", "echo", "hello"])
+        .env("DEMON_TABLE_MAX_WIDTH", "10")
+        .args(["list", "--quiet"])
         .assert()
-        .success();
+        .success()
+        .stdout(predicate::str::contains(format!("{long_id}:")));
 
-    // Wait for process to complete
-    std::thread::sleep(Duration::from_millis(100));
+    for id in ["日本語", long_id] {
+        let mut cmd = Command::cargo_bin("demon").unwrap();
+        cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+            .args(["stop", id])
+            .assert()
+            .success();
+    }
+}
 
-    // Verify files exist before clean
-    assert!(temp_dir.path().join("dead.pid").exists());
-    assert!(temp_dir.path().join("dead.stdout").exists());
-    assert!(temp_dir.path().join("dead.stderr").exists());
+#[test]
+fn test_list_all_roots_flags_duplicates() {
+    let runtime_dir = TempDir::new().unwrap();
+    let first = TempDir::new().unwrap();
+    let second = TempDir::new().unwrap();
+    let demon = |root: &TempDir, args: &[&str]| {
+        let mut cmd = Command::cargo_bin("demon").unwrap();
+        cmd.env("DEMON_ROOT_DIR", root.path())
+            .env("XDG_RUNTIME_DIR", runtime_dir.path())
+            .args(args);
+        cmd.assert()
+    };
 
-    // Clean up orphaned files
-    let mut cmd = Command::cargo_bin("demon").unwrap();
-    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
-        .args(&["clean"])
-        .assert()
+    demon(&first, &["run", "db", "--", "sleep", "30"]).success();
+    demon(&first, &["run", "web", "--", "sleep", "31"]).success();
+    demon(&second, &["run", "db", "--", "sleep", "32"]).success();
+
+    let first_root = first.path().canonicalize().unwrap();
+    let second_root = second.path().canonicalize().unwrap();
+    demon(&second, &["list", "--all-roots"])
         .success()
-        .stdout(predicate::str::contains("Cleaned up"))
-        .stdout(predicate::str::contains("orphaned"));
+        .stdout(predicate::str::contains("db *"))
+        .stdout(predicate::str::contains("web *").not())
+        .stdout(predicate::str::contains(format!(
+            "'db' in {}",
+            first_root.display()
+        )));
 
-    // Verify files are gone
-    assert!(!temp_dir.path().join("dead.pid").exists());
-    assert!(!temp_dir.path().join("dead.stdout").exists());
-    assert!(!temp_dir.path().join("dead.stderr").exists());
+    let output = Command::cargo_bin("demon")
+        .unwrap()
+        .env("DEMON_ROOT_DIR", first.path())
+        .env("XDG_RUNTIME_DIR", runtime_dir.path())
+        .args(["list", "--all-roots", "--format", "json"])
+        .output()
+        .unwrap();
+    let records: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let records = records.as_array().unwrap();
+    assert_eq!(records.len(), 3);
+    let db = records
+        .iter()
+        .find(|record| record["id"] == "db" && record["root"] == first_root.to_str().unwrap())
+        .unwrap();
+    assert_eq!(
+        db["duplicate_of"][0],
+        format!("db@{}", second_root.display())
+    );
+
+    demon(&first, &["stop", "db"]).success();
+    demon(&first, &["stop", "web"]).success();
+    demon(&second, &["stop", "db"]).success();
 }
 
 #[test]
-fn test_clean_removes_stdout_stderr_files() {
+fn test_attach_pty_daemon() {
     let temp_dir = TempDir::new().unwrap();
 
-    // Create a process that outputs to both stdout and stderr
     let mut cmd = Command::cargo_bin("demon").unwrap();
     cmd.env("DEMON_ROOT_DIR", temp_dir.path())
-        .args(&[
-            "run",
-            "test_output",
-            "--",
+        .args(["run", "--pty", "console", "--"])
+        .args([
             "sh",
             "-c",
-            "echo 'stdout content'; echo 'stderr content' >&2",
+            "read line; echo got:$line; [ -t 0 ] && echo tty",
         ])
         .assert()
         .success();
 
-    // Wait for process to complete
-    std::thread::sleep(Duration::from_millis(100));
-
-    // Verify all files exist and have content
-    assert!(temp_dir.path().join("test_output.pid").exists());
-    assert!(temp_dir.path().join("test_output.stdout").exists());
-    assert!(temp_dir.path().join("test_output.stderr").exists());
-
-    let stdout_content = fs::read_to_string(temp_dir.path().join("test_output.stdout")).unwrap();
-    let stderr_content = fs::read_to_string(temp_dir.path().join("test_output.stderr")).unwrap();
-    assert!(stdout_content.contains("stdout content"));
-    assert!(stderr_content.contains("stderr content"));
-
-    // Clean up orphaned files
     let mut cmd = Command::cargo_bin("demon").unwrap();
     cmd.env("DEMON_ROOT_DIR", temp_dir.path())
-        .args(&["clean"])
+        .args(["attach", "console"])
+        .write_stdin("hello\n")
+        .timeout(std::time::Duration::from_secs(10))
         .assert()
         .success()
-        .stdout(predicate::str::contains("Cleaned up"))
-        .stdout(predicate::str::contains("orphaned"));
+        .stdout(predicate::str::contains("got:hello"));
+    let log = fs::read_to_string(temp_dir.path().join("console/console.stdout")).unwrap();
+    assert!(log.contains("got:hello"));
+    assert!(log.contains("tty"));
+    assert!(!temp_dir.path().join("console/console.tty.sock").exists());
 
-    // Verify ALL files are gone, not just the PID file
-    assert!(!temp_dir.path().join("test_output.pid").exists());
-    assert!(!temp_dir.path().join("test_output.stdout").exists());
-    assert!(!temp_dir.path().join("test_output.stderr").exists());
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["run", "plain", "sleep", "30"])
+        .assert()
+        .success();
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["attach", "plain"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("--pty"));
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["stop", "plain"])
+        .assert()
+        .success();
 }
 
 #[test]
-fn test_default_demon_directory_creation() {
-    // This test verifies that when no --root-dir is specified,
-    // the system creates and uses a .demon subdirectory in the git root
-
-    // Create a temporary git repo
+fn test_pty_output_is_line_buffered() {
     let temp_dir = TempDir::new().unwrap();
-    let git_dir = temp_dir.path().join(".git");
-    std::fs::create_dir(&git_dir).unwrap();
 
-    // Change to the temp directory
-    let original_dir = std::env::current_dir().unwrap();
-    std::env::set_current_dir(temp_dir.path()).unwrap();
+    // Python block-buffers stdout unless it is a terminal
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["run", "--pty", "buffered", "--"])
+        .args([
+            "python3",
+            "-c",
+            "import time; print('ready'); time.sleep(30)",
+        ])
+        .assert()
+        .success();
 
-    // Restore directory when done
-    struct DirGuard(PathBuf);
-    impl Drop for DirGuard {
-        fn drop(&mut self) {
-            let _ = std::env::set_current_dir(&self.0);
+    let log = temp_dir.path().join("buffered/buffered.stdout");
+    let mut contents = String::new();
+    for _ in 0..50 {
+        contents = fs::read_to_string(&log).unwrap();
+        if contents.contains("ready") {
+            break;
         }
+        std::thread::sleep(Duration::from_millis(100));
     }
-    let _guard = DirGuard(original_dir);
+    assert_eq!(contents, "ready\n");
 
-    // Run a command without --root-dir to test default behavior
     let mut cmd = Command::cargo_bin("demon").unwrap();
-    cmd.args(&["run", "default_test", "echo", "hello"])
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["stop", "buffered"])
         .assert()
-        .success()
-        .stdout(predicate::str::contains("Started daemon 'default_test'"));
-
-    // Wait for process to complete
-    std::thread::sleep(Duration::from_millis(100));
-
-    // Verify that .demon directory was created and files are in it
-    let demon_dir = temp_dir.path().join(".demon");
-    assert!(demon_dir.exists());
-    assert!(demon_dir.is_dir());
-    assert!(demon_dir.join("default_test.pid").exists());
-    assert!(demon_dir.join("default_test.stdout").exists());
-    assert!(demon_dir.join("default_test.stderr").exists());
-
-    // Verify the stdout content
-    let stdout_content = fs::read_to_string(demon_dir.join("default_test.stdout")).unwrap();
-    assert_eq!(stdout_content.trim(), "hello");
+        .success();
 }
 
 #[test]
-fn test_run_with_complex_command() {
+fn test_sd_notify_readiness_and_watchdog() {
     let temp_dir = TempDir::new().unwrap();
 
+    // Ready after a moment, then never pings the watchdog
+    let script = "import os, socket, time\n\
+                  time.sleep(0.5)\n\
+                  sock = socket.socket(socket.AF_UNIX, socket.SOCK_DGRAM)\n\
+                  sock.sendto(b'READY=1\\nSTATUS=serving', os.environ['NOTIFY_SOCKET'])\n\
+                  time.sleep(30)\n";
+    let start = std::time::Instant::now();
     let mut cmd = Command::cargo_bin("demon").unwrap();
     cmd.env("DEMON_ROOT_DIR", temp_dir.path())
-        .args(&[
+        .args([
             "run",
-            "complex",
+            "--watchdog",
+            "2s",
+            "api",
             "--",
-            "sh",
+            "python3",
             "-c",
-            "for i in 1 2 3; do echo \"line $i\"; done",
+            script,
         ])
         .assert()
         .success();
+    assert!(start.elapsed() >= Duration::from_millis(500));
 
-    // Give the process a moment to complete
-    std::thread::sleep(Duration::from_millis(100));
-
-    // Check the output contains all lines
-    let stdout_content = fs::read_to_string(temp_dir.path().join("complex.stdout")).unwrap();
-    assert!(stdout_content.contains("line 1"));
-    assert!(stdout_content.contains("line 2"));
-    assert!(stdout_content.contains("line 3"));
-}
-
-#[test]
-fn test_timeout_configuration() {
-    let temp_dir = TempDir::new().unwrap();
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["status", "api"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Ready: yes"))
+        .stdout(predicate::str::contains("Notify status: serving"));
 
-    // Start a process
     let mut cmd = Command::cargo_bin("demon").unwrap();
     cmd.env("DEMON_ROOT_DIR", temp_dir.path())
-        .args(&["run", "timeout-test", "sleep", "5"])
+        .args(["wait", "api", "--timeout", "10"])
         .assert()
         .success();
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["status", "api"])
+        .assert()
+        .code(3)
+        .stdout(predicate::str::contains("Exit signal: 6"));
+    let events = fs::read_to_string(temp_dir.path().join("events.jsonl")).unwrap();
+    assert!(events.contains("\"ready\""));
+    assert!(events.contains("watchdog timeout"));
 
-    // Stop with custom timeout (should work normally since sleep responds to SIGTERM)
     let mut cmd = Command::cargo_bin("demon").unwrap();
     cmd.env("DEMON_ROOT_DIR", temp_dir.path())
-        .args(&["stop", "timeout-test", "--timeout", "2"])
+        .args(["run", "--sd-notify", "broken", "--", "sh", "-c", "exit 3"])
         .assert()
-        .success()
-        .stdout(predicate::str::contains("terminated gracefully"));
+        .failure()
+        .stderr(predicate::str::contains("exited before it was ready"));
 }
 
 #[test]
-fn test_invalid_process_id() {
+fn test_run_max_runtime() {
     let temp_dir = TempDir::new().unwrap();
 
-    // Create an invalid PID file
-    fs::write(temp_dir.path().join("invalid.pid"), "not-a-number").unwrap();
-
-    // Status should handle it gracefully
+    // Ignores SIGTERM, so the supervisor has to follow up with SIGKILL
     let mut cmd = Command::cargo_bin("demon").unwrap();
     cmd.env("DEMON_ROOT_DIR", temp_dir.path())
-        .args(&["status", "invalid"])
+        .env("DEMON_STOP_TIMEOUT", "1")
+        .args(["run", "job", "--max-runtime", "1s", "--", "sh", "-c"])
+        .arg("trap 'echo got term' TERM; while true; do sleep 0.1; done")
         .assert()
-        .success()
-        .stdout(predicate::str::contains("ERROR"));
+        .success();
 
-    // Clean should remove it
     let mut cmd = Command::cargo_bin("demon").unwrap();
     cmd.env("DEMON_ROOT_DIR", temp_dir.path())
-        .args(&["clean"])
+        .args(["status", "job"])
         .assert()
         .success()
-        .stdout(predicate::str::contains("invalid PID file"));
-}
+        .stdout(predicate::str::contains("Max runtime: 1s"));
 
-#[test]
-fn test_list_quiet_mode() {
-    let temp_dir = TempDir::new().unwrap();
+    std::thread::sleep(Duration::from_millis(3500));
 
-    // Test quiet mode with no processes
     let mut cmd = Command::cargo_bin("demon").unwrap();
     cmd.env("DEMON_ROOT_DIR", temp_dir.path())
-        .args(&["list", "--quiet"])
+        .args(["status", "job"])
         .assert()
-        .success()
-        .stdout(predicate::str::is_empty());
+        .code(3)
+        .stdout(predicate::str::contains("Exit signal: 9"))
+        .stdout(predicate::str::contains(
+            "Exit reason: TIMEOUT (ran longer than --max-runtime 1s)",
+        ));
+
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    let output = cmd
+        .env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["status", "job", "--format", "json"])
+        .output()
+        .unwrap();
+    let record: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(record["exit_reason"], "TIMEOUT");
 
-    // Create a process
     let mut cmd = Command::cargo_bin("demon").unwrap();
     cmd.env("DEMON_ROOT_DIR", temp_dir.path())
-        .args(&["run", "quiet-test", "echo", "done"])
+        .args(["history", "job"])
         .assert()
-        .success();
+        .success()
+        .stdout(predicate::str::contains("TIMEOUT"));
 
-    // Test quiet mode with process - should output colon-separated format
     let mut cmd = Command::cargo_bin("demon").unwrap();
     cmd.env("DEMON_ROOT_DIR", temp_dir.path())
-        .args(&["list", "-q"])
+        .args(["cat", "job", "--stdout"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("got term"));
+}
+
+#[test]
+fn test_run_require_free_resources() {
+    let temp_dir = TempDir::new().unwrap();
+    let demon = || {
+        let mut cmd = Command::cargo_bin("demon").unwrap();
+        cmd.env("DEMON_ROOT_DIR", temp_dir.path());
+        cmd
+    };
+    let disk = |size: &str| format!("{size}@{}", temp_dir.path().display());
+
+    demon()
+        .args([
+            "run",
+            "--require-free-memory",
+            "1000T",
+            "big",
+            "sleep",
+            "30",
+        ])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("--require-free-memory needs 1000"));
+
+    demon()
+        .args([
+            "run",
+            "--require-free-disk",
+            &disk("1000T"),
+            "big",
+            "sleep",
+            "30",
+        ])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("is free on the filesystem of"))
+        .stderr(predicate::str::contains("--require-free-disk needs 1000"));
+
+    demon()
+        .args([
+            "run",
+            "--require-free-disk",
+            &disk("1K"),
+            "small",
+            "sleep",
+            "30",
+        ])
+        .assert()
+        .success();
+
+    demon()
+        .args(["status", "small"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Requires: 1.0K free on"));
+
+    demon().args(["stop", "small"]).assert().success();
+}
+
+#[test]
+fn test_run_watch_restarts_on_change() {
+    let temp_dir = TempDir::new().unwrap();
+    let src = temp_dir.path().join("src");
+    std::fs::create_dir(&src).unwrap();
+    std::fs::write(src.join("version"), "1").unwrap();
+    let root = temp_dir.path().join("root");
+    std::fs::create_dir(&root).unwrap();
+    let demon = || {
+        let mut cmd = Command::cargo_bin("demon").unwrap();
+        cmd.env("DEMON_ROOT_DIR", &root)
+            .env("DEMON_DEV_DEBOUNCE", "100");
+        cmd
+    };
+    let stdout = || std::fs::read_to_string(root.join("app/app.stdout")).unwrap_or_default();
+
+    demon()
+        .args(["run", "app", "--watch"])
+        .arg(&src)
+        .args(["--watch-ignore", "*.log", "--", "sh", "-c"])
+        .arg(format!(
+            "echo version $(cat {}); sleep 30",
+            src.join("version").display()
+        ))
+        .assert()
+        .success();
+    demon()
+        .args(["status", "app"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Watch: "))
+        .stdout(predicate::str::contains("(ignoring *.log)"));
+
+    // Ignored files do not restart it
+    std::fs::write(src.join("debug.log"), "x").unwrap();
+    std::thread::sleep(Duration::from_millis(1000));
+    assert!(stdout().contains("version 1"));
+
+    std::fs::write(src.join("version"), "2").unwrap();
+    let deadline = std::time::Instant::now() + Duration::from_secs(10);
+    while !stdout().contains("version 2") && std::time::Instant::now() < deadline {
+        std::thread::sleep(Duration::from_millis(100));
+    }
+    assert_eq!(stdout().trim(), "version 2");
+
+    demon()
+        .args(["events", "--id", "app"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("restarted"))
+        .stdout(predicate::str::contains("version changed"));
+
+    demon().args(["stop", "app"]).assert().success();
+}
+
+#[test]
+fn test_status_uptime_survives_clock_changes() {
+    let temp_dir = TempDir::new().unwrap();
+    let demon = || {
+        let mut cmd = Command::cargo_bin("demon").unwrap();
+        cmd.env("DEMON_ROOT_DIR", temp_dir.path());
+        cmd
+    };
+
+    demon()
+        .args(["run", "web", "sleep", "30"])
+        .assert()
+        .success();
+    let status = || {
+        let output = demon()
+            .args(["status", "web", "--format", "json"])
+            .output()
+            .unwrap();
+        serde_json::from_slice::<serde_json::Value>(&output.stdout).unwrap()
+    };
+    assert!(status()["clock_skew_secs"].is_null());
+
+    // As if the wall clock was set back an hour after the start
+    let path = temp_dir.path().join("web/web.meta");
+    let mut meta: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+    assert!(meta["boot_id"].is_string());
+    let started_at = meta["started_at"].as_u64().unwrap();
+    meta["started_at"] = (started_at + 3600).into();
+    fs::write(&path, meta.to_string()).unwrap();
+
+    let record = status();
+    assert!(record["uptime_secs"].as_u64().unwrap() < 60);
+    assert!(record["clock_skew_secs"].as_i64().unwrap() <= -3590);
+    demon()
+        .args(["status", "web"])
+        .assert()
+        .success()
+        // 1h or 59m59s, depending on when the second turned
+        .stdout(predicate::str::contains("Clock: the wall clock moved back"))
+        .stdout(predicate::str::contains("uptime is measured from boot"));
+
+    demon().args(["stop", "web"]).assert().success();
+}
+
+#[test]
+fn test_list_sections_by_state() {
+    let temp_dir = TempDir::new().unwrap();
+    let demon = || {
+        let mut cmd = Command::cargo_bin("demon").unwrap();
+        cmd.env("DEMON_ROOT_DIR", temp_dir.path());
+        cmd
+    };
+
+    demon()
+        .args(["run", "server", "sleep", "30"])
+        .assert()
+        .success();
+    demon()
+        .args(["run", "--supervise", "job", "true"])
+        .assert()
+        .success();
+    demon()
+        .args(["run", "--supervise", "broken", "--", "sh", "-c", "exit 3"])
+        .assert()
+        .success();
+    std::thread::sleep(Duration::from_millis(500));
+
+    let output = demon().arg("list").output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let running = stdout.find("RUNNING (1)").unwrap();
+    let failed = stdout.find("FAILED (1)").unwrap();
+    let completed = stdout.find("COMPLETED (1)").unwrap();
+    assert!(running < failed && failed < completed);
+    assert!(stdout.contains("exit 3"));
+    assert!(stdout.contains("exit 0"));
+
+    // The flat format keeps the old statuses
+    demon()
+        .args(["list", "--flat"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("FAILED").not())
+        .stdout(predicate::str::contains("DEAD"));
+    demon()
+        .args(["list", "-q"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("job:").and(predicate::str::contains(":DEAD")));
+
+    demon().args(["stop", "server"]).assert().success();
+}
+
+#[test]
+fn test_cat_output() {
+    let temp_dir = TempDir::new().unwrap();
+
+    // Create a process with output
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args([
+            "run",
+            "test",
+            "--",
+            "sh",
+            "-c",
+            "echo 'stdout line'; echo 'stderr line' >&2",
+        ])
+        .assert()
+        .success();
+
+    // Cat the output
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["cat", "test"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("stdout line"))
+        .stdout(predicate::str::contains("stderr line"));
+}
+
+#[test]
+fn test_cat_stdout_only() {
+    let temp_dir = TempDir::new().unwrap();
+
+    // Create a process with output
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args([
+            "run",
+            "test",
+            "--",
+            "sh",
+            "-c",
+            "echo 'stdout line'; echo 'stderr line' >&2",
+        ])
+        .assert()
+        .success();
+
+    // Cat only stdout
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["cat", "test", "--stdout"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("stdout line"))
+        .stdout(predicate::str::contains("stderr line").not());
+}
+
+#[test]
+fn test_cat_head_tail_and_bytes() {
+    let temp_dir = TempDir::new().unwrap();
+    fs::create_dir(temp_dir.path().join("job")).unwrap();
+    std::fs::write(
+        temp_dir.path().join("job/job.stdout"),
+        b"one\ntwo\n\xff three\nfour",
+    )
+    .unwrap();
+
+    let cases: [(&[&str], &[u8]); 4] = [
+        (&["--head", "2"], b"one\ntwo\n"),
+        (&["--tail", "2"], b"\xff three\nfour"),
+        (&["--bytes", "4"], b"four"),
+        (&[], b"one\ntwo\n\xff three\nfour"),
+    ];
+    for (flags, expected) in cases {
+        let mut cmd = Command::cargo_bin("demon").unwrap();
+        cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+            .args(["cat", "job", "--stdout"])
+            .args(flags)
+            .assert()
+            .success()
+            .stdout(expected.to_vec());
+    }
+}
+
+#[test]
+fn test_cat_output_json() {
+    let temp_dir = TempDir::new().unwrap();
+
+    Command::cargo_bin("demon")
+        .unwrap()
+        .env("DEMON_ROOT_DIR", temp_dir.path())
+        .args([
+            "run",
+            "test",
+            "--",
+            "sh",
+            "-c",
+            "echo 'stdout line'; echo 'stderr line' >&2",
+        ])
+        .assert()
+        .success();
+    std::thread::sleep(Duration::from_millis(300));
+
+    // One object per line, with no headers
+    Command::cargo_bin("demon")
+        .unwrap()
+        .env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["cat", "test", "--output", "json"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            r#"{"id":"test","stream":"stdout","ts":null,"line":"stdout line","run":"#,
+        ))
+        .stdout(predicate::str::contains(
+            r#"{"id":"test","stream":"stderr","ts":null,"line":"stderr line","run":"#,
+        ))
+        .stdout(predicate::str::contains("==>").not());
+
+    // The format setting picks the same output
+    Command::cargo_bin("demon")
+        .unwrap()
+        .env("DEMON_ROOT_DIR", temp_dir.path())
+        .env("DEMON_FORMAT", "json")
+        .args(["tail", "test", "--stderr"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(r#""line":"stderr line""#))
+        .stdout(predicate::str::contains("stdout line").not());
+}
+
+#[test]
+fn test_grep_logs() {
+    let temp_dir = TempDir::new().unwrap();
+    for id in ["web", "api"] {
+        fs::create_dir(temp_dir.path().join(id)).unwrap();
+    }
+    fs::write(
+        temp_dir.path().join("web/web.stdout"),
+        "a\nb\nERROR one\nc\nd\ne\nf\nerror two\ng\n",
+    )
+    .unwrap();
+    fs::write(temp_dir.path().join("web/web.stderr"), "warn\nERROR x\n").unwrap();
+    fs::write(temp_dir.path().join("api/api.stdout"), "Error api\n").unwrap();
+    fs::write(temp_dir.path().join("api/api.stderr"), "").unwrap();
+    let grep = |args: &[&str]| {
+        let mut cmd = Command::cargo_bin("demon").unwrap();
+        cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+            .arg("grep")
+            .args(args)
+            .assert()
+    };
+
+    grep(&["web", "ERROR"])
+        .success()
+        .stdout("web.stdout:ERROR one\nweb.stderr:ERROR x\n");
+    grep(&["web", "error", "-i", "-C", "1", "-n", "--stdout"])
+        .success()
+        .stdout("2-b\n3:ERROR one\n4-c\n--\n7-f\n8:error two\n9-g\n");
+    grep(&["--all", "-i", "^error", "--stdout"])
+        .success()
+        .stdout("api.stdout:Error api\nweb.stdout:ERROR one\nweb.stdout:error two\n");
+    grep(&["web", "missing"]).code(1).stdout("");
+    grep(&["web", "("])
+        .failure()
+        .stderr(predicate::str::contains("Invalid pattern"));
+}
+
+#[test]
+fn test_cat_and_tail_split_streams() {
+    let temp_dir = TempDir::new().unwrap();
+
+    Command::cargo_bin("demon")
+        .unwrap()
+        .env("DEMON_ROOT_DIR", temp_dir.path())
+        .args([
+            "run",
+            "test",
+            "--",
+            "sh",
+            "-c",
+            "echo 'stdout line'; echo 'stderr line' >&2",
+        ])
+        .assert()
+        .success();
+    std::thread::sleep(Duration::from_millis(300));
+
+    for command in ["cat", "tail"] {
+        Command::cargo_bin("demon")
+            .unwrap()
+            .env("DEMON_ROOT_DIR", temp_dir.path())
+            .args([command, "test", "--split-streams"])
+            .assert()
+            .success()
+            .stdout("stdout line\n")
+            .stderr(predicate::str::contains("stderr line"))
+            .stderr(predicate::str::contains("stdout line").not());
+    }
+}
+
+#[test]
+fn test_run_resource_limits() {
+    let temp_dir = TempDir::new().unwrap();
+    let demon = || {
+        let mut cmd = Command::cargo_bin("demon").unwrap();
+        cmd.env("DEMON_ROOT_DIR", temp_dir.path());
+        cmd
+    };
+    demon()
+        .args([
+            "run",
+            "direct",
+            "--limit-nofile",
+            "256:512",
+            "--limit-core",
+            "0",
+        ])
+        .args(["--", "sh", "-c", "ulimit -Sn; ulimit -Hn; ulimit -c"])
+        .assert()
+        .success();
+    demon()
+        .args(["run", "supervised", "--supervise", "--limit-nofile", "100"])
+        .args(["--", "sh", "-c", "ulimit -n; sleep 30"])
+        .assert()
+        .success();
+    std::thread::sleep(Duration::from_millis(300));
+    assert_eq!(
+        fs::read_to_string(temp_dir.path().join("direct/direct.stdout")).unwrap(),
+        "256\n512\n0\n"
+    );
+    assert_eq!(
+        fs::read_to_string(temp_dir.path().join("supervised/supervised.stdout")).unwrap(),
+        "100\n"
+    );
+
+    // Recorded with the daemon
+    let meta = fs::read_to_string(temp_dir.path().join("direct/direct.meta")).unwrap();
+    assert!(meta.contains("\"limit_nofile\": \"256:512\""));
+    demon()
+        .args(["status", "supervised"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Limits: nofile=100"));
+    demon()
+        .args(["stop", "supervised", "--timeout", "1"])
+        .assert()
+        .success();
+
+    demon()
+        .args(["run", "bad", "--limit-nofile", "512:256", "--", "true"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "soft limit is above the hard limit",
+        ));
+}
+
+#[test]
+fn test_run_umask_and_core_dump_dir() {
+    let temp_dir = TempDir::new().unwrap();
+    let demon = || {
+        let mut cmd = Command::cargo_bin("demon").unwrap();
+        cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+            .current_dir(temp_dir.path());
+        cmd
+    };
+    demon()
+        .args([
+            "run",
+            "crasher",
+            "--umask",
+            "077",
+            "--core-dump-dir",
+            "crashes",
+        ])
+        .args(["--", "sh", "-c", "umask; kill -SEGV $$"])
+        .assert()
+        .success();
+    std::thread::sleep(Duration::from_millis(500));
+    assert_eq!(
+        fs::read_to_string(temp_dir.path().join("crasher/crasher.stdout")).unwrap(),
+        "0077\n"
+    );
+
+    let status = demon().args(["status", "crasher"]).output().unwrap();
+    let status = String::from_utf8_lossy(&status.stdout);
+    assert!(status.contains("Exit signal: 11"));
+    // Collected unless the kernel hands core dumps to a program
+    if !fs::read_to_string("/proc/sys/kernel/core_pattern")
+        .unwrap()
+        .starts_with('|')
+    {
+        let core = fs::read_dir(temp_dir.path().join("crashes"))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(core.len(), 1);
+        assert!(core[0].starts_with("crasher.") && core[0].ends_with(".core"));
+        assert!(status.contains("Core dump: ") && status.contains(&core[0]));
+    }
+
+    demon()
+        .args(["run", "bad", "--umask", "999", "--", "true"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("invalid umask"));
+}
+
+#[test]
+fn test_run_sandbox() {
+    let temp_dir = TempDir::new().unwrap();
+    let work_dir = TempDir::new().unwrap();
+    fs::create_dir(work_dir.path().join("out")).unwrap();
+    fs::write(work_dir.path().join("secret"), "x").unwrap();
+    let demon = || {
+        let mut cmd = Command::cargo_bin("demon").unwrap();
+        cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+            .current_dir(work_dir.path());
+        cmd
+    };
+    // Kernels without Landlock refuse to run the command unconfined
+    let started = demon()
+        .args(["run", "boxed", "--rw-path", "out", "--no-network", "--"])
+        .args(["sh", "-c"])
+        .arg(format!(
+            "echo ok > out/file; echo no > file || echo denied; cat secret; cat {} || echo hidden; cat /proc/net/dev | wc -l",
+            temp_dir.path().join("boxed/boxed.stdout").display()
+        ))
+        .output()
+        .unwrap();
+    if !started.status.success() {
+        assert!(String::from_utf8_lossy(&started.stderr).contains("Landlock"));
+        return;
+    }
+    std::thread::sleep(Duration::from_millis(500));
+    assert_eq!(
+        fs::read_to_string(work_dir.path().join("out/file")).unwrap(),
+        "ok\n"
+    );
+    assert!(!work_dir.path().join("file").exists());
+    // The working directory is readable, other directories are not, and
+    // only loopback is left
+    assert_eq!(
+        fs::read_to_string(temp_dir.path().join("boxed/boxed.stdout")).unwrap(),
+        "denied\nxhidden\n3\n"
+    );
+
+    let meta = fs::read_to_string(temp_dir.path().join("boxed/boxed.meta")).unwrap();
+    assert!(meta.contains("\"no_network\": true"));
+    assert!(meta.contains(&work_dir.path().join("out").display().to_string()));
+
+    demon()
+        .args(["run", "missing", "--ro-path", "nowhere", "--", "true"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("nowhere does not exist"));
+}
+
+#[test]
+fn test_run_priority() {
+    let temp_dir = TempDir::new().unwrap();
+    let demon = || {
+        let mut cmd = Command::cargo_bin("demon").unwrap();
+        cmd.env("DEMON_ROOT_DIR", temp_dir.path());
+        cmd
+    };
+    demon()
+        .args(["run", "batch", "--nice", "12", "--ionice-class", "idle"])
+        .args(["--cpu-affinity", "0", "--", "sh", "-c"])
+        .arg("cut -d' ' -f19 /proc/$$/stat; grep Cpus_allowed_list /proc/$$/status; sleep 30")
+        .assert()
+        .success();
+    std::thread::sleep(Duration::from_millis(300));
+    let stdout = fs::read_to_string(temp_dir.path().join("batch/batch.stdout")).unwrap();
+    assert!(stdout.starts_with("12\n"), "{stdout}");
+    assert!(stdout.contains("Cpus_allowed_list:\t0\n"), "{stdout}");
+    demon()
+        .args(["status", "batch"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "Priority: nice=12, io=idle, cpu-affinity=0",
+        ));
+    demon().args(["stop", "batch"]).assert().success();
+
+    demon()
+        .args([
+            "run",
+            "bad",
+            "--ionice-class",
+            "idle",
+            "--ionice-level",
+            "2",
+        ])
+        .args(["--", "true"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("does not apply to the idle class"));
+    demon()
+        .args(["run", "bad", "--cpu-affinity", "3-1", "--", "true"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("invalid CPU list"));
+}
+
+#[test]
+fn test_run_oom_score_adj() {
+    let temp_dir = TempDir::new().unwrap();
+    let demon = || {
+        let mut cmd = Command::cargo_bin("demon").unwrap();
+        cmd.env("DEMON_ROOT_DIR", temp_dir.path());
+        cmd
+    };
+    demon()
+        .args([
+            "run",
+            "experiment",
+            "--oom-score-adj",
+            "500",
+            "--",
+            "sh",
+            "-c",
+        ])
+        .arg("cat /proc/$$/oom_score_adj; sleep 30")
+        .assert()
+        .success();
+    std::thread::sleep(Duration::from_millis(300));
+    let stdout = fs::read_to_string(temp_dir.path().join("experiment/experiment.stdout")).unwrap();
+    assert_eq!(stdout, "500\n");
+    demon()
+        .args(["status", "experiment"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Priority: oom-score-adj=500"));
+    demon().args(["stop", "experiment"]).assert().success();
+
+    demon()
+        .args(["run", "bad", "--oom-score-adj", "1001", "--", "true"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("1001"));
+}
+
+#[test]
+fn test_run_cgroup_limits() {
+    let temp_dir = TempDir::new().unwrap();
+    let demon = || {
+        let mut cmd = Command::cargo_bin("demon").unwrap();
+        cmd.env("DEMON_ROOT_DIR", temp_dir.path());
+        cmd
+    };
+    demon()
+        .args(["run", "bad", "--cpus", "0", "--", "true"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("invalid CPU count"));
+
+    let output = demon()
+        .args(["run", "capped", "--memory", "64M", "--cpus", "0.5"])
+        .args(["--", "sh", "-c", "cat /proc/self/cgroup; sleep 30"])
+        .output()
+        .unwrap();
+    if !output.status.success() {
+        // Without delegated cgroup v2 controllers, as in most containers
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("need cgroup v2"), "{stderr}");
+        assert!(!temp_dir.path().join("capped/capped.pid").exists());
+        return;
+    }
+    std::thread::sleep(Duration::from_millis(300));
+    let stdout = fs::read_to_string(temp_dir.path().join("capped/capped.stdout")).unwrap();
+    assert!(stdout.contains("/demon/capped-"), "{stdout}");
+    demon()
+        .args(["status", "capped"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("memory=64.0M, cpus=0.5"));
+    demon()
+        .args(["stats", "capped"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("of 64.0M"));
+    let meta = fs::read_to_string(temp_dir.path().join("capped/capped.meta")).unwrap();
+    let meta: serde_json::Value = serde_json::from_str(&meta).unwrap();
+    let cgroup = PathBuf::from(meta["cgroup"].as_str().unwrap());
+    demon()
+        .args(["stop", "capped", "--timeout", "1"])
+        .assert()
+        .success();
+    assert!(!cgroup.exists());
+}
+
+#[test]
+fn test_run_systemd_scope() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let temp_dir = TempDir::new().unwrap();
+    let root = temp_dir.path().join("root");
+    let bin = temp_dir.path().join("bin");
+    let runtime = temp_dir.path().join("runtime");
+    for dir in [&root, &bin, &runtime] {
+        fs::create_dir_all(dir).unwrap();
+    }
+    // Stand-ins recording how they were called; systemd-run executes the
+    // command in its place like the real one
+    let fake = |name: &str, script: &str| {
+        let path = bin.join(name);
+        fs::write(&path, format!("#!/bin/sh\n{script}\n")).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+    };
+    let log = temp_dir.path().join("calls");
+    fake(
+        "systemd-run",
+        &format!(
+            "echo \"$@\" >> {}\nwhile [ \"$1\" != -- ]; do shift; done\nshift\nexec \"$@\"",
+            log.display()
+        ),
+    );
+    fake("systemctl", &format!("echo \"$@\" >> {}", log.display()));
+    let path = format!("{}:{}", bin.display(), std::env::var("PATH").unwrap());
+    let demon = |runtime: &PathBuf| {
+        let mut cmd = Command::cargo_bin("demon").unwrap();
+        cmd.env("DEMON_ROOT_DIR", &root)
+            .env("PATH", &path)
+            .env("XDG_RUNTIME_DIR", runtime)
+            .env_remove("DBUS_SESSION_BUS_ADDRESS");
+        cmd
+    };
+
+    demon(&runtime)
+        .args(["run", "svc", "--systemd-scope", "--", "true"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("no user bus"));
+
+    fs::write(runtime.join("bus"), "").unwrap();
+    demon(&runtime)
+        .args([
+            "run",
+            "svc",
+            "--systemd-scope",
+            "--memory",
+            "64M",
+            "--cpus",
+            "1.5",
+        ])
+        .args(["--", "sh", "-c", "echo $$; sleep 30"])
+        .assert()
+        .success();
+    std::thread::sleep(Duration::from_millis(300));
+    let calls = fs::read_to_string(&log).unwrap();
+    assert!(calls.starts_with("--user --scope --quiet --collect --unit demon-svc-"));
+    assert!(calls.contains("--property=MemoryMax=67108864 --property=CPUQuota=150% -- sh -c"));
+
+    // systemd-run executes the daemon in its place, keeping the PID
+    let pid_file = fs::read_to_string(root.join("svc/svc.pid")).unwrap();
+    let stdout = fs::read_to_string(root.join("svc/svc.stdout")).unwrap();
+    assert_eq!(pid_file.lines().next(), stdout.lines().next());
+    demon(&runtime)
+        .args(["status", "svc"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Systemd scope: demon-svc-"))
+        .stdout(predicate::str::contains("(memory=64.0M, cpus=1.5)"));
+
+    demon(&runtime)
+        .args(["stop", "svc", "--timeout", "1"])
+        .assert()
+        .success();
+    let calls = fs::read_to_string(&log).unwrap();
+    assert!(calls.contains("--user stop demon-svc-"), "{calls}");
+}
+
+#[test]
+fn test_strip_ansi() {
+    let temp_dir = TempDir::new().unwrap();
+    let demon = |args: &[&str]| {
+        let mut cmd = Command::cargo_bin("demon").unwrap();
+        cmd.env("DEMON_ROOT_DIR", temp_dir.path()).args(args);
+        cmd.assert()
+    };
+    let script = r"printf '\033[1;31merror\033[0m: disk \033]0;title\007full\n'";
+
+    // Kept in the logs, stripped when reading
+    demon(&["run", "colored", "--", "sh", "-c", script]).success();
+    std::thread::sleep(Duration::from_millis(300));
+    demon(&["cat", "colored", "--stdout"])
+        .success()
+        .stdout(predicate::str::contains("\x1b[1;31m"));
+    demon(&["cat", "colored", "--stdout", "--strip-ansi"])
+        .success()
+        .stdout("error: disk full\n");
+    demon(&["grep", "colored", "error: disk", "--strip-ansi"])
+        .success()
+        .stdout("colored.stdout:error: disk full\n");
+
+    // Stripped before the output is written to the logs
+    demon(&["run", "--strip-ansi", "plain", "--", "sh", "-c", script]).success();
+    std::thread::sleep(Duration::from_millis(500));
+    assert_eq!(
+        fs::read_to_string(temp_dir.path().join("plain/plain.stdout")).unwrap(),
+        "error: disk full\n"
+    );
+}
+
+#[test]
+fn test_cat_and_tail_since_until() {
+    let temp_dir = TempDir::new().unwrap();
+    let now = || {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+            .to_string()
+    };
+
+    Command::cargo_bin("demon")
+        .unwrap()
+        .env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["run", "--supervise", "incident", "--", "sh", "-c"])
+        .arg("echo early; sleep 3; echo during; sleep 3; echo late; sleep 30")
+        .assert()
+        .success();
+    std::thread::sleep(Duration::from_millis(1500));
+    let since = now();
+    std::thread::sleep(Duration::from_secs(3));
+    let until = now();
+    std::thread::sleep(Duration::from_millis(2500));
+
+    let window = |args: &[&str]| {
+        Command::cargo_bin("demon")
+            .unwrap()
+            .env("DEMON_ROOT_DIR", temp_dir.path())
+            .args(args)
+            .output()
+            .unwrap()
+    };
+    let output = window(&["cat", "incident", "--stdout", "--since", &since]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "during\nlate\n");
+    let output = window(&["cat", "incident", "--stdout", "--until", &until]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "early\nduring\n");
+    let output = window(&[
+        "tail", "incident", "--stdout", "--since", &since, "--until", &until,
+    ]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "during\n");
+
+    Command::cargo_bin("demon")
+        .unwrap()
+        .env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["stop", "incident"])
+        .assert()
+        .success();
+
+    // Without a supervisor nothing records when output was written
+    Command::cargo_bin("demon")
+        .unwrap()
+        .env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["run", "plain", "echo", "hello"])
+        .assert()
+        .success();
+    Command::cargo_bin("demon")
+        .unwrap()
+        .env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["cat", "plain", "--since", "10m"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "No timestamps recorded for 'plain'",
+        ));
+}
+
+#[test]
+fn test_logs_merge() {
+    let temp_dir = TempDir::new().unwrap();
+    for (id, script) in [
+        ("api", "echo request; sleep 2; echo response; sleep 30"),
+        ("worker", "sleep 1; echo 'job failed' >&2; sleep 30"),
+    ] {
+        Command::cargo_bin("demon")
+            .unwrap()
+            .env("DEMON_ROOT_DIR", temp_dir.path())
+            .args(["run", "--supervise", id, "--", "sh", "-c", script])
+            .assert()
+            .success();
+    }
+    std::thread::sleep(Duration::from_millis(2800));
+
+    let output = Command::cargo_bin("demon")
+        .unwrap()
+        .env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["logs", "api", "worker", "--merge"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines.len(), 3, "{stdout}");
+    assert!(lines[0].ends_with("api.stdout:    request"), "{stdout}");
+    assert!(lines[1].ends_with("worker.stderr: job failed"), "{stdout}");
+    assert!(lines[2].ends_with("api.stdout:    response"), "{stdout}");
+
+    let output = Command::cargo_bin("demon")
+        .unwrap()
+        .env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["logs", "worker", "--merge", "--output", "json"])
+        .output()
+        .unwrap();
+    let line: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(line["stream"], "stderr");
+    assert_eq!(line["line"], "job failed");
+    assert!(line["ts"].as_u64().is_some());
+
+    for id in ["api", "worker"] {
+        Command::cargo_bin("demon")
+            .unwrap()
+            .env("DEMON_ROOT_DIR", temp_dir.path())
+            .args(["stop", id])
+            .assert()
+            .success();
+    }
+}
+
+#[test]
+fn test_status_nonexistent() {
+    let temp_dir = TempDir::new().unwrap();
+
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["status", "nonexistent"])
+        .assert()
+        .code(4)
+        .stdout(predicate::str::contains("NOT FOUND"));
+}
+
+#[test]
+fn test_status_log_lines_and_growth() {
+    let temp_dir = TempDir::new().unwrap();
+
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args([
+            "run",
+            "logger",
+            "--",
+            "sh",
+            "-c",
+            "printf 'a\\nb\\nc'; sleep 30",
+        ])
+        .assert()
+        .success();
+    std::thread::sleep(Duration::from_millis(300));
+
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["status", "logger"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("(5 bytes, 3 lines)"))
+        .stdout(predicate::str::contains("Log growth: measuring"));
+
+    // A sample from a minute ago, as the supervisor or an earlier status
+    // would have taken
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    fs::write(
+        temp_dir.path().join("logger/logger.logsizes"),
+        format!(r#"[{{"ts":{},"stdout":0,"stderr":0}}]"#, now - 60),
+    )
+    .unwrap();
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    let output = cmd
+        .env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["status", "logger", "--format", "json"])
+        .output()
+        .unwrap();
+    let status: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(status["stdout_lines"], 3);
+    assert_eq!(status["stdout_bytes_per_min"], 5);
+    assert_eq!(status["stderr_bytes_per_min"], 0);
+
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["stop", "logger"])
+        .assert()
+        .success();
+    assert!(!temp_dir.path().join("logger/logger.logsizes").exists());
+}
+
+#[test]
+fn test_get_values() {
+    let temp_dir = TempDir::new().unwrap();
+    let demon = || {
+        let mut cmd = Command::cargo_bin("demon").unwrap();
+        cmd.env("DEMON_ROOT_DIR", temp_dir.path());
+        cmd
+    };
+    demon()
+        .args(["run", "api", "sleep", "30"])
+        .assert()
+        .success();
+    demon()
+        .args(["run", "job", "--supervise", "--", "sh", "-c", "exit 7"])
+        .assert()
+        .success();
+    std::thread::sleep(Duration::from_millis(300));
+
+    let pid = fs::read_to_string(temp_dir.path().join("api/api.pid")).unwrap();
+    let pid = pid.lines().next().unwrap();
+    demon()
+        .args(["get", "api", "pid"])
+        .assert()
+        .success()
+        .stdout(format!("{pid}\n"));
+    demon()
+        .args(["get", "api", "status"])
+        .assert()
+        .success()
+        .stdout("RUNNING\n");
+    demon()
+        .args(["get", "job", "status"])
+        .assert()
+        .success()
+        .stdout("FAILED\n");
+    demon()
+        .args(["get", "job", "exit-code"])
+        .assert()
+        .success()
+        .stdout("7\n");
+    let stdout_path = temp_dir.path().join("api/api.stdout");
+    demon()
+        .args(["get", "api", "stdout-path"])
+        .assert()
+        .success()
+        .stdout(format!("{}\n", stdout_path.display()));
+
+    // No value: empty stdout and an exit code telling why
+    demon()
+        .args(["get", "job", "pid"])
+        .assert()
+        .code(3)
+        .stdout("");
+    demon()
+        .args(["get", "missing", "status"])
+        .assert()
+        .code(4)
+        .stdout("");
+    demon()
+        .args(["get", "api", "exit-code"])
+        .assert()
+        .code(6)
+        .stdout("")
+        .stderr(predicate::str::contains("still running"));
+
+    demon().args(["stop", "api"]).assert().success();
+}
+
+#[test]
+fn test_export_systemd() {
+    let temp_dir = TempDir::new().unwrap();
+    let demon = || {
+        let mut cmd = Command::cargo_bin("demon").unwrap();
+        cmd.env("DEMON_ROOT_DIR", temp_dir.path());
+        cmd
+    };
+    demon()
+        .current_dir(temp_dir.path())
+        .env("EXPORT_GREETING", "hello world")
+        .args([
+            "run",
+            "web",
+            "--restart",
+            "on-failure",
+            "--limit-nofile",
+            "4096",
+            "--",
+            "sleep",
+            "30",
+        ])
+        .assert()
+        .success();
+    std::thread::sleep(Duration::from_millis(300));
+
+    let cwd = fs::canonicalize(temp_dir.path()).unwrap();
+    demon()
+        .args(["export", "systemd", "web"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("ExecStart=/").and(predicate::str::contains("sleep 30\n")))
+        .stdout(predicate::str::contains(format!(
+            "WorkingDirectory={}\n",
+            cwd.display()
+        )))
+        .stdout(predicate::str::contains(
+            "Environment=\"EXPORT_GREETING=hello world\"\n",
+        ))
+        .stdout(predicate::str::contains("Restart=on-failure\n"))
+        .stdout(predicate::str::contains("LimitNOFILE=4096\n"))
+        .stdout(predicate::str::contains("WantedBy=default.target\n"))
+        .stdout(predicate::str::contains("DEMON_ROOT_DIR").not());
+    demon().args(["stop", "web"]).assert().success();
+
+    // Daemons of the manifest that never ran, written to a directory
+    fs::write(
+        temp_dir.path().join("demon.toml"),
+        "[daemons.db]\ncommand = \"sleep 30\"\n\n[daemons.job]\ncommand = [\"sleep\", \"1\"]\nevery = 60\n",
+    )
+    .unwrap();
+    let units = temp_dir.path().join("units");
+    demon()
+        .args(["export", "systemd", "--all", "--system", "--output"])
+        .arg(&units)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("db.service"))
+        .stdout(predicate::str::contains("job.service"));
+    let db = fs::read_to_string(units.join("db.service")).unwrap();
+    assert!(db.contains("sh -c \"sleep 30\"\n"), "{db}");
+    assert!(db.contains("environment is not known"), "{db}");
+    assert!(db.contains("WantedBy=multi-user.target\n"), "{db}");
+    let job = fs::read_to_string(units.join("job.service")).unwrap();
+    assert!(job.contains("# Not exported: --every"), "{job}");
+
+    demon()
+        .args(["export", "systemd", "missing"])
+        .assert()
+        .failure();
+    demon()
+        .args(["--read-only", "export", "systemd", "db", "--output"])
+        .arg(&units)
+        .assert()
+        .failure();
+}
+
+#[test]
+fn test_export_launchd() {
+    let temp_dir = TempDir::new().unwrap();
+    let demon = || {
+        let mut cmd = Command::cargo_bin("demon").unwrap();
+        cmd.env("DEMON_ROOT_DIR", temp_dir.path());
+        cmd
+    };
+    demon()
+        .env("EXPORT_GREETING", "fish & chips")
+        .args([
+            "run",
+            "web",
+            "--restart",
+            "always",
+            "--limit-nofile",
+            "1024:4096",
+            "--",
+            "sleep",
+            "30",
+        ])
+        .assert()
+        .success();
+    std::thread::sleep(Duration::from_millis(300));
+
+    let stdout_path = temp_dir.path().join("web/web.stdout");
+    demon()
+        .args(["export", "launchd", "web"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("<string>local.demon.web</string>"))
+        .stdout(predicate::str::contains("<string>30</string>"))
+        .stdout(predicate::str::contains(
+            "<key>EXPORT_GREETING</key>\n\t\t<string>fish &amp; chips</string>",
+        ))
+        .stdout(predicate::str::contains("<key>KeepAlive</key>\n\t<true/>"))
+        .stdout(predicate::str::contains(
+            "<key>NumberOfFiles</key>\n\t\t<integer>4096</integer>",
+        ))
+        .stdout(predicate::str::contains(format!(
+            "<string>{}</string>",
+            stdout_path.display()
+        )));
+    demon().args(["stop", "web"]).assert().success();
+}
+
+#[test]
+fn test_status_dead_process() {
+    let temp_dir = TempDir::new().unwrap();
+
+    // Create a short-lived process
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["run", "dead", "echo", "hello"])
+        .assert()
+        .success();
+
+    // Check its status (should be dead)
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["status", "dead"])
+        .assert()
+        .code(3)
+        .stdout(predicate::str::contains("DEAD"));
+}
+
+#[test]
+fn test_stop_nonexistent() {
+    let temp_dir = TempDir::new().unwrap();
+
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["stop", "nonexistent"])
+        .assert()
+        .code(4)
+        .stdout(predicate::str::contains("not running"));
+}
+
+#[test]
+fn test_stop_process() {
+    let temp_dir = TempDir::new().unwrap();
+
+    // Start a long-running process
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["run", "long", "sleep", "10"])
+        .assert()
+        .success();
+
+    // Stop it
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["stop", "long"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("terminated gracefully"));
+
+    // Verify PID file is gone
+    assert!(!temp_dir.path().join("long/long.pid").exists());
+}
+
+#[test]
+fn test_check_nagios_states() {
+    let temp_dir = TempDir::new().unwrap();
+    let check = |args: &[&str]| {
+        let mut cmd = Command::cargo_bin("demon").unwrap();
+        cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+            .arg("check")
+            .args(args)
+            .assert()
+    };
+
+    check(&["web"])
+        .code(2)
+        .stdout("DEMON CRITICAL - web not found\n");
+
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["run", "web", "sleep", "30"])
+        .assert()
+        .success();
+    std::thread::sleep(Duration::from_millis(100));
+
+    check(&["web", "--warn-mem", "1G", "--max-restarts", "1"])
+        .code(0)
+        .stdout(predicate::str::starts_with("DEMON OK - web is running"))
+        .stdout(predicate::str::contains("| rss="))
+        .stdout(predicate::str::contains(" restarts=0;1;;0;"));
+    check(&["web", "--warn-mem", "1K"])
+        .code(1)
+        .stdout(predicate::str::starts_with("DEMON WARNING"));
+    check(&["web", "--warn-mem", "1K", "--crit-mem", "2K"])
+        .code(2)
+        .stdout(predicate::str::starts_with("DEMON CRITICAL"));
+
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let events: String = [now - 7200, now - 60, now - 30]
+        .iter()
+        .map(|ts| format!("{{\"ts\":{ts},\"id\":\"web\",\"event\":\"restarted\",\"pid\":1}}\n"))
+        .collect();
+    fs::write(temp_dir.path().join("events.jsonl"), events).unwrap();
+    check(&["web", "--max-restarts", "1"])
+        .code(1)
+        .stdout(predicate::str::contains(
+            "2 restarts in 1h00m (more than 1)",
+        ));
+    check(&["web", "--max-restarts", "1", "--window", "10s"]).code(0);
+
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["stop", "web"])
+        .assert()
+        .success();
+    fs::write(temp_dir.path().join("web/web.pid"), "not-a-number").unwrap();
+    check(&["web"])
+        .code(3)
+        .stdout(predicate::str::starts_with("DEMON UNKNOWN"));
+}
+
+#[test]
+fn test_check_plugins() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let temp_dir = TempDir::new().unwrap();
+    let plugins = temp_dir.path().join("checks.d").join("web");
+    fs::create_dir_all(&plugins).unwrap();
+    let scripts = [
+        (
+            "lag",
+            "#!/bin/sh\necho '{\"status\": \"warning\", \"message\": \"lag 42s\", \"metrics\": {\"lag_secs\": 42}}'\n",
+        ),
+        // Judged by its exit status, like a Nagios plugin
+        ("ping", "#!/bin/sh\necho \"pong $DEMON_PID\"\n"),
+    ];
+    for (name, script) in scripts {
+        let path = plugins.join(name);
+        fs::write(&path, script).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["run", "web", "sleep", "30"])
+        .assert()
+        .success();
+    std::thread::sleep(Duration::from_millis(100));
+
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["check", "web"])
+        .assert()
+        .code(1)
+        .stdout(predicate::str::contains(
+            "lag WARNING: lag 42s, ping OK: pong",
+        ))
+        .stdout(predicate::str::contains(" 'lag.lag_secs'=42"));
+
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    let output = cmd
+        .env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["status", "web", "--format", "json"])
+        .output()
+        .unwrap();
+    let status: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(status["checks"][0]["name"], "lag");
+    assert_eq!(status["checks"][0]["status"], "WARNING");
+    assert_eq!(status["checks"][0]["metrics"]["lag_secs"], 42.0);
+    assert_eq!(status["checks"][1]["status"], "OK");
+
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["stop", "web"])
+        .assert()
+        .success();
+}
+
+#[test]
+fn test_chaos_kills_daemon() {
+    let temp_dir = TempDir::new().unwrap();
+
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["chaos", "victim", "--probability", "1.5"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("invalid probability"));
+
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["run", "--supervise", "victim", "--", "sleep", "30"])
+        .assert()
+        .success();
+
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["chaos", "victim", "--kill-after", "1s", "--count", "1"])
+        .timeout(Duration::from_secs(10))
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Sent SIGKILL to daemon 'victim'"))
+        .stdout(predicate::str::contains("Disrupted daemon 'victim' 1 time"));
+    std::thread::sleep(Duration::from_millis(300));
+
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["status", "victim"])
+        .assert()
+        .code(3);
+    let events = fs::read_to_string(temp_dir.path().join("events.jsonl")).unwrap();
+    assert!(events.contains(r#""id":"victim","event":"chaos""#));
+    assert!(events.contains(r#""id":"victim","event":"crashed""#));
+}
+
+#[test]
+fn test_reload_sends_reload_signal() {
+    let temp_dir = TempDir::new().unwrap();
+    let script = "trap 'echo hup' HUP; trap 'echo usr2' USR2; while true; do sleep 0.1; done";
+
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["run", "default", "--", "sh", "-c", script])
+        .assert()
+        .success();
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["run", "--supervise", "--reload-signal", "USR2", "custom"])
+        .args(["--", "sh", "-c", script])
+        .assert()
+        .success();
+    // Give the shells time to install their traps
+    std::thread::sleep(Duration::from_millis(300));
+
+    for (id, signal) in [("default", "SIGHUP"), ("custom", "SIGUSR2")] {
+        let mut cmd = Command::cargo_bin("demon").unwrap();
+        cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+            .args(["reload", id])
+            .assert()
+            .success()
+            .stdout(predicate::str::contains(format!("Sent {signal}")));
+    }
+    std::thread::sleep(Duration::from_millis(500));
+    let log =
+        |id: &str| fs::read_to_string(temp_dir.path().join(format!("{id}/{id}.stdout"))).unwrap();
+    assert_eq!(log("default"), "hup\n");
+    assert_eq!(log("custom"), "usr2\n");
+
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["status", "custom"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Reload signal: SIGUSR2"));
+
+    for id in ["default", "custom"] {
+        let mut cmd = Command::cargo_bin("demon").unwrap();
+        cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+            .args(["stop", id])
+            .assert()
+            .success();
+    }
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["reload", "custom"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("not found"));
+}
+
+#[test]
+fn test_clean_no_orphans() {
+    let temp_dir = TempDir::new().unwrap();
+
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["clean"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("No orphaned files found"));
+}
+
+#[test]
+fn test_clean_with_orphans() {
+    let temp_dir = TempDir::new().unwrap();
+
+    // Create a dead process
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["run", "dead", "echo", "hello"])
+        .assert()
+        .success();
+
+    // Wait for process to complete
+    std::thread::sleep(Duration::from_millis(100));
+
+    // Verify files exist before clean
+    assert!(temp_dir.path().join("dead/dead.pid").exists());
+    assert!(temp_dir.path().join("dead/dead.stdout").exists());
+    assert!(temp_dir.path().join("dead/dead.stderr").exists());
+
+    // Clean up orphaned files
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["clean"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Cleaned up"))
+        .stdout(predicate::str::contains("orphaned"));
+
+    // Verify files are gone
+    assert!(!temp_dir.path().join("dead/dead.pid").exists());
+    assert!(!temp_dir.path().join("dead/dead.stdout").exists());
+    assert!(!temp_dir.path().join("dead/dead.stderr").exists());
+}
+
+#[test]
+fn test_warns_when_root_is_not_ignored_by_git() {
+    let temp_dir = TempDir::new().unwrap();
+    std::process::Command::new("git")
+        .args(["init", "--quiet"])
+        .current_dir(temp_dir.path())
+        .status()
+        .unwrap();
+    let demon = || {
+        let mut cmd = Command::cargo_bin("demon").unwrap();
+        cmd.env_remove("DEMON_ROOT_DIR")
+            .current_dir(temp_dir.path());
+        cmd
+    };
+
+    // Warned once per root
+    demon()
+        .arg("list")
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("demon init --gitignore"));
+    demon()
+        .arg("list")
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("demon init --gitignore").not());
+
+    demon()
+        .args(["init", "--gitignore"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("/.demon/*"));
+    let gitignore = fs::read_to_string(temp_dir.path().join(".gitignore")).unwrap();
+    assert!(gitignore.contains("/.demon/*\n!/.demon/config.toml\n"));
+
+    // Appended only once
+    demon()
+        .args(["init", "--gitignore"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("already ignored"));
+    assert_eq!(
+        fs::read_to_string(temp_dir.path().join(".gitignore")).unwrap(),
+        gitignore
+    );
+}
+
+#[test]
+fn test_init_imports_procfile_for_up_and_down() {
+    let temp_dir = TempDir::new().unwrap();
+    fs::write(
+        temp_dir.path().join("Procfile"),
+        "# services\nweb: sleep 30\nworker: echo $0 > worker.out; sleep 30\n",
+    )
+    .unwrap();
+    let demon = || {
+        let mut cmd = Command::cargo_bin("demon").unwrap();
+        cmd.env("DEMON_ROOT_DIR", temp_dir.path());
+        cmd
+    };
+
+    demon()
+        .args(["init", "--procfile"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("demon.toml from"));
+    let manifest = fs::read_to_string(temp_dir.path().join("demon.toml")).unwrap();
+    assert!(manifest.contains("[daemons.web]\ncommand = \"sleep 30\""));
+    let config = fs::read_to_string(temp_dir.path().join("config.toml")).unwrap();
+    assert!(config.contains("# stop.timeout = 10"));
+    demon()
+        .args(["init", "--procfile"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("already exists"));
+
+    // Started in manifest order from the manifest's directory
+    demon()
+        .arg("up")
+        .current_dir(std::env::temp_dir())
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Started daemon 'web'"))
+        .stdout(predicate::str::contains("Started daemon 'worker'"));
+    demon()
+        .args(["up", "web"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("'web' is already running"));
+    std::thread::sleep(Duration::from_millis(200));
+    assert!(temp_dir.path().join("worker.out").exists());
+
+    demon()
+        .args(["down", "--timeout", "1"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("'worker'"))
+        .stdout(predicate::str::contains("'web'"));
+    demon()
+        .args(["up", "db"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("No daemon 'db'"));
+}
+
+#[test]
+fn test_up_status_and_clean_order() {
+    let temp_dir = TempDir::new().unwrap();
+    fs::write(
+        temp_dir.path().join("demon.toml"),
+        "[daemons.zeta]\ncommand = \"sleep 30\"\n\n[daemons.mid]\ncommand = \"sleep 30\"\n",
+    )
+    .unwrap();
+    let demon = || {
+        let mut cmd = Command::cargo_bin("demon").unwrap();
+        cmd.env("DEMON_ROOT_DIR", temp_dir.path());
+        cmd
+    };
+    let ids = |output: &[u8]| -> Vec<String> {
+        let records: Vec<serde_json::Value> = serde_json::from_slice(output).unwrap();
+        records
+            .iter()
+            .map(|record| record["id"].as_str().unwrap().to_string())
+            .collect()
+    };
+
+    // The order of the manifest, not of the arguments
+    demon()
+        .args(["up", "mid", "zeta", "mid"])
+        .assert()
+        .success()
+        .stdout(
+            predicate::str::is_match(
+                "(?s)^Started daemon 'zeta'[^\n]*\nStarted daemon 'mid'[^\n]*\n$",
+            )
+            .unwrap(),
+        );
+    for id in ["beta", "alpha"] {
+        demon().args(["run", id, "true"]).assert().success();
+    }
+    std::thread::sleep(Duration::from_millis(200));
+
+    let output = demon()
+        .args(["status", "--all", "--format", "json"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(ids(&output.stdout), ["alpha", "beta", "mid", "zeta"]);
+    demon()
+        .args(["status", "--all"])
+        .assert()
+        .success()
+        .stdout(predicate::str::is_match("(?s)Daemon: alpha\n.*\n\nDaemon: beta\n").unwrap());
+    demon()
+        .args(["status", "--all", "alpha"])
+        .assert()
+        .failure();
+
+    demon().args(["down", "--timeout", "1"]).assert().success();
+    let output = demon()
+        .args(["clean", "--format", "json"])
+        .output()
+        .unwrap();
+    assert_eq!(ids(&output.stdout), ["alpha", "beta"]);
+}
+
+#[test]
+fn test_up_wait_healthy() {
+    let temp_dir = TempDir::new().unwrap();
+    fs::write(
+        temp_dir.path().join("demon.toml"),
+        r#"[daemons.db]
+command = "sleep 30"
+
+[daemons.web]
+command = "sleep 0.5; touch web.up; sleep 30"
+health_cmd = "test -f web.up"
+
+[daemons.broken]
+command = "exit 1"
+"#,
+    )
+    .unwrap();
+    let demon = || {
+        let mut cmd = Command::cargo_bin("demon").unwrap();
+        cmd.env("DEMON_ROOT_DIR", temp_dir.path());
+        cmd
+    };
+
+    demon()
+        .args(["up", "db", "web", "--wait-healthy", "--timeout", "10s"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("db is running"))
+        .stdout(predicate::str::contains("web passed its health check"))
+        .stdout(predicate::str::contains("All daemons are healthy"));
+
+    // Running daemons are waited for too, and stragglers are listed
+    demon()
+        .args(["up", "--wait-healthy", "--timeout", "1s"])
+        .assert()
+        .code(5)
+        .stdout(predicate::str::contains("web passed its health check"))
+        .stderr(predicate::str::contains(
+            "Not healthy after 1s: broken (exited",
+        ));
+
+    demon().args(["down", "--timeout", "1"]).assert().success();
+}
+
+#[test]
+fn test_concurrent_up_is_locked_out() {
+    let temp_dir = TempDir::new().unwrap();
+    fs::write(
+        temp_dir.path().join("demon.toml"),
+        "[daemons.web]\ncommand = \"sleep 30\"\nhealth_cmd = \"false\"\n",
+    )
+    .unwrap();
+    let demon = || {
+        let mut cmd = Command::cargo_bin("demon").unwrap();
+        cmd.env("DEMON_ROOT_DIR", temp_dir.path());
+        cmd
+    };
+
+    // Holds the lock while it waits for a health check that never passes
+    let mut first = std::process::Command::new(assert_cmd::cargo::cargo_bin("demon"))
+        .args(["up", "--wait-healthy", "--timeout", "2s"])
+        .env("DEMON_ROOT_DIR", temp_dir.path())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .unwrap();
+    std::thread::sleep(Duration::from_millis(500));
+
+    demon()
+        .arg("up")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Another 'demon up' (PID "))
+        .stderr(predicate::str::contains("pass --wait-lock"));
+    demon()
+        .args(["down", "--wait-lock=1s"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Waiting for 'demon up'"))
+        .stderr(predicate::str::contains("Timed out after"));
+    // The interval is only taken with =, so an ID can follow
+    demon()
+        .args(["up", "--wait-lock", "web"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Daemon 'web' is already running"));
+    first.wait().unwrap();
+
+    demon().arg("down").assert().success();
+}
+
+#[test]
+fn test_progress_json() {
+    let temp_dir = TempDir::new().unwrap();
+    fs::write(
+        temp_dir.path().join("demon.toml"),
+        "[daemons.db]\ncommand = \"sleep 30\"\n\n[daemons.web]\ncommand = \"sleep 30\"\n",
+    )
+    .unwrap();
+    let progress = |args: &[&str]| {
+        let output = Command::cargo_bin("demon")
+            .unwrap()
+            .env("DEMON_ROOT_DIR", temp_dir.path())
+            .args(args)
+            .args(["--progress", "json"])
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        // Log messages are interleaved
+        String::from_utf8(output.stderr)
+            .unwrap()
+            .lines()
+            .filter(|line| line.starts_with('{'))
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .map(|event| {
+                format!(
+                    "{} {} {} {} {}/{}",
+                    event["op"].as_str().unwrap(),
+                    event["event"].as_str().unwrap(),
+                    event["id"].as_str().unwrap_or("-"),
+                    event["phase"].as_str().unwrap_or("-"),
+                    event["done"],
+                    event["total"]
+                )
+            })
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(
+        progress(&["up"]),
+        [
+            "up start - - 0/2",
+            "up step db started 1/2",
+            "up step web started 2/2",
+            "up done - started 2/2",
+        ]
+    );
+    // How long SIGTERM takes varies, so the seconds stop waited are left out
+    let down: Vec<String> = progress(&["down", "web"])
+        .into_iter()
+        .filter(|line| !line.contains(" waiting "))
+        .map(|line| match line.strip_prefix("stop ") {
+            Some(rest) => format!("stop {}", rest.rsplit_once(' ').unwrap().0),
+            None => line,
+        })
+        .collect();
+    assert_eq!(
+        down,
+        [
+            "down start - - 0/1",
+            "stop start web -",
+            "stop step web sigterm",
+            "stop done web terminated",
+            "down step web terminated 1/1",
+            "down done - stopped 1/1",
+        ]
+    );
+    // Silent without the flag
+    Command::cargo_bin("demon")
+        .unwrap()
+        .env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["down"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("\"op\"").not());
+}
+
+#[test]
+fn test_root_strategy() {
+    let temp_dir = TempDir::new().unwrap();
+    let repo = temp_dir.path().join("repo");
+    let api = repo.join("services").join("api");
+    fs::create_dir_all(repo.join(".git")).unwrap();
+    fs::create_dir_all(api.join("src")).unwrap();
+    fs::write(
+        api.join("demon.toml"),
+        "[daemons.db]\ncommand = \"sleep 30\"\n",
+    )
+    .unwrap();
+    let demon = |dir: &PathBuf, strategy: &str| {
+        let mut cmd = Command::cargo_bin("demon").unwrap();
+        cmd.env_remove("DEMON_ROOT_DIR")
+            .env("DEMON_ROOT_STRATEGY", strategy)
+            .current_dir(dir);
+        cmd
+    };
+
+    // The nearest demon.toml, from anywhere below it
+    demon(&api.join("src"), "manifest")
+        .args(["run", "api", "sleep", "30"])
+        .assert()
+        .success();
+    assert!(api.join(".demon").join("api/api.pid").exists());
+    assert!(!repo.join(".demon").exists());
+    demon(&api, "manifest")
+        .args(["list", "--quiet"])
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with("api:"));
+
+    // Without a manifest above, the git root
+    demon(&repo.join("services"), "manifest")
+        .args(["list", "--quiet"])
+        .assert()
+        .success()
+        .stdout("");
+    assert!(repo.join(".demon").is_dir());
+    demon(&api.join("src"), "git")
+        .args(["list", "--quiet"])
+        .assert()
+        .success()
+        .stdout("");
+    demon(&api.join("src"), "cwd")
+        .args(["stop", "api", "--root-strategy", "manifest"])
+        .assert()
+        .success();
+
+    demon(&api.join("src"), "cwd")
+        .args(["list", "--quiet"])
+        .assert()
+        .success();
+    assert!(api.join("src").join(".demon").is_dir());
+}
+
+#[test]
+fn test_shell_hook_starts_and_stops_daemons() {
+    let temp_dir = TempDir::new().unwrap();
+    let project = temp_dir.path().join("project");
+    fs::create_dir_all(project.join(".git")).unwrap();
+    fs::create_dir_all(project.join("src")).unwrap();
+    fs::write(
+        project.join("demon.toml"),
+        "[daemons.db]\ncommand = \"sleep 30\"\n",
+    )
+    .unwrap();
+    let root_dir = project.join(".demon");
+
+    // The state of a project, without creating its root directory
+    Command::cargo_bin("demon")
+        .unwrap()
+        .args(["shell-hook", "--state"])
+        .env_remove("DEMON_ROOT_DIR")
+        .current_dir(project.join("src"))
+        .assert()
+        .success()
+        .stdout(format!(
+            "{}\t{}\tdb\n",
+            root_dir.display(),
+            project.join("demon.toml").display()
+        ));
+    assert!(!root_dir.exists());
+    Command::cargo_bin("demon")
+        .unwrap()
+        .args(["shell-hook", "zsh", "--stop", "leave"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("add-zsh-hook precmd _demon_hook"))
+        .stdout(predicate::str::contains("add-zsh-hook zshexit _demon_exit"));
+
+    // Entering the project starts its daemons, exiting the shell stops them
+    let bin_dir = assert_cmd::cargo::cargo_bin("demon")
+        .parent()
+        .unwrap()
+        .to_path_buf();
+    let path = format!("{}:{}", bin_dir.display(), std::env::var("PATH").unwrap());
+    let script = r#"eval "$(demon shell-hook bash --up always --stop exit)"
+cd src && _demon_prompt
+echo "root=$DEMON_SHELL_ROOT"
+demon list --quiet
+"#;
+    Command::new("bash")
+        .args(["-c", script])
+        .env("PATH", path)
+        .env_remove("DEMON_ROOT_DIR")
+        .current_dir(&project)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(format!(
+            "root={}",
+            root_dir.display()
+        )))
+        .stdout(predicate::str::contains("db:"))
+        .stdout(predicate::str::contains(":RUNNING"))
+        .stdout(predicate::str::contains("'db'"));
+    Command::cargo_bin("demon")
+        .unwrap()
+        .args(["list", "--quiet"])
+        .env("DEMON_ROOT_DIR", &root_dir)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("RUNNING").not());
+}
+
+#[test]
+fn test_clean_removes_stdout_stderr_files() {
+    let temp_dir = TempDir::new().unwrap();
+
+    // Create a process that outputs to both stdout and stderr
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args([
+            "run",
+            "test_output",
+            "--",
+            "sh",
+            "-c",
+            "echo 'stdout content'; echo 'stderr content' >&2",
+        ])
+        .assert()
+        .success();
+
+    // Wait for process to complete
+    std::thread::sleep(Duration::from_millis(100));
+
+    // Verify all files exist and have content
+    assert!(temp_dir.path().join("test_output/test_output.pid").exists());
+    assert!(
+        temp_dir
+            .path()
+            .join("test_output/test_output.stdout")
+            .exists()
+    );
+    assert!(
+        temp_dir
+            .path()
+            .join("test_output/test_output.stderr")
+            .exists()
+    );
+
+    let stdout_content =
+        fs::read_to_string(temp_dir.path().join("test_output/test_output.stdout")).unwrap();
+    let stderr_content =
+        fs::read_to_string(temp_dir.path().join("test_output/test_output.stderr")).unwrap();
+    assert!(stdout_content.contains("stdout content"));
+    assert!(stderr_content.contains("stderr content"));
+
+    // Clean up orphaned files
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["clean"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Cleaned up"))
+        .stdout(predicate::str::contains("orphaned"));
+
+    // Verify ALL files are gone, not just the PID file
+    assert!(!temp_dir.path().join("test_output/test_output.pid").exists());
+    assert!(
+        !temp_dir
+            .path()
+            .join("test_output/test_output.stdout")
+            .exists()
+    );
+    assert!(
+        !temp_dir
+            .path()
+            .join("test_output/test_output.stderr")
+            .exists()
+    );
+}
+
+#[test]
+fn test_clean_dry_run_and_scopes() {
+    let temp_dir = TempDir::new().unwrap();
+    let demon = || {
+        let mut cmd = Command::cargo_bin("demon").unwrap();
+        cmd.env("DEMON_ROOT_DIR", temp_dir.path());
+        cmd
+    };
+    for id in ["web-1", "web-2", "db"] {
+        demon()
+            .args(["run", id, "--", "echo", "done"])
+            .assert()
+            .success();
+    }
+    std::thread::sleep(Duration::from_millis(200));
+    let exists = |file: &str| temp_dir.path().join(file).exists();
+
+    demon()
+        .args(["clean", "--dry-run"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "Would clean up orphaned files for 'db'",
+        ))
+        .stdout(predicate::str::contains("web-1.stdout"))
+        .stdout(predicate::str::contains(
+            "Would clean up 3 orphaned daemon(s).",
+        ));
+    assert!(exists("db/db.pid") && exists("db/db.stdout"));
+
+    // Too recent
+    demon()
+        .args(["clean", "--older-than", "1h"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("No orphaned files found."));
+    assert!(exists("db/db.pid"));
+
+    // The logs of the matching daemons only; they are still listed
+    demon()
+        .args(["clean", "--id", "web-*", "--logs-only"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Cleaning up logs for 'web-1'"))
+        .stdout(predicate::str::contains("Cleaned up 2 orphaned daemon(s)."));
+    assert!(!exists("web-1/web-1.stdout") && !exists("web-2/web-2.stderr"));
+    assert!(exists("web-1/web-1.pid") && exists("web-2/web-2.meta") && exists("db/db.stdout"));
+    demon()
+        .args(["list", "--quiet"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("web-1:"));
+
+    demon().arg("clean").assert().success();
+    assert!(!exists("web-1/web-1.pid") && !exists("db/db.pid") && !exists("db/db.stdout"));
+    assert!(!exists("db"));
+}
+
+#[test]
+fn test_ids_with_dots_and_flat_layout() {
+    let temp_dir = TempDir::new().unwrap();
+    let root = temp_dir.path();
+    let demon = || {
+        let mut cmd = Command::cargo_bin("demon").unwrap();
+        cmd.env("DEMON_ROOT_DIR", root);
+        cmd
+    };
+
+    // A root directory left by a version keeping every file in the root
+    fs::write(root.join("old.app.pid"), "999999\necho\nold\n").unwrap();
+    fs::write(root.join("old.app.stdout"), "from the old layout\n").unwrap();
+    fs::write(root.join("old.app.stderr"), "").unwrap();
+
+    // Read-only mode reads the flat files where they are
+    demon()
+        .args(["list", "--quiet", "--read-only"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("old.app:"));
+    demon()
+        .args(["cat", "old.app"])
+        .env("DEMON_READ_ONLY", "1")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("from the old layout"));
+    assert!(root.join("old.app.pid").exists());
+    assert!(!root.join("old.app").exists());
+
+    for (id, text) in [("my", "short"), ("my.app", "dotted")] {
+        demon()
+            .args(["run", id, "--", "echo", text])
+            .assert()
+            .success();
+    }
+    std::thread::sleep(Duration::from_millis(200));
+    assert!(!root.join("old.app.pid").exists());
+    assert!(root.join("old.app/old.app.pid").exists());
+
+    demon()
+        .args(["list", "--quiet"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("my:"))
+        .stdout(predicate::str::contains("my.app:"))
+        .stdout(predicate::str::contains("old.app:"));
+    demon()
+        .args(["cat", "my.app"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("dotted"));
+    demon()
+        .args(["cat", "old.app"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("from the old layout"));
+
+    demon()
+        .args(["clean", "--id", "my"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Cleaned up 1 orphaned daemon(s)."));
+    assert!(!root.join("my").exists());
+    assert!(root.join("my.app/my.app.stdout").exists());
+
+    demon()
+        .args(["run", "runs", "--", "true"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Invalid daemon ID 'runs'"));
+}
+
+#[test]
+fn test_nuke_stops_and_removes_everything() {
+    let temp_dir = TempDir::new().unwrap();
+    let demon = || {
+        let mut cmd = Command::cargo_bin("demon").unwrap();
+        cmd.env("DEMON_ROOT_DIR", temp_dir.path());
+        cmd
+    };
+    demon()
+        .args(["run", "web", "--", "sleep", "30"])
+        .assert()
+        .success();
+    demon()
+        .args(["run", "job", "--", "echo", "done"])
+        .assert()
+        .success();
+    // A daemon stopped earlier leaves only its logs and past runs behind
+    for _ in 0..2 {
+        demon()
+            .args(["run", "old", "--", "echo", "old"])
+            .assert()
+            .success();
+        std::thread::sleep(Duration::from_millis(200));
+    }
+    demon().args(["stop", "old"]).assert().code(3);
+    let exists = |file: &str| temp_dir.path().join(file).exists();
+    assert!(!exists("old/old.pid") && exists("old/old.stdout") && exists("runs/old"));
+
+    // Nothing is asked or done without a terminal
+    demon()
+        .arg("nuke")
+        .write_stdin("y\n")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("pass --yes"));
+    assert!(exists("web/web.pid") && exists("job/job.stdout"));
+
+    demon()
+        .args(["nuke", "--yes", "--timeout", "2"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Process 'web'"))
+        .stdout(predicate::str::contains("Removed the files of 3 daemon(s)"));
+    for file in [
+        "old",
+        "runs/old",
+        "web/web.pid",
+        "web/web.stdout",
+        "web/web.meta",
+        "job/job.pid",
+        "job/job.stderr",
+    ] {
+        assert!(!exists(file), "{file} was not removed");
+    }
+
+    demon()
+        .args(["nuke", "--yes"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Nothing to nuke"));
+}
+
+#[test]
+fn test_symlinked_logs_in_status_and_clean() {
+    let temp_dir = TempDir::new().unwrap();
+    let other_disk = TempDir::new().unwrap();
+    let target = other_disk.path().join("linked.out");
+    fs::create_dir(temp_dir.path().join("linked")).unwrap();
+    std::os::unix::fs::symlink(&target, temp_dir.path().join("linked/linked.stdout")).unwrap();
+
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["run", "linked", "--", "sh", "-c", "echo 'through the link'"])
+        .assert()
+        .success();
+    std::thread::sleep(Duration::from_millis(200));
+    assert_eq!(fs::read_to_string(&target).unwrap(), "through the link\n");
+
+    // The size is that of the target
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    let output = cmd
+        .env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["status", "linked", "--format", "json"])
+        .output()
+        .unwrap();
+    let status: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(status["stdout_bytes"], 17);
+    assert!(
+        status["stdout_target"]
+            .as_str()
+            .unwrap()
+            .ends_with("linked.out")
+    );
+    assert!(status["stderr_target"].is_null());
+
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["cat", "linked", "--stdout"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("through the link"));
+
+    // Clean keeps the link and the target, emptied
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["clean"])
+        .assert()
+        .success();
+    assert!(!temp_dir.path().join("linked/linked.pid").exists());
+    assert!(!temp_dir.path().join("linked/linked.stderr").exists());
+    assert!(temp_dir.path().join("linked/linked.stdout").is_symlink());
+    assert_eq!(fs::read_to_string(&target).unwrap(), "");
+}
+
+#[test]
+fn test_default_demon_directory_creation() {
+    // This test verifies that when no --root-dir is specified,
+    // the system creates and uses a .demon subdirectory in the git root
+
+    // Create a temporary git repo
+    let temp_dir = TempDir::new().unwrap();
+    let git_dir = temp_dir.path().join(".git");
+    std::fs::create_dir(&git_dir).unwrap();
+
+    // Change to the temp directory
+    let original_dir = std::env::current_dir().unwrap();
+    std::env::set_current_dir(temp_dir.path()).unwrap();
+
+    // Restore directory when done
+    struct DirGuard(PathBuf);
+    impl Drop for DirGuard {
+        fn drop(&mut self) {
+            let _ = std::env::set_current_dir(&self.0);
+        }
+    }
+    let _guard = DirGuard(original_dir);
+
+    // Run a command without --root-dir to test default behavior
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.args(["run", "default_test", "echo", "hello"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Started daemon 'default_test'"));
+
+    // Wait for process to complete
+    std::thread::sleep(Duration::from_millis(100));
+
+    // Verify that .demon directory was created and files are in it
+    let demon_dir = temp_dir.path().join(".demon");
+    assert!(demon_dir.exists());
+    assert!(demon_dir.is_dir());
+    assert!(demon_dir.join("default_test/default_test.pid").exists());
+    assert!(demon_dir.join("default_test/default_test.stdout").exists());
+    assert!(demon_dir.join("default_test/default_test.stderr").exists());
+
+    // Verify the stdout content
+    let stdout_content =
+        fs::read_to_string(demon_dir.join("default_test/default_test.stdout")).unwrap();
+    assert_eq!(stdout_content.trim(), "hello");
+}
+
+#[test]
+fn test_run_with_complex_command() {
+    let temp_dir = TempDir::new().unwrap();
+
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args([
+            "run",
+            "complex",
+            "--",
+            "sh",
+            "-c",
+            "for i in 1 2 3; do echo \"line $i\"; done",
+        ])
+        .assert()
+        .success();
+
+    // Give the process a moment to complete
+    std::thread::sleep(Duration::from_millis(100));
+
+    // Check the output contains all lines
+    let stdout_content =
+        fs::read_to_string(temp_dir.path().join("complex/complex.stdout")).unwrap();
+    assert!(stdout_content.contains("line 1"));
+    assert!(stdout_content.contains("line 2"));
+    assert!(stdout_content.contains("line 3"));
+}
+
+#[test]
+fn test_timeout_configuration() {
+    let temp_dir = TempDir::new().unwrap();
+
+    // Start a process
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["run", "timeout-test", "sleep", "5"])
+        .assert()
+        .success();
+
+    // Stop with custom timeout (should work normally since sleep responds to SIGTERM)
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["stop", "timeout-test", "--timeout", "2"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("terminated gracefully"));
+}
+
+#[test]
+fn test_invalid_process_id() {
+    let temp_dir = TempDir::new().unwrap();
+
+    // Create an invalid PID file
+    fs::create_dir(temp_dir.path().join("invalid")).unwrap();
+    fs::write(temp_dir.path().join("invalid/invalid.pid"), "not-a-number").unwrap();
+
+    // Status should handle it gracefully
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["status", "invalid"])
+        .assert()
+        .code(1)
+        .stdout(predicate::str::contains("ERROR"));
+
+    // Clean should remove it
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["clean"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("invalid PID file"));
+}
+
+#[test]
+fn test_list_quiet_mode() {
+    let temp_dir = TempDir::new().unwrap();
+
+    // Test quiet mode with no processes
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["list", "--quiet"])
+        .assert()
+        .success()
+        .stdout(predicate::str::is_empty());
+
+    // Create a process
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["run", "quiet-test", "echo", "done"])
+        .assert()
+        .success();
+
+    // Test quiet mode with process - should output colon-separated format
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["list", "-q"])
         .assert()
         .success()
         .stdout(predicate::str::contains("quiet-test:"))
@@ -496,143 +3143,2875 @@ fn test_list_quiet_mode() {
 }
 
 #[test]
-fn test_llm_command() {
+fn test_llm_command() {
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.args(["llm"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "# Demon - Daemon Process Management CLI",
+        ))
+        .stdout(predicate::str::contains("## Available Commands"))
+        .stdout(predicate::str::contains("demon run"))
+        .stdout(predicate::str::contains("demon stop"))
+        .stdout(predicate::str::contains("demon list"))
+        .stdout(predicate::str::contains("demon tail"))
+        .stdout(predicate::str::contains("demon cat"))
+        .stdout(predicate::str::contains("demon status"))
+        .stdout(predicate::str::contains("demon clean"))
+        .stdout(predicate::str::contains("demon wait"))
+        .stdout(predicate::str::contains("Common Workflows"))
+        .stdout(predicate::str::contains("Best Practices"))
+        .stdout(predicate::str::contains("Integration Tips"));
+}
+
+#[test]
+fn test_llm_guide_covers_all_commands_and_flags() {
+    let run = |args: &[&str]| {
+        let output = Command::cargo_bin("demon")
+            .unwrap()
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        String::from_utf8(output.stdout).unwrap()
+    };
+
+    let guide = run(&["llm"]);
+    let help = run(&["--help"]);
+    let subcommands: Vec<&str> = help
+        .lines()
+        .skip_while(|line| !line.starts_with("Commands:"))
+        .skip(1)
+        .take_while(|line| line.starts_with("  "))
+        .filter_map(|line| line.split_whitespace().next())
+        .filter(|name| *name != "help")
+        .collect();
+    assert!(subcommands.contains(&"run"));
+
+    for subcommand in subcommands {
+        assert!(
+            guide.contains(&format!("### demon {subcommand}")),
+            "guide is missing command '{subcommand}'"
+        );
+
+        // Every flag from `demon <subcommand> --help`, e.g. "-n, --lines <LINES>"
+        let help = run(&[subcommand, "--help"]);
+        for flag in help
+            .lines()
+            .map(str::trim_start)
+            .filter(|line| line.starts_with('-'))
+            .flat_map(|line| line.split_whitespace().take(2))
+            .map(|word| word.trim_end_matches(','))
+            .filter(|word| word.starts_with('-'))
+            .filter(|flag| !matches!(*flag, "-h" | "--help" | "-V" | "--version"))
+        {
+            assert!(
+                guide.contains(flag),
+                "guide is missing flag '{flag}' of '{subcommand}'"
+            );
+        }
+    }
+}
+
+#[test]
+fn test_llm_json_schema() {
+    let output = Command::cargo_bin("demon")
+        .unwrap()
+        .args(["llm", "--format", "json"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let schema: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+
+    assert_eq!(schema["name"], "demon");
+    assert_eq!(schema["global_options"][0]["env"], "DEMON_ROOT_DIR");
+    let commands = schema["commands"].as_array().unwrap();
+    let command = |name: &str| {
+        commands
+            .iter()
+            .find(|command| command["name"] == name)
+            .unwrap_or_else(|| panic!("schema is missing command '{name}'"))
+    };
+
+    let tail = command("tail");
+    assert_eq!(tail["arguments"][0]["name"], "id");
+    // --group and --label take the place of the ID
+    assert_eq!(tail["arguments"][0]["required"], false);
+    let lines = tail["options"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|option| option["long"] == "lines")
+        .unwrap();
+    assert_eq!(lines["short"], "n");
+    assert_eq!(lines["kind"], "option");
+    assert_eq!(command("list")["output_formats"][1], "json");
+    assert_eq!(command("stop")["exit_codes"][1]["code"], 1);
+
+    let restart = command("run")["options"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|option| option["long"] == "restart")
+        .unwrap();
+    assert_eq!(
+        restart["possible_values"],
+        serde_json::json!(["never", "on-failure", "always"])
+    );
+}
+
+#[test]
+fn test_wait_nonexistent_process() {
+    let temp_dir = TempDir::new().unwrap();
+
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["wait", "nonexistent"])
+        .assert()
+        .code(4)
+        .stderr(predicate::str::contains("not found"));
+}
+
+#[test]
+fn test_wait_already_dead_process() {
+    let temp_dir = TempDir::new().unwrap();
+
+    // Create a short-lived process
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["run", "dead", "echo", "hello"])
+        .assert()
+        .success();
+
+    // Give it time to finish
+    std::thread::sleep(Duration::from_millis(100));
+
+    // Try to wait for it (should fail since it's already dead)
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["wait", "dead"])
+        .assert()
+        .code(3)
+        .stderr(predicate::str::contains("not running"));
+}
+
+#[test]
+fn test_wait_process_terminates() {
+    let temp_dir = TempDir::new().unwrap();
+
+    // Start a process that will run for 2 seconds
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["run", "short", "sleep", "2"])
+        .assert()
+        .success();
+
+    // Wait for it with a 5-second timeout (should succeed)
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["wait", "short", "--timeout", "5"])
+        .assert()
+        .success();
+}
+
+#[test]
+fn test_wait_timeout() {
+    let temp_dir = TempDir::new().unwrap();
+
+    // Start a long-running process
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["run", "long", "sleep", "10"])
+        .assert()
+        .success();
+
+    // Wait with a very short timeout (should fail)
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["wait", "long", "--timeout", "2"])
+        .assert()
+        .code(5)
+        .stderr(predicate::str::contains("Timeout reached"));
+
+    // Clean up the still-running process
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["stop", "long"])
+        .assert()
+        .success();
+}
+
+#[test]
+fn test_wait_infinite_timeout() {
+    let temp_dir = TempDir::new().unwrap();
+
+    // Start a short process that will finish quickly
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["run", "quick", "sleep", "1"])
+        .assert()
+        .success();
+
+    // Wait with infinite timeout (should succeed quickly)
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["wait", "quick", "--timeout", "0"])
+        .assert()
+        .success();
+}
+
+#[test]
+fn test_wait_custom_interval() {
+    let temp_dir = TempDir::new().unwrap();
+
+    // Start a short process
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["run", "interval-test", "sleep", "2"])
+        .assert()
+        .success();
+
+    // Wait with custom interval (should still succeed)
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["wait", "interval-test", "--timeout", "5", "--interval", "2"])
+        .assert()
+        .success();
+}
+
+#[test]
+fn test_clean_waits_for_root_lock() {
+    let temp_dir = TempDir::new().unwrap();
+
+    // Simulate a concurrent `demon run` holding the root lock
+    let lock_file = fs::File::create(temp_dir.path().join(".lock")).unwrap();
+    lock_file.lock().unwrap();
+
+    let mut child = std::process::Command::new(assert_cmd::cargo::cargo_bin("demon"))
+        .env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["clean"])
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .unwrap();
+
+    // Clean must block while the lock is held
+    std::thread::sleep(Duration::from_millis(300));
+    assert!(child.try_wait().unwrap().is_none());
+
+    lock_file.unlock().unwrap();
+    assert!(child.wait().unwrap().success());
+}
+
+fn free_port() -> u16 {
+    std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+#[test]
+fn test_proxy_starts_daemon_on_first_connection() {
+    use std::io::{BufRead, Read, Write};
+
+    let temp_dir = TempDir::new().unwrap();
+    let listen = format!("127.0.0.1:{}", free_port());
+    let upstream_port = free_port();
+
+    let mut proxy = std::process::Command::new(assert_cmd::cargo::cargo_bin("demon"))
+        .env("DEMON_ROOT_DIR", temp_dir.path())
+        .args([
+            "proxy",
+            "lazy-web",
+            "--listen",
+            &listen,
+            "--upstream",
+            &format!("127.0.0.1:{upstream_port}"),
+            "--",
+            "python3",
+            "-m",
+            "http.server",
+            "--bind",
+            "127.0.0.1",
+            &upstream_port.to_string(),
+        ])
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .unwrap();
+
+    // The proxy announces itself once it is listening; nothing is started yet
+    let mut proxy_stdout = std::io::BufReader::new(proxy.stdout.take().unwrap());
+    let mut announcement = String::new();
+    proxy_stdout.read_line(&mut announcement).unwrap();
+    assert!(announcement.contains("started on first connection"));
+    assert!(!temp_dir.path().join("lazy-web/lazy-web.pid").exists());
+
+    let mut stream = std::net::TcpStream::connect(&listen).unwrap();
+    stream.write_all(b"GET / HTTP/1.0\r\n\r\n").unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.0 200"));
+    assert!(temp_dir.path().join("lazy-web/lazy-web.pid").exists());
+
+    proxy.kill().unwrap();
+    proxy.wait().unwrap();
+
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["stop", "lazy-web"])
+        .assert()
+        .success();
+}
+
+#[test]
+fn test_run_verify_detach() {
+    let temp_dir = TempDir::new().unwrap();
+
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["run", "detached", "--verify-detach", "sleep", "10"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Detach verified"))
+        .stdout(predicate::str::contains("no controlling terminal"));
+
+    // Status reports the session details of the running daemon
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["status", "detached"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("(leader)"))
+        .stdout(predicate::str::contains("Controlling TTY: none"));
+
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["stop", "detached"])
+        .assert()
+        .success();
+}
+
+#[test]
+fn test_top_requires_terminal() {
+    let temp_dir = TempDir::new().unwrap();
+
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["top"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("requires an interactive terminal"));
+}
+
+#[test]
+fn test_resource_usage_in_list_status_and_stats() {
+    let temp_dir = TempDir::new().unwrap();
+
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["run", "busy", "--", "sh", "-c", "sleep 10 & wait"])
+        .assert()
+        .success();
+    std::thread::sleep(Duration::from_millis(200));
+
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["list"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("CPU%"))
+        .stdout(predicate::str::contains("RSS"));
+
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["status", "busy"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Memory (RSS):"))
+        .stdout(predicate::str::contains("Threads: 1"));
+
+    // The child `sleep` is aggregated into the totals
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["stats", "busy"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Process tree:"))
+        .stdout(predicate::str::contains("sleep 10"))
+        .stdout(predicate::str::contains("Total (2 processes)"));
+
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["stop", "busy"])
+        .assert()
+        .success();
+
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["stats", "busy"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("not found"));
+}
+
+#[test]
+fn test_whois_finds_owner_of_pid_port_and_file() {
+    let temp_dir = TempDir::new().unwrap();
+    let port = free_port();
+    let server = format!("python3 -m http.server {port} --bind 127.0.0.1 & wait");
+
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["run", "web", "--", "sh", "-c", &server])
+        .assert()
+        .success();
+    let daemon = fs::read_to_string(temp_dir.path().join("web/web.pid")).unwrap();
+    let daemon = daemon.lines().next().unwrap().to_string();
+    let start = std::time::Instant::now();
+    while std::net::TcpStream::connect(("127.0.0.1", port)).is_err() {
+        assert!(
+            start.elapsed() < Duration::from_secs(5),
+            "server never listened"
+        );
+        std::thread::sleep(Duration::from_millis(50));
+    }
+
+    // The port is held by the python child, not by the daemon itself
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    let output = cmd
+        .env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["whois", "--port", &port.to_string()])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let output = String::from_utf8(output).unwrap();
+    assert!(
+        output.starts_with("web: PID ")
+            && output.contains(&format!("started by daemon PID {daemon}"))
+            && output.contains(&format!("listening on tcp port {port}")),
+        "unexpected output: {output}"
+    );
+    let child: String = output["web: PID ".len()..]
+        .chars()
+        .take_while(char::is_ascii_digit)
+        .collect();
+
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["whois", "--pid", &child])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("http.server"));
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["whois", "--file"])
+        .arg(temp_dir.path().join("web/web.stderr"))
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(format!(
+            "web: PID {daemon}, the daemon, fd 2"
+        )));
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["whois", "--pid", "1"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("owns PID 1"));
+
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["stop", "web"])
+        .assert()
+        .success();
+}
+
+#[test]
+fn test_run_supervised() {
+    let temp_dir = TempDir::new().unwrap();
+
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["run", "supervised", "--supervise", "sleep", "30"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Started daemon 'supervised'"));
+
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["status", "supervised"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Status: RUNNING"))
+        .stdout(predicate::str::contains("Supervisor: PID"));
+
+    // Stop goes through the supervisor and removes all state files
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["stop", "supervised"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("terminated gracefully"));
+    assert!(!temp_dir.path().join("supervised/supervised.pid").exists());
+    assert!(!temp_dir.path().join("supervised/supervised.sock").exists());
+
+    // The supervisor records the exit status of daemons that exit on their own
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["run", "failing", "--supervise", "--", "sh", "-c", "exit 3"])
+        .assert()
+        .success();
+    std::thread::sleep(Duration::from_millis(500));
+
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["status", "failing"])
+        .assert()
+        .code(3)
+        .stdout(predicate::str::contains("Status: DEAD"))
+        .stdout(predicate::str::contains("Exit code: 3"));
+}
+
+#[test]
+fn test_history_records_runs() {
+    let temp_dir = TempDir::new().unwrap();
+
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["history", "svc"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("No recorded runs for 'svc'"));
+
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["run", "svc", "sleep", "30"])
+        .assert()
+        .success();
+
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["stop", "svc"])
+        .assert()
+        .success();
+
+    // A supervised run records its exit code; the log files are cleaned away
+    // but the history remains
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["run", "svc", "--supervise", "--", "sh", "-c", "exit 7"])
+        .assert()
+        .success();
+    std::thread::sleep(Duration::from_millis(500));
+
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["clean"])
+        .assert()
+        .success();
+
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["history", "svc"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("sleep 30"))
+        .stdout(predicate::str::contains("sh -c exit 7"))
+        .stdout(predicate::function(|out: &str| {
+            out.lines()
+                .any(|line| line.contains("exit 7") && line.contains(" 7 "))
+        }));
+}
+
+#[test]
+fn test_history_stats_and_retention() {
+    let temp_dir = TempDir::new().unwrap();
+    let demon = || {
+        let mut cmd = Command::cargo_bin("demon").unwrap();
+        cmd.env("DEMON_ROOT_DIR", temp_dir.path());
+        cmd
+    };
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let record = |id: &str, started_at: u64, duration: u64, exit_code: i32| {
+        format!(
+            r#"{{"id":"{id}","command":["backup"],"pid":1,"started_at":{started_at},"ended_at":{},"exit_code":{exit_code},"exit_signal":null,"peak_rss":null}}"#,
+            started_at + duration
+        )
+    };
+    let history = [
+        record("other", 1000, 5, 0),
+        record("job", now - 400, 10, 0),
+        record("job", now - 300, 10, 0),
+        record("job", now - 200, 20, 1),
+        record("other", now - 150, 5, 0),
+        record("job", now - 100, 20, 0),
+    ];
+    let path = temp_dir.path().join("history.jsonl");
+    fs::write(&path, history.join("\n") + "\n").unwrap();
+
+    demon()
+        .args(["history", "job", "--stats"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Runs:         4 since"))
+        .stdout(predicate::str::contains(
+            "Success rate: 75.0% (3 of 4 runs with a known exit status)",
+        ))
+        .stdout(predicate::str::contains("Failures:     1, last at"))
+        .stdout(predicate::str::contains("(exit 1)"))
+        .stdout(predicate::str::contains(
+            "Duration:     average 15s, shortest 10s, longest 20s",
+        ))
+        .stdout(predicate::str::contains(
+            "Trend:        last 2 runs average 20s, +100% from the 2 before (10s)",
+        ));
+
+    // Recording a run prunes the oldest runs of the daemon and old records
+    demon()
+        .env("DEMON_HISTORY_KEEP_RUNS", "2")
+        .env("DEMON_HISTORY_KEEP_DAYS", "30")
+        .args(["run", "job", "sleep", "30"])
+        .assert()
+        .success();
+    demon()
+        .env("DEMON_HISTORY_KEEP_RUNS", "2")
+        .env("DEMON_HISTORY_KEEP_DAYS", "30")
+        .args(["stop", "job"])
+        .assert()
+        .success();
+
+    let contents = fs::read_to_string(&path).unwrap();
+    let lines: Vec<&str> = contents.lines().collect();
+    assert_eq!(lines.len(), 3, "{contents}");
+    assert_eq!(lines[0], history[4]);
+    assert_eq!(lines[1], history[5]);
+    assert!(lines[2].contains("sleep"), "{contents}");
+}
+
+#[test]
+fn test_runs_and_cat_run() {
+    let temp_dir = TempDir::new().unwrap();
+
+    for n in 1..=3 {
+        let mut cmd = Command::cargo_bin("demon").unwrap();
+        cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+            .args(["run", "job", "--supervise", "--", "sh", "-c"])
+            .arg(format!("echo run {n}; echo oops {n} >&2; exit {n}"))
+            .assert()
+            .success();
+        std::thread::sleep(Duration::from_millis(500));
+    }
+
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    let output = cmd
+        .env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["runs", "job", "--format", "json"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let runs: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let runs = runs.as_array().unwrap();
+    assert_eq!(runs.len(), 3);
+    assert_eq!(runs[0]["exit_code"], 3);
+    assert_eq!(runs[1]["run"], 1);
+    assert_eq!(runs[1]["exit_code"], 2);
+
+    // The current run, then the logs the second run moved aside
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["cat", "job", "--stdout"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("run 3"))
+        .stdout(predicate::str::contains("run 2").not());
+
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["cat", "job", "--run", "previous", "--stdout"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("run 2"))
+        .stdout(predicate::str::contains("run 3").not());
+
+    // Every run, the oldest first
+    for args in [
+        ["cat", "job", "--all-runs", "--stdout"],
+        ["tail", "job", "--all-runs", "--stdout"],
+    ] {
+        let mut cmd = Command::cargo_bin("demon").unwrap();
+        let output = cmd
+            .env("DEMON_ROOT_DIR", temp_dir.path())
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            "run 1\nrun 2\nrun 3\n"
+        );
+    }
+
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["grep", "job", "oops", "--run", "2"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("oops 1"));
+
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["tail", "job", "--run", "9"])
+        .assert()
+        .code(4)
+        .stderr(predicate::str::contains("No run 9 of 'job'"));
+
+    // Past runs go away with the logs
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["clean"])
+        .assert()
+        .success();
+    assert!(!temp_dir.path().join("runs").join("job").exists());
+}
+
+#[test]
+fn test_view_needs_logs_and_terminal() {
+    let temp_dir = TempDir::new().unwrap();
+
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["view", "missing"])
+        .assert()
+        .code(4)
+        .stderr(predicate::str::contains(
+            "No log files found for daemon 'missing'",
+        ));
+
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["run", "svc", "--", "echo", "hello"])
+        .assert()
+        .success();
+
+    // Not a terminal: point scripts to cat and grep
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["view", "svc"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("requires an interactive terminal"));
+}
+
+#[test]
+fn test_bench_runs_and_summarizes() {
+    let temp_dir = TempDir::new().unwrap();
+    let demon = || {
+        let mut cmd = Command::cargo_bin("demon").unwrap();
+        cmd.env("DEMON_ROOT_DIR", temp_dir.path());
+        cmd
+    };
+
+    demon()
+        .args([
+            "bench",
+            "job",
+            "--runs",
+            "3",
+            "--",
+            "sh",
+            "-c",
+            "echo output",
+        ])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Run 1/3: wall "))
+        .stdout(predicate::str::contains("Run 3/3: wall "))
+        .stdout(predicate::str::contains("MEAN       MEDIAN     STDDEV"))
+        .stdout(predicate::str::is_match("(?m)^Wall ").unwrap())
+        .stdout(predicate::str::is_match("(?m)^CPU ").unwrap())
+        .stdout(predicate::str::is_match("(?m)^Peak RSS ").unwrap());
+
+    // Each run is a supervised run with captured logs and a history record
+    demon()
+        .args(["cat", "job", "--stdout"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("output"));
+    let history = fs::read_to_string(temp_dir.path().join("history.jsonl")).unwrap();
+    assert_eq!(history.lines().count(), 3);
+    assert!(history.contains("\"wall_ms\":"), "{history}");
+
+    demon()
+        .args(["bench", "fail", "--", "sh", "-c", "exit 3"])
+        .assert()
+        .failure()
+        .stdout(predicate::str::contains("Run 1/").not())
+        .stderr(predicate::str::contains(
+            "Run 1 of 'fail' failed with exit 3",
+        ));
+}
+
+#[test]
+fn test_config_layers_and_validation() {
+    let temp_dir = TempDir::new().unwrap();
+    let config_home = TempDir::new().unwrap();
+    let demon = || {
+        let mut cmd = Command::cargo_bin("demon").unwrap();
+        cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+            .env("XDG_CONFIG_HOME", config_home.path());
+        cmd
+    };
+
+    demon()
+        .args(["config", "get", "stop.timeout"])
+        .assert()
+        .success()
+        .stdout("10\n");
+
+    demon()
+        .args(["config", "set", "stop.timeout", "30"])
+        .assert()
+        .success();
+    demon()
+        .args(["config", "list"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("stop.timeout=30 from"))
+        .stdout(predicate::str::contains("config.toml"))
+        .stdout(predicate::str::contains("tail.lines=50 from default"));
+
+    // Environment variables override the files
+    demon()
+        .env("DEMON_STOP_TIMEOUT", "5")
+        .args(["config", "list"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "stop.timeout=5 from env DEMON_STOP_TIMEOUT",
+        ));
+
+    demon()
+        .args(["config", "set", "stop.timeout", "soon"])
+        .assert()
+        .failure();
+    demon()
+        .args(["config", "set", "no.such-key", "1"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Unknown configuration key"));
+
+    fs::write(
+        temp_dir.path().join("config.toml"),
+        "[stop]\ntimeout = \"x\"\ntimout = 3\n",
+    )
+    .unwrap();
+    demon()
+        .args(["config", "validate"])
+        .assert()
+        .failure()
+        .stdout(predicate::str::contains("unknown key 'stop.timout'"))
+        .stdout(predicate::str::contains("'stop.timeout' must be"));
+
+    // Commands refuse to run with an invalid configuration
+    demon()
+        .args(["stop", "anything"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Invalid configuration"));
+}
+
+#[test]
+fn test_config_daemon_defaults() {
+    let temp_dir = TempDir::new().unwrap();
+    let config_home = TempDir::new().unwrap();
+    let demon = || {
+        let mut cmd = Command::cargo_bin("demon").unwrap();
+        cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+            .env("XDG_CONFIG_HOME", config_home.path());
+        cmd
+    };
+    fs::write(
+        temp_dir.path().join("config.toml"),
+        "[daemon.api]\nstop-timeout = 3\nlimit-nofile = \"128\"\n",
+    )
+    .unwrap();
+
+    demon()
+        .args(["config", "list"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("daemon.api.stop_timeout=3 from"));
+
+    // Defaults apply to their ID only, and flags given take precedence
+    demon()
+        .args(["run", "api", "--", "sh", "-c", "ulimit -n"])
+        .assert()
+        .success();
+    demon()
+        .args(["run", "other", "--", "sh", "-c", "ulimit -n"])
+        .assert()
+        .success();
+    std::thread::sleep(Duration::from_millis(300));
+    assert_eq!(
+        fs::read_to_string(temp_dir.path().join("api/api.stdout")).unwrap(),
+        "128\n"
+    );
+    assert_ne!(
+        fs::read_to_string(temp_dir.path().join("other/other.stdout")).unwrap(),
+        "128\n"
+    );
+    demon().args(["clean"]).assert().success();
+    demon()
+        .args([
+            "run",
+            "api",
+            "--limit-nofile",
+            "64",
+            "--",
+            "sh",
+            "-c",
+            "ulimit -n",
+        ])
+        .assert()
+        .success();
+    std::thread::sleep(Duration::from_millis(300));
+    assert_eq!(
+        fs::read_to_string(temp_dir.path().join("api/api.stdout")).unwrap(),
+        "64\n"
+    );
+
+    fs::write(
+        temp_dir.path().join("config.toml"),
+        "[daemon.api]\nrestart = \"sometimes\"\n",
+    )
+    .unwrap();
+    demon()
+        .args(["config", "validate"])
+        .assert()
+        .failure()
+        .stdout(predicate::str::contains(
+            "'daemon.api': unknown variant `sometimes`",
+        ));
+}
+
+#[test]
+fn test_env_overrides_format_and_color() {
+    let temp_dir = TempDir::new().unwrap();
+
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["run", "env-test", "sleep", "10"])
+        .assert()
+        .success();
+
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .env("DEMON_FORMAT", "json")
+        .args(["list"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("\"id\": \"env-test\""))
+        .stdout(predicate::str::contains("\"status\": \"RUNNING\""));
+
+    // Forced colors apply to diagnostics even when stderr is not a terminal
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .env("DEMON_COLOR", "always")
+        .env("DEMON_STOP_TIMEOUT", "2")
+        .args(["stop", "env-test"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("\x1b["))
+        .stderr(predicate::str::contains("with timeout 2s"));
+
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .env("DEMON_COLOR", "sometimes")
+        .args(["list"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("DEMON_COLOR"));
+}
+
+#[test]
+fn test_format_flag_prints_records() {
+    let temp_dir = TempDir::new().unwrap();
+
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    let output = cmd
+        .env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["run", "--format", "json", "web", "sleep", "30"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let run: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(run["id"], "web");
+    let pid = run["pid"].as_u64().unwrap();
+
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    let output = cmd
+        .env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["status", "web", "--format", "json"])
+        .output()
+        .unwrap();
+    let status: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(status["pid"], pid);
+    assert_eq!(status["status"], "RUNNING");
+    assert_eq!(status["command"], serde_json::json!(["sleep", "30"]));
+    assert!(status["exit_code"].is_null());
+
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["list", "--format", "csv", "--columns", "id,pid,status"])
+        .assert()
+        .success()
+        .stdout(format!("id,pid,status,error\nweb,{pid},RUNNING,\n"));
+
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .env("DEMON_FORMAT", "plain")
+        .args(["status", "missing"])
+        .assert()
+        .code(4)
+        .stdout(predicate::str::starts_with("missing\t\tNOT FOUND\t"));
+
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    let output = cmd
+        .env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["stop", "web", "--format", "json"])
+        .output()
+        .unwrap();
+    let stopped: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(
+        stopped,
+        serde_json::json!({"id": "web", "pid": pid, "result": "terminated"})
+    );
+
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["clean", "--format", "csv"])
+        .assert()
+        .success()
+        .stdout("id,pid,reason,files\n");
+}
+
+#[test]
+fn test_read_only_refuses_mutating_commands() {
+    let temp_dir = TempDir::new().unwrap();
+
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["run", "web", "--read-only", "--", "sleep", "30"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("disabled in read-only mode"));
+    assert!(!temp_dir.path().join("web/web.pid").exists());
+
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["run", "web", "--", "sleep", "30"])
+        .assert()
+        .success();
+
+    let refused: [&[&str]; 4] = [
+        &["stop", "web"],
+        &["config", "set", "stop.timeout", "3"],
+        &["clean"],
+        &["serve", "--upgrade"],
+    ];
+    for args in refused {
+        let mut cmd = Command::cargo_bin("demon").unwrap();
+        cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+            .env("DEMON_READ_ONLY", "1")
+            .args(args)
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("disabled in read-only mode"));
+    }
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .env("DEMON_READ_ONLY", "1")
+        .args(["status", "web"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("RUNNING"));
+
+    // A false value leaves read-only mode off
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .env("DEMON_READ_ONLY", "false")
+        .args(["stop", "web"])
+        .assert()
+        .success();
+}
+
+#[test]
+fn test_read_only_writes_nothing() {
+    let temp_dir = TempDir::new().unwrap();
+    let project = temp_dir.path();
+    fs::create_dir(project.join(".git")).unwrap();
+    let root_dir = project.join(".demon");
+
+    // The root directory of a fresh repository is not created
+    Command::cargo_bin("demon")
+        .unwrap()
+        .args(["list", "--read-only"])
+        .env_remove("DEMON_ROOT_DIR")
+        .current_dir(project)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "read-only mode does not create it",
+        ));
+    assert!(!root_dir.exists());
+
+    // Nor the lock file or the marker of the git check in an existing one
+    fs::create_dir(&root_dir).unwrap();
+    for args in [
+        &["list", "--read-only"][..],
+        &["clean", "--dry-run", "--read-only"],
+    ] {
+        Command::cargo_bin("demon")
+            .unwrap()
+            .args(args)
+            .env_remove("DEMON_ROOT_DIR")
+            .current_dir(project)
+            .assert()
+            .success();
+    }
+    assert_eq!(fs::read_dir(&root_dir).unwrap().count(), 0);
+}
+
+#[test]
+fn test_policy_file_restricts_starts() {
+    let temp_dir = TempDir::new().unwrap();
+    std::fs::write(
+        temp_dir.path().join("policy.toml"),
+        "programs = [\"sleep\"]\nids = [\"web\", \"worker-*\"]\nmax_daemons = 2\n",
+    )
+    .unwrap();
+
+    let refused: [(&[&str], &str); 3] = [
+        (
+            &["run", "db", "--", "sleep", "30"],
+            "ID 'db' is not allowed",
+        ),
+        (&["run", "web", "--", "cat"], "Program 'cat' is not allowed"),
+        (
+            &["run", "web", "--", "/bin/sleep", "30"],
+            "Program '/bin/sleep' is not allowed",
+        ),
+    ];
+    for (args, error) in refused {
+        let mut cmd = Command::cargo_bin("demon").unwrap();
+        cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+            .args(args)
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains(error));
+    }
+    assert!(!temp_dir.path().join("web/web.pid").exists());
+
+    for id in ["web", "worker-1"] {
+        let mut cmd = Command::cargo_bin("demon").unwrap();
+        cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+            .args(["run", id, "--", "sleep", "30"])
+            .assert()
+            .success();
+    }
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["run", "worker-2", "--", "sleep", "30"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Already running 2 daemons"));
+
+    for id in ["web", "worker-1"] {
+        let mut cmd = Command::cargo_bin("demon").unwrap();
+        cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+            .args(["stop", id])
+            .assert()
+            .success();
+    }
+
+    // Resource flags within the maximums, and given where there is one
+    std::fs::write(
+        temp_dir.path().join("policy.toml"),
+        "[max]\nmemory = \"512M\"\ncpus = 1\nlimit_nofile = 1024\n",
+    )
+    .unwrap();
+    let refused: [(&[&str], &str); 4] = [
+        (
+            &["--memory", "1G", "--cpus", "1", "--limit-nofile", "512"],
+            "--memory 1.0G is above the 512.0M allowed",
+        ),
+        (
+            &["--memory", "256M", "--cpus", "2", "--limit-nofile", "512"],
+            "--cpus 2 is above the 1 allowed",
+        ),
+        (
+            &[
+                "--memory",
+                "256M",
+                "--cpus",
+                "1",
+                "--limit-nofile",
+                "512:unlimited",
+            ],
+            "--limit-nofile 512:unlimited is above the 1024 allowed",
+        ),
+        (
+            &["--memory", "256M", "--cpus", "1"],
+            "Running without --limit-nofile is not allowed",
+        ),
+    ];
+    for (flags, error) in refused {
+        let mut cmd = Command::cargo_bin("demon").unwrap();
+        cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+            .args(["run", "web"])
+            .args(flags)
+            .args(["--", "sleep", "30"])
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains(error));
+    }
+    std::fs::write(
+        temp_dir.path().join("policy.toml"),
+        "[max]\nlimit_nofile = 1024\n",
+    )
+    .unwrap();
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args([
+            "run",
+            "web",
+            "--limit-nofile",
+            "256:1024",
+            "--",
+            "sleep",
+            "30",
+        ])
+        .assert()
+        .success();
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["stop", "web"])
+        .assert()
+        .success();
+}
+
+#[test]
+fn test_color_flag() {
+    let temp_dir = TempDir::new().unwrap();
+
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["run", "web", "--", "sh", "-c", "echo oops >&2; sleep 30"])
+        .assert()
+        .success();
+    std::thread::sleep(Duration::from_millis(300));
+
+    // Off a terminal there are no colors unless asked for
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["list"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("\x1b[").not());
+
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .env("NO_COLOR", "1")
+        .args(["list", "--flat", "--color", "always"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("\x1b[32mRUNNING\x1b[0m "));
+
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .env("DEMON_COLOR", "always")
+        .args(["status", "web"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Status: \x1b[32mRUNNING\x1b[0m"));
+
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .env("DEMON_COLOR", "always")
+        .args(["tail", "web", "--color", "never"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("\x1b[").not());
+
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["tail", "web", "--color", "always"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("\x1b[31moops\x1b[0m\n"));
+
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["stop", "web"])
+        .assert()
+        .success();
+}
+
+#[test]
+fn test_report_status_page() {
+    let temp_dir = TempDir::new().unwrap();
+    let demon = || {
+        let mut cmd = Command::cargo_bin("demon").unwrap();
+        cmd.env("DEMON_ROOT_DIR", temp_dir.path());
+        cmd
+    };
+    demon()
+        .args(["run", "web", "--", "sh", "-c", "echo listening; sleep 30"])
+        .assert()
+        .success();
+    std::thread::sleep(Duration::from_millis(300));
+
+    demon()
+        .args(["report", "--all"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "| Daemon | Status | PID | Uptime | CPU | Memory | Restarts | Health | Logs |",
+        ))
+        .stdout(predicate::str::contains("| **web** | RUNNING |"))
+        .stdout(predicate::str::contains("[logs](#logs-of-web)"))
+        .stdout(predicate::str::contains("### Logs of web"))
+        .stdout(predicate::str::contains("```text\nlistening\n```"));
+
+    let output = demon()
+        .args(["report", "web", "--format", "json"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["daemons"][0]["id"], "web");
+    assert_eq!(report["daemons"][0]["restarts"], 0);
+    assert_eq!(report["daemons"][0]["stdout_tail"], "listening\n");
+
+    demon().arg("report").assert().failure();
+    demon().args(["stop", "web"]).assert().success();
+}
+
+#[cfg(feature = "log-shipping")]
+#[test]
+fn test_report_bundle() {
+    let temp_dir = TempDir::new().unwrap();
+    let demon = || {
+        let mut cmd = Command::cargo_bin("demon").unwrap();
+        cmd.env("DEMON_ROOT_DIR", temp_dir.path());
+        cmd
+    };
+    demon()
+        .args(["run", "web", "--", "sh", "-c", "seq 1 2000; sleep 30"])
+        .env("API_KEY", "hunter2")
+        .assert()
+        .success();
+    std::thread::sleep(Duration::from_millis(300));
+
+    let bundle = temp_dir.path().join("bundle.tar.gz");
+    demon()
+        .args(["report", "--all", "--bundle"])
+        .arg(&bundle)
+        .args(["--log-kb", "1", "--redact"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("bundle.tar.gz (8 files,"));
+    let extracted = temp_dir.path().join("extracted");
+    fs::create_dir(&extracted).unwrap();
+    let status = std::process::Command::new("tar")
+        .arg("xzf")
+        .arg(&bundle)
+        .arg("-C")
+        .arg(&extracted)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let dir = fs::read_dir(&extracted)
+        .unwrap()
+        .next()
+        .unwrap()
+        .unwrap()
+        .path();
+    assert!(
+        dir.file_name()
+            .unwrap()
+            .to_string_lossy()
+            .starts_with("demon-report-")
+    );
+    let report = fs::read_to_string(dir.join("report.md")).unwrap();
+    assert!(report.contains("| **web** | RUNNING |"));
+    let environment = fs::read_to_string(dir.join("environment.json")).unwrap();
+    assert!(environment.contains("\"demon_version\""));
+    // The last kilobyte of the log, from a whole line
+    let stdout = fs::read_to_string(dir.join("web/stdout.log")).unwrap();
+    assert!(stdout.len() <= 1024);
+    assert!(stdout.ends_with("2000\n"));
+    let first: usize = stdout.lines().next().unwrap().parse().unwrap();
+    assert_eq!(stdout.lines().count(), 2001 - first);
+    let environ = fs::read_to_string(dir.join("web/environ.txt")).unwrap();
+    assert!(environ.contains("API_KEY=[redacted]\n"));
+    assert!(!environ.contains("hunter2"));
+
+    demon()
+        .args(["report", "--all", "--redact"])
+        .assert()
+        .failure();
+    demon().args(["stop", "web"]).assert().success();
+}
+
+#[test]
+fn test_events_log() {
+    let temp_dir = TempDir::new().unwrap();
+
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["events"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("No events recorded."));
+
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["run", "server", "sleep", "30"])
+        .assert()
+        .success();
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["run", "job", "--supervise", "--", "sh", "-c", "exit 1"])
+        .assert()
+        .success();
+    // The supervisor records the crash once it has reaped the job
+    let events = temp_dir.path().join("events.jsonl");
+    let deadline = std::time::Instant::now() + Duration::from_secs(10);
+    while !fs::read_to_string(&events).is_ok_and(|log| log.contains("\"crashed\""))
+        && std::time::Instant::now() < deadline
+    {
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["stop", "server"])
+        .assert()
+        .success();
+
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    let output = cmd
+        .env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["events", "--since", "10m"])
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    // The job can crash before `run` records that it started, so the events
+    // are only in order per daemon
+    let kinds = |id: &str| -> Vec<String> {
+        stdout
+            .lines()
+            .map(|line| line.split_whitespace().collect::<Vec<_>>())
+            .filter(|fields| fields[3] == id)
+            .map(|fields| fields[2].to_string())
+            .collect()
+    };
+    assert_eq!(stdout.lines().count(), 4);
+    assert_eq!(kinds("server"), ["started", "stopped"]);
+    let mut job = kinds("job");
+    job.sort();
+    assert_eq!(job, ["crashed", "started"]);
+
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["events", "--id", "job"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("exit code 1"))
+        .stdout(predicate::str::contains("server").not());
+
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["events", "--since", "yesterday"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Invalid --since"));
+
+    // Durations that overflow are refused, not wrapped around
+    for args in [
+        ["events", "--since", "300000000000000000d"],
+        ["gc", "--max-age", "99999999999999999999"],
+    ] {
+        let mut cmd = Command::cargo_bin("demon").unwrap();
+        cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+            .args(args)
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("duration too large"));
+    }
+}
+
+#[test]
+fn test_hooks_d_plugins() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let temp_dir = TempDir::new().unwrap();
+    let plugins = temp_dir.path().join("hooks.d");
+    fs::create_dir_all(&plugins).unwrap();
+    let record = plugins.join("10-record");
+    fs::write(&record, "#!/bin/sh\ncat >> \"$DEMON_ROOT_DIR/received\"\n").unwrap();
+    // Fails the first time, so the event is delivered again
+    let flaky = plugins.join("20-flaky");
+    fs::write(
+        &flaky,
+        "#!/bin/sh\necho \"$DEMON_EVENT $DEMON_ATTEMPT\" >> \"$DEMON_ROOT_DIR/attempts\"\n[ \"$DEMON_ATTEMPT\" -ge 2 ]\n",
+    )
+    .unwrap();
+    for plugin in [&record, &flaky] {
+        fs::set_permissions(plugin, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["run", "job", "sleep", "30"])
+        .assert()
+        .success();
+
+    let received = temp_dir.path().join("received");
+    let attempts = temp_dir.path().join("attempts");
+    let deadline = std::time::Instant::now() + Duration::from_secs(10);
+    while fs::read_to_string(&attempts).map_or(0, |s| s.lines().count()) < 2
+        && std::time::Instant::now() < deadline
+    {
+        std::thread::sleep(Duration::from_millis(100));
+    }
+
+    let event: serde_json::Value =
+        serde_json::from_str(fs::read_to_string(&received).unwrap().trim()).unwrap();
+    assert_eq!(event["version"], 1);
+    assert_eq!(event["id"], "job");
+    assert_eq!(event["event"], "started");
+    assert!(event["pid"].is_u64());
+    assert_eq!(
+        fs::read_to_string(&attempts).unwrap(),
+        "started 1\nstarted 2\n"
+    );
+    let log = fs::read_to_string(temp_dir.path().join("hooks.log")).unwrap();
+    assert!(log.contains("plugin '20-flaky' failed on the 'started' event of 'job' (attempt 1/3)"));
+
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["stop", "job"])
+        .assert()
+        .success();
+}
+
+#[test]
+fn test_dev_restarts_on_change() {
+    let temp_dir = TempDir::new().unwrap();
+    let root = temp_dir.path().join("root");
+    let src = temp_dir.path().join("src");
+    fs::create_dir_all(&root).unwrap();
+    fs::create_dir_all(&src).unwrap();
+    fs::write(src.join("main.txt"), "v1").unwrap();
+
+    let dev = std::process::Command::new(assert_cmd::cargo::cargo_bin("demon"))
+        .env("DEMON_ROOT_DIR", &root)
+        .args(["dev", "app", "--watch"])
+        .arg(&src)
+        .args(["--debounce", "100", "--"])
+        .args(["sh", "-c", "echo listening; echo warn >&2; sleep 30"])
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .unwrap();
+
+    let events = root.join("events.jsonl");
+    let wait_for = |text: &str| {
+        for _ in 0..50 {
+            if fs::read_to_string(&events).is_ok_and(|e| e.contains(text)) {
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(100));
+        }
+        panic!("no '{text}' event");
+    };
+    wait_for("\"started\"");
+    let first_pid = fs::read_to_string(root.join("app/app.pid")).unwrap();
+
+    fs::write(src.join("main.txt"), "v2").unwrap();
+    wait_for("\"restarted\"");
+    let second_pid = fs::read_to_string(root.join("app/app.pid")).unwrap();
+    assert_ne!(first_pid, second_pid);
+
+    std::process::Command::new("kill")
+        .args(["-INT", &dev.id().to_string()])
+        .status()
+        .unwrap();
+    let output = dev.wait_with_output().unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("app | listening"));
+    assert!(stdout.contains("app | warn"));
+    assert!(stdout.contains("ready in"));
+    assert!(stdout.contains("main.txt changed, restarting"));
+
+    // Ctrl+C stops the daemon and removes its PID file
+    assert!(!root.join("app/app.pid").exists());
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", &root)
+        .args(["history", "app"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("running").not());
+}
+
+#[test]
+fn test_run_hooks() {
+    let temp_dir = TempDir::new().unwrap();
+    let log = temp_dir.path().join("hooks.log");
+    let hook = temp_dir.path().join("hook.sh");
+    fs::write(
+        &hook,
+        format!(
+            "#!/bin/sh\necho \"$DEMON_EVENT $DEMON_ID code=$DEMON_EXIT_CODE\" >> {}\n",
+            log.display()
+        ),
+    )
+    .unwrap();
+    std::process::Command::new("chmod")
+        .arg("+x")
+        .arg(&hook)
+        .status()
+        .unwrap();
+
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["run", "failing", "--on-start"])
+        .arg(&hook)
+        .arg("--on-crash")
+        .arg(&hook)
+        .args(["--", "sh", "-c", "sleep 0.2; exit 3"])
+        .assert()
+        .success();
+
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["run", "server", "--on-exit"])
+        .arg(&hook)
+        .args(["sleep", "30"])
+        .assert()
+        .success();
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["stop", "server"])
+        .assert()
+        .success();
+
+    std::thread::sleep(Duration::from_millis(800));
+    let mut lines: Vec<String> = fs::read_to_string(&log)
+        .unwrap()
+        .lines()
+        .map(String::from)
+        .collect();
+    lines.sort();
+    assert_eq!(
+        lines,
+        [
+            "crashed failing code=3",
+            "started failing code=",
+            "stopped server code="
+        ]
+    );
+
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["run", "broken", "--on-exit", "missing.sh", "true"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Invalid --on-exit hook"));
+}
+
+#[test]
+fn test_tail_follow_survives_truncation() {
+    use std::io::Write;
+
+    let temp_dir = TempDir::new().unwrap();
+    fs::create_dir(temp_dir.path().join("app")).unwrap();
+    let log = temp_dir.path().join("app/app.stdout");
+    fs::write(&log, "one\n").unwrap();
+
+    // Two independent followers of the same log
+    let spawn_tail = || {
+        std::process::Command::new(assert_cmd::cargo::cargo_bin("demon"))
+            .env("DEMON_ROOT_DIR", temp_dir.path())
+            .args(["tail", "-f", "--stdout", "app"])
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::null())
+            .spawn()
+            .unwrap()
+    };
+    let tails = [spawn_tail(), spawn_tail()];
+    std::thread::sleep(Duration::from_millis(700));
+
+    // Truncate and rewrite with content of the same size, as a restart does
+    fs::write(&log, "two\n").unwrap();
+    std::thread::sleep(Duration::from_millis(500));
+    fs::OpenOptions::new()
+        .append(true)
+        .open(&log)
+        .unwrap()
+        .write_all(b"three\n")
+        .unwrap();
+    std::thread::sleep(Duration::from_millis(500));
+
+    for tail in tails {
+        std::process::Command::new("kill")
+            .args(["-INT", &tail.id().to_string()])
+            .status()
+            .unwrap();
+        let output = tail.wait_with_output().unwrap();
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        assert!(
+            stdout.starts_with("one\ntwo\nthree\n"),
+            "unexpected output: {stdout}"
+        );
+    }
+}
+
+#[test]
+fn test_tail_reads_last_lines_of_large_log() {
+    use std::io::Write;
+
+    let temp_dir = TempDir::new().unwrap();
+    fs::create_dir(temp_dir.path().join("app")).unwrap();
+    let log = temp_dir.path().join("app/app.stdout");
+    // Several read chunks long
+    let contents: String = (1..=100_000).map(|n| format!("line {n}\n")).collect();
+    fs::write(&log, contents).unwrap();
+
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["tail", "--stdout", "-n", "3", "app"])
+        .assert()
+        .success()
+        .stdout("line 99998\nline 99999\nline 100000\n");
+
+    // -f starts from the last lines too
+    let tail = std::process::Command::new(assert_cmd::cargo::cargo_bin("demon"))
+        .env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["tail", "-f", "--stdout", "-n", "2", "app"])
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .unwrap();
+    std::thread::sleep(Duration::from_millis(700));
+    fs::OpenOptions::new()
+        .append(true)
+        .open(&log)
+        .unwrap()
+        .write_all(b"line 100001\n")
+        .unwrap();
+    std::thread::sleep(Duration::from_millis(500));
+
+    std::process::Command::new("kill")
+        .args(["-INT", &tail.id().to_string()])
+        .status()
+        .unwrap();
+    let output = tail.wait_with_output().unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(
+        stdout.starts_with("line 99999\nline 100000\nline 100001\n"),
+        "unexpected output: {stdout}"
+    );
+}
+
+#[test]
+fn test_tail_follow_until_exit() {
+    let temp_dir = TempDir::new().unwrap();
+
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args([
+            "run",
+            "job",
+            "--",
+            "sh",
+            "-c",
+            "echo first; sleep 0.5; echo last",
+        ])
+        .assert()
+        .success();
+
+    // Ends by itself once the job is done, with everything it wrote
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["tail", "-f", "--until-exit", "--stdout", "job"])
+        .timeout(Duration::from_secs(10))
+        .assert()
+        .success()
+        .stdout("first\nlast\n");
+}
+
+#[test]
+fn test_tail_follow_batches_and_max_rate() {
+    let temp_dir = TempDir::new().unwrap();
+
+    // About 2 MB, more than one batch, written while the tails follow
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args([
+            "run",
+            "burst",
+            "--",
+            "sh",
+            "-c",
+            "sleep 0.5; seq 1 300000; sleep 1.2",
+        ])
+        .assert()
+        .success();
+
+    let tail = |args: &[&str]| {
+        let mut cmd = std::process::Command::new(assert_cmd::cargo::cargo_bin("demon"));
+        cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+            .args(["tail", "-f", "--until-exit", "--stdout", "-n", "0", "burst"])
+            .args(args)
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::null());
+        cmd.spawn().unwrap()
+    };
+    let all = tail(&["--flush-interval", "0"]);
+    let sampled = tail(&["--max-rate", "5"]);
+
+    let output = all.wait_with_output().unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let expected: String = (1..=300000).map(|n| format!("{n}\n")).collect();
+    assert!(stdout == expected, "lines lost or reordered");
+
+    let output = sampled.wait_with_output().unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.starts_with("1\n2\n3\n4\n5\n"), "{stdout}");
+    assert!(
+        stdout.contains(" lines dropped over --max-rate 5]\n"),
+        "{stdout}"
+    );
+    assert!(stdout.lines().count() < 30, "{stdout}");
+}
+
+#[test]
+fn test_tail_follow_polling() {
+    use std::io::Write;
+
+    let temp_dir = TempDir::new().unwrap();
+    fs::create_dir(temp_dir.path().join("app")).unwrap();
+    let log = temp_dir.path().join("app/app.stdout");
+    fs::write(&log, "one\n").unwrap();
+
+    // Forced by the flag and by the config key
+    let spawn_tail = |flag: Option<&str>, env: &str| {
+        let mut cmd = std::process::Command::new(assert_cmd::cargo::cargo_bin("demon"));
+        cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+            .env("DEMON_WATCH_POLL", env)
+            .args(["tail", "-f", "--stdout", "app"])
+            .args(flag)
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::null());
+        cmd.spawn().unwrap()
+    };
+    let tails = [
+        spawn_tail(Some("--no-notify-watch"), "false"),
+        spawn_tail(None, "true"),
+    ];
+    std::thread::sleep(Duration::from_millis(700));
+
+    fs::write(&log, "two\n").unwrap();
+    std::thread::sleep(Duration::from_millis(600));
+    fs::OpenOptions::new()
+        .append(true)
+        .open(&log)
+        .unwrap()
+        .write_all(b"three\n")
+        .unwrap();
+    std::thread::sleep(Duration::from_millis(600));
+
+    for tail in tails {
+        std::process::Command::new("kill")
+            .args(["-INT", &tail.id().to_string()])
+            .status()
+            .unwrap();
+        let output = tail.wait_with_output().unwrap();
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        assert!(
+            stdout.starts_with("one\ntwo\nthree\n"),
+            "unexpected output: {stdout}"
+        );
+    }
+}
+
+#[test]
+fn test_list_watch_redraws_on_changes() {
+    let temp_dir = TempDir::new().unwrap();
+
+    // The interval is long, so the second drawing comes from the change
+    let watch = std::process::Command::new(assert_cmd::cargo::cargo_bin("demon"))
+        .env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["list", "--watch=60s", "-q"])
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .unwrap();
+    std::thread::sleep(Duration::from_millis(500));
+
     let mut cmd = Command::cargo_bin("demon").unwrap();
-    cmd.args(&["llm"])
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["run", "svc", "sleep", "30"])
         .assert()
-        .success()
-        .stdout(predicate::str::contains(
-            "# Demon - Daemon Process Management CLI",
-        ))
-        .stdout(predicate::str::contains("## Available Commands"))
-        .stdout(predicate::str::contains("demon run"))
-        .stdout(predicate::str::contains("demon stop"))
-        .stdout(predicate::str::contains("demon list"))
-        .stdout(predicate::str::contains("demon tail"))
-        .stdout(predicate::str::contains("demon cat"))
-        .stdout(predicate::str::contains("demon status"))
-        .stdout(predicate::str::contains("demon clean"))
-        .stdout(predicate::str::contains("demon wait"))
-        .stdout(predicate::str::contains("Common Workflows"))
-        .stdout(predicate::str::contains("Best Practices"))
-        .stdout(predicate::str::contains("Integration Tips"));
+        .success();
+    std::thread::sleep(Duration::from_millis(700));
+
+    std::process::Command::new("kill")
+        .args(["-INT", &watch.id().to_string()])
+        .status()
+        .unwrap();
+    let output = watch.wait_with_output().unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let drawings: Vec<&str> = stdout.split("Every 1m00s: demon list").skip(1).collect();
+    assert!(drawings.len() >= 2, "unexpected output: {stdout}");
+    assert!(!drawings[0].contains("svc:"), "unexpected output: {stdout}");
+    assert!(
+        drawings.last().unwrap().contains(":RUNNING"),
+        "unexpected output: {stdout}"
+    );
+
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["stop", "svc"])
+        .assert()
+        .success();
 }
 
 #[test]
-fn test_wait_nonexistent_process() {
+fn test_notify_url_posts_crash() {
+    use std::io::{Read, Write};
+
     let temp_dir = TempDir::new().unwrap();
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/hook", listener.local_addr().unwrap());
+
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
+        .args([
+            "run",
+            "job",
+            "--notify-url",
+            &url,
+            "--",
+            "sh",
+            "-c",
+            "exit 4",
+        ])
+        .assert()
+        .success();
+
+    let (mut stream, _) = listener.accept().unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let mut request = Vec::new();
+    let mut buffer = [0; 4096];
+    while !String::from_utf8_lossy(&request).contains("}") {
+        let n = stream.read(&mut buffer).unwrap();
+        assert!(n > 0, "connection closed before the body was sent");
+        request.extend_from_slice(&buffer[..n]);
+    }
+    stream
+        .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+        .unwrap();
+
+    let request = String::from_utf8(request).unwrap();
+    assert!(request.starts_with("POST /hook"));
+    let body = &request[request.find("\r\n\r\n").unwrap() + 4..];
+    let payload: serde_json::Value = serde_json::from_str(body).unwrap();
+    assert_eq!(payload["event"], "crashed");
+    assert_eq!(payload["id"], "job");
+    assert_eq!(payload["detail"], "exit code 4");
 
     let mut cmd = Command::cargo_bin("demon").unwrap();
     cmd.env("DEMON_ROOT_DIR", temp_dir.path())
-        .args(&["wait", "nonexistent"])
+        .args(["run", "bad", "--notify-url", "ftp://example.com", "true"])
         .assert()
         .failure()
-        .stderr(predicate::str::contains("not found"));
+        .stderr(predicate::str::contains("Invalid --notify-url"));
 }
 
 #[test]
-fn test_wait_already_dead_process() {
+fn test_trace_context() {
     let temp_dir = TempDir::new().unwrap();
 
-    // Create a short-lived process
     let mut cmd = Command::cargo_bin("demon").unwrap();
     cmd.env("DEMON_ROOT_DIR", temp_dir.path())
-        .args(&["run", "dead", "echo", "hello"])
+        .env("OTEL_RESOURCE_ATTRIBUTES", "team=infra")
+        .args([
+            "run",
+            "traced",
+            "--trace-context",
+            "--",
+            "sh",
+            "-c",
+            "echo $TRACEPARENT; echo $OTEL_RESOURCE_ATTRIBUTES",
+        ])
         .assert()
         .success();
+    std::thread::sleep(Duration::from_millis(300));
 
-    // Give it time to finish
-    std::thread::sleep(Duration::from_millis(100));
+    let output = std::fs::read_to_string(temp_dir.path().join("traced/traced.stdout")).unwrap();
+    let mut lines = output.lines();
+    let traceparent: Vec<&str> = lines.next().unwrap().split('-').collect();
+    assert_eq!(traceparent.len(), 4);
+    assert_eq!(traceparent[0], "00");
+    assert_eq!(traceparent[1].len(), 32);
+    assert_eq!(traceparent[2].len(), 16);
+    assert_eq!(traceparent[3], "01");
+    assert_eq!(
+        lines.next().unwrap(),
+        format!("team=infra,demon.id=traced,demon.run_id={}", traceparent[1])
+    );
 
-    // Try to wait for it (should fail since it's already dead)
     let mut cmd = Command::cargo_bin("demon").unwrap();
     cmd.env("DEMON_ROOT_DIR", temp_dir.path())
-        .args(&["wait", "dead"])
+        .args(["run", "bad", "--otel-endpoint", "localhost:4318", "true"])
         .assert()
         .failure()
-        .stderr(predicate::str::contains("not running"));
+        .stderr(predicate::str::contains("Invalid --otel-endpoint"));
 }
 
 #[test]
-fn test_wait_process_terminates() {
+fn test_agent_restart_health_and_schedule() {
     let temp_dir = TempDir::new().unwrap();
+    let root = temp_dir.path();
 
-    // Start a process that will run for 2 seconds
+    let agent = std::process::Command::new(assert_cmd::cargo::cargo_bin("demon"))
+        .env("DEMON_ROOT_DIR", root)
+        .arg("agent")
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .unwrap();
+    for _ in 0..50 {
+        if root.join(".agent/.agent.sock").exists() {
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+
+    let run = |args: &[&str]| {
+        let mut cmd = Command::cargo_bin("demon").unwrap();
+        cmd.env("DEMON_ROOT_DIR", root)
+            .arg("run")
+            .args(args)
+            .assert()
+            .success();
+    };
+    run(&[
+        "flaky",
+        "--supervise",
+        "--restart",
+        "on-failure",
+        "--",
+        "sh",
+        "-c",
+        "exit 1",
+    ]);
+    run(&[
+        "done",
+        "--supervise",
+        "--restart",
+        "on-failure",
+        "--",
+        "true",
+    ]);
+    run(&[
+        "sick",
+        "--health-cmd",
+        "false",
+        "--health-interval",
+        "1",
+        "--",
+        "sleep",
+        "30",
+    ]);
+    let runs = root.join("runs");
+    let job = format!("echo run >> {}", runs.display());
+    run(&["job", "--every", "1s", "--", "sh", "-c", &job]);
+
+    let events = root.join("events.jsonl");
+    let wait_for = |check: &dyn Fn(&str) -> bool, what: &str| {
+        for _ in 0..100 {
+            if fs::read_to_string(&events).is_ok_and(|e| check(&e)) {
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(100));
+        }
+        panic!("timed out waiting for {what}");
+    };
+    wait_for(
+        &|e| e.contains("\"id\":\"flaky\",\"event\":\"restarted\""),
+        "a restart of flaky",
+    );
+    wait_for(
+        &|e| e.contains("\"id\":\"sick\",\"event\":\"unhealthy\""),
+        "sick to be unhealthy",
+    );
+    wait_for(
+        &|_| fs::read_to_string(&runs).is_ok_and(|r| r.lines().count() >= 2),
+        "the job to run twice",
+    );
+    // Exiting successfully is not a failure, and a restart policy is needed to
+    // restart an unhealthy daemon
+    let log = fs::read_to_string(&events).unwrap();
+    assert!(!log.contains("\"id\":\"done\",\"event\":\"restarted\""));
+    assert!(!log.contains("\"id\":\"sick\",\"event\":\"restarted\""));
+
+    // flaky may be dead, waiting to be restarted
     let mut cmd = Command::cargo_bin("demon").unwrap();
-    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
-        .args(&["run", "short", "sleep", "2"])
+    cmd.env("DEMON_ROOT_DIR", root)
+        .args(["status", "flaky"])
         .assert()
-        .success();
+        .code(predicate::in_iter([0, 3]))
+        .stdout(predicate::str::contains("Restart policy: on-failure"))
+        .stdout(predicate::str::contains("Agent: PID"));
+
+    // A stopped daemon stays stopped; the ones between runs were not running
+    for id in ["flaky", "sick", "job"] {
+        let mut cmd = Command::cargo_bin("demon").unwrap();
+        cmd.env("DEMON_ROOT_DIR", root)
+            .args(["stop", id])
+            .assert()
+            .code(predicate::in_iter([0, 3]));
+    }
 
-    // Wait for it with a 5-second timeout (should succeed)
     let mut cmd = Command::cargo_bin("demon").unwrap();
-    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
-        .args(&["wait", "short", "--timeout", "5"])
+    cmd.env("DEMON_ROOT_DIR", root)
+        .args(["agent", "--stop"])
         .assert()
-        .success();
+        .success()
+        .stdout(predicate::str::contains("stopped"));
+    let output = agent.wait_with_output().unwrap();
+    assert!(output.status.success());
+    assert!(!root.join(".agent/.agent.sock").exists());
+    assert!(!root.join("flaky/flaky.pid").exists());
 }
 
 #[test]
-fn test_wait_timeout() {
+fn test_schedule_list_and_catch_up() {
     let temp_dir = TempDir::new().unwrap();
+    let root = temp_dir.path();
 
-    // Start a long-running process
     let mut cmd = Command::cargo_bin("demon").unwrap();
-    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
-        .args(&["run", "long", "sleep", "10"])
+    cmd.env("DEMON_ROOT_DIR", root)
+        .args([
+            "run",
+            "job",
+            "--every",
+            "3s",
+            "--catch-up",
+            "skip",
+            "--",
+            "true",
+        ])
         .assert()
         .success();
+    let schedule = || {
+        let mut cmd = Command::cargo_bin("demon").unwrap();
+        let output = cmd
+            .env("DEMON_ROOT_DIR", root)
+            .args(["schedule", "list", "--format", "json"])
+            .output()
+            .unwrap();
+        let jobs: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+        jobs[0].clone()
+    };
+    let job = schedule();
+    assert_eq!(job["id"], "job");
+    assert_eq!(job["every_secs"], 3);
+    assert_eq!(job["catch_up"], "skip");
+    assert_eq!(job["missed"], 0);
+    let last_run = job["last_run"].as_u64().unwrap();
+
+    // Two triggers pass without an agent, as if the machine slept
+    std::thread::sleep(Duration::from_secs(7));
+    let agent = std::process::Command::new(assert_cmd::cargo::cargo_bin("demon"))
+        .env("DEMON_ROOT_DIR", root)
+        .arg("agent")
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .unwrap();
+    let mut job = schedule();
+    for _ in 0..30 {
+        if job["missed"] != 0 {
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(100));
+        job = schedule();
+    }
+    // Skipped: not started again, the next run is the next trigger
+    assert_eq!(job["missed"], 2);
+    assert_eq!(job["last_run"], last_run);
+    assert_eq!(job["next_run"], last_run + 9);
+    assert!(root.join("schedule.json").exists());
 
-    // Wait with a very short timeout (should fail)
     let mut cmd = Command::cargo_bin("demon").unwrap();
-    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
-        .args(&["wait", "long", "--timeout", "2"])
+    cmd.env("DEMON_ROOT_DIR", root)
+        .args(["schedule", "list"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("CATCH-UP"))
+        .stdout(predicate::str::contains("skip"));
+
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", root)
+        .args(["agent", "--stop"])
+        .assert()
+        .success();
+    agent.wait_with_output().unwrap();
+}
+
+#[test]
+fn test_schedule_cron_jobs() {
+    let temp_dir = TempDir::new().unwrap();
+    let root = temp_dir.path();
+
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", root)
+        .args([
+            "schedule",
+            "add",
+            "job",
+            "--cron",
+            "* 25 * * *",
+            "--",
+            "true",
+        ])
         .assert()
         .failure()
-        .stderr(predicate::str::contains("Timeout reached"));
+        .stderr(predicate::str::contains("Invalid hour '25'"));
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", root)
+        .args([
+            "schedule",
+            "add",
+            "job",
+            "--cron",
+            "*/5 * * * *",
+            "--",
+            "sh",
+            "-c",
+            "echo tick",
+        ])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Added cron job 'job'"));
+    // Not started until its trigger
+    assert!(!root.join("job/job.pid").exists());
+
+    // Three triggers passed while no agent was running
+    let state_path = root.join("schedule.json");
+    let mut state: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&state_path).unwrap()).unwrap();
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let due = (now / 300 - 2) * 300;
+    state["job"]["next_run"] = due.into();
+    fs::write(&state_path, state.to_string()).unwrap();
+
+    let agent = std::process::Command::new(assert_cmd::cargo::cargo_bin("demon"))
+        .env("DEMON_ROOT_DIR", root)
+        .arg("agent")
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .unwrap();
+    let schedule = || {
+        let mut cmd = Command::cargo_bin("demon").unwrap();
+        let output = cmd
+            .env("DEMON_ROOT_DIR", root)
+            .args(["schedule", "list", "--format", "json"])
+            .output()
+            .unwrap();
+        let jobs: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+        jobs[0].clone()
+    };
+    let mut job = schedule();
+    for _ in 0..50 {
+        if root.join("job/job.pid").exists() && job["running"] == false {
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(100));
+        job = schedule();
+    }
+    assert_eq!(job["cron"], "*/5 * * * *");
+    assert_eq!(job["every_secs"], serde_json::Value::Null);
+    // Made up for with one run
+    assert_eq!(job["missed"], 2);
+    assert!(job["next_run"].as_u64().unwrap() > now);
 
-    // Clean up the still-running process
     let mut cmd = Command::cargo_bin("demon").unwrap();
-    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
-        .args(&["stop", "long"])
+    cmd.env("DEMON_ROOT_DIR", root)
+        .args(["history", "job"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("sh -c echo tick"));
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", root)
+        .args(["cat", "job", "--stdout"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("tick"));
+
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", root)
+        .args(["schedule", "remove", "job"])
+        .assert()
+        .success();
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", root)
+        .args(["schedule", "remove", "job"])
+        .assert()
+        .code(4);
+
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", root)
+        .args(["agent", "--stop"])
         .assert()
         .success();
+    agent.wait_with_output().unwrap();
 }
 
 #[test]
-fn test_wait_infinite_timeout() {
+fn test_cp_logs_records_checksum() {
+    use sha2::{Digest, Sha256};
+
     let temp_dir = TempDir::new().unwrap();
+    let root = temp_dir.path().join("root");
+    let dest = temp_dir.path().join("copies");
+    fs::create_dir_all(&root).unwrap();
 
-    // Start a short process that will finish quickly
     let mut cmd = Command::cargo_bin("demon").unwrap();
-    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
-        .args(&["run", "quick", "sleep", "1"])
+    cmd.env("DEMON_ROOT_DIR", &root)
+        .args(["run", "chatty", "--", "sh", "-c"])
+        .arg("while true; do echo out; echo err >&2; done")
         .assert()
         .success();
+    std::thread::sleep(Duration::from_millis(300));
 
-    // Wait with infinite timeout (should succeed quickly)
     let mut cmd = Command::cargo_bin("demon").unwrap();
-    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
-        .args(&["wait", "quick", "--timeout", "0"])
+    cmd.env("DEMON_ROOT_DIR", &root)
+        .args(["cp-logs", "chatty"])
+        .arg(&dest)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("sha256"));
+
+    // Every copy is a prefix of the log that kept growing, with the recorded checksum
+    let history = fs::read_to_string(root.join("history.jsonl")).unwrap();
+    let record: serde_json::Value = serde_json::from_str(history.lines().last().unwrap()).unwrap();
+    let files = record["files"].as_array().unwrap();
+    assert_eq!(files.len(), 2);
+    for file in files {
+        let copy = fs::read(file["location"].as_str().unwrap()).unwrap();
+        assert_eq!(copy.len() as u64, file["bytes"].as_u64().unwrap());
+        assert_eq!(format!("{:x}", Sha256::digest(&copy)), file["sha256"]);
+        let stream = file["stream"].as_str().unwrap();
+        let log = fs::read(root.join(format!("chatty/chatty.{stream}"))).unwrap();
+        assert!(log.starts_with(&copy));
+    }
+
+    // URLs go through the configured uploader
+    let uploaded = temp_dir.path().join("uploaded");
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", &root)
+        .env(
+            "DEMON_LOGS_UPLOADER",
+            format!("echo \"$DEMON_URL\" > {}", uploaded.display()),
+        )
+        .args(["cp-logs", "chatty", "store://bucket/logs/", "--stdout"])
+        .assert()
+        .success();
+    let url = fs::read_to_string(&uploaded).unwrap();
+    assert!(url.starts_with("store://bucket/logs/chatty-"));
+    assert!(url.trim_end().ends_with(".stdout"));
+
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", &root)
+        .args(["cp-logs", "chatty", "store://bucket"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("No uploader for store://"));
+
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", &root)
+        .args(["history", "chatty"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("LOGS COPIED"));
+
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", &root)
+        .args(["stop", "chatty"])
         .assert()
         .success();
 }
 
+#[cfg(feature = "log-shipping")]
 #[test]
-fn test_wait_custom_interval() {
+fn test_ship_logs_of_finished_runs() {
+    use sha2::{Digest, Sha256};
+
     let temp_dir = TempDir::new().unwrap();
+    let root = temp_dir.path().join("root");
+    let dest = temp_dir.path().join("shipped");
+    fs::create_dir_all(&root).unwrap();
 
-    // Start a short process
+    let shipped = |suffix: &str| -> Vec<std::path::PathBuf> {
+        let Ok(entries) = fs::read_dir(&dest) else {
+            return Vec::new();
+        };
+        entries
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.to_string_lossy().ends_with(suffix))
+            .collect()
+    };
+
+    // Shipped by `demon stop`
     let mut cmd = Command::cargo_bin("demon").unwrap();
-    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
-        .args(&["run", "interval-test", "sleep", "2"])
+    cmd.env("DEMON_ROOT_DIR", &root)
+        .args(["run", "job", "--ship-logs"])
+        .arg(&dest)
+        .args(["--", "sh", "-c", "echo hello; sleep 30"])
+        .assert()
+        .success();
+    std::thread::sleep(Duration::from_millis(300));
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", &root)
+        .args(["stop", "job"])
         .assert()
         .success();
 
-    // Wait with custom interval (should still succeed)
+    let files = shipped(".stdout.gz");
+    assert_eq!(files.len(), 1);
+    assert!(shipped(".stderr.gz").is_empty());
+    let name = files[0].file_name().unwrap().to_string_lossy().to_string();
+    assert!(name.starts_with("job-"));
+    let gz = fs::read(&files[0]).unwrap();
+    assert_eq!(&gz[..2], &[0x1f, 0x8b]);
+
+    let history = fs::read_to_string(root.join("history.jsonl")).unwrap();
+    let record = history
+        .lines()
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+        .find(|record| record.get("files").is_some())
+        .unwrap();
+    let file = &record["files"][0];
+    assert_eq!(file["stream"], "stdout");
+    assert_eq!(file["bytes"].as_u64().unwrap(), gz.len() as u64);
+    assert_eq!(format!("{:x}", Sha256::digest(&gz)), file["sha256"]);
+
+    // A run nobody saw finish is shipped by the next run, before its logs are truncated
     let mut cmd = Command::cargo_bin("demon").unwrap();
-    cmd.env("DEMON_ROOT_DIR", temp_dir.path())
-        .args(&["wait", "interval-test", "--timeout", "5", "--interval", "2"])
+    cmd.env("DEMON_ROOT_DIR", &root)
+        .env("DEMON_LOGS_SHIP_URL", &dest)
+        .args(["run", "quick", "--", "echo", "done"])
+        .assert()
+        .success();
+    std::thread::sleep(Duration::from_millis(300));
+    assert!(shipped(".stdout.gz").iter().all(|path| {
+        !path
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .starts_with("quick-")
+    }));
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", &root)
+        .env("DEMON_LOGS_SHIP_URL", &dest)
+        .args(["run", "quick", "--", "sleep", "30"])
+        .assert()
+        .success();
+    assert_eq!(shipped(".stdout.gz").len(), 2);
+
+    let mut cmd = Command::cargo_bin("demon").unwrap();
+    cmd.env("DEMON_ROOT_DIR", &root)
+        .args(["stop", "quick"])
+        .assert()
+        .success();
+}
+
+#[test]
+fn test_serve_upgrade() {
+    use std::io::{BufRead, Read, Write};
+
+    let temp_dir = TempDir::new().unwrap();
+    let listen = format!("127.0.0.1:{}", free_port());
+    let mut server = std::process::Command::new(assert_cmd::cargo::cargo_bin("demon"))
+        .env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["serve", "--listen", &listen])
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .unwrap();
+    let mut server_stdout = std::io::BufReader::new(server.stdout.take().unwrap());
+    let mut announcement = String::new();
+    server_stdout.read_line(&mut announcement).unwrap();
+    let get = || {
+        let mut stream = std::net::TcpStream::connect(&listen).unwrap();
+        write!(stream, "GET /daemons HTTP/1.1\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    };
+    assert!(get().starts_with("HTTP/1.1 200"));
+
+    Command::cargo_bin("demon")
+        .unwrap()
+        .env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["serve", "--upgrade"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(format!(
+            "demon serve (PID {}) on http://{listen} upgraded",
+            server.id()
+        )));
+    announcement.clear();
+    server_stdout.read_line(&mut announcement).unwrap();
+    assert!(
+        announcement.starts_with("Upgraded to demon"),
+        "{announcement}"
+    );
+    assert!(get().starts_with("HTTP/1.1 200"));
+    assert!(server.try_wait().unwrap().is_none());
+
+    server.kill().unwrap();
+    server.wait().unwrap();
+    Command::cargo_bin("demon")
+        .unwrap()
+        .env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["serve", "--upgrade"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("No demon serve is running"));
+}
+
+#[test]
+fn test_serve_api() {
+    use std::io::{BufRead, Read, Write};
+
+    let temp_dir = TempDir::new().unwrap();
+    let listen = format!("127.0.0.1:{}", free_port());
+
+    let mut server = std::process::Command::new(assert_cmd::cargo::cargo_bin("demon"))
+        .env("DEMON_ROOT_DIR", temp_dir.path())
+        .args(["serve", "--listen", &listen, "--token", "secret"])
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .unwrap();
+    let mut server_stdout = std::io::BufReader::new(server.stdout.take().unwrap());
+    let mut announcement = String::new();
+    server_stdout.read_line(&mut announcement).unwrap();
+    assert!(announcement.contains(&listen));
+
+    let request = |method: &str, path: &str, token: &str, body: &str| {
+        let mut stream = std::net::TcpStream::connect(&listen).unwrap();
+        write!(
+            stream,
+            "{method} {path} HTTP/1.1\r\nAuthorization: Bearer {token}\r\nContent-Length: {}\r\n\r\n{body}",
+            body.len()
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        let status: u16 = head.split_whitespace().nth(1).unwrap().parse().unwrap();
+        (
+            status,
+            serde_json::from_str::<serde_json::Value>(body).unwrap(),
+        )
+    };
+
+    assert_eq!(request("GET", "/daemons", "wrong", "").0, 401);
+
+    // The guides are served without a token
+    let (status, tools) = request("GET", "/tools.json", "", "");
+    assert_eq!(status, 200);
+    assert!(tools["commands"].as_array().unwrap().len() > 10);
+    assert_eq!(tools["http_api"]["auth"], "bearer");
+    assert!(
+        tools["http_api"]["endpoints"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|endpoint| endpoint["path"] == "/daemons/<id>/run")
+    );
+    let mut stream = std::net::TcpStream::connect(&listen).unwrap();
+    stream.write_all(b"GET /llms.txt HTTP/1.1\r\n\r\n").unwrap();
+    let mut guide = String::new();
+    stream.read_to_string(&mut guide).unwrap();
+    assert!(guide.contains("Content-Type: text/markdown"));
+    assert!(guide.contains("- `POST /daemons/<id>/stop?timeout=<seconds>`"));
+    assert!(guide.contains("### demon run"));
+
+    let run = r#"{"command": ["sh", "-c", "echo hello; sleep 30"]}"#;
+    let (status, body) = request("POST", "/daemons/api/run", "secret", run);
+    assert_eq!(status, 201);
+    assert!(body["pid"].as_u64().is_some());
+    assert_eq!(request("POST", "/daemons/api/run", "secret", run).0, 409);
+
+    let (status, body) = request("GET", "/daemons", "secret", "");
+    assert_eq!(status, 200);
+    assert_eq!(body[0]["id"], "api");
+    assert_eq!(body[0]["status"], "RUNNING");
+
+    let (status, body) = request("GET", "/daemons/api", "secret", "");
+    assert_eq!(status, 200);
+    assert_eq!(body["command"][0], "sh");
+
+    std::thread::sleep(Duration::from_millis(200));
+    let (status, body) = request("GET", "/daemons/api/logs?stream=stdout", "secret", "");
+    assert_eq!(status, 200);
+    assert_eq!(body["stdout"], "hello\n");
+
+    assert_eq!(request("GET", "/daemons/.hidden", "secret", "").0, 400);
+    assert_eq!(request("GET", "/daemons/missing", "secret", "").0, 404);
+
+    // Server-sent events carry lines written after the stream was opened
+    let mut tail = std::net::TcpStream::connect(&listen).unwrap();
+    tail.write_all(
+        b"GET /daemons/api/tail?stream=stdout HTTP/1.1\r\nAuthorization: Bearer secret\r\n\r\n",
+    )
+    .unwrap();
+    let mut tail = std::io::BufReader::new(tail);
+    let mut line = String::new();
+    tail.read_line(&mut line).unwrap();
+    assert!(line.starts_with("HTTP/1.1 200"));
+    std::thread::sleep(Duration::from_millis(300));
+    fs::OpenOptions::new()
+        .append(true)
+        .open(temp_dir.path().join("api/api.stdout"))
+        .unwrap()
+        .write_all(b"pushed\n")
+        .unwrap();
+    let mut received = String::new();
+    while !received.contains("data: pushed") {
+        line.clear();
+        assert!(tail.read_line(&mut line).unwrap() > 0);
+        received.push_str(&line);
+    }
+    assert!(received.contains("event: stdout\ndata: pushed\n"));
+    assert!(!received.contains("hello"));
+
+    let (status, body) = request("POST", "/daemons/api/stop?timeout=2", "secret", "");
+    assert_eq!(status, 200);
+    assert_eq!(body["stopped"], true);
+    assert!(!temp_dir.path().join("api/api.pid").exists());
+
+    server.kill().unwrap();
+    server.wait().unwrap();
+}
+
+#[test]
+fn test_gc_retention() {
+    let temp_dir = TempDir::new().unwrap();
+    let root = temp_dir.path().join("root");
+    fs::create_dir_all(&root).unwrap();
+    let demon = || {
+        let mut cmd = Command::cargo_bin("demon").unwrap();
+        cmd.env("DEMON_ROOT_DIR", &root);
+        cmd
+    };
+    demon()
+        .args(["run", "job", "--", "echo", "done"])
+        .assert()
+        .success();
+    std::thread::sleep(Duration::from_millis(200));
+    let copies: Vec<_> = (0..3)
+        .map(|n| temp_dir.path().join(format!("copies-{n}")))
+        .collect();
+    for dest in &copies {
+        demon()
+            .args(["cp-logs", "job"])
+            .arg(dest)
+            .assert()
+            .success();
+    }
+    let remaining = || {
+        copies
+            .iter()
+            .filter(|dest| fs::read_dir(dest).unwrap().next().is_some())
+            .count()
+    };
+
+    demon()
+        .arg("gc")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("No retention policy"));
+    demon()
+        .args(["gc", "--max-copies", "1", "--dry-run"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Would remove copy of the logs of 'job'").count(2));
+    assert_eq!(remaining(), 3);
+
+    // The policy of the manifest, with the newest copy kept
+    fs::write(root.join("demon.toml"), "[retention]\nmax_copies = 1\n").unwrap();
+    demon()
+        .arg("gc")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Freed"));
+    assert_eq!(remaining(), 1);
+    assert!(fs::read_dir(&copies[2]).unwrap().next().is_some());
+    assert!(root.join("job/job.stdout").exists());
+
+    // Ages are in whole seconds
+    std::thread::sleep(Duration::from_millis(1100));
+    fs::write(root.join("demon.toml"), "[retention]\nmax_age = 0\n").unwrap();
+    demon()
+        .arg("gc")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Removed logs of 'job'"));
+    assert!(!root.join("job/job.stdout").exists() && root.join("job/job.pid").exists());
+}
+
+#[test]
+fn test_gc_past_runs() {
+    let temp_dir = TempDir::new().unwrap();
+    let demon = || {
+        let mut cmd = Command::cargo_bin("demon").unwrap();
+        cmd.env("DEMON_ROOT_DIR", temp_dir.path());
+        cmd
+    };
+    for _ in 0..4 {
+        demon()
+            .args(["run", "big", "--", "head", "-c", "102400", "/dev/zero"])
+            .assert()
+            .success();
+        std::thread::sleep(Duration::from_millis(300));
+    }
+    assert_eq!(
+        fs::read_dir(temp_dir.path().join("runs/big"))
+            .unwrap()
+            .count(),
+        3
+    );
+
+    // Every past run is a candidate of its own, the oldest first
+    demon()
+        .args(["gc", "--max-total-bytes", "150K"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Removed past run of 'big'").count(3))
+        .stdout(predicate::str::contains("logs take 100.0K"));
+    assert!(!temp_dir.path().join("runs/big").exists());
+    assert!(temp_dir.path().join("big/big.stdout").exists());
+}
+
+#[test]
+fn test_groups_and_labels() {
+    let temp_dir = TempDir::new().unwrap();
+    let demon = || {
+        let mut cmd = Command::cargo_bin("demon").unwrap();
+        cmd.env("DEMON_ROOT_DIR", temp_dir.path());
+        cmd
+    };
+    for (id, tier) in [("shop-web", "web"), ("shop-api", "api")] {
+        demon()
+            .args(["run", id, "--group", "shop", "--label"])
+            .arg(format!("tier={tier}"))
+            .args(["--", "sh", "-c"])
+            .arg(format!("echo {id} up; sleep 30"))
+            .assert()
+            .success();
+    }
+    demon()
+        .args(["run", "db", "sleep", "30"])
+        .assert()
+        .success();
+    demon()
+        .args(["run", "x", "--label", "a=1", "--label", "a=2", "true"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "Label 'a' is given more than once",
+        ));
+    std::thread::sleep(Duration::from_millis(300));
+
+    demon()
+        .args(["list", "--group", "shop", "--columns", "id,group", "-q"])
+        .assert()
+        .success()
+        .stdout("shop-api:shop\nshop-web:shop\n");
+    demon()
+        .args(["list", "--label", "tier=api", "--format", "json"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("\"tier\": \"api\""))
+        .stdout(predicate::str::contains("shop-web").not());
+    demon()
+        .args(["status", "shop-web"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Group: shop"))
+        .stdout(predicate::str::contains("Labels: tier=web"));
+
+    demon()
+        .args(["tail", "--group", "shop"])
+        .assert()
+        .success()
+        .stdout("shop-api.stdout: shop-api up\nshop-web.stdout: shop-web up\n");
+
+    let pid =
+        |id: &str| fs::read_to_string(temp_dir.path().join(id).join(format!("{id}.pid"))).unwrap();
+    let before = pid("shop-web");
+    demon()
+        .args(["restart", "--label", "tier=web"])
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with("Restarted daemon 'shop-web'"));
+    assert_ne!(pid("shop-web"), before);
+    demon()
+        .args(["status", "shop-web"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Labels: tier=web"));
+
+    // A member that already exited is told in its record, not the exit code
+    demon()
+        .args(["run", "shop-job", "--group", "shop", "true"])
         .assert()
         .success();
+    std::thread::sleep(Duration::from_millis(200));
+    demon()
+        .args(["stop", "--group", "shop", "--format", "json"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("\"result\": \"terminated\"").count(2))
+        .stdout(predicate::str::contains("\"result\": \"already-exited\""));
+    demon()
+        .args(["stop", "--group", "shop"])
+        .assert()
+        .code(4)
+        .stderr(predicate::str::contains("No daemon matches --group shop"));
+    demon().args(["stop", "db"]).assert().success();
 }
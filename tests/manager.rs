@@ -0,0 +1,224 @@
+use demon::{DaemonManager, MemoryStore, RunOptions, Stopped, Stream};
+use std::time::Duration;
+use tempfile::TempDir;
+
+fn command(args: &[&str]) -> Vec<String> {
+    args.iter().map(|arg| arg.to_string()).collect()
+}
+
+#[test]
+fn test_manager_spawn_status_logs_stop() {
+    let temp_dir = TempDir::new().unwrap();
+    let manager = DaemonManager::new(temp_dir.path()).unwrap();
+
+    let pid = manager
+        .spawn(
+            "server",
+            &command(&["sh", "-c", "echo one; echo two; echo oops >&2; sleep 30"]),
+            &RunOptions::default(),
+        )
+        .unwrap();
+    std::thread::sleep(Duration::from_millis(300));
+
+    let status = manager.status("server").unwrap().unwrap();
+    assert_eq!(status.pid, pid);
+    assert!(status.running);
+    assert_eq!(status.command[0], "sh");
+    assert!(status.started_at.is_some());
+    assert!(manager.status("missing").unwrap().is_none());
+    assert_eq!(manager.list().unwrap(), vec![status]);
+
+    assert_eq!(
+        manager.logs("server", Stream::Stdout, None).unwrap(),
+        "one\ntwo\n"
+    );
+    assert_eq!(
+        manager.logs("server", Stream::Stdout, Some(1)).unwrap(),
+        "two\n"
+    );
+    assert_eq!(
+        manager.logs("server", Stream::Stderr, None).unwrap(),
+        "oops\n"
+    );
+
+    // The same daemon can't be spawned twice
+    assert!(
+        manager
+            .spawn("server", &command(&["true"]), &RunOptions::default())
+            .is_err()
+    );
+
+    assert_eq!(
+        manager.stop("server", Duration::from_secs(5)).unwrap(),
+        Stopped::Terminated { pid }
+    );
+    assert!(manager.status("server").unwrap().is_none());
+    assert_eq!(
+        manager.stop("server", Duration::from_secs(5)).unwrap(),
+        Stopped::NoPidFile
+    );
+}
+
+#[test]
+fn test_manager_wait() {
+    let temp_dir = TempDir::new().unwrap();
+    let manager = DaemonManager::new(temp_dir.path()).unwrap();
+
+    manager
+        .spawn("short", &command(&["sleep", "0.5"]), &RunOptions::default())
+        .unwrap();
+    manager
+        .wait("short", Some(Duration::from_secs(10)))
+        .unwrap();
+    let status = manager.status("short").unwrap().unwrap();
+    assert!(!status.running);
+
+    manager
+        .spawn("long", &command(&["sleep", "30"]), &RunOptions::default())
+        .unwrap();
+    assert!(
+        manager
+            .wait("long", Some(Duration::from_millis(300)))
+            .is_err()
+    );
+    manager.stop("long", Duration::from_secs(5)).unwrap();
+}
+
+#[test]
+fn test_manager_memory_store() {
+    let temp_dir = TempDir::new().unwrap();
+    let manager = DaemonManager::with_store(temp_dir.path(), MemoryStore::new()).unwrap();
+
+    let pid = manager
+        .spawn("server", &command(&["sleep", "30"]), &RunOptions::default())
+        .unwrap();
+    // The state is only in the store, the logs still in the directory
+    assert!(!temp_dir.path().join("server/server.pid").exists());
+    assert!(!temp_dir.path().join("server/server.meta").exists());
+    assert!(temp_dir.path().join("server/server.stdout").exists());
+
+    let status = manager.status("server").unwrap().unwrap();
+    assert_eq!(status.pid, pid);
+    assert!(status.running);
+    assert_eq!(manager.list().unwrap(), vec![status]);
+
+    // Other processes cannot see the store, so nothing can supervise
+    let mut supervised = RunOptions::default();
+    supervised.supervise = true;
+    assert!(
+        manager
+            .spawn("supervised", &command(&["sleep", "30"]), &supervised)
+            .is_err()
+    );
+
+    assert_eq!(
+        manager.stop("server", Duration::from_secs(5)).unwrap(),
+        Stopped::Terminated { pid }
+    );
+    assert!(manager.list().unwrap().is_empty());
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn test_manager_sqlite_store() {
+    use assert_cmd::Command;
+    use demon::SqliteStore;
+
+    let temp_dir = TempDir::new().unwrap();
+    let store = SqliteStore::open(temp_dir.path()).unwrap();
+    let manager = DaemonManager::with_store(temp_dir.path(), store).unwrap();
+    manager
+        .spawn("server", &command(&["sleep", "30"]), &RunOptions::default())
+        .unwrap();
+    assert!(!temp_dir.path().join("server/server.pid").exists());
+
+    // The command line finds the database too
+    Command::cargo_bin("demon")
+        .unwrap()
+        .args(["list", "--quiet"])
+        .env("DEMON_ROOT_DIR", temp_dir.path())
+        .assert()
+        .success()
+        .stdout(predicates::str::starts_with("server:"));
+    Command::cargo_bin("demon")
+        .unwrap()
+        .args(["stop", "server"])
+        .env("DEMON_ROOT_DIR", temp_dir.path())
+        .assert()
+        .success();
+    assert!(manager.list().unwrap().is_empty());
+}
+
+#[cfg(feature = "async")]
+#[tokio::test]
+async fn test_async_manager_tail_wait_stop() {
+    use demon::AsyncDaemonManager;
+    use tokio_stream::StreamExt;
+
+    let temp_dir = TempDir::new().unwrap();
+    let manager = AsyncDaemonManager::new(temp_dir.path()).unwrap();
+
+    let pid = manager
+        .spawn(
+            "ticker",
+            &command(&[
+                "sh",
+                "-c",
+                "echo old; sleep 0.5; echo one; echo two; sleep 30",
+            ]),
+            &RunOptions::default(),
+        )
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    // Only lines written from now on
+    let mut lines = manager.tail("ticker", Stream::Stdout);
+    let next = tokio::time::timeout(Duration::from_secs(5), lines.next());
+    assert_eq!(next.await.unwrap().unwrap().unwrap(), "one");
+    assert_eq!(lines.next().await.unwrap().unwrap(), "two");
+    drop(lines);
+
+    assert!(
+        manager
+            .wait("ticker", Some(Duration::from_millis(300)))
+            .await
+            .is_err()
+    );
+    assert_eq!(
+        manager
+            .stop("ticker", Duration::from_secs(5))
+            .await
+            .unwrap(),
+        Stopped::Terminated { pid }
+    );
+    assert!(manager.list().await.unwrap().is_empty());
+
+    manager
+        .spawn("short", &command(&["sleep", "0.3"]), &RunOptions::default())
+        .await
+        .unwrap();
+    manager
+        .wait("short", Some(Duration::from_secs(10)))
+        .await
+        .unwrap();
+
+    // SIGTERM ignored until the timeout, then SIGKILL
+    let pid = manager
+        .spawn(
+            "stubborn",
+            &command(&["sh", "-c", "trap '' TERM; while :; do sleep 0.1; done"]),
+            &RunOptions::default(),
+        )
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert_eq!(
+        manager
+            .stop("stubborn", Duration::from_secs(1))
+            .await
+            .unwrap(),
+        Stopped::Killed { pid }
+    );
+    assert!(manager.status("stubborn").await.unwrap().is_none());
+}